@@ -8,6 +8,36 @@ use clap::{Args, Parser, Subcommand};
 )]
 #[command(version)]
 pub struct Cli {
+    /// Answer yes to confirmation prompts (required for destructive commands
+    /// without a terminal)
+    #[arg(long, short = 'y', global = true)]
+    pub yes: bool,
+
+    /// Seconds to wait for slow nix evaluations, such as checking that a
+    /// flake has a package, before going on without them
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = 60)]
+    pub timeout: u64,
+
+    /// Never change a flake.lock: builds fail if one needs updating, and
+    /// update, undo-update and upgrade refuse to run (also NIXY_FROZEN=1)
+    #[arg(long, global = true)]
+    pub frozen: bool,
+
+    /// Don't use the network: reuse earlier Nixhub resolutions (installs
+    /// take the version and commit a package was resolved at before), pass
+    /// --offline to nix, and refuse update and upgrade
+    #[arg(long, global = true)]
+    pub offline: bool,
+
+    /// Only print warnings, errors and what was asked for, not progress
+    #[arg(long, short = 'q', global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Also print every nix command before running it, and when each
+    /// package was installed in `nixy list`
+    #[arg(long, short = 'v', global = true)]
+    pub verbose: bool,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -24,49 +54,179 @@ pub enum Commands {
 
     /// List packages in flake.nix [alias: ls]
     #[command(alias = "ls")]
-    List,
+    List(ListArgs),
 
     /// Search for packages
-    Search {
-        /// Search query
-        query: String,
-    },
+    Search(SearchArgs),
+
+    /// Run a package's program once, without installing it
+    Run(RunArgs),
+
+    /// Start a shell with extra packages on top of the active profile
+    Shell(ShellArgs),
 
     /// Update packages and flake inputs
     Update(UpdateArgs),
 
+    /// Restore the profile(s) as they were before the last `nixy update`
+    #[command(name = "undo-update", alias = "undo-upgrade")]
+    UndoUpdate(UndoUpdateArgs),
+
+    /// Go back to the environment the active profile had before its last build
+    Rollback(RollbackArgs),
+
     /// Build environment from flake.nix and create symlink
     Sync(SyncArgs),
 
-    /// Output shell config (for eval in rc files)
-    Config {
-        /// Shell type (bash, zsh, fish)
-        shell: String,
-    },
+    /// Output shell config (for eval in rc files), or print nixy paths
+    Config(ConfigArgs),
 
     /// Profile management commands
     Profile(ProfileArgs),
 
+    /// List profiles (same as `nixy profile` without arguments)
+    Profiles,
+
+    /// Show the packages that differ between two profiles
+    Diff(DiffArgs),
+
+    /// Write the active profile and local packages to a portable manifest
+    Export(ExportArgs),
+
+    /// Create or update a profile from a manifest written by `nixy export`
+    Import(ImportArgs),
+
     /// Upgrade nixy to the latest version
     Upgrade(UpgradeArgs),
 
     /// Show path to package source file in Nix store
     File(FileArgs),
 
-    /// Print dynamic completion candidates (used by shell completions)
-    #[command(hide = true)]
+    /// Show details of an installed package
+    Info(InfoArgs),
+
+    /// Show which installed package provides a command in the environment
+    Which(WhichArgs),
+
+    /// Explain why a package is in the active profile's environment
+    Why(WhyArgs),
+
+    /// Change the platforms an installed package is built for
+    Platform(PlatformArgs),
+
+    /// Drop nixy's stale GC roots, then collect garbage in the Nix store
+    Gc(GcArgs),
+
+    /// Check the nixy setup for common problems
+    Doctor(DoctorArgs),
+
+    /// Print a completion script for bash, zsh or fish
+    #[command(after_help = "Load the script from your shell configuration:
+  bash: source <(nixy completions bash)
+  zsh:  source <(nixy completions zsh)   (after compinit)
+  fish: nixy completions fish | source")]
     Completions(CompletionsArgs),
 }
 
 #[derive(Args)]
 pub struct InstallArgs {
-    /// Package name to install
-    pub package: Option<String>,
+    /// Packages to install (several nixpkgs packages are built together)
+    pub packages: Vec<String>,
 
     /// Only install on specific platform(s). Valid values: darwin, macos, linux,
-    /// x86_64-darwin, aarch64-darwin, x86_64-linux, aarch64-linux
+    /// aarch64, x86_64 (or amd64), x86_64-darwin, aarch64-darwin, x86_64-linux,
+    /// aarch64-linux
     #[arg(long, short = 'p')]
     pub platform: Vec<String>,
+
+    /// Install from a flake without checking that it has the package (a
+    /// wrong name then fails the build instead)
+    #[arg(long)]
+    pub no_validate: bool,
+
+    /// With several packages, install none of them if any is not found
+    /// (by default the others are installed)
+    #[arg(long)]
+    pub atomic: bool,
+
+    /// Pin the package, so `nixy update` leaves it where it is until
+    /// `nixy update <package> --unpin`: at the given version, or without one
+    /// at the current nixos-unstable commit
+    #[arg(long)]
+    pub pin: bool,
+
+    /// Link this output of the package (e.g. doc, man) instead of its
+    /// default one, as a separate entry named <package>-<output>
+    #[arg(long, value_name = "OUTPUT")]
+    pub output: Option<String>,
+
+    /// Link these outputs of the package (e.g. out,man,lib), replacing the
+    /// ones it links by default
+    #[arg(
+        long,
+        value_name = "OUTPUTS",
+        value_delimiter = ',',
+        num_args = 1..,
+        conflicts_with = "output"
+    )]
+    pub outputs: Vec<String>,
+
+    /// Install the package under this name instead (e.g. to keep two
+    /// versions or outputs of one package side by side)
+    #[arg(long = "as", value_name = "NAME")]
+    pub install_as: Option<String>,
+
+    /// Install a flake at this git commit (a full hash); `nixy update`
+    /// leaves it there
+    #[arg(long, value_name = "HASH")]
+    pub rev: Option<String>,
+
+    /// Commit the change to the git repositories of nixy.json and the state
+    /// directory with this message (instead of the one `auto_commit` generates)
+    #[arg(long, value_name = "MESSAGE")]
+    pub commit_message: Option<String>,
+
+    /// Commit the change to the git repositories of nixy.json and the state
+    /// directory with the generated message, even when `auto_commit` is off
+    #[arg(long, conflicts_with = "commit_message")]
+    pub git_commit: bool,
+
+    /// Show the changes to nixy.json and flake.nix without writing or
+    /// building anything
+    #[arg(long, conflicts_with_all = ["commit_message", "git_commit"])]
+    pub dry_run: bool,
+
+    /// Ask Nixhub again instead of reusing resolutions cached in the last
+    /// 24 hours
+    #[arg(long)]
+    pub refresh: bool,
+}
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// Package to run, with an optional version (e.g., cowsay, nodejs@20),
+    /// or a flake reference (e.g., github:user/repo#tool)
+    pub package: String,
+
+    /// Take the package from this flake instead of nixpkgs
+    #[arg(long, value_name = "FLAKE")]
+    pub from: Option<String>,
+
+    /// Arguments for the program, after `--`
+    #[arg(last = true, value_name = "ARGS")]
+    pub args: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct ShellArgs {
+    /// Packages to add, with optional versions (e.g., protobuf cmake@3),
+    /// or flake references
+    #[arg(required = true)]
+    pub packages: Vec<String>,
+
+    /// Take the packages from this flake instead of nixpkgs
+    #[arg(long, value_name = "FLAKE")]
+    pub from: Option<String>,
 }
 
 #[derive(Args)]
@@ -77,18 +237,156 @@ pub struct UpdateArgs {
     /// Update all packages and inputs
     #[arg(long, conflicts_with = "inputs")]
     pub all: bool,
+
+    /// Update this profile instead of the active one (only the active profile is rebuilt)
+    #[arg(long, value_name = "NAME", conflicts_with = "all_profiles")]
+    pub profile: Option<String>,
+
+    /// Update every profile (only the active profile is rebuilt)
+    #[arg(long)]
+    pub all_profiles: bool,
+
+    /// Re-resolve the packages pinned to this nixpkgs commit (e.g. one GitHub no longer serves)
+    #[arg(long, value_name = "HASH", conflicts_with_all = ["inputs", "all", "all_profiles"])]
+    pub commit: Option<String>,
+
+    /// With --commit, pin those packages to this nixpkgs commit instead of re-resolving them
+    #[arg(
+        long,
+        value_name = "REV",
+        requires = "commit",
+        conflicts_with_all = ["inputs", "all", "all_profiles"]
+    )]
+    pub pin_missing_to: Option<String>,
+
+    /// Release the named packages from an `install --pin`, then update them
+    #[arg(long, requires = "inputs", conflicts_with_all = ["all", "all_profiles"])]
+    pub unpin: bool,
+
+    /// Also re-resolve packages installed with `--pin`, keeping them pinned
+    #[arg(long, conflicts_with_all = ["commit", "unpin"])]
+    pub include_pinned: bool,
+
+    /// Show the version and lock changes the update would make, then discard them
+    #[arg(long, conflicts_with_all = ["all_profiles", "commit", "unpin"])]
+    pub dry_run: bool,
+
+    /// Ask Nixhub again instead of reusing resolutions cached in the last 24 hours
+    #[arg(long)]
+    pub refresh: bool,
+}
+
+#[derive(Args)]
+pub struct UndoUpdateArgs {
+    /// Restore even if the profile changed again after the update
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Args)]
+pub struct RollbackArgs {
+    /// List the recorded environments instead of rolling back
+    #[arg(long)]
+    pub list: bool,
 }
 
 #[derive(Args)]
-pub struct SyncArgs {}
+pub struct DiffArgs {
+    /// Profile to compare from (its packages are shown as -name)
+    pub from: String,
+
+    /// Profile to compare to (its packages are shown as +name; defaults to
+    /// the active profile)
+    pub to: Option<String>,
+
+    /// Print the differences as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Args, Default)]
+pub struct SyncArgs {
+    /// When the active profile has no flake.lock, start from this profile's
+    /// lock instead of resolving floating packages fresh
+    #[arg(long, value_name = "PROFILE")]
+    pub copy_lock_from: Option<String>,
+
+    /// Also drop flake.lock entries of inputs flake.nix no longer declares,
+    /// such as nixpkgs commits no package uses anymore
+    #[arg(long)]
+    pub prune: bool,
+
+    /// Show how flake.nix would change and exit without writing or building;
+    /// fails when it would change
+    #[arg(long, conflicts_with_all = ["copy_lock_from", "prune"])]
+    pub dry_run: bool,
+
+    /// Regenerate and rebuild every profile, several at once, instead of
+    /// only the active one
+    #[arg(long, conflicts_with_all = ["copy_lock_from", "dry_run"])]
+    pub all: bool,
+
+    /// Commit the change to the git repositories of nixy.json and the state
+    /// directory with this message (instead of the one `auto_commit` generates)
+    #[arg(long, value_name = "MESSAGE", conflicts_with = "dry_run")]
+    pub commit_message: Option<String>,
+
+    /// Commit the change to the git repositories of nixy.json and the state
+    /// directory with the generated message, even when `auto_commit` is off
+    #[arg(long, conflicts_with_all = ["commit_message", "dry_run"])]
+    pub git_commit: bool,
+}
 
 #[derive(Args)]
 pub struct UninstallArgs {
-    /// Package name to uninstall
-    pub package: String,
+    /// Packages to uninstall (omit to pick from a list on a terminal)
+    pub packages: Vec<String>,
+
+    /// Also delete the local package definition in packages/ without prompting
+    /// when no other profile references it
+    #[arg(long)]
+    pub purge: bool,
+
+    /// Commit the change to the git repositories of nixy.json and the state
+    /// directory with this message (instead of the one `auto_commit` generates)
+    #[arg(long, value_name = "MESSAGE")]
+    pub commit_message: Option<String>,
+
+    /// Commit the change to the git repositories of nixy.json and the state
+    /// directory with the generated message, even when `auto_commit` is off
+    #[arg(long, conflicts_with = "commit_message")]
+    pub git_commit: bool,
+
+    /// Show the changes to nixy.json and flake.nix without writing or
+    /// building anything
+    #[arg(long, conflicts_with_all = ["commit_message", "git_commit"])]
+    pub dry_run: bool,
 }
 
 #[derive(Args)]
+pub struct ConfigArgs {
+    /// Shell type (bash, zsh, fish)
+    #[arg(required_unless_present = "print")]
+    pub shell: Option<String>,
+
+    /// Print the environment directory of the active profile (fails if never built)
+    #[arg(long, group = "print", conflicts_with = "shell")]
+    pub print_env_dir: bool,
+
+    /// Print the environment's bin directory (fails if never built)
+    #[arg(long, group = "print", conflicts_with = "shell")]
+    pub print_bin_dir: bool,
+
+    /// Print the path to nixy.json
+    #[arg(long, group = "print", conflicts_with = "shell")]
+    pub print_config_path: bool,
+
+    /// Print the state directory
+    #[arg(long, group = "print", conflicts_with = "shell")]
+    pub print_state_dir: bool,
+}
+
+#[derive(Args, Default)]
 pub struct ProfileArgs {
     /// Profile name
     pub name: Option<String>,
@@ -100,6 +398,72 @@ pub struct ProfileArgs {
     /// Delete the specified profile
     #[arg(short, conflicts_with = "c")]
     pub d: bool,
+
+    /// When the profile has no flake.lock, start from this profile's lock
+    /// instead of resolving floating packages fresh
+    #[arg(long, value_name = "PROFILE", requires = "name", conflicts_with = "d")]
+    pub copy_lock_from: Option<String>,
+
+    /// Only switch: reuse the profile's last build instead of regenerating
+    /// and building (warns if it was never built)
+    #[arg(long, requires = "name", conflicts_with_all = ["c", "d", "copy_lock_from"])]
+    pub activate_only: bool,
+
+    /// Set the profile's one-line description without switching to it
+    /// (an empty string clears it)
+    #[arg(
+        long,
+        value_name = "TEXT",
+        requires = "name",
+        conflicts_with_all = ["c", "d", "copy_lock_from", "activate_only"]
+    )]
+    pub set_description: Option<String>,
+
+    /// Rename the profile, keeping its packages and last build
+    #[arg(
+        long,
+        value_name = "NEW",
+        requires = "name",
+        conflicts_with_all = ["c", "d", "copy_lock_from", "activate_only", "set_description"]
+    )]
+    pub rename: Option<String>,
+
+    /// Create the profile as a copy of SOURCE's packages, flake.nix and
+    /// flake.lock (add -c to also switch to it)
+    #[arg(
+        long,
+        visible_alias = "copy-from",
+        value_name = "SOURCE",
+        requires = "name",
+        conflicts_with_all = ["d", "copy_lock_from", "activate_only", "set_description", "rename"]
+    )]
+    pub copy: Option<String>,
+
+    /// With --copy, replace the profile if it already exists
+    #[arg(long, requires = "copy")]
+    pub force: bool,
+
+    /// Set the base nixpkgs of the profile: a flake URL, or a branch of
+    /// NixOS/nixpkgs such as nixos-24.05 (an empty string goes back to
+    /// nixos-unstable). The active profile is rebuilt
+    #[arg(
+        long,
+        value_name = "URL",
+        requires = "name",
+        conflicts_with_all = ["c", "d", "copy_lock_from", "activate_only", "set_description", "rename", "copy"]
+    )]
+    pub channel: Option<String>,
+
+    /// Set the outputs linked besides each package's main one, e.g.
+    /// "man,info" (an empty string links none, "default" goes back to man,
+    /// doc, info and dev). The active profile is rebuilt
+    #[arg(
+        long,
+        value_name = "OUTPUTS",
+        requires = "name",
+        conflicts_with_all = ["c", "d", "copy_lock_from", "activate_only", "set_description", "rename", "copy", "channel"]
+    )]
+    pub outputs: Option<String>,
 }
 
 #[derive(Args)]
@@ -115,8 +479,169 @@ pub struct FileArgs {
     pub package: String,
 }
 
+#[derive(Args)]
+pub struct ExportArgs {
+    /// Write the manifest to this file instead of stdout (with --flake, the
+    /// directory to write the flake to)
+    #[arg(long, short = 'o', value_name = "PATH")]
+    pub output: Option<String>,
+
+    /// Profile to export (defaults to the active profile)
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    /// Write a standalone flake (flake.nix, flake.lock and local packages)
+    /// instead of a manifest
+    #[arg(long, requires = "output")]
+    pub flake: bool,
+}
+
+#[derive(Args)]
+pub struct ImportArgs {
+    /// Manifest file (`-` for stdin), another nixy.json, or a flake.nix
+    /// generated by nixy (or the directory holding it)
+    pub file: String,
+
+    /// Profile to import into (defaults to the exported profile's name; the
+    /// active profile for a nixy.json or flake.nix)
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    /// Profile to take from a nixy.json (defaults to its active profile)
+    #[arg(long, value_name = "NAME")]
+    pub from_profile: Option<String>,
+
+    /// Add the manifest's packages to the profile instead of replacing it
+    /// (always the case for a nixy.json or flake.nix)
+    #[arg(long)]
+    pub merge: bool,
+
+    /// Overwrite packages and local package files that differ
+    #[arg(long, alias = "overwrite")]
+    pub force: bool,
+}
+
+#[derive(Args)]
+pub struct InfoArgs {
+    /// Package name
+    pub package: String,
+
+    /// Print the details as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Args)]
+pub struct WhichArgs {
+    /// Command name, as found in the environment's bin/ directory
+    pub binary: String,
+}
+
+#[derive(Args)]
+pub struct WhyArgs {
+    /// Package name
+    pub package: String,
+}
+
+#[derive(Args)]
+pub struct GcArgs {
+    /// Show the roots that would be removed and the store paths that would
+    /// be deleted, without removing anything
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Args)]
+pub struct PlatformArgs {
+    /// Package name
+    pub package: String,
+
+    /// Only install on these platform(s), with the same values as
+    /// `install --platform`
+    #[arg(
+        long,
+        value_name = "PLATFORM",
+        num_args = 1..,
+        value_delimiter = ',',
+        required_unless_present = "clear",
+        conflicts_with = "clear"
+    )]
+    pub set: Vec<String>,
+
+    /// Install on every platform again
+    #[arg(long)]
+    pub clear: bool,
+}
+
+#[derive(Args)]
+pub struct SearchArgs {
+    /// Search query
+    pub query: String,
+
+    /// Search nixpkgs as of this commit instead of Nixhub's latest versions
+    #[arg(long, value_name = "COMMIT")]
+    pub rev: Option<String>,
+
+    /// Search the nixpkgs commit the active profile's flake.lock pins
+    #[arg(long, conflicts_with = "rev")]
+    pub pinned: bool,
+
+    /// Show at most this many results
+    #[arg(long, value_name = "N", default_value_t = 20)]
+    pub limit: usize,
+
+    /// Print the results as a JSON array of name, version and description
+    #[arg(long)]
+    pub json: bool,
+
+    /// Only show packages that are installed in the active profile
+    #[arg(long)]
+    pub installed_only: bool,
+}
+
+#[derive(Args)]
+pub struct ListArgs {
+    /// Check that every declared package ends up in the generated flake, and
+    /// that the profile's flake.nix on disk matches nixy.json
+    #[arg(long)]
+    pub verify: bool,
+
+    /// Print directly instead of paging long output
+    #[arg(long)]
+    pub no_pager: bool,
+
+    /// Print only the number of installed packages
+    #[arg(long, conflicts_with = "verify")]
+    pub count: bool,
+
+    /// Print the active profile, all profiles and the packages as JSON
+    /// (with --count, the number of packages broken down by kind)
+    #[arg(long, conflicts_with = "verify")]
+    pub json: bool,
+
+    /// Show the resolved packages Nixhub has a newer version of, within
+    /// their version spec, without changing anything
+    #[arg(long, conflicts_with_all = ["verify", "count", "json"])]
+    pub outdated: bool,
+}
+
+#[derive(Args)]
+pub struct DoctorArgs {
+    /// Print results as a JSON array of {check, status, detail} objects
+    #[arg(long)]
+    pub json: bool,
+
+    /// First repair what is safe to: migrate leftover legacy profiles,
+    /// regenerate flake.nix files from nixy.json and remove stray build links
+    #[arg(long)]
+    pub fix: bool,
+}
+
 #[derive(Args)]
 pub struct CompletionsArgs {
-    /// What to complete (e.g. installed, profiles)
+    /// Shell to print a completion script for (bash, zsh, fish). The shell
+    /// scripts also pass `installed` or `profiles` for the candidates to
+    /// complete
+    #[arg(value_name = "SHELL")]
     pub kind: String,
 }
@@ -1,13 +1,22 @@
-//! Dynamic completion helper.
+//! Shell completion scripts and their dynamic completion helper.
 //!
-//! Prints newline-separated completion candidates consumed by the shell
-//! completion scripts (`src/completions/nixy.zsh`, `nixy.bash`). This keeps the
-//! candidate lists (installed packages, profiles) always in sync with the real
-//! config instead of being hard-coded in the shell scripts.
+//! `nixy completions <shell>` prints a completion script generated from the
+//! CLI definition by clap_complete, followed by a short hook that completes
+//! profile and package names by calling back into nixy.
+//!
+//! Any other argument is a kind of candidate, printed newline-separated for
+//! those hooks and for the scripts of `nixy config` (`src/completions/nixy.zsh`,
+//! `nixy.bash`). This keeps the candidate lists (installed packages, profiles)
+//! always in sync with the real config instead of being hard-coded in the
+//! shell scripts.
 //!
 //! All lookups swallow errors and fall back to an empty list: completion must
 //! never fail or print diagnostics, even when the config is missing or invalid.
 
+use clap::CommandFactory;
+use clap_complete::Shell;
+
+use crate::cli::Cli;
 use crate::config::Config;
 use crate::error::Result;
 use crate::flake::parser::collect_local_packages;
@@ -15,7 +24,31 @@ use crate::nixy_config::{nixy_json_exists, NixyConfig};
 use crate::profile::get_flake_dir;
 use crate::state::{get_state_path, PackageState};
 
+/// Subcommands whose positional arguments are completed dynamically, and the
+/// kind of candidate each takes
+const DYNAMIC: &[(&str, &str)] = &[
+    ("profile", "profiles"),
+    ("diff", "profiles"),
+    ("uninstall", "installed"),
+    ("remove", "installed"),
+    ("file", "installed"),
+    ("info", "installed"),
+    ("why", "installed"),
+    ("platform", "installed"),
+];
+
 pub fn run(config: &Config, kind: &str) -> Result<()> {
+    let shell = match kind {
+        "bash" => Some(Shell::Bash),
+        "zsh" => Some(Shell::Zsh),
+        "fish" => Some(Shell::Fish),
+        _ => None,
+    };
+    if let Some(shell) = shell {
+        print!("{}", script(shell));
+        return Ok(());
+    }
+
     let candidates = match kind {
         "installed" => installed_package_names(config),
         "profiles" => profile_names(config),
@@ -30,8 +63,93 @@ pub fn run(config: &Config, kind: &str) -> Result<()> {
     Ok(())
 }
 
+/// The clap_complete script for `shell` with the dynamic hook appended
+fn script(shell: Shell) -> String {
+    let mut buffer = Vec::new();
+    clap_complete::generate(shell, &mut Cli::command(), "nixy", &mut buffer);
+    let mut script = String::from_utf8_lossy(&buffer).into_owned();
+    script.push_str(&dynamic_hook(shell));
+    script
+}
+
+/// Shell code completing the positional arguments of [`DYNAMIC`]
+/// subcommands with `nixy completions <kind>`, deferring to the generated
+/// completion everywhere else
+fn dynamic_hook(shell: Shell) -> String {
+    let commands_of = |kind: &str| {
+        DYNAMIC
+            .iter()
+            .filter(|(_, k)| *k == kind)
+            .map(|(command, _)| *command)
+            .collect::<Vec<_>>()
+    };
+    let kinds = ["profiles", "installed"];
+    // The `case` arms picking the kind in bash and zsh
+    let cases: String = kinds
+        .iter()
+        .map(|kind| {
+            format!(
+                "        {}) kind={} ;;\n",
+                commands_of(kind).join("|"),
+                kind
+            )
+        })
+        .collect();
+
+    match shell {
+        Shell::Fish => {
+            let mut hook = String::from("\n# Profile and package names\n");
+            for kind in kinds {
+                hook.push_str(&format!(
+                    "complete -c nixy -n \"__fish_seen_subcommand_from {}\" -f -a \"(command nixy completions {} 2>/dev/null)\"\n",
+                    commands_of(kind).join(" "),
+                    kind
+                ));
+            }
+            hook
+        }
+        Shell::Zsh => {
+            format!(
+                r#"
+# Profile and package names
+_nixy_dynamic() {{
+    local kind
+    case $words[2] in
+{cases}    esac
+    if [[ -n $kind && $CURRENT -gt 2 && $PREFIX != -* ]]; then
+        local -a candidates
+        candidates=(${{(f)"$(command nixy completions $kind 2>/dev/null)"}})
+        (( $#candidates )) && compadd -a candidates && return 0
+    fi
+    _nixy "$@"
+}}
+compdef _nixy_dynamic nixy
+"#
+            )
+        }
+        _ => {
+            format!(
+                r#"
+# Profile and package names
+_nixy_dynamic() {{
+    local kind cur="${{COMP_WORDS[COMP_CWORD]}}"
+    case "${{COMP_WORDS[1]}}" in
+{cases}    esac
+    if [[ -n $kind && $COMP_CWORD -ge 2 && $cur != -* ]]; then
+        COMPREPLY=( $(compgen -W "$(command nixy completions $kind 2>/dev/null)" -- "$cur") )
+        [[ ${{#COMPREPLY[@]}} -gt 0 ]] && return 0
+    fi
+    _nixy "$@"
+}}
+complete -F _nixy_dynamic -o bashdefault -o default nixy
+"#
+            )
+        }
+    }
+}
+
 /// Names of all installed packages in the active profile (or legacy state).
-fn installed_package_names(config: &Config) -> Vec<String> {
+pub fn installed_package_names(config: &Config) -> Vec<String> {
     let mut names = Vec::new();
 
     if nixy_json_exists(config) {
@@ -1,17 +1,64 @@
+use std::path::Path;
+
+use crate::cli::ConfigArgs;
+use crate::config::Config;
 use crate::error::{Error, Result};
+use crate::profile::stale_env_target;
 
 /// POSIX PATH export shared by bash/zsh/sh.
 const POSIX_PATH: &str = r#"# nixy shell configuration
 export PATH="$HOME/.local/state/nixy/env/bin:$PATH""#;
 
-pub fn run(shell: &str) -> Result<()> {
-    match shell {
+/// Completions shipped by installed packages, for zsh. compinit usually ran
+/// before this is evaluated, so new functions are registered with compdef
+/// from their `#compdef` line, as compinit itself would.
+const ZSH_ENV_COMPLETIONS: &str = r#"# Completions of packages in the nixy environment
+() {
+    local dir="$HOME/.local/state/nixy/env/share/zsh/site-functions" f line
+    [[ -d $dir ]] || return 0
+    fpath=("$dir" $fpath)
+    typeset -f compdef >/dev/null || return 0
+    for f in "$dir"/_*(N); do
+        read -r line <"$f"
+        if [[ $line == '#compdef '* ]]; then
+            autoload -Uz -- "${f:t}"
+            compdef "${f:t}" ${=line#\#compdef }
+        fi
+    done
+}"#;
+
+/// Completions shipped by installed packages, for bash
+const BASH_ENV_COMPLETIONS: &str = r#"# Completions of packages in the nixy environment
+for __nixy_f in "$HOME"/.local/state/nixy/env/share/bash-completion/completions/*; do
+    [ -f "$__nixy_f" ] && . "$__nixy_f"
+done
+unset __nixy_f"#;
+
+pub fn run(config: &Config, args: ConfigArgs) -> Result<()> {
+    // Path lookups for external tools: a single line each, no nix invocation
+    if args.print_env_dir {
+        return print_path(built_env_dir(config)?);
+    }
+    if args.print_bin_dir {
+        return print_path(&built_env_dir(config)?.join("bin"));
+    }
+    if args.print_config_path {
+        return print_path(&config.nixy_json);
+    }
+    if args.print_state_dir {
+        return print_path(&config.state_dir);
+    }
+
+    let shell = args.shell.unwrap_or_default();
+    match shell.as_str() {
         "zsh" => {
             println!("{}", POSIX_PATH);
+            println!("{}", ZSH_ENV_COMPLETIONS);
             print!("{}", include_str!("../completions/nixy.zsh"));
         }
         "bash" => {
             println!("{}", POSIX_PATH);
+            println!("{}", BASH_ENV_COMPLETIONS);
             print!("{}", include_str!("../completions/nixy.bash"));
         }
         "sh" => {
@@ -20,7 +67,8 @@ pub fn run(shell: &str) -> Result<()> {
         "fish" => {
             println!(
                 r#"# nixy shell configuration
-set -gx PATH $HOME/.local/state/nixy/env/bin $PATH"#
+set -gx PATH $HOME/.local/state/nixy/env/bin $PATH
+set -g fish_complete_path $HOME/.local/state/nixy/env/share/fish/vendor_completions.d $fish_complete_path"#
             );
         }
         "" => {
@@ -35,9 +83,25 @@ Add to your shell config:
             ));
         }
         _ => {
-            return Err(Error::UnknownShell(shell.to_string()));
+            return Err(Error::UnknownShell(shell));
         }
     }
 
     Ok(())
 }
+
+/// The environment symlink, as long as it points at an existing build
+fn built_env_dir(config: &Config) -> Result<&Path> {
+    if let Some(target) = stale_env_target(&config.env_link) {
+        return Err(Error::StaleEnv { target });
+    }
+    if !config.env_link.exists() {
+        return Err(Error::EnvNotBuilt);
+    }
+    Ok(&config.env_link)
+}
+
+fn print_path(path: &Path) -> Result<()> {
+    println!("{}", path.display());
+    Ok(())
+}
@@ -0,0 +1,319 @@
+use std::collections::BTreeMap;
+
+use colored::Colorize;
+
+use crate::cli::DiffArgs;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::nixy_config::{nixy_json_exists, NixyConfig, ProfileConfig};
+
+use super::info;
+
+/// How one nixy.json package list differs between two profiles
+#[derive(Debug, Default, PartialEq)]
+struct CategoryDiff {
+    /// Only in the second profile: name and version or URL
+    added: Vec<(String, String)>,
+    /// Only in the first profile
+    removed: Vec<(String, String)>,
+    /// In both, at another version or URL: name, first, second
+    changed: Vec<(String, String, String)>,
+}
+
+impl CategoryDiff {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// A package of one list, keyed by name: what to show for it, and what
+/// must match for it to count as unchanged
+type Entries = BTreeMap<String, (String, String)>;
+
+pub fn run(config: &Config, args: DiffArgs) -> Result<()> {
+    if !nixy_json_exists(config) {
+        return Err(Error::Usage(
+            "nixy diff requires nixy.json. Run 'nixy sync' to migrate first.".to_string(),
+        ));
+    }
+    let nixy_config = NixyConfig::load(config)?;
+    let to_name = args
+        .to
+        .unwrap_or_else(|| nixy_config.active_profile.clone());
+    let profile = |name: &str| {
+        nixy_config
+            .profiles
+            .get(name)
+            .ok_or_else(|| Error::ProfileNotFound(name.to_string()))
+    };
+    let diffs = diff_profiles(profile(&args.from)?, profile(&to_name)?);
+
+    if args.json {
+        print_json(&args.from, &to_name, &diffs);
+        return Ok(());
+    }
+
+    if diffs.iter().all(|(_, diff)| diff.is_empty()) {
+        info(&format!(
+            "Profiles '{}' and '{}' have the same packages",
+            args.from, to_name
+        ));
+        return Ok(());
+    }
+    info(&format!("Changes from '{}' to '{}':", args.from, to_name));
+    for (category, diff) in diffs.iter().filter(|(_, diff)| !diff.is_empty()) {
+        println!("{}:", category);
+        for (name, value) in &diff.removed {
+            println!(
+                "  {}",
+                format!("-{}", describe(category, name, value)).red()
+            );
+        }
+        for (name, value) in &diff.added {
+            println!(
+                "  {}",
+                format!("+{}", describe(category, name, value)).green()
+            );
+        }
+        for (name, from, to) in &diff.changed {
+            println!("  {}", format!("~{} {} -> {}", name, from, to).yellow());
+        }
+    }
+    Ok(())
+}
+
+/// Compare the package lists of `from` and `to`, named like in nixy.json
+fn diff_profiles(from: &ProfileConfig, to: &ProfileConfig) -> Vec<(&'static str, CategoryDiff)> {
+    vec![
+        (
+            "packages",
+            diff_entries(&package_entries(from), &package_entries(to)),
+        ),
+        (
+            "resolved_packages",
+            diff_entries(&resolved_entries(from), &resolved_entries(to)),
+        ),
+        (
+            "custom_packages",
+            diff_entries(&custom_entries(from), &custom_entries(to)),
+        ),
+    ]
+}
+
+fn package_entries(profile: &ProfileConfig) -> Entries {
+    profile
+        .packages
+        .iter()
+        .map(|name| (name.clone(), (String::new(), String::new())))
+        .collect()
+}
+
+/// Resolved packages show their version; a new commit at the same version
+/// is still a change
+fn resolved_entries(profile: &ProfileConfig) -> Entries {
+    profile
+        .resolved_packages
+        .iter()
+        .map(|pkg| {
+            (
+                pkg.name.clone(),
+                (pkg.resolved_version.clone(), pkg.commit_hash.clone()),
+            )
+        })
+        .collect()
+}
+
+fn custom_entries(profile: &ProfileConfig) -> Entries {
+    profile
+        .custom_packages
+        .iter()
+        .map(|pkg| {
+            (
+                pkg.name.clone(),
+                (pkg.input_url.clone(), pkg.input_url.clone()),
+            )
+        })
+        .collect()
+}
+
+fn diff_entries(from: &Entries, to: &Entries) -> CategoryDiff {
+    let mut diff = CategoryDiff::default();
+    for (name, (shown, key)) in from {
+        match to.get(name) {
+            None => diff.removed.push((name.clone(), shown.clone())),
+            Some((to_shown, to_key)) if to_key != key => {
+                let (from_shown, to_shown) = if to_shown == shown {
+                    // Same version at another nixpkgs commit
+                    (short_commit(key), short_commit(to_key))
+                } else {
+                    (shown.clone(), to_shown.clone())
+                };
+                diff.changed.push((name.clone(), from_shown, to_shown));
+            }
+            Some(_) => {}
+        }
+    }
+    for (name, (shown, _)) in to {
+        if !from.contains_key(name) {
+            diff.added.push((name.clone(), shown.clone()));
+        }
+    }
+    diff
+}
+
+fn short_commit(commit: &str) -> String {
+    format!("commit {}", &commit[..8.min(commit.len())])
+}
+
+/// `name@version` for resolved packages, `name (url)` for custom ones
+fn describe(category: &str, name: &str, value: &str) -> String {
+    match category {
+        "resolved_packages" => format!("{}@{}", name, value),
+        "custom_packages" => format!("{} ({})", name, value),
+        _ => name.to_string(),
+    }
+}
+
+/// Print the differences as one JSON object, with `added`, `removed` and
+/// `changed` for each package list
+fn print_json(from: &str, to: &str, diffs: &[(&str, CategoryDiff)]) {
+    let mut document = serde_json::json!({ "from": from, "to": to });
+    for (category, diff) in diffs {
+        // Resolved packages carry a version, custom ones a flake URL
+        let field = match *category {
+            "resolved_packages" => Some("version"),
+            "custom_packages" => Some("url"),
+            _ => None,
+        };
+        let entry = |(name, value): &(String, String)| match field {
+            Some(field) => serde_json::json!({ "name": name, field: value }),
+            None => serde_json::json!({ "name": name }),
+        };
+        document[*category] = serde_json::json!({
+            "added": diff.added.iter().map(entry).collect::<Vec<_>>(),
+            "removed": diff.removed.iter().map(entry).collect::<Vec<_>>(),
+            "changed": diff
+                .changed
+                .iter()
+                .map(|(name, from, to)| serde_json::json!({ "name": name, "from": from, "to": to }))
+                .collect::<Vec<_>>(),
+        });
+    }
+    println!("{}", document);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{CustomPackage, ResolvedNixpkgPackage};
+
+    fn resolved(name: &str, version: &str, commit: &str) -> ResolvedNixpkgPackage {
+        ResolvedNixpkgPackage {
+            name: name.to_string(),
+            version_spec: None,
+            resolved_version: version.to_string(),
+            attribute_path: name.to_string(),
+            commit_hash: commit.to_string(),
+            platforms: None,
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
+        }
+    }
+
+    fn custom(name: &str, url: &str) -> CustomPackage {
+        CustomPackage {
+            name: name.to_string(),
+            input_name: name.to_string(),
+            input_url: url.to_string(),
+            package_output: "packages".to_string(),
+            source_name: None,
+            platforms: None,
+            unvalidated: false,
+            record: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_diff_profiles() {
+        let from = ProfileConfig {
+            packages: vec!["fd".to_string(), "jq".to_string()],
+            resolved_packages: vec![
+                resolved("hello", "2.12.1", "aaaaaaaaaaaa"),
+                resolved("nodejs", "20.11.0", "bbbbbbbbbbbb"),
+                resolved("ripgrep", "14.1.1", "cccccccccccc"),
+            ],
+            custom_packages: vec![custom("tool", "github:owner/tool")],
+            ..Default::default()
+        };
+        let to = ProfileConfig {
+            packages: vec!["fd".to_string(), "bat".to_string()],
+            resolved_packages: vec![
+                resolved("hello", "2.12.1", "dddddddddddd"),
+                resolved("nodejs", "20.12.0", "eeeeeeeeeeee"),
+            ],
+            custom_packages: vec![custom("tool", "github:owner/tool/v2")],
+            ..Default::default()
+        };
+
+        let diffs = diff_profiles(&from, &to);
+        assert_eq!(
+            diffs[0],
+            (
+                "packages",
+                CategoryDiff {
+                    added: vec![("bat".to_string(), String::new())],
+                    removed: vec![("jq".to_string(), String::new())],
+                    changed: vec![],
+                }
+            )
+        );
+        assert_eq!(
+            diffs[1].1,
+            CategoryDiff {
+                added: vec![],
+                removed: vec![("ripgrep".to_string(), "14.1.1".to_string())],
+                changed: vec![
+                    (
+                        "hello".to_string(),
+                        "commit aaaaaaaa".to_string(),
+                        "commit dddddddd".to_string()
+                    ),
+                    (
+                        "nodejs".to_string(),
+                        "20.11.0".to_string(),
+                        "20.12.0".to_string()
+                    ),
+                ],
+            }
+        );
+        assert_eq!(
+            diffs[2].1.changed,
+            vec![(
+                "tool".to_string(),
+                "github:owner/tool".to_string(),
+                "github:owner/tool/v2".to_string()
+            )]
+        );
+
+        assert!(diff_profiles(&from, &from)
+            .iter()
+            .all(|(_, diff)| diff.is_empty()));
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(describe("packages", "fd", ""), "fd");
+        assert_eq!(
+            describe("resolved_packages", "hello", "2.12.1"),
+            "hello@2.12.1"
+        );
+        assert_eq!(
+            describe("custom_packages", "tool", "github:owner/tool"),
+            "tool (github:owner/tool)"
+        );
+    }
+}
@@ -0,0 +1,687 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::process::Command;
+
+use colored::Colorize;
+use serde::Serialize;
+
+use crate::cli::DoctorArgs;
+use crate::commits::{CommitChecker, Reachability};
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::flake::template::{missing_local_packages, regenerate_flake_from_profile};
+use crate::migration;
+use crate::nix::{FlakePackageCheck, Nix};
+use crate::nixy_config::{nixy_json_exists, NixyConfig};
+use crate::profile::{
+    check_env_link, get_active_profile, get_flake_path, get_profile_flake_dir, has_legacy_flake,
+    remove_result_links, EnvLinkProblem, Profile,
+};
+
+use super::{eval_timeout, info, stale_notice, success};
+
+/// Outcome of a single doctor check
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+/// Result of a single doctor check, serialized as-is for `--json`
+#[derive(Debug, Serialize)]
+struct CheckResult {
+    check: &'static str,
+    status: Status,
+    detail: String,
+}
+
+impl CheckResult {
+    fn new(check: &'static str, status: Status, detail: impl Into<String>) -> Self {
+        Self {
+            check,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+pub fn run(config: &Config, args: DoctorArgs) -> Result<()> {
+    if args.fix {
+        let repairs = fix(config)?;
+        if !args.json {
+            if repairs.is_empty() {
+                info("Nothing to repair");
+            }
+            for repair in &repairs {
+                success(repair);
+            }
+        }
+    }
+
+    let results = vec![
+        check_nix(),
+        check_features(),
+        check_config(config),
+        check_legacy(config),
+        check_flake(config),
+        check_state_dirs(config),
+        check_locks(config),
+        check_needs_sync(config),
+        check_commits(config),
+        check_unvalidated(config),
+        check_local_packages(config),
+        check_env(config),
+        check_path(config),
+    ];
+
+    if args.json {
+        let json =
+            serde_json::to_string_pretty(&results).map_err(|e| Error::StateFile(e.to_string()))?;
+        println!("{}", json);
+    } else {
+        info("Checking nixy setup...");
+        let width = results.iter().map(|r| r.check.len()).max().unwrap_or(0);
+        for result in &results {
+            let mark = match result.status {
+                Status::Ok => "ok".green(),
+                Status::Warn => "warn".yellow(),
+                Status::Fail => "fail".red(),
+            };
+            println!(
+                "  {:<4}  {:<width$}  {}",
+                mark,
+                result.check,
+                result.detail,
+                width = width
+            );
+        }
+    }
+
+    let failed = results.iter().filter(|r| r.status == Status::Fail).count();
+    if failed > 0 {
+        return Err(Error::DoctorFailed(failed));
+    }
+
+    if !args.json {
+        success("No problems found");
+    }
+    Ok(())
+}
+
+/// The repairs of `--fix`, none of which builds anything or touches
+/// nixy.json beyond adding migrated profiles; returns what was done
+fn fix(config: &Config) -> Result<Vec<String>> {
+    let mut repairs = Vec::new();
+    if !nixy_json_exists(config) {
+        if !migration::needs_migration(config) {
+            return Ok(repairs);
+        }
+        migration::run_migration_if_needed(config)?;
+        repairs.push("Migrated the legacy configuration to nixy.json".to_string());
+    }
+
+    let migrated = migration::migrate_leftover_profiles(config)?;
+    if !migrated.is_empty() {
+        repairs.push(format!(
+            "Migrated legacy profile(s) into nixy.json: {}",
+            migrated.join(", ")
+        ));
+    }
+
+    // Nothing below can be done from a nixy.json that doesn't load
+    let Ok(nixy_config) = NixyConfig::load(config) else {
+        return Ok(repairs);
+    };
+    let mut regenerated = Vec::new();
+    let mut removed = Vec::new();
+    for (name, profile) in &nixy_config.profiles {
+        let flake_dir = get_profile_flake_dir(config, name)?;
+        let flake_path = flake_dir.join("flake.nix");
+        let previous = fs::read_to_string(&flake_path).ok();
+        regenerate_flake_from_profile(
+            &flake_dir,
+            profile,
+            Some(config.global_packages_dir.as_path()),
+            nixy_config.format,
+        )?;
+        if previous.as_deref() != Some(fs::read_to_string(&flake_path)?.as_str()) {
+            Profile::new(name, config).mark_needs_sync("nixy doctor --fix")?;
+            regenerated.push(name.clone());
+        }
+        removed.extend(
+            remove_result_links(&flake_dir)?
+                .into_iter()
+                .map(|link| format!("{}/{}", name, link)),
+        );
+    }
+    if !regenerated.is_empty() {
+        repairs.push(format!(
+            "Regenerated flake.nix from nixy.json for profile(s): {} (run 'nixy sync' to rebuild the active one)",
+            regenerated.join(", ")
+        ));
+    }
+    if !removed.is_empty() {
+        repairs.push(format!(
+            "Removed stray build link(s): {}",
+            removed.join(", ")
+        ));
+    }
+    Ok(repairs)
+}
+
+/// nix must be on PATH for every command except `config`
+fn check_nix() -> CheckResult {
+    match Command::new("nix").arg("--version").output() {
+        Ok(output) if output.status.success() => CheckResult::new(
+            "nix",
+            Status::Ok,
+            String::from_utf8_lossy(&output.stdout).trim(),
+        ),
+        Ok(output) => CheckResult::new(
+            "nix",
+            Status::Fail,
+            format!(
+                "'nix --version' failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ),
+        Err(_) => CheckResult::new("nix", Status::Fail, "nix is not installed or not on PATH"),
+    }
+}
+
+/// nixy enables the features it needs itself, but running nix by hand on
+/// its flakes needs them in nix.conf
+fn check_features() -> CheckResult {
+    let required = ["nix-command", "flakes"];
+    let enabled = match Nix::configured_features() {
+        Ok(features) => features.unwrap_or_default(),
+        Err(e) => {
+            return CheckResult::new(
+                "features",
+                Status::Warn,
+                format!("could not read the nix configuration: {}", e),
+            )
+        }
+    };
+    let missing: Vec<&str> = required
+        .into_iter()
+        .filter(|feature| !enabled.iter().any(|f| f == feature))
+        .collect();
+    if missing.is_empty() {
+        CheckResult::new("features", Status::Ok, "nix-command and flakes enabled")
+    } else {
+        CheckResult::new(
+            "features",
+            Status::Warn,
+            format!(
+                "{} not enabled in nix.conf; nixy enables them for its own calls, add 'experimental-features = nix-command flakes' to run nix on its flakes by hand",
+                missing.join(" and ")
+            ),
+        )
+    }
+}
+
+/// nixy.json must parse; a missing one is fine until the first install
+fn check_config(config: &Config) -> CheckResult {
+    if !nixy_json_exists(config) {
+        if has_legacy_flake(config) {
+            return CheckResult::new(
+                "config",
+                Status::Warn,
+                "legacy format detected; run any nixy command to migrate to nixy.json",
+            );
+        }
+        return CheckResult::new(
+            "config",
+            Status::Warn,
+            format!(
+                "{} not found; it is created on first install",
+                config.nixy_json.display()
+            ),
+        );
+    }
+
+    match NixyConfig::load(config) {
+        Ok(nixy_config) => CheckResult::new(
+            "config",
+            Status::Ok,
+            format!(
+                "{} ({} profile(s), active: {})",
+                config.nixy_json.display(),
+                nixy_config.profiles.len(),
+                nixy_config.active_profile
+            ),
+        ),
+        Err(e) => CheckResult::new(
+            "config",
+            Status::Fail,
+            format!("{}: {}", config.nixy_json.display(), e),
+        ),
+    }
+}
+
+/// Every legacy profile directory should have made it into nixy.json
+fn check_legacy(config: &Config) -> CheckResult {
+    if !nixy_json_exists(config) {
+        if migration::needs_migration(config) {
+            return CheckResult::new(
+                "legacy",
+                Status::Warn,
+                "legacy configuration not migrated yet; run 'nixy doctor --fix' or any other nixy command",
+            );
+        }
+        return CheckResult::new("legacy", Status::Ok, "no legacy configuration");
+    }
+    let Ok(nixy_config) = NixyConfig::load(config) else {
+        return CheckResult::new("legacy", Status::Ok, "nixy.json not readable; skipped");
+    };
+
+    let names = migration::unmigrated_profiles(config, &nixy_config);
+    if names.is_empty() {
+        CheckResult::new("legacy", Status::Ok, "no legacy profiles left to migrate")
+    } else {
+        CheckResult::new(
+            "legacy",
+            Status::Warn,
+            format!(
+                "legacy profile(s) in {} missing from nixy.json: {}; run 'nixy doctor --fix' to migrate them",
+                config.profiles_dir.display(),
+                names.join(", ")
+            ),
+        )
+    }
+}
+
+/// The active profile's flake.nix and flake.lock are generated by `nixy sync`;
+/// every other profile gets its flake.nix when it is created
+fn check_flake(config: &Config) -> CheckResult {
+    let flake_path = get_flake_path(config);
+    if !flake_path.exists() {
+        return CheckResult::new(
+            "flake",
+            Status::Warn,
+            format!(
+                "no flake.nix for profile '{}'; run 'nixy sync'",
+                get_active_profile(config)
+            ),
+        );
+    }
+
+    let lock_exists = flake_path
+        .parent()
+        .is_some_and(|dir| dir.join("flake.lock").exists());
+    if !lock_exists {
+        return CheckResult::new(
+            "flake",
+            Status::Warn,
+            format!(
+                "{} has no flake.lock; run 'nixy sync'",
+                flake_path.display()
+            ),
+        );
+    }
+
+    let missing: Vec<String> = NixyConfig::load(config)
+        .map(|nixy_config| {
+            nixy_config
+                .profiles
+                .into_keys()
+                .filter(|name| !Profile::new(name, config).flake_path.exists())
+                .collect()
+        })
+        .unwrap_or_default();
+    if !missing.is_empty() {
+        return CheckResult::new(
+            "flake",
+            Status::Warn,
+            format!(
+                "no flake.nix for profile(s): {}; run 'nixy doctor --fix' to generate them from nixy.json",
+                missing.join(", ")
+            ),
+        );
+    }
+
+    CheckResult::new("flake", Status::Ok, flake_path.display().to_string())
+}
+
+/// Profile state directories are flake sources: anything besides the files
+/// nixy manages there is copied into the store on every evaluation
+fn check_state_dirs(config: &Config) -> CheckResult {
+    let Ok(entries) = fs::read_dir(&config.profiles_state_dir) else {
+        return CheckResult::new("state", Status::Ok, "no profile state directories yet");
+    };
+
+    let mut names: Vec<String> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_dir())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+
+    let problems: Vec<String> = names
+        .iter()
+        .filter_map(|name| {
+            let unexpected = Profile::new(name, config).unexpected_entries();
+            (!unexpected.is_empty()).then(|| format!("{}: {}", name, unexpected.join(", ")))
+        })
+        .collect();
+
+    if problems.is_empty() {
+        CheckResult::new(
+            "state",
+            Status::Ok,
+            format!(
+                "{} contains only generated files",
+                config.profiles_state_dir.display()
+            ),
+        )
+    } else {
+        CheckResult::new(
+            "state",
+            Status::Warn,
+            format!(
+                "unexpected files in profile state directories ({}); remove them ('nixy sync' cleans up result links)",
+                problems.join("; ")
+            ),
+        )
+    }
+}
+
+/// A profile without a flake.lock resolves its floating packages fresh on
+/// the next build, which can silently change their versions
+fn check_locks(config: &Config) -> CheckResult {
+    let Ok(nixy_config) = NixyConfig::load(config) else {
+        return CheckResult::new("locks", Status::Ok, "nixy.json not readable; skipped");
+    };
+
+    let unlocked: Vec<(&String, usize)> = nixy_config
+        .profiles
+        .iter()
+        .filter(|(name, _)| {
+            !Profile::new(name, config)
+                .state_dir
+                .join("flake.lock")
+                .exists()
+        })
+        .map(|(name, profile)| (name, profile.packages.len() + profile.custom_packages.len()))
+        .collect();
+
+    if unlocked.is_empty() {
+        return CheckResult::new("locks", Status::Ok, "every profile has a flake.lock");
+    }
+    let names: Vec<&str> = unlocked.iter().map(|(name, _)| name.as_str()).collect();
+    let detail = format!("never built / no lock: {}", names.join(", "));
+    if unlocked.iter().all(|(_, floating)| *floating == 0) {
+        return CheckResult::new("locks", Status::Ok, detail);
+    }
+    CheckResult::new(
+        "locks",
+        Status::Warn,
+        format!(
+            "{}; floating packages there resolve fresh on the next build (seed a lock with 'nixy profile <name> --copy-lock-from <profile>')",
+            detail
+        ),
+    )
+}
+
+/// Profiles whose flake changed without a rebuild (e.g. `nixy update
+/// --profile`) are rebuilt on the next switch or sync
+fn check_needs_sync(config: &Config) -> CheckResult {
+    let Ok(nixy_config) = NixyConfig::load(config) else {
+        return CheckResult::new("sync", Status::Ok, "nixy.json not readable; skipped");
+    };
+
+    let stale: Vec<String> = nixy_config
+        .profiles
+        .keys()
+        .filter_map(|name| {
+            let marker = Profile::new(name, config).needs_sync()?;
+            Some(format!("{}: {}", name, stale_notice(&marker)))
+        })
+        .collect();
+
+    if stale.is_empty() {
+        return CheckResult::new("sync", Status::Ok, "every profile is built");
+    }
+    CheckResult::new(
+        "sync",
+        Status::Warn,
+        format!(
+            "{}; run 'nixy sync' (or switch to the profile) to rebuild",
+            stale.join("; ")
+        ),
+    )
+}
+
+/// Resolved packages whose nixpkgs commit GitHub no longer serves fail every
+/// fresh build; better to find out here than on a new machine
+fn check_commits(config: &Config) -> CheckResult {
+    if Nix::offline() {
+        return CheckResult::new("commits", Status::Ok, "offline; skipped");
+    }
+    let Ok(nixy_config) = NixyConfig::load(config) else {
+        return CheckResult::new("commits", Status::Ok, "nixy.json not readable; skipped");
+    };
+
+    // commit -> "profile: package" entries pinned to it
+    let mut pinned: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (profile_name, profile) in &nixy_config.profiles {
+        for pkg in &profile.resolved_packages {
+            pinned
+                .entry(pkg.commit_hash.clone())
+                .or_default()
+                .push(format!("{}: {}", profile_name, pkg.name));
+        }
+    }
+    if pinned.is_empty() {
+        return CheckResult::new("commits", Status::Ok, "no pinned nixpkgs commits");
+    }
+
+    let commits: Vec<String> = pinned.keys().cloned().collect();
+    let results =
+        CommitChecker::new().check_all(&commits, &config.state_dir.join("commit-check.json"));
+
+    let missing: Vec<String> = results
+        .iter()
+        .filter(|(_, r)| **r == Reachability::Missing)
+        .map(|(commit, _)| format!("{} ({})", short(commit), pinned[commit].join(", ")))
+        .collect();
+    if !missing.is_empty() {
+        return CheckResult::new(
+            "commits",
+            Status::Fail,
+            format!(
+                "no longer on GitHub: {}; run 'nixy update --commit <hash>' (with --profile for other profiles) to re-resolve",
+                missing.join("; ")
+            ),
+        );
+    }
+
+    let unknown: Vec<String> = results
+        .iter()
+        .filter_map(|(commit, r)| match r {
+            Reachability::Unknown(reason) => Some(format!("{} ({})", short(commit), reason)),
+            _ => None,
+        })
+        .collect();
+    if !unknown.is_empty() {
+        return CheckResult::new(
+            "commits",
+            Status::Warn,
+            format!("could not check: {}", unknown.join("; ")),
+        );
+    }
+
+    CheckResult::new(
+        "commits",
+        Status::Ok,
+        format!("{} pinned nixpkgs commit(s) reachable", commits.len()),
+    )
+}
+
+/// Packages installed from a flake without validation (`--no-validate`, or
+/// a check that timed out) may not exist; look them up again now
+fn check_unvalidated(config: &Config) -> CheckResult {
+    let Ok(nixy_config) = NixyConfig::load(config) else {
+        return CheckResult::new("unvalidated", Status::Ok, "nixy.json not readable; skipped");
+    };
+
+    let mut found = 0;
+    let mut missing = Vec::new();
+    let mut unknown = Vec::new();
+    for (profile_name, profile) in &nixy_config.profiles {
+        for pkg in profile.custom_packages.iter().filter(|p| p.unvalidated) {
+            let entry = format!("{}: {} ({})", profile_name, pkg.name, pkg.input_url);
+            match Nix::check_flake_package(
+                &pkg.input_url,
+                pkg.source_package_name(),
+                eval_timeout(),
+            ) {
+                Ok(FlakePackageCheck::Found(_)) => found += 1,
+                Ok(FlakePackageCheck::Missing) => missing.push(entry),
+                Ok(FlakePackageCheck::TimedOut) => unknown.push(format!("{}, timed out", entry)),
+                Err(e) => unknown.push(format!("{}, {}", entry, e)),
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        return CheckResult::new(
+            "unvalidated",
+            Status::Fail,
+            format!(
+                "not found in their flakes: {}; check the name with 'nix flake show <url>' and reinstall",
+                missing.join("; ")
+            ),
+        );
+    }
+    if !unknown.is_empty() {
+        return CheckResult::new(
+            "unvalidated",
+            Status::Warn,
+            format!("could not check: {}", unknown.join("; ")),
+        );
+    }
+    if found == 0 {
+        return CheckResult::new("unvalidated", Status::Ok, "no unvalidated flake packages");
+    }
+    CheckResult::new(
+        "unvalidated",
+        Status::Ok,
+        format!(
+            "{} unvalidated flake package(s) found in their flakes",
+            found
+        ),
+    )
+}
+
+fn short(commit: &str) -> &str {
+    commit.get(..8).unwrap_or(commit)
+}
+
+/// Packages whose local source path is gone are left out of every build
+/// until they are restored or uninstalled
+fn check_local_packages(config: &Config) -> CheckResult {
+    let Ok(nixy_config) = NixyConfig::load(config) else {
+        return CheckResult::new("local", Status::Ok, "nixy.json not readable; skipped");
+    };
+
+    let problems: Vec<String> = nixy_config
+        .profiles
+        .iter()
+        .flat_map(|(profile_name, profile)| {
+            missing_local_packages(profile, Some(&config.global_packages_dir))
+                .into_iter()
+                .map(move |pkg| format!("{}: {} ({})", profile_name, pkg.name, pkg.path.display()))
+        })
+        .collect();
+
+    if problems.is_empty() {
+        return CheckResult::new("local", Status::Ok, "all local package sources exist");
+    }
+    let status = if nixy_config.strict_local {
+        Status::Fail
+    } else {
+        Status::Warn
+    };
+    CheckResult::new(
+        "local",
+        status,
+        format!(
+            "missing local package sources, left out of builds ({}); restore them or uninstall",
+            problems.join("; ")
+        ),
+    )
+}
+
+/// The env symlink must point at a live, readable build in the Nix store
+/// once it exists
+fn check_env(config: &Config) -> CheckResult {
+    let link = &config.env_link;
+    match check_env_link(link, &Nix::store_dir()) {
+        None if link.is_symlink() => {
+            let target = fs::read_link(link)
+                .map(|t| t.display().to_string())
+                .unwrap_or_default();
+            CheckResult::new(
+                "env",
+                Status::Ok,
+                format!("{} -> {}", link.display(), target),
+            )
+        }
+        None => CheckResult::new(
+            "env",
+            Status::Warn,
+            "environment has not been built yet; run 'nixy sync'",
+        ),
+        Some(EnvLinkProblem::Dangling(target)) => CheckResult::new(
+            "env",
+            Status::Fail,
+            format!(
+                "stale: {} points to {}, which is no longer in the Nix store (garbage-collected?); run 'nixy sync'",
+                link.display(),
+                target.display()
+            ),
+        ),
+        Some(EnvLinkProblem::NotSymlink) => CheckResult::new(
+            "env",
+            Status::Fail,
+            format!("{} exists but is not a symlink", link.display()),
+        ),
+        Some(problem @ EnvLinkProblem::OutsideStore(_)) => CheckResult::new(
+            "env",
+            Status::Warn,
+            format!("{}: {}; run 'nixy sync'", link.display(), problem),
+        ),
+        Some(problem @ EnvLinkProblem::Unreadable(..)) => CheckResult::new(
+            "env",
+            Status::Fail,
+            format!("{}: {}; run 'nixy sync'", link.display(), problem),
+        ),
+    }
+}
+
+/// The env's bin directory should be on PATH (see `nixy config <shell>`)
+fn check_path(config: &Config) -> CheckResult {
+    let bin_dir = config.env_link.join("bin");
+    let on_path = std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|p| p == bin_dir));
+    if on_path {
+        CheckResult::new(
+            "path",
+            Status::Ok,
+            format!("{} is on PATH", bin_dir.display()),
+        )
+    } else {
+        CheckResult::new(
+            "path",
+            Status::Warn,
+            format!(
+                "{} is not on PATH; add 'eval \"$(nixy config <shell>)\"' to your shell rc",
+                bin_dir.display()
+            ),
+        )
+    }
+}
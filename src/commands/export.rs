@@ -0,0 +1,190 @@
+use std::fs;
+use std::path::Path;
+
+use crate::cli::ExportArgs;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::flake::format::format_flake;
+use crate::flake::template::generate_standalone_flake;
+use crate::manifest::Manifest;
+use crate::nixy_config::{nixy_json_exists, NixyConfig, ProfileConfig};
+use crate::profile::Profile;
+
+use super::{success, warn};
+
+pub fn run(config: &Config, args: ExportArgs) -> Result<()> {
+    if !nixy_json_exists(config) {
+        return Err(Error::Usage(
+            "nixy export requires nixy.json. Run 'nixy sync' to migrate first.".to_string(),
+        ));
+    }
+    let nixy_config = NixyConfig::load(config)?;
+    let name = args.profile.as_ref().unwrap_or(&nixy_config.active_profile);
+    let profile = nixy_config
+        .profiles
+        .get(name)
+        .ok_or_else(|| Error::ProfileNotFound(name.clone()))?;
+
+    if args.flake {
+        // clap requires --output with --flake
+        let output = args.output.as_deref().unwrap_or_default();
+        return export_flake(config, &nixy_config, name, profile, Path::new(output));
+    }
+
+    let manifest = Manifest::from_profile(name, profile, &config.global_packages_dir)?;
+    let content = manifest.to_json()?;
+
+    match args.output {
+        Some(path) => {
+            fs::write(&path, content)?;
+            success(&format!(
+                "Exported profile '{}' ({} package(s), {} local file(s)) to {}",
+                name,
+                manifest.package_count(),
+                manifest.local_packages.len(),
+                path
+            ));
+        }
+        // Only the manifest goes to stdout, so it can be piped
+        None => print!("{}", content),
+    }
+    Ok(())
+}
+
+/// Write `profile` to `output` as a flake that builds on its own: flake.nix,
+/// the profile's flake.lock, and a copy of the local packages
+///
+/// Only reads the profile's state; `output` must not exist yet or be empty.
+fn export_flake(
+    config: &Config,
+    nixy_config: &NixyConfig,
+    name: &str,
+    profile: &ProfileConfig,
+    output: &Path,
+) -> Result<()> {
+    if fs::read_dir(output).is_ok_and(|mut entries| entries.next().is_some()) {
+        return Err(Error::Usage(format!(
+            "{} is not empty; export the flake to a new directory",
+            output.display()
+        )));
+    }
+
+    let state_dir = Profile::new(name, config).state_dir;
+    let packages_dir = config.global_packages_dir.as_path();
+    let content = generate_standalone_flake(profile, Some(packages_dir), &state_dir)?;
+
+    fs::create_dir_all(output)?;
+    fs::write(
+        output.join("flake.nix"),
+        format_flake(&content, nixy_config.format),
+    )?;
+    let has_lock = state_dir.join("flake.lock").exists();
+    if has_lock {
+        fs::copy(state_dir.join("flake.lock"), output.join("flake.lock"))?;
+    } else {
+        warn(&format!(
+            "Profile '{}' has no flake.lock; the flake will lock its inputs on first use",
+            name
+        ));
+    }
+    let copied = if packages_dir.exists() {
+        copy_resolving_links(packages_dir, &output.join("packages"))?
+    } else {
+        0
+    };
+
+    for pkg in &profile.custom_packages {
+        if is_machine_local(&pkg.input_url) {
+            warn(&format!(
+                "'{}' comes from {}, which only exists on this machine",
+                pkg.name, pkg.input_url
+            ));
+        }
+    }
+
+    success(&format!(
+        "Exported profile '{}' as a flake to {} ({} local file(s){})",
+        name,
+        output.display(),
+        copied,
+        if has_lock {
+            ", with its flake.lock"
+        } else {
+            ""
+        }
+    ));
+    Ok(())
+}
+
+/// Copy the directory `from` to `to`, following symlinks so that the copy
+/// holds their targets; dangling links are skipped. Returns the number of
+/// files copied.
+fn copy_resolving_links(from: &Path, to: &Path) -> Result<usize> {
+    fs::create_dir_all(to)?;
+    let mut copied = 0;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let source = entry.path();
+        let target = to.join(entry.file_name());
+        // fs::metadata follows symlinks, and fails on dangling ones
+        let Ok(metadata) = fs::metadata(&source) else {
+            continue;
+        };
+        if metadata.is_dir() {
+            copied += copy_resolving_links(&source, &target)?;
+        } else {
+            fs::copy(&source, &target)?;
+            copied += 1;
+        }
+    }
+    Ok(copied)
+}
+
+/// Whether a flake URL points at this machine's filesystem
+fn is_machine_local(url: &str) -> bool {
+    ["path:/", "git+file:", "file:"]
+        .iter()
+        .any(|prefix| url.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_copy_resolving_links() {
+        let temp = TempDir::new().unwrap();
+        let elsewhere = temp.path().join("elsewhere");
+        fs::create_dir_all(elsewhere.join("tool")).unwrap();
+        fs::write(elsewhere.join("tool/flake.nix"), "{ }").unwrap();
+        fs::write(elsewhere.join("hello.nix"), "{ hello }: hello").unwrap();
+
+        let packages = temp.path().join("packages");
+        fs::create_dir_all(&packages).unwrap();
+        fs::write(packages.join("own.nix"), "{ jq }: jq").unwrap();
+        std::os::unix::fs::symlink(elsewhere.join("hello.nix"), packages.join("hello.nix"))
+            .unwrap();
+        std::os::unix::fs::symlink(elsewhere.join("tool"), packages.join("tool")).unwrap();
+        std::os::unix::fs::symlink(temp.path().join("gone.nix"), packages.join("gone.nix"))
+            .unwrap();
+
+        let copy = temp.path().join("copy");
+        assert_eq!(copy_resolving_links(&packages, &copy).unwrap(), 3);
+        assert!(!copy.join("hello.nix").is_symlink());
+        assert_eq!(
+            fs::read_to_string(copy.join("hello.nix")).unwrap(),
+            "{ hello }: hello"
+        );
+        assert!(copy.join("tool/flake.nix").is_file());
+        assert!(!copy.join("gone.nix").exists());
+    }
+
+    #[test]
+    fn test_is_machine_local() {
+        assert!(is_machine_local("path:/home/me/tool"));
+        assert!(is_machine_local("git+file:///home/me/tool"));
+        assert!(!is_machine_local("github:user/repo"));
+        assert!(!is_machine_local("path:./packages/tool"));
+    }
+}
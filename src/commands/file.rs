@@ -37,11 +37,16 @@ pub fn run(config: &Config, args: FileArgs) -> Result<()> {
     {
         // Resolved nixpkgs package: get source path via meta.position
         let system = Nix::current_system()?;
-        Nix::get_package_source_path(&resolved.commit_hash, &resolved.attribute_path, &system)?
+        Nix::get_package_source_path(
+            &resolved.commit_hash,
+            resolved.flake_output(),
+            &resolved.attribute_path,
+            &system,
+        )?
     } else if state.packages.contains(&args.package) {
         // Legacy nixpkgs package: use nixos-unstable
         let system = Nix::current_system()?;
-        Nix::get_package_source_path("nixos-unstable", &args.package, &system)?
+        Nix::get_package_source_path("nixos-unstable", "legacyPackages", &args.package, &system)?
     } else if let Some(local_path) = find_local_package(&flake_dir, &args.package) {
         // Local package in the packages/ directory
         local_path
@@ -79,11 +84,16 @@ fn run_with_nixy_config(config: &Config, args: FileArgs) -> Result<()> {
     {
         // Resolved nixpkgs package: get source path via meta.position
         let system = Nix::current_system()?;
-        Nix::get_package_source_path(&resolved.commit_hash, &resolved.attribute_path, &system)?
+        Nix::get_package_source_path(
+            &resolved.commit_hash,
+            resolved.flake_output(),
+            &resolved.attribute_path,
+            &system,
+        )?
     } else if profile.packages.contains(&args.package) {
         // Legacy nixpkgs package: use nixos-unstable
         let system = Nix::current_system()?;
-        Nix::get_package_source_path("nixos-unstable", &args.package, &system)?
+        Nix::get_package_source_path("nixos-unstable", "legacyPackages", &args.package, &system)?
     } else {
         return Err(Error::PackageNotInstalled(args.package));
     };
@@ -107,7 +117,7 @@ fn find_local_package_global(config: &Config, package_name: &str) -> Option<Path
 
 /// Find a local package by its pname/name in the given directory.
 /// Returns the source file path if found.
-fn find_local_package_in_dir(
+pub fn find_local_package_in_dir(
     packages_dir: &std::path::Path,
     package_name: &str,
 ) -> Option<PathBuf> {
@@ -0,0 +1,128 @@
+//! Garbage-collect the Nix store after dropping the GC roots nixy no longer
+//! needs.
+//!
+//! nixy's roots are the build links in `builds/`, one per profile, which
+//! `nix build --out-link` registered. A profile's current build stays a
+//! root; links of profiles that are gone, links whose build is already
+//! gone, and `result` links left in profile directories are removed. Roots
+//! outside nixy's state directory are never touched.
+//!
+//! `--dry-run` lists what a collection would delete right now, and a real
+//! run reports the space nix freed.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cli::GcArgs;
+use crate::config::Config;
+use crate::error::Result;
+use crate::nix::Nix;
+use crate::profile::{get_profile_flake_dir, list_profiles, result_links, Profile};
+
+use super::{info, success};
+
+pub fn run(config: &Config, args: GcArgs) -> Result<()> {
+    let profiles = list_profiles(config)?;
+    let stale = stale_roots(config, &profiles);
+
+    for root in &stale {
+        if args.dry_run {
+            info(&format!("Would remove stale root {}", root.display()));
+        } else {
+            fs::remove_file(root)?;
+            info(&format!("Removed stale root {}", root.display()));
+        }
+    }
+
+    let (built, never_built): (Vec<&str>, Vec<&str>) = profiles
+        .iter()
+        .map(String::as_str)
+        .partition(|name| Profile::new(name, config).last_build().is_some());
+    if !built.is_empty() {
+        info(&format!("Keeping the last build of: {}", built.join(", ")));
+    }
+    for name in never_built {
+        info(&format!("Profile '{}' has no build to keep", name));
+    }
+
+    if args.dry_run {
+        let dead = Nix::dead_paths()?;
+        for path in &dead {
+            println!("{}", path);
+        }
+        info(&format!("{} store path(s) would be deleted", dead.len()));
+        if !stale.is_empty() {
+            info("Not counting paths only the stale roots above keep alive");
+        }
+        return Ok(());
+    }
+
+    match Nix::collect_garbage()? {
+        Some(freed) => success(&format!(
+            "Freed {} ({} store path(s) deleted)",
+            freed.space, freed.paths
+        )),
+        // nix's own output above is all there is to go by
+        None => success("Garbage collection finished"),
+    }
+    Ok(())
+}
+
+/// nixy's GC roots that no profile's current build needs
+fn stale_roots(config: &Config, profiles: &[String]) -> Vec<PathBuf> {
+    let mut stale = stale_build_links(&config.state_dir.join("builds"), profiles);
+    for name in profiles {
+        if let Ok(flake_dir) = get_profile_flake_dir(config, name) {
+            stale.extend(result_links(&flake_dir));
+        }
+    }
+    stale
+}
+
+/// Links in `builds_dir` of profiles not in `profiles`, or whose build is
+/// already gone from the store
+fn stale_build_links(builds_dir: &Path, profiles: &[String]) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(builds_dir) else {
+        return Vec::new();
+    };
+    let mut links: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_symlink())
+        .filter(|path| {
+            let name = path.file_name().map(|n| n.to_string_lossy().into_owned());
+            // exists() follows the link, so it is false for a dangling one
+            !profiles.iter().any(|p| Some(p) == name.as_ref()) || !path.exists()
+        })
+        .collect();
+    links.sort();
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_stale_build_links() {
+        let temp = tempdir().unwrap();
+        let builds = temp.path().join("builds");
+        let store = temp.path().join("store");
+        fs::create_dir_all(&builds).unwrap();
+        fs::create_dir_all(store.join("env-default")).unwrap();
+        fs::create_dir_all(store.join("env-old")).unwrap();
+        symlink(store.join("env-default"), builds.join("default")).unwrap();
+        symlink(store.join("env-old"), builds.join("old")).unwrap();
+        symlink(store.join("collected"), builds.join("work")).unwrap();
+        fs::write(builds.join("notes"), "not a link").unwrap();
+
+        let profiles = vec!["default".to_string(), "work".to_string()];
+        assert_eq!(
+            stale_build_links(&builds, &profiles),
+            vec![builds.join("old"), builds.join("work")]
+        );
+        assert!(stale_build_links(&temp.path().join("missing"), &profiles).is_empty());
+    }
+}
@@ -0,0 +1,188 @@
+use std::fs;
+use std::io::{self, IsTerminal, Read};
+use std::path::Path;
+
+use crate::cli::{ImportArgs, SyncArgs};
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::flake::template::regenerate_flake_from_profile;
+use crate::manifest::{Manifest, UNKNOWN_VERSION};
+use crate::nix::Nix;
+use crate::nixy_config::{nixy_json_exists, NixyConfig, ProfileConfig};
+use crate::profile::{get_active_profile, get_profile_flake_dir, validate_profile_name, Profile};
+
+use super::{assume_yes, confirm, info, success, warn};
+
+pub fn run(config: &Config, args: ImportArgs) -> Result<()> {
+    if !nixy_json_exists(config) {
+        return Err(Error::Usage(
+            "nixy import requires nixy.json. Run 'nixy sync' to migrate first.".to_string(),
+        ));
+    }
+    let mut nixy_config = NixyConfig::load(config)?;
+    let original_config = nixy_config.clone();
+    let packages_dir = &config.global_packages_dir;
+
+    let (manifest, merge) = read_source(&args, &nixy_config.active_profile)?;
+    let name = args.profile.unwrap_or_else(|| manifest.profile.clone());
+    validate_profile_name(&name)?;
+
+    let conflicts = manifest.conflicts(nixy_config.profiles.get(&name), packages_dir);
+    for conflict in &conflicts {
+        warn(&format!(
+            "{}: {} is installed, the import has {}",
+            conflict.name, conflict.existing, conflict.incoming
+        ));
+    }
+    if !conflicts.is_empty() && !args.force {
+        // Asked only when someone can answer; otherwise --force decides
+        let replace = (assume_yes() || io::stdin().is_terminal())
+            && confirm(&format!(
+                "Replace {} conflicting package(s) and file(s)?",
+                conflicts.len()
+            ))?;
+        if !replace {
+            return Err(Error::ImportConflicts(conflicts.len()));
+        }
+    }
+
+    nixy_config.create_profile(&name)?;
+    let profile = nixy_config.profiles.get_mut(&name).unwrap();
+    manifest.apply(profile, merge);
+    nixy_config.save(config)?;
+    let created = manifest.write_local_packages(packages_dir)?;
+    info(&format!(
+        "Imported {} package(s) and {} local file(s) into profile '{}'",
+        manifest.package_count(),
+        manifest.local_packages.len(),
+        name
+    ));
+
+    if get_active_profile(config) != name {
+        let flake_dir = get_profile_flake_dir(config, &name)?;
+        regenerate_flake_from_profile(
+            &flake_dir,
+            &nixy_config.profiles[&name],
+            Some(packages_dir),
+            nixy_config.format,
+        )?;
+        Profile::new(&name, config).mark_needs_sync("nixy import")?;
+        success(&format!(
+            "Profile '{}' will be built when you switch to it",
+            name
+        ));
+        return Ok(());
+    }
+
+    if let Err(e) = super::sync::sync(config, SyncArgs::default()) {
+        // Leave the setup as it was before the import
+        original_config.save(config)?;
+        for path in &created {
+            let _ = fs::remove_file(packages_dir.join(path));
+        }
+        if let Some(profile) = original_config.profiles.get(&name) {
+            let _ = regenerate_flake_from_profile(
+                &get_profile_flake_dir(config, &name)?,
+                profile,
+                Some(packages_dir),
+                original_config.format,
+            );
+        }
+        warn("Build failed. Reverted nixy.json and the new local package files.");
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// The manifest `args.file` holds or stands for, and whether it is merged
+/// into the profile rather than replacing it
+///
+/// A manifest replaces the profile unless `--merge` is given. The packages
+/// of another nixy.json or of a flake.nix nixy generated (for a directory,
+/// the one in it) are always merged, into the active profile by default.
+fn read_source(args: &ImportArgs, active_profile: &str) -> Result<(Manifest, bool)> {
+    let path = Path::new(&args.file);
+    // The local packages of a nixy.json or an exported flake sit next to it
+    let packages_dir = (args.file != "-").then(|| {
+        let dir = if path.is_dir() {
+            path
+        } else {
+            path.parent().unwrap_or(Path::new("."))
+        };
+        dir.join("packages")
+    });
+
+    let content = if args.file == "-" {
+        let mut content = String::new();
+        io::stdin().read_to_string(&mut content)?;
+        content
+    } else if path.is_dir() {
+        fs::read_to_string(path.join("flake.nix"))?
+    } else {
+        fs::read_to_string(path)?
+    };
+
+    let mut manifest = match serde_json::from_str::<serde_json::Value>(&content) {
+        Ok(json) if json.get("profiles").is_some() => Manifest::from_nixy_json(
+            &content,
+            args.from_profile.as_deref(),
+            packages_dir.as_deref(),
+        )?,
+        Ok(_) => {
+            if args.from_profile.is_some() {
+                return Err(Error::Usage(
+                    "--from-profile picks a profile of a nixy.json; a manifest holds just one"
+                        .to_string(),
+                ));
+            }
+            return Ok((Manifest::parse(&content)?, args.merge));
+        }
+        Err(_) => {
+            let (mut manifest, skipped) =
+                Manifest::from_flake(active_profile, &content, packages_dir.as_deref())?;
+            for package in skipped {
+                warn(&format!(
+                    "Skipping '{}': not an entry nixy generates, so there is nothing to import it as",
+                    package
+                ));
+            }
+            look_up_versions(&mut manifest.config);
+            manifest
+        }
+    };
+    manifest.profile = active_profile.to_string();
+    Ok((manifest, true))
+}
+
+/// Fill in the versions a flake.nix doesn't record, from the nixpkgs commit
+/// each package comes from; the ones that can't be looked up stay unknown
+fn look_up_versions(profile: &mut ProfileConfig) {
+    let unknown = profile
+        .resolved_packages
+        .iter()
+        .filter(|pkg| pkg.resolved_version == UNKNOWN_VERSION)
+        .count();
+    if unknown == 0 || Nix::offline() {
+        return;
+    }
+    let Ok(system) = Nix::current_system() else {
+        return;
+    };
+    info(&format!(
+        "Looking up the versions of {} package(s)...",
+        unknown
+    ));
+    for pkg in &mut profile.resolved_packages {
+        if pkg.resolved_version != UNKNOWN_VERSION {
+            continue;
+        }
+        if let Ok(Some(version)) = Nix::nixpkgs_package_version(
+            &pkg.commit_hash,
+            pkg.flake_output(),
+            &pkg.attribute_path,
+            &system,
+        ) {
+            pkg.resolved_version = version;
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Show what nixy knows about one installed package.
+
+use std::path::PathBuf;
+
+use crate::cli::InfoArgs;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::flake::parser::collect_local_packages;
+use crate::nix::Nix;
+use crate::nixhub::NixhubClient;
+use crate::nixy_config::{nixy_json_exists, NixyConfig, ProfileConfig};
+
+use super::list::local_package_path;
+use super::{info, warn};
+
+/// Everything `nixy info` reports about a package
+#[derive(Debug, Default, PartialEq)]
+pub(super) struct PackageInfo {
+    pub(super) name: String,
+    /// Named like in `list --json`: legacy, resolved, custom, local-file or
+    /// local-flake
+    pub(super) kind: &'static str,
+    pub(super) version: Option<String>,
+    pub(super) version_spec: Option<String>,
+    pub(super) commit: Option<String>,
+    pub(super) pinned: bool,
+    pub(super) platforms: Option<Vec<String>>,
+    pub(super) input_url: Option<String>,
+    /// Source file or flake directory of a local package
+    pub(super) path: Option<PathBuf>,
+    /// Profiles whose environment contains the package
+    pub(super) profiles: Vec<String>,
+    /// The Nixhub package to look the latest version up as, for nixpkgs
+    /// packages
+    pub(super) nixhub_name: Option<String>,
+}
+
+pub fn run(config: &Config, args: InfoArgs) -> Result<()> {
+    if !nixy_json_exists(config) {
+        return Err(Error::Usage(
+            "nixy info requires nixy.json. Run 'nixy sync' to migrate first.".to_string(),
+        ));
+    }
+    let nixy_config = NixyConfig::load(config)?;
+    let package = find_package(config, &nixy_config, &args.package)
+        .ok_or_else(|| Error::PackageNotInstalled(args.package.clone()))?;
+
+    let latest = match &package.nixhub_name {
+        Some(name) if !Nix::offline() => match NixhubClient::new().resolve(name, "latest") {
+            Ok(response) => Some(response.version),
+            Err(e) => {
+                warn(&format!("Could not look up the latest version: {}", e));
+                None
+            }
+        },
+        _ => None,
+    };
+
+    if args.json {
+        print_json(&package, latest.as_deref());
+    } else {
+        print_human(&package, latest.as_deref());
+    }
+    Ok(())
+}
+
+/// Look `name` up the way flake generation does: local packages first, then
+/// the active profile, then any other profile
+fn find_package(config: &Config, nixy_config: &NixyConfig, name: &str) -> Option<PackageInfo> {
+    if let Some(package) = find_local_package(config, nixy_config, name) {
+        return Some(package);
+    }
+
+    let profiles: Vec<String> = nixy_config
+        .profiles
+        .iter()
+        .filter(|(_, profile)| profile.has_package(name))
+        .map(|(profile_name, _)| profile_name.clone())
+        .collect();
+    let profile = nixy_config
+        .get_active_profile()
+        .filter(|profile| profile.has_package(name))
+        .or_else(|| nixy_config.profiles.get(profiles.first()?))?;
+    Some(PackageInfo {
+        profiles,
+        ..entry_info(profile, name)?
+    })
+}
+
+/// Package `name` from the global packages directory, if there is one
+pub(super) fn find_local_package(
+    config: &Config,
+    nixy_config: &NixyConfig,
+    name: &str,
+) -> Option<PackageInfo> {
+    let packages_dir = &config.global_packages_dir;
+    if !packages_dir.exists() {
+        return None;
+    }
+    let (local_packages, local_flakes) = collect_local_packages(packages_dir);
+    let kind = if local_packages.iter().any(|p| p.name == name) {
+        "local-file"
+    } else if local_flakes.iter().any(|f| f.name == name) {
+        "local-flake"
+    } else {
+        return None;
+    };
+    Some(PackageInfo {
+        name: name.to_string(),
+        kind,
+        path: local_package_path(packages_dir, name),
+        // Local packages are part of every profile
+        profiles: nixy_config.list_profiles(),
+        ..Default::default()
+    })
+}
+
+/// The details `profile` stores for package `name`
+pub(super) fn entry_info(profile: &ProfileConfig, name: &str) -> Option<PackageInfo> {
+    if let Some(pkg) = profile.get_resolved_package(name) {
+        return Some(PackageInfo {
+            name: name.to_string(),
+            kind: "resolved",
+            version: Some(pkg.resolved_version.clone()),
+            version_spec: pkg.version_spec.clone(),
+            commit: Some(pkg.commit_hash.clone()),
+            pinned: pkg.pinned,
+            platforms: pkg.platforms.clone(),
+            nixhub_name: Some(pkg.source_package_name().to_string()),
+            ..Default::default()
+        });
+    }
+    if let Some(pkg) = profile.custom_packages.iter().find(|p| p.name == name) {
+        return Some(PackageInfo {
+            name: name.to_string(),
+            kind: "custom",
+            platforms: pkg.platforms.clone(),
+            input_url: Some(pkg.input_url.clone()),
+            ..Default::default()
+        });
+    }
+    profile
+        .packages
+        .iter()
+        .any(|p| p == name)
+        .then(|| PackageInfo {
+            name: name.to_string(),
+            kind: "legacy",
+            nixhub_name: Some(name.to_string()),
+            ..Default::default()
+        })
+}
+
+fn print_human(package: &PackageInfo, latest: Option<&str>) {
+    info(&package.name);
+    let field = |label: &str, value: &str| println!("  {:<10} {}", format!("{}:", label), value);
+
+    field("Kind", package.kind);
+    if let Some(version) = &package.version {
+        match &package.version_spec {
+            Some(spec) => field("Version", &format!("{} (requested {})", version, spec)),
+            None => field("Version", version),
+        }
+    }
+    if let Some(latest) = latest {
+        let note = match &package.version {
+            Some(version) if version == latest => " (installed)",
+            Some(_) => " (newer than installed)",
+            None => "",
+        };
+        field("Latest", &format!("{}{}", latest, note));
+    }
+    if let Some(commit) = &package.commit {
+        let pinned = if package.pinned { " (pinned)" } else { "" };
+        field("Commit", &format!("nixpkgs {}{}", commit, pinned));
+    }
+    if let Some(url) = &package.input_url {
+        field("Source", url);
+    }
+    if let Some(path) = &package.path {
+        field("Source", &path.display().to_string());
+    }
+    match &package.platforms {
+        Some(platforms) if !platforms.is_empty() => field("Platforms", &platforms.join(", ")),
+        _ => field("Platforms", "all"),
+    }
+    field("Profiles", &package.profiles.join(", "));
+}
+
+/// Print every field, null where it doesn't apply
+fn print_json(package: &PackageInfo, latest: Option<&str>) {
+    let document = serde_json::json!({
+        "name": package.name,
+        "kind": package.kind,
+        "version": package.version,
+        "version_spec": package.version_spec,
+        "latest_version": latest,
+        "commit": package.commit,
+        "pinned": package.pinned,
+        "platforms": package.platforms,
+        "input_url": package.input_url,
+        "path": package.path.as_ref().map(|p| p.to_string_lossy().into_owned()),
+        "profiles": package.profiles,
+    });
+    println!("{}", document);
+}
@@ -1,19 +1,39 @@
+use std::collections::HashSet;
+
 use crate::cli::InstallArgs;
 use crate::config::Config;
 use crate::error::{Error, Result};
 use crate::flake::template::{regenerate_flake, regenerate_flake_from_profile};
-use crate::nix::Nix;
-use crate::nixhub::{parse_package_spec, NixhubClient};
+use crate::nix::{FlakePackageCheck, Nix};
+use crate::nixhub::{parse_package_spec, NixhubClient, ResolvedPackageInfo};
 use crate::nixy_config::{nixy_json_exists, NixyConfig};
 use crate::profile::get_flake_dir;
 use crate::rollback::{self, RollbackContext};
+use crate::snapshot::{generation_tag, Snapshot};
 use crate::state::{
-    get_state_path, normalize_platforms, CustomPackage, PackageState, ResolvedNixpkgPackage,
+    get_state_path, normalize_platforms, CustomPackage, InstallRecord, PackageState,
+    ResolvedNixpkgPackage,
 };
 
-use super::{info, success, warn};
+use super::{auto_commit, eval_timeout, info, nixhub_client, preview_changes, success, warn};
 
 pub fn run(config: &Config, args: InstallArgs) -> Result<()> {
+    let default_message = format!("nixy: install {}", args.packages.join(", "));
+    // --git-commit asks for a commit with the generated message
+    let commit_message = args
+        .commit_message
+        .clone()
+        .or_else(|| args.git_commit.then(|| default_message.clone()));
+    let dry_run = args.dry_run;
+    let result = install(config, args);
+    // Without --atomic, the packages that were found are installed anyway
+    if !dry_run && matches!(result, Ok(()) | Err(Error::SomePackagesNotFound(_))) {
+        auto_commit(config, commit_message.as_deref(), &default_message);
+    }
+    result
+}
+
+fn install(config: &Config, args: InstallArgs) -> Result<()> {
     // Validate and normalize platform names early
     let platforms = if args.platform.is_empty() {
         None
@@ -21,16 +41,53 @@ pub fn run(config: &Config, args: InstallArgs) -> Result<()> {
         Some(normalize_platforms(&args.platform).map_err(Error::Usage)?)
     };
 
-    // Standard nixpkgs install (via Nixhub)
-    let pkg_spec_str = args.package.ok_or_else(|| {
-        Error::Usage(
-            "Usage: nixy install <package>[@version] or nixy install <flake-ref>".to_string(),
-        )
-    })?;
+    let mut specs = args.packages;
+    let mut seen = HashSet::new();
+    specs.retain(|spec| seen.insert(spec.clone()));
+    if specs.is_empty() {
+        return Err(Error::Usage(
+            "Usage: nixy install <package>[@version]... or nixy install <flake-ref>".to_string(),
+        ));
+    }
+    if args.dry_run && !nixy_json_exists(config) {
+        return Err(Error::Usage(
+            "--dry-run requires nixy.json. Run 'nixy sync' to migrate first.".to_string(),
+        ));
+    }
+    if specs.len() > 1 {
+        if specs.iter().any(|s| s.contains(':')) {
+            return Err(Error::Usage(
+                "Install packages from a flake one at a time: nixy install <flake-ref>".to_string(),
+            ));
+        }
+        if args.output.is_some() || args.install_as.is_some() {
+            return Err(Error::Usage(
+                "--output and --as apply to a single package".to_string(),
+            ));
+        }
+    }
 
     // Check if this looks like a flake reference (github:user/repo, path:./foo, etc.)
     // If so, route through install_from_flake_url instead of Nixhub
-    if pkg_spec_str.contains(':') {
+    if specs[0].contains(':') {
+        let pkg_spec_str = specs.remove(0);
+        if args.pin {
+            return Err(Error::Usage(
+                "--pin only applies to nixpkgs packages; a flake is pinned by its flake.lock"
+                    .to_string(),
+            ));
+        }
+        if args.output.is_some() || args.install_as.is_some() {
+            return Err(Error::Usage(
+                "--output and --as only apply to nixpkgs packages; name a flake's package with <flake-ref>#<package>"
+                    .to_string(),
+            ));
+        }
+        if !args.outputs.is_empty() {
+            return Err(Error::Usage(
+                "--outputs only applies to nixpkgs packages".to_string(),
+            ));
+        }
         let (flake_url, pkg, source_name) =
             if let Some((url, pkg_name)) = pkg_spec_str.split_once('#') {
                 (url.to_string(), pkg_name.to_string(), pkg_name.to_string())
@@ -42,19 +99,73 @@ pub fn run(config: &Config, args: InstallArgs) -> Result<()> {
                 let name = derive_package_name_from_url(&pkg_spec_str);
                 (pkg_spec_str.clone(), name, "default".to_string())
             };
-        return install_from_flake_url(config, &flake_url, &pkg, &source_name, platforms);
+        let flake_url = match &args.rev {
+            Some(rev) => with_rev(&flake_url, rev)?,
+            None => flake_url,
+        };
+        return install_from_flake_url(
+            config,
+            &flake_url,
+            &pkg,
+            &source_name,
+            platforms,
+            !args.no_validate,
+            args.dry_run,
+        );
     }
 
-    // Parse package spec (e.g., "nodejs@20" or "ripgrep")
-    let pkg_spec = parse_package_spec(&pkg_spec_str);
+    if args.no_validate {
+        return Err(Error::Usage(
+            "--no-validate only applies to packages from a flake (nixy install <flake-ref>)"
+                .to_string(),
+        ));
+    }
+    if args.rev.is_some() {
+        return Err(Error::Usage(
+            "--rev only applies to packages from a flake (nixy install <flake-ref> --rev <hash>); pin a nixpkgs package with --pin"
+                .to_string(),
+        ));
+    }
+
+    // Parse package specs (e.g., "nodejs@20" or "ripgrep")
+    let pkg_specs: Vec<_> = specs.iter().map(|s| parse_package_spec(s)).collect();
+    let mut outputs: Vec<String> = Vec::new();
+    for output in args
+        .outputs
+        .iter()
+        .map(|o| o.trim())
+        .filter(|o| !o.is_empty())
+    {
+        if !outputs.iter().any(|o| o == output) {
+            outputs.push(output.to_string());
+        }
+    }
+
+    let requests: Vec<NixpkgsRequest> = pkg_specs
+        .iter()
+        .map(|spec| NixpkgsRequest {
+            name: &spec.name,
+            version: spec.version.as_deref(),
+            pin: args.pin,
+            output: args.output.as_deref(),
+            outputs: (!outputs.is_empty()).then_some(outputs.as_slice()),
+            install_as: args.install_as.as_deref(),
+        })
+        .collect();
+    for request in &requests {
+        request.check()?;
+    }
 
     // Use NixyConfig if available (new format), otherwise fall back to legacy
     if nixy_json_exists(config) {
         return install_with_nixy_config(
             config,
-            &pkg_spec.name,
-            pkg_spec.version.as_deref(),
+            &requests,
             platforms,
+            args.atomic,
+            args.dry_run,
+            Nix::offline(),
+            &nixhub_client(config, args.refresh),
         );
     }
 
@@ -65,44 +176,26 @@ pub fn run(config: &Config, args: InstallArgs) -> Result<()> {
     // Load state
     let mut state = PackageState::load(&state_path)?;
 
-    // Check if package is already installed
-    if state.has_package(&pkg_spec.name) {
-        success(&format!("Package '{}' is already installed", pkg_spec.name));
+    // Skip packages that are already installed
+    let pending = not_installed(&requests, |name| state.has_package(name));
+    if pending.is_empty() {
         return Ok(());
     }
 
-    // Resolve package via Nixhub
-    let version_display = pkg_spec.version.as_deref().unwrap_or("latest");
-    info(&format!(
-        "Resolving {}@{} via Nixhub...",
-        pkg_spec.name, version_display
-    ));
-
-    let client = NixhubClient::new();
-    let resolved = client.resolve_for_current_system(
-        &pkg_spec.name,
-        pkg_spec.version.as_deref().unwrap_or("latest"),
-    )?;
-
-    info(&format!(
-        "Found {} version {} (commit {})",
-        resolved.name,
-        resolved.version,
-        &resolved.commit_hash[..8.min(resolved.commit_hash.len())]
-    ));
+    let client = nixhub_client(config, args.refresh);
+    let (entries, not_found) = resolve_all(&pending, &platforms, |request| {
+        resolve(&client, request.name, request.version, request.pin)
+    })?;
+    require_found(&not_found, &entries, args.atomic)?;
 
     // Save original state for rollback
     let original_state = state.clone();
 
-    // Add resolved package to state
-    state.add_resolved_package(ResolvedNixpkgPackage {
-        name: resolved.name.clone(),
-        version_spec: pkg_spec.version.clone(),
-        resolved_version: resolved.version.clone(),
-        attribute_path: resolved.attribute_path.clone(),
-        commit_hash: resolved.commit_hash.clone(),
-        platforms: platforms.clone(),
-    });
+    // Add the resolved packages to state
+    let installing = describe(&entries);
+    for entry in entries {
+        state.add_resolved_package(entry);
+    }
     state.save(&state_path)?;
 
     // Regenerate flake.nix (rollback state if this fails)
@@ -119,11 +212,8 @@ pub fn run(config: &Config, args: InstallArgs) -> Result<()> {
         original_state.clone(),
     ));
 
-    info(&format!(
-        "Installing {}@{}...",
-        resolved.name, resolved.version
-    ));
-    if let Err(e) = super::sync::run(config) {
+    info(&format!("Installing {}...", installing));
+    if let Err(e) = super::sync::sync(config, Default::default()) {
         // Clear rollback context since we're handling the error here
         rollback::clear_context();
         // Sync failed, revert state and flake
@@ -136,63 +226,224 @@ pub fn run(config: &Config, args: InstallArgs) -> Result<()> {
     // Clear rollback context on success
     rollback::clear_context();
 
+    report_not_found(not_found)
+}
+
+/// A nixpkgs package as given to `nixy install`
+struct NixpkgsRequest<'a> {
+    /// Package name on Nixhub
+    name: &'a str,
+    version: Option<&'a str>,
+    pin: bool,
+    /// Output to link instead of the default one (`--output`)
+    output: Option<&'a str>,
+    /// Outputs to link instead of the default ones (`--outputs`)
+    outputs: Option<&'a [String]>,
+    /// Name to install the package as (`--as`)
+    install_as: Option<&'a str>,
+}
+
+impl NixpkgsRequest<'_> {
+    /// Reject `--output`, `--outputs` and `--as` values that cannot be flake
+    /// attributes
+    fn check(&self) -> Result<()> {
+        let is_identifier = |s: &str| {
+            s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && s.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        };
+        let outputs = self.outputs.unwrap_or_default().iter();
+        let values = [("--output", self.output), ("--as", self.install_as)]
+            .into_iter()
+            .chain(outputs.map(|o| ("--outputs", Some(o.as_str()))));
+        for (flag, value) in values {
+            if let Some(value) = value.filter(|v| !is_identifier(v)) {
+                return Err(Error::Usage(format!(
+                    "Invalid {} '{}'. Use letters, numbers, dashes and underscores, starting with a letter.",
+                    flag, value
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Name of the profile entry for the package called `name`: `--as`,
+    /// else `<name>-<output>` with `--output`, else `name` itself
+    fn entry_name(&self, name: &str) -> String {
+        match (self.install_as, self.output) {
+            (Some(alias), _) => alias.to_string(),
+            (None, Some(output)) => format!("{}-{}", name, output),
+            (None, None) => name.to_string(),
+        }
+    }
+
+    /// The profile entry for `resolved`
+    fn entry(
+        &self,
+        resolved: &ResolvedPackageInfo,
+        platforms: Option<Vec<String>>,
+    ) -> ResolvedNixpkgPackage {
+        let name = self.entry_name(&resolved.name);
+        ResolvedNixpkgPackage {
+            source_name: (name != resolved.name).then(|| resolved.name.clone()),
+            name,
+            version_spec: self.version.map(String::from),
+            resolved_version: resolved.version.clone(),
+            attribute_path: resolved.attribute_path.clone(),
+            commit_hash: resolved.commit_hash.clone(),
+            platforms,
+            pinned: self.pin,
+            output: self.output.map(String::from),
+            package_output: None,
+            outputs: self.outputs.map(<[String]>::to_vec),
+            record: InstallRecord::now(),
+        }
+    }
+}
+
+/// Skip the requests whose entry is already installed, saying so
+fn not_installed<'r, 'a>(
+    requests: &'r [NixpkgsRequest<'a>],
+    is_installed: impl Fn(&str) -> bool,
+) -> Vec<&'r NixpkgsRequest<'a>> {
+    requests
+        .iter()
+        .filter(|request| {
+            let entry_name = request.entry_name(request.name);
+            if is_installed(&entry_name) {
+                success(&format!("Package '{}' is already installed", entry_name));
+                return false;
+            }
+            true
+        })
+        .collect()
+}
+
+/// Resolve every request into its profile entry with `resolve`
+///
+/// With several packages, all of them are tried, and the ones that could not
+/// be found (or not at the requested version) are returned alongside the
+/// entries instead of failing.
+fn resolve_all(
+    requests: &[&NixpkgsRequest],
+    platforms: &Option<Vec<String>>,
+    resolve: impl Fn(&NixpkgsRequest) -> Result<ResolvedPackageInfo>,
+) -> Result<(Vec<ResolvedNixpkgPackage>, Vec<String>)> {
+    let mut entries = Vec::new();
+    let mut not_found = Vec::new();
+    for request in requests {
+        match resolve(request) {
+            Ok(resolved) => entries.push(request.entry(&resolved, platforms.clone())),
+            Err(
+                e @ (Error::PackageNotFound(_)
+                | Error::NixhubPackageNotFound(_)
+                | Error::NixhubVersionNotFound(..)
+                | Error::NotResolvedOffline(_)),
+            ) if requests.len() > 1 => {
+                warn(&e.to_string());
+                not_found.push(match request.version {
+                    Some(version) => format!("{}@{}", request.name, version),
+                    None => request.name.to_string(),
+                });
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok((entries, not_found))
+}
+
+/// Fail before anything is written if none of the packages was found, or
+/// with `atomic` (`--atomic`) if any of them was not
+fn require_found(
+    not_found: &[String],
+    entries: &[ResolvedNixpkgPackage],
+    atomic: bool,
+) -> Result<()> {
+    if !not_found.is_empty() && (atomic || entries.is_empty()) {
+        return Err(Error::PackagesNotFound(not_found.join(", ")));
+    }
     Ok(())
 }
 
-/// Install a package using the new nixy.json format
+/// After installing the packages that were found, fail naming the others
+fn report_not_found(not_found: Vec<String>) -> Result<()> {
+    if not_found.is_empty() {
+        return Ok(());
+    }
+    Err(Error::SomePackagesNotFound(not_found.join(", ")))
+}
+
+/// `name@version` of each entry, for progress messages
+fn describe(entries: &[ResolvedNixpkgPackage]) -> String {
+    entries
+        .iter()
+        .map(|e| format!("{}@{}", e.name, e.resolved_version))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Install packages using the new nixy.json format
+///
+/// With `dry_run`, only shows the changes to nixy.json and flake.nix. With
+/// `offline`, packages are not resolved on Nixhub but taken from
+/// [`previously_resolved`].
 fn install_with_nixy_config(
     config: &Config,
-    name: &str,
-    version: Option<&str>,
+    requests: &[NixpkgsRequest],
     platforms: Option<Vec<String>>,
+    atomic: bool,
+    dry_run: bool,
+    offline: bool,
+    client: &NixhubClient,
 ) -> Result<()> {
     let mut nixy_config = NixyConfig::load(config)?;
     let active_profile = nixy_config.active_profile.clone();
 
-    // Check if package is already installed (scope the borrow)
-    {
+    // Skip packages that are already installed (scope the borrow)
+    let pending = {
         let profile = nixy_config
             .get_active_profile()
             .ok_or_else(|| Error::ProfileNotFound(active_profile.clone()))?;
-        if profile.has_package(name) {
-            success(&format!("Package '{}' is already installed", name));
-            return Ok(());
-        }
+        not_installed(requests, |name| profile.has_package(name))
+    };
+    if pending.is_empty() {
+        return Ok(());
     }
 
-    // Resolve package via Nixhub
-    let version_display = version.unwrap_or("latest");
-    info(&format!(
-        "Resolving {}@{} via Nixhub...",
-        name, version_display
-    ));
-
-    let client = NixhubClient::new();
-    let resolved = client.resolve_for_current_system(name, version.unwrap_or("latest"))?;
-
-    info(&format!(
-        "Found {} version {} (commit {})",
-        resolved.name,
-        resolved.version,
-        &resolved.commit_hash[..8.min(resolved.commit_hash.len())]
-    ));
+    let (entries, not_found) = if offline {
+        let known = previously_resolved(config, &nixy_config)?;
+        resolve_all(&pending, &platforms, |request| {
+            resolve_offline(&known, request.name, request.version).or_else(|e| {
+                // Nixhub's answer from before, if it was cached
+                client
+                    .resolve_for_current_system(request.name, request.version.unwrap_or("latest"))
+                    .map_err(|_| e)
+            })
+        })?
+    } else {
+        resolve_all(&pending, &platforms, |request| {
+            resolve(client, request.name, request.version, request.pin)
+        })?
+    };
+    require_found(&not_found, &entries, atomic)?;
 
     // Save original config for rollback BEFORE mutating
     let original_config = nixy_config.clone();
 
-    // Add resolved package to profile
+    // Add the resolved packages to the profile in one pass
+    let installing = describe(&entries);
     {
         let profile = nixy_config
             .get_active_profile_mut()
             .ok_or_else(|| Error::ProfileNotFound(active_profile.clone()))?;
-        profile.add_resolved_package(ResolvedNixpkgPackage {
-            name: resolved.name.clone(),
-            version_spec: version.map(String::from),
-            resolved_version: resolved.version.clone(),
-            attribute_path: resolved.attribute_path.clone(),
-            commit_hash: resolved.commit_hash.clone(),
-            platforms: platforms.clone(),
-        });
+        for entry in entries {
+            profile.add_resolved_package(entry);
+        }
+    }
+    if dry_run {
+        preview_changes(config, &original_config, &nixy_config)?;
+        return report_not_found(not_found);
     }
     nixy_config.save(config)?;
 
@@ -204,9 +455,12 @@ fn install_with_nixy_config(
         None
     };
     let profile_for_flake = nixy_config.get_active_profile().unwrap();
-    if let Err(e) =
-        regenerate_flake_from_profile(&flake_dir, profile_for_flake, global_packages_dir)
-    {
+    if let Err(e) = regenerate_flake_from_profile(
+        &flake_dir,
+        profile_for_flake,
+        global_packages_dir,
+        nixy_config.format,
+    ) {
         original_config.save(config)?;
         warn("Failed to regenerate flake.nix. Reverted changes.");
         return Err(e);
@@ -220,17 +474,19 @@ fn install_with_nixy_config(
         global_packages_dir,
     ));
 
-    info(&format!(
-        "Installing {}@{}...",
-        resolved.name, resolved.version
-    ));
-    if let Err(e) = super::sync::run(config) {
+    info(&format!("Installing {}...", installing));
+    if let Err(e) = super::sync::sync(config, Default::default()) {
         // Clear rollback context since we're handling the error here
         rollback::clear_context();
         // Sync failed, revert config
         original_config.save(config)?;
         let original_profile = original_config.get_active_profile().unwrap();
-        let _ = regenerate_flake_from_profile(&flake_dir, original_profile, global_packages_dir);
+        let _ = regenerate_flake_from_profile(
+            &flake_dir,
+            original_profile,
+            global_packages_dir,
+            original_config.format,
+        );
         warn("Sync failed. Reverted changes.");
         return Err(e);
     }
@@ -238,7 +494,178 @@ fn install_with_nixy_config(
     // Clear rollback context on success
     rollback::clear_context();
 
-    Ok(())
+    report_not_found(not_found)
+}
+
+/// A package for a profile, resolved the way `nixy install` resolves it
+pub(super) enum ProfileEntry {
+    Resolved(ResolvedNixpkgPackage),
+    Custom(CustomPackage),
+}
+
+/// Resolve `spec` (`<name>[@version]` or a flake reference) into the entry
+/// `nixy install` would add for it, without touching any profile
+pub(super) fn resolve_entry(client: &NixhubClient, spec: &str) -> Result<ProfileEntry> {
+    if !spec.contains(':') {
+        let spec = parse_package_spec(spec);
+        let request = NixpkgsRequest {
+            name: &spec.name,
+            version: spec.version.as_deref(),
+            pin: false,
+            output: None,
+            outputs: None,
+            install_as: None,
+        };
+        let resolved = resolve(client, request.name, request.version, request.pin)?;
+        return Ok(ProfileEntry::Resolved(request.entry(&resolved, None)));
+    }
+
+    let (flake_url, pkg, source_name) = match spec.split_once('#') {
+        Some((url, pkg)) => (url, pkg.to_string(), pkg.to_string()),
+        None => (
+            spec,
+            derive_package_name_from_url(spec),
+            "default".to_string(),
+        ),
+    };
+    let input_name = derive_input_name_from_url(flake_url);
+    let (effective_source_name, pkg_output, validated) =
+        validate_and_resolve_flake_package(flake_url, &pkg, &source_name, &input_name, true)?;
+    Ok(ProfileEntry::Custom(CustomPackage {
+        source_name: (effective_source_name != pkg).then_some(effective_source_name),
+        name: pkg,
+        input_name,
+        input_url: flake_url.to_string(),
+        package_output: pkg_output,
+        platforms: None,
+        unvalidated: !validated,
+        record: InstallRecord::now(),
+    }))
+}
+
+/// Resolve `name` for the current system through Nixhub, or with `pin` and
+/// no version in nixpkgs at the commit nixos-unstable points at now
+fn resolve(
+    client: &NixhubClient,
+    name: &str,
+    version: Option<&str>,
+    pin: bool,
+) -> Result<ResolvedPackageInfo> {
+    let resolved = if pin && version.is_none() {
+        info(&format!(
+            "Resolving {} at the current nixos-unstable...",
+            name
+        ));
+        let commit = Nix::nixpkgs_branch_commit("nixos-unstable")?;
+        let version =
+            Nix::nixpkgs_package_version(&commit, "legacyPackages", name, &Nix::current_system()?)?
+                .ok_or_else(|| Error::PackageNotFound(name.to_string()))?;
+        ResolvedPackageInfo {
+            name: name.to_string(),
+            version,
+            attribute_path: name.to_string(),
+            commit_hash: commit,
+        }
+    } else {
+        info(&format!(
+            "Resolving {}@{} via Nixhub...",
+            name,
+            version.unwrap_or("latest")
+        ));
+        client.resolve_for_current_system(name, version.unwrap_or("latest"))?
+    };
+
+    info(&format!(
+        "Found {} version {} (commit {})",
+        resolved.name,
+        resolved.version,
+        &resolved.commit_hash[..8.min(resolved.commit_hash.len())]
+    ));
+    Ok(resolved)
+}
+
+/// Resolved packages nixy has built before, with where they are from: the
+/// active profile, the other profiles, then each profile's generations,
+/// newest first
+fn previously_resolved(
+    config: &Config,
+    nixy_config: &NixyConfig,
+) -> Result<Vec<(String, ResolvedNixpkgPackage)>> {
+    let mut names = vec![nixy_config.active_profile.clone()];
+    names.extend(
+        nixy_config
+            .list_profiles()
+            .into_iter()
+            .filter(|name| *name != nixy_config.active_profile),
+    );
+
+    let mut known = Vec::new();
+    for name in &names {
+        if let Some(profile) = nixy_config.profiles.get(name) {
+            let source = format!("profile '{}'", name);
+            known.extend(
+                profile
+                    .resolved_packages
+                    .iter()
+                    .map(|pkg| (source.clone(), pkg.clone())),
+            );
+        }
+    }
+    for name in &names {
+        for generation in Snapshot::list(config, &generation_tag(name))?.iter().rev() {
+            // An unreadable generation just has nothing to offer
+            let Ok(profile) = generation.profile(name) else {
+                continue;
+            };
+            let source = format!("an earlier generation of profile '{}'", name);
+            known.extend(
+                profile
+                    .resolved_packages
+                    .into_iter()
+                    .map(|pkg| (source.clone(), pkg)),
+            );
+        }
+    }
+    Ok(known)
+}
+
+/// Resolve `name` to the first of `known` it was resolved to before,
+/// preferring one requested the same way (at `version`, or without one)
+fn resolve_offline(
+    known: &[(String, ResolvedNixpkgPackage)],
+    name: &str,
+    version: Option<&str>,
+) -> Result<ResolvedPackageInfo> {
+    let candidates = || {
+        known
+            .iter()
+            .filter(|(_, pkg)| pkg.source_package_name() == name)
+    };
+    let (source, pkg) = candidates()
+        .find(|(_, pkg)| pkg.version_spec.as_deref() == version)
+        .or_else(|| {
+            candidates().find(|(_, pkg)| version.map_or(true, |v| pkg.resolved_version == v))
+        })
+        .ok_or_else(|| {
+            Error::NotResolvedOffline(match version {
+                Some(version) => format!("{}@{}", name, version),
+                None => name.to_string(),
+            })
+        })?;
+
+    info(&format!(
+        "Reusing {} version {} (commit {}) from {}",
+        name,
+        pkg.resolved_version,
+        &pkg.commit_hash[..8.min(pkg.commit_hash.len())],
+        source
+    ));
+    Ok(ResolvedPackageInfo {
+        name: name.to_string(),
+        version: pkg.resolved_version.clone(),
+        attribute_path: pkg.attribute_path.clone(),
+        commit_hash: pkg.commit_hash.clone(),
+    })
 }
 
 /// Try to validate a flake package, with smart fallback.
@@ -247,24 +674,66 @@ fn install_with_nixy_config(
 /// If that fails and `source_name == "default"` and `pkg != "default"`,
 /// tries `pkg` as a fallback (e.g., the repo name like "realm").
 ///
-/// Returns `(effective_source_name, pkg_output)` on success.
+/// Without `validate`, or when the flake takes longer than `--timeout` to
+/// evaluate, `source_name` is taken as is under a guessed `packages` output.
+///
+/// Returns `(effective_source_name, pkg_output, validated)` on success.
 fn validate_and_resolve_flake_package(
     flake_url: &str,
     pkg: &str,
     source_name: &str,
     input_name: &str,
-) -> Result<(String, String)> {
+    validate: bool,
+) -> Result<(String, String, bool)> {
+    let unvalidated = || Ok((source_name.to_string(), "packages".to_string(), false));
+    if !validate || Nix::offline() {
+        info(&format!(
+            "Not validating package '{}' in {} ({})",
+            source_name,
+            input_name,
+            if validate {
+                "--offline"
+            } else {
+                "--no-validate"
+            }
+        ));
+        return unvalidated();
+    }
+
+    info(&format!(
+        "Validating package '{}' in {}...",
+        source_name, input_name
+    ));
+    let timeout = eval_timeout();
+    let timed_out = || {
+        warn(&format!(
+            "Validating '{}' in {} took longer than {}s; installing without validation. A wrong name will fail the build instead (raise --timeout to wait longer).",
+            source_name,
+            input_name,
+            timeout.as_secs()
+        ));
+        unvalidated()
+    };
+
     // Try the requested source_name first
-    if let Some(pkg_output) = Nix::validate_flake_package(flake_url, source_name)? {
-        return Ok((source_name.to_string(), pkg_output));
+    match Nix::check_flake_package(flake_url, source_name, timeout)? {
+        FlakePackageCheck::Found(pkg_output) => {
+            return Ok((source_name.to_string(), pkg_output, true));
+        }
+        FlakePackageCheck::TimedOut => return timed_out(),
+        FlakePackageCheck::Missing => {}
     }
 
     // Fallback: if source_name is "default" and pkg is different, try pkg as the attribute
     let tried_fallback = source_name == "default" && pkg != "default";
     if tried_fallback {
         info(&format!("Package 'default' not found, trying '{}'...", pkg));
-        if let Some(pkg_output) = Nix::validate_flake_package(flake_url, pkg)? {
-            return Ok((pkg.to_string(), pkg_output));
+        match Nix::check_flake_package(flake_url, pkg, timeout)? {
+            FlakePackageCheck::Found(pkg_output) => {
+                return Ok((pkg.to_string(), pkg_output, true));
+            }
+            FlakePackageCheck::TimedOut => return timed_out(),
+            FlakePackageCheck::Missing => {}
         }
     }
 
@@ -307,6 +776,8 @@ fn install_from_flake_url(
     pkg: &str,
     source_name: &str,
     platforms: Option<Vec<String>>,
+    validate: bool,
+    dry_run: bool,
 ) -> Result<()> {
     // Use NixyConfig if available (new format)
     if nixy_json_exists(config) {
@@ -316,6 +787,8 @@ fn install_from_flake_url(
             pkg,
             source_name,
             platforms,
+            validate,
+            dry_run,
         );
     }
 
@@ -336,12 +809,8 @@ fn install_from_flake_url(
     let input_name = derive_input_name_from_url(flake_url);
 
     // Validate the package exists (with smart fallback for no-fragment URLs)
-    info(&format!(
-        "Validating package '{}' in {}...",
-        source_name, input_name
-    ));
-    let (effective_source_name, pkg_output) =
-        validate_and_resolve_flake_package(flake_url, pkg, source_name, &input_name)?;
+    let (effective_source_name, pkg_output, validated) =
+        validate_and_resolve_flake_package(flake_url, pkg, source_name, &input_name, validate)?;
 
     // Save original state for rollback
     let original_state = state.clone();
@@ -359,6 +828,8 @@ fn install_from_flake_url(
         package_output: pkg_output,
         source_name: stored_source_name,
         platforms,
+        unvalidated: !validated,
+        record: InstallRecord::now(),
     });
     state.save(&state_path)?;
 
@@ -377,7 +848,7 @@ fn install_from_flake_url(
     ));
 
     info(&format!("Installing {} from {}...", pkg, input_name));
-    if let Err(e) = super::sync::run(config) {
+    if let Err(e) = super::sync::sync(config, Default::default()) {
         // Clear rollback context since we're handling the error here
         rollback::clear_context();
         // Sync failed, revert state and flake
@@ -400,6 +871,8 @@ fn install_from_flake_url_with_nixy_config(
     pkg: &str,
     source_name: &str,
     platforms: Option<Vec<String>>,
+    validate: bool,
+    dry_run: bool,
 ) -> Result<()> {
     let mut nixy_config = NixyConfig::load(config)?;
     let active_profile = nixy_config.active_profile.clone();
@@ -419,12 +892,8 @@ fn install_from_flake_url_with_nixy_config(
     let input_name = derive_input_name_from_url(flake_url);
 
     // Validate the package exists using the source attribute name
-    info(&format!(
-        "Validating package '{}' in {}...",
-        source_name, input_name
-    ));
-    let (effective_source_name, pkg_output) =
-        validate_and_resolve_flake_package(flake_url, pkg, source_name, &input_name)?;
+    let (effective_source_name, pkg_output, validated) =
+        validate_and_resolve_flake_package(flake_url, pkg, source_name, &input_name, validate)?;
 
     // Save original config for rollback BEFORE mutating
     let original_config = nixy_config.clone();
@@ -446,8 +915,13 @@ fn install_from_flake_url_with_nixy_config(
             package_output: pkg_output,
             source_name: stored_source_name,
             platforms,
+            unvalidated: !validated,
+            record: InstallRecord::now(),
         });
     }
+    if dry_run {
+        return preview_changes(config, &original_config, &nixy_config);
+    }
     nixy_config.save(config)?;
 
     // Regenerate flake.nix
@@ -458,9 +932,12 @@ fn install_from_flake_url_with_nixy_config(
         None
     };
     let profile_for_flake = nixy_config.get_active_profile().unwrap();
-    if let Err(e) =
-        regenerate_flake_from_profile(&flake_dir, profile_for_flake, global_packages_dir)
-    {
+    if let Err(e) = regenerate_flake_from_profile(
+        &flake_dir,
+        profile_for_flake,
+        global_packages_dir,
+        nixy_config.format,
+    ) {
         original_config.save(config)?;
         warn("Failed to regenerate flake.nix. Reverted changes.");
         return Err(e);
@@ -475,12 +952,17 @@ fn install_from_flake_url_with_nixy_config(
     ));
 
     info(&format!("Installing {} from {}...", pkg, input_name));
-    if let Err(e) = super::sync::run(config) {
+    if let Err(e) = super::sync::sync(config, Default::default()) {
         // Clear rollback context since we're handling the error here
         rollback::clear_context();
         original_config.save(config)?;
         let original_profile = original_config.get_active_profile().unwrap();
-        let _ = regenerate_flake_from_profile(&flake_dir, original_profile, global_packages_dir);
+        let _ = regenerate_flake_from_profile(
+            &flake_dir,
+            original_profile,
+            global_packages_dir,
+            original_config.format,
+        );
         warn("Sync failed. Reverted changes.");
         return Err(e);
     }
@@ -491,6 +973,38 @@ fn install_from_flake_url_with_nixy_config(
     Ok(())
 }
 
+/// `flake_url` fixed at git commit `rev`, as the `?rev=` parameter nix
+/// understands for every git-based flake reference
+fn with_rev(flake_url: &str, rev: &str) -> Result<String> {
+    if rev.len() != 40 || !rev.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::Usage(format!(
+            "--rev needs a full 40-character commit hash, not '{}'",
+            rev
+        )));
+    }
+    let scheme = flake_url.split_once(':').map_or("", |(scheme, _)| scheme);
+    if !matches!(scheme, "github" | "gitlab" | "sourcehut" | "git") && !scheme.starts_with("git+") {
+        return Err(Error::Usage(format!(
+            "--rev needs a git flake reference (github:, gitlab:, sourcehut: or git+...), not '{}'",
+            flake_url
+        )));
+    }
+    let (base, query) = flake_url.split_once('?').unwrap_or((flake_url, ""));
+    let names_ref = query
+        .split('&')
+        .any(|p| p.starts_with("rev=") || p.starts_with("ref="))
+        || (scheme != "git" && !scheme.starts_with("git+") && base.split('/').count() > 2);
+    if names_ref {
+        return Err(Error::Usage(format!(
+            "'{}' already names a branch, tag or commit; drop it or --rev",
+            flake_url
+        )));
+    }
+    let separator = if query.is_empty() { '?' } else { '&' };
+    let flake_url = flake_url.trim_end_matches('?');
+    Ok(format!("{}{}rev={}", flake_url, separator, rev))
+}
+
 /// Sanitize a string for use as an input name
 fn sanitize_input_name(s: &str) -> String {
     let sanitized: String = s
@@ -508,7 +1022,22 @@ fn sanitize_input_name(s: &str) -> String {
 
 /// Derive a package name from a flake URL (uses the last path component, e.g., repo name)
 /// For "github:user/repo" → "repo", for "path:./foo/bar" → "bar"
+///
+/// Flakes in a repository subdirectory (`?dir=tools/foo`) are named after the
+/// subdirectory instead.
 fn derive_package_name_from_url(url: &str) -> String {
+    let (url, dir) = split_flake_dir(url);
+    if let Some(subdir) = dir {
+        return sanitize_input_name(subdir);
+    }
+
+    if let Some(segments) = git_url_path_segments(url) {
+        return segments
+            .last()
+            .map(|repo| sanitize_input_name(repo))
+            .unwrap_or_else(|| "default".to_string());
+    }
+
     // Strip the scheme (everything before and including ':')
     let path = url.split_once(':').map(|(_, p)| p).unwrap_or(url);
     // Take the last path component
@@ -526,7 +1055,31 @@ fn derive_package_name_from_url(url: &str) -> String {
 }
 
 /// Derive an input name from a flake URL
+///
+/// Git URLs use the last two path segments, so nested GitLab groups like
+/// "git+https://gitlab.com/group/sub/repo.git" become "sub-repo". Query
+/// parameters are not part of the name, except that a `?dir=` subdirectory
+/// is appended so several flakes from one monorepo get distinct inputs.
 fn derive_input_name_from_url(url: &str) -> String {
+    let (url, dir) = split_flake_dir(url);
+    let name = derive_repo_input_name(url);
+    match dir {
+        Some(subdir) => sanitize_input_name(&format!("{}-{}", name, subdir)),
+        None => name,
+    }
+}
+
+/// Input name for a flake reference without query parameters
+fn derive_repo_input_name(url: &str) -> String {
+    if let Some(segments) = git_url_path_segments(url) {
+        let name = match segments.as_slice() {
+            [] => return "custom-flake".to_string(),
+            [repo] => repo.to_string(),
+            [.., group, repo] => format!("{}-{}", group, repo),
+        };
+        return sanitize_input_name(&name);
+    }
+
     // Try to extract owner-repo from URL
     let parts: Vec<&str> = url.split('/').collect();
     if parts.len() >= 2 {
@@ -538,6 +1091,58 @@ fn derive_input_name_from_url(url: &str) -> String {
     }
 }
 
+/// Split a flake reference into the repository it names, without a query
+/// string or a `github:o/r/<ref>` branch or commit, and the last path
+/// segment of its `dir` parameter, if any.
+/// "github:o/r?ref=v1&dir=sub/tool#pkg" → ("github:o/r", Some("tool"))
+fn split_flake_dir(url: &str) -> (&str, Option<&str>) {
+    let url = url.split('#').next().unwrap_or(url);
+    let (base, query) = url.split_once('?').unwrap_or((url, ""));
+    let subdir = query
+        .split('&')
+        .find_map(|param| param.strip_prefix("dir="))
+        .and_then(|dir| dir.trim_end_matches('/').rsplit('/').next())
+        .filter(|subdir| !subdir.is_empty());
+    (strip_ref_segment(base), subdir)
+}
+
+/// `github:`, `gitlab:` and `sourcehut:` references name a branch or commit
+/// as a third path segment; the repository is the part before it
+fn strip_ref_segment(url: &str) -> &str {
+    let Some((scheme, path)) = url.split_once(':') else {
+        return url;
+    };
+    if !matches!(scheme, "github" | "gitlab" | "sourcehut") {
+        return url;
+    }
+    match path.match_indices('/').nth(1) {
+        Some((i, _)) => &url[..scheme.len() + 1 + i],
+        None => url,
+    }
+}
+
+/// Split a git URL (git+https://, git+ssh://, git+file://, git://) into its
+/// repository path segments, dropping the host, any query string, and a
+/// trailing ".git". Returns None for other flake references.
+fn git_url_path_segments(url: &str) -> Option<Vec<&str>> {
+    let rest = if let Some(rest) = url.strip_prefix("git+") {
+        rest.split_once("://")?.1
+    } else {
+        url.strip_prefix("git://")?
+    };
+
+    let path = rest.split(['?', '#']).next().unwrap_or_default();
+    // The first component is the authority (e.g., "git@gitlab.com:2222")
+    let (_, path) = path.split_once('/')?;
+
+    let mut segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    if let Some(last) = segments.last_mut() {
+        *last = last.trim_end_matches(".git");
+    }
+    segments.retain(|s| !s.is_empty());
+    Some(segments)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -565,6 +1170,120 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_derive_input_name_from_git_urls() {
+        // Nested GitLab groups use the last two segments
+        assert_eq!(
+            derive_input_name_from_url("git+https://gitlab.com/group/sub/repo.git"),
+            "sub-repo"
+        );
+        assert_eq!(
+            derive_input_name_from_url("git+https://gitlab.com/group/repo"),
+            "group-repo"
+        );
+        // SSH URLs: the user, host, and port are not part of the name
+        assert_eq!(
+            derive_input_name_from_url("git+ssh://git@gitlab.com/group/sub/repo.git"),
+            "sub-repo"
+        );
+        assert_eq!(
+            derive_input_name_from_url("git+ssh://git@example.com:2222/team/tool"),
+            "team-tool"
+        );
+        // Query strings and .git suffixes are dropped
+        assert_eq!(
+            derive_input_name_from_url("git+https://example.com/org/repo.git?ref=main&rev=abc"),
+            "org-repo"
+        );
+        assert_eq!(
+            derive_input_name_from_url("git://example.com/repo.git"),
+            "repo"
+        );
+        assert_eq!(
+            derive_input_name_from_url("git+file:///home/me/src/tool"),
+            "src-tool"
+        );
+        assert_eq!(
+            derive_input_name_from_url("git+https://example.com/"),
+            "custom-flake"
+        );
+    }
+
+    #[test]
+    fn test_derive_package_name_from_git_urls() {
+        assert_eq!(
+            derive_package_name_from_url("git+https://gitlab.com/group/sub/repo.git"),
+            "repo"
+        );
+        assert_eq!(
+            derive_package_name_from_url("git+ssh://git@gitlab.com/group/repo.git?ref=v1"),
+            "repo"
+        );
+    }
+
+    #[test]
+    fn test_derive_names_ignore_the_rev() {
+        let rev = "0123456789abcdef0123456789abcdef01234567";
+        for url in [
+            format!("github:owner/repo?rev={}", rev),
+            format!("github:owner/repo/{}", rev),
+            "github:owner/repo/v1.2".to_string(),
+        ] {
+            assert_eq!(
+                derive_input_name_from_url(&url),
+                "github-owner-repo",
+                "{}",
+                url
+            );
+            assert_eq!(derive_package_name_from_url(&url), "repo", "{}", url);
+        }
+        let url = format!("git+https://example.com/owner/repo.git?rev={}", rev);
+        assert_eq!(derive_input_name_from_url(&url), "owner-repo");
+        assert_eq!(derive_package_name_from_url(&url), "repo");
+    }
+
+    #[test]
+    fn test_with_rev() {
+        let rev = "0123456789abcdef0123456789abcdef01234567";
+        assert_eq!(
+            with_rev("github:owner/repo", rev).unwrap(),
+            format!("github:owner/repo?rev={}", rev)
+        );
+        assert_eq!(
+            with_rev("git+https://example.com/repo.git?dir=tool", rev).unwrap(),
+            format!("git+https://example.com/repo.git?dir=tool&rev={}", rev)
+        );
+        assert!(with_rev("github:owner/repo", "abc123").is_err());
+        assert!(with_rev("path:./tool", rev).is_err());
+        assert!(with_rev("github:owner/repo/v1.2", rev).is_err());
+        assert!(with_rev("github:owner/repo?ref=main", rev).is_err());
+    }
+
+    #[test]
+    fn test_derive_names_from_dir_query() {
+        let url = "github:owner/repo?dir=subprojects/tool";
+        assert_eq!(derive_input_name_from_url(url), "github-owner-repo-tool");
+        assert_eq!(derive_package_name_from_url(url), "tool");
+
+        let url = "github:owner/repo?ref=v1.2&dir=subprojects/tool";
+        assert_eq!(derive_input_name_from_url(url), "github-owner-repo-tool");
+        assert_eq!(derive_package_name_from_url(url), "tool");
+
+        let url = "git+https://gitlab.com/group/mono.git?dir=pkgs/cli&ref=main";
+        assert_eq!(derive_input_name_from_url(url), "group-mono-cli");
+        assert_eq!(derive_package_name_from_url(url), "cli");
+
+        // Other query parameters never end up in the name
+        assert_eq!(
+            derive_input_name_from_url("github:owner/repo?ref=v1.2"),
+            "github-owner-repo"
+        );
+        assert_eq!(
+            derive_package_name_from_url("github:owner/repo?ref=v1.2"),
+            "repo"
+        );
+    }
+
     #[test]
     fn test_flake_reference_detection() {
         // Strings containing ':' should be detected as flake references
@@ -621,6 +1340,55 @@ mod tests {
         assert_eq!(derive_package_name_from_url("path:./single"), "single");
     }
 
+    #[test]
+    fn test_resolve_offline() {
+        let entry = |name: &str, spec: Option<&str>, version: &str, commit: &str| {
+            (
+                "profile 'default'".to_string(),
+                ResolvedNixpkgPackage {
+                    name: name.to_string(),
+                    version_spec: spec.map(String::from),
+                    resolved_version: version.to_string(),
+                    attribute_path: "nodejs_20".to_string(),
+                    commit_hash: commit.to_string(),
+                    platforms: None,
+                    pinned: false,
+                    source_name: (name != "nodejs").then(|| "nodejs".to_string()),
+                    output: None,
+                    package_output: None,
+                    outputs: None,
+                    record: Default::default(),
+                },
+            )
+        };
+        let known = [
+            entry("nodejs", Some("20"), "20.18.1", "aaaaaaaa"),
+            entry("node-man", None, "22.1.0", "bbbbbbbb"),
+        ];
+
+        // Requested the same way wins; entries installed under --as count
+        let resolved = resolve_offline(&known, "nodejs", None).unwrap();
+        assert_eq!(
+            (resolved.version.as_str(), resolved.commit_hash.as_str()),
+            ("22.1.0", "bbbbbbbb")
+        );
+        assert_eq!(resolved.attribute_path, "nodejs_20");
+        let resolved = resolve_offline(&known, "nodejs", Some("20")).unwrap();
+        assert_eq!(resolved.commit_hash, "aaaaaaaa");
+        // Otherwise the exact version
+        let resolved = resolve_offline(&known, "nodejs", Some("22.1.0")).unwrap();
+        assert_eq!(resolved.commit_hash, "bbbbbbbb");
+
+        assert!(matches!(
+            resolve_offline(&known, "nodejs", Some("18")),
+            Err(Error::NotResolvedOffline(spec)) if spec == "nodejs@18"
+        ));
+        assert!(matches!(
+            resolve_offline(&known, "hello", None),
+            Err(Error::NotResolvedOffline(_))
+        ));
+    }
+
     #[test]
     fn test_regenerate_flake() {
         let temp = TempDir::new().unwrap();
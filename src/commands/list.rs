@@ -1,13 +1,31 @@
 use std::collections::HashSet;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
 
+use colored::Colorize;
+
+use crate::cli::ListArgs;
 use crate::config::Config;
-use crate::error::Result;
-use crate::flake::parser::collect_local_packages;
+use crate::error::{Error, Result};
+use crate::flake::local_hashes::local_package_hashes;
+use crate::flake::parser::{collect_local_packages, parse_generated_packages};
+use crate::flake::template::{
+    custom_package_expr, generate_flake, generate_flake_from_profile, nix_attr_name,
+};
+use crate::nix::Nix;
+use crate::nixhub::NixhubClient;
 use crate::nixy_config::{nixy_json_exists, NixyConfig};
-use crate::profile::get_flake_dir;
-use crate::state::{get_state_path, PackageState};
+use crate::profile::{
+    check_env_link, get_active_profile, get_flake_dir, get_profile_flake_dir, list_profiles,
+    EnvLinkProblem, Profile,
+};
+use crate::state::{
+    get_state_path, CustomPackage, InstallRecord, PackageState, ResolvedNixpkgPackage,
+};
 
-use super::info;
+use super::file::find_local_package_in_dir;
+use super::{info, page, stale_notice, success, verbose, warn};
 
 /// Package entry with source information
 struct PackageEntry {
@@ -15,36 +33,94 @@ struct PackageEntry {
     source: PackageSource,
     /// Platform restrictions (None means all platforms)
     platforms: Option<Vec<String>>,
+    /// When it was installed and updated, shown with `--verbose`
+    record: InstallRecord,
 }
 
 /// Source of a package
 enum PackageSource {
     /// Standard nixpkgs package (legacy, no version info)
     Nixpkgs,
-    /// Resolved nixpkgs package with version
-    NixpkgsVersioned { version: String },
+    /// Resolved nixpkgs package with version, possibly installed with `--pin`
+    NixpkgsVersioned {
+        version: String,
+        commit: String,
+        pinned: bool,
+        /// The nixpkgs package, for an entry installed under another name
+        /// (`install --as` or `--output`)
+        source_name: Option<String>,
+    },
     /// Custom package from an external flake
     Custom { url: String },
-    /// Local package from packages/ directory
-    Local,
+    /// Local package from packages/ directory, either a `.nix` file or a flake;
+    /// `changed` when its definition differs from the one last synced
+    Local { flake: bool, changed: bool },
 }
 
 impl PackageSource {
     fn display(&self) -> String {
         match self {
-            PackageSource::Nixpkgs | PackageSource::NixpkgsVersioned { .. } => {
-                "nixpkgs".to_string()
+            PackageSource::Nixpkgs => "nixpkgs".to_string(),
+            PackageSource::NixpkgsVersioned {
+                pinned,
+                source_name,
+                ..
+            } => {
+                let mut display = match source_name {
+                    Some(source_name) => format!("nixpkgs {}", source_name),
+                    None => "nixpkgs".to_string(),
+                };
+                if *pinned {
+                    display.push_str(", pinned");
+                }
+                display
             }
             PackageSource::Custom { url } => url.clone(),
-            PackageSource::Local => "local".to_string(),
+            PackageSource::Local { changed: false, .. } => "local".to_string(),
+            PackageSource::Local { changed: true, .. } => {
+                "local, changed since last sync".to_string()
+            }
+        }
+    }
+
+    /// Where the package comes from, as reported by `list --json`
+    fn source_name(&self) -> &'static str {
+        match self {
+            PackageSource::Nixpkgs => "nixpkgs",
+            PackageSource::NixpkgsVersioned { .. } => "resolved",
+            PackageSource::Custom { .. } => "custom",
+            PackageSource::Local { .. } => "local",
+        }
+    }
+
+    /// The package kind, as reported by `list --json`
+    fn kind(&self) -> &'static str {
+        match self {
+            PackageSource::Nixpkgs => "legacy",
+            PackageSource::NixpkgsVersioned { .. } => "resolved",
+            PackageSource::Custom { .. } => "custom",
+            PackageSource::Local { flake: false, .. } => "local-file",
+            PackageSource::Local { flake: true, .. } => "local-flake",
         }
     }
 }
 
+/// Absolute path of a local package's `.nix` file or flake directory
+pub fn local_package_path(packages_dir: &Path, name: &str) -> Option<PathBuf> {
+    let file = find_local_package_in_dir(packages_dir, name)?;
+    // A local flake is its directory, not the flake.nix in it
+    let path = if file.ends_with("flake.nix") {
+        file.parent()?.to_path_buf()
+    } else {
+        file
+    };
+    Some(std::path::absolute(&path).unwrap_or(path))
+}
+
 /// Format package name with version if available
 fn format_package_name(entry: &PackageEntry) -> String {
     match &entry.source {
-        PackageSource::NixpkgsVersioned { version } => {
+        PackageSource::NixpkgsVersioned { version, .. } => {
             format!("{}@{}", entry.name, version)
         }
         _ => entry.name.clone(),
@@ -71,9 +147,297 @@ fn format_platforms(platforms: &Option<Vec<String>>) -> String {
     }
 }
 
-pub fn run(config: &Config) -> Result<()> {
-    info("Installed packages:");
+/// Format when a package was installed and updated, for `--verbose`
+fn format_record(record: &InstallRecord) -> String {
+    let mut parts = Vec::new();
+    if let Some(at) = &record.installed_at {
+        parts.push(format!("installed {}", at));
+    }
+    if let Some(version) = &record.installed_with {
+        parts.push(format!("with nixy {}", version));
+    }
+    if let Some(at) = &record.updated_at {
+        parts.push(format!("updated {}", at));
+    }
+    parts.join(", ")
+}
+
+pub fn run(config: &Config, args: ListArgs) -> Result<()> {
+    if args.outdated {
+        return print_outdated(config);
+    }
+    let entries = collect_entries(config)?;
 
+    if args.count {
+        return print_count(&entries, args.json);
+    }
+    if args.json {
+        return print_json(config, &entries);
+    }
+
+    // Built up front so long lists can go through the pager in one piece
+    let mut output = format!("{} Installed packages:\n", "==>".blue());
+    if entries.is_empty() {
+        output.push_str("  (none)\n");
+    } else {
+        // Calculate column width for alignment (using formatted name with version)
+        let max_name_len = entries
+            .iter()
+            .map(|e| format_package_name(e).len())
+            .max()
+            .unwrap_or(0);
+
+        let verbose = verbose();
+        let max_source_len = entries
+            .iter()
+            .map(|e| e.source.display().len() + format_platforms(&e.platforms).len())
+            .max()
+            .unwrap_or(0);
+
+        for entry in entries {
+            let formatted_name = format_package_name(&entry);
+            let source = format!(
+                "({}){}",
+                entry.source.display(),
+                format_platforms(&entry.platforms)
+            );
+            let record = if verbose {
+                format_record(&entry.record)
+            } else {
+                String::new()
+            };
+            let line = format!(
+                "  {:<name_width$}  {:<source_width$}  {}",
+                formatted_name,
+                source,
+                record,
+                name_width = max_name_len,
+                source_width = max_source_len + 2
+            );
+            let _ = writeln!(output, "{}", line.trim_end());
+        }
+    }
+    page(&output, args.no_pager);
+
+    match check_env_link(&config.env_link, &Nix::store_dir()) {
+        Some(EnvLinkProblem::Dangling(target)) => warn(&Error::StaleEnv { target }.to_string()),
+        Some(problem) => warn(&format!(
+            "Environment {}: {}. Run 'nixy sync' to rebuild it.",
+            config.env_link.display(),
+            problem
+        )),
+        None => {}
+    }
+    let active = get_active_profile(config);
+    if let Some(marker) = Profile::new(&active, config).needs_sync() {
+        warn(&format!(
+            "Profile '{}': {}. Run 'nixy sync' to rebuild.",
+            active,
+            stale_notice(&marker)
+        ));
+    }
+
+    if args.verify {
+        verify(config)?;
+    }
+
+    Ok(())
+}
+
+/// Print the number of installed packages, optionally per nixy.json bucket
+fn print_count(entries: &[PackageEntry], json: bool) -> Result<()> {
+    if !json {
+        println!("{}", entries.len());
+        return Ok(());
+    }
+
+    let count =
+        |pred: fn(&PackageSource) -> bool| entries.iter().filter(|e| pred(&e.source)).count();
+    let counts = serde_json::json!({
+        "total": entries.len(),
+        "packages": count(|s| matches!(s, PackageSource::Nixpkgs)),
+        "resolved_packages": count(|s| matches!(s, PackageSource::NixpkgsVersioned { .. })),
+        "custom_packages": count(|s| matches!(s, PackageSource::Custom { .. })),
+        "local": count(|s| matches!(s, PackageSource::Local { .. })),
+    });
+    println!("{}", counts);
+    Ok(())
+}
+
+/// Nixhub lookups `list --outdated` runs at once
+const PARALLEL_LOOKUPS: usize = 4;
+
+/// Print the resolved packages of the active profile that Nixhub has a newer
+/// version of within their version spec (where `nixy update` would move
+/// them), and the flake packages, which only their flakes can tell
+///
+/// Only reads: nixy.json, the flakes and the Nixhub cache are left alone.
+fn print_outdated(config: &Config) -> Result<()> {
+    if !nixy_json_exists(config) {
+        return Err(Error::Usage(
+            "--outdated requires nixy.json. Run 'nixy sync' to migrate first.".to_string(),
+        ));
+    }
+    let nixy_config = NixyConfig::load(config)?;
+    let profile = nixy_config
+        .get_active_profile()
+        .ok_or_else(|| Error::ProfileNotFound(nixy_config.active_profile.clone()))?;
+    let resolved = &profile.resolved_packages;
+    if Nix::offline() {
+        warn("Offline: not asking Nixhub for newer versions");
+        return Ok(());
+    }
+
+    let mut checked = Vec::new();
+    if !resolved.is_empty() {
+        let system = Nix::current_system()?;
+        let client = NixhubClient::new();
+        info(&format!(
+            "Checking {} package(s) on Nixhub...",
+            resolved.len()
+        ));
+        for chunk in resolved.chunks(PARALLEL_LOOKUPS) {
+            let latest: Vec<Result<String>> = std::thread::scope(|s| {
+                let handles: Vec<_> = chunk
+                    .iter()
+                    .map(|pkg| {
+                        let (client, system) = (&client, &system);
+                        s.spawn(move || {
+                            let version = pkg.version_spec.as_deref().unwrap_or("latest");
+                            client
+                                .resolve_for_system(pkg.source_package_name(), version, system)
+                                .map(|resolved| resolved.version)
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|h| h.join().expect("Nixhub lookup panicked"))
+                    .collect()
+            });
+            checked.extend(chunk.iter().zip(latest));
+        }
+    }
+
+    let failed: Vec<_> = checked
+        .iter()
+        .filter_map(|(pkg, latest)| latest.as_ref().err().map(|e| (pkg, e)))
+        .collect();
+    if !failed.is_empty() && failed.len() == checked.len() {
+        warn(&format!(
+            "Could not check for newer versions: {}",
+            failed[0].1
+        ));
+    } else {
+        for (pkg, e) in &failed {
+            warn(&format!("Could not check {}: {}", pkg.name, e));
+        }
+    }
+    let outdated: Vec<(&ResolvedNixpkgPackage, &String)> = checked
+        .iter()
+        .filter_map(|(pkg, latest)| match latest {
+            Ok(latest) if *latest != pkg.resolved_version => Some((*pkg, latest)),
+            _ => None,
+        })
+        .collect();
+
+    let width = outdated
+        .iter()
+        .map(|(pkg, _)| pkg.name.len())
+        .chain(profile.custom_packages.iter().map(|pkg| pkg.name.len()))
+        .max()
+        .unwrap_or(0);
+    for (pkg, latest) in &outdated {
+        let mut line = format!(
+            "  {:<width$}  {} -> {}",
+            pkg.name, pkg.resolved_version, latest
+        );
+        if let Some(spec) = &pkg.version_spec {
+            let _ = write!(line, " (requested {})", spec);
+        }
+        if pkg.pinned {
+            line.push_str(" (pinned)");
+        }
+        println!("{}", line);
+    }
+    for pkg in &profile.custom_packages {
+        println!("  {:<width$}  check manually ({})", pkg.name, pkg.input_url);
+    }
+
+    if !outdated.is_empty() {
+        info("Run 'nixy update' to upgrade them ('--include-pinned' for pinned ones)");
+    } else if failed.is_empty() && !checked.is_empty() {
+        success("All resolved packages are up to date");
+    }
+    Ok(())
+}
+
+/// Print the active profile, all profile names and the installed packages
+/// as one JSON object; every package has the same fields, null where they
+/// don't apply, plus `url` for custom and `path` for local packages, and
+/// `source_name` for resolved packages installed under another name
+fn print_json(config: &Config, entries: &[PackageEntry]) -> Result<()> {
+    let packages_dir = if nixy_json_exists(config) {
+        config.global_packages_dir.clone()
+    } else {
+        get_flake_dir(config)?.join("packages")
+    };
+    let packages: Vec<serde_json::Value> = entries
+        .iter()
+        .map(|entry| {
+            let mut package = serde_json::json!({
+                "name": entry.name,
+                "source": entry.source.source_name(),
+                "kind": entry.source.kind(),
+                "version": null,
+                "commit": null,
+                "pinned": false,
+                "platforms": entry.platforms,
+                "input_url": null,
+            });
+            match &entry.source {
+                PackageSource::NixpkgsVersioned {
+                    version,
+                    commit,
+                    pinned,
+                    source_name,
+                } => {
+                    package["version"] = version.clone().into();
+                    package["commit"] = commit.clone().into();
+                    package["pinned"] = (*pinned).into();
+                    if let Some(source_name) = source_name {
+                        package["source_name"] = source_name.clone().into();
+                    }
+                }
+                PackageSource::Custom { url } => {
+                    package["input_url"] = url.clone().into();
+                    package["url"] = url.clone().into();
+                }
+                PackageSource::Local { changed, .. } => {
+                    package["changed_since_sync"] = (*changed).into();
+                    if let Some(path) = local_package_path(&packages_dir, &entry.name) {
+                        package["path"] = path.to_string_lossy().into_owned().into();
+                    }
+                }
+                PackageSource::Nixpkgs => {}
+            }
+            package
+        })
+        .collect();
+    let document = serde_json::json!({
+        "active_profile": get_active_profile(config),
+        "profiles": list_profiles(config)?,
+        "packages": packages,
+    });
+    println!("{}", document);
+    Ok(())
+}
+
+/// Collect every installed package of the active profile, sorted by name.
+///
+/// Reads only nixy.json (or legacy state) and the packages/ directory, so
+/// it is cheap enough for shell prompts.
+fn collect_entries(config: &Config) -> Result<Vec<PackageEntry>> {
     // Collect all packages with their sources
     let mut entries: Vec<PackageEntry> = Vec::new();
     let mut seen: HashSet<String> = HashSet::new();
@@ -86,19 +450,42 @@ pub fn run(config: &Config) -> Result<()> {
         if config.global_packages_dir.exists() {
             let (local_packages, local_flakes) =
                 collect_local_packages(&config.global_packages_dir);
+            let hashes = local_package_hashes(&config.global_packages_dir);
+            // Keyed by the definition's path in packages/, which for a
+            // `.nix` file need not match the package name
+            let changed = |name: &str| {
+                let Some(file) = find_local_package_in_dir(&config.global_packages_dir, name)
+                else {
+                    return false;
+                };
+                let key = file
+                    .strip_prefix(&config.global_packages_dir)
+                    .ok()
+                    .and_then(|key| key.to_str());
+                key.and_then(|key| nixy_config.local_hashes.get(key).zip(hashes.get(key)))
+                    .is_some_and(|(recorded, current)| recorded != current)
+            };
             for pkg in local_packages {
                 entries.push(PackageEntry {
                     name: pkg.name.clone(),
-                    source: PackageSource::Local,
+                    source: PackageSource::Local {
+                        flake: false,
+                        changed: changed(&pkg.name),
+                    },
                     platforms: None,
+                    record: InstallRecord::default(),
                 });
                 seen.insert(pkg.name);
             }
             for flake in local_flakes {
                 entries.push(PackageEntry {
                     name: flake.name.clone(),
-                    source: PackageSource::Local,
+                    source: PackageSource::Local {
+                        flake: true,
+                        changed: changed(&flake.name),
+                    },
                     platforms: None,
+                    record: InstallRecord::default(),
                 });
                 seen.insert(flake.name);
             }
@@ -113,6 +500,7 @@ pub fn run(config: &Config) -> Result<()> {
                         name: name.clone(),
                         source: PackageSource::Nixpkgs,
                         platforms: None,
+                        record: InstallRecord::default(),
                     });
                     seen.insert(name.clone());
                 }
@@ -125,8 +513,12 @@ pub fn run(config: &Config) -> Result<()> {
                         name: pkg.name.clone(),
                         source: PackageSource::NixpkgsVersioned {
                             version: pkg.resolved_version.clone(),
+                            commit: pkg.commit_hash.clone(),
+                            pinned: pkg.pinned,
+                            source_name: pkg.source_name.clone(),
                         },
                         platforms: pkg.platforms.clone(),
+                        record: pkg.record.clone(),
                     });
                     seen.insert(pkg.name.clone());
                 }
@@ -141,6 +533,7 @@ pub fn run(config: &Config) -> Result<()> {
                             url: pkg.input_url.clone(),
                         },
                         platforms: pkg.platforms.clone(),
+                        record: pkg.record.clone(),
                     });
                     seen.insert(pkg.name.clone());
                 }
@@ -160,6 +553,7 @@ pub fn run(config: &Config) -> Result<()> {
                 name: name.clone(),
                 source: PackageSource::Nixpkgs,
                 platforms: None,
+                record: InstallRecord::default(),
             });
             seen.insert(name.clone());
         }
@@ -170,8 +564,12 @@ pub fn run(config: &Config) -> Result<()> {
                 name: pkg.name.clone(),
                 source: PackageSource::NixpkgsVersioned {
                     version: pkg.resolved_version.clone(),
+                    commit: pkg.commit_hash.clone(),
+                    pinned: pkg.pinned,
+                    source_name: pkg.source_name.clone(),
                 },
                 platforms: pkg.platforms.clone(),
+                record: pkg.record.clone(),
             });
             seen.insert(pkg.name.clone());
         }
@@ -184,6 +582,7 @@ pub fn run(config: &Config) -> Result<()> {
                     url: pkg.input_url.clone(),
                 },
                 platforms: pkg.platforms.clone(),
+                record: pkg.record.clone(),
             });
             seen.insert(pkg.name.clone());
         }
@@ -196,8 +595,12 @@ pub fn run(config: &Config) -> Result<()> {
                 if !seen.contains(&pkg.name) {
                     entries.push(PackageEntry {
                         name: pkg.name.clone(),
-                        source: PackageSource::Local,
+                        source: PackageSource::Local {
+                            flake: false,
+                            changed: false,
+                        },
                         platforms: None,
+                        record: InstallRecord::default(),
                     });
                     seen.insert(pkg.name);
                 }
@@ -206,8 +609,12 @@ pub fn run(config: &Config) -> Result<()> {
                 if !seen.contains(&flake.name) {
                     entries.push(PackageEntry {
                         name: flake.name.clone(),
-                        source: PackageSource::Local,
+                        source: PackageSource::Local {
+                            flake: true,
+                            changed: false,
+                        },
                         platforms: None,
+                        record: InstallRecord::default(),
                     });
                     seen.insert(flake.name);
                 }
@@ -218,28 +625,281 @@ pub fn run(config: &Config) -> Result<()> {
     // Sort by name
     entries.sort_by(|a, b| a.name.cmp(&b.name));
 
-    if entries.is_empty() {
-        println!("  (none)");
-    } else {
-        // Calculate column width for alignment (using formatted name with version)
-        let max_name_len = entries
-            .iter()
-            .map(|e| format_package_name(e).len())
-            .max()
-            .unwrap_or(0);
+    Ok(entries)
+}
 
-        for entry in entries {
-            let formatted_name = format_package_name(&entry);
-            let platform_str = format_platforms(&entry.platforms);
-            println!(
-                "  {:<width$}  ({}){}",
-                formatted_name,
-                entry.source.display(),
-                platform_str,
-                width = max_name_len
-            );
+/// A declared package and the binding nixy generates for it
+struct DeclaredPackage {
+    name: String,
+    expected: String,
+}
+
+impl DeclaredPackage {
+    fn nixpkgs(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            expected: format!("pkgs.{}", nix_attr_name(name)),
+        }
+    }
+
+    fn resolved(pkg: &ResolvedNixpkgPackage) -> Self {
+        let commit = &pkg.commit_hash[..8.min(pkg.commit_hash.len())];
+        Self {
+            name: pkg.name.clone(),
+            expected: format!(
+                "inputs.nixpkgs-{}.{}.${{system}}.{}",
+                commit,
+                pkg.flake_output(),
+                pkg.attribute_path
+            ),
         }
     }
 
+    fn custom(pkg: &CustomPackage) -> Self {
+        Self {
+            name: pkg.name.clone(),
+            expected: custom_package_expr(pkg),
+        }
+    }
+}
+
+fn declared_packages(
+    packages: &[String],
+    resolved: &[ResolvedNixpkgPackage],
+    custom: &[CustomPackage],
+) -> Vec<DeclaredPackage> {
+    packages
+        .iter()
+        .map(|name| DeclaredPackage::nixpkgs(name))
+        .chain(resolved.iter().map(DeclaredPackage::resolved))
+        .chain(custom.iter().map(DeclaredPackage::custom))
+        .collect()
+}
+
+/// Generate the flake in memory and check that every declared package is
+/// bound to its own source and included in the environment, then check the
+/// profile's flake.nix on disk against nixy.json
+fn verify(config: &Config) -> Result<()> {
+    let (declared, packages_dir, content, flake_dir) = if nixy_json_exists(config) {
+        let nixy_config = NixyConfig::load(config)?;
+        let Some(profile) = nixy_config.get_active_profile() else {
+            success("No declared packages to verify");
+            return Ok(());
+        };
+        let declared = declared_packages(
+            &profile.packages,
+            &profile.resolved_packages,
+            &profile.custom_packages,
+        );
+        let flake_dir = Profile::new(&nixy_config.active_profile, config).state_dir;
+        let content =
+            generate_flake_from_profile(profile, Some(&config.global_packages_dir), &flake_dir)?;
+        let on_disk = get_profile_flake_dir(config, &nixy_config.active_profile)?;
+        (
+            declared,
+            config.global_packages_dir.clone(),
+            content,
+            on_disk,
+        )
+    } else {
+        let flake_dir = get_flake_dir(config)?;
+        let state = PackageState::load(&get_state_path(&flake_dir))?;
+        let declared = declared_packages(
+            &state.packages,
+            &state.resolved_packages,
+            &state.custom_packages,
+        );
+        let content = generate_flake(&state, Some(&flake_dir));
+        (declared, flake_dir.join("packages"), content, flake_dir)
+    };
+
+    let generated = parse_generated_packages(&content).ok_or(Error::InvalidGeneratedFlake)?;
+
+    let local_names: HashSet<String> = if packages_dir.exists() {
+        let (local_packages, local_flakes) = collect_local_packages(&packages_dir);
+        local_packages
+            .into_iter()
+            .map(|p| p.name)
+            .chain(local_flakes.into_iter().map(|f| f.name))
+            .collect()
+    } else {
+        HashSet::new()
+    };
+
+    let mut missing = 0;
+    for pkg in &declared {
+        let problem = match generated.bindings.get(&pkg.name) {
+            None => "not in the generated flake",
+            Some(_) if local_names.contains(&pkg.name) => {
+                "shadowed by a local package in packages/"
+            }
+            Some(expr) if *expr != pkg.expected => {
+                "overridden by another package with the same name"
+            }
+            Some(_) if !generated.paths.contains(&pkg.name) => {
+                "not included in the environment paths"
+            }
+            Some(_) => continue,
+        };
+        warn(&format!("{}: {}", pkg.name, problem));
+        missing += 1;
+    }
+
+    if missing > 0 {
+        return Err(Error::VerifyFailed(missing));
+    }
+
+    success(&format!(
+        "All {} declared package(s) are in the generated flake",
+        declared.len()
+    ));
+
+    let drift = check_flake_on_disk(&flake_dir, &declared, &local_names)?;
+    if drift > 0 {
+        return Err(Error::FlakeDrift(drift));
+    }
     Ok(())
 }
+
+/// Compare the flake.nix nixy last wrote (and built, unless the profile
+/// needs a sync) with nixy.json, returning the number of differences
+///
+/// The attribute names come from evaluating the flake; the environment's
+/// paths from its syntax tree.
+fn check_flake_on_disk(
+    flake_dir: &Path,
+    declared: &[DeclaredPackage],
+    local_names: &HashSet<String>,
+) -> Result<usize> {
+    let flake_path = flake_dir.join("flake.nix");
+    let Ok(content) = fs::read_to_string(&flake_path) else {
+        warn("The profile has no flake.nix yet. Run 'nixy sync' to build it.");
+        return Ok(0);
+    };
+    let Some(on_disk) = parse_generated_packages(&content) else {
+        warn(&format!(
+            "{} is not a flake nixy generated. Run 'nixy sync' to regenerate it.",
+            flake_path.display()
+        ));
+        return Ok(1);
+    };
+    let attributes = Nix::flake_package_names(flake_dir)?;
+
+    let declared: Vec<&str> = declared.iter().map(|p| p.name.as_str()).collect();
+    let drift = reconcile(&declared, local_names, &attributes, &on_disk.paths);
+    for d in &drift {
+        warn(&d.to_string());
+    }
+    if drift.is_empty() {
+        success(&format!("{} matches nixy.json", flake_path.display()));
+    }
+    Ok(drift.len())
+}
+
+/// A difference between nixy.json and a profile's flake.nix
+#[derive(Debug, PartialEq, Eq)]
+enum Drift {
+    /// Declared, but the flake has no attribute for it
+    NotInFlake(String),
+    /// In the flake, but left out of the environment
+    NotInPaths(String),
+    /// An attribute nixy.json does not know about, e.g. added by hand
+    Unknown(String),
+}
+
+impl std::fmt::Display for Drift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Drift::NotInFlake(name) => write!(
+                f,
+                "{}: in nixy.json but not in the profile's flake.nix. Run 'nixy sync' to add it.",
+                name
+            ),
+            Drift::NotInPaths(name) => write!(
+                f,
+                "{}: in the profile's flake.nix but not in the environment. Run 'nixy sync' to add it.",
+                name
+            ),
+            Drift::Unknown(name) => write!(
+                f,
+                "{}: in the profile's flake.nix but not in nixy.json (edited by hand?); the next sync drops it. To keep it, define it in packages/{}.nix.",
+                name, name
+            ),
+        }
+    }
+}
+
+/// Cross-check the declared packages against a flake's `packages` attribute
+/// names and buildEnv `paths`
+///
+/// Local packages are discovered rather than declared, so they are only
+/// exempt from being reported as unknown.
+fn reconcile(
+    declared: &[&str],
+    local: &HashSet<String>,
+    attributes: &[String],
+    paths: &[String],
+) -> Vec<Drift> {
+    let mut drift = Vec::new();
+    for name in declared {
+        if !attributes.iter().any(|a| a == name) {
+            drift.push(Drift::NotInFlake(name.to_string()));
+        } else if !paths.iter().any(|p| p == name) {
+            drift.push(Drift::NotInPaths(name.to_string()));
+        }
+    }
+    for attribute in attributes {
+        let known = attribute == "default"
+            || declared.contains(&attribute.as_str())
+            || local.contains(attribute);
+        if !known {
+            drift.push(Drift::Unknown(attribute.clone()));
+        }
+    }
+    drift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_reconcile_matching_flake() {
+        let local = HashSet::from(["mytool".to_string()]);
+        let drift = reconcile(
+            &["hello", "ripgrep"],
+            &local,
+            &names(&["default", "hello", "mytool", "ripgrep"]),
+            &names(&["hello", "mytool", "ripgrep"]),
+        );
+        assert!(drift.is_empty(), "{:?}", drift);
+    }
+
+    #[test]
+    fn test_reconcile_reports_each_kind_of_drift() {
+        let drift = reconcile(
+            &["hello", "jq", "ripgrep"],
+            &HashSet::new(),
+            &names(&["default", "hello", "jq", "handmade"]),
+            &names(&["hello", "handmade"]),
+        );
+        assert_eq!(
+            drift,
+            vec![
+                Drift::NotInPaths("jq".to_string()),
+                Drift::NotInFlake("ripgrep".to_string()),
+                Drift::Unknown("handmade".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reconcile_empty_profile() {
+        let drift = reconcile(&[], &HashSet::new(), &names(&["default"]), &[]);
+        assert!(drift.is_empty());
+    }
+}
@@ -1,24 +1,170 @@
 pub mod completions;
 pub mod config;
+pub mod diff;
+pub mod doctor;
+pub mod export;
 pub mod file;
+pub mod gc;
+pub mod import;
+pub mod info;
 pub mod install;
 pub mod list;
+pub mod platform;
 pub mod profile;
+pub mod rollback;
+pub mod run;
 pub mod search;
+pub mod shell;
 pub mod sync;
+pub mod undo_update;
 pub mod uninstall;
 pub mod update;
 pub mod upgrade;
+pub mod which;
+pub mod why;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
 use colored::Colorize;
+use dialoguer::Confirm;
+
+use crate::commits;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::flake::format::format_flake;
+use crate::flake::lock::seed_lock;
+use crate::flake::template::{generate_flake_from_profile, missing_local_packages};
+use crate::fonts;
+use crate::nix::Nix;
+use crate::nixhub::{NixhubClient, ResolveCache};
+use crate::nixy_config::{nixy_json_exists, NixyConfig, ProfileConfig};
+use crate::profile::{get_profile_flake_dir, NeedsSync, Profile};
+use crate::snapshot::Snapshot;
+use crate::state::CustomPackage;
+
+/// Set from the global `--yes` flag before any command runs
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+
+pub fn set_assume_yes(yes: bool) {
+    ASSUME_YES.store(yes, Ordering::Relaxed);
+}
+
+/// Whether `--yes` was given
+pub fn assume_yes() -> bool {
+    ASSUME_YES.load(Ordering::Relaxed)
+}
+
+/// Set from the global `--timeout` flag before any command runs
+static EVAL_TIMEOUT_SECS: AtomicU64 = AtomicU64::new(60);
+
+pub fn set_eval_timeout(secs: u64) {
+    EVAL_TIMEOUT_SECS.store(secs, Ordering::Relaxed);
+}
+
+/// How long slow nix evaluations may take (`--timeout`)
+pub fn eval_timeout() -> Duration {
+    Duration::from_secs(EVAL_TIMEOUT_SECS.load(Ordering::Relaxed))
+}
+
+/// Hours a Nixhub resolution is reused when nixy.json doesn't say otherwise
+const DEFAULT_NIXHUB_CACHE_HOURS: u64 = 24;
+
+/// A Nixhub client that reuses resolutions cached in the state directory for
+/// `nixhub_cache_hours`; `refresh` asks Nixhub again and rewrites the cache
+pub fn nixhub_client(config: &Config, refresh: bool) -> NixhubClient {
+    let hours = if nixy_json_exists(config) {
+        NixyConfig::load(config)
+            .ok()
+            .and_then(|c| c.nixhub_cache_hours)
+    } else {
+        None
+    }
+    .unwrap_or(DEFAULT_NIXHUB_CACHE_HOURS);
+    NixhubClient::new().with_cache(ResolveCache::new(
+        config.state_dir.join("nixhub-cache.json"),
+        Duration::from_secs(hours * 3600),
+        refresh,
+    ))
+}
+
+/// Set from the global `--quiet` flag before any command runs
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Set from the global `--verbose` flag before any command runs
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+/// Whether `--verbose` was given
+pub fn verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// Set by commands whose stdout belongs to the program they start (`nixy
+/// run`, `nixy shell`), so their progress messages go to stderr instead
+static STDOUT_TO_CHILD: AtomicBool = AtomicBool::new(false);
+
+pub fn leave_stdout_to_child() {
+    STDOUT_TO_CHILD.store(true, Ordering::Relaxed);
+}
+
+/// Ask a yes/no question before a destructive operation.
+///
+/// Returns true without asking under `--yes`. Without a terminal there is
+/// nobody to ask, so this fails instead of guessing.
+pub fn confirm(prompt: &str) -> Result<bool> {
+    if assume_yes() {
+        return Ok(true);
+    }
+    if !io::stdin().is_terminal() {
+        return Err(Error::Usage(format!(
+            "{} Confirmation requires a terminal or --yes.",
+            prompt
+        )));
+    }
+    Ok(Confirm::new()
+        .with_prompt(prompt)
+        .default(false)
+        .interact()?)
+}
 
 /// Print info message
 pub fn info(msg: &str) {
-    println!("{} {}", "==>".blue(), msg);
+    status(&format!("{} {}", "==>".blue(), msg));
 }
 
 /// Print success message
 pub fn success(msg: &str) {
-    println!("{} {}", "==>".green(), msg);
+    status(&format!("{} {}", "==>".green(), msg));
+}
+
+/// Print a line of a command's result, such as what a dry run would
+/// change, which `--quiet` does not hide
+pub fn report(msg: &str) {
+    println!("{} {}", "==>".blue(), msg);
+}
+
+/// Print a progress line unless `--quiet`, on stdout unless a child owns it
+fn status(line: &str) {
+    if QUIET.load(Ordering::Relaxed) {
+        return;
+    }
+    if STDOUT_TO_CHILD.load(Ordering::Relaxed) {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
 }
 
 /// Print warning message
@@ -30,3 +176,610 @@ pub fn warn(msg: &str) {
 pub fn error(msg: &str) {
     eprintln!("{} {}", "Error:".red(), msg);
 }
+
+/// Warn about packages of `profile` whose local source path is gone.
+///
+/// Flake generation leaves them out so the rest of the environment still
+/// builds; with `strict_local` set in nixy.json this fails instead.
+pub fn check_local_packages(
+    config: &Config,
+    nixy_config: &NixyConfig,
+    profile: &ProfileConfig,
+) -> Result<()> {
+    let missing = missing_local_packages(profile, Some(&config.global_packages_dir));
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    for pkg in &missing {
+        warn(&format!(
+            "Local package '{}' not found: {} does not exist",
+            pkg.name,
+            pkg.path.display()
+        ));
+    }
+    if nixy_config.strict_local {
+        return Err(Error::MissingLocalPackages(missing.len()));
+    }
+    warn(&format!(
+        "Building without {} missing local package(s). Restore them or uninstall to silence this.",
+        missing.len()
+    ));
+    Ok(())
+}
+
+/// Make sure the profile's flake in `flake_dir` is about to build against a
+/// lock, or say why it isn't.
+///
+/// A missing flake.lock (e.g. nixy.json restored without the state
+/// directory) makes floating packages resolve to whatever the channel has
+/// now. `copy_lock_from` seeds the lock from another profile instead; `retry`
+/// is the command line to suggest for that.
+pub fn prepare_lock(
+    config: &Config,
+    nixy_config: &NixyConfig,
+    profile_name: &str,
+    flake_dir: &Path,
+    copy_lock_from: Option<&str>,
+    retry: &str,
+) -> Result<()> {
+    let has_lock = flake_dir.join("flake.lock").exists();
+
+    let Some(from) = copy_lock_from else {
+        let Some(profile) = nixy_config.profiles.get(profile_name) else {
+            return Ok(());
+        };
+        let floating = profile.packages.len() + profile.custom_packages.len();
+        if !has_lock && floating > 0 {
+            warn(&format!(
+                "Profile '{}' has no flake.lock: {} floating package(s) will resolve fresh and may change version.",
+                profile_name, floating
+            ));
+            info(&format!(
+                "To keep the versions another profile uses instead: {} --copy-lock-from <profile>",
+                retry
+            ));
+        }
+        return Ok(());
+    };
+
+    if from == profile_name {
+        return Err(Error::Usage(
+            "--copy-lock-from needs a profile other than the one being built".to_string(),
+        ));
+    }
+    if !nixy_config.profile_exists(from) {
+        return Err(Error::ProfileNotFound(from.to_string()));
+    }
+    if has_lock {
+        return Err(Error::Usage(format!(
+            "Profile '{}' already has a flake.lock; --copy-lock-from only seeds a missing one",
+            profile_name
+        )));
+    }
+    if Nix::frozen() {
+        return Err(Error::Frozen("--copy-lock-from".to_string()));
+    }
+
+    seed_lock(&Profile::new(from, config).state_dir, flake_dir)?;
+    info(&format!("Seeded flake.lock from profile '{}'", from));
+    Ok(())
+}
+
+/// Name the packages of `profile_name` behind a build that failed on
+/// nixpkgs commits GitHub no longer serves, or on an attribute missing from
+/// a flake it was installed from without validation
+pub fn explain_build_error(config: &Config, profile_name: &str, err: Error) -> Error {
+    if !matches!(
+        err,
+        Error::UnreachableCommits { .. } | Error::MissingAttribute { .. }
+    ) {
+        return err;
+    }
+    let Some(profile) = NixyConfig::load(config)
+        .ok()
+        .and_then(|c| c.profiles.get(profile_name).cloned())
+    else {
+        return err;
+    };
+    match err {
+        Error::MissingAttribute { attribute, .. } => Error::MissingAttribute {
+            packages: unvalidated_suspects(&attribute, &profile.custom_packages),
+            attribute,
+        },
+        err => commits::attribute_packages(err, &profile.resolved_packages),
+    }
+}
+
+/// Unvalidated custom packages that could be missing `attribute`: those
+/// named by it if any, otherwise all of them (the attribute may be an
+/// output such as `legacyPackages`)
+fn unvalidated_suspects(attribute: &str, custom: &[CustomPackage]) -> Vec<String> {
+    let unvalidated: Vec<&CustomPackage> = custom.iter().filter(|p| p.unvalidated).collect();
+    let named: Vec<&&CustomPackage> = unvalidated
+        .iter()
+        .filter(|p| p.source_package_name() == attribute || p.input_name == attribute)
+        .collect();
+    let suspects: Vec<&CustomPackage> = if named.is_empty() {
+        unvalidated
+    } else {
+        named.into_iter().copied().collect()
+    };
+    suspects
+        .iter()
+        .map(|p| format!("'{}' ({})", p.name, p.input_url))
+        .collect()
+}
+
+/// The command line nixy was run with, as recorded in snapshots
+pub fn command_line() -> String {
+    std::iter::once("nixy".to_string())
+        .chain(std::env::args().skip(1))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Record the environment just built for `profile_name` as its newest
+/// generation, so `nixy rollback` can return to it later.
+///
+/// Never fails the build it follows; problems are only warned about.
+pub fn record_generation(config: &Config, profile_name: &str) {
+    if !nixy_json_exists(config) {
+        return;
+    }
+    let Some(env) = Profile::new(profile_name, config).last_build() else {
+        return;
+    };
+    if let Err(e) = Snapshot::record_generation(config, profile_name, &env, &command_line()) {
+        warn(&format!(
+            "Could not record this environment for 'nixy rollback': {}",
+            e
+        ));
+    }
+}
+
+/// Bring the fonts placed for `link_fonts` in line with the environment
+/// just built for `profile_name`, or remove them if it does not set it.
+///
+/// Never fails the build it follows; problems are only warned about.
+pub fn update_fonts(config: &Config, profile_name: &str) {
+    let enabled = nixy_json_exists(config)
+        && NixyConfig::load(config)
+            .ok()
+            .and_then(|c| c.profiles.get(profile_name).map(|p| p.link_fonts))
+            .unwrap_or(false);
+    if !enabled {
+        unlink_fonts(config);
+        return;
+    }
+
+    let Some(dir) = fonts::font_dir() else {
+        warn("Could not link fonts: no home directory");
+        return;
+    };
+    match fonts::link(config, profile_name, &dir, fonts::Mode::native()) {
+        Ok(changes) if !changes.is_empty() => {
+            info(&format!(
+                "Fonts in {}: {} added, {} updated, {} removed",
+                dir.display(),
+                changes.added,
+                changes.updated,
+                changes.removed
+            ));
+            refresh_font_cache(&dir);
+        }
+        Ok(_) => {}
+        Err(e) => warn(&format!(
+            "Could not link fonts into {}: {}",
+            dir.display(),
+            e
+        )),
+    }
+}
+
+/// Remove the fonts placed for `link_fonts`, if any
+pub fn unlink_fonts(config: &Config) {
+    match fonts::unlink(config) {
+        Ok(changes) if changes.removed > 0 => {
+            info(&format!("Removed {} linked font(s)", changes.removed));
+            if let Some(dir) = fonts::font_dir() {
+                refresh_font_cache(&dir);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => warn(&format!("Could not remove linked fonts: {}", e)),
+    }
+}
+
+fn refresh_font_cache(dir: &Path) {
+    // macOS picks up changes in its font folders by itself
+    if !cfg!(target_os = "linux") {
+        return;
+    }
+    if let Err(e) = fonts::refresh_font_cache(dir) {
+        warn(&format!("Could not refresh the font cache: {}", e));
+    }
+}
+
+/// Commit the change to nixy.json, the active profile's flake.nix and
+/// flake.lock, and the local package files, when `auto_commit` is set in
+/// nixy.json (or an explicit `message` asks for it)
+///
+/// Each file is committed in the git repository it is in, so a config
+/// directory kept in a dotfiles repository gets its own commit next to the
+/// state directory's; files outside any repository are left alone. Commits
+/// are limited to these paths, so anything else staged stays staged. Never
+/// fails the change it follows; git problems are only warned about.
+pub fn auto_commit(config: &Config, message: Option<&str>, default_message: &str) {
+    if !nixy_json_exists(config) {
+        return;
+    }
+    let Ok(nixy_config) = NixyConfig::load(config) else {
+        return;
+    };
+    if !nixy_config.auto_commit && message.is_none() {
+        return;
+    }
+    let Ok(flake_dir) = get_profile_flake_dir(config, &nixy_config.active_profile) else {
+        return;
+    };
+
+    let paths = [
+        config.nixy_json.clone(),
+        config.global_packages_dir.clone(),
+        flake_dir.join("flake.nix"),
+        flake_dir.join("flake.lock"),
+    ];
+    // Repository work tree -> paths in it
+    let mut repos: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+    for path in paths {
+        let Ok(path) = fs::canonicalize(&path) else {
+            continue;
+        };
+        if let Some(toplevel) = git_toplevel(&path) {
+            repos
+                .entry(toplevel)
+                .or_default()
+                .push(path.to_string_lossy().into_owned());
+        }
+    }
+
+    let message = message.unwrap_or(default_message);
+    for (repo, files) in &repos {
+        commit_paths(repo, files, message);
+    }
+}
+
+/// Commit `files` (absolute paths) in the work tree `repo` with `message`,
+/// when they changed
+fn commit_paths(repo: &Path, files: &[String], message: &str) {
+    let git = |args: &[&str]| Command::new("git").arg("-C").arg(repo).args(args).output();
+    let files: Vec<&str> = files.iter().map(String::as_str).collect();
+    let _ = git(&[&["add", "--"], files.as_slice()].concat());
+    // Nothing to commit when the change left these files as they were
+    let unchanged = git(&[&["diff", "--cached", "--quiet", "--"], files.as_slice()].concat())
+        .is_ok_and(|o| o.status.success());
+    if unchanged {
+        return;
+    }
+
+    match git(&[&["commit", "-m", message, "--"], files.as_slice()].concat()) {
+        Ok(o) if o.status.success() => {
+            info(&format!("Committed '{}' in {}", message, repo.display()))
+        }
+        Ok(o) => warn(&format!(
+            "Could not commit the change in {}: {}",
+            repo.display(),
+            String::from_utf8_lossy(&o.stderr).trim()
+        )),
+        Err(e) => warn(&format!("Could not run git: {}", e)),
+    }
+}
+
+/// The top of the git work tree `path` is in, if any
+fn git_toplevel(path: &Path) -> Option<PathBuf> {
+    let dir = if path.is_dir() { path } else { path.parent()? };
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["rev-parse", "--show-toplevel"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    fs::canonicalize(String::from_utf8_lossy(&output.stdout).trim()).ok()
+}
+
+/// Whether `dir` is inside a git work tree
+pub fn is_git_repo(dir: &Path) -> bool {
+    dir.join(".git").exists()
+        || Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["rev-parse", "--git-dir"])
+            .output()
+            .map(|o| o.status.success())
+            .unwrap_or(false)
+}
+
+/// Print what `changed` would write to nixy.json and to its active profile's
+/// flake.nix, against `original` and the flake.nix there now (`--dry-run`)
+///
+/// Nothing is written and nothing is built.
+pub fn preview_changes(config: &Config, original: &NixyConfig, changed: &NixyConfig) -> Result<()> {
+    changed.validate()?;
+    let to_json = |nixy_config: &NixyConfig| {
+        serde_json::to_string_pretty(nixy_config).map_err(|e| Error::StateFile(e.to_string()))
+    };
+    print_line_diff("nixy.json", &to_json(original)?, &to_json(changed)?);
+
+    let profile = changed
+        .get_active_profile()
+        .ok_or_else(|| Error::ProfileNotFound(changed.active_profile.clone()))?;
+    let flake_dir = get_profile_flake_dir(config, &changed.active_profile)?;
+    let global_packages_dir = config
+        .global_packages_dir
+        .exists()
+        .then_some(config.global_packages_dir.as_path());
+    let flake = format_flake(
+        &generate_flake_from_profile(profile, global_packages_dir, &flake_dir)?,
+        changed.format,
+    );
+    // A profile that was never synced has no flake.nix yet
+    let current = fs::read_to_string(flake_dir.join("flake.nix")).unwrap_or_default();
+    print_line_diff("flake.nix", &current, &flake);
+
+    report("Dry run: no changes written");
+    Ok(())
+}
+
+/// Print a unified diff from `old` to `new`, or that `label` is unchanged
+pub fn print_line_diff(label: &str, old: &str, new: &str) {
+    let Some(diff) = unified_diff(label, old, new) else {
+        report(&format!("{}: unchanged", label));
+        return;
+    };
+    report(&format!("{}:", label));
+    for (i, line) in diff.iter().enumerate() {
+        // The first two lines are the `---`/`+++` header
+        let line = match line.chars().next() {
+            _ if i < 2 => line.bold(),
+            Some('-') => line.red(),
+            Some('+') => line.green(),
+            Some('@') => line.cyan(),
+            _ => line.normal(),
+        };
+        println!("{}", line);
+    }
+}
+
+/// Context lines shown around each change of a unified diff
+const DIFF_CONTEXT: usize = 3;
+
+/// The lines of a unified diff from `old` to `new` (`None` if they have the
+/// same lines), with [`DIFF_CONTEXT`] lines of context around each hunk
+fn unified_diff(label: &str, old: &str, new: &str) -> Option<Vec<String>> {
+    let lines = line_diff(old, new);
+    let changed: Vec<usize> = (0..lines.len()).filter(|&i| lines[i].0 != ' ').collect();
+    let first = *changed.first()?;
+
+    // Changes closer together than twice the context share a hunk
+    let mut hunks = vec![(first, first)];
+    for &i in &changed[1..] {
+        let last = hunks.last_mut().unwrap();
+        if i - last.1 <= 2 * DIFF_CONTEXT {
+            last.1 = i;
+        } else {
+            hunks.push((i, i));
+        }
+    }
+
+    let mut out = vec![format!("--- a/{}", label), format!("+++ b/{}", label)];
+    for (first, last) in hunks {
+        let start = first.saturating_sub(DIFF_CONTEXT);
+        let end = (last + DIFF_CONTEXT + 1).min(lines.len());
+        // Line numbers count the lines each side has before the hunk
+        let before = &lines[..start];
+        let old_start = before.iter().filter(|(sign, _)| *sign != '+').count();
+        let new_start = before.iter().filter(|(sign, _)| *sign != '-').count();
+        let hunk = &lines[start..end];
+        let old_len = hunk.iter().filter(|(sign, _)| *sign != '+').count();
+        let new_len = hunk.iter().filter(|(sign, _)| *sign != '-').count();
+        // An empty side names the line before it, as `diff -u` does
+        let range = |start: usize, len: usize| {
+            format!("{},{}", if len == 0 { start } else { start + 1 }, len)
+        };
+        out.push(format!(
+            "@@ -{} +{} @@",
+            range(old_start, old_len),
+            range(new_start, new_len)
+        ));
+        out.extend(hunk.iter().map(|(sign, line)| format!("{}{}", sign, line)));
+    }
+    Some(out)
+}
+
+/// Lines of `old` and `new` marked ' ' (in both), '-' (only in `old`) or
+/// '+' (only in `new`), from their longest common subsequence
+fn line_diff<'a>(old: &'a str, new: &'a str) -> Vec<(char, &'a str)> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            lines.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if common[i + 1][j] >= common[i][j + 1] {
+            lines.push(('-', old[i]));
+            i += 1;
+        } else {
+            lines.push(('+', new[j]));
+            j += 1;
+        }
+    }
+    lines.extend(old[i..].iter().map(|line| ('-', *line)));
+    lines.extend(new[j..].iter().map(|line| ('+', *line)));
+    lines
+}
+
+/// "environment out of date (<reason>, <when>)" for a profile with a
+/// needs-sync marker
+pub fn stale_notice(marker: &NeedsSync) -> String {
+    match marker.since {
+        Some(since) => format!(
+            "environment out of date ({}, {})",
+            marker.reason,
+            time_ago(since)
+        ),
+        None => format!("environment out of date ({})", marker.reason),
+    }
+}
+
+fn time_ago(since: SystemTime) -> String {
+    let secs = SystemTime::now()
+        .duration_since(since)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (n, unit) = match secs {
+        0..=59 => return "just now".to_string(),
+        60..=3599 => (secs / 60, "minute"),
+        3600..=86399 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day"),
+    };
+    format!("{} {}{} ago", n, unit, if n == 1 { "" } else { "s" })
+}
+
+/// Print `output` to stdout, through a pager when it would not fit on screen.
+///
+/// The pager comes from `NIXY_PAGER`, then `PAGER`, falling back to `less -R`;
+/// an empty value disables paging. Output is printed directly when stdout is
+/// not a terminal, when `no_pager` is set, or when the pager cannot be run.
+pub fn page(output: &str, no_pager: bool) {
+    if no_pager || !io::stdout().is_terminal() || fits_on_screen(output) {
+        print!("{}", output);
+        return;
+    }
+
+    let pager = std::env::var("NIXY_PAGER")
+        .or_else(|_| std::env::var("PAGER"))
+        .unwrap_or_else(|_| "less -R".to_string());
+    if pager.trim().is_empty() || !run_pager(&pager, output) {
+        print!("{}", output);
+    }
+}
+
+fn fits_on_screen(output: &str) -> bool {
+    let (rows, _) = console::Term::stdout().size();
+    output.lines().count() < rows as usize
+}
+
+/// Returns false if the pager could not be started, so the caller can fall
+/// back to printing
+fn run_pager(pager: &str, output: &str) -> bool {
+    let mut child = match Command::new("sh")
+        .arg("-c")
+        .arg(pager)
+        .stdin(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(_) => return false,
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // The user quitting the pager early closes the pipe; that's not an error
+        let _ = stdin.write_all(output.as_bytes());
+    }
+    child.wait().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_stale_notice() {
+        let marker = NeedsSync {
+            reason: "nixy update".to_string(),
+            since: Some(SystemTime::now() - Duration::from_secs(2 * 3600 + 5)),
+        };
+        assert_eq!(
+            stale_notice(&marker),
+            "environment out of date (nixy update, 2 hours ago)"
+        );
+
+        let marker = NeedsSync {
+            since: None,
+            ..marker
+        };
+        assert_eq!(
+            stale_notice(&marker),
+            "environment out of date (nixy update)"
+        );
+    }
+
+    #[test]
+    fn test_time_ago() {
+        let ago = |secs| time_ago(SystemTime::now() - Duration::from_secs(secs));
+        assert_eq!(ago(5), "just now");
+        assert_eq!(ago(60), "1 minute ago");
+        assert_eq!(ago(59 * 60), "59 minutes ago");
+        assert_eq!(ago(86400 * 3), "3 days ago");
+    }
+
+    #[test]
+    fn test_line_diff() {
+        assert_eq!(
+            line_diff("a\nb\nc\n", "a\nx\nc\nd\n"),
+            vec![(' ', "a"), ('-', "b"), ('+', "x"), (' ', "c"), ('+', "d")]
+        );
+        assert_eq!(line_diff("", "a"), vec![('+', "a")]);
+        assert!(line_diff("a\nb", "a\nb")
+            .iter()
+            .all(|(sign, _)| *sign == ' '));
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        let new = "1\nx\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n";
+        assert_eq!(
+            unified_diff("f", old, new).unwrap(),
+            vec![
+                "--- a/f",
+                "+++ b/f",
+                "@@ -1,5 +1,5 @@",
+                " 1",
+                "-2",
+                "+x",
+                " 3",
+                " 4",
+                " 5",
+                "@@ -10,3 +10,4 @@",
+                " 10",
+                " 11",
+                " 12",
+                "+13",
+            ]
+        );
+        assert_eq!(
+            unified_diff("f", "", "a\n").unwrap(),
+            vec!["--- a/f", "+++ b/f", "@@ -0,0 +1,1 @@", "+a"]
+        );
+        assert_eq!(unified_diff("f", old, old), None);
+    }
+}
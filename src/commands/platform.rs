@@ -0,0 +1,74 @@
+//! Change the platform restriction of an installed package.
+
+use crate::cli::PlatformArgs;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::flake::template::regenerate_flake_from_profile;
+use crate::nixy_config::{nixy_json_exists, NixyConfig};
+use crate::profile::get_flake_dir;
+use crate::state::normalize_platforms;
+
+use super::info::find_local_package;
+use super::{info, success, warn};
+
+pub fn run(config: &Config, args: PlatformArgs) -> Result<()> {
+    if !nixy_json_exists(config) {
+        return Err(Error::Usage(
+            "nixy platform requires nixy.json. Run 'nixy sync' to migrate first.".to_string(),
+        ));
+    }
+    let platforms = if args.clear {
+        None
+    } else {
+        Some(normalize_platforms(&args.set).map_err(Error::Usage)?)
+    };
+    let name = &args.package;
+
+    let mut nixy_config = NixyConfig::load(config)?;
+    let original_config = nixy_config.clone();
+    if find_local_package(config, &nixy_config, name).is_some() {
+        return Err(Error::Usage(format!(
+            "'{}' is a local package in packages/, which is part of every profile on every platform",
+            name
+        )));
+    }
+    let active_profile = nixy_config.active_profile.clone();
+    let profile = nixy_config
+        .get_active_profile_mut()
+        .ok_or_else(|| Error::ProfileNotFound(active_profile.clone()))?;
+    if profile.is_legacy_package(name) {
+        return Err(Error::Usage(format!(
+            "'{}' is a legacy entry without a resolved version, which can't be limited to platforms. \
+             Reinstall it first: nixy uninstall {} && nixy install {}",
+            name, name, name
+        )));
+    }
+    let current = profile
+        .platforms_mut(name)
+        .ok_or_else(|| Error::PackageNotInstalled(name.to_string()))?;
+
+    let description = match &platforms {
+        Some(platforms) => platforms.join(", "),
+        None => "all platforms".to_string(),
+    };
+    if *current == platforms {
+        success(&format!("'{}' already installs on {}", name, description));
+        return Ok(());
+    }
+    *current = platforms;
+    nixy_config.save(config)?;
+    info(&format!("'{}' now installs on {}", name, description));
+
+    if let Err(e) = super::sync::sync(config, Default::default()) {
+        original_config.save(config)?;
+        let _ = regenerate_flake_from_profile(
+            &get_flake_dir(config)?,
+            &original_config.profiles[&active_profile],
+            Some(config.global_packages_dir.as_path()),
+            original_config.format,
+        );
+        warn("Build failed. Reverted the platform change.");
+        return Err(e);
+    }
+    Ok(())
+}
@@ -1,26 +1,59 @@
 use std::fs;
 use std::io::{self, IsTerminal};
 
-use dialoguer::{Confirm, Select};
+use colored::Colorize;
+use dialoguer::Select;
 
 use crate::cli::ProfileArgs;
 use crate::config::{Config, DEFAULT_PROFILE};
 use crate::error::{Error, Result};
-use crate::flake::template::{local_path_input_names, regenerate_flake_from_profile};
+use crate::flake::template::{
+    local_path_input_names, regenerate_flake_from_profile, DEFAULT_NIXPKGS_URL,
+};
+use crate::fonts;
 use crate::nix::Nix;
 use crate::nixy_config::{nixy_json_exists, NixyConfig, ProfileConfig};
 use crate::profile::{
-    get_active_profile, get_flake_dir, has_legacy_flake, list_profiles, migrate_legacy_flake,
-    set_active_profile, validate_profile_name, Profile,
+    get_active_profile, get_profile_flake_dir, has_legacy_flake, list_profiles,
+    migrate_legacy_flake, repoint_link, set_active_profile, validate_profile_name, Profile,
 };
+use crate::snapshot::{generation_tag, Snapshot};
 
-use super::{error, info, success, warn};
+use super::{
+    confirm, explain_build_error, info, prepare_lock, record_generation, stale_notice, success,
+    unlink_fonts, update_fonts, warn,
+};
 
 pub fn run(config: &Config, args: ProfileArgs) -> Result<()> {
+    if let (Some(name), Some(description)) = (&args.name, &args.set_description) {
+        return set_description(config, name, description);
+    }
+    if let (Some(name), Some(new_name)) = (&args.name, &args.rename) {
+        return rename(config, name, new_name);
+    }
+    if let (Some(name), Some(channel)) = (&args.name, &args.channel) {
+        return set_channel(config, name, channel);
+    }
+    if let (Some(name), Some(outputs)) = (&args.name, &args.outputs) {
+        return set_outputs(config, name, outputs);
+    }
+    if let (Some(name), Some(source)) = (&args.name, &args.copy) {
+        copy(config, source, name, args.force)?;
+        if args.c {
+            return switch(config, name, false, None);
+        }
+        return Ok(());
+    }
+    if args.activate_only {
+        if let Some(name) = &args.name {
+            return activate_only(config, name);
+        }
+    }
+
     match (args.name, args.c, args.d) {
         (None, false, false) => interactive_select(config),
-        (Some(name), false, false) => switch(config, &name, false),
-        (Some(name), true, false) => switch(config, &name, true),
+        (Some(name), false, false) => switch(config, &name, false, args.copy_lock_from.as_deref()),
+        (Some(name), true, false) => switch(config, &name, true, args.copy_lock_from.as_deref()),
         (Some(name), false, true) => delete_interactive(config, &name),
         (None, _, _) => Err(Error::Usage(
             "Profile name required with -c or -d flag".to_string(),
@@ -48,30 +81,32 @@ fn interactive_select(config: &Config) -> Result<()> {
         return Ok(());
     }
 
+    // Descriptions are optional; a legacy or unreadable config just has none
+    let nixy_config = if nixy_json_exists(config) {
+        NixyConfig::load(config).ok()
+    } else {
+        None
+    };
+    let label = |name: &str| {
+        let description = nixy_config
+            .as_ref()
+            .and_then(|c| c.profiles.get(name))
+            .and_then(|p| p.description.as_deref());
+        profile_label(config, name, &active, description)
+    };
+
     // If not a TTY, just list profiles
     if !io::stdin().is_terminal() {
         info("Available profiles:");
         for name in &profiles {
-            if *name == active {
-                println!("  * {} (active)", name);
-            } else {
-                println!("    {}", name);
-            }
+            let marker = if *name == active { "*" } else { " " };
+            println!("  {} {}", marker, label(name));
         }
         return Ok(());
     }
 
     // Build selection items with active marker
-    let items: Vec<String> = profiles
-        .iter()
-        .map(|name| {
-            if *name == active {
-                format!("{} (active)", name)
-            } else {
-                name.clone()
-            }
-        })
-        .collect();
+    let items: Vec<String> = profiles.iter().map(|name| label(name)).collect();
 
     // Find index of active profile
     let active_index = profiles.iter().position(|n| *n == active).unwrap_or(0);
@@ -89,7 +124,7 @@ fn interactive_select(config: &Config) -> Result<()> {
                 info(&format!("Already on profile '{}'", selected));
                 Ok(())
             } else {
-                switch(config, selected, false)
+                switch(config, selected, false, None)
             }
         }
         None => {
@@ -99,7 +134,334 @@ fn interactive_select(config: &Config) -> Result<()> {
     }
 }
 
-fn switch(config: &Config, name: &str, create: bool) -> Result<()> {
+/// Profile name with its active and out-of-date state and description, for
+/// listings
+fn profile_label(config: &Config, name: &str, active: &str, description: Option<&str>) -> String {
+    let mut label = name.to_string();
+    if name == active {
+        label.push_str(" (active)");
+    }
+    if let Some(description) = description {
+        label.push_str(&format!(" {}", description.dimmed()));
+    }
+    if let Some(marker) = Profile::new(name, config).needs_sync() {
+        label.push_str(&format!(" - {}", stale_notice(&marker)));
+    }
+    label
+}
+
+/// Set a profile's description, or clear it when `description` is empty
+fn set_description(config: &Config, name: &str, description: &str) -> Result<()> {
+    if !nixy_json_exists(config) {
+        return Err(Error::Usage(
+            "--set-description needs the nixy.json format".to_string(),
+        ));
+    }
+    if description.contains(['\n', '\r']) {
+        return Err(Error::Usage(
+            "A profile description must be a single line".to_string(),
+        ));
+    }
+
+    let mut nixy_config = NixyConfig::load(config)?;
+    let profile = nixy_config
+        .profiles
+        .get_mut(name)
+        .ok_or_else(|| Error::ProfileNotFound(name.to_string()))?;
+    let description = description.trim();
+    profile.description = (!description.is_empty()).then(|| description.to_string());
+    nixy_config.save(config)?;
+
+    if description.is_empty() {
+        success(&format!("Cleared the description of profile '{}'", name));
+    } else {
+        success(&format!("Set the description of profile '{}'", name));
+    }
+    Ok(())
+}
+
+/// Point the profile's base nixpkgs at `channel`, then rebuild it if it is
+/// the active one
+fn set_channel(config: &Config, name: &str, channel: &str) -> Result<()> {
+    if !nixy_json_exists(config) {
+        return Err(Error::Usage(
+            "--channel needs the nixy.json format".to_string(),
+        ));
+    }
+    let url = channel_url(channel)?;
+
+    let mut nixy_config = NixyConfig::load(config)?;
+    let original_config = nixy_config.clone();
+    let profile = nixy_config
+        .profiles
+        .get_mut(name)
+        .ok_or_else(|| Error::ProfileNotFound(name.to_string()))?;
+    let changed = profile.base_nixpkgs_url() != url;
+    profile.nixpkgs_url = (url != DEFAULT_NIXPKGS_URL).then(|| url.clone());
+    if !changed {
+        success(&format!("Profile '{}' already uses {}", name, url));
+        return Ok(());
+    }
+    nixy_config.save(config)?;
+    info(&format!("Profile '{}' now uses {}", name, url));
+
+    rebuild_changed_profile(
+        config,
+        name,
+        &nixy_config,
+        &original_config,
+        "--channel",
+        "the channel",
+    )
+}
+
+/// Set the outputs linked besides each package's main one: a comma-separated
+/// list, empty for none, or `default` for man, doc, info and dev. The active
+/// profile is rebuilt
+fn set_outputs(config: &Config, name: &str, outputs: &str) -> Result<()> {
+    if !nixy_json_exists(config) {
+        return Err(Error::Usage(
+            "--outputs needs the nixy.json format".to_string(),
+        ));
+    }
+    let outputs = parse_outputs(outputs)?;
+
+    let mut nixy_config = NixyConfig::load(config)?;
+    let original_config = nixy_config.clone();
+    let profile = nixy_config
+        .profiles
+        .get_mut(name)
+        .ok_or_else(|| Error::ProfileNotFound(name.to_string()))?;
+    if profile.extra_outputs == outputs {
+        success(&format!(
+            "Profile '{}' already links {}",
+            name,
+            describe_outputs(outputs.as_deref())
+        ));
+        return Ok(());
+    }
+    profile.extra_outputs = outputs;
+    let linked = describe_outputs(profile.extra_outputs.as_deref());
+    nixy_config.save(config)?;
+    info(&format!("Profile '{}' now links {}", name, linked));
+
+    rebuild_changed_profile(
+        config,
+        name,
+        &nixy_config,
+        &original_config,
+        "--outputs",
+        "the outputs",
+    )
+}
+
+/// The `extra_outputs` for `--outputs`: `None` for `default`, else the
+/// comma-separated names (empty for none)
+fn parse_outputs(outputs: &str) -> Result<Option<Vec<String>>> {
+    let outputs = outputs.trim();
+    if outputs == "default" {
+        return Ok(None);
+    }
+    let mut names: Vec<String> = Vec::new();
+    for output in outputs.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+        if !output
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(Error::Usage(format!(
+                "Invalid output name '{}'. Give names such as man,info (or default).",
+                output
+            )));
+        }
+        if !names.iter().any(|n| n == output) {
+            names.push(output.to_string());
+        }
+    }
+    Ok(Some(names))
+}
+
+fn describe_outputs(outputs: Option<&[String]>) -> String {
+    match outputs {
+        None => "the default outputs (man, doc, info, dev)".to_string(),
+        Some([]) => "no extra outputs".to_string(),
+        Some(outputs) => format!("outputs {}", outputs.join(", ")),
+    }
+}
+
+/// After a setting of profile `name` changed in `nixy_config`: rebuild it
+/// when it is active, putting `original_config` back if that fails, or
+/// regenerate its flake for the next switch otherwise
+fn rebuild_changed_profile(
+    config: &Config,
+    name: &str,
+    nixy_config: &NixyConfig,
+    original_config: &NixyConfig,
+    flag: &str,
+    what: &str,
+) -> Result<()> {
+    if get_active_profile(config) != name {
+        regenerate_flake_from_profile(
+            &get_profile_flake_dir(config, name)?,
+            &nixy_config.profiles[name],
+            Some(config.global_packages_dir.as_path()),
+            nixy_config.format,
+        )?;
+        Profile::new(name, config).mark_needs_sync(&format!("nixy profile {}", flag))?;
+        success(&format!(
+            "Profile '{}' will be built when you switch to it",
+            name
+        ));
+        return Ok(());
+    }
+
+    if let Err(e) = super::sync::sync(config, Default::default()) {
+        original_config.save(config)?;
+        let _ = regenerate_flake_from_profile(
+            &get_profile_flake_dir(config, name)?,
+            &original_config.profiles[name],
+            Some(config.global_packages_dir.as_path()),
+            original_config.format,
+        );
+        warn(&format!("Build failed. Reverted {}.", what));
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// The flake URL for `--channel`: as given when it has a scheme, else a
+/// branch of NixOS/nixpkgs; empty for the default
+fn channel_url(channel: &str) -> Result<String> {
+    let channel = channel.trim();
+    if channel.is_empty() {
+        return Ok(DEFAULT_NIXPKGS_URL.to_string());
+    }
+    if channel.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\' || c == '$') {
+        return Err(Error::Usage(format!(
+            "Invalid channel '{}'. Give a flake URL or a nixpkgs branch such as nixos-24.05.",
+            channel
+        )));
+    }
+    if channel.contains(':') {
+        Ok(channel.to_string())
+    } else {
+        Ok(format!("github:NixOS/nixpkgs/{}", channel))
+    }
+}
+
+fn rename(config: &Config, name: &str, new_name: &str) -> Result<()> {
+    if !nixy_json_exists(config) {
+        return Err(Error::Usage(
+            "--rename needs the nixy.json format".to_string(),
+        ));
+    }
+
+    let mut nixy_config = NixyConfig::load(config)?;
+    nixy_config.rename_profile(name, new_name)?;
+
+    // Move the flake.nix / flake.lock (and any needs-sync marker) first: if
+    // saving nixy.json fails, they can be moved back
+    let from = Profile::new(name, config);
+    let to = Profile::new(new_name, config);
+    if to.state_dir.exists() {
+        return Err(Error::Usage(format!(
+            "{} is in the way. Remove it before renaming.",
+            to.state_dir.display()
+        )));
+    }
+    if from.state_dir.exists() {
+        fs::rename(&from.state_dir, &to.state_dir)?;
+    }
+    if let Err(e) = nixy_config.save(config) {
+        if to.state_dir.exists() {
+            let _ = fs::rename(&to.state_dir, &from.state_dir);
+        }
+        return Err(e);
+    }
+
+    // The build's GC root is the out-link's path, so the renamed link has
+    // to be registered again, or the environment becomes garbage
+    if let Ok(build) = fs::read_link(&from.build_link) {
+        if let Err(e) = Nix::add_root(&build, &to.build_link) {
+            warn(&e.to_string());
+            repoint_link(&to.build_link, &build)?;
+            to.mark_needs_sync("nixy profile --rename")?;
+        }
+        fs::remove_file(&from.build_link)?;
+    }
+    // Generations restore into the profile they were recorded for
+    for generation in Snapshot::list(config, &generation_tag(name))? {
+        generation.rename_profile(name, new_name)?;
+    }
+    if nixy_config.active_profile == new_name {
+        update_fonts(config, new_name);
+    }
+
+    success(&format!("Renamed profile '{}' to '{}'", name, new_name));
+    Ok(())
+}
+
+/// Create profile `name` as a copy of `source`, without switching to it;
+/// an existing `name` is only replaced with `force`
+fn copy(config: &Config, source: &str, name: &str, force: bool) -> Result<()> {
+    if !nixy_json_exists(config) {
+        return Err(Error::Usage(
+            "--copy needs the nixy.json format".to_string(),
+        ));
+    }
+
+    let mut nixy_config = NixyConfig::load(config)?;
+    let replaced = nixy_config.profiles.contains_key(name);
+    match nixy_config.copy_profile(source, name, force) {
+        Err(Error::ProfileExists(_)) => {
+            return Err(Error::Usage(format!(
+                "Profile '{}' already exists. Use --force to replace it.",
+                name
+            )));
+        }
+        result => result?,
+    }
+    nixy_config.save(config)?;
+
+    // Profile flakes refer to local packages by their absolute path in the
+    // global packages/ directory, so the copied flake.nix works as it is
+    let from = Profile::new(source, config);
+    let to = Profile::new(name, config);
+    to.create()?;
+    for file in ["flake.nix", "flake.lock"] {
+        if from.state_dir.join(file).exists() {
+            fs::copy(from.state_dir.join(file), to.state_dir.join(file))?;
+        } else if to.state_dir.join(file).exists() {
+            // Don't keep a replaced profile's lock around
+            fs::remove_file(to.state_dir.join(file))?;
+        }
+    }
+    if !to.flake_path.exists() {
+        let global_packages_dir = if config.global_packages_dir.exists() {
+            Some(config.global_packages_dir.as_path())
+        } else {
+            None
+        };
+        regenerate_flake_from_profile(
+            &to.state_dir,
+            &nixy_config.profiles[name],
+            global_packages_dir,
+            nixy_config.format,
+        )?;
+    }
+    to.mark_needs_sync(&format!("nixy profile {} --copy {}", name, source))?;
+
+    if replaced {
+        success(&format!(
+            "Replaced profile '{}' with a copy of '{}'",
+            name, source
+        ));
+    } else {
+        success(&format!("Copied profile '{}' to '{}'", source, name));
+    }
+    Ok(())
+}
+
+fn switch(config: &Config, name: &str, create: bool, copy_lock_from: Option<&str>) -> Result<()> {
     validate_profile_name(name)?;
 
     let profile = Profile::new(name, config);
@@ -143,6 +505,7 @@ fn switch(config: &Config, name: &str, create: bool) -> Result<()> {
                 &profile.state_dir,
                 &profile_config,
                 global_packages_dir,
+                nixy_config.format,
             )?;
         } else {
             return Err(Error::Usage(format!(
@@ -153,24 +516,59 @@ fn switch(config: &Config, name: &str, create: bool) -> Result<()> {
     }
 
     info(&format!("Switching to profile '{}'...", name));
-    set_active_profile(config, name)?;
-
-    // Build environment for the new profile
-    if profile.flake_path.exists() {
-        info(&format!("Building environment for profile '{}'...", name));
 
-        if let Some(parent) = config.env_link.parent() {
-            fs::create_dir_all(parent)?;
+    if nixy_json_exists(config) {
+        let nixy_config = NixyConfig::load(config)?;
+        // nixy.json may have been restored without the state directory;
+        // rebuild the flake from it rather than report an empty profile
+        if !profile.flake_path.exists() {
+            if let Some(profile_config) = nixy_config.profiles.get(name) {
+                let has_packages = !profile_config.packages.is_empty()
+                    || !profile_config.resolved_packages.is_empty()
+                    || !profile_config.custom_packages.is_empty();
+                if has_packages {
+                    regenerate_flake_from_profile(
+                        &get_profile_flake_dir(config, name)?,
+                        profile_config,
+                        Some(config.global_packages_dir.as_path()),
+                        nixy_config.format,
+                    )?;
+                }
+            }
         }
+        prepare_lock(
+            config,
+            &nixy_config,
+            name,
+            &profile.state_dir,
+            copy_lock_from,
+            &format!("nixy profile {}", name),
+        )?;
+    } else if copy_lock_from.is_some() {
+        return Err(Error::Usage(
+            "--copy-lock-from needs the nixy.json format".to_string(),
+        ));
+    }
+
+    if let Some(marker) = profile.needs_sync() {
+        info(&format!(
+            "Profile '{}' changed since it was last built: {}",
+            name,
+            stale_notice(&marker)
+        ));
+    }
 
-        // Use get_flake_dir to resolve symlinks consistently with sync/upgrade
-        let flake_dir = get_flake_dir(config)?;
+    // Build the new profile before switching to it, so a failed build leaves
+    // the previous profile active and its environment in place
+    if profile.flake_path.exists() {
+        info(&format!("Building environment for profile '{}'...", name));
+        let flake_dir = profile.state_dir.clone();
 
         // Re-lock local `path:` inputs before building. Their flake.lock
         // entries pin a content hash (narHash), so any change to a local
         // package directory makes the existing lock stale and `nix build`
         // fails with a "NAR hash mismatch" error.
-        if flake_dir.join("flake.lock").exists() {
+        if !Nix::frozen() && flake_dir.join("flake.lock").exists() {
             let local_inputs = local_path_input_names(&config.global_packages_dir);
             if !local_inputs.is_empty() {
                 info("Refreshing local package inputs...");
@@ -180,15 +578,25 @@ fn switch(config: &Config, name: &str, create: bool) -> Result<()> {
             }
         }
 
-        match Nix::build(&flake_dir, "default", &config.env_link) {
-            Ok(_) => success(&format!("Switched to profile '{}'", name)),
-            Err(e) => {
-                warn("Profile switched but environment build failed. Run 'nixy sync' to rebuild.");
-                error(&format!("{}", e));
-                success(&format!("Switched to profile '{}'", name));
+        // nix only replaces the profile's build link once the build succeeds
+        if let Err(e) = profile.build(&flake_dir) {
+            if profile.needs_sync().is_none() {
+                profile.mark_needs_sync("build failed on switch")?;
             }
+            warn(&format!(
+                "Build failed; still on profile '{}'.",
+                get_active_profile(config)
+            ));
+            return Err(explain_build_error(config, name, e));
         }
+        set_active_profile(config, name)?;
+        repoint_link(&config.env_link, &fs::read_link(&profile.build_link)?)?;
+        profile.clear_needs_sync();
+        record_generation(config, name);
+        update_fonts(config, name);
+        success(&format!("Switched to profile '{}'", name));
     } else {
+        set_active_profile(config, name)?;
         success(&format!(
             "Switched to profile '{}' (no packages installed)",
             name
@@ -198,6 +606,51 @@ fn switch(config: &Config, name: &str, create: bool) -> Result<()> {
     Ok(())
 }
 
+/// Switch without touching the flake or building: point the env link at the
+/// profile's last build, if it still has one
+fn activate_only(config: &Config, name: &str) -> Result<()> {
+    validate_profile_name(name)?;
+
+    let profile = Profile::new(name, config);
+    let profile_exists = if nixy_json_exists(config) {
+        Profile::exists_in_config(name, config)
+    } else {
+        profile.exists()
+    };
+    if !profile_exists {
+        return Err(Error::ProfileNotFound(name.to_string()));
+    }
+
+    set_active_profile(config, name)?;
+
+    match profile.last_build() {
+        Some(build) => {
+            repoint_link(&config.env_link, &build)?;
+            update_fonts(config, name);
+            success(&format!("Switched to profile '{}' (last build)", name));
+            if let Some(marker) = profile.needs_sync() {
+                warn(&format!(
+                    "Profile '{}': {}. Run 'nixy sync' to rebuild.",
+                    name,
+                    stale_notice(&marker)
+                ));
+            }
+        }
+        None => {
+            if profile.needs_sync().is_none() {
+                profile.mark_needs_sync("activated without a build")?;
+            }
+            success(&format!("Switched to profile '{}'", name));
+            warn(&format!(
+                "Profile '{}' has no build yet; the environment still has the previous profile's packages. Run 'nixy sync' to build it.",
+                name
+            ));
+        }
+    }
+
+    Ok(())
+}
+
 fn delete_interactive(config: &Config, name: &str) -> Result<()> {
     validate_profile_name(name)?;
 
@@ -216,25 +669,13 @@ fn delete_interactive(config: &Config, name: &str) -> Result<()> {
 
     let active = get_active_profile(config);
     if name == active {
-        return Err(Error::CannotDeleteActiveProfile);
+        return Err(Error::CannotDeleteActiveProfile(name.to_string()));
     }
-
-    // If not a TTY, require explicit confirmation
-    if !io::stdin().is_terminal() {
-        return Err(Error::Usage(
-            "Cannot delete profile non-interactively. Use a terminal for confirmation.".to_string(),
-        ));
+    if name == DEFAULT_PROFILE {
+        return Err(Error::CannotDeleteDefaultProfile);
     }
 
-    warn(&format!(
-        "This will delete profile '{}' and all its packages.",
-        name
-    ));
-
-    let confirmed = Confirm::new()
-        .with_prompt("Are you sure?")
-        .default(false)
-        .interact()?;
+    let confirmed = confirm(&format!("Delete profile '{}' and all its packages?", name))?;
 
     if !confirmed {
         info("Deletion cancelled.");
@@ -246,6 +687,13 @@ fn delete_interactive(config: &Config, name: &str) -> Result<()> {
     // Delete state directory first to avoid inconsistent state
     // (if this fails, the config still references the profile, which is fine)
     profile.delete()?;
+    // A new profile of the same name must not roll back into this one
+    for generation in Snapshot::list(config, &generation_tag(name))? {
+        generation.remove()?;
+    }
+    if fonts::linked_profile(config).as_deref() == Some(name) {
+        unlink_fonts(config);
+    }
 
     // Delete from nixy.json if using new format
     if nixy_json_exists(config) {
@@ -258,3 +706,34 @@ fn delete_interactive(config: &Config, name: &str) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_url() {
+        assert_eq!(
+            channel_url("nixos-24.05").unwrap(),
+            "github:NixOS/nixpkgs/nixos-24.05"
+        );
+        assert_eq!(
+            channel_url("github:me/nixpkgs/my-branch").unwrap(),
+            "github:me/nixpkgs/my-branch"
+        );
+        assert_eq!(channel_url("").unwrap(), DEFAULT_NIXPKGS_URL);
+        assert!(channel_url("nixos 24.05").is_err());
+        assert!(channel_url("github:a/b\"; evil").is_err());
+    }
+
+    #[test]
+    fn test_parse_outputs() {
+        assert_eq!(
+            parse_outputs("man, info,,man").unwrap(),
+            Some(vec!["man".to_string(), "info".to_string()])
+        );
+        assert_eq!(parse_outputs("").unwrap(), Some(Vec::new()));
+        assert_eq!(parse_outputs(" default ").unwrap(), None);
+        assert!(parse_outputs("man\" info").is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::cli::RollbackArgs;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::nixy_config::{nixy_json_exists, NixyConfig};
+use crate::profile::{repoint_link, Profile};
+use crate::snapshot::{generation_tag, Snapshot};
+
+use super::{info, success, time_ago, update_fonts, warn};
+
+pub fn run(config: &Config, args: RollbackArgs) -> Result<()> {
+    if !nixy_json_exists(config) {
+        return Err(Error::Usage(
+            "Nothing to roll back: environments are only recorded with nixy.json.".to_string(),
+        ));
+    }
+    let active = NixyConfig::load(config)?.active_profile;
+    let mut generations = Snapshot::list(config, &generation_tag(&active))?;
+
+    if args.list {
+        list(&active, &generations);
+        return Ok(());
+    }
+
+    let Some(current) = generations.pop() else {
+        return Err(no_earlier(&active));
+    };
+    // Older builds are not GC roots; skip the ones nix-collect-garbage took
+    let Some(at) = generations.iter().rposition(is_alive) else {
+        return match generations.last() {
+            Some(previous) => Err(Error::GenerationGone {
+                target: previous.meta.env.clone().unwrap_or_default(),
+            }),
+            None => Err(no_earlier(&active)),
+        };
+    };
+    let previous = generations.remove(at);
+    let collected = generations.split_off(at);
+    let env = previous.meta.env.clone().unwrap_or_default();
+    for generation in collected {
+        warn(&format!(
+            "Skipping the environment from '{}' ({}): {} was garbage-collected",
+            generation.meta.command,
+            when(&generation),
+            generation.meta.env.clone().unwrap_or_default().display()
+        ));
+        generation.remove()?;
+    }
+
+    info(&format!(
+        "Rolling back profile '{}' to its environment from '{}' ({})...",
+        active,
+        previous.meta.command,
+        when(&previous)
+    ));
+    previous.restore(config)?;
+    let profile = Profile::new(&active, config);
+    repoint_link(&profile.build_link, &env)?;
+    repoint_link(&config.env_link, &env)?;
+    profile.clear_needs_sync();
+    // Rolling back twice should reach the environment before this one
+    current.remove()?;
+    update_fonts(config, &active);
+
+    success("Rolled back");
+    Ok(())
+}
+
+/// Print the environments recorded for `profile`, oldest first
+fn list(profile: &str, generations: &[Snapshot]) {
+    if generations.is_empty() {
+        info(&format!(
+            "No environments recorded for profile '{}' yet",
+            profile
+        ));
+        return;
+    }
+
+    info(&format!("Environments of profile '{}':", profile));
+    let width = generations.iter().map(|g| when(g).len()).max().unwrap_or(0);
+    for (i, generation) in generations.iter().enumerate() {
+        let note = if i + 1 == generations.len() {
+            "  (current)"
+        } else if !is_alive(generation) {
+            "  (garbage-collected)"
+        } else {
+            ""
+        };
+        println!(
+            "  {:>2}  {:<width$}  {}{}",
+            i + 1,
+            when(generation),
+            generation.meta.command,
+            note,
+            width = width
+        );
+        if let Some(env) = &generation.meta.env {
+            println!("      {:<width$}  {}", "", env.display(), width = width);
+        }
+    }
+}
+
+fn no_earlier(profile: &str) -> Error {
+    Error::Usage(format!(
+        "No earlier environment of profile '{}' to roll back to.",
+        profile
+    ))
+}
+
+/// Whether the store path built for `generation` is still there
+fn is_alive(generation: &Snapshot) -> bool {
+    generation.meta.env.as_ref().is_some_and(|env| env.exists())
+}
+
+fn when(generation: &Snapshot) -> String {
+    time_ago(UNIX_EPOCH + Duration::from_secs(generation.meta.created))
+}
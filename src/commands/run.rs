@@ -0,0 +1,121 @@
+//! Run a package's program once, without installing it.
+
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+use crate::cli::RunArgs;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::nix::Nix;
+use crate::nixhub::parse_package_spec;
+use crate::rollback;
+
+use super::{info, leave_stdout_to_child, nixhub_client};
+
+pub fn run(config: &Config, args: RunArgs) -> Result<()> {
+    leave_stdout_to_child();
+    let installable = installable(config, &args.package, args.from.as_deref())?;
+
+    info(&format!("Running {}...", installable));
+    rollback::set_child_running(true);
+    let status = Nix::run(&installable, &args.args);
+    rollback::set_child_running(false);
+
+    match exit_code(status?) {
+        0 => Ok(()),
+        code => Err(Error::CommandExited(code)),
+    }
+}
+
+/// What `nix run` runs for `package`: `<from>#<package>` with `--from`, a
+/// flake reference as it is, and otherwise the nixpkgs commit Nixhub
+/// resolves the package (and its version, if any) to, like `nixy install`
+fn installable(config: &Config, package: &str, from: Option<&str>) -> Result<String> {
+    if let Some(flake) = from {
+        return from_flake(package, flake);
+    }
+    if package.contains(':') {
+        return Ok(package.to_string());
+    }
+
+    let spec = parse_package_spec(package);
+    let version = spec.version.as_deref().unwrap_or("latest");
+    info(&format!(
+        "Resolving {}@{} via Nixhub...",
+        spec.name, version
+    ));
+    let resolved = nixhub_client(config, false)
+        .resolve_for_current_system(&spec.name, version)
+        .map_err(|e| match e {
+            Error::NixhubPackageNotFound(name) => Error::PackageNotFound(name),
+            Error::NixhubVersionNotFound(name, _) if spec.version.is_none() => {
+                Error::PackageNotFound(name)
+            }
+            e => e,
+        })?;
+    Ok(nixpkgs_installable(
+        &resolved.commit_hash,
+        &resolved.attribute_path,
+    ))
+}
+
+/// `<flake>#<package>`, for `--from <flake>`; `package` must be a bare name
+pub(super) fn from_flake(package: &str, flake: &str) -> Result<String> {
+    if package.contains(['#', ':', '@']) {
+        return Err(Error::Usage(format!(
+            "--from takes package names, not '{}': e.g. <package> --from {}",
+            package, flake
+        )));
+    }
+    Ok(format!("{}#{}", flake, package))
+}
+
+fn nixpkgs_installable(commit: &str, attribute_path: &str) -> String {
+    format!("github:NixOS/nixpkgs/{}#{}", commit, attribute_path)
+}
+
+/// The exit code to pass on: the program's own, or 128 + the signal that
+/// ended it, as shells report it
+pub(super) fn exit_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nixpkgs_installable() {
+        assert_eq!(
+            nixpkgs_installable("a3a3dda3bacf61e8a39258a0ed9c924eeca8e293", "hello"),
+            "github:NixOS/nixpkgs/a3a3dda3bacf61e8a39258a0ed9c924eeca8e293#hello"
+        );
+    }
+
+    #[test]
+    fn test_flake_installables_are_not_resolved() {
+        let config = Config::new();
+        assert_eq!(
+            installable(&config, "tool", Some("github:user/repo")).unwrap(),
+            "github:user/repo#tool"
+        );
+        assert_eq!(
+            installable(&config, "github:user/repo#tool", None).unwrap(),
+            "github:user/repo#tool"
+        );
+        assert!(matches!(
+            installable(&config, "tool@1.0", Some("github:user/repo")),
+            Err(Error::Usage(_))
+        ));
+    }
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(exit_code(ExitStatus::from_raw(3 << 8)), 3);
+        // Killed by SIGINT
+        assert_eq!(exit_code(ExitStatus::from_raw(2)), 130);
+    }
+}
@@ -1,49 +1,411 @@
-use crate::error::Result;
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::cli::SearchArgs;
+use crate::commits::is_commit_hash;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::flake::lock::channel_rev;
+use crate::nix::{Nix, SearchResult};
 use crate::nixhub::NixhubClient;
+use crate::nixy_config::{nixy_json_exists, NixyConfig};
+use crate::profile::{get_active_profile, get_flake_dir, Profile};
+use crate::state::{get_state_path, CustomPackage, PackageState, ResolvedNixpkgPackage};
 
 use super::info;
 
-pub fn run(query: &str) -> Result<()> {
-    info(&format!("Searching for {}...", query));
-
-    let client = NixhubClient::new();
-    let results = client.search(query)?;
+pub fn run(config: &Config, args: SearchArgs) -> Result<()> {
+    let rev = if args.pinned {
+        Some(pinned_rev(config)?)
+    } else {
+        args.rev
+    };
+    // Keep stdout to the results with --json
+    let (results, total) = match &rev {
+        Some(rev) => {
+            check_rev(rev)?;
+            if !args.json {
+                info(&format!("Searching nixpkgs {} for {}...", rev, args.query));
+            }
+            let results = Nix::search_rev(rev, &args.query)?;
+            let total = results.len();
+            (results, total)
+        }
+        None => {
+            if !args.json {
+                info(&format!("Searching for {}...", args.query));
+            }
+            search_nixhub(&args.query)?
+        }
+    };
+    let installed = installed_markers(config);
+    let (results, total) = if args.installed_only {
+        let results: Vec<SearchResult> = results
+            .into_iter()
+            .filter(|r| installed.contains_key(&r.name))
+            .collect();
+        let total = results.len();
+        (results, total)
+    } else {
+        (results, total)
+    };
+    let shown = &results[..results.len().min(args.limit)];
 
-    if results.results.is_empty() {
-        println!("No packages found for '{}'", query);
+    if args.json {
+        println!("{}", serde_json::json!(shown));
         return Ok(());
     }
 
+    if shown.is_empty() {
+        if args.installed_only {
+            println!("No installed packages found for '{}'", args.query);
+        } else {
+            println!("No packages found for '{}'", args.query);
+        }
+        return Ok(());
+    }
+    println!();
+    if total > shown.len() {
+        println!(
+            "Found {} packages, showing the first {} (see --limit):",
+            total,
+            shown.len()
+        );
+    } else {
+        println!("Found {} packages:", total);
+    }
     println!();
-    println!("Found {} packages:", results.total_results);
+    let width = console::Term::stdout()
+        .size_checked()
+        .map(|(_, cols)| cols as usize);
+    for line in format_table(shown, &installed, width) {
+        println!("{}", line);
+    }
+
+    // Nixhub results carry no version: list them for the most relevant one
+    if rev.is_none() {
+        show_versions(&shown[0].name);
+    }
+    Ok(())
+}
+
+fn check_rev(rev: &str) -> Result<()> {
+    if !is_commit_hash(rev) {
+        return Err(Error::Usage(format!(
+            "--rev expects a nixpkgs commit hash (7 to 40 hex digits), got '{}'",
+            rev
+        )));
+    }
+    Ok(())
+}
+
+/// The nixpkgs commit the active profile's flake.lock pins its channel to
+fn pinned_rev(config: &Config) -> Result<String> {
+    let profile = Profile::new(&get_active_profile(config), config);
+    let lock_path = profile.state_dir.join("flake.lock");
+    if !lock_path.exists() {
+        return Err(Error::NoFlakeLock);
+    }
+    channel_rev(&fs::read_to_string(&lock_path)?).ok_or(Error::InvalidFlakeLock)
+}
+
+/// Nixhub's results for `query`, and how many it found in all
+fn search_nixhub(query: &str) -> Result<(Vec<SearchResult>, usize)> {
+    let response = NixhubClient::new().search(query)?;
+    let results: Vec<SearchResult> = response
+        .results
+        .into_iter()
+        .map(|pkg| SearchResult {
+            name: pkg.name,
+            version: None,
+            description: pkg.summary,
+        })
+        .collect();
+    let total = (response.total_results.max(0) as usize).max(results.len());
+    Ok((results, total))
+}
+
+/// What to mark search hits for installed packages with, by the nixpkgs
+/// name (or attribute) they are found under: `[installed]`, with the
+/// version of a resolved package and the entry name of one installed with
+/// `--as`
+///
+/// Like completion, this never fails: without a readable config nothing is
+/// marked.
+fn installed_markers(config: &Config) -> BTreeMap<String, String> {
+    if nixy_json_exists(config) {
+        NixyConfig::load(config)
+            .ok()
+            .and_then(|nixy_config| {
+                let profile = nixy_config.get_active_profile()?;
+                Some(markers(
+                    &profile.packages,
+                    &profile.resolved_packages,
+                    &profile.custom_packages,
+                ))
+            })
+            .unwrap_or_default()
+    } else {
+        get_flake_dir(config)
+            .and_then(|flake_dir| PackageState::load(&get_state_path(&flake_dir)))
+            .map(|state| {
+                markers(
+                    &state.packages,
+                    &state.resolved_packages,
+                    &state.custom_packages,
+                )
+            })
+            .unwrap_or_default()
+    }
+}
+
+fn markers(
+    packages: &[String],
+    resolved: &[ResolvedNixpkgPackage],
+    custom: &[CustomPackage],
+) -> BTreeMap<String, String> {
+    let mut labels: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for name in packages {
+        labels.entry(name.clone()).or_default();
+    }
+    for pkg in resolved {
+        let label = if pkg.name == pkg.source_package_name() {
+            pkg.resolved_version.clone()
+        } else {
+            format!("{} as {}", pkg.resolved_version, pkg.name)
+        };
+        labels
+            .entry(pkg.source_package_name().to_string())
+            .or_default()
+            .push(label.clone());
+        // `nix search` finds versioned packages under their attribute
+        if pkg.attribute_path != pkg.source_package_name() {
+            labels
+                .entry(pkg.attribute_path.clone())
+                .or_default()
+                .push(label);
+        }
+    }
+    for pkg in custom {
+        labels.entry(pkg.name.clone()).or_default();
+    }
+    labels
+        .into_iter()
+        .map(|(name, labels)| {
+            let marker = if labels.is_empty() {
+                "[installed]".to_string()
+            } else {
+                format!("[installed {}]", labels.join(", "))
+            };
+            (name, marker)
+        })
+        .collect()
+}
+
+fn show_versions(name: &str) {
     println!();
+    info(&format!("Fetching versions for {}...", name));
 
-    for pkg in &results.results {
-        println!("  {} - {}", pkg.name, pkg.summary);
-    }
-
-    // Show version info for the first (most relevant) result
-    if let Some(first) = results.results.first() {
-        println!();
-        info(&format!("Fetching versions for {}...", first.name));
-
-        match client.get_package(&first.name) {
-            Ok(details) => {
-                if !details.releases.is_empty() {
-                    println!();
-                    println!("Available versions for {}:", first.name);
-                    for release in &details.releases {
-                        println!("  {}", release.version);
-                    }
-                    println!();
-                    println!("Install with: nixy add {}@<version>", first.name);
+    match NixhubClient::new().get_package(name) {
+        Ok(details) => {
+            if !details.releases.is_empty() {
+                println!();
+                println!("Available versions for {}:", name);
+                for release in &details.releases {
+                    println!("  {}", release.version);
                 }
+                println!();
+                println!("Install with: nixy add {}@<version>", name);
             }
-            Err(e) => {
-                eprintln!("  Failed to fetch versions: {}", e);
+        }
+        Err(e) => {
+            eprintln!("  Failed to fetch versions: {}", e);
+        }
+    }
+}
+
+/// One aligned line per result: name, version (when any result has one) and
+/// description, cut to fit `width` columns, then the marker of an installed
+/// package from `installed`
+fn format_table(
+    results: &[SearchResult],
+    installed: &BTreeMap<String, String>,
+    width: Option<usize>,
+) -> Vec<String> {
+    let name_width = results
+        .iter()
+        .map(|r| r.name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let version_width = results
+        .iter()
+        .filter_map(|r| r.version.as_ref())
+        .map(|v| v.chars().count())
+        .max();
+
+    results
+        .iter()
+        .map(|r| {
+            let mut line = format!("  {:<name_width$}", r.name);
+            if let Some(version_width) = version_width {
+                let version = r.version.as_deref().unwrap_or("");
+                line.push_str(&format!("  {:<version_width$}", version));
+            }
+            let marker = installed.get(&r.name);
+            let used =
+                line.chars().count() + 2 + marker.map_or(0, |marker| marker.chars().count() + 1);
+            let description = match width {
+                Some(width) => truncate(&r.description, width.saturating_sub(used)),
+                None => r.description.clone(),
+            };
+            let mut line = if description.is_empty() {
+                line.trim_end().to_string()
+            } else {
+                format!("{}  {}", line, description)
+            };
+            if let Some(marker) = marker {
+                line.push(' ');
+                line.push_str(marker);
             }
+            line
+        })
+        .collect()
+}
+
+/// `text` cut to at most `max` characters, ending in "…" when cut
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    if max == 0 {
+        return String::new();
+    }
+    let mut cut: String = text.chars().take(max - 1).collect();
+    cut.push('…');
+    cut
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(name: &str, version: Option<&str>, description: &str) -> SearchResult {
+        SearchResult {
+            name: name.to_string(),
+            version: version.map(String::from),
+            description: description.to_string(),
         }
     }
 
-    Ok(())
+    #[test]
+    fn test_format_table_aligns_columns() {
+        let results = [
+            result(
+                "hello",
+                Some("2.12.1"),
+                "A program that produces a familiar greeting",
+            ),
+            result("ripgrep", Some("14.1.1"), "Fast grep"),
+            result("empty", None, ""),
+        ];
+        assert_eq!(
+            format_table(&results, &BTreeMap::new(), None),
+            vec![
+                "  hello    2.12.1  A program that produces a familiar greeting",
+                "  ripgrep  14.1.1  Fast grep",
+                "  empty",
+            ]
+        );
+        assert_eq!(
+            format_table(&results[..1], &BTreeMap::new(), Some(30)),
+            vec!["  hello  2.12.1  A program th…"]
+        );
+    }
+
+    #[test]
+    fn test_format_table_without_versions() {
+        let results = [
+            result("fd", None, "Find files"),
+            result("ripgrep", None, "Grep"),
+        ];
+        assert_eq!(
+            format_table(&results, &BTreeMap::new(), None),
+            vec!["  fd       Find files", "  ripgrep  Grep"]
+        );
+    }
+
+    #[test]
+    fn test_markers() {
+        let resolved = |name: &str, attribute_path: &str, version: &str| ResolvedNixpkgPackage {
+            name: name.to_string(),
+            version_spec: None,
+            resolved_version: version.to_string(),
+            attribute_path: attribute_path.to_string(),
+            commit_hash: "abc123".to_string(),
+            platforms: None,
+            pinned: false,
+            source_name: (name != "ripgrep").then(|| "nodejs".to_string()),
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
+        };
+        let markers = markers(
+            &["fd".to_string()],
+            &[
+                resolved("ripgrep", "ripgrep", "14.1.1"),
+                resolved("node18", "nodejs_18", "18.20.5"),
+                resolved("node20", "nodejs_20", "20.18.1"),
+            ],
+            &[CustomPackage {
+                name: "tool".to_string(),
+                input_name: "tool".to_string(),
+                input_url: "github:owner/tool".to_string(),
+                package_output: "packages".to_string(),
+                source_name: None,
+                platforms: None,
+                unvalidated: false,
+                record: Default::default(),
+            }],
+        );
+
+        assert_eq!(markers["fd"], "[installed]");
+        assert_eq!(markers["ripgrep"], "[installed 14.1.1]");
+        assert_eq!(
+            markers["nodejs"],
+            "[installed 18.20.5 as node18, 20.18.1 as node20]"
+        );
+        assert_eq!(markers["nodejs_20"], "[installed 20.18.1 as node20]");
+        assert_eq!(markers["tool"], "[installed]");
+        assert!(!markers.contains_key("node18"));
+    }
+
+    #[test]
+    fn test_format_table_marks_installed_packages() {
+        let results = [
+            result("ripgrep", None, "Fast grep that respects gitignore"),
+            result("ripgrep-all", None, "Grep in PDFs"),
+        ];
+        let installed = BTreeMap::from([("ripgrep".to_string(), "[installed 14.1.1]".to_string())]);
+        assert_eq!(
+            format_table(&results, &installed, None),
+            vec![
+                "  ripgrep      Fast grep that respects gitignore [installed 14.1.1]",
+                "  ripgrep-all  Grep in PDFs",
+            ]
+        );
+        // The marker is kept, the description cut instead
+        assert_eq!(
+            format_table(&results[..1], &installed, Some(40)),
+            vec!["  ripgrep  Fast grep… [installed 14.1.1]"]
+        );
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("exactly", 7), "exactly");
+        assert_eq!(truncate("too long", 4), "too…");
+        assert_eq!(truncate("anything", 0), "");
+    }
 }
@@ -0,0 +1,175 @@
+//! Start a shell with extra packages layered on the active profile.
+//!
+//! The packages are resolved like `nixy install` does, added to a copy of the
+//! active profile, and built from a flake in a scratch directory. nixy.json
+//! and the profile's own flake are never written; the scratch directory (and
+//! with it the build's GC root) is removed when the shell exits.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+use crate::cli::ShellArgs;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::flake::template::regenerate_flake_from_profile;
+use crate::nix::Nix;
+use crate::nixy_config::{nixy_json_exists, NixyConfig, ProfileConfig};
+use crate::profile::get_flake_dir;
+use crate::rollback;
+
+use super::install::{resolve_entry, ProfileEntry};
+use super::run::{exit_code, from_flake};
+use super::{check_local_packages, info, leave_stdout_to_child, nixhub_client, success};
+
+pub fn run(config: &Config, args: ShellArgs) -> Result<()> {
+    leave_stdout_to_child();
+    if !nixy_json_exists(config) {
+        return Err(Error::Usage(
+            "nixy shell needs nixy.json. Run 'nixy sync' to migrate first.".to_string(),
+        ));
+    }
+    let specs = match &args.from {
+        Some(flake) => args
+            .packages
+            .iter()
+            .map(|package| from_flake(package, flake))
+            .collect::<Result<Vec<_>>>()?,
+        None => args.packages.clone(),
+    };
+
+    let nixy_config = NixyConfig::load(config)?;
+    let active = nixy_config.active_profile.clone();
+    let mut profile = nixy_config
+        .get_active_profile()
+        .cloned()
+        .ok_or_else(|| Error::ProfileNotFound(active.clone()))?;
+    check_local_packages(config, &nixy_config, &profile)?;
+
+    // Every package is resolved before anything is built
+    let added = add_packages(config, &mut profile, &specs)?;
+
+    let scratch = ScratchDir::create()?;
+    let flake_dir = scratch.path.join("flake");
+    fs::create_dir_all(&flake_dir)?;
+    // The profile's flake.nix brings its user blocks along, and its lock
+    // keeps the packages already installed at the versions they are at
+    let profile_dir = get_flake_dir(config)?;
+    for file in ["flake.nix", "flake.lock"] {
+        if profile_dir.join(file).exists() {
+            fs::copy(profile_dir.join(file), flake_dir.join(file))?;
+        }
+    }
+    regenerate_flake_from_profile(
+        &flake_dir,
+        &profile,
+        Some(config.global_packages_dir.as_path()),
+        nixy_config.format,
+    )?;
+
+    info(&format!(
+        "Building profile '{}' with {}...",
+        active,
+        added.join(", ")
+    ));
+    let env_link = scratch.path.join("env");
+    Nix::build(&flake_dir, "default", &env_link)?;
+
+    let shell = env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+    success(&format!(
+        "Starting {} with {}; exit it to leave them behind",
+        shell,
+        added.join(", ")
+    ));
+    rollback::set_child_running(true);
+    let status = Command::new(&shell)
+        .env("PATH", search_path(&env_link)?)
+        .status();
+    rollback::set_child_running(false);
+    drop(scratch);
+
+    match exit_code(status?) {
+        0 => Ok(()),
+        code => Err(Error::CommandExited(code)),
+    }
+}
+
+/// Add the packages of `specs` to `profile`, returning them as
+/// `name@version` (or just the name for flake packages)
+fn add_packages(
+    config: &Config,
+    profile: &mut ProfileConfig,
+    specs: &[String],
+) -> Result<Vec<String>> {
+    let client = nixhub_client(config, false);
+    let mut added = Vec::new();
+    for spec in specs {
+        match resolve_entry(&client, spec)? {
+            ProfileEntry::Resolved(pkg) => {
+                if profile.has_package(&pkg.name) {
+                    info(&format!("'{}' is already in the profile", pkg.name));
+                    continue;
+                }
+                added.push(format!("{}@{}", pkg.name, pkg.resolved_version));
+                profile.add_resolved_package(pkg);
+            }
+            ProfileEntry::Custom(pkg) => {
+                if profile.has_package(&pkg.name) {
+                    info(&format!("'{}' is already in the profile", pkg.name));
+                    continue;
+                }
+                added.push(pkg.name.clone());
+                profile.add_custom_package(pkg);
+            }
+        }
+    }
+    if added.is_empty() {
+        added.push("nothing extra".to_string());
+    }
+    Ok(added)
+}
+
+/// `PATH` with the environment built at `env_link` in front
+fn search_path(env_link: &Path) -> Result<std::ffi::OsString> {
+    let current = env::var_os("PATH").unwrap_or_default();
+    env::join_paths(std::iter::once(env_link.join("bin")).chain(env::split_paths(&current)))
+        .map_err(|e| Error::Usage(format!("Cannot extend PATH: {}", e)))
+}
+
+/// A directory in the system temp directory, removed when dropped or when
+/// Ctrl+C interrupts the build
+struct ScratchDir {
+    path: PathBuf,
+}
+
+impl ScratchDir {
+    fn create() -> Result<Self> {
+        let path = env::temp_dir().join(format!("nixy-shell-{}", process::id()));
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+        fs::create_dir_all(&path)?;
+        rollback::set_scratch_dir(Some(path.clone()));
+        Ok(Self { path })
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        rollback::set_scratch_dir(None);
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_path_puts_the_environment_first() {
+        let path = search_path(Path::new("/tmp/nixy-shell-1/env")).unwrap();
+        let first = env::split_paths(&path).next().unwrap();
+        assert_eq!(first, PathBuf::from("/tmp/nixy-shell-1/env/bin"));
+    }
+}
@@ -1,18 +1,61 @@
+use std::collections::BTreeMap;
 use std::fs;
+use std::path::{Path, PathBuf};
 
+use colored::Colorize;
+
+use crate::cli::SyncArgs;
 use crate::config::Config;
 use crate::error::{Error, Result};
+use crate::flake::format::format_flake;
+use crate::flake::local_hashes::{local_package_hashes, package_name, LocalChanges};
+use crate::flake::lock::{declared_inputs, prune_lock, stale_lock_inputs};
+use crate::flake::parser::parse_generated_packages;
 use crate::flake::template::{
-    local_path_input_names, regenerate_flake, regenerate_flake_from_profile,
+    generate_flake_from_profile, local_path_input_names, orphaned_nixpkgs_inputs, regenerate_flake,
+    regenerate_flake_from_profile,
 };
 use crate::nix::Nix;
 use crate::nixy_config::{nixy_json_exists, NixyConfig};
-use crate::profile::get_flake_dir;
+use crate::profile::{
+    check_env_link, get_active_profile, get_flake_dir, get_profile_flake_dir, remove_result_links,
+    Profile,
+};
 use crate::state::{get_state_path, PackageState};
 
-use super::{info, success, warn};
+use super::{
+    auto_commit, check_local_packages, error, explain_build_error, info, prepare_lock,
+    print_line_diff, record_generation, report, success, update_fonts, warn,
+};
+
+/// Profiles `sync --all` builds at once
+const PARALLEL_BUILDS: usize = 4;
+
+pub fn run(config: &Config, args: SyncArgs) -> Result<()> {
+    let default_message = "nixy: sync";
+    // --git-commit asks for a commit with the generated message
+    let commit_message = args
+        .commit_message
+        .clone()
+        .or_else(|| args.git_commit.then(|| default_message.to_string()));
+    let dry_run = args.dry_run;
+    sync(config, args)?;
+    if !dry_run {
+        auto_commit(config, commit_message.as_deref(), default_message);
+    }
+    Ok(())
+}
+
+/// Regenerate and build the active profile (or every profile with `--all`);
+/// what other commands run after changing nixy.json
+pub fn sync(config: &Config, args: SyncArgs) -> Result<()> {
+    if args.all {
+        if !nixy_json_exists(config) {
+            return Err(Error::Usage("--all needs the nixy.json format".to_string()));
+        }
+        return sync_all(config, args.prune);
+    }
 
-pub fn run(config: &Config) -> Result<()> {
     let flake_dir = get_flake_dir(config)?;
     let flake_path = flake_dir.join("flake.nix");
 
@@ -24,6 +67,9 @@ pub fn run(config: &Config) -> Result<()> {
         flake_dir.join("packages")
     };
 
+    // Local package hashes to record once the build succeeds
+    let mut local_hashes = None;
+
     // When using nixy.json, always regenerate flake.nix to ensure it reflects
     // the current state (nixy.json is the source of truth)
     if nixy_json_exists(config) {
@@ -31,11 +77,50 @@ pub fn run(config: &Config) -> Result<()> {
         let active_profile_name = nixy_config.active_profile.clone();
         let profile = nixy_config
             .get_active_profile()
-            .ok_or(Error::ProfileNotFound(active_profile_name))?;
+            .ok_or_else(|| Error::ProfileNotFound(active_profile_name.clone()))?;
         // Always pass global_packages_dir from config - even if it doesn't exist yet,
         // it will be created when local packages are installed
         let global_packages_dir = Some(config.global_packages_dir.as_path());
-        regenerate_flake_from_profile(&flake_dir, profile, global_packages_dir)?;
+        check_local_packages(config, &nixy_config, profile)?;
+        local_hashes = check_local_definitions(&nixy_config, &config.global_packages_dir)?;
+        if args.dry_run {
+            let flake = format_flake(
+                &generate_flake_from_profile(profile, global_packages_dir, &flake_dir)?,
+                nixy_config.format,
+            );
+            return dry_run(&flake_path, &flake);
+        }
+        let previous = fs::read_to_string(&flake_path).unwrap_or_default();
+        regenerate_flake_from_profile(
+            &flake_dir,
+            profile,
+            global_packages_dir,
+            nixy_config.format,
+        )?;
+        check_nixpkgs_inputs(&previous, &fs::read_to_string(&flake_path)?);
+        prepare_lock(
+            config,
+            &nixy_config,
+            &active_profile_name,
+            &flake_dir,
+            args.copy_lock_from.as_deref(),
+            "nixy sync",
+        )?;
+        if flake_dir.join("flake.lock").exists() {
+            check_lock_inputs(&flake_dir, args.prune)?;
+        }
+    } else if args.copy_lock_from.is_some() {
+        return Err(Error::Usage(
+            "--copy-lock-from needs the nixy.json format".to_string(),
+        ));
+    } else if args.dry_run {
+        return Err(Error::Usage(
+            "--dry-run needs the nixy.json format".to_string(),
+        ));
+    } else if args.prune {
+        return Err(Error::Usage(
+            "--prune needs the nixy.json format".to_string(),
+        ));
     } else if !flake_path.exists() {
         // Legacy mode: regenerate only if flake.nix is missing
         let state_path = get_state_path(&flake_dir);
@@ -49,32 +134,345 @@ pub fn run(config: &Config) -> Result<()> {
         flake_path.display()
     ));
 
+    prepare_build(&flake_dir, &packages_dir)?;
+
+    // The build below repoints the link, even when nothing else changed
+    if let Some(problem) = check_env_link(&config.env_link, &Nix::store_dir()) {
+        warn(&format!(
+            "Environment {}: {}. Rebuilding it.",
+            config.env_link.display(),
+            problem
+        ));
+    }
+
+    // Build environment and create symlink
+    info("Building nixy environment...");
+
+    let active = get_active_profile(config);
+    let profile = Profile::new(&active, config);
+    profile
+        .build_env(&flake_dir, &config.env_link)
+        .map_err(|e| explain_build_error(config, &active, e))?;
+    profile.clear_needs_sync();
+    record_generation(config, &active);
+    update_fonts(config, &active);
+    if let Some(hashes) = local_hashes {
+        record_local_hashes(config, hashes)?;
+    }
+
+    success("Sync complete");
+    Ok(())
+}
+
+/// Say which local package definitions changed since the last sync, and
+/// fail if one that was there is gone
+///
+/// The generated flake refers to a definition by path, so an edit leaves
+/// flake.nix as it was. Returns the current hashes when they differ from
+/// the recorded ones, to record after a successful build.
+fn check_local_definitions(
+    nixy_config: &NixyConfig,
+    packages_dir: &Path,
+) -> Result<Option<BTreeMap<String, String>>> {
+    let current = local_package_hashes(packages_dir);
+    let changes = LocalChanges::between(&nixy_config.local_hashes, &current);
+    if !changes.removed.is_empty() {
+        let removed: Vec<&str> = changes.removed.iter().map(|p| package_name(p)).collect();
+        return Err(Error::LocalDefinitionsRemoved(removed.join(", ")));
+    }
+    for path in &changes.changed {
+        info(&format!(
+            "Local package {} changed ({})",
+            package_name(path),
+            path
+        ));
+    }
+    Ok((!changes.is_empty()).then_some(current))
+}
+
+/// Record `hashes` as those of the local package definitions just built
+fn record_local_hashes(config: &Config, hashes: BTreeMap<String, String>) -> Result<()> {
+    let mut nixy_config = NixyConfig::load(config)?;
+    nixy_config.local_hashes = hashes;
+    nixy_config.save(config)
+}
+
+/// Get the flake in `flake_dir` ready to build
+fn prepare_build(flake_dir: &Path, packages_dir: &Path) -> Result<()> {
+    // A `result` link from running `nix build` by hand in the flake directory
+    // would be copied into the store with the flake source (and break
+    // evaluation once it dangles).
+    let removed = remove_result_links(flake_dir)?;
+    if !removed.is_empty() {
+        info(&format!(
+            "Removed stray build link(s) from {}: {}",
+            flake_dir.display(),
+            removed.join(", ")
+        ));
+    }
+
     // Re-lock local `path:` inputs before building. Their flake.lock entries
     // pin a content hash (narHash), so any change to a local package
     // directory makes the existing lock stale and `nix build` fails with a
     // "NAR hash mismatch" error.
-    if flake_dir.join("flake.lock").exists() {
-        let local_inputs = local_path_input_names(&packages_dir);
+    // Frozen, the build reports the stale lock instead
+    if !Nix::frozen() && flake_dir.join("flake.lock").exists() {
+        let local_inputs = local_path_input_names(packages_dir);
         if !local_inputs.is_empty() {
             info("Refreshing local package inputs...");
-            if let Err(e) = Nix::flake_update(&flake_dir, &local_inputs) {
+            if let Err(e) = Nix::flake_update(flake_dir, &local_inputs) {
                 // Not fatal on its own: the build below will surface the real
                 // error if the lock is actually broken.
                 warn(&format!("Failed to refresh local package inputs: {}", e));
             }
         }
     }
+    Ok(())
+}
 
-    // Build environment and create symlink
-    info("Building nixy environment...");
+/// Regenerate every profile's flake, then build them [`PARALLEL_BUILDS`] at
+/// a time, continuing past failures.
+///
+/// Each profile builds from its own state directory into its own build
+/// link, so the builds don't share anything; only the active profile's
+/// build is linked as the environment.
+fn sync_all(config: &Config, prune: bool) -> Result<()> {
+    let nixy_config = NixyConfig::load(config)?;
+    let active = nixy_config.active_profile.as_str();
+    let local_hashes = check_local_definitions(&nixy_config, &config.global_packages_dir)?;
+    let mut results: Vec<(String, Result<()>)> = Vec::new();
+    let mut ready: Vec<(String, PathBuf)> = Vec::new();
 
-    // Ensure parent directory exists
-    if let Some(parent) = config.env_link.parent() {
-        fs::create_dir_all(parent)?;
+    for (name, profile) in &nixy_config.profiles {
+        info(&format!("Regenerating profile '{}'...", name));
+        let prepared = get_profile_flake_dir(config, name).and_then(|flake_dir| {
+            check_local_packages(config, &nixy_config, profile)?;
+            regenerate_flake_from_profile(
+                &flake_dir,
+                profile,
+                Some(config.global_packages_dir.as_path()),
+                nixy_config.format,
+            )?;
+            prepare_lock(config, &nixy_config, name, &flake_dir, None, "nixy sync")?;
+            if flake_dir.join("flake.lock").exists() {
+                check_lock_inputs(&flake_dir, prune)?;
+            }
+            prepare_build(&flake_dir, &config.global_packages_dir)?;
+            Ok(flake_dir)
+        });
+        match prepared {
+            Ok(flake_dir) => ready.push((name.clone(), flake_dir)),
+            Err(e) => results.push((name.clone(), Err(e))),
+        }
+    }
+
+    info(&format!("Building {} profile(s)...", ready.len()));
+    for chunk in ready.chunks(PARALLEL_BUILDS) {
+        let built: Vec<(String, Result<()>)> = std::thread::scope(|s| {
+            let handles: Vec<_> = chunk
+                .iter()
+                .map(|(name, flake_dir)| {
+                    s.spawn(move || {
+                        let profile = Profile::new(name, config);
+                        let result = if name == active {
+                            profile.build_env(flake_dir, &config.env_link)
+                        } else {
+                            profile.build(flake_dir)
+                        };
+                        (name.clone(), result)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("profile build panicked"))
+                .collect()
+        });
+        for (name, result) in built {
+            let result = result.map_err(|e| explain_build_error(config, &name, e));
+            match &result {
+                Ok(()) => {
+                    Profile::new(&name, config).clear_needs_sync();
+                    record_generation(config, &name);
+                    if name == active {
+                        update_fonts(config, &name);
+                    }
+                }
+                Err(e) => error(&format!("Profile '{}': {}", name, e)),
+            }
+            results.push((name, result));
+        }
     }
 
-    Nix::build(&flake_dir, "default", &config.env_link)?;
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    info("Summary:");
+    let width = results.iter().map(|(n, _)| n.len()).max().unwrap_or(0);
+    let mut failed = Vec::new();
+    for (name, result) in &results {
+        let status = match result {
+            Ok(()) if name == active => "built (active)".to_string(),
+            Ok(()) => "built".to_string(),
+            Err(e) => {
+                failed.push(name.clone());
+                // Build errors carry nix's log; the first line names the cause
+                let e = e.to_string();
+                format!("failed: {}", e.lines().next().unwrap_or_default())
+            }
+        };
+        println!("  {:<width$}  {}", name, status, width = width);
+    }
 
+    if !failed.is_empty() {
+        return Err(Error::ProfileSyncFailed(failed.join(", ")));
+    }
+    if let Some(hashes) = local_hashes {
+        record_local_hashes(config, hashes)?;
+    }
     success("Sync complete");
     Ok(())
 }
+
+/// Report the nixpkgs commit inputs regenerating flake.nix dropped, and
+/// warn about any input of the new flake.nix that no package uses
+fn check_nixpkgs_inputs(previous: &str, generated: &str) {
+    let declared = declared_inputs(generated);
+    let dropped: Vec<String> = declared_inputs(previous)
+        .into_keys()
+        .filter(|name| name.starts_with("nixpkgs-") && !declared.contains_key(name))
+        .collect();
+    if !dropped.is_empty() {
+        info(&format!(
+            "Dropped nixpkgs input(s) no package uses anymore: {}",
+            dropped.join(", ")
+        ));
+    }
+
+    let orphaned = orphaned_nixpkgs_inputs(generated);
+    if !orphaned.is_empty() {
+        warn(&format!(
+            "flake.nix declares nixpkgs input(s) no package uses: {}",
+            orphaned.join(", ")
+        ));
+    }
+}
+
+/// Prune flake.lock entries flake.nix no longer declares, or point at
+/// `--prune` when a frozen build would refuse them
+fn check_lock_inputs(flake_dir: &Path, prune: bool) -> Result<()> {
+    if prune {
+        let pruned = prune_lock(flake_dir)?;
+        if !pruned.is_empty() {
+            info(&format!(
+                "Pruned stale input(s) from flake.lock: {}",
+                pruned.join(", ")
+            ));
+        }
+        return Ok(());
+    }
+
+    // Unfrozen, nix drops them itself when it writes the lock
+    if Nix::frozen() {
+        let stale = stale_lock_inputs(
+            &fs::read_to_string(flake_dir.join("flake.nix"))?,
+            &fs::read_to_string(flake_dir.join("flake.lock"))?,
+        );
+        if !stale.is_empty() {
+            warn(&format!(
+                "flake.lock still locks input(s) flake.nix no longer declares: {}. Run 'nixy sync --prune' to drop them.",
+                stale.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Print how regenerating would change the flake.nix at `flake_path` into
+/// `flake`, failing with [`Error::FlakeDrift`] when it would
+fn dry_run(flake_path: &Path, flake: &str) -> Result<()> {
+    // A profile that was never synced has no flake.nix yet
+    let current = fs::read_to_string(flake_path).unwrap_or_default();
+    if current == flake {
+        report(&format!("{} is up to date", flake_path.display()));
+        report("Dry run: nothing to sync");
+        return Ok(());
+    }
+
+    print_line_diff("flake.nix", &current, flake);
+    let changes = package_changes(&current, flake);
+    if !changes.is_empty() {
+        report("Packages:");
+        for (sign, name) in &changes {
+            let line = format!("{}{}", sign, name);
+            let line = match sign {
+                '+' => line.green(),
+                '-' => line.red(),
+                _ => line.yellow(),
+            };
+            println!("  {}", line);
+        }
+    }
+    report("Dry run: no changes written");
+    // Changes beside the packages (inputs, settings) still count once
+    Err(Error::FlakeDrift(changes.len().max(1)))
+}
+
+/// The packages `new` adds ('+'), drops ('-') or builds from another
+/// expression ('~') compared with `old`, by name
+fn package_changes(old: &str, new: &str) -> Vec<(char, String)> {
+    let bindings = |content: &str| {
+        parse_generated_packages(content)
+            .map(|generated| generated.bindings)
+            .unwrap_or_default()
+    };
+    let (old, new): (BTreeMap<_, _>, BTreeMap<_, _>) = (bindings(old), bindings(new));
+    let mut changes: Vec<(char, String)> = old
+        .iter()
+        .filter_map(|(name, expr)| match new.get(name) {
+            None => Some(('-', name.clone())),
+            Some(new_expr) if new_expr != expr => Some(('~', name.clone())),
+            Some(_) => None,
+        })
+        .chain(
+            new.keys()
+                .filter(|name| !old.contains_key(*name))
+                .map(|name| ('+', name.clone())),
+        )
+        .collect();
+    changes.sort_by(|a, b| a.1.cmp(&b.1));
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flake(bindings: &str, paths: &str) -> String {
+        format!(
+            "{{ outputs = _: {{ packages = {{ {} default = pkgs.buildEnv {{ paths = [ {} ]; }}; }}; }}; }}",
+            bindings, paths
+        )
+    }
+
+    #[test]
+    fn test_package_changes() {
+        let old = flake(
+            "fd = pkgs.fd; jq = pkgs.jq; inherit (inputs.nixpkgs-aaaaaaaa.legacyPackages.${system}) hello;",
+            "fd jq hello",
+        );
+        let new = flake(
+            "fd = pkgs.fd; rg = pkgs.ripgrep; inherit (inputs.nixpkgs-bbbbbbbb.legacyPackages.${system}) hello;",
+            "fd rg hello",
+        );
+        assert_eq!(
+            package_changes(&old, &new),
+            vec![
+                ('~', "hello".to_string()),
+                ('-', "jq".to_string()),
+                ('+', "rg".to_string()),
+            ]
+        );
+        assert!(package_changes(&old, &old).is_empty());
+        // No flake.nix yet: everything is new
+        assert_eq!(package_changes("", &new).len(), 3);
+    }
+}
@@ -0,0 +1,72 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::cli::UndoUpdateArgs;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::nix::Nix;
+use crate::nixy_config::{nixy_json_exists, NixyConfig};
+use crate::profile::{get_profile_flake_dir, Profile};
+use crate::snapshot::{Snapshot, PRE_UPDATE};
+
+use super::{explain_build_error, info, record_generation, success, time_ago, update_fonts};
+
+pub fn run(config: &Config, args: UndoUpdateArgs) -> Result<()> {
+    if Nix::frozen() {
+        return Err(Error::Frozen("nixy undo-update".to_string()));
+    }
+    if !nixy_json_exists(config) {
+        return Err(Error::Usage(
+            "Nothing to undo: updates are only recorded with nixy.json.".to_string(),
+        ));
+    }
+    let Some(snapshot) = Snapshot::latest(config, PRE_UPDATE)? else {
+        return Err(Error::Usage("No update to undo.".to_string()));
+    };
+
+    let when = time_ago(UNIX_EPOCH + Duration::from_secs(snapshot.meta.created));
+    let profiles: Vec<String> = snapshot.meta.profiles.keys().cloned().collect();
+
+    // Restoring would also throw away whatever happened after the update
+    let changed = snapshot.changed_profiles(config)?;
+    if !changed.is_empty() && !args.force {
+        return Err(Error::Usage(format!(
+            "Profile(s) {} changed after '{}' ({}), or the update did not finish. Pass --force to restore the state from before it anyway.",
+            changed.join(", "),
+            snapshot.meta.command,
+            when
+        )));
+    }
+
+    info(&format!(
+        "Restoring {} as before '{}' ({})...",
+        profiles.join(", "),
+        snapshot.meta.command,
+        when
+    ));
+    snapshot.restore(config)?;
+    // Undoing twice should reach the update before this one, not redo it
+    snapshot.remove()?;
+
+    let active = NixyConfig::load(config)?.active_profile;
+    for name in profiles.iter().filter(|n| **n != active) {
+        Profile::new(name, config).mark_needs_sync("nixy undo-update")?;
+    }
+    if !profiles.contains(&active) {
+        success("Restored. The profile(s) will be rebuilt when you switch to them.");
+        return Ok(());
+    }
+
+    // Build the restored files as they are; a sync would regenerate them
+    info("Rebuilding environment...");
+    let profile = Profile::new(&active, config);
+    if let Err(e) = profile.build_env(&get_profile_flake_dir(config, &active)?, &config.env_link) {
+        profile.mark_needs_sync("build failed on undo-update")?;
+        return Err(explain_build_error(config, &active, e));
+    }
+    profile.clear_needs_sync();
+    record_generation(config, &active);
+    update_fonts(config, &active);
+
+    success("Update undone");
+    Ok(())
+}
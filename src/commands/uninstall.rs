@@ -1,4 +1,7 @@
+use std::collections::HashSet;
 use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
 use std::process::Command;
 
 use crate::cli::UninstallArgs;
@@ -10,14 +13,44 @@ use crate::profile::get_flake_dir;
 use crate::rollback::{self, RollbackContext};
 use crate::state::{get_state_path, PackageState};
 
-use super::{info, warn};
+use super::completions::installed_package_names;
+use super::{assume_yes, auto_commit, confirm, info, is_git_repo, preview_changes, report, warn};
 
 pub fn run(config: &Config, args: UninstallArgs) -> Result<()> {
-    let package = &args.package;
+    let packages = if args.packages.is_empty() {
+        let selected = pick_packages(config)?;
+        if selected.is_empty() {
+            info("Nothing selected.");
+            return Ok(());
+        }
+        selected
+    } else {
+        let mut seen = HashSet::new();
+        args.packages
+            .into_iter()
+            .filter(|p| seen.insert(p.clone()))
+            .collect()
+    };
 
     // Use NixyConfig if available (new format)
     if nixy_json_exists(config) {
-        return uninstall_with_nixy_config(config, package);
+        let packages = uninstall_with_nixy_config(config, &packages, args.purge, args.dry_run)?;
+        if args.dry_run {
+            return Ok(());
+        }
+        let default_message = format!("nixy: uninstall {}", packages.join(", "));
+        // --git-commit asks for a commit with the generated message
+        let commit_message = args
+            .commit_message
+            .or_else(|| args.git_commit.then(|| default_message.clone()));
+        auto_commit(config, commit_message.as_deref(), &default_message);
+        return Ok(());
+    }
+
+    if args.dry_run {
+        return Err(Error::Usage(
+            "--dry-run requires nixy.json. Run 'nixy sync' to migrate first.".to_string(),
+        ));
     }
 
     // Legacy format
@@ -37,46 +70,51 @@ pub fn run(config: &Config, args: UninstallArgs) -> Result<()> {
     let original_state = state.clone();
     let original_flake = fs::read_to_string(&flake_path)?;
 
-    info(&format!("Uninstalling {}...", package));
-
-    // Remove local package file or flake directory if exists
     let pkg_dir = flake_dir.join("packages");
-    let local_pkg_file = pkg_dir.join(format!("{}.nix", package));
-    let local_flake_dir = pkg_dir.join(package);
-
-    let mut removed_local = false;
-    if local_pkg_file.exists() {
-        info(&format!(
-            "Removing local package definition: {}",
-            local_pkg_file.display()
-        ));
-        fs::remove_file(&local_pkg_file)?;
-        git_rm(&flake_dir, &format!("packages/{}.nix", package));
-        removed_local = true;
-    } else if local_flake_dir.exists() && local_flake_dir.join("flake.nix").exists() {
-        info(&format!(
-            "Removing local flake: {}",
-            local_flake_dir.display()
-        ));
-        fs::remove_dir_all(&local_flake_dir)?;
-        git_rm_recursive(&flake_dir, &format!("packages/{}", package));
-        removed_local = true;
-    }
+    let local_pkg_file = |package: &str| pkg_dir.join(format!("{}.nix", package));
+    let local_flake_dir = |package: &str| pkg_dir.join(package);
+
+    // Check every package before touching anything
+    let packages = installed_only(&packages, |package| {
+        local_pkg_file(package).exists()
+            || local_flake_dir(package).join("flake.nix").exists()
+            || state.has_package(package)
+    })?;
+
+    info(&format!("Uninstalling {}...", packages.join(", ")));
+
+    for package in &packages {
+        // Remove local package file or flake directory if exists
+        let local_pkg_file = local_pkg_file(package);
+        let local_flake_dir = local_flake_dir(package);
+        if local_pkg_file.exists() {
+            info(&format!(
+                "Removing local package definition: {}",
+                local_pkg_file.display()
+            ));
+            fs::remove_file(&local_pkg_file)?;
+            git_rm(&flake_dir, &format!("packages/{}.nix", package));
+        } else if local_flake_dir.exists() && local_flake_dir.join("flake.nix").exists() {
+            info(&format!(
+                "Removing local flake: {}",
+                local_flake_dir.display()
+            ));
+            fs::remove_dir_all(&local_flake_dir)?;
+            git_rm_recursive(&flake_dir, &format!("packages/{}", package));
+        }
 
-    // Remove package from state (local packages are auto-discovered, not in state)
-    let removed_from_state = state.remove_package(package);
-    if !removed_local && !removed_from_state {
-        return Err(Error::PackageNotFound(package.to_string()));
+        // Remove package from state (local packages are auto-discovered, not in state)
+        state.remove_package(package);
     }
     state.save(&state_path)?;
 
     // Regenerate flake.nix
     let content = generate_flake(&state, Some(&flake_dir));
     fs::write(&flake_path, content)?;
-    super::success(&format!("Removed {} from flake.nix", package));
+    super::success(&format!("Removed {} from flake.nix", packages.join(", ")));
 
     info("Rebuilding environment...");
-    if let Err(e) = super::sync::run(config) {
+    if let Err(e) = super::sync::sync(config, Default::default()) {
         // Sync failed, revert state and flake (note: local file deletions cannot be undone)
         original_state.save(&state_path)?;
         fs::write(&flake_path, original_flake)?;
@@ -87,15 +125,45 @@ pub fn run(config: &Config, args: UninstallArgs) -> Result<()> {
     Ok(())
 }
 
-/// Uninstall a package using the new nixy.json format
-fn uninstall_with_nixy_config(config: &Config, package: &str) -> Result<()> {
+/// Keep the packages `is_installed` knows, warning about the others
+///
+/// Fails only when none of them is installed.
+fn installed_only(packages: &[String], is_installed: impl Fn(&str) -> bool) -> Result<Vec<String>> {
+    let (present, missing): (Vec<String>, Vec<String>) =
+        packages.iter().cloned().partition(|p| is_installed(p));
+    match (present.is_empty(), missing.as_slice()) {
+        (true, [package]) => Err(Error::PackageNotFound(package.clone())),
+        (true, _) => Err(Error::Usage(format!(
+            "None of the packages is installed: {}",
+            missing.join(", ")
+        ))),
+        (false, _) => {
+            for package in &missing {
+                warn(&format!("Package '{}' is not installed; skipping", package));
+            }
+            Ok(present)
+        }
+    }
+}
+
+/// Uninstall packages using the new nixy.json format, rebuilding once
+///
+/// Returns the packages that were actually uninstalled. With `dry_run`, only
+/// shows the changes to nixy.json and flake.nix, and which local definitions
+/// would be deleted (`--purge` stands in for the prompt).
+fn uninstall_with_nixy_config(
+    config: &Config,
+    packages: &[String],
+    purge: bool,
+    dry_run: bool,
+) -> Result<Vec<String>> {
     let mut nixy_config = NixyConfig::load(config)?;
     let active_profile = nixy_config.active_profile.clone();
     let flake_dir = get_flake_dir(config)?;
     let flake_path = flake_dir.join("flake.nix");
 
     // Auto-regenerate flake.nix if missing
-    if !flake_path.exists() {
+    if !flake_path.exists() && !dry_run {
         if let Some(profile) = nixy_config.get_active_profile() {
             info("Regenerating flake.nix from nixy.json...");
             let global_packages_dir = if config.global_packages_dir.exists() {
@@ -103,7 +171,12 @@ fn uninstall_with_nixy_config(config: &Config, package: &str) -> Result<()> {
             } else {
                 None
             };
-            regenerate_flake_from_profile(&flake_dir, profile, global_packages_dir)?;
+            regenerate_flake_from_profile(
+                &flake_dir,
+                profile,
+                global_packages_dir,
+                nixy_config.format,
+            )?;
         }
     }
 
@@ -115,54 +188,114 @@ fn uninstall_with_nixy_config(config: &Config, package: &str) -> Result<()> {
         None
     };
 
-    info(&format!("Uninstalling {}...", package));
-
-    // Check for a local package definition in the shared packages/ directory.
-    // Local packages are auto-discovered into every profile's flake, so they may
-    // not appear in any profile's package list at all.
-    let global_pkg_file = config.global_packages_dir.join(format!("{}.nix", package));
-    let global_flake_dir = config.global_packages_dir.join(package);
-    let local_def_exists = global_pkg_file.exists()
-        || (global_flake_dir.exists() && global_flake_dir.join("flake.nix").exists());
-
-    // Remove package from profile
+    // Check every package before touching anything
     let profile = nixy_config
-        .get_active_profile_mut()
+        .get_active_profile()
         .ok_or_else(|| Error::ProfileNotFound(active_profile.clone()))?;
-    let removed_from_config = profile.remove_package(package);
-
-    if removed_from_config {
-        // Package was listed in the profile. We do NOT delete the global package
-        // definition from packages/ because it is shared across all profiles.
-        if local_def_exists {
-            warn(
-                "Note: Local package definition in packages/ was not removed (shared across profiles)",
-            );
-        }
-    } else if local_def_exists {
-        // Package exists only as an auto-discovered local definition. The only way
-        // to remove it is to delete the definition itself.
-        warn("Removing local package definition from packages/ (shared across profiles)");
-        if global_pkg_file.exists() {
+    let packages = installed_only(packages, |p| {
+        profile.has_package(p)
+            || local_definition_exists(config, p)
+            || nixy_config.tracks_local_definition(p)
+    })?;
+
+    info(&format!("Uninstalling {}...", packages.join(", ")));
+
+    // Local definitions to delete once every package has been checked
+    let mut definitions_to_remove: Vec<&str> = Vec::new();
+
+    for package in &packages {
+        // Check for a local package definition in the shared packages/ directory.
+        // Local packages are auto-discovered into every profile's flake, so they may
+        // not appear in any profile's package list at all.
+        let local_def_exists = local_definition_exists(config, package);
+
+        // Other profiles that still list this package and would lose it if the shared
+        // definition were deleted.
+        let other_refs = nixy_config.profiles_referencing(package, &active_profile);
+
+        // Remove package from profile
+        let profile = nixy_config
+            .get_active_profile_mut()
+            .ok_or_else(|| Error::ProfileNotFound(active_profile.clone()))?;
+        let removed_from_config = profile.remove_package(package);
+
+        if removed_from_config {
+            // Package was listed in the profile. The global package definition is
+            // shared across all profiles, so only offer to delete it once nothing
+            // else references it.
+            if local_def_exists {
+                if !other_refs.is_empty() {
+                    info(&format!(
+                        "Keeping local package definition for {} in packages/ (still used by: {})",
+                        package,
+                        other_refs.join(", ")
+                    ));
+                } else if purge || (!dry_run && confirm_remove_local(package)?) {
+                    definitions_to_remove.push(package);
+                } else {
+                    warn(&format!(
+                        "Note: Local package definition for {} in packages/ was not removed (use --purge to delete it)",
+                        package
+                    ));
+                }
+            }
+        } else if !local_def_exists {
+            // Its definition was deleted by hand; only its recorded hash is left
             info(&format!(
-                "Removing local package definition: {}",
-                global_pkg_file.display()
+                "The local package definition for {} is already gone",
+                package
             ));
-            fs::remove_file(&global_pkg_file)?;
-            git_rm(&config.global_packages_dir, &format!("{}.nix", package));
+            nixy_config.forget_local_definition(package);
         } else {
-            info(&format!(
-                "Removing local flake: {}",
-                global_flake_dir.display()
+            // Package exists only as an auto-discovered local definition. The only way
+            // to remove it is to delete the definition itself. Other profiles losing
+            // it takes --purge or an answer at the prompt, never just --yes.
+            if !other_refs.is_empty()
+                && !purge
+                && (dry_run || assume_yes() || !confirm_remove_shared(package, &other_refs)?)
+            {
+                return Err(Error::Usage(format!(
+                    "Local package '{}' is still used by profile(s): {}. Use --purge to delete it anyway.",
+                    package,
+                    other_refs.join(", ")
+                )));
+            }
+            if !dry_run {
+                warn(&format!(
+                    "Removing local package definition for {} from packages/ (shared across profiles)",
+                    package
+                ));
+            }
+            definitions_to_remove.push(package);
+        }
+    }
+
+    if dry_run {
+        for package in definitions_to_remove {
+            report(&format!(
+                "Would delete the local package definition for {} from packages/",
+                package
             ));
-            fs::remove_dir_all(&global_flake_dir)?;
-            git_rm_recursive(&config.global_packages_dir, package);
         }
-    } else {
-        return Err(Error::PackageNotFound(package.to_string()));
+        preview_changes(config, &original_config, &nixy_config)?;
+        return Ok(packages);
     }
 
-    nixy_config.save(config)?;
+    // Definitions are only moved aside until the rebuild succeeds, so a
+    // failed or interrupted uninstall can put them back
+    let mut set_aside = SetAside::new(config);
+    let mut removed = Ok(());
+    for package in definitions_to_remove {
+        removed = set_aside.take(config, package);
+        if removed.is_err() {
+            break;
+        }
+        nixy_config.forget_local_definition(package);
+    }
+    if let Err(e) = removed.and_then(|_| nixy_config.save(config)) {
+        set_aside.restore();
+        return Err(e);
+    }
 
     // Regenerate flake.nix
     let global_packages_dir = if config.global_packages_dir.exists() {
@@ -171,46 +304,251 @@ fn uninstall_with_nixy_config(config: &Config, package: &str) -> Result<()> {
         None
     };
     let profile_for_flake = nixy_config.get_active_profile().unwrap();
-    regenerate_flake_from_profile(&flake_dir, profile_for_flake, global_packages_dir)?;
-    super::success(&format!("Removed {} from flake.nix", package));
+    if let Err(e) = regenerate_flake_from_profile(
+        &flake_dir,
+        profile_for_flake,
+        global_packages_dir,
+        nixy_config.format,
+    ) {
+        set_aside.restore();
+        original_config.save(config)?;
+        return Err(e);
+    }
+    super::success(&format!("Removed {} from flake.nix", packages.join(", ")));
 
     // Set up rollback context for Ctrl+C handling
-    rollback::set_context(RollbackContext::nixy_config(
-        flake_dir.clone(),
-        config.nixy_json.clone(),
-        original_config.clone(),
-        global_packages_dir,
-    ));
+    rollback::set_context(
+        RollbackContext::nixy_config(
+            flake_dir.clone(),
+            config.nixy_json.clone(),
+            original_config.clone(),
+            global_packages_dir,
+        )
+        .with_set_aside(set_aside.moved.clone()),
+    );
 
     info("Rebuilding environment...");
-    if let Err(e) = super::sync::run(config) {
+    if let Err(e) = super::sync::sync(config, Default::default()) {
         // Clear rollback context since we're handling the error here
         rollback::clear_context();
         // Sync failed, revert
+        set_aside.restore();
         original_config.save(config)?;
         if let Some(original) = original_flake {
             fs::write(&flake_path, original)?;
         }
-        warn("Sync failed. Reverted nixy.json and flake.nix.");
+        if set_aside.moved.is_empty() {
+            warn("Sync failed. Reverted nixy.json and flake.nix.");
+        } else {
+            warn("Sync failed. Reverted nixy.json, flake.nix and the local package definitions.");
+        }
         return Err(e);
     }
+    set_aside.discard(config);
 
     // Clear rollback context on success
     rollback::clear_context();
 
-    Ok(())
+    Ok(packages)
+}
+
+/// Let the user choose installed packages from a numbered list.
+///
+/// Without a terminal there is nobody to ask, so a package name is required.
+fn pick_packages(config: &Config) -> Result<Vec<String>> {
+    if !io::stdin().is_terminal() {
+        return Err(Error::Usage(
+            "nixy uninstall requires a package name (interactive selection needs a terminal)"
+                .to_string(),
+        ));
+    }
+
+    let installed = installed_package_names(config);
+    if installed.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    info("Installed packages:");
+    let width = installed.len().to_string().len();
+    for (i, name) in installed.iter().enumerate() {
+        println!("  {:>width$}) {}", i + 1, name, width = width);
+    }
+
+    loop {
+        print!("Packages to uninstall (e.g. 1 3 5-7, empty to cancel): ");
+        io::stdout().flush()?;
+
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input)? == 0 {
+            return Ok(Vec::new());
+        }
+
+        match parse_selection(&input, installed.len()) {
+            Ok(indices) => {
+                return Ok(indices.into_iter().map(|i| installed[i].clone()).collect());
+            }
+            Err(msg) => warn(&msg),
+        }
+    }
+}
+
+/// Parse a selection like `1 3, 5-7` into sorted, deduplicated zero-based
+/// indices into a list of `count` items
+fn parse_selection(input: &str, count: usize) -> std::result::Result<Vec<usize>, String> {
+    let parse_number = |s: &str| -> std::result::Result<usize, String> {
+        match s.trim().parse::<usize>() {
+            Ok(n) if (1..=count).contains(&n) => Ok(n - 1),
+            _ => Err(format!(
+                "'{}' is not a number between 1 and {}",
+                s.trim(),
+                count
+            )),
+        }
+    };
+
+    let mut indices = Vec::new();
+    for part in input.split([' ', ',']).filter(|p| !p.trim().is_empty()) {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (parse_number(start)?, parse_number(end)?);
+                if start > end {
+                    return Err(format!("Invalid range '{}'", part.trim()));
+                }
+                indices.extend(start..=end);
+            }
+            None => indices.push(parse_number(part)?),
+        }
+    }
+
+    indices.sort_unstable();
+    indices.dedup();
+    Ok(indices)
+}
+
+/// Whether a local package definition (.nix file or flake directory) exists in
+/// the global packages/ directory
+fn local_definition_exists(config: &Config, package: &str) -> bool {
+    let global_flake_dir = config.global_packages_dir.join(package);
+    config
+        .global_packages_dir
+        .join(format!("{}.nix", package))
+        .exists()
+        || (global_flake_dir.exists() && global_flake_dir.join("flake.nix").exists())
+}
+
+/// Ask whether a now-unused local package definition should be deleted.
+///
+/// Keeping the definition is the safe default, so without a terminal (and
+/// without `--yes`) this returns false instead of failing the uninstall.
+fn confirm_remove_local(package: &str) -> Result<bool> {
+    if !assume_yes() && !io::stdin().is_terminal() {
+        return Ok(false);
+    }
+    confirm(&format!(
+        "No other profile uses '{}'. Remove its local package definition from packages/?",
+        package
+    ))
+}
+
+/// Ask whether a local package definition should be deleted even though
+/// `profiles` still use it
+///
+/// Without a terminal this returns false, so the uninstall stops and points
+/// at `--purge`.
+fn confirm_remove_shared(package: &str, profiles: &[String]) -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        return Ok(false);
+    }
+    confirm(&format!(
+        "'{}' is still used by profile(s): {}. Remove its local package definition from packages/ anyway?",
+        package,
+        profiles.join(", ")
+    ))
+}
+
+/// Local package definitions moved out of packages/ while an uninstall is
+/// rebuilt
+///
+/// They sit in a scratch directory next to packages/ (same filesystem, so
+/// moving them is a rename) until `discard` deletes them for good or
+/// `restore` puts them back.
+struct SetAside {
+    dir: PathBuf,
+    /// (original path, path in `dir`) of every definition moved so far
+    moved: Vec<(PathBuf, PathBuf)>,
+}
+
+impl SetAside {
+    fn new(config: &Config) -> Self {
+        Self {
+            dir: config.config_dir.join(".uninstall-backup"),
+            moved: Vec::new(),
+        }
+    }
+
+    /// Move the .nix file or flake directory of `package` aside
+    fn take(&mut self, config: &Config, package: &str) -> Result<()> {
+        let global_pkg_file = config.global_packages_dir.join(format!("{}.nix", package));
+        let original = if global_pkg_file.exists() {
+            info(&format!(
+                "Removing local package definition: {}",
+                global_pkg_file.display()
+            ));
+            global_pkg_file
+        } else {
+            let global_flake_dir = config.global_packages_dir.join(package);
+            info(&format!(
+                "Removing local flake: {}",
+                global_flake_dir.display()
+            ));
+            global_flake_dir
+        };
+        let aside = self.dir.join(original.file_name().unwrap());
+        // Left over from an uninstall that was killed outright
+        if aside.is_dir() {
+            fs::remove_dir_all(&aside)?;
+        } else if aside.exists() {
+            fs::remove_file(&aside)?;
+        }
+        fs::create_dir_all(&self.dir)?;
+        fs::rename(&original, &aside)?;
+        self.moved.push((original, aside));
+        Ok(())
+    }
+
+    /// Put every definition back where it was
+    fn restore(&self) {
+        for (original, aside) in &self.moved {
+            if let Err(e) = fs::rename(aside, original) {
+                warn(&format!(
+                    "Could not restore {} (it is in {}): {}",
+                    original.display(),
+                    aside.display(),
+                    e
+                ));
+                return;
+            }
+        }
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+
+    /// Delete the definitions for good and drop them from git
+    fn discard(self, config: &Config) {
+        for (original, aside) in &self.moved {
+            let name = original.file_name().unwrap().to_string_lossy();
+            if aside.is_dir() {
+                git_rm_recursive(&config.global_packages_dir, &name);
+            } else {
+                git_rm(&config.global_packages_dir, &name);
+            }
+        }
+        let _ = fs::remove_dir_all(&self.dir);
+    }
 }
 
 /// Remove a file from git index
 fn git_rm(dir: &std::path::Path, file: &str) {
-    let is_git_repo = dir.join(".git").exists()
-        || Command::new("git")
-            .args(["-C", &dir.to_string_lossy(), "rev-parse", "--git-dir"])
-            .output()
-            .map(|o| o.status.success())
-            .unwrap_or(false);
-
-    if is_git_repo {
+    if is_git_repo(dir) {
         let _ = Command::new("git")
             .args(["-C", &dir.to_string_lossy(), "rm", "--cached", file])
             .output();
@@ -219,14 +557,7 @@ fn git_rm(dir: &std::path::Path, file: &str) {
 
 /// Remove a directory from git index recursively
 fn git_rm_recursive(dir: &std::path::Path, path: &str) {
-    let is_git_repo = dir.join(".git").exists()
-        || Command::new("git")
-            .args(["-C", &dir.to_string_lossy(), "rev-parse", "--git-dir"])
-            .output()
-            .map(|o| o.status.success())
-            .unwrap_or(false);
-
-    if is_git_repo {
+    if is_git_repo(dir) {
         let _ = Command::new("git")
             .args(["-C", &dir.to_string_lossy(), "rm", "-r", "--cached", path])
             .output();
@@ -264,4 +595,20 @@ mod tests {
         assert!(!final_state.has_package("hello"));
         assert!(final_state.has_package("world"));
     }
+
+    #[test]
+    fn test_parse_selection() {
+        assert_eq!(parse_selection("1 3", 5).unwrap(), vec![0, 2]);
+        assert_eq!(parse_selection("2-4, 1", 5).unwrap(), vec![0, 1, 2, 3]);
+        assert_eq!(parse_selection(" 3 3 2-3\n", 5).unwrap(), vec![1, 2]);
+        assert!(parse_selection("", 5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_selection_rejects_out_of_range() {
+        assert!(parse_selection("0", 5).is_err());
+        assert!(parse_selection("6", 5).is_err());
+        assert!(parse_selection("abc", 5).is_err());
+        assert!(parse_selection("4-2", 5).is_err());
+    }
 }
@@ -1,20 +1,58 @@
-use std::fs;
+use std::path::Path;
 
 use crate::cli::UpdateArgs;
+use crate::commits::{is_commit_hash, is_pinned_to};
 use crate::config::Config;
 use crate::error::{Error, Result};
+use crate::flake::lock::locked_revs;
 use crate::flake::template::{regenerate_flake, regenerate_flake_from_profile};
 use crate::nix::Nix;
 use crate::nixhub::NixhubClient;
 use crate::nixy_config::{nixy_json_exists, NixyConfig, ProfileConfig};
-use crate::profile::get_flake_dir;
+use crate::profile::{
+    copy_dir_recursive, get_active_profile, get_flake_dir, get_profile_flake_dir, Profile,
+};
 use crate::rollback::{self, RollbackContext};
-use crate::state::{get_state_path, CustomPackage, PackageState, ResolvedNixpkgPackage};
+use crate::snapshot::{Snapshot, PRE_UPDATE};
+use crate::state::{
+    get_state_path, CustomPackage, InstallRecord, PackageState, ResolvedNixpkgPackage,
+};
 
-use super::{info, success, warn};
+use super::{
+    command_line, error, explain_build_error, info, nixhub_client, record_generation, report,
+    success, update_fonts, warn,
+};
 
 pub fn run(config: &Config, args: UpdateArgs) -> Result<()> {
+    if Nix::frozen() {
+        return Err(Error::Frozen("nixy update".to_string()));
+    }
+    if Nix::offline() {
+        return Err(Error::Offline("nixy update".to_string()));
+    }
     let inputs = args.inputs;
+    let client = nixhub_client(config, args.refresh);
+
+    if let Some(commit) = &args.commit {
+        if !nixy_json_exists(config) {
+            return Err(Error::Usage(
+                "--commit requires nixy.json. Run 'nixy sync' to migrate first.".to_string(),
+            ));
+        }
+        let profile_name = match args.profile {
+            Some(name) => name,
+            None => NixyConfig::load(config)?.active_profile,
+        };
+        return with_snapshot(config, std::slice::from_ref(&profile_name), || {
+            update_commit(
+                config,
+                &profile_name,
+                commit,
+                args.pin_missing_to.as_deref(),
+                &client,
+            )
+        });
+    }
 
     // Require either specific targets or --all to update everything
     if inputs.is_empty() && !args.all {
@@ -24,9 +62,42 @@ pub fn run(config: &Config, args: UpdateArgs) -> Result<()> {
         ));
     }
 
+    if args.dry_run {
+        return dry_run(config, args.profile, &inputs, args.include_pinned, &client);
+    }
+
     // Use NixyConfig if available (new format)
     if nixy_json_exists(config) {
-        return upgrade_with_nixy_config(config, inputs);
+        let nixy_config = NixyConfig::load(config)?;
+        if args.all_profiles {
+            return with_snapshot(config, &nixy_config.list_profiles(), || {
+                update_all_profiles(config, &inputs, args.include_pinned, &client)
+            });
+        }
+
+        let profile_name = args.profile.unwrap_or(nixy_config.active_profile);
+        if !nixy_config.profiles.contains_key(&profile_name) {
+            return Err(Error::ProfileNotFound(profile_name));
+        }
+        return with_snapshot(config, std::slice::from_ref(&profile_name), || {
+            if args.unpin {
+                let mut nixy_config = NixyConfig::load(config)?;
+                if let Some(profile) = nixy_config.profiles.get_mut(&profile_name) {
+                    if unpin(&mut profile.resolved_packages, &inputs) {
+                        nixy_config.save(config)?;
+                    }
+                }
+            }
+            upgrade_with_nixy_config(config, &profile_name, &inputs, args.include_pinned, &client)
+                .map(|_| ())
+        });
+    }
+
+    if args.profile.is_some() || args.all_profiles {
+        return Err(Error::Usage(
+            "--profile and --all-profiles require nixy.json. Run 'nixy sync' to migrate first."
+                .to_string(),
+        ));
     }
 
     // Legacy format
@@ -37,6 +108,9 @@ pub fn run(config: &Config, args: UpdateArgs) -> Result<()> {
 
     // Load state
     let mut state = PackageState::load(&state_path)?;
+    if args.unpin && unpin(&mut state.resolved_packages, &inputs) {
+        state.save(&state_path)?;
+    }
 
     // Auto-regenerate flake.nix if missing
     if !flake_path.exists() {
@@ -58,7 +132,12 @@ pub fn run(config: &Config, args: UpdateArgs) -> Result<()> {
 
         // Upgrade resolved packages
         if !packages_to_upgrade.is_empty() {
-            upgrade_resolved_packages(&mut state, &packages_to_upgrade)?;
+            upgrade_resolved_packages(
+                &mut state,
+                &packages_to_upgrade,
+                args.include_pinned,
+                &client,
+            )?;
             state.save(&state_path)?;
             regenerate_flake(&flake_dir, &state)?;
         }
@@ -90,11 +169,14 @@ pub fn run(config: &Config, args: UpdateArgs) -> Result<()> {
                 ));
             }
 
-            info(&format!(
-                "Updating inputs: {}...",
-                classified.inputs_to_update.join(", ")
-            ));
-            Nix::flake_update(&flake_dir, &classified.inputs_to_update)?;
+            skip_at_rev(&classified.at_rev);
+            if !classified.inputs_to_update.is_empty() {
+                info(&format!(
+                    "Updating inputs: {}...",
+                    classified.inputs_to_update.join(", ")
+                ));
+                Nix::flake_update(&flake_dir, &classified.inputs_to_update)?;
+            }
         }
     } else {
         // --all: upgrade all resolved packages
@@ -105,7 +187,7 @@ pub fn run(config: &Config, args: UpdateArgs) -> Result<()> {
                 .map(|p| p.name.clone())
                 .collect();
             let all_refs: Vec<&String> = all_names.iter().collect();
-            upgrade_resolved_packages(&mut state, &all_refs)?;
+            upgrade_resolved_packages(&mut state, &all_refs, args.include_pinned, &client)?;
             state.save(&state_path)?;
             regenerate_flake(&flake_dir, &state)?;
         }
@@ -117,12 +199,7 @@ pub fn run(config: &Config, args: UpdateArgs) -> Result<()> {
 
     info("Rebuilding environment...");
 
-    // Ensure parent directory exists
-    if let Some(parent) = config.env_link.parent() {
-        fs::create_dir_all(parent)?;
-    }
-
-    Nix::build(&flake_dir, "default", &config.env_link)?;
+    Profile::new(&get_active_profile(config), config).build_env(&flake_dir, &config.env_link)?;
 
     if !inputs.is_empty() {
         success(&format!("Updated: {}", inputs.join(", ")));
@@ -133,11 +210,290 @@ pub fn run(config: &Config, args: UpdateArgs) -> Result<()> {
     Ok(())
 }
 
-/// Upgrade packages using the new nixy.json format
-fn upgrade_with_nixy_config(config: &Config, inputs: Vec<String>) -> Result<()> {
+/// Run an update of `profiles` after saving them to a pre-update snapshot,
+/// so `nixy undo-update` can put them back in one step
+///
+/// The snapshot is kept (and sealed) even when the update fails part-way:
+/// by then it may already have changed flake.lock.
+fn with_snapshot(
+    config: &Config,
+    profiles: &[String],
+    update: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    let nixy_config = NixyConfig::load(config)?;
+    let mut snapshot = Snapshot::take(config, &nixy_config, profiles, PRE_UPDATE, &command_line())?;
+
+    let result = update();
+    if let Err(e) = snapshot.seal(config) {
+        warn(&format!("Could not finish the pre-update snapshot: {}", e));
+    }
+    result
+}
+
+/// Show what `nixy update` would change in one profile, without changing it
+///
+/// Packages are re-resolved on a copy of the profile and `nix flake update`
+/// runs on a scratch copy of its flake directory; both are thrown away.
+fn dry_run(
+    config: &Config,
+    profile_name: Option<String>,
+    inputs: &[String],
+    include_pinned: bool,
+    client: &NixhubClient,
+) -> Result<()> {
+    if !nixy_json_exists(config) {
+        return Err(Error::Usage(
+            "--dry-run requires nixy.json. Run 'nixy sync' to migrate first.".to_string(),
+        ));
+    }
+    let nixy_config = NixyConfig::load(config)?;
+    let profile_name = profile_name.unwrap_or_else(|| nixy_config.active_profile.clone());
+    let mut profile = nixy_config
+        .profiles
+        .get(&profile_name)
+        .cloned()
+        .ok_or_else(|| Error::ProfileNotFound(profile_name.clone()))?;
+    let flake_dir = get_profile_flake_dir(config, &profile_name)?;
+    let lock_file = flake_dir.join("flake.lock");
+
+    let (packages, flake_inputs): (Vec<&String>, Vec<&String>) = if inputs.is_empty() {
+        (
+            profile.resolved_packages.iter().map(|p| &p.name).collect(),
+            Vec::new(),
+        )
+    } else {
+        inputs
+            .iter()
+            .partition(|input| profile.resolved_packages.iter().any(|p| &p.name == *input))
+    };
+    let packages: Vec<String> = packages.into_iter().cloned().collect();
+
+    let flake_inputs = if flake_inputs.is_empty() {
+        Vec::new()
+    } else {
+        if !lock_file.exists() {
+            return Err(Error::NoFlakeLock);
+        }
+        let available = Nix::get_flake_inputs(&lock_file)?;
+        let classified = classify_update_targets(
+            &flake_inputs,
+            &available,
+            &profile.packages,
+            &profile.custom_packages,
+        );
+        if !classified.legacy.is_empty() {
+            warn_legacy_packages(&classified.legacy);
+            return Ok(());
+        }
+        if !classified.invalid.is_empty() {
+            return Err(Error::InvalidFlakeInputs(
+                classified.invalid.join(", "),
+                available.join(" "),
+            ));
+        }
+        classified.inputs_to_update
+    };
+
+    let original = profile.resolved_packages.clone();
+    if !packages.is_empty() {
+        upgrade_resolved_packages_in_profile(
+            &mut profile,
+            &packages.iter().collect::<Vec<_>>(),
+            include_pinned,
+            client,
+        )?;
+    }
+    let packages_changed = profile.resolved_packages != original;
+
+    // Without --all, only named flake inputs touch the lock
+    if inputs.is_empty() || !flake_inputs.is_empty() {
+        let scratch = std::env::temp_dir().join(format!("nixy-dry-run-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&scratch);
+        let result = preview_lock_changes(
+            config,
+            &nixy_config,
+            &profile,
+            &flake_dir,
+            &scratch,
+            packages_changed,
+            &flake_inputs,
+        );
+        let _ = std::fs::remove_dir_all(&scratch);
+        result?;
+    }
+
+    report("Dry run: nothing was changed");
+    Ok(())
+}
+
+/// Update a scratch copy of `flake_dir` in `scratch` and print how each
+/// locked input moves
+fn preview_lock_changes(
+    config: &Config,
+    nixy_config: &NixyConfig,
+    profile: &ProfileConfig,
+    flake_dir: &Path,
+    scratch: &Path,
+    packages_changed: bool,
+    flake_inputs: &[String],
+) -> Result<()> {
+    if flake_dir.exists() {
+        copy_dir_recursive(flake_dir, scratch)?;
+    } else {
+        std::fs::create_dir_all(scratch)?;
+    }
+    if packages_changed || !scratch.join("flake.nix").exists() {
+        let global_packages_dir = if config.global_packages_dir.exists() {
+            Some(config.global_packages_dir.as_path())
+        } else {
+            None
+        };
+        regenerate_flake_from_profile(scratch, profile, global_packages_dir, nixy_config.format)?;
+    }
+
+    if flake_inputs.is_empty() {
+        info("Checking all flake inputs...");
+        Nix::flake_update_all(scratch)?;
+    } else {
+        info(&format!("Checking inputs: {}...", flake_inputs.join(", ")));
+        Nix::flake_update(scratch, flake_inputs)?;
+    }
+
+    let read_revs = |dir: &Path| {
+        std::fs::read_to_string(dir.join("flake.lock"))
+            .ok()
+            .and_then(|content| locked_revs(&content))
+            .unwrap_or_default()
+    };
+    let before = read_revs(flake_dir);
+    let after = read_revs(scratch);
+
+    let short = |rev: &str| -> String {
+        if is_commit_hash(rev) {
+            rev.chars().take(8).collect()
+        } else {
+            rev.to_string()
+        }
+    };
+    let mut changes = Vec::new();
+    for (name, rev) in &after {
+        match before.get(name) {
+            Some(old) if old == rev => {}
+            Some(old) => changes.push(format!("  {}: {} -> {}", name, short(old), short(rev))),
+            None => changes.push(format!("  {}: added ({})", name, short(rev))),
+        }
+    }
+    for name in before.keys().filter(|name| !after.contains_key(*name)) {
+        changes.push(format!("  {}: removed", name));
+    }
+
+    if changes.is_empty() {
+        info("No lock changes");
+    } else {
+        info("Lock changes:");
+        for change in changes {
+            println!("{}", change);
+        }
+    }
+    Ok(())
+}
+
+/// What `nixy update` did to a single profile
+enum UpdateOutcome {
+    /// Updated and the environment was rebuilt (active profile)
+    Rebuilt,
+    /// Updated but not built (inactive profile), marked as needing a sync
+    NeedsSync,
+    /// Nothing was updated
+    Skipped,
+    /// The named packages already are at their newest matching version
+    UpToDate,
+}
+
+/// Update every profile, continuing past failures
+fn update_all_profiles(
+    config: &Config,
+    inputs: &[String],
+    include_pinned: bool,
+    client: &NixhubClient,
+) -> Result<()> {
+    let nixy_config = NixyConfig::load(config)?;
+    let mut results: Vec<(String, Result<UpdateOutcome>)> = Vec::new();
+
+    for name in nixy_config.list_profiles() {
+        // With explicit targets, only touch profiles that contain them
+        let targets = if inputs.is_empty() {
+            Vec::new()
+        } else {
+            let lock_file = Profile::new(&name, config).state_dir.join("flake.lock");
+            targets_in_profile(&nixy_config.profiles[&name], &lock_file, inputs)
+        };
+        if !inputs.is_empty() && targets.is_empty() {
+            results.push((name, Ok(UpdateOutcome::Skipped)));
+            continue;
+        }
+
+        info(&format!("Updating profile '{}'...", name));
+        let result = upgrade_with_nixy_config(config, &name, &targets, include_pinned, client);
+        if let Err(e) = &result {
+            error(&format!("Profile '{}': {}", name, e));
+        }
+        results.push((name, result));
+    }
+
+    info("Summary:");
+    let width = results.iter().map(|(n, _)| n.len()).max().unwrap_or(0);
+    let mut failed = Vec::new();
+    for (name, result) in &results {
+        let status = match result {
+            Ok(UpdateOutcome::Rebuilt) => "updated".to_string(),
+            Ok(UpdateOutcome::NeedsSync) => "updated (rebuilt on next switch or sync)".to_string(),
+            Ok(UpdateOutcome::Skipped) => "skipped".to_string(),
+            Ok(UpdateOutcome::UpToDate) => "already up to date".to_string(),
+            Err(e) => {
+                failed.push(name.clone());
+                // Build errors carry nix's log; the first line names the cause
+                let e = e.to_string();
+                format!("failed: {}", e.lines().next().unwrap_or_default())
+            }
+        };
+        println!("  {:<width$}  {}", name, status, width = width);
+    }
+
+    if !failed.is_empty() {
+        return Err(Error::ProfileUpdateFailed(failed.join(", ")));
+    }
+    Ok(())
+}
+
+/// Targets that name a package or locked flake input of the given profile
+fn targets_in_profile(profile: &ProfileConfig, lock_file: &Path, inputs: &[String]) -> Vec<String> {
+    let lock_inputs = if lock_file.exists() {
+        Nix::get_flake_inputs(lock_file).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+
+    inputs
+        .iter()
+        .filter(|t| profile.has_package(t) || lock_inputs.contains(t))
+        .cloned()
+        .collect()
+}
+
+/// Upgrade packages of one profile using the new nixy.json format
+///
+/// Only the active profile is rebuilt; other profiles get their flake
+/// regenerated and are marked as needing a sync.
+fn upgrade_with_nixy_config(
+    config: &Config,
+    profile_name: &str,
+    inputs: &[String],
+    include_pinned: bool,
+    client: &NixhubClient,
+) -> Result<UpdateOutcome> {
     let mut nixy_config = NixyConfig::load(config)?;
-    let active_profile = nixy_config.active_profile.clone();
-    let flake_dir = get_flake_dir(config)?;
+    let flake_dir = get_profile_flake_dir(config, profile_name)?;
     let flake_path = flake_dir.join("flake.nix");
     let lock_file = flake_dir.join("flake.lock");
 
@@ -146,14 +502,19 @@ fn upgrade_with_nixy_config(config: &Config, inputs: Vec<String>) -> Result<()>
 
     // Auto-regenerate flake.nix if missing
     if !flake_path.exists() {
-        if let Some(profile) = nixy_config.get_active_profile() {
+        if let Some(profile) = nixy_config.profiles.get(profile_name) {
             info("Regenerating flake.nix from nixy.json...");
             let global_packages_dir = if config.global_packages_dir.exists() {
                 Some(config.global_packages_dir.as_path())
             } else {
                 None
             };
-            regenerate_flake_from_profile(&flake_dir, profile, global_packages_dir)?;
+            regenerate_flake_from_profile(
+                &flake_dir,
+                profile,
+                global_packages_dir,
+                nixy_config.format,
+            )?;
         }
     }
 
@@ -170,8 +531,9 @@ fn upgrade_with_nixy_config(config: &Config, inputs: Vec<String>) -> Result<()>
         // Get resolved package names (scope the borrow)
         let resolved_names: Vec<String> = {
             let profile = nixy_config
-                .get_active_profile()
-                .ok_or_else(|| Error::ProfileNotFound(active_profile.clone()))?;
+                .profiles
+                .get(profile_name)
+                .ok_or_else(|| Error::ProfileNotFound(profile_name.to_string()))?;
             profile
                 .resolved_packages
                 .iter()
@@ -185,16 +547,34 @@ fn upgrade_with_nixy_config(config: &Config, inputs: Vec<String>) -> Result<()>
 
         // Upgrade resolved packages
         if !packages_to_upgrade.is_empty() {
-            {
+            let changed = {
                 let profile = nixy_config
-                    .get_active_profile_mut()
-                    .ok_or_else(|| Error::ProfileNotFound(active_profile.clone()))?;
-                upgrade_resolved_packages_in_profile(profile, &packages_to_upgrade)?;
+                    .profiles
+                    .get_mut(profile_name)
+                    .ok_or_else(|| Error::ProfileNotFound(profile_name.to_string()))?;
+                let before = profile.resolved_packages.clone();
+                upgrade_resolved_packages_in_profile(
+                    profile,
+                    &packages_to_upgrade,
+                    include_pinned,
+                    client,
+                )?;
+                profile.resolved_packages != before
+            };
+            // Nothing to rebuild when Nixhub has nothing newer
+            if !changed && flake_inputs_to_update.is_empty() {
+                success(&format!("Already up to date: {}", inputs.join(", ")));
+                return Ok(UpdateOutcome::UpToDate);
             }
             nixy_config.save(config)?;
             config_modified = true;
-            let profile_for_flake = nixy_config.get_active_profile().unwrap();
-            regenerate_flake_from_profile(&flake_dir, profile_for_flake, global_packages_dir)?;
+            let profile_for_flake = nixy_config.profiles.get(profile_name).unwrap();
+            regenerate_flake_from_profile(
+                &flake_dir,
+                profile_for_flake,
+                global_packages_dir,
+                nixy_config.format,
+            )?;
         }
 
         // Update flake inputs
@@ -206,8 +586,9 @@ fn upgrade_with_nixy_config(config: &Config, inputs: Vec<String>) -> Result<()>
             let available = Nix::get_flake_inputs(&lock_file)?;
             let classified = {
                 let profile = nixy_config
-                    .get_active_profile()
-                    .ok_or_else(|| Error::ProfileNotFound(active_profile.clone()))?;
+                    .profiles
+                    .get(profile_name)
+                    .ok_or_else(|| Error::ProfileNotFound(profile_name.to_string()))?;
                 classify_update_targets(
                     &flake_inputs_to_update,
                     &available,
@@ -218,7 +599,7 @@ fn upgrade_with_nixy_config(config: &Config, inputs: Vec<String>) -> Result<()>
 
             if !classified.legacy.is_empty() {
                 warn_legacy_packages(&classified.legacy);
-                return Ok(());
+                return Ok(UpdateOutcome::Skipped);
             }
 
             if !classified.invalid.is_empty() {
@@ -228,19 +609,23 @@ fn upgrade_with_nixy_config(config: &Config, inputs: Vec<String>) -> Result<()>
                 ));
             }
 
-            info(&format!(
-                "Updating inputs: {}...",
-                classified.inputs_to_update.join(", ")
-            ));
-            Nix::flake_update(&flake_dir, &classified.inputs_to_update)?;
+            skip_at_rev(&classified.at_rev);
+            if !classified.inputs_to_update.is_empty() {
+                info(&format!(
+                    "Updating inputs: {}...",
+                    classified.inputs_to_update.join(", ")
+                ));
+                Nix::flake_update(&flake_dir, &classified.inputs_to_update)?;
+            }
         }
     } else {
         // --all: upgrade all resolved packages
         // Get package names first (scope the borrow)
         let all_names: Vec<String> = {
             let profile = nixy_config
-                .get_active_profile()
-                .ok_or_else(|| Error::ProfileNotFound(active_profile.clone()))?;
+                .profiles
+                .get(profile_name)
+                .ok_or_else(|| Error::ProfileNotFound(profile_name.to_string()))?;
             profile
                 .resolved_packages
                 .iter()
@@ -252,22 +637,165 @@ fn upgrade_with_nixy_config(config: &Config, inputs: Vec<String>) -> Result<()>
             let all_refs: Vec<&String> = all_names.iter().collect();
             {
                 let profile = nixy_config
-                    .get_active_profile_mut()
-                    .ok_or_else(|| Error::ProfileNotFound(active_profile.clone()))?;
-                upgrade_resolved_packages_in_profile(profile, &all_refs)?;
+                    .profiles
+                    .get_mut(profile_name)
+                    .ok_or_else(|| Error::ProfileNotFound(profile_name.to_string()))?;
+                upgrade_resolved_packages_in_profile(profile, &all_refs, include_pinned, client)?;
             }
             nixy_config.save(config)?;
             config_modified = true;
-            let profile_for_flake = nixy_config.get_active_profile().unwrap();
-            regenerate_flake_from_profile(&flake_dir, profile_for_flake, global_packages_dir)?;
+            let profile_for_flake = nixy_config.profiles.get(profile_name).unwrap();
+            regenerate_flake_from_profile(
+                &flake_dir,
+                profile_for_flake,
+                global_packages_dir,
+                nixy_config.format,
+            )?;
         }
 
         info("Updating all flake inputs...");
         Nix::flake_update_all(&flake_dir)?;
     }
 
+    let outcome = rebuild_updated_profile(
+        config,
+        profile_name,
+        config_modified.then_some(&original_config),
+    )?;
+    if !matches!(outcome, UpdateOutcome::Rebuilt) {
+        return Ok(outcome);
+    }
+
+    if !inputs.is_empty() {
+        success(&format!("Updated: {}", inputs.join(", ")));
+    } else {
+        success("All packages updated");
+    }
+
+    Ok(UpdateOutcome::Rebuilt)
+}
+
+/// Move the packages pinned to `commit` onto a commit that can still be
+/// fetched: whatever Nixhub resolves them to now, or `pin_to`
+fn update_commit(
+    config: &Config,
+    profile_name: &str,
+    commit: &str,
+    pin_to: Option<&str>,
+    client: &NixhubClient,
+) -> Result<()> {
+    if let Some(rev) = pin_to {
+        if !is_commit_hash(rev) {
+            return Err(Error::Usage(format!(
+                "--pin-missing-to expects a nixpkgs commit hash, got '{}'",
+                rev
+            )));
+        }
+    }
+
+    let mut nixy_config = NixyConfig::load(config)?;
+    let original_config = nixy_config.clone();
+    let profile = nixy_config
+        .profiles
+        .get_mut(profile_name)
+        .ok_or_else(|| Error::ProfileNotFound(profile_name.to_string()))?;
+
+    let pinned: Vec<String> = profile
+        .resolved_packages
+        .iter()
+        .filter(|p| is_pinned_to(&p.commit_hash, commit))
+        .map(|p| p.name.clone())
+        .collect();
+    if pinned.is_empty() {
+        return Err(Error::Usage(format!(
+            "No package in profile '{}' is pinned to commit {}",
+            profile_name, commit
+        )));
+    }
+
+    match pin_to {
+        Some(rev) => {
+            for pkg in profile
+                .resolved_packages
+                .iter_mut()
+                .filter(|p| is_pinned_to(&p.commit_hash, commit))
+            {
+                pkg.commit_hash = rev.to_string();
+                pkg.record.mark_updated();
+            }
+            info(&format!("Pinned {} to nixpkgs {}", pinned.join(", "), rev));
+        }
+        None => {
+            let names: Vec<&String> = pinned.iter().collect();
+            upgrade_resolved_packages_in_profile(profile, &names, false, client)?;
+
+            // Nixhub may still point at the same commit (or be unreachable)
+            let stuck: Vec<&str> = profile
+                .resolved_packages
+                .iter()
+                .filter(|p| is_pinned_to(&p.commit_hash, commit))
+                .map(|p| p.name.as_str())
+                .collect();
+            if !stuck.is_empty() {
+                return Err(Error::Usage(format!(
+                    "{} still resolve(s) to commit {}. Choose a commit with 'nixy update --commit {} --pin-missing-to <rev>'.",
+                    stuck.join(", "),
+                    commit,
+                    commit
+                )));
+            }
+        }
+    }
+
+    nixy_config.save(config)?;
+    let global_packages_dir = if config.global_packages_dir.exists() {
+        Some(config.global_packages_dir.as_path())
+    } else {
+        None
+    };
+    regenerate_flake_from_profile(
+        &get_profile_flake_dir(config, profile_name)?,
+        &nixy_config.profiles[profile_name],
+        global_packages_dir,
+        nixy_config.format,
+    )?;
+
+    if let UpdateOutcome::Rebuilt =
+        rebuild_updated_profile(config, profile_name, Some(&original_config))?
+    {
+        success(&format!("Updated: {}", pinned.join(", ")));
+    }
+    Ok(())
+}
+
+/// Rebuild the environment after an update of the active profile, reverting
+/// nixy.json and flake.nix to `original_config` (if given) when the build
+/// fails. Other profiles are only marked as needing a sync.
+fn rebuild_updated_profile(
+    config: &Config,
+    profile_name: &str,
+    original_config: Option<&NixyConfig>,
+) -> Result<UpdateOutcome> {
+    let is_active = get_active_profile(config) == profile_name;
+    let flake_dir = get_profile_flake_dir(config, profile_name)?;
+    let global_packages_dir = if config.global_packages_dir.exists() {
+        Some(config.global_packages_dir.as_path())
+    } else {
+        None
+    };
+
+    // Inactive profiles are rebuilt when switched to; don't touch the env link
+    if !is_active {
+        Profile::new(profile_name, config).mark_needs_sync("nixy update")?;
+        success(&format!(
+            "Updated profile '{}'. It will be rebuilt when you switch to it.",
+            profile_name
+        ));
+        return Ok(UpdateOutcome::NeedsSync);
+    }
+
     // Set up rollback context for Ctrl+C handling if we modified the config
-    if config_modified {
+    if let Some(original_config) = original_config {
         rollback::set_context(RollbackContext::nixy_config(
             flake_dir.clone(),
             config.nixy_json.clone(),
@@ -278,19 +806,21 @@ fn upgrade_with_nixy_config(config: &Config, inputs: Vec<String>) -> Result<()>
 
     info("Rebuilding environment...");
 
-    if let Some(parent) = config.env_link.parent() {
-        fs::create_dir_all(parent)?;
-    }
-
-    if let Err(e) = Nix::build(&flake_dir, "default", &config.env_link) {
+    let profile = Profile::new(profile_name, config);
+    if let Err(e) = profile.build_env(&flake_dir, &config.env_link) {
+        let e = explain_build_error(config, profile_name, e);
         // Clear rollback context since we're handling the error here
         rollback::clear_context();
         // Build failed, revert config if we modified it
-        if config_modified {
+        if let Some(original_config) = original_config {
             original_config.save(config)?;
-            let original_profile = original_config.get_active_profile().unwrap();
-            let _ =
-                regenerate_flake_from_profile(&flake_dir, original_profile, global_packages_dir);
+            let original_profile = original_config.profiles.get(profile_name).unwrap();
+            let _ = regenerate_flake_from_profile(
+                &flake_dir,
+                original_profile,
+                global_packages_dir,
+                original_config.format,
+            );
             warn("Build failed. Reverted nixy.json and flake.nix.");
         }
         return Err(e);
@@ -298,27 +828,58 @@ fn upgrade_with_nixy_config(config: &Config, inputs: Vec<String>) -> Result<()>
 
     // Clear rollback context on success
     rollback::clear_context();
+    profile.clear_needs_sync();
+    record_generation(config, profile_name);
+    update_fonts(config, profile_name);
 
-    if !inputs.is_empty() {
-        success(&format!("Updated: {}", inputs.join(", ")));
-    } else {
-        success("All packages updated");
+    Ok(UpdateOutcome::Rebuilt)
+}
+
+/// Release the pinned packages among `names`; whether there were any
+fn unpin(resolved: &mut [ResolvedNixpkgPackage], names: &[String]) -> bool {
+    let mut unpinned = false;
+    for pkg in resolved
+        .iter_mut()
+        .filter(|p| p.pinned && names.contains(&p.name))
+    {
+        info(&format!("Unpinned {}", pkg.name));
+        pkg.pinned = false;
+        unpinned = true;
     }
+    unpinned
+}
 
-    Ok(())
+/// Note that `pkg` was installed with `--pin`, which keeps it where it is
+fn skip_pinned(pkg: &ResolvedNixpkgPackage) {
+    info(&format!(
+        "  {} is pinned to nixpkgs {}; skipping (release it with 'nixy update {} --unpin', or pass --include-pinned)",
+        pkg.name,
+        &pkg.commit_hash[..8.min(pkg.commit_hash.len())],
+        pkg.name
+    ));
 }
 
 /// Upgrade resolved packages by re-resolving them via Nixhub
-fn upgrade_resolved_packages(state: &mut PackageState, package_names: &[&String]) -> Result<()> {
-    let client = NixhubClient::new();
-
+///
+/// Packages installed with `--pin` are skipped unless `include_pinned`; they
+/// stay pinned at whatever they are re-resolved to.
+fn upgrade_resolved_packages(
+    state: &mut PackageState,
+    package_names: &[&String],
+    include_pinned: bool,
+    client: &NixhubClient,
+) -> Result<()> {
     for name in package_names {
         if let Some(existing) = state.resolved_packages.iter().find(|p| &p.name == *name) {
+            if existing.pinned && !include_pinned {
+                skip_pinned(existing);
+                continue;
+            }
             // Determine version to resolve
             let version = existing.version_spec.as_deref().unwrap_or("latest");
             info(&format!("Resolving {}@{}...", name, version));
 
-            match client.resolve_for_current_system(name, version) {
+            match client.resolve_for_current_system(existing.source_package_name(), version) {
                 Ok(resolved) => {
                     if resolved.version != existing.resolved_version
                         || resolved.commit_hash != existing.commit_hash
@@ -332,12 +893,18 @@ fn upgrade_resolved_packages(state: &mut PackageState, package_names: &[&String]
 
                         // Update the package, preserving platform restrictions
                         state.add_resolved_package(ResolvedNixpkgPackage {
-                            name: resolved.name,
+                            name: existing.name.clone(),
                             version_spec: existing.version_spec.clone(),
                             resolved_version: resolved.version,
                             attribute_path: resolved.attribute_path,
                             commit_hash: resolved.commit_hash,
                             platforms: existing.platforms.clone(),
+                            pinned: existing.pinned,
+                            source_name: existing.source_name.clone(),
+                            output: existing.output.clone(),
+                            package_output: existing.package_output.clone(),
+                            outputs: existing.outputs.clone(),
+                            record: updated_record(existing),
                         });
                     } else {
                         info(&format!("  {} is already at the latest version", name));
@@ -353,19 +920,30 @@ fn upgrade_resolved_packages(state: &mut PackageState, package_names: &[&String]
     Ok(())
 }
 
+/// `existing`'s install record, marked as updated now
+fn updated_record(existing: &ResolvedNixpkgPackage) -> InstallRecord {
+    let mut record = existing.record.clone();
+    record.mark_updated();
+    record
+}
+
 /// Upgrade resolved packages in a ProfileConfig
 fn upgrade_resolved_packages_in_profile(
     profile: &mut ProfileConfig,
     package_names: &[&String],
+    include_pinned: bool,
+    client: &NixhubClient,
 ) -> Result<()> {
-    let client = NixhubClient::new();
-
     for name in package_names {
         if let Some(existing) = profile.resolved_packages.iter().find(|p| &p.name == *name) {
+            if existing.pinned && !include_pinned {
+                skip_pinned(existing);
+                continue;
+            }
             let version = existing.version_spec.as_deref().unwrap_or("latest");
             info(&format!("Resolving {}@{}...", name, version));
 
-            match client.resolve_for_current_system(name, version) {
+            match client.resolve_for_current_system(existing.source_package_name(), version) {
                 Ok(resolved) => {
                     if resolved.version != existing.resolved_version
                         || resolved.commit_hash != existing.commit_hash
@@ -378,12 +956,18 @@ fn upgrade_resolved_packages_in_profile(
                         ));
 
                         profile.add_resolved_package(ResolvedNixpkgPackage {
-                            name: resolved.name,
+                            name: existing.name.clone(),
                             version_spec: existing.version_spec.clone(),
                             resolved_version: resolved.version,
                             attribute_path: resolved.attribute_path,
                             commit_hash: resolved.commit_hash,
                             platforms: existing.platforms.clone(),
+                            pinned: existing.pinned,
+                            source_name: existing.source_name.clone(),
+                            output: existing.output.clone(),
+                            package_output: existing.package_output.clone(),
+                            outputs: existing.outputs.clone(),
+                            record: updated_record(existing),
                         });
                     } else {
                         info(&format!("  {} is already at the latest version", name));
@@ -407,6 +991,8 @@ struct ClassifiedTargets {
     legacy: Vec<String>,
     /// Targets that match no flake input, custom package, or legacy package.
     invalid: Vec<String>,
+    /// Custom packages installed at a fixed commit (`install --rev`), with it
+    at_rev: Vec<(String, String)>,
 }
 
 /// Classify update targets (which may be flake input names OR package names)
@@ -425,9 +1011,17 @@ fn classify_update_targets(
     let mut inputs_to_update = Vec::new();
     let mut legacy = Vec::new();
     let mut invalid = Vec::new();
+    let mut at_rev = Vec::new();
 
     for target in targets {
-        if available_inputs.contains(*target) {
+        // Updating can't move a commit fixed in the flake reference
+        if let Some((pkg, rev)) = custom_packages
+            .iter()
+            .filter(|p| &p.name == *target || &p.input_name == *target)
+            .find_map(|p| Some((p, p.pinned_rev()?)))
+        {
+            at_rev.push((pkg.name.clone(), rev.to_string()));
+        } else if available_inputs.contains(*target) {
             // Already a real flake input name.
             inputs_to_update.push((*target).clone());
         } else if let Some(pkg) = custom_packages.iter().find(|p| &p.name == *target) {
@@ -451,6 +1045,18 @@ fn classify_update_targets(
         inputs_to_update,
         legacy,
         invalid,
+        at_rev,
+    }
+}
+
+/// Say why packages installed at a fixed commit are left where they are
+fn skip_at_rev(at_rev: &[(String, String)]) {
+    for (name, rev) in at_rev {
+        info(&format!(
+            "  {} was installed at commit {} (--rev); skipping (reinstall it to move it)",
+            name,
+            &rev[..8.min(rev.len())]
+        ));
     }
 }
 
@@ -475,6 +1081,8 @@ mod tests {
             package_output: "packages".to_string(),
             source_name: None,
             platforms: None,
+            unvalidated: false,
+            record: Default::default(),
         }
     }
 
@@ -540,6 +1148,28 @@ mod tests {
         assert_eq!(result.invalid, vec!["pi-nix"]);
     }
 
+    #[test]
+    fn custom_package_at_a_fixed_rev_is_left_alone() {
+        let rev = "0123456789abcdef0123456789abcdef01234567";
+        let mut pinned = custom("tool", "github-owner-tool");
+        pinned.input_url = format!("github:owner/tool?rev={}", rev);
+        let available = vec!["nixpkgs".to_string(), "github-owner-tool".to_string()];
+        let by_name = "tool".to_string();
+        let by_input = "github-owner-tool".to_string();
+
+        let result = classify_update_targets(&[&by_name, &by_input], &available, &[], &[pinned]);
+
+        assert!(result.inputs_to_update.is_empty());
+        assert!(result.invalid.is_empty());
+        assert_eq!(
+            result.at_rev,
+            vec![
+                ("tool".to_string(), rev.to_string()),
+                ("tool".to_string(), rev.to_string())
+            ]
+        );
+    }
+
     #[test]
     fn unknown_target_is_invalid() {
         let available = vec!["nixpkgs".to_string()];
@@ -1,4 +1,5 @@
 use crate::error::{Error, Result};
+use crate::nix::Nix;
 
 use super::{info, success};
 
@@ -26,6 +27,12 @@ impl Drop for TempFileGuard {
 }
 
 pub fn run(force: bool) -> Result<()> {
+    if Nix::frozen() {
+        return Err(Error::Frozen("nixy upgrade".to_string()));
+    }
+    if Nix::offline() {
+        return Err(Error::Offline("nixy upgrade".to_string()));
+    }
     let current_version = env!("CARGO_PKG_VERSION");
     info(&format!("Current version: {}", current_version));
 
@@ -0,0 +1,170 @@
+//! Find the installed package an executable in the environment comes from.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::cli::WhichArgs;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::nix::Nix;
+use crate::nixy_config::{nixy_json_exists, NixyConfig};
+use crate::profile::get_flake_dir;
+
+use super::info;
+use super::info::{entry_info, find_local_package};
+use super::why::explain;
+
+pub fn run(config: &Config, args: WhichArgs) -> Result<()> {
+    if !nixy_json_exists(config) {
+        return Err(Error::Usage(
+            "nixy which requires nixy.json. Run 'nixy sync' to migrate first.".to_string(),
+        ));
+    }
+    if args.binary.is_empty() || args.binary.contains('/') {
+        return Err(Error::Usage(format!(
+            "'{}' is not a command name",
+            args.binary
+        )));
+    }
+    let env_bin = config.env_link.join("bin");
+    let link = env_bin.join(&args.binary);
+    if fs::symlink_metadata(&link).is_err() {
+        return Err(Error::Usage(not_in_env(&args.binary, &env_bin)));
+    }
+    let target = fs::canonicalize(&link)?;
+    let store_dir = Nix::store_dir();
+    let store_dir = fs::canonicalize(&store_dir).unwrap_or(store_dir);
+    let store_path = store_path_of(&target, &store_dir).ok_or_else(|| {
+        Error::Usage(format!(
+            "{} is {}, outside the Nix store",
+            args.binary,
+            target.display()
+        ))
+    })?;
+
+    let nixy_config = NixyConfig::load(config)?;
+    let profile_name = &nixy_config.active_profile;
+    info(&format!(
+        "Evaluating the packages of profile '{}'...",
+        profile_name
+    ));
+    let out_paths = Nix::flake_package_out_paths(&get_flake_dir(config)?)?;
+    let package = providing_package(&out_paths, &store_path).ok_or_else(|| {
+        Error::Usage(format!(
+            "{} comes from {}, which no package of profile '{}' builds now \
+             (run 'nixy sync' if the environment is out of date)",
+            args.binary,
+            store_path.display(),
+            profile_name
+        ))
+    })?;
+
+    println!("{}: {}", args.binary, target.display());
+    let entry = find_local_package(config, &nixy_config, package).or_else(|| {
+        nixy_config
+            .get_active_profile()
+            .and_then(|profile| entry_info(profile, package))
+    });
+    match entry {
+        Some(entry) => println!("{}", explain(&entry, profile_name)),
+        None => println!(
+            "{} is in the profile's flake.nix but not in nixy.json (a user block?)",
+            package
+        ),
+    }
+    Ok(())
+}
+
+/// The store path (`<store>/<hash>-<name>`) `path` is in, if it is in the
+/// store at all
+fn store_path_of(path: &Path, store_dir: &Path) -> Option<PathBuf> {
+    let name = path.strip_prefix(store_dir).ok()?.components().next()?;
+    Some(store_dir.join(name))
+}
+
+/// The package among `out_paths` (package -> output paths) with an output
+/// at `store_path`
+fn providing_package<'a>(
+    out_paths: &'a BTreeMap<String, Vec<String>>,
+    store_path: &Path,
+) -> Option<&'a str> {
+    out_paths
+        .iter()
+        .find(|(_, outputs)| outputs.iter().any(|out| Path::new(out) == store_path))
+        .map(|(name, _)| name.as_str())
+}
+
+/// Why `binary` is not found, naming where else on PATH it is, if anywhere
+fn not_in_env(binary: &str, env_bin: &Path) -> String {
+    let env_bin = fs::canonicalize(env_bin).unwrap_or_else(|_| env_bin.to_path_buf());
+    let elsewhere = env::var_os("PATH").and_then(|path| {
+        env::split_paths(&path)
+            .filter(|dir| fs::canonicalize(dir).map_or(true, |dir| dir != env_bin))
+            .map(|dir| dir.join(binary))
+            .find(|file| is_executable(file))
+    });
+    match elsewhere {
+        Some(file) => format!(
+            "'{}' is not in the nixy environment; the one on PATH is {}",
+            binary,
+            file.display()
+        ),
+        None => format!(
+            "'{}' is not in the nixy environment, nor anywhere else on PATH \
+             (try 'nixy search {}')",
+            binary, binary
+        ),
+    }
+}
+
+fn is_executable(file: &Path) -> bool {
+    fs::metadata(file).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_path_of() {
+        let store = Path::new("/nix/store");
+        assert_eq!(
+            store_path_of(Path::new("/nix/store/abc-ripgrep-14.1.1/bin/rg"), store),
+            Some(PathBuf::from("/nix/store/abc-ripgrep-14.1.1"))
+        );
+        assert_eq!(store_path_of(Path::new("/usr/bin/rg"), store), None);
+        assert_eq!(store_path_of(store, store), None);
+    }
+
+    #[test]
+    fn test_providing_package() {
+        let out_paths = BTreeMap::from([
+            (
+                "git".to_string(),
+                vec![
+                    "/nix/store/aaa-git-2.47.0".to_string(),
+                    "/nix/store/bbb-git-2.47.0-doc".to_string(),
+                ],
+            ),
+            (
+                "ripgrep".to_string(),
+                vec!["/nix/store/ccc-ripgrep-14.1.1".to_string()],
+            ),
+        ]);
+        assert_eq!(
+            providing_package(&out_paths, Path::new("/nix/store/bbb-git-2.47.0-doc")),
+            Some("git")
+        );
+        assert_eq!(
+            providing_package(&out_paths, Path::new("/nix/store/ccc-ripgrep-14.1.1")),
+            Some("ripgrep")
+        );
+        assert_eq!(
+            providing_package(&out_paths, Path::new("/nix/store/ddd-fd-10.2.0")),
+            None
+        );
+    }
+}
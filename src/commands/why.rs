@@ -0,0 +1,145 @@
+//! Explain how a package got into the active profile's environment.
+
+use crate::cli::WhyArgs;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::nixy_config::{nixy_json_exists, NixyConfig};
+
+use super::info::{entry_info, find_local_package, PackageInfo};
+
+pub fn run(config: &Config, args: WhyArgs) -> Result<()> {
+    if !nixy_json_exists(config) {
+        return Err(Error::Usage(
+            "nixy why requires nixy.json. Run 'nixy sync' to migrate first.".to_string(),
+        ));
+    }
+    let nixy_config = NixyConfig::load(config)?;
+    let profile_name = &nixy_config.active_profile;
+    let package = find_local_package(config, &nixy_config, &args.package).or_else(|| {
+        nixy_config
+            .get_active_profile()
+            .and_then(|profile| entry_info(profile, &args.package))
+    });
+
+    match package {
+        Some(package) => {
+            println!("{}", explain(&package, profile_name));
+            Ok(())
+        }
+        None => Err(Error::Usage(format!(
+            "'{}' is not installed in profile '{}'. Run 'nixy list' to see what is.",
+            args.package, profile_name
+        ))),
+    }
+}
+
+/// One sentence on where `package` comes from
+pub(super) fn explain(package: &PackageInfo, profile: &str) -> String {
+    let name = &package.name;
+    let path = || {
+        package
+            .path
+            .as_ref()
+            .map(|p| format!(" ({})", p.display()))
+            .unwrap_or_default()
+    };
+    match package.kind {
+        "local-file" => format!(
+            "{} is a local package in the global packages directory{}, part of every profile",
+            name,
+            path()
+        ),
+        "local-flake" => format!(
+            "{} is a local flake in the global packages directory{}, part of every profile",
+            name,
+            path()
+        ),
+        "custom" => format!(
+            "{} is a flake package in profile '{}', from {}",
+            name,
+            profile,
+            package.input_url.as_deref().unwrap_or("an unknown flake")
+        ),
+        "resolved" => {
+            let mut sentence = format!("{} is installed directly in profile '{}'", name, profile);
+            if let Some(source) = package.nixhub_name.as_deref().filter(|s| s != name) {
+                sentence.push_str(&format!(" as nixpkgs package {}", source));
+            }
+            if let Some(version) = &package.version {
+                sentence.push_str(&format!(" at version {}", version));
+            }
+            if let Some(spec) = &package.version_spec {
+                sentence.push_str(&format!(" (requested {})", spec));
+            }
+            if let Some(commit) = &package.commit {
+                sentence.push_str(&format!(", from nixpkgs commit {}", commit));
+            }
+            if package.pinned {
+                sentence.push_str(" (pinned)");
+            }
+            sentence
+        }
+        _ => format!(
+            "{} is installed directly in profile '{}', from the profile's nixpkgs channel",
+            name, profile
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_explain() {
+        let legacy = PackageInfo {
+            name: "ripgrep".to_string(),
+            kind: "legacy",
+            ..Default::default()
+        };
+        assert_eq!(
+            explain(&legacy, "default"),
+            "ripgrep is installed directly in profile 'default', from the profile's nixpkgs channel"
+        );
+
+        let resolved = PackageInfo {
+            name: "node18".to_string(),
+            kind: "resolved",
+            version: Some("18.20.5".to_string()),
+            version_spec: Some("18".to_string()),
+            commit: Some("abc123".to_string()),
+            pinned: true,
+            nixhub_name: Some("nodejs".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            explain(&resolved, "work"),
+            "node18 is installed directly in profile 'work' as nixpkgs package nodejs \
+             at version 18.20.5 (requested 18), from nixpkgs commit abc123 (pinned)"
+        );
+
+        let custom = PackageInfo {
+            name: "tool".to_string(),
+            kind: "custom",
+            input_url: Some("github:owner/tool".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            explain(&custom, "default"),
+            "tool is a flake package in profile 'default', from github:owner/tool"
+        );
+
+        let local = PackageInfo {
+            name: "my-flake".to_string(),
+            kind: "local-flake",
+            path: Some(PathBuf::from("/config/packages/my-flake")),
+            ..Default::default()
+        };
+        assert_eq!(
+            explain(&local, "default"),
+            "my-flake is a local flake in the global packages directory \
+             (/config/packages/my-flake), part of every profile"
+        );
+    }
+}
@@ -0,0 +1,350 @@
+//! Reachability of the nixpkgs commits resolved packages are pinned to.
+//!
+//! Resolved packages fetch `github:NixOS/nixpkgs/<commit>`. When a commit is
+//! no longer served (force-pushed away or garbage-collected), every fresh
+//! build fails with a tarball download or unpack error that does not say
+//! which packages are affected. This module recognizes that failure in nix
+//! output and checks commits ahead of time with HEAD requests against the
+//! archive URL nix would download.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use regex::Regex;
+
+use crate::error::Error;
+use crate::state::ResolvedNixpkgPackage;
+
+const GITHUB_URL: &str = "https://github.com";
+
+/// A commit found reachable is not checked again for this long
+const CACHE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Commits checked at the same time
+const PARALLEL_CHECKS: usize = 8;
+
+/// A nixpkgs commit in a fetch URL (`github:NixOS/nixpkgs/<rev>`,
+/// `.../NixOS/nixpkgs/archive/<rev>.tar.gz`) or a generated input name
+/// (`nixpkgs-<rev prefix>`)
+static COMMIT_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:NixOS/nixpkgs(?:/archive)?/|\bnixpkgs-)([0-9a-f]{7,40})\b")
+        .expect("Invalid regex pattern")
+});
+
+/// Ways nix reports a source it cannot get. Network outages ("unable to
+/// download ... Could not resolve host") are deliberately not among them.
+const MISSING_MARKERS: &[&str] = &[
+    "http error 404",
+    "http error 410",
+    "http error 422",
+    "failed to open archive",
+    "failed to unpack",
+];
+
+/// How many lines before a marker may still name the commit it is about
+const MARKER_CONTEXT: usize = 2;
+
+/// nixpkgs commits that nix output reports as missing upstream
+///
+/// Only commits named on (or just before) a line with a 404/unpack failure
+/// count, so pinned commits that merely appear elsewhere in the log are not
+/// blamed.
+pub fn unreachable_in_log(log: &str) -> Vec<String> {
+    let lines: Vec<&str> = log.lines().collect();
+    let mut commits = BTreeSet::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        let lower = line.to_lowercase();
+        if !MISSING_MARKERS.iter().any(|m| lower.contains(m)) {
+            continue;
+        }
+        for context in &lines[i.saturating_sub(MARKER_CONTEXT)..=i] {
+            for c in COMMIT_REGEX.captures_iter(context) {
+                commits.insert(c[1].to_string());
+            }
+        }
+    }
+    commits.into_iter().collect()
+}
+
+/// Whether nix output says something it fetched does not exist upstream,
+/// whichever commit or ref it was about
+pub fn reports_missing(log: &str) -> bool {
+    log.lines().any(|line| {
+        let lower = line.to_lowercase();
+        MISSING_MARKERS.iter().any(|m| lower.contains(m))
+    })
+}
+
+/// A (possibly abbreviated) git commit hash
+pub fn is_commit_hash(rev: &str) -> bool {
+    (7..=40).contains(&rev.len()) && rev.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Whether a package pinned to `commit_hash` is pinned to `commit`, which may
+/// be abbreviated (as in the `nixpkgs-<rev>` input names)
+pub fn is_pinned_to(commit_hash: &str, commit: &str) -> bool {
+    !commit.is_empty() && commit_hash.starts_with(commit)
+}
+
+/// Fill in the packages pinned to the commits of an
+/// [`Error::UnreachableCommits`]; other errors are returned unchanged
+pub fn attribute_packages(err: Error, packages: &[ResolvedNixpkgPackage]) -> Error {
+    match err {
+        Error::UnreachableCommits { commits, .. } => {
+            // Report full hashes where the log only had an input name prefix
+            let commits: BTreeSet<String> = commits
+                .into_iter()
+                .map(|c| {
+                    packages
+                        .iter()
+                        .find(|p| is_pinned_to(&p.commit_hash, &c))
+                        .map_or(c, |p| p.commit_hash.clone())
+                })
+                .collect();
+            let affected = packages
+                .iter()
+                .filter(|p| commits.iter().any(|c| is_pinned_to(&p.commit_hash, c)))
+                .map(|p| p.name.clone())
+                .collect();
+            Error::UnreachableCommits {
+                commits: commits.into_iter().collect(),
+                packages: affected,
+            }
+        }
+        other => other,
+    }
+}
+
+/// Whether a commit can still be downloaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reachability {
+    Reachable,
+    /// The archive is gone (404 and friends)
+    Missing,
+    /// The check itself failed, e.g. no network
+    Unknown(String),
+}
+
+/// HEAD-request checker for nixpkgs commit archives
+pub struct CommitChecker {
+    base: String,
+    agent: ureq::Agent,
+}
+
+impl Default for CommitChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommitChecker {
+    /// Checker against GitHub, or the host in `NIXY_GITHUB_URL` if set
+    /// (e.g. a local server in tests)
+    pub fn new() -> Self {
+        let base = std::env::var("NIXY_GITHUB_URL")
+            .ok()
+            .filter(|url| !url.is_empty())
+            .unwrap_or_else(|| GITHUB_URL.to_string());
+        Self::with_base(base.trim_end_matches('/'))
+    }
+
+    /// Checker against a GitHub-compatible server at `base`
+    pub fn with_base(base: &str) -> Self {
+        // The archive URL redirects to codeload for live commits; the
+        // redirect alone answers the question
+        let agent = ureq::AgentBuilder::new()
+            .redirects(0)
+            .timeout(Duration::from_secs(10))
+            .build();
+        Self {
+            base: base.to_string(),
+            agent,
+        }
+    }
+
+    /// Check one commit
+    pub fn check(&self, commit: &str) -> Reachability {
+        let url = format!("{}/NixOS/nixpkgs/archive/{}.tar.gz", self.base, commit);
+        match self.agent.head(&url).call() {
+            Ok(_) => Reachability::Reachable,
+            Err(ureq::Error::Status(404 | 410 | 422, _)) => Reachability::Missing,
+            Err(ureq::Error::Status(code, _)) => Reachability::Unknown(format!("HTTP {}", code)),
+            Err(ureq::Error::Transport(e)) => Reachability::Unknown(e.to_string()),
+        }
+    }
+
+    /// Check several commits in parallel
+    ///
+    /// Commits found reachable within the last week are taken from the cache
+    /// at `cache_path` without a request. A cache that cannot be read or
+    /// written only costs the requests.
+    pub fn check_all(
+        &self,
+        commits: &[String],
+        cache_path: &Path,
+    ) -> BTreeMap<String, Reachability> {
+        let mut cache = load_cache(cache_path);
+        let now = now_secs();
+        let fresh = |checked: u64| now.saturating_sub(checked) < CACHE_TTL.as_secs();
+
+        let mut results = BTreeMap::new();
+        let mut pending = Vec::new();
+        for commit in commits.iter().collect::<BTreeSet<_>>() {
+            if cache.get(commit).is_some_and(|&checked| fresh(checked)) {
+                results.insert(commit.clone(), Reachability::Reachable);
+            } else {
+                pending.push(commit.clone());
+            }
+        }
+
+        for chunk in pending.chunks(PARALLEL_CHECKS) {
+            let checked: Vec<(String, Reachability)> = std::thread::scope(|s| {
+                let handles: Vec<_> = chunk
+                    .iter()
+                    .map(|commit| s.spawn(move || (commit.clone(), self.check(commit))))
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|h| h.join().expect("commit check panicked"))
+                    .collect()
+            });
+            results.extend(checked);
+        }
+
+        // Only successes are cached: a missing commit must keep failing
+        // until it is fixed, and an unknown result says nothing
+        cache.retain(|_, checked| fresh(*checked));
+        for (commit, reachability) in &results {
+            if *reachability == Reachability::Reachable {
+                cache.entry(commit.clone()).or_insert(now);
+            }
+        }
+        if let Ok(json) = serde_json::to_string_pretty(&cache) {
+            if let Some(parent) = cache_path.parent() {
+                let _ = fs::create_dir_all(parent);
+            }
+            let _ = fs::write(cache_path, json);
+        }
+
+        results
+    }
+}
+
+fn load_cache(path: &Path) -> BTreeMap<String, u64> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolved(name: &str, commit: &str) -> ResolvedNixpkgPackage {
+        ResolvedNixpkgPackage {
+            name: name.to_string(),
+            version_spec: None,
+            resolved_version: "1.0".to_string(),
+            attribute_path: name.to_string(),
+            commit_hash: commit.to_string(),
+            platforms: None,
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_unreachable_in_log_reads_archive_404() {
+        let log = "error:\n       … while fetching the input 'github:NixOS/nixpkgs/0123456789abcdef0123456789abcdef01234567'\n\n       error: unable to download 'https://github.com/NixOS/nixpkgs/archive/0123456789abcdef0123456789abcdef01234567.tar.gz': HTTP error 404\n";
+        assert_eq!(
+            unreachable_in_log(log),
+            vec!["0123456789abcdef0123456789abcdef01234567".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_unreachable_in_log_reads_unpack_failure_after_input_name() {
+        let log = "error: … while updating the lock file of flake 'path:/x'\n       … while fetching input 'nixpkgs-abc123de'\n       error: Failed to open archive (Unrecognized archive format)\n";
+        assert_eq!(unreachable_in_log(log), vec!["abc123de".to_string()]);
+    }
+
+    #[test]
+    fn test_unreachable_in_log_ignores_unrelated_failures() {
+        // A network outage is not a missing commit
+        let offline = "error: unable to download 'https://github.com/NixOS/nixpkgs/archive/0123456789abcdef.tar.gz': Could not resolve hostname (6)\n";
+        assert!(unreachable_in_log(offline).is_empty());
+
+        // Pinned commits mentioned far from the failure are not blamed
+        let unrelated = "fetching 'github:NixOS/nixpkgs/0123456789abcdef'\nbuilding...\nbuilding...\nbuilding...\nerror: builder for '/nix/store/x-foo.drv' failed with exit code 1\n";
+        assert!(unreachable_in_log(unrelated).is_empty());
+    }
+
+    #[test]
+    fn test_attribute_packages_names_affected_packages() {
+        let packages = vec![
+            resolved("nodejs", "abc123def4567890"),
+            resolved("python", "abc123def4567890"),
+            resolved("go", "fedcba9876543210"),
+        ];
+        let err = Error::UnreachableCommits {
+            commits: vec!["abc123de".to_string()],
+            packages: Vec::new(),
+        };
+
+        match attribute_packages(err, &packages) {
+            Error::UnreachableCommits { commits, packages } => {
+                assert_eq!(commits, vec!["abc123def4567890".to_string()]);
+                assert_eq!(packages, vec!["nodejs".to_string(), "python".to_string()]);
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+    }
+
+    #[test]
+    fn test_attribute_packages_leaves_other_errors() {
+        let err = attribute_packages(Error::NoFlakeLock, &[]);
+        assert!(matches!(err, Error::NoFlakeLock));
+    }
+
+    #[test]
+    fn test_reports_missing() {
+        assert!(reports_missing(
+            "error: unable to download 'https://api.github.com/repos/NixOS/nixpkgs/commits/deadbeef': HTTP error 422"
+        ));
+        assert!(!reports_missing(
+            "error: unable to download 'https://github.com/x': Could not resolve hostname (6)"
+        ));
+    }
+
+    #[test]
+    fn test_is_commit_hash() {
+        assert!(is_commit_hash("a3a3dda"));
+        assert!(is_commit_hash("a3a3dda3bacf61e8a39258a0ed9c924eeca8e293"));
+        assert!(!is_commit_hash("a3a3dd"));
+        assert!(!is_commit_hash("nixos-24.05"));
+    }
+
+    #[test]
+    fn test_is_pinned_to_matches_prefixes() {
+        assert!(is_pinned_to("abc123def4567890", "abc123de"));
+        assert!(is_pinned_to("abc123def4567890", "abc123def4567890"));
+        assert!(!is_pinned_to("abc123def4567890", "abd"));
+        assert!(!is_pinned_to("abc123def4567890", ""));
+    }
+}
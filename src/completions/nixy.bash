@@ -1,13 +1,13 @@
 # nixy bash completion
-# Dynamic completion candidates are produced by the hidden `nixy completions`
-# helper, so installed package names and profiles always stay in sync.
+# Dynamic completion candidates are produced by `nixy completions <kind>`
+# so installed package names and profiles always stay in sync.
 
 _nixy() {
     local cur prev cmd
     cur="${COMP_WORDS[COMP_CWORD]}"
     prev="${COMP_WORDS[COMP_CWORD-1]}"
 
-    local subcommands="install add uninstall remove list ls search update sync config profile upgrade file"
+    local subcommands="install add uninstall remove list ls search run shell update undo-update rollback sync config profile profiles diff export import upgrade file info which why platform gc doctor completions"
 
     if [[ $COMP_CWORD -eq 1 ]]; then
         COMPREPLY=( $(compgen -W "$subcommands" -- "$cur") )
@@ -16,27 +16,104 @@ _nixy() {
 
     cmd="${COMP_WORDS[1]}"
     case "$cmd" in
-        uninstall|remove|file)
+        uninstall|remove)
+            COMPREPLY=( $(compgen -W "--purge --commit-message --git-commit --dry-run --yes $(command nixy completions installed 2>/dev/null)" -- "$cur") )
+            ;;
+        file)
             COMPREPLY=( $(compgen -W "$(command nixy completions installed 2>/dev/null)" -- "$cur") )
             ;;
         update)
-            COMPREPLY=( $(compgen -W "--all $(command nixy completions installed 2>/dev/null)" -- "$cur") )
+            if [[ "$prev" == "--profile" ]]; then
+                COMPREPLY=( $(compgen -W "$(command nixy completions profiles 2>/dev/null)" -- "$cur") )
+            else
+                COMPREPLY=( $(compgen -W "--all --profile --all-profiles --commit --pin-missing-to --unpin --include-pinned --dry-run --refresh $(command nixy completions installed 2>/dev/null)" -- "$cur") )
+            fi
             ;;
         install|add)
             if [[ "$cur" == -* || "$prev" == "-p" || "$prev" == "--platform" ]]; then
-                COMPREPLY=( $(compgen -W "-p --platform darwin macos linux x86_64-darwin aarch64-darwin x86_64-linux aarch64-linux" -- "$cur") )
+                COMPREPLY=( $(compgen -W "-p --platform --no-validate --rev --pin --atomic --output --outputs --as --commit-message --git-commit --dry-run --offline --refresh darwin macos linux aarch64 x86_64 amd64 x86_64-darwin aarch64-darwin x86_64-linux aarch64-linux" -- "$cur") )
             fi
             ;;
         config)
-            COMPREPLY=( $(compgen -W "zsh bash fish" -- "$cur") )
+            COMPREPLY=( $(compgen -W "zsh bash fish --print-env-dir --print-bin-dir --print-config-path --print-state-dir" -- "$cur") )
+            ;;
+        sync)
+            if [[ "$prev" == "--copy-lock-from" ]]; then
+                COMPREPLY=( $(compgen -W "$(command nixy completions profiles 2>/dev/null)" -- "$cur") )
+            else
+                COMPREPLY=( $(compgen -W "--copy-lock-from --prune --dry-run --all --commit-message --git-commit" -- "$cur") )
+            fi
             ;;
         profile)
             if [[ "$cur" == -* ]]; then
-                COMPREPLY=( $(compgen -W "-c -d" -- "$cur") )
+                COMPREPLY=( $(compgen -W "-c -d --copy-lock-from --activate-only --set-description --rename --copy --copy-from --force --channel --outputs --yes" -- "$cur") )
             else
                 COMPREPLY=( $(compgen -W "$(command nixy completions profiles 2>/dev/null)" -- "$cur") )
             fi
             ;;
+        diff)
+            COMPREPLY=( $(compgen -W "--json $(command nixy completions profiles 2>/dev/null)" -- "$cur") )
+            ;;
+        export)
+            if [[ "$prev" == "--output" || "$prev" == "-o" ]]; then
+                COMPREPLY=( $(compgen -f -- "$cur") )
+            elif [[ "$prev" == "--profile" ]]; then
+                COMPREPLY=( $(compgen -W "$(command nixy completions profiles 2>/dev/null)" -- "$cur") )
+            else
+                COMPREPLY=( $(compgen -W "--output --profile --flake" -- "$cur") )
+            fi
+            ;;
+        import)
+            if [[ "$prev" == "--profile" ]]; then
+                COMPREPLY=( $(compgen -W "$(command nixy completions profiles 2>/dev/null)" -- "$cur") )
+            elif [[ "$cur" == -* ]]; then
+                COMPREPLY=( $(compgen -W "--profile --from-profile --merge --force --overwrite" -- "$cur") )
+            else
+                COMPREPLY=( $(compgen -f -- "$cur") )
+            fi
+            ;;
+        info)
+            COMPREPLY=( $(compgen -W "--offline --json $(command nixy completions installed 2>/dev/null)" -- "$cur") )
+            ;;
+        run|shell)
+            if [[ "$cur" == -* ]]; then
+                COMPREPLY=( $(compgen -W "--from" -- "$cur") )
+            fi
+            ;;
+        which)
+            COMPREPLY=( $(compgen -c -- "$cur") )
+            ;;
+        why)
+            COMPREPLY=( $(compgen -W "$(command nixy completions installed 2>/dev/null)" -- "$cur") )
+            ;;
+        gc)
+            COMPREPLY=( $(compgen -W "--dry-run" -- "$cur") )
+            ;;
+        platform)
+            if [[ "$prev" == "--set" ]]; then
+                COMPREPLY=( $(compgen -W "darwin macos linux aarch64 x86_64 amd64 x86_64-darwin aarch64-darwin x86_64-linux aarch64-linux" -- "$cur") )
+            else
+                COMPREPLY=( $(compgen -W "--set --clear $(command nixy completions installed 2>/dev/null)" -- "$cur") )
+            fi
+            ;;
+        list|ls)
+            COMPREPLY=( $(compgen -W "--verify --no-pager --count --json --outdated" -- "$cur") )
+            ;;
+        search)
+            COMPREPLY=( $(compgen -W "--rev --pinned --limit --json --installed-only" -- "$cur") )
+            ;;
+        doctor)
+            COMPREPLY=( $(compgen -W "--json --fix" -- "$cur") )
+            ;;
+        completions)
+            COMPREPLY=( $(compgen -W "bash zsh fish" -- "$cur") )
+            ;;
+        undo-update|undo-upgrade)
+            COMPREPLY=( $(compgen -W "--force" -- "$cur") )
+            ;;
+        rollback)
+            COMPREPLY=( $(compgen -W "--list" -- "$cur") )
+            ;;
         upgrade)
             COMPREPLY=( $(compgen -W "-f --force" -- "$cur") )
             ;;
@@ -36,8 +36,7 @@ pub struct Config {
     pub nixy_json: PathBuf,
     /// Global local packages directory (~/.config/nixy/packages) - NEW
     pub global_packages_dir: PathBuf,
-    /// State directory (~/.local/state/nixy) - NEW (used in tests)
-    #[allow(dead_code)]
+    /// State directory (~/.local/state/nixy) - NEW
     pub state_dir: PathBuf,
     /// Profiles state directory (~/.local/state/nixy/profiles) - NEW
     pub profiles_state_dir: PathBuf,
@@ -52,6 +51,8 @@ pub struct Config {
 }
 
 impl Config {
+    /// Resolve paths from `NIXY_CONFIG_DIR`, `NIXY_STATE_DIR`, and `NIXY_ENV`,
+    /// falling back to the XDG-style defaults
     pub fn new() -> Self {
         // Use ~/.config/nixy to match bash script behavior (XDG-style, not platform-specific)
         let config_dir = std::env::var("NIXY_CONFIG_DIR")
@@ -71,11 +72,27 @@ impl Config {
                     .join(".local/state/nixy")
             });
 
+        let mut config = Self::from_dirs(config_dir, state_dir);
+
         // Use ~/.local/state/nixy/env to match bash script behavior
-        let env_link = std::env::var("NIXY_ENV")
-            .map(PathBuf::from)
-            .unwrap_or_else(|_| state_dir.join("env"));
+        if let Ok(env_link) = std::env::var("NIXY_ENV") {
+            config.env_link = PathBuf::from(env_link);
+        }
+
+        config
+    }
 
+    /// Build the paths for explicit config and state directories, ignoring
+    /// the `NIXY_*` environment variables
+    ///
+    /// ```
+    /// use nixy_core::Config;
+    ///
+    /// let config = Config::from_dirs("/tmp/nixy/config".into(), "/tmp/nixy/state".into());
+    /// assert_eq!(config.nixy_json, std::path::Path::new("/tmp/nixy/config/nixy.json"));
+    /// assert_eq!(config.env_link, std::path::Path::new("/tmp/nixy/state/env"));
+    /// ```
+    pub fn from_dirs(config_dir: PathBuf, state_dir: PathBuf) -> Self {
         Self {
             nixy_json: config_dir.join("nixy.json"),
             global_packages_dir: config_dir.join("packages"),
@@ -84,9 +101,9 @@ impl Config {
             profiles_dir: config_dir.join("profiles"),
             active_file: config_dir.join("active"),
             legacy_flake: config_dir.join("flake.nix"),
+            env_link: state_dir.join("env"),
             config_dir,
             state_dir,
-            env_link,
         }
     }
 }
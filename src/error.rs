@@ -15,18 +15,39 @@ pub enum Error {
     #[error("Package '{0}' not found in nixpkgs or is not a valid derivation")]
     PackageNotFound(String),
 
+    #[error("Package(s) not found: {0}. Nothing was installed.")]
+    PackagesNotFound(String),
+
+    #[error("Package(s) not found: {0}. The others were installed.")]
+    SomePackagesNotFound(String),
+
     #[error("Package '{0}' is not installed")]
     PackageNotInstalled(String),
 
     #[error("Profile '{0}' does not exist")]
     ProfileNotFound(String),
 
-    #[error("Cannot delete the active profile. Switch to another profile first.")]
-    CannotDeleteActiveProfile,
+    #[error("Cannot delete the active profile '{0}'. Switch to another profile first with 'nixy profile <name>'.")]
+    CannotDeleteActiveProfile(String),
+
+    #[error("Cannot delete the default profile.")]
+    CannotDeleteDefaultProfile,
+
+    #[error("Cannot rename the default profile.")]
+    CannotRenameDefaultProfile,
+
+    #[error("Profile '{0}' already exists")]
+    ProfileExists(String),
 
     #[error("Invalid profile name '{0}'. Use only letters, numbers, dashes, and underscores.")]
     InvalidProfileName(String),
 
+    #[error("Update failed for profile(s): {0}")]
+    ProfileUpdateFailed(String),
+
+    #[error("Sync failed for profile(s): {0}")]
+    ProfileSyncFailed(String),
+
     #[error("Nix command failed: {0}")]
     NixCommand(String),
 
@@ -42,6 +63,93 @@ pub enum Error {
     #[error("Failed to parse flake.lock. The file may be corrupted.")]
     InvalidFlakeLock,
 
+    #[error("Cannot copy {}: {reason}", path.display())]
+    IncompatibleLock {
+        path: std::path::PathBuf,
+        reason: String,
+    },
+
+    #[error("{}", unreachable_commits_message(commits, packages))]
+    UnreachableCommits {
+        commits: Vec<String>,
+        packages: Vec<String>,
+    },
+
+    #[error("nixpkgs revision '{0}' does not exist on GitHub. Check the commit hash.")]
+    UnknownRev(String),
+
+    #[error("{0} is not allowed with --frozen (NIXY_FROZEN)")]
+    Frozen(String),
+
+    #[error("{0} needs network access, which --offline forbids")]
+    Offline(String),
+
+    #[error("The program exited with status {0}")]
+    CommandExited(i32),
+
+    #[error("The flake.lock in {} needs changes (e.g. for a new input), which --frozen forbids. Run 'nixy sync' without --frozen and commit the updated flake.lock.", .0.display())]
+    LockChangeNeeded(std::path::PathBuf),
+
+    #[error("Failed to build environment: {0}")]
+    BuildFailed(String),
+
+    #[error("{}", missing_attribute_message(attribute, packages))]
+    MissingAttribute {
+        attribute: String,
+        packages: Vec<String>,
+    },
+
+    #[error("Environment has not been built yet. Run 'nixy sync' first.")]
+    EnvNotBuilt,
+
+    #[error("Environment is stale: its build {} is no longer in the Nix store (garbage-collected?). Run 'nixy sync' to rebuild it.", target.display())]
+    StaleEnv { target: std::path::PathBuf },
+
+    #[error("Cannot roll back: the previous environment {} is no longer in the Nix store (garbage-collected?). Run 'nixy sync' to rebuild the current one.", target.display())]
+    GenerationGone { target: std::path::PathBuf },
+
+    #[error("{0} doctor check(s) failed")]
+    DoctorFailed(usize),
+
+    #[error("Package name(s) defined more than once: {0}. Remove the duplicate entries from nixy.json or packages/.")]
+    DuplicatePackageNames(String),
+
+    #[error("The generated flake.nix could not be parsed")]
+    InvalidGeneratedFlake,
+
+    #[error("The generated flake.nix has a syntax error ({0}), so it was not written. Check package names and URLs in nixy.json for unusual characters.")]
+    GeneratedFlakeSyntax(String),
+
+    #[error("Cannot keep the user block in flake.nix: {0}. Fix its markers; flake.nix was not regenerated.")]
+    UserBlock(String),
+
+    #[error("{0} declared package(s) missing from the generated flake")]
+    VerifyFailed(usize),
+
+    #[error("{0} difference(s) between nixy.json and the profile's flake.nix")]
+    FlakeDrift(usize),
+
+    #[error("{0} local package(s) not found (set \"strict_local\": false in nixy.json to build without them)")]
+    MissingLocalPackages(usize),
+
+    #[error("local package definition(s) removed since the last sync: {0} (restore them, or run 'nixy uninstall' on them to drop them)")]
+    LocalDefinitionsRemoved(String),
+
+    #[error("Invalid manifest: {0}")]
+    InvalidManifest(String),
+
+    #[error("{0} conflict(s) with what is already installed (pass --force to overwrite)")]
+    ImportConflicts(usize),
+
+    #[error("'{0}' has not been resolved before, so it needs network access to resolve (run without --offline)")]
+    NotResolvedOffline(String),
+
+    #[error("Cannot create state directory {}: {reason}", path.display())]
+    StateDir {
+        path: std::path::PathBuf,
+        reason: String,
+    },
+
     #[error("Unknown shell: {0}. Supported: bash, zsh, fish")]
     UnknownShell(String),
 
@@ -54,6 +162,9 @@ pub enum Error {
     #[error("State file error: {0}")]
     StateFile(String),
 
+    #[error("Refusing to save an invalid nixy.json: {0}")]
+    InvalidConfig(String),
+
     #[error("Nixhub API error: {0}")]
     NixhubApi(String),
 
@@ -79,4 +190,33 @@ pub enum Error {
     Dialoguer(#[from] dialoguer::Error),
 }
 
+fn unreachable_commits_message(commits: &[String], packages: &[String]) -> String {
+    let used_by = if packages.is_empty() {
+        String::new()
+    } else {
+        format!(" (pinned by {})", packages.join(", "))
+    };
+    let example = commits.first().map_or("<hash>", String::as_str);
+    format!(
+        "nixpkgs commit(s) {} can no longer be downloaded from GitHub{}. Run 'nixy update --commit {}' to re-resolve them onto a live commit, or add '--pin-missing-to <rev>' to choose one yourself.",
+        commits.join(", "),
+        used_by,
+        example
+    )
+}
+
+fn missing_attribute_message(attribute: &str, packages: &[String]) -> String {
+    if packages.is_empty() {
+        return format!(
+            "Failed to build environment: attribute '{}' missing. See output above for details.",
+            attribute
+        );
+    }
+    format!(
+        "Failed to build environment: attribute '{}' missing, likely from {}, installed without validation. Check the name with 'nix flake show <url>', then reinstall it.",
+        attribute,
+        packages.join(", ")
+    )
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,387 @@
+//! Formatting pass for generated `flake.nix` files.
+//!
+//! Generated flakes are run through a small built-in formatter, so the same
+//! nixy.json gives the same flake.nix on every machine. `nixfmt` or
+//! `alejandra` can be opted into; the built-in formatter stands in when the
+//! tool is missing, fails, or takes longer than [`EXTERNAL_TIMEOUT`]. The
+//! built-in formatter only normalizes indentation: it walks the `rnix` syntax tree and
+//! indents each line one level deeper than the line that opened its innermost
+//! enclosing multi-line node. Token text, line breaks, and the contents of
+//! multi-line strings are left untouched, so formatting is idempotent.
+//!
+//! The behavior is controlled by the `format` setting in `nixy.json`:
+//! - `true` / `"builtin"` (default): always use the built-in formatter
+//! - `"auto"`: use `nixfmt`, then `alejandra`, then the built-in formatter
+//! - `"nixfmt"` / `"alejandra"`: prefer that tool, falling back to the built-in formatter
+//! - `false`: write the flake exactly as generated
+
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rnix::{SyntaxKind, SyntaxNode, SyntaxToken};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Indentation unit used by the built-in formatter
+const INDENT: usize = 2;
+
+/// How long an external formatter may run before the built-in one is used
+pub const EXTERNAL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Formatter preference for generated flakes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FlakeFormat {
+    /// Use the first available external formatter, else the built-in one
+    Auto,
+    /// Prefer `nixfmt`
+    Nixfmt,
+    /// Prefer `alejandra`
+    Alejandra,
+    /// Always use the built-in formatter
+    #[default]
+    Builtin,
+    /// Do not format generated flakes
+    Off,
+}
+
+impl FlakeFormat {
+    /// Whether this is the default setting (omitted from nixy.json)
+    pub fn is_default(&self) -> bool {
+        *self == FlakeFormat::default()
+    }
+}
+
+impl Serialize for FlakeFormat {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            FlakeFormat::Builtin => serializer.serialize_bool(true),
+            FlakeFormat::Off => serializer.serialize_bool(false),
+            FlakeFormat::Auto => serializer.serialize_str("auto"),
+            FlakeFormat::Nixfmt => serializer.serialize_str("nixfmt"),
+            FlakeFormat::Alejandra => serializer.serialize_str("alejandra"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for FlakeFormat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bool(bool),
+            Name(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Bool(true) => Ok(FlakeFormat::Builtin),
+            Raw::Bool(false) => Ok(FlakeFormat::Off),
+            Raw::Name(name) => match name.as_str() {
+                "auto" => Ok(FlakeFormat::Auto),
+                "nixfmt" => Ok(FlakeFormat::Nixfmt),
+                "alejandra" => Ok(FlakeFormat::Alejandra),
+                "builtin" => Ok(FlakeFormat::Builtin),
+                other => Err(serde::de::Error::custom(format!(
+                    "unknown format '{}'. Valid values: true, false, \"builtin\", \"auto\", \"nixfmt\", \"alejandra\"",
+                    other
+                ))),
+            },
+        }
+    }
+}
+
+/// Format generated flake content according to the given preference
+pub fn format_flake(content: &str, format: FlakeFormat) -> String {
+    let external: &[&str] = match format {
+        FlakeFormat::Off => return content.to_string(),
+        FlakeFormat::Builtin => &[],
+        FlakeFormat::Auto => &["nixfmt", "alejandra"],
+        FlakeFormat::Nixfmt => &["nixfmt"],
+        FlakeFormat::Alejandra => &["alejandra"],
+    };
+
+    external
+        .iter()
+        .find_map(|tool| run_external(tool, content, EXTERNAL_TIMEOUT))
+        .unwrap_or_else(|| format_builtin(content))
+}
+
+/// Pipe content through an external formatter, returning None if it is
+/// missing, fails, or is still running after `timeout`
+fn run_external(tool: &str, content: &str, timeout: Duration) -> Option<String> {
+    let args: &[&str] = match tool {
+        "alejandra" => &["--quiet", "-"],
+        _ => &[],
+    };
+
+    let mut child = Command::new(tool)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+
+    // Feed and drain the pipes on their own threads, so a formatter that
+    // stops reading or writing can't outlast the deadline
+    let mut stdin = child.stdin.take()?;
+    let input = content.to_string();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let mut stdout = child.stdout.take()?;
+    let reader = thread::spawn(move || {
+        let mut out = Vec::new();
+        stdout.read_to_end(&mut out).map(|_| out)
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait().ok()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return None;
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    writer.join().ok()?.ok()?;
+    let output = reader.join().ok()?.ok()?;
+
+    if !status.success() || output.is_empty() {
+        return None;
+    }
+    String::from_utf8(output).ok()
+}
+
+/// Normalize indentation using the rnix syntax tree.
+///
+/// Content that fails to parse is returned unchanged.
+pub fn format_builtin(content: &str) -> String {
+    let parse = rnix::Root::parse(content);
+    if !parse.errors().is_empty() {
+        return content.to_string();
+    }
+    let root = parse.syntax();
+
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(content.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let line_of = |offset: usize| line_starts.partition_point(|&s| s <= offset) - 1;
+
+    // Lines that start or end inside a multi-line token (strings, comments)
+    // are content, not layout, and must not be touched.
+    let mut starts_in_token = vec![false; line_starts.len()];
+    let mut ends_in_token = vec![false; line_starts.len()];
+    let mut leading: Vec<Option<SyntaxToken>> = vec![None; line_starts.len()];
+
+    for token in root
+        .descendants_with_tokens()
+        .filter_map(|e| e.into_token())
+    {
+        let start = usize::from(token.text_range().start());
+        let end = usize::from(token.text_range().end());
+
+        if token.kind() != SyntaxKind::TOKEN_WHITESPACE {
+            for (i, _) in token.text().match_indices('\n') {
+                let next_line = line_of(start + i) + 1;
+                ends_in_token[next_line - 1] = true;
+                if start + i + 1 < end {
+                    starts_in_token[next_line] = true;
+                }
+            }
+
+            let line = line_of(start);
+            if leading[line].is_none()
+                && content[line_starts[line]..start]
+                    .chars()
+                    .all(|c| c == ' ' || c == '\t')
+            {
+                leading[line] = Some(token);
+            }
+        }
+    }
+
+    let mut indents: Vec<usize> = Vec::with_capacity(line_starts.len());
+    let mut output = String::with_capacity(content.len());
+
+    for (line, text) in content.split('\n').enumerate() {
+        if line > 0 {
+            output.push('\n');
+        }
+
+        let indent = match &leading[line] {
+            Some(token) if !starts_in_token[line] => {
+                line_indent(token, line_starts[line], &indents, &line_of)
+            }
+            _ => text.len() - text.trim_start_matches([' ', '\t']).len(),
+        };
+        indents.push(indent);
+
+        let text = if ends_in_token[line] {
+            text
+        } else {
+            text.trim_end_matches([' ', '\t'])
+        };
+
+        if starts_in_token[line] {
+            output.push_str(text);
+        } else {
+            let body = text.trim_start_matches([' ', '\t']);
+            if !body.is_empty() {
+                output.push_str(&" ".repeat(indent));
+                output.push_str(body);
+            }
+        }
+    }
+
+    output
+}
+
+/// Indentation for a line whose first token is `token`: one level deeper than
+/// the line that opened the innermost enclosing node spanning multiple lines,
+/// or the same level if `token` closes that node.
+fn line_indent(
+    token: &SyntaxToken,
+    line_start: usize,
+    indents: &[usize],
+    line_of: &impl Fn(usize) -> usize,
+) -> usize {
+    let enclosing = token
+        .parent()
+        .into_iter()
+        .flat_map(|node| node.ancestors())
+        .find(|node| usize::from(node.text_range().start()) < line_start);
+
+    let Some(node) = enclosing else {
+        return 0;
+    };
+
+    let base = indents[line_of(usize::from(node.text_range().start()))];
+    if closes(token, &node) {
+        base
+    } else {
+        base + INDENT
+    }
+}
+
+/// Whether `token` is the closing delimiter of `node`
+fn closes(token: &SyntaxToken, node: &SyntaxNode) -> bool {
+    token.parent().as_ref() == Some(node)
+        && matches!(
+            token.kind(),
+            SyntaxKind::TOKEN_R_BRACE
+                | SyntaxKind::TOKEN_R_BRACK
+                | SyntaxKind::TOKEN_R_PAREN
+                | SyntaxKind::TOKEN_IN
+                | SyntaxKind::TOKEN_INTERPOL_END
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_format_builtin_reindents_blocks() {
+        let input =
+            "{\ninputs = {\n      nixpkgs.url = \"x\";\n };\n  paths = [\n a\n      b\n ];\n}\n";
+        let expected =
+            "{\n  inputs = {\n    nixpkgs.url = \"x\";\n  };\n  paths = [\n    a\n    b\n  ];\n}\n";
+        assert_eq!(format_builtin(input), expected);
+    }
+
+    #[test]
+    fn test_format_builtin_let_in_and_lambdas() {
+        let input = "{\n  outputs = { self }:\n  let\n  x = 1;\n  in {\n  y = x;\n  };\n}\n";
+        let expected =
+            "{\n  outputs = { self }:\n    let\n      x = 1;\n    in {\n      y = x;\n    };\n}\n";
+        assert_eq!(format_builtin(input), expected);
+    }
+
+    #[test]
+    fn test_format_builtin_optionals_chain() {
+        let input = "{\n  paths = [\n    a\n  ] ++ lib.optionals c [\n          b\n  ];\n}\n";
+        let expected = "{\n  paths = [\n    a\n  ] ++ lib.optionals c [\n    b\n  ];\n}\n";
+        assert_eq!(format_builtin(input), expected);
+    }
+
+    #[test]
+    fn test_format_builtin_preserves_multiline_strings() {
+        let input = "{\n      script = ''\n   keep   \n      this\n    '';\n}\n";
+        let expected = "{\n  script = ''\n   keep   \n      this\n    '';\n}\n";
+        assert_eq!(format_builtin(input), expected);
+    }
+
+    #[test]
+    fn test_format_builtin_strips_trailing_whitespace() {
+        assert_eq!(
+            format_builtin("{   \n  a = 1;  \n    \n}\n"),
+            "{\n  a = 1;\n\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_format_builtin_leaves_invalid_nix_unchanged() {
+        let input = "{\n      a = ;\n";
+        assert_eq!(format_builtin(input), input);
+    }
+
+    #[test]
+    fn test_format_builtin_is_idempotent() {
+        let input = "{\n  a = {\n b = [\n c\n ] ++ d [\n e ];\n   };\n}\n";
+        let once = format_builtin(input);
+        assert_eq!(format_builtin(&once), once);
+    }
+
+    #[test]
+    fn test_format_off_returns_input_verbatim() {
+        let input = "{\n      a = 1;\n}\n";
+        assert_eq!(format_flake(input, FlakeFormat::Off), input);
+    }
+
+    #[test]
+    fn test_flake_format_serde_roundtrip() {
+        for (json, format) in [
+            ("true", FlakeFormat::Builtin),
+            ("false", FlakeFormat::Off),
+            ("\"auto\"", FlakeFormat::Auto),
+            ("\"nixfmt\"", FlakeFormat::Nixfmt),
+            ("\"alejandra\"", FlakeFormat::Alejandra),
+        ] {
+            let parsed: FlakeFormat = serde_json::from_str(json).unwrap();
+            assert_eq!(parsed, format);
+            assert_eq!(serde_json::to_string(&format).unwrap(), json);
+        }
+        assert_eq!(
+            serde_json::from_str::<FlakeFormat>("\"builtin\"").unwrap(),
+            FlakeFormat::Builtin
+        );
+        assert_eq!(FlakeFormat::default(), FlakeFormat::Builtin);
+        assert!(serde_json::from_str::<FlakeFormat>("\"prettier\"").is_err());
+    }
+
+    #[test]
+    fn test_run_external_pipes_content_through_tool() {
+        let out = run_external("cat", "{ a = 1; }\n", EXTERNAL_TIMEOUT);
+        assert_eq!(out.as_deref(), Some("{ a = 1; }\n"));
+        assert_eq!(
+            run_external("nixy-no-such-formatter", "{ }\n", EXTERNAL_TIMEOUT),
+            None
+        );
+    }
+
+    #[test]
+    fn test_run_external_gives_up_on_a_hung_tool() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let tool = temp.path().join("hang");
+        std::fs::write(&tool, "#!/bin/sh\nexec sleep 30\n").unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let started = Instant::now();
+        let out = run_external(tool.to_str().unwrap(), "{ }\n", Duration::from_millis(200));
+        assert_eq!(out, None);
+        assert!(started.elapsed() < Duration::from_secs(20));
+    }
+}
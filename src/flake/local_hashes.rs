@@ -0,0 +1,154 @@
+//! Content hashes of the local package definitions in `packages/`.
+//!
+//! The generated flake refers to a local package by path, so editing its
+//! file leaves flake.nix as it was. nixy.json records a hash of each
+//! definition (`<name>.nix`, or `<name>/flake.nix` for a local flake) as of
+//! the last sync, which lets `nixy sync` say which definitions changed and
+//! refuse when one it built before has disappeared.
+//!
+//! The hash is 64-bit FNV-1a: stable across Rust releases and platforms,
+//! which is all a change check needs.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Hash of `content`, as 16 hex digits
+pub fn content_hash(content: &[u8]) -> String {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    let hash = content.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+    });
+    format!("{:016x}", hash)
+}
+
+/// Hashes of the local package definitions in `packages_dir`, by path
+/// relative to it; symlinks are followed and dangling ones left out
+pub fn local_package_hashes(packages_dir: &Path) -> BTreeMap<String, String> {
+    let mut hashes = BTreeMap::new();
+    let Ok(entries) = fs::read_dir(packages_dir) else {
+        return hashes;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let (key, file) = if path.is_dir() {
+            (format!("{}/flake.nix", name), path.join("flake.nix"))
+        } else if name.ends_with(".nix") {
+            (name.to_string(), path.clone())
+        } else {
+            continue;
+        };
+        if let Ok(content) = fs::read(&file) {
+            hashes.insert(key, content_hash(&content));
+        }
+    }
+    hashes
+}
+
+/// How the local package definitions differ from the recorded hashes
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct LocalChanges {
+    /// Definitions whose content changed
+    pub changed: Vec<String>,
+    /// Definitions that were recorded but are gone
+    pub removed: Vec<String>,
+    /// Definitions that were not recorded yet
+    pub added: Vec<String>,
+}
+
+impl LocalChanges {
+    /// Compare `current` hashes with `recorded` ones
+    pub fn between(
+        recorded: &BTreeMap<String, String>,
+        current: &BTreeMap<String, String>,
+    ) -> Self {
+        let mut changes = Self::default();
+        for (path, hash) in current {
+            match recorded.get(path) {
+                Some(old) if old != hash => changes.changed.push(path.clone()),
+                Some(_) => {}
+                None => changes.added.push(path.clone()),
+            }
+        }
+        changes.removed = recorded
+            .keys()
+            .filter(|path| !current.contains_key(*path))
+            .cloned()
+            .collect();
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.removed.is_empty() && self.added.is_empty()
+    }
+}
+
+/// The package a definition path in `packages/` belongs to
+pub fn package_name(path: &str) -> &str {
+    path.strip_suffix("/flake.nix")
+        .or_else(|| path.strip_suffix(".nix"))
+        .unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_content_hash_is_fnv1a() {
+        assert_eq!(content_hash(b""), "cbf29ce484222325");
+        assert_eq!(content_hash(b"a"), "af63dc4c8601ec8c");
+        assert_ne!(
+            content_hash(b"{ hello }: hello"),
+            content_hash(b"{ hello }: hello ")
+        );
+    }
+
+    #[test]
+    fn test_local_package_hashes() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("mine.nix"), "{ hello }: hello").unwrap();
+        fs::write(dir.path().join("README.md"), "not a package").unwrap();
+        fs::create_dir_all(dir.path().join("tool")).unwrap();
+        fs::write(dir.path().join("tool/flake.nix"), "{ }").unwrap();
+        fs::create_dir_all(dir.path().join("empty")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("gone.nix"), dir.path().join("gone.nix"))
+            .unwrap();
+
+        let hashes = local_package_hashes(dir.path());
+        let keys: Vec<&String> = hashes.keys().collect();
+        assert_eq!(keys, vec!["mine.nix", "tool/flake.nix"]);
+        assert_eq!(hashes["tool/flake.nix"], content_hash(b"{ }"));
+        assert!(local_package_hashes(&dir.path().join("missing")).is_empty());
+    }
+
+    #[test]
+    fn test_changes_between() {
+        let recorded: BTreeMap<String, String> = [
+            ("kept.nix", "1"),
+            ("edited.nix", "2"),
+            ("gone/flake.nix", "3"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let current: BTreeMap<String, String> =
+            [("kept.nix", "1"), ("edited.nix", "9"), ("new.nix", "4")]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+
+        let changes = LocalChanges::between(&recorded, &current);
+        assert_eq!(changes.changed, vec!["edited.nix"]);
+        assert_eq!(changes.removed, vec!["gone/flake.nix"]);
+        assert_eq!(changes.added, vec!["new.nix"]);
+        assert!(LocalChanges::between(&current, &current).is_empty());
+        assert_eq!(package_name("gone/flake.nix"), "gone");
+        assert_eq!(package_name("edited.nix"), "edited");
+    }
+}
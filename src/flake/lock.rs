@@ -0,0 +1,453 @@
+//! Seeding a profile's `flake.lock` from another profile.
+//!
+//! A profile without a lock resolves its floating inputs (`nixpkgs` and any
+//! flake URLs without a pinned revision) fresh on the next build. Copying the
+//! lock of a profile that tracks the same channel keeps the versions that
+//! profile already uses; nix then adds or drops inputs that differ between
+//! the two flakes on the first build.
+//!
+//! Pruning drops the lock entries of inputs a regenerated flake.nix no
+//! longer declares, which a frozen build would otherwise refuse.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+
+/// `<name>.url = "<url>";` as written in the `inputs` of a generated flake
+static INPUT_URL_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?m)^\s*([A-Za-z0-9_-]+)\.url\s*=\s*"([^"]*)"\s*;"#)
+        .expect("Invalid regex pattern")
+});
+
+/// Input that decides which nixpkgs channel floating packages come from
+const CHANNEL_INPUT: &str = "nixpkgs";
+
+/// Input URLs declared in the `inputs` of a flake.nix
+pub fn declared_inputs(flake_content: &str) -> BTreeMap<String, String> {
+    INPUT_URL_REGEX
+        .captures_iter(flake_content)
+        .map(|c| (c[1].to_string(), normalize_url(&c[2])))
+        .collect()
+}
+
+/// The original (unlocked) URL of each root input in a flake.lock
+///
+/// Returns `None` when the lock is not valid JSON or has no root node.
+pub fn locked_inputs(lock_content: &str) -> Option<BTreeMap<String, String>> {
+    let lock: Value = serde_json::from_str(lock_content).ok()?;
+    let nodes = lock.get("nodes")?.as_object()?;
+    let root = nodes.get(lock.get("root")?.as_str()?)?;
+    let inputs = root.get("inputs")?.as_object()?;
+
+    Some(
+        inputs
+            .iter()
+            .filter_map(|(name, node)| {
+                // Follows (`["a", "b"]`) point into other inputs and have no
+                // source of their own
+                let node = nodes.get(node.as_str()?)?;
+                Some((name.clone(), original_url(node.get("original")?)?))
+            })
+            .collect(),
+    )
+}
+
+/// The revision each root input of a flake.lock is locked to
+///
+/// Inputs locked without a revision (e.g. tarballs) report their narHash.
+/// Returns `None` when the lock is not valid JSON or has no root node.
+pub fn locked_revs(lock_content: &str) -> Option<BTreeMap<String, String>> {
+    let lock: Value = serde_json::from_str(lock_content).ok()?;
+    let nodes = lock.get("nodes")?.as_object()?;
+    let root = nodes.get(lock.get("root")?.as_str()?)?;
+    let inputs = root.get("inputs")?.as_object()?;
+
+    Some(
+        inputs
+            .iter()
+            .filter_map(|(name, node)| {
+                let locked = nodes.get(node.as_str()?)?.get("locked")?;
+                let rev = locked.get("rev").or_else(|| locked.get("narHash"))?;
+                Some((name.clone(), rev.as_str()?.to_string()))
+            })
+            .collect(),
+    )
+}
+
+/// The nixpkgs commit a flake.lock pins the channel input to
+pub fn channel_rev(lock_content: &str) -> Option<String> {
+    let lock: Value = serde_json::from_str(lock_content).ok()?;
+    let nodes = lock.get("nodes")?.as_object()?;
+    let root = nodes.get(lock.get("root")?.as_str()?)?;
+    let node = nodes.get(root.get("inputs")?.get(CHANNEL_INPUT)?.as_str()?)?;
+    Some(node.get("locked")?.get("rev")?.as_str()?.to_string())
+}
+
+/// Check that the lock in `source_dir` fits the flake in `target_dir`, then
+/// copy it over.
+///
+/// Refuses when the two track different nixpkgs channels, or when an input
+/// both declare points somewhere else in each: the copied pins would then
+/// not be the versions the user asked for.
+pub fn seed_lock(source_dir: &Path, target_dir: &Path) -> Result<()> {
+    let source_lock = source_dir.join("flake.lock");
+    if !source_lock.exists() {
+        return Err(Error::NoFlakeLock);
+    }
+    let lock_content = fs::read_to_string(&source_lock)?;
+    let locked = locked_inputs(&lock_content).ok_or(Error::InvalidFlakeLock)?;
+    let declared = declared_inputs(&fs::read_to_string(target_dir.join("flake.nix"))?);
+
+    check_compatible(&locked, &declared).map_err(|reason| Error::IncompatibleLock {
+        path: source_lock.clone(),
+        reason,
+    })?;
+
+    fs::write(target_dir.join("flake.lock"), lock_content)?;
+    Ok(())
+}
+
+/// Root inputs of a flake.lock that `flake_content` no longer declares
+///
+/// Returns nothing when the lock is not valid JSON.
+pub fn stale_lock_inputs(flake_content: &str, lock_content: &str) -> Vec<String> {
+    let declared = declared_inputs(flake_content);
+    locked_revs(lock_content)
+        .unwrap_or_default()
+        .into_keys()
+        .filter(|name| !declared.contains_key(name))
+        .collect()
+}
+
+/// Remove the inputs flake.nix in `flake_dir` no longer declares from its
+/// flake.lock, along with the nodes only they used; the removed input names
+pub fn prune_lock(flake_dir: &Path) -> Result<Vec<String>> {
+    let lock_path = flake_dir.join("flake.lock");
+    let lock_content = fs::read_to_string(&lock_path)?;
+    let stale = stale_lock_inputs(
+        &fs::read_to_string(flake_dir.join("flake.nix"))?,
+        &lock_content,
+    );
+    if stale.is_empty() {
+        return Ok(stale);
+    }
+
+    let mut lock: Value =
+        serde_json::from_str(&lock_content).map_err(|_| Error::InvalidFlakeLock)?;
+    let root = lock
+        .get("root")
+        .and_then(Value::as_str)
+        .ok_or(Error::InvalidFlakeLock)?
+        .to_string();
+    let nodes = lock
+        .get_mut("nodes")
+        .and_then(Value::as_object_mut)
+        .ok_or(Error::InvalidFlakeLock)?;
+    if let Some(inputs) = nodes
+        .get_mut(&root)
+        .and_then(|node| node.get_mut("inputs"))
+        .and_then(Value::as_object_mut)
+    {
+        for name in &stale {
+            inputs.remove(name);
+        }
+    }
+
+    // Keep only nodes still reachable from the root; follows (`["a", "b"]`)
+    // point at inputs of other nodes and keep nothing alive themselves
+    let mut reachable = BTreeSet::new();
+    let mut pending = vec![root];
+    while let Some(key) = pending.pop() {
+        if !reachable.insert(key.clone()) {
+            continue;
+        }
+        if let Some(inputs) = nodes
+            .get(&key)
+            .and_then(|node| node.get("inputs"))
+            .and_then(Value::as_object)
+        {
+            pending.extend(inputs.values().filter_map(Value::as_str).map(String::from));
+        }
+    }
+    nodes.retain(|key, _| reachable.contains(key));
+
+    let mut content = serde_json::to_string_pretty(&lock).map_err(|_| Error::InvalidFlakeLock)?;
+    content.push('\n');
+    fs::write(&lock_path, content)?;
+    Ok(stale)
+}
+
+fn check_compatible(
+    locked: &BTreeMap<String, String>,
+    declared: &BTreeMap<String, String>,
+) -> std::result::Result<(), String> {
+    match (locked.get(CHANNEL_INPUT), declared.get(CHANNEL_INPUT)) {
+        (Some(from), Some(to)) if from != to => {
+            return Err(format!("channels differ ({} vs {})", from, to));
+        }
+        (None, _) => return Err(format!("it does not lock '{}'", CHANNEL_INPUT)),
+        _ => {}
+    }
+
+    let conflicts: Vec<String> = declared
+        .iter()
+        .filter_map(|(name, to)| {
+            let from = locked.get(name)?;
+            (from != to).then(|| format!("'{}' is {} there but {} here", name, from, to))
+        })
+        .collect();
+    if !conflicts.is_empty() {
+        return Err(format!("inputs differ: {}", conflicts.join(", ")));
+    }
+    Ok(())
+}
+
+/// Rebuild a flake URL from a lock node's `original` attributes, in the form
+/// a generated flake.nix writes it
+fn original_url(original: &Value) -> Option<String> {
+    let attr = |key: &str| original.get(key).and_then(Value::as_str);
+    let kind = attr("type")?;
+
+    let mut url = match kind {
+        "github" | "gitlab" | "sourcehut" => {
+            let mut url = format!("{}:{}/{}", kind, attr("owner")?, attr("repo")?);
+            if let Some(reference) = attr("ref").or_else(|| attr("rev")) {
+                url.push('/');
+                url.push_str(reference);
+            }
+            url
+        }
+        "path" => format!("path:{}", attr("path")?),
+        "git" | "hg" => format!("{}+{}", kind, attr("url")?),
+        "tarball" | "file" => attr("url")?.to_string(),
+        "indirect" => format!("flake:{}", attr("id")?),
+        _ => return None,
+    };
+
+    // `dir` picks another flake out of the same source, so it is part of
+    // which input this is
+    if let Some(dir) = attr("dir") {
+        url.push_str(&format!("?dir={}", dir));
+    }
+    Some(url)
+}
+
+/// Bring a declared URL into the shape [`original_url`] produces
+fn normalize_url(url: &str) -> String {
+    let url = url.trim_end_matches('/');
+    if url.contains(':') {
+        url.to_string()
+    } else {
+        // Bare registry names like `nixpkgs`
+        format!("flake:{}", url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const UNSTABLE_LOCK: &str = include_str!("../../tests/fixtures/locks/unstable.json");
+    const STABLE_LOCK: &str = include_str!("../../tests/fixtures/locks/stable.json");
+
+    const UNSTABLE_FLAKE: &str = r#"{
+  inputs = {
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+    nixpkgs-a1b2c3d.url = "github:NixOS/nixpkgs/a1b2c3d4e5f6";
+  };
+}
+"#;
+
+    fn profile_dir(flake: &str, lock: Option<&str>) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("flake.nix"), flake).unwrap();
+        if let Some(lock) = lock {
+            fs::write(dir.path().join("flake.lock"), lock).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_locked_inputs_reads_original_urls() {
+        let inputs = locked_inputs(UNSTABLE_LOCK).unwrap();
+        assert_eq!(
+            inputs.get("nixpkgs").map(String::as_str),
+            Some("github:NixOS/nixpkgs/nixos-unstable")
+        );
+        assert_eq!(
+            inputs.get("nixpkgs-a1b2c3d").map(String::as_str),
+            Some("github:NixOS/nixpkgs/a1b2c3d4e5f6")
+        );
+        assert_eq!(
+            inputs.get("mytool").map(String::as_str),
+            Some("github:owner/mytool")
+        );
+    }
+
+    #[test]
+    fn test_channel_rev() {
+        assert_eq!(
+            channel_rev(UNSTABLE_LOCK).as_deref(),
+            Some("9f4128e00b0ae8ec65918efeba59db998750ead6")
+        );
+        assert_eq!(channel_rev("not json"), None);
+    }
+
+    #[test]
+    fn test_locked_revs() {
+        let revs = locked_revs(UNSTABLE_LOCK).unwrap();
+        assert_eq!(
+            revs.get("nixpkgs").map(String::as_str),
+            Some("9f4128e00b0ae8ec65918efeba59db998750ead6")
+        );
+        assert_eq!(revs.len(), 3);
+
+        let tarball = r#"{"nodes": {"root": {"inputs": {"t": "t"}},
+            "t": {"locked": {"type": "tarball", "narHash": "sha256-abc"}}}, "root": "root"}"#;
+        assert_eq!(
+            locked_revs(tarball).unwrap().get("t").map(String::as_str),
+            Some("sha256-abc")
+        );
+    }
+
+    #[test]
+    fn test_locked_inputs_rejects_garbage() {
+        assert!(locked_inputs("not json").is_none());
+        assert!(locked_inputs(r#"{"nodes": {}}"#).is_none());
+    }
+
+    #[test]
+    fn test_declared_inputs_normalizes_registry_names() {
+        let inputs = declared_inputs("inputs = {\n  nixpkgs.url = \"nixpkgs\";\n};\n");
+        assert_eq!(
+            inputs.get("nixpkgs").map(String::as_str),
+            Some("flake:nixpkgs")
+        );
+    }
+
+    #[test]
+    fn test_seed_lock_copies_when_channels_match() {
+        let source = profile_dir(UNSTABLE_FLAKE, Some(UNSTABLE_LOCK));
+        let target = profile_dir(UNSTABLE_FLAKE, None);
+
+        seed_lock(source.path(), target.path()).unwrap();
+
+        let copied = fs::read_to_string(target.path().join("flake.lock")).unwrap();
+        assert_eq!(copied, UNSTABLE_LOCK);
+    }
+
+    #[test]
+    fn test_seed_lock_allows_inputs_only_on_one_side() {
+        // The target has a package the source doesn't (and vice versa for
+        // `mytool`); nix locks or drops those on the next build
+        let flake = r#"
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+    other.url = "github:owner/other";
+"#;
+        let source = profile_dir(UNSTABLE_FLAKE, Some(UNSTABLE_LOCK));
+        let target = profile_dir(flake, None);
+
+        assert!(seed_lock(source.path(), target.path()).is_ok());
+    }
+
+    #[test]
+    fn test_seed_lock_refuses_different_channels() {
+        let source = profile_dir(UNSTABLE_FLAKE, Some(STABLE_LOCK));
+        let target = profile_dir(UNSTABLE_FLAKE, None);
+
+        let err = seed_lock(source.path(), target.path()).unwrap_err();
+        assert!(
+            err.to_string().contains("channels differ"),
+            "unexpected error: {}",
+            err
+        );
+        assert!(!target.path().join("flake.lock").exists());
+    }
+
+    #[test]
+    fn test_seed_lock_refuses_conflicting_input() {
+        let flake = r#"
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+    mytool.url = "github:someone-else/mytool";
+"#;
+        let source = profile_dir(UNSTABLE_FLAKE, Some(UNSTABLE_LOCK));
+        let target = profile_dir(flake, None);
+
+        let err = seed_lock(source.path(), target.path()).unwrap_err();
+        assert!(
+            err.to_string().contains("'mytool'"),
+            "unexpected error: {}",
+            err
+        );
+        assert!(!target.path().join("flake.lock").exists());
+    }
+
+    #[test]
+    fn test_seed_lock_requires_source_lock() {
+        let source = profile_dir(UNSTABLE_FLAKE, None);
+        let target = profile_dir(UNSTABLE_FLAKE, None);
+
+        assert!(matches!(
+            seed_lock(source.path(), target.path()),
+            Err(Error::NoFlakeLock)
+        ));
+    }
+
+    #[test]
+    fn test_seed_lock_rejects_corrupt_source_lock() {
+        let source = profile_dir(UNSTABLE_FLAKE, Some("{"));
+        let target = profile_dir(UNSTABLE_FLAKE, None);
+
+        assert!(matches!(
+            seed_lock(source.path(), target.path()),
+            Err(Error::InvalidFlakeLock)
+        ));
+    }
+
+    #[test]
+    fn test_stale_lock_inputs() {
+        assert_eq!(
+            stale_lock_inputs(UNSTABLE_FLAKE, UNSTABLE_LOCK),
+            vec!["mytool"]
+        );
+        assert!(stale_lock_inputs(UNSTABLE_FLAKE, "not json").is_empty());
+    }
+
+    #[test]
+    fn test_prune_lock_drops_undeclared_inputs_and_their_nodes() {
+        let flake = r#"{
+  inputs = {
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  };
+}
+"#;
+        let dir = profile_dir(flake, Some(UNSTABLE_LOCK));
+
+        assert_eq!(
+            prune_lock(dir.path()).unwrap(),
+            vec!["mytool", "nixpkgs-a1b2c3d"]
+        );
+        let lock = fs::read_to_string(dir.path().join("flake.lock")).unwrap();
+        let nodes: Value = serde_json::from_str(&lock).unwrap();
+        let keys: Vec<&String> = nodes["nodes"].as_object().unwrap().keys().collect();
+        assert_eq!(keys, vec!["nixpkgs", "root"]);
+        assert_eq!(
+            channel_rev(&lock).as_deref(),
+            Some("9f4128e00b0ae8ec65918efeba59db998750ead6")
+        );
+
+        // Nothing left to prune
+        assert!(prune_lock(dir.path()).unwrap().is_empty());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("flake.lock")).unwrap(),
+            lock
+        );
+    }
+}
@@ -3,10 +3,18 @@
 //! This module provides functionality for parsing and generating Nix flake files.
 //!
 //! Submodules:
+//! - `format`: Formatting pass applied to generated `flake.nix` files
+//! - `local_hashes`: Content hashes of local package definitions
+//! - `lock`: Seeding a profile's `flake.lock` from another profile
 //! - `parser`: AST-based parsing of Nix files using the `rnix` library
+//! - `preserve`: Hand-written blocks kept when `flake.nix` is regenerated
 //! - `template`: Generation of `flake.nix` content from package state
 
+pub mod format;
+pub mod local_hashes;
+pub mod lock;
 pub mod parser;
+pub mod preserve;
 pub mod template;
 
 /// Local package information parsed from .nix files
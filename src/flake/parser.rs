@@ -7,6 +7,7 @@
 //! - Nested attribute sets
 //! - String interpolation detection (returns None for dynamic values)
 
+use std::collections::BTreeMap;
 use std::path::Path;
 
 use rnix::SyntaxKind;
@@ -268,6 +269,121 @@ fn find_url_value(node: &rnix::SyntaxNode) -> Option<String> {
     None
 }
 
+/// Packages defined by a generated flake.nix
+#[derive(Debug, Default)]
+pub struct GeneratedPackages {
+    /// Package bindings in the `packages` set (name -> expression), excluding `default`
+    pub bindings: BTreeMap<String, String>,
+    /// Names listed in the buildEnv `paths`, including platform-conditional ones
+    pub paths: Vec<String>,
+}
+
+/// Parse the package bindings and buildEnv paths out of a flake generated by nixy.
+///
+/// Returns None if the content has syntax errors or has no buildEnv `paths`.
+pub fn parse_generated_packages(content: &str) -> Option<GeneratedPackages> {
+    let parse = rnix::Root::parse(content);
+    if !parse.errors().is_empty() {
+        return None;
+    }
+
+    let root = parse.tree();
+    let paths_binding = root
+        .syntax()
+        .descendants()
+        .filter_map(rnix::ast::AttrpathValue::cast)
+        .find(|av| simple_attr_name(av).as_deref() == Some("paths"))?;
+
+    let mut generated = GeneratedPackages::default();
+
+    // Plain identifiers inside the list literals: `[ a b ] ++ optionals (...) [ c ]`,
+    // or `self.packages.${system}."a.b"` for names that aren't identifiers, and
+    // the names of `builtins.attrValues { inherit d e; }`
+    let paths_value = paths_binding.value()?;
+    for node in paths_value.syntax().descendants() {
+        if let Some(inherit) = rnix::ast::Inherit::cast(node.clone()) {
+            if inherit.from().is_none() {
+                generated
+                    .paths
+                    .extend(inherit.attrs().filter_map(|attr| attr_name(&attr)));
+            }
+            continue;
+        }
+        if node.kind() != SyntaxKind::NODE_LIST {
+            continue;
+        }
+        for item in node.children() {
+            if let Some(ident) = rnix::ast::Ident::cast(item.clone()) {
+                if let Some(token) = ident.ident_token() {
+                    generated.paths.push(token.text().to_string());
+                }
+            } else if let Some(select) = rnix::ast::Select::cast(item) {
+                let is_own_package = select
+                    .expr()
+                    .is_some_and(|expr| expr.syntax().text() == "self");
+                let last = select.attrpath().and_then(|path| path.attrs().last());
+                if let (true, Some(name)) = (is_own_package, last.as_ref().and_then(attr_name)) {
+                    generated.paths.push(name);
+                }
+            }
+        }
+    }
+
+    // The packages set is the one holding `default = pkgs.buildEnv { paths = ...; }`
+    let packages_set = paths_binding
+        .syntax()
+        .ancestors()
+        .filter_map(rnix::ast::AttrpathValue::cast)
+        .find(|av| simple_attr_name(av).as_deref() == Some("default"))?
+        .syntax()
+        .parent()?;
+
+    for child in packages_set.children() {
+        if let Some(av) = rnix::ast::AttrpathValue::cast(child.clone()) {
+            if let (Some(name), Some(value)) = (simple_attr_name(&av), av.value()) {
+                if name != "default" {
+                    generated
+                        .bindings
+                        .insert(name, value.syntax().text().to_string());
+                }
+            }
+        } else if let Some(inherit) = rnix::ast::Inherit::cast(child) {
+            // `inherit (source) a b;` binds each name to `source.<name>`
+            let Some(source) = inherit.from().and_then(|from| from.expr()) else {
+                continue;
+            };
+            let source = source.syntax().text().to_string();
+            for attr in inherit.attrs() {
+                if let Some(name) = attr_name(&attr) {
+                    let expr = format!("{}.{}", source, attr.syntax().text());
+                    generated.bindings.insert(name, expr);
+                }
+            }
+        }
+    }
+
+    Some(generated)
+}
+
+/// The name of a binding whose attrpath is a single identifier or string
+/// (`name = ...;`, `"name.with.dots" = ...;`)
+fn simple_attr_name(av: &rnix::ast::AttrpathValue) -> Option<String> {
+    let attrs: Vec<_> = av.attrpath()?.attrs().collect();
+    match attrs.as_slice() {
+        [attr] => attr_name(attr),
+        _ => None,
+    }
+}
+
+/// The name an identifier or string attribute stands for
+fn attr_name(attr: &rnix::ast::Attr) -> Option<String> {
+    match attr {
+        rnix::ast::Attr::Ident(ident) => Some(ident.ident_token()?.text().to_string()),
+        rnix::ast::Attr::Str(s) => extract_string_value(s),
+        rnix::ast::Attr::Dynamic(_) => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -616,4 +732,65 @@ pkgs.stdenv.mkDerivation {
             Some("github:user/first".to_string())
         );
     }
+
+    #[test]
+    fn test_parse_generated_packages() {
+        let content = r#"{
+  outputs = { self, nixpkgs }@inputs: {
+    packages = forAllSystems (system:
+      let pkgs = nixpkgs.legacyPackages.${system};
+      in rec {
+        hello = pkgs.hello;
+        neovim = inputs.neovim-nightly.packages.${system}.neovim;
+        default = pkgs.buildEnv {
+          name = "nixy-env";
+          paths = [
+            hello
+          ] ++ pkgs.lib.optionals (builtins.elem system [ "x86_64-linux" ]) [
+            neovim
+          ];
+        };
+      });
+  };
+}"#;
+        let generated = parse_generated_packages(content).unwrap();
+        assert_eq!(generated.paths, vec!["hello", "neovim"]);
+        assert_eq!(generated.bindings.len(), 2);
+        assert_eq!(generated.bindings["hello"], "pkgs.hello");
+        assert_eq!(
+            generated.bindings["neovim"],
+            "inputs.neovim-nightly.packages.${system}.neovim"
+        );
+    }
+
+    #[test]
+    fn test_parse_generated_packages_inherit() {
+        let content = r#"{
+  outputs = { self, nixpkgs }@inputs: {
+    packages = forAllSystems (system:
+      let pkgs = nixpkgs.legacyPackages.${system};
+      in rec {
+        inherit (pkgs)
+          bat
+          fzf;
+        default = pkgs.buildEnv {
+          paths = [
+            bat
+            fzf
+          ];
+        };
+      });
+  };
+}"#;
+        let generated = parse_generated_packages(content).unwrap();
+        assert_eq!(generated.paths, vec!["bat", "fzf"]);
+        assert_eq!(generated.bindings["bat"], "pkgs.bat");
+        assert_eq!(generated.bindings["fzf"], "pkgs.fzf");
+    }
+
+    #[test]
+    fn test_parse_generated_packages_invalid() {
+        assert!(parse_generated_packages("{ paths = [ ").is_none());
+        assert!(parse_generated_packages("{ a = 1; }").is_none());
+    }
 }
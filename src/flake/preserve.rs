@@ -0,0 +1,182 @@
+//! Hand-written blocks kept across regenerations of a profile's flake.nix.
+//!
+//! nixy rewrites flake.nix from nixy.json on every change, so edits to it are
+//! normally lost. Lines between `# nixy:user-begin` and `# nixy:user-end` are
+//! the exception: they are read from the old flake.nix and written back into
+//! the new one, in the place the begin marker names:
+//!
+//! - `inputs`: inside `inputs = { ... }`, e.g. an extra flake input (reach it
+//!   through `inputs.<name>` in the other blocks)
+//! - `packages` (the default): inside the package set, e.g. an overridden
+//!   package bound to a new name
+//! - `paths`: inside the `paths` of the environment, to install such a package
+
+use crate::error::{Error, Result};
+
+/// Opens a user block; an optional slot name follows
+pub const USER_BEGIN: &str = "# nixy:user-begin";
+
+/// Closes a user block
+pub const USER_END: &str = "# nixy:user-end";
+
+/// Where in the generated flake a user block goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    Inputs,
+    Packages,
+    Paths,
+}
+
+impl Slot {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "inputs" => Some(Self::Inputs),
+            "" | "packages" => Some(Self::Packages),
+            "paths" => Some(Self::Paths),
+            _ => None,
+        }
+    }
+}
+
+/// The user blocks of a flake.nix, markers included, in file order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PreservedContent {
+    blocks: Vec<(Slot, String)>,
+}
+
+impl PreservedContent {
+    /// Collect the user blocks of `content`
+    ///
+    /// Fails on a begin marker that is never closed, an end marker without a
+    /// begin, nested blocks and unknown slot names, rather than silently
+    /// dropping what the markers were meant to keep.
+    pub fn extract(content: &str) -> Result<Self> {
+        let mut blocks = Vec::new();
+        let mut open: Option<(usize, Slot, String)> = None;
+
+        for (i, line) in content.lines().enumerate() {
+            let number = i + 1;
+            let trimmed = line.trim();
+            if let Some(rest) = trimmed.strip_prefix(USER_BEGIN) {
+                if let Some((begin, ..)) = &open {
+                    return Err(Error::UserBlock(format!(
+                        "'{}' on line {} is inside the block opened on line {}",
+                        USER_BEGIN, number, begin
+                    )));
+                }
+                let name = rest.trim();
+                let slot = Slot::parse(name).ok_or_else(|| {
+                    Error::UserBlock(format!(
+                        "unknown place '{}' on line {} (use inputs, packages or paths)",
+                        name, number
+                    ))
+                })?;
+                open = Some((number, slot, format!("{}\n", line)));
+            } else if trimmed.starts_with(USER_END) {
+                let Some((_, slot, mut block)) = open.take() else {
+                    return Err(Error::UserBlock(format!(
+                        "'{}' on line {} has no '{}' before it",
+                        USER_END, number, USER_BEGIN
+                    )));
+                };
+                block.push_str(line);
+                block.push('\n');
+                blocks.push((slot, block));
+            } else if let Some((.., block)) = &mut open {
+                block.push_str(line);
+                block.push('\n');
+            }
+        }
+
+        if let Some((begin, ..)) = open {
+            return Err(Error::UserBlock(format!(
+                "'{}' on line {} is never closed with '{}'",
+                USER_BEGIN, begin, USER_END
+            )));
+        }
+        Ok(Self { blocks })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// The blocks for `slot`, one after another
+    pub fn slot(&self, slot: Slot) -> String {
+        self.blocks
+            .iter()
+            .filter(|(s, _)| *s == slot)
+            .map(|(_, block)| block.as_str())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_keeps_blocks_by_slot() {
+        let content = r#"{
+  inputs = {
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+    # nixy:user-begin inputs
+    mine.url = "github:me/mine";
+    # nixy:user-end
+  };
+          # nixy:user-begin
+          myhello = pkgs.hello.overrideAttrs (_: { doCheck = false; });
+          # nixy:user-end
+              # nixy:user-begin paths
+              myhello
+              # nixy:user-end
+}
+"#;
+        let preserved = PreservedContent::extract(content).unwrap();
+        assert_eq!(
+            preserved.slot(Slot::Inputs),
+            "    # nixy:user-begin inputs\n    mine.url = \"github:me/mine\";\n    # nixy:user-end\n"
+        );
+        assert!(preserved
+            .slot(Slot::Packages)
+            .contains("myhello = pkgs.hello.overrideAttrs"));
+        assert_eq!(
+            preserved.slot(Slot::Paths),
+            "              # nixy:user-begin paths\n              myhello\n              # nixy:user-end\n"
+        );
+    }
+
+    #[test]
+    fn test_extract_without_blocks() {
+        let preserved = PreservedContent::extract("{ }\n").unwrap();
+        assert!(preserved.is_empty());
+        assert_eq!(preserved.slot(Slot::Packages), "");
+    }
+
+    #[test]
+    fn test_extract_rejects_unbalanced_markers() {
+        let unclosed = "# nixy:user-begin\nfoo = 1;\n";
+        let err = PreservedContent::extract(unclosed).unwrap_err().to_string();
+        assert!(err.contains("line 1 is never closed"), "{}", err);
+
+        let stray = "foo = 1;\n# nixy:user-end\n";
+        let err = PreservedContent::extract(stray).unwrap_err().to_string();
+        assert!(err.contains("line 2 has no"), "{}", err);
+
+        let nested = "# nixy:user-begin\n# nixy:user-begin paths\n# nixy:user-end\n";
+        let err = PreservedContent::extract(nested).unwrap_err().to_string();
+        assert!(
+            err.contains("line 2 is inside the block opened on line 1"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_extract_rejects_unknown_slots() {
+        let err = PreservedContent::extract("# nixy:user-begin overlays\n# nixy:user-end\n")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("unknown place 'overlays'"), "{}", err);
+    }
+}
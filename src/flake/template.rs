@@ -9,14 +9,34 @@
 //!
 //! The generated flake uses `buildEnv` to create a unified environment with
 //! all installed packages.
+//!
+//! Local paths that no longer exist (see [`missing_local_packages`]) are left
+//! out of profile flakes so the rest of the environment still builds.
+//!
+//! Each nixpkgs commit used by resolved packages gets one `nixpkgs-<commit>`
+//! input; [`orphaned_nixpkgs_inputs`] finds those no package refers to.
+//!
+//! In the buildEnv `paths`, many packages of one input are listed as one
+//! `builtins.attrValues { inherit ...; }` instead of a line each.
+//!
+//! Package names that aren't Nix identifiers, such as `python3.11`, are bound
+//! as quoted attributes (see [`nix_attr_name`]).
+//!
+//! Blocks between `# nixy:user-begin` and `# nixy:user-end` in the previous
+//! flake.nix are written back into the new one (see [`super::preserve`]).
 
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use super::format::{format_flake, FlakeFormat};
+use super::lock::declared_inputs;
 use super::parser::collect_local_packages;
+use super::preserve::{PreservedContent, Slot};
 use super::{LocalFlake, LocalPackage};
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::nixy_config::ProfileConfig;
 use crate::state::{CustomPackage, PackageState, ResolvedNixpkgPackage};
 
@@ -26,10 +46,66 @@ struct PathEntry {
     name: String,
     /// Platform restrictions (None means all platforms)
     platforms: Option<Vec<String>>,
+    /// Packages bound from one input under their own names share a group,
+    /// which can go into `paths` as a single `builtins.attrValues`
+    group: Option<usize>,
+}
+
+impl PathEntry {
+    fn new(name: &str, platforms: Option<Vec<String>>) -> Self {
+        Self {
+            name: name.to_string(),
+            platforms,
+            group: None,
+        }
+    }
+}
+
+/// `attribute` with its `meta.outputsToInstall` replaced by `outputs`, which
+/// buildEnv links for it (along with `extraOutputsToInstall`)
+fn with_outputs_to_install(attribute: &str, outputs: Option<&[String]>) -> String {
+    let Some(outputs) = outputs else {
+        return attribute.to_string();
+    };
+    let outputs = outputs
+        .iter()
+        .map(|o| format!("\"{}\" ", o))
+        .collect::<String>();
+    format!(
+        "let p = {}; in p // {{ meta = (p.meta or {{ }}) // {{ outputsToInstall = [ {}]; }}; }}",
+        attribute, outputs
+    )
+}
+
+/// A package whose local source path is gone, e.g. after copying nixy.json
+/// to a new machine without the `packages/` directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingLocalPackage {
+    /// Package name as it appears in the profile or `packages/`
+    pub name: String,
+    /// The path the flake would have referenced
+    pub path: PathBuf,
 }
 
+/// Approximate size of the fixed parts of the flake template
+const FLAKE_TEMPLATE_SIZE: usize = 1024;
+
+/// Packages of one input it takes before they go into `paths` as one
+/// `builtins.attrValues { inherit ...; }` line instead of a line each; below
+/// this the wrapper costs more than it saves
+const PATH_GROUP_MIN: usize = 8;
+
+/// Outputs linked into the environment besides each package's main output,
+/// unless a profile sets its own `extra_outputs`
+pub const DEFAULT_EXTRA_OUTPUTS: &[&str] = &["man", "doc", "info", "dev"];
+
+/// The base nixpkgs of a profile that doesn't set its own `nixpkgs_url`
+pub const DEFAULT_NIXPKGS_URL: &str = "github:NixOS/nixpkgs/nixos-unstable";
+
 /// Intermediate representation for building flake content
 struct FlakeBuilder {
+    /// URL of the `nixpkgs` input
+    nixpkgs_url: String,
     /// Additional flake inputs (beyond nixpkgs)
     inputs: String,
     /// Set of input names already added
@@ -46,11 +122,18 @@ struct FlakeBuilder {
     custom_entries: String,
     /// Package names for buildEnv paths with platform restrictions
     buildenv_paths: Vec<PathEntry>,
+    /// `extraOutputsToInstall` of the buildEnv
+    extra_outputs: Vec<String>,
+    /// Number of path groups handed out so far
+    path_groups: usize,
+    /// User blocks carried over from the previous flake.nix
+    preserved: PreservedContent,
 }
 
 impl FlakeBuilder {
     fn new() -> Self {
         Self {
+            nixpkgs_url: DEFAULT_NIXPKGS_URL.to_string(),
             inputs: String::new(),
             seen_inputs: HashSet::new(),
             overlays: String::new(),
@@ -59,24 +142,52 @@ impl FlakeBuilder {
             local_entries: String::new(),
             custom_entries: String::new(),
             buildenv_paths: Vec::new(),
+            path_groups: 0,
+            extra_outputs: DEFAULT_EXTRA_OUTPUTS
+                .iter()
+                .map(|o| o.to_string())
+                .collect(),
+            preserved: PreservedContent::default(),
         }
     }
 
+    /// Write the user blocks of the flake.nix in `flake_dir` back into the
+    /// new one
+    fn preserve_from(&mut self, flake_dir: &Path) -> Result<()> {
+        if let Ok(content) = fs::read_to_string(flake_dir.join("flake.nix")) {
+            self.preserved = PreservedContent::extract(&content)?;
+        }
+        Ok(())
+    }
+
+    /// A new group for packages bound from one input under their own names
+    fn new_path_group(&mut self) -> usize {
+        self.path_groups += 1;
+        self.path_groups
+    }
+
+    /// Replace the default extra outputs; an empty list gives the smallest
+    /// closure (no man pages, docs or headers)
+    fn set_extra_outputs(&mut self, outputs: &[String]) {
+        self.extra_outputs = outputs.to_vec();
+    }
+
+    /// Take legacy packages (and `lib`) from another nixpkgs, e.g. a stable
+    /// release branch
+    fn set_nixpkgs_url(&mut self, url: &str) {
+        self.nixpkgs_url = url.to_string();
+    }
+
     /// Add standard nixpkgs packages (legacy, from default nixpkgs)
     fn add_standard_packages(&mut self, packages: &[&String]) {
-        let entries: Vec<String> = packages
-            .iter()
-            .map(|pkg| format!("          {} = pkgs.{};", pkg, pkg))
-            .collect();
-
-        if !entries.is_empty() {
-            self.standard_entries = format!("{}\n", entries.join("\n"));
-        }
+        let names: Vec<&str> = packages.iter().map(|p| p.as_str()).collect();
+        write_same_name_bindings(&mut self.standard_entries, "pkgs", &names);
 
+        let group = self.new_path_group();
         self.buildenv_paths
             .extend(packages.iter().map(|p| PathEntry {
-                name: p.to_string(),
-                platforms: None,
+                group: Some(group),
+                ..PathEntry::new(p, None)
             }));
     }
 
@@ -86,8 +197,8 @@ impl FlakeBuilder {
             return;
         }
 
-        // Group packages by commit hash
-        let mut by_commit: HashMap<&str, Vec<&ResolvedNixpkgPackage>> = HashMap::new();
+        // Group packages by commit hash (sorted, so output is stable)
+        let mut by_commit: BTreeMap<&str, Vec<&ResolvedNixpkgPackage>> = BTreeMap::new();
         for pkg in packages {
             by_commit.entry(&pkg.commit_hash).or_default().push(pkg);
         }
@@ -98,22 +209,46 @@ impl FlakeBuilder {
 
             // Add input if not already seen
             if self.seen_inputs.insert(input_name.clone()) {
-                self.inputs.push_str(&format!(
-                    "    {}.url = \"github:NixOS/nixpkgs/{}\";\n",
+                let _ = writeln!(
+                    self.inputs,
+                    "    {}.url = \"github:NixOS/nixpkgs/{}\";",
                     input_name, commit
-                ));
+                );
             }
 
-            // Add package entries
+            // Packages named after their attribute share one `inherit` per
+            // flake output
+            let mut by_output: BTreeMap<&str, Vec<&ResolvedNixpkgPackage>> = BTreeMap::new();
             for pkg in pkgs {
-                self.resolved_entries.push_str(&format!(
-                    "          {} = inputs.{}.legacyPackages.${{system}}.{};\n",
-                    pkg.name, input_name, pkg.attribute_path
-                ));
-                self.buildenv_paths.push(PathEntry {
-                    name: pkg.name.clone(),
-                    platforms: pkg.platforms.clone(),
-                });
+                by_output.entry(pkg.flake_output()).or_default().push(pkg);
+            }
+            for (output, pkgs) in by_output {
+                let source = format!("inputs.{}.{}.${{system}}", input_name, output);
+                let (same_name, renamed): (Vec<&ResolvedNixpkgPackage>, Vec<_>) = pkgs
+                    .iter()
+                    .partition(|pkg| pkg.name == pkg.attribute() && pkg.outputs.is_none());
+                let group = self.new_path_group();
+                self.buildenv_paths
+                    .extend(same_name.iter().map(|pkg| PathEntry {
+                        group: Some(group),
+                        ..PathEntry::new(&pkg.name, pkg.platforms.clone())
+                    }));
+                self.buildenv_paths.extend(
+                    renamed
+                        .iter()
+                        .map(|pkg| PathEntry::new(&pkg.name, pkg.platforms.clone())),
+                );
+                let same_name: Vec<&str> = same_name.iter().map(|pkg| pkg.name.as_str()).collect();
+                write_same_name_bindings(&mut self.resolved_entries, &source, &same_name);
+                for pkg in renamed {
+                    let attribute = format!("{}.{}", source, pkg.attribute());
+                    let _ = writeln!(
+                        self.resolved_entries,
+                        "          {} = {};",
+                        nix_attr_name(&pkg.name),
+                        with_outputs_to_install(&attribute, pkg.outputs.as_deref())
+                    );
+                }
             }
         }
     }
@@ -121,19 +256,18 @@ impl FlakeBuilder {
     /// Add local flake-type packages from packages/ directory
     fn add_local_flakes(&mut self, flakes: &[LocalFlake]) {
         for flake in flakes {
-            self.inputs.push_str(&format!(
-                "    {}.url = \"path:./packages/{}\";\n",
+            let _ = writeln!(
+                self.inputs,
+                "    {}.url = \"path:./packages/{}\";",
                 flake.name, flake.name
-            ));
+            );
             self.seen_inputs.insert(flake.name.clone());
-            self.local_entries.push_str(&format!(
-                "          {} = inputs.{}.packages.${{system}}.default;\n",
+            let _ = writeln!(
+                self.local_entries,
+                "          {} = inputs.{}.packages.${{system}}.default;",
                 flake.name, flake.name
-            ));
-            self.buildenv_paths.push(PathEntry {
-                name: flake.name.clone(),
-                platforms: None,
-            });
+            );
+            self.buildenv_paths.push(PathEntry::new(&flake.name, None));
         }
     }
 
@@ -172,17 +306,14 @@ impl FlakeBuilder {
             } else {
                 format!("path:./packages/{}", flake.name)
             };
-            self.inputs
-                .push_str(&format!("    {}.url = \"{}\";\n", flake.name, path));
+            let _ = writeln!(self.inputs, "    {}.url = \"{}\";", flake.name, path);
             self.seen_inputs.insert(flake.name.clone());
-            self.local_entries.push_str(&format!(
-                "          {} = inputs.{}.packages.${{system}}.default;\n",
+            let _ = writeln!(
+                self.local_entries,
+                "          {} = inputs.{}.packages.${{system}}.default;",
                 flake.name, flake.name
-            ));
-            self.buildenv_paths.push(PathEntry {
-                name: flake.name.clone(),
-                platforms: None,
-            });
+            );
+            self.buildenv_paths.push(PathEntry::new(&flake.name, None));
         }
     }
 
@@ -191,21 +322,21 @@ impl FlakeBuilder {
         for pkg in packages {
             if let (Some(input_name), Some(input_url)) = (&pkg.input_name, &pkg.input_url) {
                 if self.seen_inputs.insert(input_name.clone()) {
-                    self.inputs
-                        .push_str(&format!("    {}.url = \"{}\";\n", input_name, input_url));
+                    let _ = writeln!(self.inputs, "    {}.url = \"{}\";", input_name, input_url);
                 }
             }
 
             if let Some(overlay) = &pkg.overlay {
-                self.overlays.push_str(&format!("          {}\n", overlay));
+                let _ = writeln!(self.overlays, "          {}", overlay);
             }
 
-            self.local_entries
-                .push_str(&format!("          {} = {};\n", pkg.name, pkg.package_expr));
-            self.buildenv_paths.push(PathEntry {
-                name: pkg.name.clone(),
-                platforms: None,
-            });
+            let _ = writeln!(
+                self.local_entries,
+                "          {} = {};",
+                nix_attr_name(&pkg.name),
+                pkg.package_expr
+            );
+            self.buildenv_paths.push(PathEntry::new(&pkg.name, None));
         }
     }
 
@@ -218,13 +349,12 @@ impl FlakeBuilder {
         for pkg in packages {
             if let (Some(input_name), Some(input_url)) = (&pkg.input_name, &pkg.input_url) {
                 if self.seen_inputs.insert(input_name.clone()) {
-                    self.inputs
-                        .push_str(&format!("    {}.url = \"{}\";\n", input_name, input_url));
+                    let _ = writeln!(self.inputs, "    {}.url = \"{}\";", input_name, input_url);
                 }
             }
 
             if let Some(overlay) = &pkg.overlay {
-                self.overlays.push_str(&format!("          {}\n", overlay));
+                let _ = writeln!(self.overlays, "          {}", overlay);
             }
 
             // Update package expression to use absolute path if needed
@@ -253,48 +383,94 @@ impl FlakeBuilder {
                 pkg.package_expr.clone()
             };
 
-            self.local_entries
-                .push_str(&format!("          {} = {};\n", pkg.name, package_expr));
-            self.buildenv_paths.push(PathEntry {
-                name: pkg.name.clone(),
-                platforms: None,
-            });
+            let _ = writeln!(
+                self.local_entries,
+                "          {} = {};",
+                nix_attr_name(&pkg.name),
+                package_expr
+            );
+            self.buildenv_paths.push(PathEntry::new(&pkg.name, None));
         }
     }
 
     /// Add custom packages from external flakes
     fn add_custom_packages(&mut self, packages: &[CustomPackage]) {
+        let mut groups: BTreeMap<&str, usize> = BTreeMap::new();
         for pkg in packages {
             if self.seen_inputs.insert(pkg.input_name.clone()) {
-                self.inputs.push_str(&format!(
-                    "    {}.url = \"{}\";\n",
+                let _ = writeln!(
+                    self.inputs,
+                    "    {}.url = \"{}\";",
                     pkg.input_name, pkg.input_url
-                ));
+                );
             }
 
-            self.custom_entries.push_str(&format!(
-                "          {} = inputs.{}.{}.${{system}}.{};\n",
-                pkg.name,
-                pkg.input_name,
-                pkg.package_output,
-                pkg.source_package_name()
-            ));
+            let _ = writeln!(
+                self.custom_entries,
+                "          {} = {};",
+                nix_attr_name(&pkg.name),
+                custom_package_expr(pkg)
+            );
+            let group = match groups.get(pkg.input_name.as_str()) {
+                Some(group) => *group,
+                None => {
+                    let group = self.new_path_group();
+                    groups.insert(&pkg.input_name, group);
+                    group
+                }
+            };
             self.buildenv_paths.push(PathEntry {
-                name: pkg.name.clone(),
-                platforms: pkg.platforms.clone(),
+                group: Some(group),
+                ..PathEntry::new(&pkg.name, pkg.platforms.clone())
             });
         }
     }
 
+    /// Package attribute names emitted more than once. Normal installs
+    /// never produce these, but a hand-edited nixy.json, or a local package
+    /// shadowing a custom one, would make nix reject the whole flake with
+    /// "attribute already defined".
+    fn duplicate_names(&self) -> Vec<String> {
+        let mut seen = HashSet::from(["default"]);
+        let mut duplicates: Vec<String> = self
+            .buildenv_paths
+            .iter()
+            .filter(|entry| !seen.insert(entry.name.as_str()))
+            .map(|entry| entry.name.clone())
+            .collect();
+        duplicates.sort();
+        duplicates.dedup();
+        duplicates
+    }
+
+    /// [`Self::build`], refusing to emit duplicate attribute names or
+    /// anything that doesn't parse as Nix
+    fn build_checked(self) -> Result<String> {
+        let duplicates = self.duplicate_names();
+        if !duplicates.is_empty() {
+            return Err(Error::DuplicatePackageNames(duplicates.join(", ")));
+        }
+        let content = self.build();
+        check_syntax(&content)?;
+        Ok(content)
+    }
+
     /// Build the output function parameters
+    ///
+    /// Inputs from a user block aren't known by name, so they are let in
+    /// with `...`.
     fn build_output_params(&self) -> String {
-        if self.seen_inputs.is_empty() {
+        let mut params = if self.seen_inputs.is_empty() {
             "self, nixpkgs".to_string()
         } else {
             let mut inputs_list: Vec<_> = self.seen_inputs.iter().cloned().collect();
             inputs_list.sort();
             format!("self, nixpkgs, {}", inputs_list.join(", "))
+        };
+        if !self.preserved.slot(Slot::Inputs).is_empty() {
+            params.push_str(", ...");
         }
+        params
     }
 
     /// Build the pkgs definition (with or without overlays)
@@ -323,16 +499,40 @@ impl FlakeBuilder {
         let output_params = self.build_output_params();
         let (pkgs_def, pkgs_binding) = self.build_pkgs_definition();
         let (paths_content, _has_platform_conditionals) = self.build_paths_section_with_info();
-
-        let paths_section = format!("paths = [\n{}            ];", paths_content);
-
-        format!(
+        let user_inputs = self.preserved.slot(Slot::Inputs);
+        let user_packages = self.preserved.slot(Slot::Packages);
+        // Ahead of the generated paths, which may end in a platform-only list
+        let user_paths = self.preserved.slot(Slot::Paths);
+        let extra_outputs = self
+            .extra_outputs
+            .iter()
+            .map(|o| format!("\"{}\" ", o))
+            .collect::<String>();
+
+        // Size the output up front; large profiles produce hundreds of KB
+        let capacity = FLAKE_TEMPLATE_SIZE
+            + self.nixpkgs_url.len()
+            + self.inputs.len()
+            + output_params.len()
+            + pkgs_def.len()
+            + self.standard_entries.len()
+            + self.resolved_entries.len()
+            + self.local_entries.len()
+            + self.custom_entries.len()
+            + paths_content.len()
+            + user_inputs.len()
+            + user_packages.len()
+            + user_paths.len();
+        let mut flake = String::with_capacity(capacity);
+
+        let _ = write!(
+            flake,
             r#"{{
   description = "nixy managed packages";
 
   inputs = {{
-    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
-{all_inputs}  }};
+    nixpkgs.url = "{nixpkgs_url}";
+{all_inputs}{user_inputs}  }};
 
   outputs = {{ {output_params} }}@inputs:
     let
@@ -343,17 +543,20 @@ impl FlakeBuilder {
       packages = forAllSystems (system:
         {pkgs_binding}
         in rec {{
-{pkg_entries}{resolved_entries}{local_entries}{custom_entries}
+{pkg_entries}{resolved_entries}{local_entries}{custom_entries}{user_packages}
           default = pkgs.buildEnv {{
             name = "nixy-env";
-            {paths_section}
-            extraOutputsToInstall = [ "man" "doc" "info" "dev" ];
+            paths = [
+{user_paths}{paths_content}            ];
+            extraOutputsToInstall = [ {extra_outputs}];
           }};
         }});
     }};
 }}
 "#,
+            nixpkgs_url = self.nixpkgs_url,
             all_inputs = self.inputs,
+            user_inputs = user_inputs,
             output_params = output_params,
             pkgs_def = pkgs_def,
             pkgs_binding = pkgs_binding,
@@ -361,8 +564,12 @@ impl FlakeBuilder {
             resolved_entries = self.resolved_entries,
             local_entries = self.local_entries,
             custom_entries = self.custom_entries,
-            paths_section = paths_section,
-        )
+            user_packages = user_packages,
+            user_paths = user_paths,
+            paths_content = paths_content,
+            extra_outputs = extra_outputs,
+        );
+        flake
     }
 
     /// Build the buildEnv paths section and return whether it has platform conditionals
@@ -371,64 +578,181 @@ impl FlakeBuilder {
             return (String::new(), false);
         }
 
+        // Large groups of packages from one input go in as
+        // `builtins.attrValues { inherit a b c; }`, which saves a line each
+        let groupable =
+            |entry: &PathEntry| entry.platforms.is_none() && is_nix_identifier(&entry.name);
+        let mut group_sizes: BTreeMap<usize, usize> = BTreeMap::new();
+        for entry in self.buildenv_paths.iter().filter(|e| groupable(e)) {
+            if let Some(group) = entry.group {
+                *group_sizes.entry(group).or_default() += 1;
+            }
+        }
+        let mut grouped: BTreeMap<usize, Vec<&str>> = BTreeMap::new();
+
         // Group packages by their platform restrictions
         // None means all platforms, Some([...]) means specific platforms
-        let mut universal: Vec<&str> = Vec::new();
-        let mut by_platforms: HashMap<Vec<String>, Vec<&str>> = HashMap::new();
+        let mut universal: Vec<Cow<str>> = Vec::new();
+        let mut by_platforms: BTreeMap<Vec<String>, Vec<Cow<str>>> = BTreeMap::new();
 
         for entry in &self.buildenv_paths {
+            if let Some(group) = entry.group.filter(|g| {
+                groupable(entry) && group_sizes.get(g).is_some_and(|n| *n >= PATH_GROUP_MIN)
+            }) {
+                grouped.entry(group).or_default().push(&entry.name);
+                continue;
+            }
+            let reference = path_reference(&entry.name);
             match &entry.platforms {
-                None => universal.push(&entry.name),
+                None => universal.push(reference),
                 Some(platforms) => {
                     let mut sorted_platforms = platforms.clone();
                     sorted_platforms.sort();
                     by_platforms
                         .entry(sorted_platforms)
                         .or_default()
-                        .push(&entry.name);
+                        .push(reference);
                 }
             }
         }
 
         let has_conditionals = !by_platforms.is_empty();
-        let mut result = String::new();
+        let capacity = self
+            .buildenv_paths
+            .iter()
+            .map(|entry| entry.name.len() + 17)
+            .sum::<usize>()
+            + by_platforms.len() * 128;
+        let mut result = String::with_capacity(capacity);
 
         // Add universal packages (no platform restriction)
         for pkg in &universal {
-            result.push_str(&format!("              {}\n", pkg));
+            let _ = writeln!(result, "              {}", pkg);
         }
 
-        // Add platform-specific packages with lib.optionals
-        let mut platform_groups: Vec<_> = by_platforms.into_iter().collect();
-        platform_groups.sort_by(|a, b| a.0.cmp(&b.0));
+        for names in grouped.values() {
+            let _ = writeln!(
+                result,
+                "            ] ++ builtins.attrValues {{ inherit {}; }} ++ [",
+                names.join(" ")
+            );
+        }
 
-        for (platforms, packages) in platform_groups {
+        // Add platform-specific packages with lib.optionals
+        for (platforms, packages) in by_platforms {
             let platforms_str = platforms
                 .iter()
                 .map(|p| format!("\"{}\"", p))
                 .collect::<Vec<_>>()
                 .join(" ");
-            let packages_str = packages
-                .iter()
-                .map(|p| format!("\n                {}", p))
-                .collect::<Vec<_>>()
-                .join("");
-            result.push_str(&format!(
-                "            ] ++ pkgs.lib.optionals (builtins.elem system [ {} ]) [{}\n",
-                platforms_str, packages_str
-            ));
+            let _ = write!(
+                result,
+                "            ] ++ pkgs.lib.optionals (builtins.elem system [ {} ]) [",
+                platforms_str
+            );
+            for pkg in packages {
+                let _ = write!(result, "\n                {}", pkg);
+            }
+            result.push('\n');
         }
 
         (result, has_conditionals)
     }
 }
 
+/// The expression a custom package is bound to in the generated flake
+///
+/// An unvalidated package's `package_output` is only a guess, so it falls
+/// back to the other output rather than fail on the wrong one.
+pub fn custom_package_expr(pkg: &CustomPackage) -> String {
+    let expr = |output: &str| {
+        format!(
+            "inputs.{}.{}.${{system}}.{}",
+            pkg.input_name,
+            output,
+            pkg.source_package_name()
+        )
+    };
+    if !pkg.unvalidated {
+        return expr(&pkg.package_output);
+    }
+    let other = if pkg.package_output == "packages" {
+        "legacyPackages"
+    } else {
+        "packages"
+    };
+    format!("{} or {}", expr(&pkg.package_output), expr(other))
+}
+
+/// Bind each of `names` to the attribute of the same name in `source`.
+///
+/// Several names become a single `inherit (source) a b c;`, which keeps
+/// generated flakes for large profiles compact.
+fn write_same_name_bindings(out: &mut String, source: &str, names: &[&str]) {
+    match names {
+        [] => {}
+        [name] => {
+            let attr = nix_attr_name(name);
+            let _ = writeln!(out, "          {} = {}.{};", attr, source, attr);
+        }
+        _ => {
+            let _ = writeln!(out, "          inherit ({})", source);
+            for (i, name) in names.iter().enumerate() {
+                let end = if i + 1 == names.len() { ";" } else { "" };
+                let _ = writeln!(out, "            {}{}", nix_attr_name(name), end);
+            }
+        }
+    }
+}
+
+/// Nix keywords, which can't be bare attribute names
+const NIX_KEYWORDS: &[&str] = &[
+    "assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with",
+];
+
+/// Whether `name` can be written as a bare Nix identifier
+fn is_nix_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '\'' | '-'))
+        && !NIX_KEYWORDS.contains(&name)
+}
+
+/// `name` as a Nix attribute name: bare when it's an identifier, quoted
+/// otherwise (`"python3.11"`)
+pub fn nix_attr_name(name: &str) -> Cow<'_, str> {
+    if is_nix_identifier(name) {
+        return Cow::Borrowed(name);
+    }
+    let escaped = name
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${");
+    Cow::Owned(format!("\"{}\"", escaped))
+}
+
+/// How the buildEnv paths refer to package `name` of the packages set. A
+/// quoted attribute of a `rec` set isn't in scope as a variable, so it is
+/// selected from the flake's own outputs instead.
+fn path_reference(name: &str) -> Cow<'_, str> {
+    if is_nix_identifier(name) {
+        return Cow::Borrowed(name);
+    }
+    Cow::Owned(format!("self.packages.${{system}}.{}", nix_attr_name(name)))
+}
+
 /// Generate flake.nix content from package state
 ///
 /// # Arguments
 /// * `state` - The package state (legacy format)
 /// * `flake_dir` - Optional flake directory for collecting local packages (legacy)
 pub fn generate_flake(state: &PackageState, flake_dir: Option<&Path>) -> String {
+    state_flake_builder(state, flake_dir).build()
+}
+
+fn state_flake_builder(state: &PackageState, flake_dir: Option<&Path>) -> FlakeBuilder {
     // Collect local packages if flake_dir is provided
     let (local_packages, local_flakes) = if let Some(dir) = flake_dir {
         let packages_dir = dir.join("packages");
@@ -468,7 +792,7 @@ pub fn generate_flake(state: &PackageState, flake_dir: Option<&Path>) -> String
     builder.add_local_flakes(&local_flakes);
     builder.add_local_packages(&local_packages);
     builder.add_custom_packages(&state.custom_packages);
-    builder.build()
+    builder
 }
 
 /// Generate flake.nix content from profile config
@@ -476,12 +800,42 @@ pub fn generate_flake(state: &PackageState, flake_dir: Option<&Path>) -> String
 /// # Arguments
 /// * `profile` - The profile configuration (new nixy.json format)
 /// * `global_packages_dir` - Optional global packages directory for local packages
-/// * `_flake_dir` - Reserved for future use
+/// * `flake_dir` - Where the profile's flake.nix is; its user blocks are
+///   kept, and markers that don't balance are an error, as they are for
+///   [`regenerate_flake_from_profile`]
 pub fn generate_flake_from_profile(
     profile: &ProfileConfig,
     global_packages_dir: Option<&Path>,
-    _flake_dir: &Path,
-) -> String {
+    flake_dir: &Path,
+) -> Result<String> {
+    let mut builder = profile_flake_builder(profile, global_packages_dir, false);
+    builder.preserve_from(flake_dir)?;
+    Ok(builder.build())
+}
+
+/// Generate a flake.nix for `profile` that does not depend on this machine's
+/// nixy setup, for `nixy export --flake`
+///
+/// Local packages are referenced as `./packages/<name>`, so a copy of
+/// `global_packages_dir` has to sit next to it. User blocks are taken from
+/// the flake.nix in `flake_dir`, like [`regenerate_flake_from_profile`] does.
+pub fn generate_standalone_flake(
+    profile: &ProfileConfig,
+    global_packages_dir: Option<&Path>,
+    flake_dir: &Path,
+) -> Result<String> {
+    let mut builder = profile_flake_builder(profile, global_packages_dir, true);
+    builder.preserve_from(flake_dir)?;
+    builder.build_checked()
+}
+
+/// The builder for `profile`; with `relative_local`, local packages are
+/// referenced under `./packages` instead of at their absolute paths
+fn profile_flake_builder(
+    profile: &ProfileConfig,
+    global_packages_dir: Option<&Path>,
+    relative_local: bool,
+) -> FlakeBuilder {
     // Collect local packages from global packages directory
     let (local_packages, local_flakes) = if let Some(dir) = global_packages_dir {
         if dir.exists() {
@@ -493,6 +847,21 @@ pub fn generate_flake_from_profile(
         (Vec::new(), Vec::new())
     };
 
+    // Leave out packages whose local source is gone instead of emitting
+    // `path:` inputs that fail the whole build
+    let missing = missing_local_packages(profile, global_packages_dir);
+    let is_missing = |name: &str| missing.iter().any(|m| m.name == name);
+    let local_packages: Vec<LocalPackage> = local_packages
+        .into_iter()
+        .filter(|pkg| !is_missing(&pkg.name))
+        .collect();
+    let custom_packages: Vec<CustomPackage> = profile
+        .custom_packages
+        .iter()
+        .filter(|pkg| !is_missing(&pkg.name))
+        .cloned()
+        .collect();
+
     // Filter out local packages from legacy packages list
     let filtered_legacy_packages: Vec<&String> = profile
         .packages
@@ -517,10 +886,99 @@ pub fn generate_flake_from_profile(
     let mut builder = FlakeBuilder::new();
     builder.add_standard_packages(&filtered_legacy_packages);
     builder.add_resolved_packages(&filtered_resolved_packages);
-    builder.add_local_flakes_with_absolute_paths(&local_flakes, global_packages_dir);
-    builder.add_local_packages_with_absolute_paths(&local_packages, global_packages_dir);
-    builder.add_custom_packages(&profile.custom_packages);
-    builder.build()
+    if relative_local {
+        builder.add_local_flakes(&local_flakes);
+        builder.add_local_packages(&local_packages);
+    } else {
+        builder.add_local_flakes_with_absolute_paths(&local_flakes, global_packages_dir);
+        builder.add_local_packages_with_absolute_paths(&local_packages, global_packages_dir);
+    }
+    builder.add_custom_packages(&custom_packages);
+    if let Some(outputs) = &profile.extra_outputs {
+        builder.set_extra_outputs(outputs);
+    }
+    builder.set_nixpkgs_url(profile.base_nixpkgs_url());
+    builder
+}
+
+/// Find the packages of a profile whose local source path does not exist.
+///
+/// Covers custom packages installed from `path:` or `git+file:` URLs, local
+/// `.nix` packages whose declared `path:` input is gone, and dangling
+/// symlinks in `packages/`. Relative paths are not checked since they resolve
+/// against the flake directory at build time.
+pub fn missing_local_packages(
+    profile: &ProfileConfig,
+    global_packages_dir: Option<&Path>,
+) -> Vec<MissingLocalPackage> {
+    let mut missing = Vec::new();
+
+    for pkg in &profile.custom_packages {
+        if let Some(path) = local_url_path(&pkg.input_url) {
+            if !path.exists() {
+                missing.push(MissingLocalPackage {
+                    name: pkg.name.clone(),
+                    path,
+                });
+            }
+        }
+    }
+
+    let Some(dir) = global_packages_dir.filter(|dir| dir.exists()) else {
+        return missing;
+    };
+
+    let (local_packages, _) = collect_local_packages(dir);
+    for pkg in &local_packages {
+        if let Some(path) = pkg.input_url.as_deref().and_then(local_url_path) {
+            if !path.exists() {
+                missing.push(MissingLocalPackage {
+                    name: pkg.name.clone(),
+                    path,
+                });
+            }
+        }
+    }
+
+    // A symlinked package file or flake whose target is gone is skipped by
+    // discovery, so it would otherwise disappear without a trace
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            // packages/<name>.nix, or packages/<name>/flake.nix
+            let (link, name) = if path.is_dir() {
+                (path.join("flake.nix"), path.file_name())
+            } else if path.extension().is_some_and(|e| e == "nix") {
+                (path.clone(), path.file_stem())
+            } else {
+                continue;
+            };
+            if !link.is_symlink() || link.exists() {
+                continue;
+            }
+            if let Some(name) = name.and_then(|n| n.to_str()) {
+                missing.push(MissingLocalPackage {
+                    name: name.to_string(),
+                    path: fs::read_link(&link).unwrap_or(link),
+                });
+            }
+        }
+    }
+
+    missing.sort_by(|a, b| a.name.cmp(&b.name));
+    missing
+}
+
+/// Absolute filesystem path behind a local flake URL, if it is one
+fn local_url_path(url: &str) -> Option<PathBuf> {
+    let rest = url
+        .strip_prefix("path:")
+        .or_else(|| url.strip_prefix("git+file://"))
+        .or_else(|| url.strip_prefix("file://"))
+        .unwrap_or(url);
+    let rest = rest.split(['?', '#']).next().unwrap_or(rest);
+    let path = PathBuf::from(rest.replace("%20", " "));
+    path.is_absolute().then_some(path)
 }
 
 /// Collect local packages from the packages directory
@@ -555,12 +1013,28 @@ pub fn local_path_input_names(packages_dir: &Path) -> Vec<String> {
     names
 }
 
+/// Fail on the first syntax error in generated flake content
+///
+/// Names and URLs are templated into the flake as they are, so one with
+/// odd characters would otherwise only show up as a cryptic `nix build`
+/// failure.
+fn check_syntax(content: &str) -> Result<()> {
+    match rnix::Root::parse(content).errors().first() {
+        Some(error) => Err(Error::GeneratedFlakeSyntax(error.to_string())),
+        None => Ok(()),
+    }
+}
+
 /// Regenerate flake.nix from state (legacy format)
+///
+/// Legacy state has no settings, so the default formatter preference is used.
 pub fn regenerate_flake(flake_dir: &Path, state: &PackageState) -> Result<()> {
     let flake_path = flake_dir.join("flake.nix");
     fs::create_dir_all(flake_dir)?;
-    let content = generate_flake(state, Some(flake_dir));
-    fs::write(&flake_path, content)?;
+    let mut builder = state_flake_builder(state, Some(flake_dir));
+    builder.preserve_from(flake_dir)?;
+    let content = builder.build_checked()?;
+    fs::write(&flake_path, format_flake(&content, FlakeFormat::default()))?;
     Ok(())
 }
 
@@ -569,19 +1043,43 @@ pub fn regenerate_flake_from_profile(
     flake_dir: &Path,
     profile: &ProfileConfig,
     global_packages_dir: Option<&Path>,
+    format: FlakeFormat,
 ) -> Result<()> {
     let flake_path = flake_dir.join("flake.nix");
     fs::create_dir_all(flake_dir)?;
-    let content = generate_flake_from_profile(profile, global_packages_dir, flake_dir);
-    fs::write(&flake_path, content)?;
+    let mut builder = profile_flake_builder(profile, global_packages_dir, false);
+    builder.preserve_from(flake_dir)?;
+    let content = builder.build_checked()?;
+    fs::write(&flake_path, format_flake(&content, format))?;
     Ok(())
 }
 
+/// The `nixpkgs-<commit>` inputs of a flake.nix that no package entry uses
+///
+/// Flakes generated from nixy.json never have any: an input is declared
+/// only for the commits of its resolved packages. A hand-edited flake.nix,
+/// or one that drifted from its profile, can still declare commits nothing
+/// is built from anymore.
+pub fn orphaned_nixpkgs_inputs(flake_content: &str) -> Vec<String> {
+    declared_inputs(flake_content)
+        .into_keys()
+        .filter(|name| name.starts_with("nixpkgs-"))
+        .filter(|name| !flake_content.contains(&format!("inputs.{}.", name)))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::state::{CustomPackage, ResolvedNixpkgPackage};
 
+    /// The flake for `profile` when there is no flake.nix to keep user
+    /// blocks from yet
+    fn fresh_flake(profile: &ProfileConfig) -> String {
+        let temp = tempfile::tempdir().unwrap();
+        generate_flake_from_profile(profile, None, temp.path()).unwrap()
+    }
+
     #[test]
     fn test_generate_empty_flake() {
         let state = PackageState::default();
@@ -609,10 +1107,10 @@ mod tests {
 
         let flake = generate_flake(&state, None);
 
-        // Should have package entries
-        assert!(flake.contains("ripgrep = pkgs.ripgrep;"));
-        assert!(flake.contains("fzf = pkgs.fzf;"));
-        assert!(flake.contains("bat = pkgs.bat;"));
+        // Should bind all of them with a single inherit
+        assert!(flake.contains(
+            "          inherit (pkgs)\n            bat\n            fzf\n            ripgrep;\n"
+        ));
 
         // Should have packages in paths
         assert!(flake.contains("ripgrep"));
@@ -630,6 +1128,8 @@ mod tests {
             package_output: "packages".to_string(),
             source_name: None,
             platforms: None,
+            unvalidated: false,
+            record: Default::default(),
         });
 
         let flake = generate_flake(&state, None);
@@ -677,6 +1177,56 @@ mod tests {
         assert!(flake.contains("extraOutputsToInstall = [ \"man\" \"doc\" \"info\" \"dev\" ]"));
     }
 
+    #[test]
+    fn test_profile_extra_outputs_can_be_emptied() {
+        let profile = ProfileConfig {
+            extra_outputs: Some(Vec::new()),
+            ..Default::default()
+        };
+        let flake = fresh_flake(&profile);
+        assert!(flake.contains("extraOutputsToInstall = [ ];"), "{}", flake);
+    }
+
+    #[test]
+    fn test_profile_nixpkgs_url() {
+        let mut profile = ProfileConfig::default();
+        profile.add_package("hello");
+        let flake = fresh_flake(&profile);
+        assert!(
+            flake.contains("nixpkgs.url = \"github:NixOS/nixpkgs/nixos-unstable\";"),
+            "{}",
+            flake
+        );
+
+        profile.nixpkgs_url = Some("github:NixOS/nixpkgs/nixos-24.05".to_string());
+        let flake = fresh_flake(&profile);
+        assert!(
+            flake.contains("nixpkgs.url = \"github:NixOS/nixpkgs/nixos-24.05\";"),
+            "{}",
+            flake
+        );
+        assert!(!flake.contains("nixos-unstable"), "{}", flake);
+        assert!(flake.contains("hello = pkgs.hello;"), "{}", flake);
+    }
+
+    #[test]
+    fn test_profile_extra_outputs_override_default() {
+        let profile = ProfileConfig {
+            extra_outputs: Some(vec!["man".to_string()]),
+            ..Default::default()
+        };
+        let flake = fresh_flake(&profile);
+        assert!(
+            flake.contains("extraOutputsToInstall = [ \"man\" ];"),
+            "{}",
+            flake
+        );
+
+        // Unset keeps the defaults
+        let flake = fresh_flake(&ProfileConfig::default());
+        assert!(flake.contains("extraOutputsToInstall = [ \"man\" \"doc\" \"info\" \"dev\" ];"));
+    }
+
     #[test]
     fn test_flake_has_no_devshells() {
         let mut state = PackageState::default();
@@ -700,6 +1250,8 @@ mod tests {
             package_output: "packages".to_string(),
             source_name: None,
             platforms: None,
+            unvalidated: false,
+            record: Default::default(),
         });
 
         let flake = generate_flake(&state, None);
@@ -709,6 +1261,30 @@ mod tests {
         assert!(!flake.contains("# [/nixy:"));
     }
 
+    #[test]
+    fn test_unvalidated_custom_package_falls_back_to_other_output() {
+        let mut state = PackageState::default();
+        state.add_custom_package(CustomPackage {
+            name: "tool".to_string(),
+            input_name: "big".to_string(),
+            input_url: "github:me/big".to_string(),
+            package_output: "packages".to_string(),
+            source_name: None,
+            platforms: None,
+            unvalidated: true,
+            record: Default::default(),
+        });
+
+        let flake = generate_flake(&state, None);
+        assert!(
+            flake.contains(
+                "tool = inputs.big.packages.${system}.tool or inputs.big.legacyPackages.${system}.tool;"
+            ),
+            "{}",
+            flake
+        );
+    }
+
     #[test]
     fn test_multiple_custom_packages_share_input() {
         let mut state = PackageState::default();
@@ -719,6 +1295,8 @@ mod tests {
             package_output: "legacyPackages".to_string(),
             source_name: None,
             platforms: None,
+            unvalidated: false,
+            record: Default::default(),
         });
         state.add_custom_package(CustomPackage {
             name: "world".to_string(),
@@ -727,6 +1305,8 @@ mod tests {
             package_output: "legacyPackages".to_string(),
             source_name: None,
             platforms: None,
+            unvalidated: false,
+            record: Default::default(),
         });
 
         let flake = generate_flake(&state, None);
@@ -748,6 +1328,8 @@ mod tests {
             package_output: "packages".to_string(),
             source_name: None,
             platforms: None,
+            unvalidated: false,
+            record: Default::default(),
         });
 
         let flake = generate_flake(&state, None);
@@ -783,6 +1365,12 @@ mod tests {
             attribute_path: "nodejs_20".to_string(),
             commit_hash: "abc123def456".to_string(),
             platforms: None,
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
         });
 
         let flake = generate_flake(&state, None);
@@ -812,6 +1400,12 @@ mod tests {
             attribute_path: "nodejs_20".to_string(),
             commit_hash: "abc123def456".to_string(),
             platforms: None,
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
         });
         state.add_resolved_package(ResolvedNixpkgPackage {
             name: "python".to_string(),
@@ -820,6 +1414,12 @@ mod tests {
             attribute_path: "python311".to_string(),
             commit_hash: "abc123def456".to_string(), // Same commit
             platforms: None,
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
         });
 
         let flake = generate_flake(&state, None);
@@ -847,6 +1447,12 @@ mod tests {
             attribute_path: "nodejs_20".to_string(),
             commit_hash: "abc123def456".to_string(),
             platforms: None,
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
         });
         state.add_resolved_package(ResolvedNixpkgPackage {
             name: "python".to_string(),
@@ -855,6 +1461,12 @@ mod tests {
             attribute_path: "python311".to_string(),
             commit_hash: "xyz789ghi012".to_string(), // Different commit
             platforms: None,
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
         });
 
         let flake = generate_flake(&state, None);
@@ -885,6 +1497,12 @@ mod tests {
             attribute_path: "nodejs_20".to_string(),
             commit_hash: "abc123def456".to_string(),
             platforms: None,
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
         });
 
         let flake = generate_flake(&state, None);
@@ -920,6 +1538,12 @@ mod tests {
                 "aarch64-darwin".to_string(),
                 "x86_64-darwin".to_string(),
             ]),
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
         });
 
         let flake = generate_flake(&state, None);
@@ -955,6 +1579,12 @@ mod tests {
                 "aarch64-darwin".to_string(),
                 "x86_64-darwin".to_string(),
             ]),
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
         });
 
         let flake = generate_flake(&state, None);
@@ -979,6 +1609,8 @@ mod tests {
                 "x86_64-linux".to_string(),
                 "aarch64-linux".to_string(),
             ]),
+            unvalidated: false,
+            record: Default::default(),
         });
 
         let flake = generate_flake(&state, None);
@@ -1058,6 +1690,12 @@ mod tests {
             attribute_path: "hello".to_string(),
             commit_hash: "abc123".to_string(),
             platforms: None,
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
         });
         let flake = generate_flake(&state, None);
         validate_brackets(&flake).expect("Resolved packages should produce balanced brackets");
@@ -1074,6 +1712,12 @@ mod tests {
                 "aarch64-darwin".to_string(),
                 "x86_64-darwin".to_string(),
             ]),
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
         });
         let flake = generate_flake(&state, None);
         validate_brackets(&flake)
@@ -1092,6 +1736,12 @@ mod tests {
                 "aarch64-darwin".to_string(),
                 "x86_64-darwin".to_string(),
             ]),
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
         });
         let flake = generate_flake(&state, None);
         validate_brackets(&flake)
@@ -1109,6 +1759,8 @@ mod tests {
                 "x86_64-linux".to_string(),
                 "aarch64-linux".to_string(),
             ]),
+            unvalidated: false,
+            record: Default::default(),
         });
         let flake = generate_flake(&state, None);
         validate_brackets(&flake)
@@ -1123,6 +1775,8 @@ mod tests {
             package_output: "packages".to_string(),
             source_name: None,
             platforms: None,
+            unvalidated: false,
+            record: Default::default(),
         });
         let flake = generate_flake(&state, None);
         validate_brackets(&flake)
@@ -1139,6 +1793,12 @@ mod tests {
             attribute_path: "jq".to_string(),
             commit_hash: "abc123".to_string(),
             platforms: None,
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
         });
         state.add_resolved_package(ResolvedNixpkgPackage {
             name: "terminal-notifier".to_string(),
@@ -1147,6 +1807,12 @@ mod tests {
             attribute_path: "terminal-notifier".to_string(),
             commit_hash: "def456".to_string(),
             platforms: Some(vec!["aarch64-darwin".to_string()]),
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
         });
         state.add_custom_package(CustomPackage {
             name: "neovim".to_string(),
@@ -1155,9 +1821,37 @@ mod tests {
             package_output: "packages".to_string(),
             source_name: None,
             platforms: Some(vec!["x86_64-linux".to_string()]),
+            unvalidated: false,
+            record: Default::default(),
         });
         let flake = generate_flake(&state, None);
         validate_brackets(&flake).expect("Complex mixed scenario should produce balanced brackets");
+
+        // Test case 9: Output-customized packages next to plain ones
+        let mut state = PackageState::default();
+        state.add_package("hello");
+        state.add_resolved_package(resolved("jq", "jq", "abc123"));
+        state.add_resolved_package(ResolvedNixpkgPackage {
+            outputs: Some(vec![
+                "out".to_string(),
+                "man".to_string(),
+                "lib".to_string(),
+            ]),
+            ..resolved("gcc", "gcc", "abc123")
+        });
+        state.add_resolved_package(ResolvedNixpkgPackage {
+            platforms: Some(vec!["x86_64-linux".to_string()]),
+            outputs: Some(Vec::new()),
+            ..resolved("openssl", "openssl", "def456")
+        });
+        let flake = generate_flake(&state, None);
+        validate_brackets(&flake)
+            .expect("Output-customized packages should produce balanced brackets");
+
+        // Test case 10: Large input groups listed through builtins.attrValues
+        let flake = generate_flake(&large_state(100), None);
+        assert!(flake.contains("builtins.attrValues"));
+        validate_brackets(&flake).expect("Grouped paths should produce balanced brackets");
     }
 
     #[test]
@@ -1368,4 +2062,826 @@ mod tests {
             builder.inputs
         );
     }
+
+    /// Builder exercising every block nixy emits: inputs, overlays, rec set
+    /// entries, and platform-conditional paths
+    fn feature_rich_flake() -> String {
+        let mut builder = FlakeBuilder::new();
+        builder.add_standard_packages(&[&"hello".to_string()]);
+        builder.add_resolved_packages(&[ResolvedNixpkgPackage {
+            name: "terminal-notifier".to_string(),
+            version_spec: None,
+            resolved_version: "2.0.0".to_string(),
+            attribute_path: "terminal-notifier".to_string(),
+            commit_hash: "abc123def456".to_string(),
+            platforms: Some(vec![
+                "aarch64-darwin".to_string(),
+                "x86_64-darwin".to_string(),
+            ]),
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
+        }]);
+        builder.add_local_packages(&[LocalPackage {
+            name: "my-tool".to_string(),
+            input_name: Some("tool-src".to_string()),
+            input_url: Some("github:user/tool".to_string()),
+            overlay: Some("(final: prev: {\n  my-tool = prev.hello;\n})".to_string()),
+            package_expr: "pkgs.my-tool".to_string(),
+        }]);
+        builder.add_custom_packages(&[CustomPackage {
+            name: "neovim".to_string(),
+            input_name: "neovim-nightly".to_string(),
+            input_url: "github:nix-community/neovim-nightly-overlay".to_string(),
+            package_output: "packages".to_string(),
+            source_name: None,
+            platforms: Some(vec!["x86_64-linux".to_string()]),
+            unvalidated: false,
+            record: Default::default(),
+        }]);
+        builder.build()
+    }
+
+    #[test]
+    fn test_builtin_format_of_generated_flake_is_idempotent() {
+        let once = format_flake(&feature_rich_flake(), FlakeFormat::Builtin);
+        assert_eq!(format_flake(&once, FlakeFormat::Builtin), once);
+    }
+
+    #[test]
+    fn test_builtin_format_only_changes_whitespace() {
+        let flake = feature_rich_flake();
+        let formatted = format_flake(&flake, FlakeFormat::Builtin);
+        let strip = |s: &str| s.split_whitespace().collect::<Vec<_>>().join(" ");
+        assert_eq!(strip(&formatted), strip(&flake));
+        assert_eq!(formatted.lines().count(), flake.lines().count());
+    }
+
+    #[test]
+    fn test_builtin_format_normalizes_nested_blocks() {
+        let formatted = format_flake(&feature_rich_flake(), FlakeFormat::Builtin);
+        // Overlay bodies and platform-conditional entries get one level of
+        // indentation relative to the line that opens them
+        assert!(formatted.contains(
+            "\n          (final: prev: {\n            my-tool = prev.hello;\n          })\n"
+        ));
+        assert!(formatted.contains("[ \"x86_64-linux\" ]) [\n              neovim\n            ];"));
+    }
+
+    #[test]
+    fn test_regenerate_flake_from_profile_respects_format_off() {
+        use tempfile::tempdir;
+
+        let temp = tempdir().unwrap();
+        let profile = ProfileConfig::default();
+        let raw = generate_flake_from_profile(&profile, None, temp.path()).unwrap();
+
+        regenerate_flake_from_profile(temp.path(), &profile, None, FlakeFormat::Off).unwrap();
+        let written = fs::read_to_string(temp.path().join("flake.nix")).unwrap();
+        assert_eq!(written, raw);
+    }
+
+    #[test]
+    fn test_regenerate_keeps_user_blocks() {
+        use tempfile::tempdir;
+
+        let temp = tempdir().unwrap();
+        let profile = ProfileConfig {
+            resolved_packages: vec![resolved("ripgrep", "ripgrep", "aaaaaaaa1111")],
+            ..Default::default()
+        };
+        regenerate_flake_from_profile(temp.path(), &profile, None, FlakeFormat::Off).unwrap();
+
+        // Hand edits: an input, a package built with it, and its install
+        let flake_path = temp.path().join("flake.nix");
+        let edited = fs::read_to_string(&flake_path)
+            .unwrap()
+            .replace(
+                "  };
+
+  outputs",
+                "    # nixy:user-begin inputs\n    mine.url = \"github:me/mine\";\n    # nixy:user-end\n  };\n\n  outputs",
+            )
+            .replace(
+                "\n          default = pkgs.buildEnv",
+                "          # nixy:user-begin\n          mine = inputs.mine.packages.${system}.default;\n          # nixy:user-end\n\n          default = pkgs.buildEnv",
+            )
+            .replace(
+                "              ripgrep\n",
+                "              ripgrep\n              # nixy:user-begin paths\n              mine\n              # nixy:user-end\n",
+            );
+        fs::write(&flake_path, &edited).unwrap();
+
+        let profile = ProfileConfig {
+            resolved_packages: vec![
+                resolved("jq", "jq", "aaaaaaaa1111"),
+                resolved("ripgrep", "ripgrep", "aaaaaaaa1111"),
+            ],
+            ..Default::default()
+        };
+        regenerate_flake_from_profile(temp.path(), &profile, None, FlakeFormat::Off).unwrap();
+        let flake = fs::read_to_string(&flake_path).unwrap();
+        assert!(flake.contains("jq"), "{}", flake);
+        assert!(
+            flake.contains("    mine.url = \"github:me/mine\";\n    # nixy:user-end\n  };"),
+            "{}",
+            flake
+        );
+        assert!(flake.contains("@inputs:"), "{}", flake);
+        assert!(flake.contains(", ... }@inputs"), "{}", flake);
+        assert!(
+            flake.contains("          mine = inputs.mine.packages.${system}.default;\n          # nixy:user-end\n\n          default"),
+            "{}",
+            flake
+        );
+        assert!(
+            flake
+                .contains("paths = [\n              # nixy:user-begin paths\n              mine\n"),
+            "{}",
+            flake
+        );
+        assert!(check_syntax(&flake).is_ok());
+
+        // Regenerating again changes nothing
+        regenerate_flake_from_profile(temp.path(), &profile, None, FlakeFormat::Off).unwrap();
+        assert_eq!(fs::read_to_string(&flake_path).unwrap(), flake);
+    }
+
+    #[test]
+    fn test_regenerate_refuses_unbalanced_user_blocks() {
+        use tempfile::tempdir;
+
+        let temp = tempdir().unwrap();
+        let flake_path = temp.path().join("flake.nix");
+        let edited = "{\n  # nixy:user-begin\n  foo = 1;\n}\n";
+        fs::write(&flake_path, edited).unwrap();
+
+        let err = regenerate_flake_from_profile(
+            temp.path(),
+            &ProfileConfig::default(),
+            None,
+            FlakeFormat::Off,
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::UserBlock(_)), "{}", err);
+        assert_eq!(fs::read_to_string(&flake_path).unwrap(), edited);
+    }
+
+    #[test]
+    fn test_preview_refuses_unbalanced_user_blocks() {
+        use tempfile::tempdir;
+
+        let temp = tempdir().unwrap();
+        fs::write(
+            temp.path().join("flake.nix"),
+            "{\n  # nixy:user-begin\n  foo = 1;\n}\n",
+        )
+        .unwrap();
+
+        let err =
+            generate_flake_from_profile(&ProfileConfig::default(), None, temp.path()).unwrap_err();
+        assert!(matches!(err, Error::UserBlock(_)), "{}", err);
+    }
+
+    fn resolved(name: &str, attribute_path: &str, commit: &str) -> ResolvedNixpkgPackage {
+        ResolvedNixpkgPackage {
+            name: name.to_string(),
+            version_spec: None,
+            resolved_version: "1.0".to_string(),
+            attribute_path: attribute_path.to_string(),
+            commit_hash: commit.to_string(),
+            platforms: None,
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_regenerate_rejects_name_resolved_twice() {
+        use tempfile::tempdir;
+
+        // As after a hand edit: `add_resolved_package` would have deduped
+        let temp = tempdir().unwrap();
+        let profile = ProfileConfig {
+            resolved_packages: vec![
+                resolved("jq", "jq", "aaaaaaaa1111"),
+                resolved("jq", "jq", "bbbbbbbb2222"),
+                resolved("ripgrep", "ripgrep", "aaaaaaaa1111"),
+            ],
+            ..Default::default()
+        };
+
+        let err = regenerate_flake_from_profile(temp.path(), &profile, None, FlakeFormat::Off)
+            .unwrap_err();
+        assert!(matches!(&err, Error::DuplicatePackageNames(names) if names == "jq"));
+        assert!(!temp.path().join("flake.nix").exists());
+    }
+
+    #[test]
+    fn test_resolved_package_output_beside_the_package() {
+        let profile = ProfileConfig {
+            resolved_packages: vec![
+                resolved("git", "git", "aaaaaaaa1111"),
+                ResolvedNixpkgPackage {
+                    source_name: Some("git".to_string()),
+                    output: Some("doc".to_string()),
+                    ..resolved("git-doc", "git", "aaaaaaaa1111")
+                },
+            ],
+            ..Default::default()
+        };
+
+        let flake = fresh_flake(&profile);
+        assert!(
+            flake.contains("git = inputs.nixpkgs-aaaaaaaa.legacyPackages.${system}.git;"),
+            "{}",
+            flake
+        );
+        assert!(
+            flake.contains("git-doc = inputs.nixpkgs-aaaaaaaa.legacyPackages.${system}.git.doc;"),
+            "{}",
+            flake
+        );
+        assert!(flake.contains("              git\n"), "{}", flake);
+        assert!(flake.contains("              git-doc\n"), "{}", flake);
+    }
+
+    #[test]
+    fn test_resolved_package_from_another_flake_output() {
+        let profile = ProfileConfig {
+            resolved_packages: vec![
+                resolved("fd", "fd", "aaaaaaaa1111"),
+                ResolvedNixpkgPackage {
+                    package_output: Some("packages".to_string()),
+                    ..resolved("jq", "jq", "aaaaaaaa1111")
+                },
+                ResolvedNixpkgPackage {
+                    package_output: Some("packages".to_string()),
+                    ..resolved("rg", "ripgrep", "aaaaaaaa1111")
+                },
+            ],
+            ..Default::default()
+        };
+
+        let flake = fresh_flake(&profile);
+        assert_eq!(
+            flake.matches("nixpkgs-aaaaaaaa.url").count(),
+            1,
+            "{}",
+            flake
+        );
+        assert!(
+            flake.contains("fd = inputs.nixpkgs-aaaaaaaa.legacyPackages.${system}.fd;"),
+            "{}",
+            flake
+        );
+        assert!(
+            flake.contains("jq = inputs.nixpkgs-aaaaaaaa.packages.${system}.jq;"),
+            "{}",
+            flake
+        );
+        assert!(
+            flake.contains("rg = inputs.nixpkgs-aaaaaaaa.packages.${system}.ripgrep;"),
+            "{}",
+            flake
+        );
+    }
+
+    #[test]
+    fn test_resolved_package_with_outputs() {
+        let profile = ProfileConfig {
+            resolved_packages: vec![
+                resolved("fd", "fd", "aaaaaaaa1111"),
+                ResolvedNixpkgPackage {
+                    outputs: Some(vec!["out".to_string(), "dev".to_string()]),
+                    ..resolved("zlib", "zlib", "aaaaaaaa1111")
+                },
+            ],
+            ..Default::default()
+        };
+
+        let flake = fresh_flake(&profile);
+        assert!(
+            flake.contains("fd = inputs.nixpkgs-aaaaaaaa.legacyPackages.${system}.fd;"),
+            "{}",
+            flake
+        );
+        assert!(
+            flake.contains(
+                "zlib = let p = inputs.nixpkgs-aaaaaaaa.legacyPackages.${system}.zlib; \
+                 in p // { meta = (p.meta or { }) // { outputsToInstall = [ \"out\" \"dev\" ]; }; };"
+            ),
+            "{}",
+            flake
+        );
+        assert!(flake.contains("              zlib\n"), "{}", flake);
+        assert!(check_syntax(&flake).is_ok());
+    }
+
+    #[test]
+    fn test_aliased_versions_side_by_side() {
+        // `nixy install nodejs@18 --as node18` and `nodejs@20 --as node20`
+        let profile = ProfileConfig {
+            resolved_packages: vec![
+                ResolvedNixpkgPackage {
+                    source_name: Some("nodejs".to_string()),
+                    ..resolved("node18", "nodejs_18", "aaaaaaaa1111")
+                },
+                ResolvedNixpkgPackage {
+                    source_name: Some("nodejs".to_string()),
+                    ..resolved("node20", "nodejs_20", "bbbbbbbb2222")
+                },
+            ],
+            ..Default::default()
+        };
+
+        let flake = fresh_flake(&profile);
+        assert!(
+            flake.contains("node18 = inputs.nixpkgs-aaaaaaaa.legacyPackages.${system}.nodejs_18;"),
+            "{}",
+            flake
+        );
+        assert!(
+            flake.contains("node20 = inputs.nixpkgs-bbbbbbbb.legacyPackages.${system}.nodejs_20;"),
+            "{}",
+            flake
+        );
+        let paths_start = flake.find("paths = [").unwrap();
+        let paths_end = flake[paths_start..].find("];").unwrap();
+        let paths_section = &flake[paths_start..paths_start + paths_end];
+        assert!(paths_section.contains("node18\n"), "{}", paths_section);
+        assert!(paths_section.contains("node20\n"), "{}", paths_section);
+        assert!(!paths_section.contains("nodejs"), "{}", paths_section);
+    }
+
+    #[test]
+    fn test_regenerate_rejects_name_shared_across_sources() {
+        use tempfile::tempdir;
+
+        let temp = tempdir().unwrap();
+        let profile = ProfileConfig {
+            packages: vec!["hello".to_string()],
+            resolved_packages: vec![resolved("neovim", "neovim", "aaaaaaaa1111")],
+            custom_packages: vec![CustomPackage {
+                name: "neovim".to_string(),
+                input_name: "neovim-nightly".to_string(),
+                input_url: "github:nix-community/neovim-nightly-overlay".to_string(),
+                package_output: "packages".to_string(),
+                source_name: None,
+                platforms: None,
+                unvalidated: false,
+                record: Default::default(),
+            }],
+            ..Default::default()
+        };
+
+        let err = regenerate_flake_from_profile(temp.path(), &profile, None, FlakeFormat::Off)
+            .unwrap_err();
+        assert!(err.to_string().contains("neovim"), "{}", err);
+    }
+
+    #[test]
+    fn test_regenerate_rejects_package_named_default() {
+        use tempfile::tempdir;
+
+        // `default` is the buildEnv itself
+        let temp = tempdir().unwrap();
+        let profile = ProfileConfig {
+            resolved_packages: vec![resolved("default", "hello", "aaaaaaaa1111")],
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            regenerate_flake_from_profile(temp.path(), &profile, None, FlakeFormat::Off),
+            Err(Error::DuplicatePackageNames(names)) if names == "default"
+        ));
+    }
+
+    #[test]
+    fn test_nix_attr_name() {
+        assert_eq!(nix_attr_name("ripgrep"), "ripgrep");
+        assert_eq!(nix_attr_name("nodejs_20"), "nodejs_20");
+        assert_eq!(nix_attr_name("my-tool'"), "my-tool'");
+        assert_eq!(nix_attr_name("python3.11"), "\"python3.11\"");
+        assert_eq!(nix_attr_name("7zip"), "\"7zip\"");
+        assert_eq!(nix_attr_name("-tool"), "\"-tool\"");
+        assert_eq!(nix_attr_name("with"), "\"with\"");
+        assert_eq!(nix_attr_name("a\"b${c}"), "\"a\\\"b\\${c}\"");
+    }
+
+    #[test]
+    fn test_quoted_package_names() {
+        use crate::flake::parser::parse_generated_packages;
+
+        let profile = ProfileConfig {
+            packages: vec!["python3.11".to_string(), "7zip".to_string()],
+            resolved_packages: vec![
+                ResolvedNixpkgPackage {
+                    platforms: Some(vec!["x86_64-linux".to_string()]),
+                    ..resolved("2048-in-terminal", "_2048-in-terminal", "aaaaaaaa1111")
+                },
+                resolved("ripgrep", "ripgrep", "aaaaaaaa1111"),
+            ],
+            ..Default::default()
+        };
+        let flake = fresh_flake(&profile);
+
+        assert!(flake.contains("\"python3.11\""), "{}", flake);
+        assert!(flake.contains("\"7zip\""), "{}", flake);
+        assert!(
+            flake.contains(
+                "\"2048-in-terminal\" = inputs.nixpkgs-aaaaaaaa.legacyPackages.${system}._2048-in-terminal;"
+            ),
+            "{}",
+            flake
+        );
+        assert!(
+            flake.contains("self.packages.${system}.\"python3.11\""),
+            "{}",
+            flake
+        );
+        assert!(
+            flake.contains("self.packages.${system}.\"2048-in-terminal\""),
+            "{}",
+            flake
+        );
+        check_syntax(&flake).unwrap();
+
+        let generated = parse_generated_packages(&flake).unwrap();
+        assert_eq!(generated.bindings["python3.11"], "pkgs.\"python3.11\"");
+        assert_eq!(generated.bindings["7zip"], "pkgs.\"7zip\"");
+        for name in ["python3.11", "7zip", "2048-in-terminal", "ripgrep"] {
+            assert!(generated.paths.iter().any(|p| p == name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_regenerate_rejects_malformed_output() {
+        use tempfile::tempdir;
+
+        let temp = tempdir().unwrap();
+        let profile = ProfileConfig {
+            custom_packages: vec![CustomPackage {
+                name: "tool".to_string(),
+                input_name: "tool".to_string(),
+                input_url: "github:owner/\"tool".to_string(),
+                package_output: "packages".to_string(),
+                source_name: None,
+                platforms: None,
+                unvalidated: false,
+                record: Default::default(),
+            }],
+            ..Default::default()
+        };
+
+        let err = regenerate_flake_from_profile(temp.path(), &profile, None, FlakeFormat::Off)
+            .unwrap_err();
+        assert!(matches!(err, Error::GeneratedFlakeSyntax(_)), "{}", err);
+        assert!(!temp.path().join("flake.nix").exists());
+
+        // Every well-formed profile still goes through
+        let profile = ProfileConfig {
+            packages: vec!["ripgrep".to_string()],
+            resolved_packages: vec![resolved("hello", "hello", "aaaaaaaa1111")],
+            ..Default::default()
+        };
+        regenerate_flake_from_profile(temp.path(), &profile, None, FlakeFormat::Off).unwrap();
+        assert!(temp.path().join("flake.nix").exists());
+    }
+
+    #[test]
+    fn test_regenerate_allows_shared_commit_with_distinct_names() {
+        use tempfile::tempdir;
+
+        let temp = tempdir().unwrap();
+        let profile = ProfileConfig {
+            resolved_packages: vec![
+                resolved("nodejs", "nodejs_20", "aaaaaaaa1111"),
+                resolved("node", "nodejs_20", "aaaaaaaa1111"),
+            ],
+            ..Default::default()
+        };
+
+        regenerate_flake_from_profile(temp.path(), &profile, None, FlakeFormat::Off).unwrap();
+    }
+
+    /// Synthetic profile with `count` packages spread over every kind of entry
+    fn large_state(count: usize) -> PackageState {
+        let mut state = PackageState::default();
+        for i in 0..count {
+            match i % 4 {
+                0 => state.add_package(&format!("pkg{}", i)),
+                1 => state.add_resolved_package(ResolvedNixpkgPackage {
+                    name: format!("pkg{}", i),
+                    version_spec: None,
+                    resolved_version: "1.0".to_string(),
+                    attribute_path: format!("pkg{}", i),
+                    commit_hash: format!("{:08x}deadbeef", i % 5),
+                    platforms: None,
+                    pinned: false,
+                    source_name: None,
+                    output: None,
+                    package_output: None,
+                    outputs: None,
+                    record: Default::default(),
+                }),
+                2 => state.add_resolved_package(ResolvedNixpkgPackage {
+                    name: format!("pkg{}", i),
+                    version_spec: None,
+                    resolved_version: "1.0".to_string(),
+                    attribute_path: format!("pkg{}_attr", i),
+                    commit_hash: format!("{:08x}deadbeef", i % 5),
+                    platforms: Some(vec!["x86_64-linux".to_string()]),
+                    pinned: false,
+                    source_name: None,
+                    output: None,
+                    package_output: None,
+                    outputs: None,
+                    record: Default::default(),
+                }),
+                _ => state.add_custom_package(CustomPackage {
+                    name: format!("pkg{}", i),
+                    input_name: format!("flake{}", i % 7),
+                    input_url: format!("github:owner/flake{}", i % 7),
+                    package_output: "packages".to_string(),
+                    source_name: None,
+                    platforms: None,
+                    unvalidated: false,
+                    record: Default::default(),
+                }),
+            }
+        }
+        state
+    }
+
+    #[test]
+    fn test_large_profile_generation() {
+        let state = large_state(1000);
+
+        let flake = generate_flake(&state, None);
+
+        let parse = rnix::Root::parse(&flake);
+        assert!(parse.errors().is_empty(), "{:?}", parse.errors());
+
+        let generated = crate::flake::parser::parse_generated_packages(&flake).unwrap();
+        assert_eq!(generated.bindings.len(), 1000);
+        assert_eq!(generated.paths.len(), 1000);
+        assert_eq!(generated.bindings["pkg0"], "pkgs.pkg0");
+        assert_eq!(
+            generated.bindings["pkg2"],
+            "inputs.nixpkgs-00000002.legacyPackages.${system}.pkg2_attr"
+        );
+    }
+
+    /// Generation time for a large profile; run with `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn bench_large_profile_generation() {
+        let state = large_state(1000);
+
+        let start = std::time::Instant::now();
+        let flake = generate_flake(&state, None);
+        let elapsed = start.elapsed();
+        // Quadratic growth would blow well past this
+        assert!(
+            elapsed < std::time::Duration::from_secs(2),
+            "{} bytes in {:?}",
+            flake.len(),
+            elapsed
+        );
+    }
+
+    #[test]
+    fn test_large_input_groups_go_into_paths_as_attr_values() {
+        let state = large_state(1000);
+        let flake = generate_flake(&state, None);
+
+        // Standard packages, resolved ones per commit and custom ones per
+        // input; platform-restricted ones stay in their own lists
+        let groups: Vec<&str> = flake
+            .lines()
+            .filter(|line| line.contains("builtins.attrValues"))
+            .collect();
+        assert_eq!(groups.len(), 1 + 5 + 7, "{}", flake);
+        assert!(
+            groups[0]
+                .starts_with("            ] ++ builtins.attrValues { inherit pkg0 pkg100 pkg104 "),
+            "{}",
+            groups[0]
+        );
+        assert!(!flake.contains("              pkg0\n"), "{}", flake);
+        assert!(flake.contains("                pkg2\n"), "{}", flake);
+
+        // Small groups are listed a name per line
+        let flake = generate_flake(&large_state(8), None);
+        assert!(!flake.contains("builtins.attrValues"), "{}", flake);
+        let flake = fresh_flake(&ProfileConfig {
+            packages: (0..PATH_GROUP_MIN).map(|i| format!("pkg{}", i)).collect(),
+            ..Default::default()
+        });
+        assert!(
+            flake.contains("] ++ builtins.attrValues { inherit pkg0 pkg1 pkg2 pkg3 pkg4 pkg5 pkg6 pkg7; } ++ ["),
+            "{}",
+            flake
+        );
+        assert!(check_syntax(&flake).is_ok());
+    }
+
+    #[test]
+    fn test_resolved_packages_named_after_attribute_are_inherited() {
+        let mut state = PackageState::default();
+        for (name, attr) in [("jq", "jq"), ("fd", "fd"), ("nodejs", "nodejs_20")] {
+            state.add_resolved_package(ResolvedNixpkgPackage {
+                name: name.to_string(),
+                version_spec: None,
+                resolved_version: "1.0".to_string(),
+                attribute_path: attr.to_string(),
+                commit_hash: "abc123def456".to_string(),
+                platforms: None,
+                pinned: false,
+                source_name: None,
+                output: None,
+                package_output: None,
+                outputs: None,
+                record: Default::default(),
+            });
+        }
+        let flake = generate_flake(&state, None);
+
+        assert!(flake.contains(
+            "          inherit (inputs.nixpkgs-abc123de.legacyPackages.${system})\n            fd\n            jq;\n"
+        ));
+        assert!(
+            flake.contains("nodejs = inputs.nixpkgs-abc123de.legacyPackages.${system}.nodejs_20;")
+        );
+        let formatted = crate::flake::format::format_builtin(&flake);
+        assert_eq!(crate::flake::format::format_builtin(&formatted), formatted);
+    }
+
+    #[test]
+    fn test_large_generated_flake_is_smaller_with_inherit() {
+        let mut state = PackageState::default();
+        for i in 0..1000 {
+            state.add_package(&format!("package-number-{}", i));
+        }
+        let flake = generate_flake(&state, None);
+        let one_binding_per_package: usize = (0..1000)
+            .map(|i| {
+                format!(
+                    "          package-number-{0} = pkgs.package-number-{0};\n",
+                    i
+                )
+                .len()
+            })
+            .sum();
+        let inherit_bindings = flake.find("default = pkgs.buildEnv").unwrap();
+        assert!(inherit_bindings < one_binding_per_package);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_missing_local_packages_are_left_out() {
+        use std::os::unix::fs::symlink;
+        use tempfile::tempdir;
+
+        let temp = tempdir().unwrap();
+        let packages_dir = temp.path().join("packages");
+        fs::create_dir_all(&packages_dir).unwrap();
+
+        // A .nix package whose path input exists, and one whose input is gone
+        let src = temp.path().join("src");
+        fs::create_dir_all(&src).unwrap();
+        for (name, input) in [("kept", &src), ("lost", &temp.path().join("lost-src"))] {
+            fs::write(
+                packages_dir.join(format!("{}.nix", name)),
+                format!(
+                    "{{\n  pname = \"{name}\";\n  inputs = {{\n    {name}-src.url = \"path:{}\";\n  }};\n}}\n",
+                    input.display()
+                ),
+            )
+            .unwrap();
+        }
+        // A package file symlinked from dotfiles that weren't copied over
+        symlink(
+            temp.path().join("dotfiles/linked.nix"),
+            packages_dir.join("linked.nix"),
+        )
+        .unwrap();
+
+        let mut profile = ProfileConfig::default();
+        profile.add_package("hello");
+        for (name, url) in [
+            ("here", format!("path:{}", src.display())),
+            (
+                "gone",
+                format!("path:{}", temp.path().join("gone").display()),
+            ),
+            ("remote", "github:owner/repo".to_string()),
+            ("relative", "path:./somewhere".to_string()),
+        ] {
+            profile.add_custom_package(CustomPackage {
+                name: name.to_string(),
+                input_name: name.to_string(),
+                input_url: url,
+                package_output: "packages".to_string(),
+                source_name: None,
+                platforms: None,
+                unvalidated: false,
+                record: Default::default(),
+            });
+        }
+
+        let missing = missing_local_packages(&profile, Some(&packages_dir));
+        let names: Vec<&str> = missing.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["gone", "linked", "lost"]);
+        assert_eq!(missing[0].path, temp.path().join("gone"));
+        assert_eq!(missing[1].path, temp.path().join("dotfiles/linked.nix"));
+
+        let flake =
+            generate_flake_from_profile(&profile, Some(&packages_dir), temp.path()).unwrap();
+        for present in [
+            "hello = pkgs.hello;",
+            "here = inputs.here",
+            "kept-src.url",
+            "remote = inputs.remote",
+            "relative = inputs.relative",
+        ] {
+            assert!(flake.contains(present), "missing {}: {}", present, flake);
+        }
+        for absent in ["gone", "lost", "linked"] {
+            assert!(
+                !flake.contains(absent),
+                "{} should be left out: {}",
+                absent,
+                flake
+            );
+        }
+        assert!(rnix::Root::parse(&flake).errors().is_empty());
+    }
+
+    #[test]
+    fn test_local_url_path() {
+        assert_eq!(local_url_path("path:/a/b"), Some(PathBuf::from("/a/b")));
+        assert_eq!(
+            local_url_path("path:/a/my%20dir?dir=sub"),
+            Some(PathBuf::from("/a/my dir"))
+        );
+        assert_eq!(
+            local_url_path("git+file:///src/tool"),
+            Some(PathBuf::from("/src/tool"))
+        );
+        assert_eq!(
+            local_url_path("/abs/flake#pkg"),
+            Some(PathBuf::from("/abs/flake"))
+        );
+        assert_eq!(local_url_path("path:./rel"), None);
+        assert_eq!(local_url_path("github:owner/repo"), None);
+    }
+
+    #[test]
+    fn test_removing_one_of_two_packages_keeps_their_shared_input() {
+        let mut profile = ProfileConfig {
+            resolved_packages: vec![
+                resolved("git", "git", "aaaaaaaaaaaa"),
+                resolved("nodejs", "nodejs_20", "aaaaaaaaaaaa"),
+                resolved("hello", "hello", "bbbbbbbbbbbb"),
+            ],
+            ..Default::default()
+        };
+        let flake = fresh_flake(&profile);
+        assert!(orphaned_nixpkgs_inputs(&flake).is_empty(), "{}", flake);
+
+        profile.resolved_packages.retain(|pkg| pkg.name != "git");
+        let flake = fresh_flake(&profile);
+        assert!(flake.contains("nixpkgs-aaaaaaaa.url"), "{}", flake);
+        assert!(orphaned_nixpkgs_inputs(&flake).is_empty(), "{}", flake);
+
+        profile.resolved_packages.retain(|pkg| pkg.name != "nodejs");
+        let flake = fresh_flake(&profile);
+        assert!(!flake.contains("nixpkgs-aaaaaaaa"), "{}", flake);
+        assert!(orphaned_nixpkgs_inputs(&flake).is_empty(), "{}", flake);
+    }
+
+    #[test]
+    fn test_orphaned_nixpkgs_inputs() {
+        let flake = r#"{
+  inputs = {
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+    nixpkgs-aaaaaaaa.url = "github:NixOS/nixpkgs/aaaaaaaaaaaa";
+    nixpkgs-bbbbbbbb.url = "github:NixOS/nixpkgs/bbbbbbbbbbbb";
+  };
+  outputs = { self, nixpkgs, nixpkgs-aaaaaaaa, nixpkgs-bbbbbbbb }@inputs: {
+    hello = inputs.nixpkgs-aaaaaaaa.legacyPackages.x86_64-linux.hello;
+  };
+}
+"#;
+        assert_eq!(orphaned_nixpkgs_inputs(flake), vec!["nixpkgs-bbbbbbbb"]);
+    }
 }
@@ -0,0 +1,452 @@
+//! Fonts from the environment, made visible to the desktop.
+//!
+//! Fonts installed into a profile end up under `<env>/share/fonts`, where
+//! neither fontconfig nor macOS looks. For profiles with `link_fonts` set,
+//! [`link`] mirrors that tree into a directory of the user font dir that
+//! nixy owns: as symlinks into the store on Linux, as copies on macOS,
+//! which does not reliably follow symlinked fonts.
+//!
+//! What was put there is recorded in `~/.local/state/nixy/fonts.json`, so a
+//! later sync removes fonts that left the environment and [`unlink`] removes
+//! everything again, without touching files nixy did not create.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+
+const MANIFEST_FILE: &str = "fonts.json";
+
+/// Where the fonts are inside an environment
+const ENV_FONTS: &str = "share/fonts";
+
+/// How font files are placed in the font directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Symlinks to the files in the store
+    Symlink,
+    /// Copies of the files
+    Copy,
+}
+
+impl Mode {
+    /// Copies on macOS, symlinks elsewhere
+    pub fn native() -> Self {
+        if cfg!(target_os = "macos") {
+            Mode::Copy
+        } else {
+            Mode::Symlink
+        }
+    }
+}
+
+/// Font files added, replaced and removed by [`link`] or [`unlink`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FontChanges {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+}
+
+impl FontChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added == 0 && self.updated == 0 && self.removed == 0
+    }
+}
+
+/// The fonts nixy placed, as recorded in the state directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Manifest {
+    /// Profile whose environment they came from
+    profile: String,
+    /// Font directory they were placed in
+    dir: PathBuf,
+    /// Paths relative to `dir`
+    files: Vec<PathBuf>,
+}
+
+/// The directory nixy places fonts in: `NIXY_FONT_DIR` if set, otherwise
+/// `~/Library/Fonts/Nixy` on macOS and `~/.local/share/fonts/nixy` elsewhere
+pub fn font_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("NIXY_FONT_DIR").filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(dir));
+    }
+    let home = dirs::home_dir()?;
+    Some(if cfg!(target_os = "macos") {
+        home.join("Library/Fonts/Nixy")
+    } else {
+        home.join(".local/share/fonts/nixy")
+    })
+}
+
+/// Profile whose fonts are currently placed, if any
+pub fn linked_profile(config: &Config) -> Option<String> {
+    load_manifest(config).ok().flatten().map(|m| m.profile)
+}
+
+/// Mirror the fonts of the environment at `config.env_link` into `dir`
+///
+/// Files already in place are left alone, changed ones replaced and ones no
+/// longer in the environment removed. Fonts placed in another directory by
+/// an earlier call are removed from there.
+pub fn link(config: &Config, profile: &str, dir: &Path, mode: Mode) -> Result<FontChanges> {
+    let mut changes = FontChanges::default();
+    let previous = load_manifest(config)?;
+
+    let mut stale: Vec<PathBuf> = Vec::new();
+    if let Some(previous) = previous {
+        if previous.dir == dir {
+            stale = previous.files;
+        } else {
+            changes.removed += remove_files(&previous.dir, &previous.files)?;
+        }
+    }
+
+    let mut sources = BTreeMap::new();
+    collect_fonts(
+        &config.env_link.join(ENV_FONTS),
+        Path::new(""),
+        &mut sources,
+    )?;
+
+    for (rel, source) in &sources {
+        let dest = dir.join(rel);
+        match place(source, &dest, mode)? {
+            Placed::Added => changes.added += 1,
+            Placed::Updated => changes.updated += 1,
+            Placed::Unchanged => {}
+        }
+    }
+
+    stale.retain(|rel| !sources.contains_key(rel));
+    changes.removed += remove_files(dir, &stale)?;
+
+    if sources.is_empty() {
+        remove_manifest(config)?;
+    } else {
+        save_manifest(
+            config,
+            &Manifest {
+                profile: profile.to_string(),
+                dir: dir.to_path_buf(),
+                files: sources.into_keys().collect(),
+            },
+        )?;
+    }
+    Ok(changes)
+}
+
+/// Remove every font [`link`] placed
+pub fn unlink(config: &Config) -> Result<FontChanges> {
+    let Some(manifest) = load_manifest(config)? else {
+        return Ok(FontChanges::default());
+    };
+    let removed = remove_files(&manifest.dir, &manifest.files)?;
+    remove_manifest(config)?;
+    Ok(FontChanges {
+        removed,
+        ..Default::default()
+    })
+}
+
+/// Run `fc-cache -f` on `dir`, or the font directory above it once `dir`
+/// is gone, so fontconfig picks up the change; returns false if fontconfig
+/// is not installed
+pub fn refresh_font_cache(dir: &Path) -> Result<bool> {
+    let Some(dir) = dir.ancestors().take(2).find(|d| d.is_dir()) else {
+        return Ok(false);
+    };
+    match Command::new("fc-cache").arg("-f").arg(dir).output() {
+        Ok(output) if output.status.success() => Ok(true),
+        Ok(output) => Err(Error::Io(io::Error::other(format!(
+            "fc-cache failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Font files under `dir`, keyed by their path relative to the fonts root
+/// and resolved to the file in the store
+fn collect_fonts(dir: &Path, rel: &Path, out: &mut BTreeMap<PathBuf, PathBuf>) -> Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        let rel = rel.join(entry.file_name());
+        // buildEnv links whole directories or single files; follow both
+        let Ok(metadata) = fs::metadata(&path) else {
+            continue;
+        };
+        if metadata.is_dir() {
+            collect_fonts(&path, &rel, out)?;
+        } else if metadata.is_file() {
+            out.insert(rel, fs::canonicalize(&path)?);
+        }
+    }
+    Ok(())
+}
+
+enum Placed {
+    Added,
+    Updated,
+    Unchanged,
+}
+
+fn place(source: &Path, dest: &Path, mode: Mode) -> Result<Placed> {
+    let existed = dest.symlink_metadata().is_ok();
+    let current = match mode {
+        Mode::Symlink => fs::read_link(dest).is_ok_and(|target| target == source),
+        Mode::Copy => !dest.is_symlink() && same_contents(source, dest),
+    };
+    if current {
+        return Ok(Placed::Unchanged);
+    }
+
+    if existed {
+        // Store files are read-only, so copies cannot be overwritten in place
+        fs::remove_file(dest)?;
+    } else if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match mode {
+        Mode::Symlink => symlink(source, dest)?,
+        Mode::Copy => {
+            fs::copy(source, dest)?;
+        }
+    }
+    Ok(if existed {
+        Placed::Updated
+    } else {
+        Placed::Added
+    })
+}
+
+fn same_contents(a: &Path, b: &Path) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(ma), Ok(mb)) if ma.len() == mb.len() => {
+            matches!((fs::read(a), fs::read(b)), (Ok(x), Ok(y)) if x == y)
+        }
+        _ => false,
+    }
+}
+
+/// Remove `files` (relative to `dir`) and the directories they leave empty,
+/// `dir` included; returns how many files were there to remove
+fn remove_files(dir: &Path, files: &[PathBuf]) -> Result<usize> {
+    let mut removed = 0;
+    for rel in files {
+        // Only ever touch paths inside `dir`
+        if rel.is_absolute() || rel.components().any(|c| c.as_os_str() == "..") {
+            continue;
+        }
+        let path = dir.join(rel);
+        match fs::remove_file(&path) {
+            Ok(()) => removed += 1,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        let mut parent = path.parent();
+        while let Some(p) = parent.filter(|p| p.starts_with(dir)) {
+            // Fails, and stops here, on directories that still have files
+            if fs::remove_dir(p).is_err() {
+                break;
+            }
+            parent = p.parent();
+        }
+    }
+    Ok(removed)
+}
+
+fn manifest_path(config: &Config) -> PathBuf {
+    config.state_dir.join(MANIFEST_FILE)
+}
+
+fn load_manifest(config: &Config) -> Result<Option<Manifest>> {
+    let content = match fs::read_to_string(manifest_path(config)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    serde_json::from_str(&content)
+        .map(Some)
+        .map_err(|e| Error::StateFile(format!("{}: {}", MANIFEST_FILE, e)))
+}
+
+fn save_manifest(config: &Config, manifest: &Manifest) -> Result<()> {
+    fs::create_dir_all(&config.state_dir)?;
+    let json =
+        serde_json::to_string_pretty(manifest).map_err(|e| Error::StateFile(e.to_string()))?;
+    fs::write(manifest_path(config), json)?;
+    Ok(())
+}
+
+fn remove_manifest(config: &Config) -> Result<()> {
+    match fs::remove_file(manifest_path(config)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    struct Fixture {
+        _temp: TempDir,
+        config: Config,
+        store: PathBuf,
+        fonts: PathBuf,
+    }
+
+    /// An environment whose `share/fonts` links into a fake store, like
+    /// buildEnv output
+    fn fixture() -> Fixture {
+        let temp = TempDir::new().unwrap();
+        let config = Config::from_dirs(temp.path().join("config"), temp.path().join("state"));
+        let store = temp.path().join("store");
+        fs::create_dir_all(store.join("nerd-fonts/share/fonts/truetype")).unwrap();
+        let env = temp.path().join("env-build");
+        fs::create_dir_all(env.join("share")).unwrap();
+        symlink(
+            store.join("nerd-fonts/share/fonts"),
+            env.join("share/fonts"),
+        )
+        .unwrap();
+        fs::create_dir_all(&config.state_dir).unwrap();
+        symlink(&env, &config.env_link).unwrap();
+        let fonts = temp.path().join("fonts/nixy");
+        Fixture {
+            _temp: temp,
+            config,
+            store,
+            fonts,
+        }
+    }
+
+    impl Fixture {
+        fn add_font(&self, name: &str, contents: &str) {
+            let path = self
+                .store
+                .join("nerd-fonts/share/fonts/truetype")
+                .join(name);
+            let _ = fs::remove_file(&path);
+            fs::write(path, contents).unwrap();
+        }
+
+        fn remove_font(&self, name: &str) {
+            fs::remove_file(
+                self.store
+                    .join("nerd-fonts/share/fonts/truetype")
+                    .join(name),
+            )
+            .unwrap();
+        }
+    }
+
+    fn changes(added: usize, updated: usize, removed: usize) -> FontChanges {
+        FontChanges {
+            added,
+            updated,
+            removed,
+        }
+    }
+
+    #[test]
+    fn test_link_symlinks_fonts_and_removes_stale_ones() {
+        let f = fixture();
+        f.add_font("Hack.ttf", "hack");
+        f.add_font("Fira.ttf", "fira");
+
+        let result = link(&f.config, "default", &f.fonts, Mode::Symlink).unwrap();
+        assert_eq!(result, changes(2, 0, 0));
+        let hack = f.fonts.join("truetype/Hack.ttf");
+        assert!(hack.is_symlink());
+        assert_eq!(fs::read_to_string(&hack).unwrap(), "hack");
+        assert_eq!(linked_profile(&f.config).as_deref(), Some("default"));
+
+        // Nothing changed, nothing to do
+        let result = link(&f.config, "default", &f.fonts, Mode::Symlink).unwrap();
+        assert!(result.is_empty());
+
+        f.remove_font("Fira.ttf");
+        let result = link(&f.config, "default", &f.fonts, Mode::Symlink).unwrap();
+        assert_eq!(result, changes(0, 0, 1));
+        assert!(!f.fonts.join("truetype/Fira.ttf").exists());
+        assert!(hack.exists());
+    }
+
+    #[test]
+    fn test_link_copies_and_updates_changed_fonts() {
+        let f = fixture();
+        f.add_font("Hack.ttf", "hack v1");
+
+        let result = link(&f.config, "default", &f.fonts, Mode::Copy).unwrap();
+        assert_eq!(result, changes(1, 0, 0));
+        let hack = f.fonts.join("truetype/Hack.ttf");
+        assert!(!hack.is_symlink());
+        assert_eq!(fs::read_to_string(&hack).unwrap(), "hack v1");
+
+        f.add_font("Hack.ttf", "hack v2");
+        let result = link(&f.config, "default", &f.fonts, Mode::Copy).unwrap();
+        assert_eq!(result, changes(0, 1, 0));
+        assert_eq!(fs::read_to_string(&hack).unwrap(), "hack v2");
+    }
+
+    #[test]
+    fn test_unlink_removes_only_placed_fonts() {
+        let f = fixture();
+        f.add_font("Hack.ttf", "hack");
+        link(&f.config, "default", &f.fonts, Mode::Copy).unwrap();
+        fs::write(f.fonts.join("mine.ttf"), "user font").unwrap();
+
+        let result = unlink(&f.config).unwrap();
+        assert_eq!(result, changes(0, 0, 1));
+        assert!(!f.fonts.join("truetype").exists());
+        assert!(f.fonts.join("mine.ttf").exists());
+        assert_eq!(linked_profile(&f.config), None);
+
+        // Nothing left to remove
+        assert!(unlink(&f.config).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_link_without_fonts_cleans_up() {
+        let f = fixture();
+        f.add_font("Hack.ttf", "hack");
+        link(&f.config, "default", &f.fonts, Mode::Copy).unwrap();
+
+        f.remove_font("Hack.ttf");
+        let result = link(&f.config, "default", &f.fonts, Mode::Copy).unwrap();
+        assert_eq!(result, changes(0, 0, 1));
+        assert!(!f.fonts.exists());
+        assert_eq!(linked_profile(&f.config), None);
+    }
+
+    #[test]
+    fn test_link_moves_fonts_to_a_new_dir() {
+        let f = fixture();
+        f.add_font("Hack.ttf", "hack");
+        link(&f.config, "default", &f.fonts, Mode::Copy).unwrap();
+
+        let other = f.fonts.with_file_name("elsewhere");
+        let result = link(&f.config, "work", &other, Mode::Copy).unwrap();
+        assert_eq!(result, changes(1, 0, 1));
+        assert!(!f.fonts.exists());
+        assert!(other.join("truetype/Hack.ttf").exists());
+        assert_eq!(linked_profile(&f.config).as_deref(), Some("work"));
+    }
+}
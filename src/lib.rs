@@ -0,0 +1,110 @@
+//! Core library behind the `nixy` CLI.
+//!
+//! `nixy_core` holds the domain logic for reading and changing nixy profiles
+//! without going through the CLI: the `nixy.json` model ([`NixyConfig`],
+//! [`ProfileConfig`]), package state types, flake generation, Nixhub version
+//! resolution, and the [`NixBackend`] trait over the `nix` command. The
+//! `nixy` binary is a thin layer that parses arguments and renders the
+//! results of these functions.
+//!
+//! Library functions never print; they return values or [`Error`]s for the
+//! caller to present. Everything re-exported from the crate root, plus the
+//! public items of the modules below, follows semver with the crate version.
+//!
+//! # Loading a configuration
+//!
+//! ```
+//! use nixy_core::{Config, NixyConfig};
+//!
+//! let dir = tempfile::tempdir()?;
+//! let config = Config::from_dirs(dir.path().join("config"), dir.path().join("state"));
+//!
+//! // A missing nixy.json loads as a single empty "default" profile
+//! let nixy_config = NixyConfig::load(&config)?;
+//! assert_eq!(nixy_config.active_profile, "default");
+//! assert!(nixy_config.get_active_profile().unwrap().resolved_packages.is_empty());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! # Adding a resolved package
+//!
+//! ```
+//! use nixy_core::{Config, NixyConfig, ResolvedNixpkgPackage};
+//!
+//! let dir = tempfile::tempdir()?;
+//! let config = Config::from_dirs(dir.path().join("config"), dir.path().join("state"));
+//!
+//! let mut nixy_config = NixyConfig::load(&config)?;
+//! nixy_config
+//!     .get_active_profile_mut()
+//!     .unwrap()
+//!     .add_resolved_package(ResolvedNixpkgPackage {
+//!         name: "nodejs".to_string(),
+//!         version_spec: Some("20".to_string()),
+//!         resolved_version: "20.11.0".to_string(),
+//!         attribute_path: "nodejs_20".to_string(),
+//!         commit_hash: "abc123def4567890".to_string(),
+//!         platforms: None,
+//!         pinned: false,
+//!         source_name: None,
+//!         output: None,
+//!         package_output: None,
+//!         outputs: None,
+//!         record: Default::default(),
+//!     });
+//! nixy_config.save(&config)?;
+//!
+//! let reloaded = NixyConfig::load(&config)?;
+//! assert!(reloaded.get_active_profile().unwrap().has_package("nodejs"));
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! # Regenerating a profile's flake
+//!
+//! ```
+//! use nixy_core::flake::format::FlakeFormat;
+//! use nixy_core::flake::template::regenerate_flake_from_profile;
+//! use nixy_core::profile::Profile;
+//! use nixy_core::{Config, NixyConfig};
+//!
+//! let dir = tempfile::tempdir()?;
+//! let config = Config::from_dirs(dir.path().join("config"), dir.path().join("state"));
+//!
+//! let mut nixy_config = NixyConfig::load(&config)?;
+//! nixy_config.get_active_profile_mut().unwrap().add_package("ripgrep");
+//!
+//! let profile = Profile::new(&nixy_config.active_profile, &config);
+//! regenerate_flake_from_profile(
+//!     &profile.state_dir,
+//!     nixy_config.get_active_profile().unwrap(),
+//!     Some(&config.global_packages_dir),
+//!     FlakeFormat::Builtin,
+//! )?;
+//!
+//! let flake = std::fs::read_to_string(&profile.flake_path)?;
+//! assert!(flake.contains("ripgrep = pkgs.ripgrep;"));
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Building the result needs Nix: pass the profile's state directory to
+//! [`NixBackend::build`] (or [`Nix::build`]) with an out-link outside it.
+
+pub mod commits;
+pub mod config;
+pub mod error;
+pub mod flake;
+pub mod fonts;
+pub mod manifest;
+pub mod nix;
+pub mod nixhub;
+pub mod nixy_config;
+pub mod profile;
+pub mod snapshot;
+pub mod state;
+
+pub use config::Config;
+pub use error::{Error, Result};
+pub use nix::{Nix, NixBackend};
+pub use nixhub::NixhubClient;
+pub use nixy_config::{NixyConfig, ProfileConfig};
+pub use state::{CustomPackage, PackageState, ResolvedNixpkgPackage};
@@ -1,15 +1,12 @@
 mod cli;
 mod commands;
-mod config;
-mod error;
-mod flake;
 mod migration;
-mod nix;
-mod nixhub;
-mod nixy_config;
-mod profile;
 mod rollback;
-mod state;
+
+use nixy_core::{
+    commits, config, error, flake, fonts, manifest, nix, nixhub, nixy_config, profile, snapshot,
+    state,
+};
 
 use clap::Parser;
 
@@ -23,17 +20,33 @@ fn main() {
     rollback::init_signal_handler();
 
     let cli = Cli::parse();
+    commands::set_assume_yes(cli.yes);
+    commands::set_eval_timeout(cli.timeout);
+    commands::set_quiet(cli.quiet);
+    commands::set_verbose(cli.verbose);
+    if cli.verbose {
+        Nix::set_command_hook(Some(|line| eprintln!("+ {}", line)));
+    }
+    Nix::set_offline(cli.offline);
+    Nix::set_frozen(
+        cli.frozen
+            || std::env::var("NIXY_FROZEN")
+                .is_ok_and(|v| !v.is_empty() && v != "0" && !v.eq_ignore_ascii_case("false")),
+    );
 
     // Meta commands don't touch the Nix store or config state. Skip the nix
     // dependency check so they stay fast (e.g. shell completions run the binary
     // on every <Tab>) and usable even when nix isn't installed.
-    let is_meta = matches!(
-        &cli.command,
-        Commands::Config { .. } | Commands::Completions(_)
-    );
+    // `list --count` and `list --json` only read config, for use in shell
+    // prompts and scripts.
+    let is_meta = matches!(&cli.command, Commands::Config(_) | Commands::Completions(_))
+        || matches!(&cli.command, Commands::List(args) if args.count || args.json);
+
+    // doctor reports a missing nix itself, and must not migrate anything
+    let is_doctor = matches!(&cli.command, Commands::Doctor(_));
 
     // Check dependencies
-    if !is_meta {
+    if !is_meta && !is_doctor {
         if let Err(e) = Nix::check_installed() {
             commands::error(&e.to_string());
             std::process::exit(1);
@@ -43,8 +56,12 @@ fn main() {
     let config = Config::new();
 
     // Commands that don't need config state (skip migration)
-    let skip_migration =
-        is_meta || matches!(&cli.command, Commands::Search { .. } | Commands::Upgrade(_));
+    let skip_migration = is_meta
+        || is_doctor
+        || matches!(
+            &cli.command,
+            Commands::Search(_) | Commands::Run(_) | Commands::Upgrade(_)
+        );
 
     // Auto-migrate from legacy format if needed
     if !skip_migration {
@@ -57,14 +74,28 @@ fn main() {
     let result = match cli.command {
         Commands::Install(args) => commands::install::run(&config, args),
         Commands::Uninstall(args) => commands::uninstall::run(&config, args),
-        Commands::List => commands::list::run(&config),
-        Commands::Search { query } => commands::search::run(&query),
+        Commands::List(args) => commands::list::run(&config, args),
+        Commands::Search(args) => commands::search::run(&config, args),
+        Commands::Run(args) => commands::run::run(&config, args),
+        Commands::Shell(args) => commands::shell::run(&config, args),
         Commands::Update(args) => commands::update::run(&config, args),
-        Commands::Sync(_) => commands::sync::run(&config),
-        Commands::Config { shell } => commands::config::run(&shell),
+        Commands::UndoUpdate(args) => commands::undo_update::run(&config, args),
+        Commands::Rollback(args) => commands::rollback::run(&config, args),
+        Commands::Sync(args) => commands::sync::run(&config, args),
+        Commands::Config(args) => commands::config::run(&config, args),
         Commands::Profile(args) => commands::profile::run(&config, args),
+        Commands::Profiles => commands::profile::run(&config, Default::default()),
+        Commands::Diff(args) => commands::diff::run(&config, args),
+        Commands::Export(args) => commands::export::run(&config, args),
+        Commands::Import(args) => commands::import::run(&config, args),
         Commands::Upgrade(args) => commands::upgrade::run(args.force),
         Commands::File(args) => commands::file::run(&config, args),
+        Commands::Info(args) => commands::info::run(&config, args),
+        Commands::Which(args) => commands::which::run(&config, args),
+        Commands::Why(args) => commands::why::run(&config, args),
+        Commands::Platform(args) => commands::platform::run(&config, args),
+        Commands::Gc(args) => commands::gc::run(&config, args),
+        Commands::Doctor(args) => commands::doctor::run(&config, args),
         Commands::Completions(args) => commands::completions::run(&config, &args.kind),
     };
 
@@ -74,6 +105,8 @@ fn main() {
                 // Usage errors don't need "Error:" prefix
                 eprintln!("{}", msg);
             }
+            // The program `nixy run` started has said what went wrong
+            Error::CommandExited(code) => std::process::exit(code),
             _ => {
                 commands::error(&e.to_string());
             }
@@ -0,0 +1,664 @@
+//! Portable profile manifests for `nixy export` and `nixy import`.
+//!
+//! A manifest is one JSON document holding a profile's entry from nixy.json
+//! (packages, resolved packages with their commits, custom packages and
+//! settings) together with the contents of the local package files in the
+//! global `packages/` directory, which every profile builds. Importing it on
+//! another machine recreates the profile without copying `~/.config/nixy`.
+//!
+//! `nixy import` also takes a profile out of another nixy.json
+//! ([`Manifest::from_nixy_json`]) or recovers one from a flake.nix nixy
+//! generated ([`Manifest::from_flake`]), and imports it the same way.
+//!
+//! ```text
+//! {
+//!   "version": 1,
+//!   "profile": "default",
+//!   "resolved_packages": [ ... ],
+//!   "custom_packages": [ ... ],
+//!   "local_packages": { "mytool.nix": "{ stdenv, ... }: ..." }
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::flake::lock::declared_inputs;
+use crate::flake::parser::parse_generated_packages;
+use crate::nixy_config::{NixyConfig, ProfileConfig};
+use crate::state::{CustomPackage, InstallRecord, ResolvedNixpkgPackage};
+
+/// Current version of the manifest format
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Files of a local flake directory that go into a manifest
+const LOCAL_FLAKE_FILES: &[&str] = &["flake.nix", "flake.lock"];
+
+/// A profile as written by `nixy export`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    /// Name of the exported profile, used when importing without a name
+    pub profile: String,
+    #[serde(flatten)]
+    pub config: ProfileConfig,
+    /// Local package files by their path in `packages/`: `<file>.nix`, or
+    /// `<dir>/flake.nix` and `<dir>/flake.lock` for a local flake
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub local_packages: BTreeMap<String, String>,
+}
+
+/// Version recorded for packages recovered from a flake.nix whose version
+/// could not be looked up
+pub const UNKNOWN_VERSION: &str = "unknown";
+
+/// A package or local file that an import would replace with something else
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportConflict {
+    /// Package name, or `packages/<path>` for a local file
+    pub name: String,
+    /// Where the installed one comes from
+    pub existing: String,
+    /// Where the manifest's comes from
+    pub incoming: String,
+}
+
+impl Manifest {
+    /// Capture `profile` and the local packages in `packages_dir`
+    pub fn from_profile(name: &str, profile: &ProfileConfig, packages_dir: &Path) -> Result<Self> {
+        Ok(Self {
+            version: MANIFEST_VERSION,
+            profile: name.to_string(),
+            config: profile.clone(),
+            local_packages: read_local_packages(packages_dir)?,
+        })
+    }
+
+    /// Read a manifest, refusing newer formats and local file paths that
+    /// would land outside `packages/`
+    pub fn parse(content: &str) -> Result<Self> {
+        let manifest: Self =
+            serde_json::from_str(content).map_err(|e| Error::InvalidManifest(e.to_string()))?;
+        if manifest.version > MANIFEST_VERSION {
+            return Err(Error::InvalidManifest(format!(
+                "version {} is newer than this nixy supports ({}); upgrade nixy",
+                manifest.version, MANIFEST_VERSION
+            )));
+        }
+        if let Some(path) = manifest
+            .local_packages
+            .keys()
+            .find(|path| !is_local_package_path(path))
+        {
+            return Err(Error::InvalidManifest(format!(
+                "'{}' is not a local package file",
+                path
+            )));
+        }
+        Ok(manifest)
+    }
+
+    /// Profile `profile` of a nixy.json (its active one if `None`), with the
+    /// local packages in `packages_dir` (the `packages/` next to it) if given
+    pub fn from_nixy_json(
+        content: &str,
+        profile: Option<&str>,
+        packages_dir: Option<&Path>,
+    ) -> Result<Self> {
+        let nixy_config: NixyConfig =
+            serde_json::from_str(content).map_err(|e| Error::InvalidManifest(e.to_string()))?;
+        let name = profile.unwrap_or(&nixy_config.active_profile);
+        let config = nixy_config
+            .profiles
+            .get(name)
+            .ok_or_else(|| Error::ProfileNotFound(name.to_string()))?;
+        Ok(Self {
+            version: MANIFEST_VERSION,
+            profile: name.to_string(),
+            config: config.clone(),
+            local_packages: read_optional_local_packages(packages_dir)?,
+        })
+    }
+
+    /// The packages of a flake.nix generated by nixy, recovered from its
+    /// package set and inputs, with the local packages in `packages_dir` if
+    /// given
+    ///
+    /// Packages from a `github:NixOS/nixpkgs/<commit>` input become resolved
+    /// packages with version [`UNKNOWN_VERSION`], those of other inputs
+    /// custom packages, and `pkgs.<name>` plain nixpkgs packages. Local
+    /// packages and entries of any other shape (hand-written ones) are left
+    /// out; their names are returned alongside. Platform restrictions are
+    /// not recovered.
+    pub fn from_flake(
+        name: &str,
+        content: &str,
+        packages_dir: Option<&Path>,
+    ) -> Result<(Self, Vec<String>)> {
+        let generated = parse_generated_packages(content).ok_or_else(|| {
+            Error::InvalidManifest("not a nixy manifest, nixy.json or nixy flake.nix".to_string())
+        })?;
+        let inputs = declared_inputs(content);
+
+        let mut config = ProfileConfig::default();
+        let mut skipped = Vec::new();
+        for package in &generated.paths {
+            let Some(expr) = generated.bindings.get(package) else {
+                skipped.push(package.clone());
+                continue;
+            };
+            match flake_entry(package, expr, &inputs) {
+                Some(FlakeEntry::Nixpkgs) => config.add_package(package),
+                Some(FlakeEntry::Resolved(pkg)) => config.add_resolved_package(pkg),
+                Some(FlakeEntry::Custom(pkg)) => config.add_custom_package(pkg),
+                None => skipped.push(package.clone()),
+            }
+        }
+
+        let manifest = Self {
+            version: MANIFEST_VERSION,
+            profile: name.to_string(),
+            config,
+            local_packages: read_optional_local_packages(packages_dir)?,
+        };
+        // Local packages come with their files
+        skipped.retain(|package| !manifest.has_local_package(package));
+        Ok((manifest, skipped))
+    }
+
+    /// Whether `name` is one of the manifest's local package files
+    fn has_local_package(&self, name: &str) -> bool {
+        self.local_packages.contains_key(&format!("{}.nix", name))
+            || self
+                .local_packages
+                .contains_key(&format!("{}/flake.nix", name))
+    }
+
+    /// Pretty-printed JSON, ending with a newline
+    pub fn to_json(&self) -> Result<String> {
+        let mut content = serde_json::to_string_pretty(self)
+            .map_err(|e| Error::InvalidManifest(e.to_string()))?;
+        content.push('\n');
+        Ok(content)
+    }
+
+    /// Number of packages in the profile, local ones not included
+    pub fn package_count(&self) -> usize {
+        self.config.packages.len()
+            + self.config.resolved_packages.len()
+            + self.config.custom_packages.len()
+    }
+
+    /// Packages of `existing` that the manifest has under the same name from
+    /// another source, and files in `packages_dir` it would overwrite with
+    /// other contents
+    pub fn conflicts(
+        &self,
+        existing: Option<&ProfileConfig>,
+        packages_dir: &Path,
+    ) -> Vec<ImportConflict> {
+        let mut conflicts = Vec::new();
+        if let Some(existing) = existing {
+            let installed = package_sources(existing);
+            for (name, incoming) in package_sources(&self.config) {
+                match installed.get(&name) {
+                    Some(existing) if *existing != incoming => conflicts.push(ImportConflict {
+                        name,
+                        existing: existing.clone(),
+                        incoming,
+                    }),
+                    _ => {}
+                }
+            }
+        }
+        for (path, content) in &self.local_packages {
+            match fs::read_to_string(packages_dir.join(path)) {
+                Ok(current) if current != *content => conflicts.push(ImportConflict {
+                    name: format!("packages/{}", path),
+                    existing: "local file".to_string(),
+                    incoming: "different contents".to_string(),
+                }),
+                _ => {}
+            }
+        }
+        conflicts
+    }
+
+    /// Put the manifest's packages into `profile`: replace it entirely, or
+    /// with `merge` add them to what it has, keeping its settings
+    pub fn apply(&self, profile: &mut ProfileConfig, merge: bool) {
+        if !merge {
+            *profile = self.config.clone();
+            return;
+        }
+        for name in &self.config.packages {
+            profile.remove_package(name);
+            profile.add_package(name);
+        }
+        for pkg in &self.config.resolved_packages {
+            profile.remove_package(&pkg.name);
+            profile.add_resolved_package(pkg.clone());
+        }
+        for pkg in &self.config.custom_packages {
+            profile.remove_package(&pkg.name);
+            profile.add_custom_package(pkg.clone());
+        }
+    }
+
+    /// Write the local package files into `packages_dir`; the paths of those
+    /// that did not exist before
+    pub fn write_local_packages(&self, packages_dir: &Path) -> Result<Vec<String>> {
+        let mut created = Vec::new();
+        for (path, content) in &self.local_packages {
+            let target = packages_dir.join(path);
+            if !target.exists() {
+                created.push(path.clone());
+            }
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&target, content)?;
+        }
+        Ok(created)
+    }
+}
+
+/// A package entry of a generated flake.nix, as it goes into a profile
+enum FlakeEntry {
+    Nixpkgs,
+    Resolved(ResolvedNixpkgPackage),
+    Custom(CustomPackage),
+}
+
+/// `expr` without the `meta.outputsToInstall` override nixy wraps a package
+/// installed with `--outputs` in, and those outputs
+fn strip_outputs_to_install(expr: &str) -> (&str, Option<Vec<String>>) {
+    let parsed = expr.strip_prefix("let p = ").and_then(|rest| {
+        let (attribute, rest) = rest.split_once("; in p // ")?;
+        let (_, outputs) = rest.split_once("outputsToInstall = [")?;
+        let (outputs, _) = outputs.split_once(']')?;
+        let outputs = outputs
+            .split_whitespace()
+            .map(|o| o.trim_matches('"').to_string())
+            .collect();
+        Some((attribute, Some(outputs)))
+    });
+    parsed.unwrap_or((expr, None))
+}
+
+/// What `name = expr;` in the package set of a generated flake.nix with
+/// `inputs` (name -> URL) was installed as, if it is an entry nixy writes
+fn flake_entry(name: &str, expr: &str, inputs: &BTreeMap<String, String>) -> Option<FlakeEntry> {
+    let expr = expr.trim();
+    if expr.strip_prefix("pkgs.") == Some(name) {
+        return Some(FlakeEntry::Nixpkgs);
+    }
+
+    let (expr, outputs) = strip_outputs_to_install(expr);
+
+    // inputs.<input>.<output>.${system}.<attribute>, with a fallback to the
+    // other output (` or ...`) when it was installed without validation
+    let (expr, unvalidated) = match expr.split_once(" or ") {
+        Some((first, _)) => (first, true),
+        None => (expr, false),
+    };
+    let rest = expr.strip_prefix("inputs.")?;
+    let (input, rest) = rest.split_once('.')?;
+    let (output, attribute) = rest.split_once(".${system}.")?;
+    let url = inputs.get(input)?;
+    let attribute = attribute.trim_matches('"').to_string();
+
+    if let Some(commit) = url.strip_prefix("github:NixOS/nixpkgs/") {
+        if !commit.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        return Some(FlakeEntry::Resolved(ResolvedNixpkgPackage {
+            name: name.to_string(),
+            version_spec: None,
+            resolved_version: UNKNOWN_VERSION.to_string(),
+            source_name: (attribute != name).then(|| attribute.clone()),
+            attribute_path: attribute,
+            commit_hash: commit.to_string(),
+            platforms: None,
+            pinned: false,
+            output: None,
+            package_output: (output != "legacyPackages").then(|| output.to_string()),
+            outputs,
+            record: InstallRecord::now(),
+        }));
+    }
+    // Local flakes are imported as files
+    if url.starts_with("path:") {
+        return None;
+    }
+    Some(FlakeEntry::Custom(CustomPackage {
+        name: name.to_string(),
+        input_name: input.to_string(),
+        input_url: url.clone(),
+        package_output: output.to_string(),
+        source_name: (attribute != name).then_some(attribute),
+        platforms: None,
+        unvalidated,
+        record: InstallRecord::now(),
+    }))
+}
+
+/// Where each package of `profile` comes from, to compare two profiles by
+fn package_sources(profile: &ProfileConfig) -> BTreeMap<String, String> {
+    let legacy = profile
+        .packages
+        .iter()
+        .map(|name| (name.clone(), "nixpkgs".to_string()));
+    let resolved = profile.resolved_packages.iter().map(|pkg| {
+        (
+            pkg.name.clone(),
+            format!(
+                "nixpkgs {}@{} (commit {})",
+                pkg.attribute(),
+                pkg.resolved_version,
+                &pkg.commit_hash[..8.min(pkg.commit_hash.len())]
+            ),
+        )
+    });
+    let custom = profile
+        .custom_packages
+        .iter()
+        .map(|pkg| (pkg.name.clone(), pkg.input_url.clone()));
+    legacy.chain(resolved).chain(custom).collect()
+}
+
+/// The `.nix` files in `packages_dir` and the flake files of its local
+/// flake directories, by path relative to it
+fn read_local_packages(packages_dir: &Path) -> Result<BTreeMap<String, String>> {
+    let mut files = BTreeMap::new();
+    let Ok(entries) = fs::read_dir(packages_dir) else {
+        return Ok(files);
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if path.is_dir() {
+            // A directory without flake.nix is not a local package
+            if !path.join("flake.nix").is_file() {
+                continue;
+            }
+            for file in LOCAL_FLAKE_FILES {
+                if path.join(file).is_file() {
+                    files.insert(
+                        format!("{}/{}", name, file),
+                        fs::read_to_string(path.join(file))?,
+                    );
+                }
+            }
+        } else if path.is_file() && is_local_package_path(name) {
+            files.insert(name.to_string(), fs::read_to_string(&path)?);
+        }
+    }
+    Ok(files)
+}
+
+/// [`read_local_packages`], or none without a directory
+fn read_optional_local_packages(packages_dir: Option<&Path>) -> Result<BTreeMap<String, String>> {
+    match packages_dir {
+        Some(dir) => read_local_packages(dir),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// Whether `path` names a local package file directly in `packages/` or a
+/// flake file one directory down, without leaving it
+fn is_local_package_path(path: &str) -> bool {
+    let plain = |segment: &str| !segment.is_empty() && !segment.starts_with('.');
+    match path.split('/').collect::<Vec<_>>()[..] {
+        [file] => plain(file) && file.ends_with(".nix") && file != ".nix",
+        [dir, file] => plain(dir) && LOCAL_FLAKE_FILES.contains(&file),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{CustomPackage, ResolvedNixpkgPackage};
+    use tempfile::TempDir;
+
+    fn resolved(name: &str, version: &str, commit: &str) -> ResolvedNixpkgPackage {
+        ResolvedNixpkgPackage {
+            name: name.to_string(),
+            version_spec: None,
+            resolved_version: version.to_string(),
+            attribute_path: name.to_string(),
+            commit_hash: commit.to_string(),
+            platforms: None,
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
+        }
+    }
+
+    fn custom(name: &str, url: &str) -> CustomPackage {
+        CustomPackage {
+            name: name.to_string(),
+            input_name: name.to_string(),
+            input_url: url.to_string(),
+            package_output: "packages".to_string(),
+            source_name: None,
+            platforms: None,
+            unvalidated: false,
+            record: Default::default(),
+        }
+    }
+
+    fn profile() -> ProfileConfig {
+        ProfileConfig {
+            packages: vec!["fd".to_string()],
+            resolved_packages: vec![resolved("hello", "2.12.1", "aaaaaaaaaaaa")],
+            custom_packages: vec![custom("tool", "github:owner/tool")],
+            link_fonts: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_manifest_round_trips_with_local_packages() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("mine.nix"), "{ stdenv }: stdenv").unwrap();
+        fs::write(dir.path().join("notes.txt"), "not a package").unwrap();
+        fs::create_dir_all(dir.path().join("flaky")).unwrap();
+        fs::write(dir.path().join("flaky/flake.nix"), "{ outputs = _: { }; }").unwrap();
+        fs::write(dir.path().join("flaky/README"), "ignored").unwrap();
+        fs::create_dir_all(dir.path().join("empty")).unwrap();
+
+        let manifest = Manifest::from_profile("work", &profile(), dir.path()).unwrap();
+        let keys: Vec<&String> = manifest.local_packages.keys().collect();
+        assert_eq!(keys, vec!["flaky/flake.nix", "mine.nix"]);
+
+        let parsed = Manifest::parse(&manifest.to_json().unwrap()).unwrap();
+        assert_eq!(parsed.profile, "work");
+        assert_eq!(parsed.package_count(), 3);
+        assert!(parsed.config.link_fonts);
+        assert_eq!(parsed.config.resolved_packages, profile().resolved_packages);
+        assert_eq!(parsed.local_packages, manifest.local_packages);
+    }
+
+    #[test]
+    fn test_parse_rejects_paths_outside_packages_dir() {
+        for path in [
+            "../evil.nix",
+            "/etc/evil.nix",
+            "a/b/flake.nix",
+            "dir/other.nix",
+            ".nix",
+        ] {
+            let content = serde_json::json!({
+                "version": 1,
+                "profile": "default",
+                "local_packages": { path: "" },
+            });
+            assert!(
+                matches!(
+                    Manifest::parse(&content.to_string()),
+                    Err(Error::InvalidManifest(_))
+                ),
+                "{} should be rejected",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_newer_versions() {
+        let content = r#"{ "version": 2, "profile": "default" }"#;
+        assert!(matches!(
+            Manifest::parse(content),
+            Err(Error::InvalidManifest(_))
+        ));
+    }
+
+    #[test]
+    fn test_conflicts_and_merge() {
+        let dir = TempDir::new().unwrap();
+        let mut manifest = Manifest::from_profile("default", &profile(), dir.path()).unwrap();
+        manifest
+            .local_packages
+            .insert("mine.nix".to_string(), "new".to_string());
+        fs::write(dir.path().join("mine.nix"), "old").unwrap();
+
+        let mut existing = ProfileConfig {
+            packages: vec!["fd".to_string(), "jq".to_string()],
+            resolved_packages: vec![resolved("hello", "2.12.0", "bbbbbbbbbbbb")],
+            custom_packages: vec![custom("tool", "github:owner/tool")],
+            ..Default::default()
+        };
+        let conflicts = manifest.conflicts(Some(&existing), dir.path());
+        assert_eq!(
+            conflicts,
+            vec![
+                ImportConflict {
+                    name: "hello".to_string(),
+                    existing: "nixpkgs hello@2.12.0 (commit bbbbbbbb)".to_string(),
+                    incoming: "nixpkgs hello@2.12.1 (commit aaaaaaaa)".to_string(),
+                },
+                ImportConflict {
+                    name: "packages/mine.nix".to_string(),
+                    existing: "local file".to_string(),
+                    incoming: "different contents".to_string(),
+                },
+            ]
+        );
+        assert_eq!(manifest.conflicts(None, dir.path()).len(), 1);
+
+        manifest.apply(&mut existing, true);
+        assert_eq!(existing.packages, vec!["fd", "jq"]);
+        assert_eq!(existing.resolved_packages[0].resolved_version, "2.12.1");
+        assert!(!existing.link_fonts, "merging keeps the profile's settings");
+
+        manifest.apply(&mut existing, false);
+        assert_eq!(existing.packages, vec!["fd"]);
+        assert!(existing.link_fonts);
+    }
+
+    #[test]
+    fn test_write_local_packages_reports_new_files() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("mine.nix"), "old").unwrap();
+        let manifest = Manifest {
+            version: MANIFEST_VERSION,
+            profile: "default".to_string(),
+            config: ProfileConfig::default(),
+            local_packages: BTreeMap::from([
+                ("mine.nix".to_string(), "new".to_string()),
+                ("flaky/flake.nix".to_string(), "{ }".to_string()),
+            ]),
+        };
+
+        assert_eq!(
+            manifest.write_local_packages(dir.path()).unwrap(),
+            vec!["flaky/flake.nix"]
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("mine.nix")).unwrap(),
+            "new"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("flaky/flake.nix")).unwrap(),
+            "{ }"
+        );
+    }
+
+    #[test]
+    fn test_from_flake_recovers_generated_entries() {
+        let dir = TempDir::new().unwrap();
+        let mut profile = profile();
+        let mut renamed = resolved("node", "20.11.0", "bbbbbbbbbbbb");
+        renamed.attribute_path = "nodejs_20".to_string();
+        renamed.outputs = Some(vec!["out".to_string(), "dev".to_string()]);
+        profile.resolved_packages.push(renamed);
+        profile.custom_packages[0].unvalidated = true;
+        let flake = crate::flake::template::generate_flake_from_profile(
+            &profile,
+            None,
+            &dir.path().join("state"),
+        )
+        .unwrap()
+        .replace(
+            "          fd = pkgs.fd;",
+            "          fd = pkgs.fd;\n          mine = pkgs.hello.override { };",
+        )
+        .replace(
+            "              fd\n",
+            "              fd\n              mine\n",
+        );
+
+        let (manifest, skipped) = Manifest::from_flake("work", &flake, None).unwrap();
+        assert_eq!(skipped, vec!["mine"]);
+        assert_eq!(manifest.profile, "work");
+        assert_eq!(manifest.config.packages, vec!["fd"]);
+        let resolved = &manifest.config.resolved_packages;
+        assert_eq!(resolved.len(), 2);
+        assert_eq!(resolved[0].name, "hello");
+        assert_eq!(resolved[0].commit_hash, "aaaaaaaaaaaa");
+        assert_eq!(resolved[0].resolved_version, UNKNOWN_VERSION);
+        assert_eq!(resolved[1].name, "node");
+        assert_eq!(resolved[1].attribute_path, "nodejs_20");
+        assert_eq!(
+            resolved[1].outputs,
+            Some(vec!["out".to_string(), "dev".to_string()])
+        );
+        let custom = &manifest.config.custom_packages[0];
+        assert_eq!(custom.input_url, "github:owner/tool");
+        assert_eq!(custom.package_output, "packages");
+        assert!(custom.unvalidated);
+    }
+
+    #[test]
+    fn test_from_nixy_json_takes_one_profile() {
+        let content = serde_json::json!({
+            "version": 2,
+            "active_profile": "work",
+            "profiles": {
+                "default": { "packages": ["fd"] },
+                "work": { "packages": ["jq", "git"] },
+            },
+        })
+        .to_string();
+        let manifest = Manifest::from_nixy_json(&content, None, None).unwrap();
+        assert_eq!(manifest.profile, "work");
+        assert_eq!(manifest.config.packages, vec!["jq", "git"]);
+        let manifest = Manifest::from_nixy_json(&content, Some("default"), None).unwrap();
+        assert_eq!(manifest.config.packages, vec!["fd"]);
+        assert!(matches!(
+            Manifest::from_nixy_json(&content, Some("home"), None),
+            Err(Error::ProfileNotFound(_))
+        ));
+    }
+}
@@ -40,10 +40,16 @@ use std::path::Path;
 
 use crate::config::{Config, DEFAULT_PROFILE};
 use crate::error::Result;
+use crate::flake::format::FlakeFormat;
 use crate::flake::template::regenerate_flake_from_profile;
 use crate::nixy_config::{NixyConfig, ProfileConfig, NIXY_CONFIG_VERSION};
+use crate::profile::{validate_profile_name, Profile};
 use crate::state::PackageState;
 
+/// needs-sync reason for profiles whose flake was regenerated by migration;
+/// the existing environment still points at the legacy flake
+const MIGRATED_REASON: &str = "migrated to nixy.json";
+
 /// Check if migration is needed.
 ///
 /// Migration is needed when:
@@ -95,6 +101,11 @@ pub fn migrate_to_nixy_json(config: &Config) -> Result<NixyConfig> {
         version: NIXY_CONFIG_VERSION,
         active_profile: DEFAULT_PROFILE.to_string(),
         profiles: std::collections::BTreeMap::new(),
+        format: FlakeFormat::default(),
+        strict_local: false,
+        auto_commit: false,
+        nixhub_cache_hours: None,
+        local_hashes: Default::default(),
     };
 
     // Read active profile from legacy file
@@ -150,21 +161,15 @@ pub fn migrate_to_nixy_json(config: &Config) -> Result<NixyConfig> {
 
     // Second pass: migrate profiles and generate flakes
     for (name, path, profile_config) in profile_dirs {
-        nixy_config
-            .profiles
-            .insert(name.clone(), profile_config.clone());
-
-        // Create state directory and copy only flake.lock (to preserve versions)
-        let state_profile_dir = config.profiles_state_dir.join(&name);
-        fs::create_dir_all(&state_profile_dir)?;
-
-        let legacy_lock = path.join("flake.lock");
-        if legacy_lock.exists() {
-            fs::copy(&legacy_lock, state_profile_dir.join("flake.lock"))?;
-        }
-
-        // Regenerate flake.nix with correct paths for new directory structure
-        regenerate_flake_from_profile(&state_profile_dir, &profile_config, global_packages_dir)?;
+        move_profile_state(
+            config,
+            &name,
+            &path,
+            &profile_config,
+            global_packages_dir,
+            nixy_config.format,
+        )?;
+        nixy_config.profiles.insert(name, profile_config);
     }
 
     // Handle very old format (flake.nix directly in config dir)
@@ -186,7 +191,13 @@ pub fn migrate_to_nixy_json(config: &Config) -> Result<NixyConfig> {
         }
 
         // Regenerate flake.nix with correct paths (global_packages_dir already computed above)
-        regenerate_flake_from_profile(&state_profile_dir, &profile_config, global_packages_dir)?;
+        regenerate_flake_from_profile(
+            &state_profile_dir,
+            &profile_config,
+            global_packages_dir,
+            nixy_config.format,
+        )?;
+        Profile::new(DEFAULT_PROFILE, config).mark_needs_sync(MIGRATED_REASON)?;
     }
 
     // Ensure default profile exists
@@ -207,6 +218,80 @@ pub fn migrate_to_nixy_json(config: &Config) -> Result<NixyConfig> {
     Ok(nixy_config)
 }
 
+/// Set up the state directory of a profile migrated from `profile_dir`: its
+/// flake.lock (to preserve versions) and a flake.nix generated from
+/// `profile_config`, to be built on the next sync
+fn move_profile_state(
+    config: &Config,
+    name: &str,
+    profile_dir: &Path,
+    profile_config: &ProfileConfig,
+    global_packages_dir: Option<&Path>,
+    format: FlakeFormat,
+) -> Result<()> {
+    let state_profile_dir = config.profiles_state_dir.join(name);
+    fs::create_dir_all(&state_profile_dir)?;
+
+    let legacy_lock = profile_dir.join("flake.lock");
+    if legacy_lock.exists() {
+        fs::copy(&legacy_lock, state_profile_dir.join("flake.lock"))?;
+    }
+
+    // Regenerate flake.nix with correct paths for new directory structure
+    regenerate_flake_from_profile(
+        &state_profile_dir,
+        profile_config,
+        global_packages_dir,
+        format,
+    )?;
+    Profile::new(name, config).mark_needs_sync(MIGRATED_REASON)
+}
+
+/// Legacy profile directories with a packages.json that nixy.json has no
+/// profile for, e.g. created by an old nixy after the migration
+pub fn unmigrated_profiles(config: &Config, nixy_config: &NixyConfig) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(&config.profiles_dir) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.path().join("packages.json").exists())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| !nixy_config.profiles.contains_key(name))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Add the [`unmigrated_profiles`] to nixy.json, returning their names
+pub fn migrate_leftover_profiles(config: &Config) -> Result<Vec<String>> {
+    let mut nixy_config = NixyConfig::load(config)?;
+    let names = unmigrated_profiles(config, &nixy_config);
+    for name in &names {
+        validate_profile_name(name)?;
+        let path = config.profiles_dir.join(name);
+        merge_local_packages(&path.join("packages"), &config.global_packages_dir)?;
+        let profile_config = migrate_profile(&path)?;
+        let global_packages_dir = config
+            .global_packages_dir
+            .exists()
+            .then_some(config.global_packages_dir.as_path());
+        move_profile_state(
+            config,
+            name,
+            &path,
+            &profile_config,
+            global_packages_dir,
+            nixy_config.format,
+        )?;
+        nixy_config.profiles.insert(name.clone(), profile_config);
+    }
+    if !names.is_empty() {
+        nixy_config.save(config)?;
+    }
+    Ok(names)
+}
+
 /// Migrate a single profile from its directory.
 fn migrate_profile(profile_dir: &Path) -> Result<ProfileConfig> {
     let state_path = profile_dir.join("packages.json");
@@ -431,6 +516,12 @@ mod tests {
                 attribute_path: "nodejs_20".to_string(),
                 commit_hash: "abc123".to_string(),
                 platforms: None,
+                pinned: false,
+                source_name: None,
+                output: None,
+                package_output: None,
+                outputs: None,
+                record: Default::default(),
             }],
             custom_packages: vec![CustomPackage {
                 name: "neovim".to_string(),
@@ -439,6 +530,8 @@ mod tests {
                 package_output: "packages".to_string(),
                 source_name: None,
                 platforms: None,
+                unvalidated: false,
+                record: Default::default(),
             }],
         };
         state.save(&profile_dir.join("packages.json")).unwrap();
@@ -597,4 +690,29 @@ mod tests {
         // Broken symlink should be skipped (not cause an error)
         assert!(!config.global_packages_dir.join("broken.nix").exists());
     }
+
+    #[test]
+    fn test_unmigrated_profiles() {
+        let temp = TempDir::new().unwrap();
+        let config = test_config(&temp);
+        for name in ["default", "work", "old"] {
+            let dir = config.profiles_dir.join(name);
+            fs::create_dir_all(&dir).unwrap();
+            PackageState::default()
+                .save(&dir.join("packages.json"))
+                .unwrap();
+        }
+        // Without packages.json there is nothing to migrate
+        fs::create_dir_all(config.profiles_dir.join("empty")).unwrap();
+
+        let mut nixy_config = NixyConfig::default();
+        nixy_config
+            .profiles
+            .insert("work".to_string(), ProfileConfig::default());
+
+        assert_eq!(
+            unmigrated_profiles(&config, &nixy_config),
+            vec!["old".to_string()]
+        );
+    }
 }
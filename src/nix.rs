@@ -8,15 +8,178 @@
 //! - Captures stderr for better error messages
 //! - Handles path escaping for flake references
 
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, RwLock};
+use std::time::{Duration, Instant};
 
+use regex::Regex;
+
+use crate::commits;
 use crate::config::NIX_FLAGS;
 use crate::error::{Error, Result};
 
 /// Wrapper for Nix command execution
 pub struct Nix;
 
+/// Set with [`Nix::set_frozen`]: no command may change a profile's flake.lock
+static FROZEN: AtomicBool = AtomicBool::new(false);
+
+/// Set with [`Nix::set_offline`]: nothing may be downloaded
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Set with [`Nix::set_command_hook`]: told every nix command before it runs
+static COMMAND_HOOK: RwLock<Option<fn(&str)>> = RwLock::new(None);
+
+/// What nix reports when `--no-update-lock-file` stops it from locking
+const LOCK_PROHIBITED: &str = "prohibited by --no-update-lock-file";
+
+/// `error: attribute 'x' missing`, as nix reports a bad attribute path
+static MISSING_ATTRIBUTE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"attribute '([^']+)' missing").expect("Invalid regex pattern"));
+
+/// The line nix closes a garbage collection with, e.g.
+/// `12 store paths deleted, 3.40 MiB freed`
+static GC_FREED_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(\d+) store paths? deleted, ([\d.]+ \S+) freed").expect("Invalid regex pattern")
+});
+
+/// What a garbage collection reported freeing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GcFreed {
+    pub paths: u64,
+    /// As nix formats it, e.g. "3.40 MiB"
+    pub space: String,
+}
+
+/// The last summary in a garbage collection's output, if nix printed one
+/// nixy can read
+pub fn gc_freed(log: &str) -> Option<GcFreed> {
+    let c = GC_FREED_REGEX.captures_iter(log).last()?;
+    Some(GcFreed {
+        paths: c[1].parse().ok()?,
+        space: c[2].to_string(),
+    })
+}
+
+/// Outcome of [`Nix::check_flake_package`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlakePackageCheck {
+    /// The flake has the package under this output (`packages` or
+    /// `legacyPackages`)
+    Found(String),
+    /// The flake has no such package
+    Missing,
+    /// Evaluating the flake did not finish in time
+    TimedOut,
+}
+
+/// A package found by [`Nix::search_rev`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct SearchResult {
+    /// Attribute name to install it by, without `legacyPackages.<system>.`
+    pub name: String,
+    pub version: Option<String>,
+    pub description: String,
+}
+
+/// Parse the output of `nix search --json` into results sorted by name
+///
+/// Empty output counts as no results.
+pub fn parse_search_json(content: &str) -> Result<Vec<SearchResult>> {
+    #[derive(serde::Deserialize)]
+    struct Entry {
+        #[serde(default)]
+        version: String,
+        #[serde(default)]
+        description: String,
+    }
+
+    if content.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let entries: std::collections::BTreeMap<String, Entry> = serde_json::from_str(content)
+        .map_err(|e| Error::NixCommand(format!("Unexpected nix search output: {}", e)))?;
+    let mut results: Vec<SearchResult> = entries
+        .into_iter()
+        .map(|(attr, entry)| {
+            // legacyPackages.x86_64-linux.python3Packages.requests
+            let name = match attr.splitn(3, '.').collect::<Vec<_>>()[..] {
+                ["legacyPackages" | "packages", _, name] => name.to_string(),
+                _ => attr.clone(),
+            };
+            SearchResult {
+                name,
+                version: (!entry.version.is_empty()).then_some(entry.version),
+                description: entry.description,
+            }
+        })
+        .collect();
+    results.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(results)
+}
+
+/// The features in `nix config show experimental-features` output, or in the
+/// `experimental-features = ...` line of `nix show-config`
+fn parse_features(output: &str) -> Vec<String> {
+    let value = output
+        .lines()
+        .find_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            (key.trim() == "experimental-features").then_some(value)
+        })
+        .unwrap_or(output);
+    value.split_whitespace().map(String::from).collect()
+}
+
+/// The Nix operations nixy's build and install flows depend on.
+///
+/// [`Nix`] implements this by running the `nix` CLI. Library users can supply
+/// their own implementation (for example a fake that records calls in tests)
+/// wherever a backend is accepted.
+pub trait NixBackend {
+    /// The current system double (e.g. "x86_64-linux")
+    fn current_system(&self) -> Result<String>;
+
+    /// Build `<flake_dir>#<output>` and point `out_link` at the result
+    fn build(&self, flake_dir: &Path, output: &str, out_link: &Path) -> Result<()>;
+
+    /// Re-lock the given inputs of the flake in `flake_dir`
+    fn flake_update(&self, flake_dir: &Path, inputs: &[String]) -> Result<()>;
+
+    /// Re-lock every input of the flake in `flake_dir`
+    fn flake_update_all(&self, flake_dir: &Path) -> Result<()>;
+
+    /// Find `pkg` in a flake, returning the output set ("packages" or
+    /// "legacyPackages") that contains it
+    fn validate_flake_package(&self, flake_url: &str, pkg: &str) -> Result<Option<String>>;
+}
+
+impl NixBackend for Nix {
+    fn current_system(&self) -> Result<String> {
+        Nix::current_system()
+    }
+
+    fn build(&self, flake_dir: &Path, output: &str, out_link: &Path) -> Result<()> {
+        Nix::build(flake_dir, output, out_link)
+    }
+
+    fn flake_update(&self, flake_dir: &Path, inputs: &[String]) -> Result<()> {
+        Nix::flake_update(flake_dir, inputs)
+    }
+
+    fn flake_update_all(&self, flake_dir: &Path) -> Result<()> {
+        Nix::flake_update_all(flake_dir)
+    }
+
+    fn validate_flake_package(&self, flake_url: &str, pkg: &str) -> Result<Option<String>> {
+        Nix::validate_flake_package(flake_url, pkg)
+    }
+}
+
 /// Format a path as a flake reference with optional output
 /// Handles paths with spaces by using proper escaping
 fn flake_ref(path: &Path, output: Option<&str>) -> String {
@@ -29,6 +192,151 @@ fn flake_ref(path: &Path, output: Option<&str>) -> String {
     }
 }
 
+/// Passing a command to the hook set with [`Nix::set_command_hook`]
+trait Logged {
+    fn logged(&mut self) -> &mut Self;
+}
+
+impl Logged for Command {
+    fn logged(&mut self) -> &mut Self {
+        let hook = *COMMAND_HOOK.read().unwrap_or_else(|e| e.into_inner());
+        if let Some(hook) = hook {
+            hook(&command_line(self));
+        }
+        self
+    }
+}
+
+/// `cmd` as it would be typed in a shell
+fn command_line(cmd: &Command) -> String {
+    let quote = |arg: &std::ffi::OsStr| {
+        let arg = arg.to_string_lossy();
+        let plain = !arg.is_empty()
+            && arg
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_./:=@#%+,".contains(c));
+        if plain {
+            arg.into_owned()
+        } else {
+            format!("'{}'", arg.replace('\'', "'\\''"))
+        }
+    };
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(quote)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Run a command to completion, or kill it at `deadline`; `None` means it
+/// was killed
+fn run_until(cmd: &mut Command, deadline: Instant) -> Result<Option<ExitStatus>> {
+    let mut child = cmd
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .logged()
+        .spawn()
+        .map_err(|e| Error::NixCommand(e.to_string()))?;
+
+    loop {
+        if let Some(status) = child
+            .try_wait()
+            .map_err(|e| Error::NixCommand(e.to_string()))?
+        {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Most lines of a failed build's log repeated in the error
+const BUILD_ERROR_LINES: usize = 20;
+
+/// The part of a failed build's log that explains it: from nix's first
+/// `error:` line on (or the last lines, when there is none), capped at
+/// [`BUILD_ERROR_LINES`]
+fn build_error_excerpt(log: &str, status: ExitStatus) -> String {
+    let lines: Vec<&str> = log
+        .lines()
+        .map(str::trim_end)
+        .filter(|l| !l.is_empty())
+        .collect();
+    let start = lines
+        .iter()
+        .position(|l| l.trim_start().starts_with("error:"))
+        .unwrap_or(0)
+        .max(lines.len().saturating_sub(BUILD_ERROR_LINES));
+    if start == lines.len() {
+        return format!("nix build {}", status);
+    }
+    lines[start..].join("\n")
+}
+
+/// Run a command with its stderr passed through to ours, also keeping a copy
+/// so failures can be recognized afterwards
+fn run_relaying_stderr(cmd: &mut Command) -> Result<(ExitStatus, String)> {
+    let mut child = cmd
+        .stderr(Stdio::piped())
+        .logged()
+        .spawn()
+        .map_err(|e| Error::NixCommand(e.to_string()))?;
+    let log = relay_stderr(&mut child);
+
+    let status = child.wait().map_err(|e| Error::NixCommand(e.to_string()))?;
+    Ok((status, log))
+}
+
+/// Copy the piped stderr of `child` to ours until it closes, returning it
+fn relay_stderr(child: &mut std::process::Child) -> String {
+    let mut log = Vec::new();
+    if let Some(mut stderr) = child.stderr.take() {
+        let mut buf = [0u8; 4096];
+        let mut out = std::io::stderr();
+        loop {
+            match stderr.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    let _ = out.write_all(&buf[..n]);
+                    log.extend_from_slice(&buf[..n]);
+                }
+            }
+        }
+    }
+    String::from_utf8_lossy(&log).into_owned()
+}
+
+/// [`run_relaying_stderr`], also collecting what the command prints to stdout
+fn run_capturing_stdout(cmd: &mut Command) -> Result<(ExitStatus, Vec<u8>, String)> {
+    let mut child = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .logged()
+        .spawn()
+        .map_err(|e| Error::NixCommand(e.to_string()))?;
+
+    // Read stdout on its own thread: a full pipe would stall nix while
+    // stderr is being relayed
+    let stdout = child.stdout.take().map(|mut stdout| {
+        std::thread::spawn(move || {
+            let mut out = Vec::new();
+            let _ = stdout.read_to_end(&mut out);
+            out
+        })
+    });
+    let log = relay_stderr(&mut child);
+
+    let status = child.wait().map_err(|e| Error::NixCommand(e.to_string()))?;
+    let out = stdout
+        .and_then(|reader| reader.join().ok())
+        .unwrap_or_default();
+    Ok((status, out, log))
+}
+
 impl Nix {
     /// Check if nix is installed
     pub fn check_installed() -> Result<()> {
@@ -36,11 +344,50 @@ impl Nix {
             .arg("--version")
             .stdout(Stdio::null())
             .stderr(Stdio::null())
+            .logged()
             .status()
             .map_err(|_| Error::NixNotInstalled)?;
         Ok(())
     }
 
+    /// Experimental features the user's nix configuration enables, or `None`
+    /// when it doesn't even enable the `nix` command
+    ///
+    /// nixy passes the features it needs on every call, so these only matter
+    /// for running nix by hand on nixy's flakes.
+    pub fn configured_features() -> Result<Option<Vec<String>>> {
+        let output = Command::new("nix")
+            .args(["config", "show", "experimental-features"])
+            .logged()
+            .output()
+            .map_err(|_| Error::NixNotInstalled)?;
+        if output.status.success() {
+            return Ok(Some(parse_features(&String::from_utf8_lossy(
+                &output.stdout,
+            ))));
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains("experimental Nix feature 'nix-command' is disabled") {
+            return Ok(None);
+        }
+
+        // Before nix 2.20 the setting is only listed by show-config
+        let output = Command::new("nix")
+            .arg("show-config")
+            .logged()
+            .output()
+            .map_err(|_| Error::NixNotInstalled)?;
+        if !output.status.success() {
+            return Err(Error::NixCommand(format!(
+                "Failed to read the nix configuration: {}",
+                stderr.trim()
+            )));
+        }
+        Ok(Some(parse_features(&String::from_utf8_lossy(
+            &output.stdout,
+        ))))
+    }
+
     /// Get the current system (e.g., "x86_64-darwin", "aarch64-linux")
     pub fn current_system() -> Result<String> {
         let output = Command::new("nix")
@@ -52,6 +399,7 @@ impl Nix {
                 "builtins.currentSystem",
                 "--raw",
             ])
+            .logged()
             .output()
             .map_err(|e| Error::NixCommand(e.to_string()))?;
 
@@ -67,32 +415,128 @@ impl Nix {
     }
 
     /// Build a flake and create an out-link
+    ///
+    /// The out-link must live outside the flake directory: path flakes copy
+    /// their whole directory into the store, so a link inside it would become
+    /// part of the flake source on the next evaluation.
     pub fn build(flake_dir: &Path, output: &str, out_link: &Path) -> Result<()> {
+        if out_link.starts_with(flake_dir) {
+            return Err(Error::Usage(format!(
+                "Build out-link {} must be outside the flake directory {}. Set NIXY_ENV to another path.",
+                out_link.display(),
+                flake_dir.display()
+            )));
+        }
+
         let ref_str = flake_ref(flake_dir, Some(output));
         let out_link_str = out_link.to_string_lossy();
 
-        let status = Command::new("nix")
-            .args(NIX_FLAGS)
+        let mut cmd = Command::new("nix");
+        cmd.args(NIX_FLAGS)
             .env("NIXPKGS_ALLOW_UNFREE", "1")
             .args(["build", &ref_str, "--out-link", &out_link_str, "--impure"])
-            .status()
-            .map_err(|e| Error::NixCommand(e.to_string()))?;
+            .args(Self::lock_flags())
+            .args(Self::offline_flags());
+        let (status, log) = run_relaying_stderr(&mut cmd)?;
 
         if !status.success() {
-            return Err(Error::NixCommand(
-                "Failed to build environment. See output above for details.".to_string(),
-            ));
+            if log.contains(LOCK_PROHIBITED) {
+                return Err(Error::LockChangeNeeded(flake_dir.to_path_buf()));
+            }
+            let commits = commits::unreachable_in_log(&log);
+            if !commits.is_empty() {
+                return Err(Error::UnreachableCommits {
+                    commits,
+                    packages: Vec::new(),
+                });
+            }
+            if let Some(c) = MISSING_ATTRIBUTE_REGEX.captures(&log) {
+                return Err(Error::MissingAttribute {
+                    attribute: c[1].to_string(),
+                    packages: Vec::new(),
+                });
+            }
+            return Err(Error::BuildFailed(build_error_excerpt(&log, status)));
         }
 
         Ok(())
     }
 
+    /// Run the main program of `installable` with `args`, attached to the
+    /// terminal, without adding anything to a profile or leaving a GC root
+    pub fn run(installable: &str, args: &[String]) -> Result<ExitStatus> {
+        Command::new("nix")
+            .args(NIX_FLAGS)
+            .env("NIXPKGS_ALLOW_UNFREE", "1")
+            .args(["run", "--impure"])
+            .args(Self::offline_flags())
+            .arg(installable)
+            .arg("--")
+            .args(args)
+            .logged()
+            .status()
+            .map_err(|e| Error::NixCommand(e.to_string()))
+    }
+
+    /// Names of the attributes in the `packages.<system>` set of the flake in
+    /// `flake_dir`, for the current system
+    ///
+    /// Only the names are evaluated, not the packages behind them.
+    pub fn flake_package_names(flake_dir: &Path) -> Result<Vec<String>> {
+        let output = Self::eval_flake_packages(flake_dir, "builtins.attrNames")?;
+        serde_json::from_slice(&output).map_err(|e| Error::NixCommand(e.to_string()))
+    }
+
+    /// Store paths of the outputs of each package in the `packages.<system>`
+    /// set of the flake in `flake_dir`, except the environment (`default`)
+    ///
+    /// The paths are evaluated, not built.
+    pub fn flake_package_out_paths(flake_dir: &Path) -> Result<BTreeMap<String, Vec<String>>> {
+        let output = Self::eval_flake_packages(
+            flake_dir,
+            "ps: builtins.mapAttrs (_: p: map (o: p.${o}.outPath) (p.outputs or [\"out\"])) \
+             (removeAttrs ps [\"default\"])",
+        )?;
+        serde_json::from_slice(&output).map_err(|e| Error::NixCommand(e.to_string()))
+    }
+
+    /// `nix eval --json` of the flake in `flake_dir`'s `packages.<system>`
+    /// set with `apply`, for the current system
+    fn eval_flake_packages(flake_dir: &Path, apply: &str) -> Result<Vec<u8>> {
+        let system = Self::current_system()?;
+        let attr = flake_ref(flake_dir, Some(&format!("packages.{}", system)));
+        let output = Command::new("nix")
+            .args(NIX_FLAGS)
+            .env("NIXPKGS_ALLOW_UNFREE", "1")
+            .args(["eval", "--json", &attr, "--apply", apply, "--impure"])
+            .args(Self::lock_flags())
+            .args(Self::offline_flags())
+            .logged()
+            .output()
+            .map_err(|e| Error::NixCommand(e.to_string()))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if stderr.contains(LOCK_PROHIBITED) {
+                return Err(Error::LockChangeNeeded(flake_dir.to_path_buf()));
+            }
+            return Err(Error::NixCommand(format!(
+                "Failed to evaluate {}: {}",
+                flake_dir.display(),
+                stderr.trim()
+            )));
+        }
+        Ok(output.stdout)
+    }
+
     /// Search for packages in nixpkgs (passes through to stdout/stderr)
     #[allow(dead_code)]
     pub fn search(query: &str) -> Result<()> {
         let status = Command::new("nix")
             .args(NIX_FLAGS)
             .args(["search", "nixpkgs", query])
+            .args(Self::offline_flags())
+            .logged()
             .status()
             .map_err(|e| Error::NixCommand(e.to_string()))?;
 
@@ -106,8 +550,155 @@ impl Nix {
         Ok(())
     }
 
+    /// Delete the store paths no GC root refers to anymore
+    ///
+    /// nix's output is relayed as it runs; returns what it reported freeing,
+    /// or None if its summary couldn't be read.
+    pub fn collect_garbage() -> Result<Option<GcFreed>> {
+        let mut cmd = Command::new("nix");
+        cmd.args(NIX_FLAGS).args(["store", "gc"]);
+        let (status, log) = run_relaying_stderr(&mut cmd)?;
+
+        if !status.success() {
+            return Err(Error::NixCommand(
+                "Garbage collection failed. See output above for details.".to_string(),
+            ));
+        }
+
+        Ok(gc_freed(&log))
+    }
+
+    /// The store paths a garbage collection would delete now
+    ///
+    /// `nix store gc --dry-run` doesn't list them, so this asks
+    /// `nix-store --gc --print-dead`.
+    pub fn dead_paths() -> Result<Vec<String>> {
+        let mut cmd = Command::new("nix-store");
+        cmd.args(["--gc", "--print-dead"]);
+        let (status, out, _) = run_capturing_stdout(&mut cmd)?;
+
+        if !status.success() {
+            return Err(Error::NixCommand(
+                "Listing garbage failed. See output above for details.".to_string(),
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&out)
+            .lines()
+            .filter(|line| line.starts_with('/'))
+            .map(String::from)
+            .collect())
+    }
+
+    /// Point `link` at `store_path` and register it as a GC root, the way
+    /// `nix build --out-link` does, without building anything
+    pub fn add_root(store_path: &Path, link: &Path) -> Result<()> {
+        let mut cmd = Command::new("nix-store");
+        cmd.arg("--add-root")
+            .arg(link)
+            .arg("--realise")
+            .arg(store_path);
+        let (status, _, log) = run_capturing_stdout(&mut cmd)?;
+
+        if !status.success() {
+            return Err(Error::NixCommand(format!(
+                "Could not register {} as a GC root: {}",
+                link.display(),
+                log.trim()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Search nixpkgs as of commit `rev` instead of the registry's nixpkgs
+    ///
+    /// Progress and errors from nix are relayed to stderr as it runs.
+    pub fn search_rev(rev: &str, query: &str) -> Result<Vec<SearchResult>> {
+        let flake = format!("github:NixOS/nixpkgs/{}", rev);
+        let mut cmd = Command::new("nix");
+        cmd.args(NIX_FLAGS)
+            .args(["search", &flake, query, "--json"])
+            .args(Self::offline_flags());
+        let (status, out, log) = run_capturing_stdout(&mut cmd)?;
+
+        if !status.success() {
+            if commits::reports_missing(&log) {
+                return Err(Error::UnknownRev(rev.to_string()));
+            }
+            return Err(Error::NixCommand(format!(
+                "Search failed for query '{}' at nixpkgs {}",
+                query, rev
+            )));
+        }
+
+        parse_search_json(&String::from_utf8_lossy(&out))
+    }
+
+    /// Forbid (or allow again) changes to flake.lock files: builds and
+    /// evaluations of a profile's flake pass `--no-update-lock-file`, and
+    /// flake updates are refused
+    pub fn set_frozen(frozen: bool) {
+        FROZEN.store(frozen, Ordering::Relaxed);
+    }
+
+    /// Forbid (or allow again) network access: nix commands get `--offline`
+    /// and use only what is already in the store and nix's caches, and
+    /// commands that exist to fetch something new are refused
+    pub fn set_offline(offline: bool) {
+        OFFLINE.store(offline, Ordering::Relaxed);
+    }
+
+    /// Whether the network is off limits, see [`Nix::set_offline`]
+    pub fn offline() -> bool {
+        OFFLINE.load(Ordering::Relaxed)
+    }
+
+    /// Call `hook` with every nix command, as it would be typed in a shell,
+    /// before it runs (`None` to stop), e.g. to echo them under `--verbose`
+    pub fn set_command_hook(hook: Option<fn(&str)>) {
+        *COMMAND_HOOK.write().unwrap_or_else(|e| e.into_inner()) = hook;
+    }
+
+    /// Whether flake.lock files are frozen, see [`Nix::set_frozen`]
+    pub fn frozen() -> bool {
+        FROZEN.load(Ordering::Relaxed)
+    }
+
+    /// The Nix store directory: `NIX_STORE_DIR` like nix itself, else
+    /// /nix/store
+    pub fn store_dir() -> PathBuf {
+        std::env::var_os("NIX_STORE_DIR")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("/nix/store"))
+    }
+
+    /// `--no-update-lock-file` when frozen
+    fn lock_flags() -> &'static [&'static str] {
+        if Self::frozen() {
+            &["--no-update-lock-file"]
+        } else {
+            &[]
+        }
+    }
+
+    /// `--offline` when offline
+    fn offline_flags() -> &'static [&'static str] {
+        if Self::offline() {
+            &["--offline"]
+        } else {
+            &[]
+        }
+    }
+
     /// Update flake inputs
     pub fn flake_update(flake_dir: &Path, inputs: &[String]) -> Result<()> {
+        if Self::frozen() {
+            return Err(Error::Frozen("Updating flake inputs".to_string()));
+        }
+        if Self::offline() {
+            return Err(Error::Offline("Updating flake inputs".to_string()));
+        }
         let mut cmd = Command::new("nix");
         cmd.args(NIX_FLAGS).arg("flake").arg("update");
 
@@ -117,7 +708,10 @@ impl Nix {
 
         cmd.arg("--flake").arg(flake_dir);
 
-        let status = cmd.status().map_err(|e| Error::NixCommand(e.to_string()))?;
+        let status = cmd
+            .logged()
+            .status()
+            .map_err(|e| Error::NixCommand(e.to_string()))?;
 
         if !status.success() {
             return Err(Error::NixCommand(
@@ -130,10 +724,17 @@ impl Nix {
 
     /// Update all flake inputs
     pub fn flake_update_all(flake_dir: &Path) -> Result<()> {
+        if Self::frozen() {
+            return Err(Error::Frozen("Updating flake inputs".to_string()));
+        }
+        if Self::offline() {
+            return Err(Error::Offline("Updating flake inputs".to_string()));
+        }
         let status = Command::new("nix")
             .args(NIX_FLAGS)
             .args(["flake", "update", "--flake"])
             .arg(flake_dir)
+            .logged()
             .status()
             .map_err(|e| Error::NixCommand(e.to_string()))?;
 
@@ -154,6 +755,7 @@ impl Nix {
         let output = Command::new("nix")
             .args(NIX_FLAGS)
             .args(["eval", &attr])
+            .logged()
             .output()
             .map_err(|e| Error::NixCommand(e.to_string()))?;
 
@@ -175,6 +777,8 @@ impl Nix {
         let output = Command::new("nix")
             .args(NIX_FLAGS)
             .args(["eval", "--no-write-lock-file", &attr])
+            .args(Self::offline_flags())
+            .logged()
             .output()
             .map_err(|e| Error::NixCommand(e.to_string()))?;
 
@@ -188,6 +792,8 @@ impl Nix {
         let output = Command::new("nix")
             .args(NIX_FLAGS)
             .args(["eval", "--no-write-lock-file", &attr])
+            .args(Self::offline_flags())
+            .logged()
             .output()
             .map_err(|e| Error::NixCommand(e.to_string()))?;
 
@@ -199,6 +805,38 @@ impl Nix {
         Ok(None)
     }
 
+    /// Look `pkg` up in the flake at `flake_url` like
+    /// [`Nix::validate_flake_package`], but give up once `timeout` has passed
+    ///
+    /// Huge flakes (nixpkgs forks, big overlays) can take minutes to fetch
+    /// and evaluate; [`FlakePackageCheck::TimedOut`] leaves it to the caller
+    /// whether to go on without knowing.
+    pub fn check_flake_package(
+        flake_url: &str,
+        pkg: &str,
+        timeout: Duration,
+    ) -> Result<FlakePackageCheck> {
+        let deadline = Instant::now() + timeout;
+        let system = Self::current_system()?;
+
+        for output in ["packages", "legacyPackages"] {
+            let attr = format!("{}#{}.{}.{}.name", flake_url, output, system, pkg);
+            let mut cmd = Command::new("nix");
+            cmd.args(NIX_FLAGS)
+                .args(["eval", "--no-write-lock-file", "--json", &attr])
+                .args(Self::offline_flags());
+            match run_until(&mut cmd, deadline)? {
+                Some(status) if status.success() => {
+                    return Ok(FlakePackageCheck::Found(output.to_string()));
+                }
+                Some(_) => {}
+                None => return Ok(FlakePackageCheck::TimedOut),
+            }
+        }
+
+        Ok(FlakePackageCheck::Missing)
+    }
+
     /// List packages in a flake
     pub fn list_flake_packages(flake_url: &str, output_type: Option<&str>) -> Result<Vec<String>> {
         let system = Self::current_system()?;
@@ -223,6 +861,8 @@ impl Nix {
                     r#"pkgs: builtins.concatStringsSep "\n" (builtins.attrNames pkgs)"#,
                     "--raw",
                 ])
+                .args(Self::offline_flags())
+                .logged()
                 .output()
                 .map_err(|e| Error::NixCommand(e.to_string()))?;
 
@@ -242,6 +882,8 @@ impl Nix {
         let output = Command::new("nix")
             .args(NIX_FLAGS)
             .args(["flake", "prefetch", "--json", url])
+            .args(Self::offline_flags())
+            .logged()
             .output()
             .map_err(|e| Error::NixCommand(e.to_string()))?;
 
@@ -264,17 +906,25 @@ impl Nix {
             .ok_or_else(|| Error::NixCommand("Missing storePath in flake prefetch output".into()))
     }
 
-    /// Get package source path via meta.position
+    /// Get package source path via meta.position of `attr` under the flake
+    /// `output` (normally `legacyPackages`)
     /// Returns the file path (without line number) from the position attribute
-    pub fn get_package_source_path(commit: &str, attr: &str, system: &str) -> Result<PathBuf> {
+    pub fn get_package_source_path(
+        commit: &str,
+        output: &str,
+        attr: &str,
+        system: &str,
+    ) -> Result<PathBuf> {
         let flake_ref = format!(
-            "github:NixOS/nixpkgs/{}#legacyPackages.{}.{}.meta.position",
-            commit, system, attr
+            "github:NixOS/nixpkgs/{}#{}.{}.{}.meta.position",
+            commit, output, system, attr
         );
 
         let output = Command::new("nix")
             .args(NIX_FLAGS)
             .args(["eval", "--raw", &flake_ref])
+            .args(Self::offline_flags())
+            .logged()
             .output()
             .map_err(|e| Error::NixCommand(e.to_string()))?;
 
@@ -299,6 +949,63 @@ impl Nix {
         Ok(PathBuf::from(path))
     }
 
+    /// The nixpkgs commit `branch` (e.g. "nixos-unstable") points at now
+    pub fn nixpkgs_branch_commit(branch: &str) -> Result<String> {
+        let url = format!("github:NixOS/nixpkgs/{}", branch);
+        let output = Command::new("nix")
+            .args(NIX_FLAGS)
+            .args(["flake", "metadata", "--json", &url])
+            .args(Self::offline_flags())
+            .logged()
+            .output()
+            .map_err(|e| Error::NixCommand(e.to_string()))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(Error::NixCommand(format!(
+                "Failed to look up nixpkgs {}: {}",
+                branch,
+                stderr.trim()
+            )));
+        }
+
+        let json: serde_json::Value =
+            serde_json::from_slice(&output.stdout).map_err(|e| Error::NixCommand(e.to_string()))?;
+        json["locked"]["rev"]
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| Error::NixCommand("Missing locked.rev in flake metadata output".into()))
+    }
+
+    /// Version of `attr` under the flake `output` (normally `legacyPackages`)
+    /// of nixpkgs at `commit`, or None if that nixpkgs has no such package
+    pub fn nixpkgs_package_version(
+        commit: &str,
+        output: &str,
+        attr: &str,
+        system: &str,
+    ) -> Result<Option<String>> {
+        let flake_ref = format!(
+            "github:NixOS/nixpkgs/{}#{}.{}.{}.version",
+            commit, output, system, attr
+        );
+
+        let output = Command::new("nix")
+            .args(NIX_FLAGS)
+            .args(["eval", "--raw", &flake_ref])
+            .args(Self::offline_flags())
+            .logged()
+            .output()
+            .map_err(|e| Error::NixCommand(e.to_string()))?;
+
+        if !output.status.success() {
+            return Ok(None);
+        }
+        Ok(Some(
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        ))
+    }
+
     /// Get flake inputs from flake.lock
     pub fn get_flake_inputs(lock_file: &Path) -> Result<Vec<String>> {
         let lock_path = lock_file.to_string_lossy();
@@ -318,6 +1025,7 @@ impl Nix {
                 r#"names: builtins.concatStringsSep "\n" names"#,
                 "--raw",
             ])
+            .logged()
             .output()
             .map_err(|e| Error::NixCommand(e.to_string()))?;
 
@@ -336,8 +1044,74 @@ impl Nix {
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    #[test]
+    fn test_command_line() {
+        let mut cmd = Command::new("nix");
+        cmd.args(["build", "/tmp/my profile#default", "--out-link", "it's", ""]);
+        assert_eq!(
+            command_line(&cmd),
+            "nix build '/tmp/my profile#default' --out-link 'it'\\''s' ''"
+        );
+    }
     use std::path::PathBuf;
 
+    #[test]
+    fn test_gc_freed() {
+        let log = "finding garbage collector roots...\n\
+                   deleting '/nix/store/abc-hello-2.12'\n\
+                   1 store path deleted, 0.12 MiB freed\n\
+                   12 store paths deleted, 3.40 MiB freed\n";
+        assert_eq!(
+            gc_freed(log),
+            Some(GcFreed {
+                paths: 12,
+                space: "3.40 MiB".to_string()
+            })
+        );
+        assert_eq!(gc_freed("note: currently hard linking saves 1.2 GiB"), None);
+    }
+
+    #[test]
+    fn test_missing_attribute_regex() {
+        let log = "error:\n       … while evaluating the attribute 'tool'\n       error: attribute 'tool' missing\n       at /nix/store/x-source/flake.nix:20:18:";
+        let c = MISSING_ATTRIBUTE_REGEX.captures(log).unwrap();
+        assert_eq!(&c[1], "tool");
+    }
+
+    #[test]
+    fn test_build_error_excerpt() {
+        use std::os::unix::process::ExitStatusExt;
+        let failed = ExitStatus::from_raw(1 << 8);
+
+        let log = "copying path '/nix/store/a-src'\nbuilding '/nix/store/x-hello.drv'\nerror: builder for '/nix/store/x-hello.drv' failed with exit code 1;\n       last 1 log lines:\n       > make: *** [all] Error 2\n\n";
+        assert_eq!(
+            build_error_excerpt(log, failed),
+            "error: builder for '/nix/store/x-hello.drv' failed with exit code 1;\n       last 1 log lines:\n       > make: *** [all] Error 2"
+        );
+
+        let long: String = (0..30).map(|i| format!("line {}\n", i)).collect();
+        let excerpt = build_error_excerpt(&long, failed);
+        assert_eq!(excerpt.lines().count(), BUILD_ERROR_LINES);
+        assert!(excerpt.ends_with("line 29"));
+
+        assert_eq!(build_error_excerpt("", failed), "nix build exit status: 1");
+    }
+
+    #[test]
+    fn test_run_until_kills_at_deadline() {
+        let mut cmd = Command::new("sleep");
+        cmd.arg("5");
+        let start = Instant::now();
+        let status = run_until(&mut cmd, start + Duration::from_millis(100)).unwrap();
+        assert!(status.is_none());
+        assert!(start.elapsed() < Duration::from_secs(4));
+
+        let mut cmd = Command::new("true");
+        let status = run_until(&mut cmd, Instant::now() + Duration::from_secs(5)).unwrap();
+        assert!(status.unwrap().success());
+    }
+
     #[test]
     fn test_flake_ref_simple_path() {
         let path = PathBuf::from("/home/user/.config/nixy");
@@ -376,4 +1150,36 @@ mod tests {
         let result = flake_ref(&path, Some("default"));
         assert_eq!(result, "/tmp/nixy%20test%20dir/config#default");
     }
+
+    #[test]
+    fn test_parse_search_json() {
+        let output = r#"{
+            "legacyPackages.x86_64-linux.ripgrep": {"pname": "ripgrep", "version": "14.1.1", "description": "Fast grep"},
+            "legacyPackages.x86_64-linux.python3Packages.requests": {"pname": "requests", "version": "", "description": ""},
+            "legacyPackages.x86_64-linux.fd": {"pname": "fd", "version": "10.2.0", "description": "Find files"}
+        }"#;
+        let results = parse_search_json(output).unwrap();
+        let names: Vec<&str> = results.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["fd", "python3Packages.requests", "ripgrep"]);
+        assert_eq!(results[0].version.as_deref(), Some("10.2.0"));
+        assert_eq!(results[0].description, "Find files");
+        assert_eq!(results[1].version, None);
+
+        assert!(parse_search_json("").unwrap().is_empty());
+        assert!(parse_search_json("{}").unwrap().is_empty());
+        assert!(parse_search_json("* hello (2.10)").is_err());
+    }
+
+    #[test]
+    fn test_parse_features() {
+        assert_eq!(
+            parse_features("flakes nix-command\n"),
+            ["flakes", "nix-command"]
+        );
+        assert!(parse_features("\n").is_empty());
+        let show_config =
+            "allowed-users = *\nexperimental-features = flakes nix-command\nmax-jobs = 8\n";
+        assert_eq!(parse_features(show_config), ["flakes", "nix-command"]);
+        assert!(parse_features("experimental-features = \n").is_empty());
+    }
 }
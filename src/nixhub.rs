@@ -5,8 +5,11 @@
 //!
 //! API documentation: https://www.jetify.com/docs/nixhub
 
-use serde::{Deserialize, Deserializer};
-use std::collections::HashMap;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::error::{Error, Result};
 use crate::nix::Nix;
@@ -28,6 +31,7 @@ const SEARCH_API_ENDPOINT: &str = "https://search.devbox.sh";
 /// Nixhub API client
 pub struct NixhubClient {
     host: String,
+    cache: Option<ResolveCache>,
 }
 
 impl Default for NixhubClient {
@@ -37,14 +41,35 @@ impl Default for NixhubClient {
 }
 
 impl NixhubClient {
+    /// Client for the public API, or the host in `NIXY_NIXHUB_URL` if set
+    /// (e.g. a local server replaying recorded responses in tests)
     pub fn new() -> Self {
+        let host = std::env::var("NIXY_NIXHUB_URL")
+            .ok()
+            .filter(|url| !url.is_empty())
+            .unwrap_or_else(|| SEARCH_API_ENDPOINT.to_string());
+        Self::with_host(host.trim_end_matches('/'))
+    }
+
+    /// Client for an API-compatible server at `host`
+    pub fn with_host(host: &str) -> Self {
         Self {
-            host: SEARCH_API_ENDPOINT.to_string(),
+            host: host.to_string(),
+            cache: None,
         }
     }
 
+    /// Reuse resolutions from `cache` in [`NixhubClient::resolve_for_system`]
+    pub fn with_cache(mut self, cache: ResolveCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
     /// Search for packages by query
     pub fn search(&self, query: &str) -> Result<SearchResponse> {
+        if Nix::offline() {
+            return Err(Error::Offline("Searching Nixhub".to_string()));
+        }
         if query.is_empty() {
             return Err(Error::Usage("Search query cannot be empty".to_string()));
         }
@@ -65,6 +90,9 @@ impl NixhubClient {
 
     /// Get package details including all versions
     pub fn get_package(&self, name: &str) -> Result<PackageDetails> {
+        if Nix::offline() {
+            return Err(Error::Offline("Looking packages up on Nixhub".to_string()));
+        }
         let url = format!("{}/v2/pkg?name={}", self.host, urlencoding::encode(name));
         let response: PackageDetails = ureq::get(&url)
             .call()
@@ -81,6 +109,9 @@ impl NixhubClient {
 
     /// Resolve a package name and version to a nixpkgs commit and attribute path
     pub fn resolve(&self, name: &str, version: &str) -> Result<ResolveResponse> {
+        if Nix::offline() {
+            return Err(Error::Offline("Resolving packages on Nixhub".to_string()));
+        }
         let url = format!(
             "{}/v2/resolve?name={}&version={}",
             self.host,
@@ -107,11 +138,47 @@ impl NixhubClient {
         &self,
         name: &str,
         version: &str,
+    ) -> Result<ResolvedPackageInfo> {
+        self.resolve_for_system(name, version, &Nix::current_system()?)
+    }
+
+    /// Resolve a package to the details for a specific system, from the
+    /// cache when it has a fresh answer
+    pub fn resolve_for_system(
+        &self,
+        name: &str,
+        version: &str,
+        system: &str,
+    ) -> Result<ResolvedPackageInfo> {
+        let key = ResolveCache::key(name, version, system);
+        if Nix::offline() {
+            // However old, a cached answer beats none without the network
+            return self
+                .cache
+                .as_ref()
+                .and_then(|c| c.last(&key))
+                .ok_or_else(|| Error::NotResolvedOffline(format!("{}@{}", name, version)));
+        }
+        let now = now_secs();
+        if let Some(cached) = self.cache.as_ref().and_then(|c| c.get(&key, now)) {
+            return Ok(cached);
+        }
+        let resolved = self.resolve_uncached(name, version, system)?;
+        if let Some(cache) = &self.cache {
+            cache.put(&key, &resolved, now);
+        }
+        Ok(resolved)
+    }
+
+    fn resolve_uncached(
+        &self,
+        name: &str,
+        version: &str,
+        system: &str,
     ) -> Result<ResolvedPackageInfo> {
         let response = self.resolve(name, version)?;
-        let system = Nix::current_system()?;
 
-        let system_info = response.systems.get(&system).ok_or_else(|| {
+        let system_info = response.systems.get(system).ok_or_else(|| {
             Error::NixhubResolve(
                 name.to_string(),
                 version.to_string(),
@@ -128,6 +195,113 @@ impl NixhubClient {
     }
 }
 
+/// Nixhub resolutions kept on disk, keyed by package, version spec and
+/// system, so resolving the same spec again doesn't ask Nixhub.
+///
+/// A cache file that can't be read counts as empty and is rewritten by the
+/// next resolution; failing to write it is ignored.
+#[derive(Debug, Clone)]
+pub struct ResolveCache {
+    path: PathBuf,
+    ttl: Duration,
+    /// Ignore cached answers but still record new ones (`--refresh`)
+    refresh: bool,
+}
+
+/// One cached resolution
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedResolution {
+    name: String,
+    version: String,
+    attribute_path: String,
+    commit_hash: String,
+    /// Unix time of the lookup
+    resolved_at: u64,
+}
+
+impl CachedResolution {
+    fn info(&self) -> ResolvedPackageInfo {
+        ResolvedPackageInfo {
+            name: self.name.clone(),
+            version: self.version.clone(),
+            attribute_path: self.attribute_path.clone(),
+            commit_hash: self.commit_hash.clone(),
+        }
+    }
+}
+
+impl ResolveCache {
+    /// Cache in `path` whose answers are reused for `ttl`; a zero `ttl`
+    /// turns it off
+    pub fn new(path: PathBuf, ttl: Duration, refresh: bool) -> Self {
+        Self { path, ttl, refresh }
+    }
+
+    fn key(name: &str, version: &str, system: &str) -> String {
+        format!("{}@{} {}", name, version, system)
+    }
+
+    fn fresh(&self, entry: &CachedResolution, now: u64) -> bool {
+        now.saturating_sub(entry.resolved_at) < self.ttl.as_secs()
+    }
+
+    /// The answer cached under `key`, unless it is older than the TTL
+    fn get(&self, key: &str, now: u64) -> Option<ResolvedPackageInfo> {
+        if self.refresh {
+            return None;
+        }
+        let entries = self.load();
+        entries
+            .get(key)
+            .filter(|e| self.fresh(e, now))
+            .map(CachedResolution::info)
+    }
+
+    /// The answer cached under `key`, however old
+    fn last(&self, key: &str) -> Option<ResolvedPackageInfo> {
+        self.load().get(key).map(CachedResolution::info)
+    }
+
+    /// Record `resolved` under `key`, dropping entries that have expired
+    fn put(&self, key: &str, resolved: &ResolvedPackageInfo, now: u64) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        let mut entries = self.load();
+        entries.retain(|_, e| self.fresh(e, now));
+        entries.insert(
+            key.to_string(),
+            CachedResolution {
+                name: resolved.name.clone(),
+                version: resolved.version.clone(),
+                attribute_path: resolved.attribute_path.clone(),
+                commit_hash: resolved.commit_hash.clone(),
+                resolved_at: now,
+            },
+        );
+        if let Ok(json) = serde_json::to_string_pretty(&entries) {
+            if let Some(parent) = self.path.parent() {
+                let _ = fs::create_dir_all(parent);
+            }
+            let _ = fs::write(&self.path, json);
+        }
+    }
+
+    fn load(&self) -> BTreeMap<String, CachedResolution> {
+        fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
 /// Parsed package specification (name and optional version)
 #[derive(Debug, Clone, PartialEq)]
 pub struct PackageSpec {
@@ -287,6 +461,91 @@ pub struct ResolveOutput {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tempfile::tempdir;
+
+    fn hello(version: &str) -> ResolvedPackageInfo {
+        ResolvedPackageInfo {
+            name: "hello".to_string(),
+            version: version.to_string(),
+            attribute_path: "hello".to_string(),
+            commit_hash: "a3a3dda3bacf61e8a39258a0ed9c924eeca8e293".to_string(),
+        }
+    }
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    #[test]
+    fn test_resolve_cache_hit_and_miss() {
+        let temp = tempdir().unwrap();
+        let cache = ResolveCache::new(
+            temp.path().join("nixhub-cache.json"),
+            Duration::from_secs(DAY),
+            false,
+        );
+        let key = ResolveCache::key("hello", "latest", "x86_64-linux");
+        assert!(cache.get(&key, 1_000).is_none());
+
+        cache.put(&key, &hello("2.12.1"), 1_000);
+        let hit = cache.get(&key, 1_000 + DAY - 1).unwrap();
+        assert_eq!(hit.version, "2.12.1");
+        assert_eq!(hit.commit_hash, hello("2.12.1").commit_hash);
+        // Another version spec or system is another entry
+        assert!(cache
+            .get(&ResolveCache::key("hello", "2", "x86_64-linux"), 1_000)
+            .is_none());
+        assert!(cache
+            .get(
+                &ResolveCache::key("hello", "latest", "aarch64-darwin"),
+                1_000
+            )
+            .is_none());
+
+        // --refresh asks again, and records the new answer
+        let refresh = ResolveCache::new(cache.path.clone(), cache.ttl, true);
+        assert!(refresh.get(&key, 1_000).is_none());
+        refresh.put(&key, &hello("2.12.2"), 2_000);
+        assert_eq!(cache.get(&key, 2_000).unwrap().version, "2.12.2");
+    }
+
+    #[test]
+    fn test_resolve_cache_expiry() {
+        let temp = tempdir().unwrap();
+        let cache = ResolveCache::new(
+            temp.path().join("nixhub-cache.json"),
+            Duration::from_secs(DAY),
+            false,
+        );
+        let old = ResolveCache::key("hello", "latest", "x86_64-linux");
+        let new = ResolveCache::key("jq", "latest", "x86_64-linux");
+        cache.put(&old, &hello("2.12.1"), 0);
+        assert!(cache.get(&old, DAY).is_none());
+
+        // Writing another entry drops the expired one
+        cache.put(&new, &hello("1.7"), DAY);
+        assert!(!cache.load().contains_key(&old));
+        assert!(cache.load().contains_key(&new));
+
+        // A zero TTL turns the cache off
+        let off = ResolveCache::new(temp.path().join("off.json"), Duration::ZERO, false);
+        off.put(&old, &hello("2.12.1"), 0);
+        assert!(off.get(&old, 0).is_none());
+        assert!(!temp.path().join("off.json").exists());
+    }
+
+    #[test]
+    fn test_resolve_cache_ignores_and_rewrites_a_corrupt_file() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("nixhub-cache.json");
+        fs::write(&path, "{ not json").unwrap();
+        let cache = ResolveCache::new(path.clone(), Duration::from_secs(DAY), false);
+        let key = ResolveCache::key("hello", "latest", "x86_64-linux");
+        assert!(cache.get(&key, 0).is_none());
+
+        cache.put(&key, &hello("2.12.1"), 0);
+        assert_eq!(cache.get(&key, 0).unwrap().version, "2.12.1");
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(serde_json::from_str::<serde_json::Value>(&content).is_ok());
+    }
 
     #[test]
     fn test_parse_package_spec_with_version() {
@@ -28,6 +28,10 @@ use serde::{Deserialize, Serialize};
 
 use crate::config::{Config, DEFAULT_PROFILE};
 use crate::error::{Error, Result};
+use crate::flake::format::FlakeFormat;
+use crate::flake::local_hashes;
+use crate::flake::template::DEFAULT_NIXPKGS_URL;
+use crate::profile::validate_profile_name;
 use crate::state::{CustomPackage, ResolvedNixpkgPackage};
 
 /// Current version of the nixy.json format
@@ -45,9 +49,29 @@ pub struct ProfileConfig {
     /// Packages from custom flake URLs
     #[serde(default)]
     pub custom_packages: Vec<CustomPackage>,
+    /// Outputs to link besides each package's main one (unset for man, doc,
+    /// info and dev; empty for a minimal closure)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extra_outputs: Option<Vec<String>>,
+    /// Place the environment's fonts where the desktop finds them after
+    /// each build
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub link_fonts: bool,
+    /// One-line note shown next to the name in profile listings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Flake URL of the base nixpkgs that legacy packages and the flake's
+    /// `lib` come from (unset for [`DEFAULT_NIXPKGS_URL`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nixpkgs_url: Option<String>,
 }
 
 impl ProfileConfig {
+    /// The base nixpkgs of the profile's flake
+    pub fn base_nixpkgs_url(&self) -> &str {
+        self.nixpkgs_url.as_deref().unwrap_or(DEFAULT_NIXPKGS_URL)
+    }
+
     /// Add a standard nixpkgs package (legacy method for backwards compatibility)
     #[allow(dead_code)]
     pub fn add_package(&mut self, name: &str) {
@@ -123,6 +147,18 @@ impl ProfileConfig {
     pub fn is_legacy_package(&self, name: &str) -> bool {
         self.packages.contains(&name.to_string())
     }
+
+    /// The platform restriction of a resolved or custom package (legacy
+    /// entries are plain names and can't have one)
+    pub fn platforms_mut(&mut self, name: &str) -> Option<&mut Option<Vec<String>>> {
+        if let Some(pkg) = self.resolved_packages.iter_mut().find(|p| p.name == name) {
+            return Some(&mut pkg.platforms);
+        }
+        self.custom_packages
+            .iter_mut()
+            .find(|p| p.name == name)
+            .map(|p| &mut p.platforms)
+    }
 }
 
 /// The main nixy.json configuration file
@@ -134,6 +170,25 @@ pub struct NixyConfig {
     pub active_profile: String,
     /// All profile configurations
     pub profiles: BTreeMap<String, ProfileConfig>,
+    /// How generated flake.nix files are formatted (`false` to opt out)
+    #[serde(default, skip_serializing_if = "FlakeFormat::is_default")]
+    pub format: FlakeFormat,
+    /// Fail instead of leaving out packages whose local source is missing
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub strict_local: bool,
+    /// Commit nixy.json, local package files and the profile's flake.nix and
+    /// flake.lock after each install, uninstall and sync, in whichever git
+    /// repositories they are in
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub auto_commit: bool,
+    /// Hours a Nixhub resolution is reused before asking again (default 24,
+    /// `0` turns the cache off)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nixhub_cache_hours: Option<u64>,
+    /// Content hashes of the local package definitions as of the last sync,
+    /// by path in `packages/` (see [`crate::flake::local_hashes`])
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub local_hashes: BTreeMap<String, String>,
 }
 
 impl Default for NixyConfig {
@@ -144,6 +199,11 @@ impl Default for NixyConfig {
             version: NIXY_CONFIG_VERSION,
             active_profile: DEFAULT_PROFILE.to_string(),
             profiles,
+            format: FlakeFormat::default(),
+            strict_local: false,
+            auto_commit: false,
+            nixhub_cache_hours: None,
+            local_hashes: BTreeMap::new(),
         }
     }
 }
@@ -187,8 +247,66 @@ impl NixyConfig {
         }
     }
 
+    /// Check the invariants [`Self::normalize`] establishes on load, plus
+    /// that no profile lists a package in more than one of `packages`,
+    /// `resolved_packages` and `custom_packages`
+    pub fn validate(&self) -> Result<()> {
+        if !self.profiles.contains_key(DEFAULT_PROFILE) {
+            return Err(Error::InvalidConfig(format!(
+                "the '{}' profile is missing",
+                DEFAULT_PROFILE
+            )));
+        }
+        if !self.profiles.contains_key(&self.active_profile) {
+            return Err(Error::InvalidConfig(format!(
+                "active profile '{}' does not exist",
+                self.active_profile
+            )));
+        }
+
+        for (name, profile) in &self.profiles {
+            let buckets: [(&str, Vec<&str>); 3] = [
+                (
+                    "packages",
+                    profile.packages.iter().map(String::as_str).collect(),
+                ),
+                (
+                    "resolved_packages",
+                    profile
+                        .resolved_packages
+                        .iter()
+                        .map(|p| p.name.as_str())
+                        .collect(),
+                ),
+                (
+                    "custom_packages",
+                    profile
+                        .custom_packages
+                        .iter()
+                        .map(|p| p.name.as_str())
+                        .collect(),
+                ),
+            ];
+            for (i, (first, names)) in buckets.iter().enumerate() {
+                for (second, others) in &buckets[i + 1..] {
+                    if let Some(dup) = names.iter().find(|n| others.contains(n)) {
+                        return Err(Error::InvalidConfig(format!(
+                            "profile '{}' lists '{}' in both {} and {}",
+                            name, dup, first, second
+                        )));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     /// Save nixy.json to the config directory atomically
+    ///
+    /// Fails without writing anything if [`Self::validate`] does.
     pub fn save(&self, config: &Config) -> Result<()> {
+        self.validate()?;
         let path = &config.nixy_json;
 
         // Resolve symlink if nixy.json is a symlink
@@ -262,7 +380,10 @@ impl NixyConfig {
     /// Delete a profile
     pub fn delete_profile(&mut self, name: &str) -> Result<()> {
         if name == self.active_profile {
-            return Err(Error::CannotDeleteActiveProfile);
+            return Err(Error::CannotDeleteActiveProfile(name.to_string()));
+        }
+        if name == DEFAULT_PROFILE {
+            return Err(Error::CannotDeleteDefaultProfile);
         }
         if self.profiles.remove(name).is_none() {
             return Err(Error::ProfileNotFound(name.to_string()));
@@ -270,6 +391,48 @@ impl NixyConfig {
         Ok(())
     }
 
+    /// Rename profile `from` to `to`, following it with the active profile
+    pub fn rename_profile(&mut self, from: &str, to: &str) -> Result<()> {
+        validate_profile_name(to)?;
+        if from == DEFAULT_PROFILE {
+            return Err(Error::CannotRenameDefaultProfile);
+        }
+        if self.profiles.contains_key(to) {
+            return Err(Error::ProfileExists(to.to_string()));
+        }
+        let profile = self
+            .profiles
+            .remove(from)
+            .ok_or_else(|| Error::ProfileNotFound(from.to_string()))?;
+        self.profiles.insert(to.to_string(), profile);
+        if self.active_profile == from {
+            self.active_profile = to.to_string();
+        }
+        Ok(())
+    }
+
+    /// Add profile `to` as a copy of profile `from`, replacing an existing
+    /// `to` only when `replace` is set
+    pub fn copy_profile(&mut self, from: &str, to: &str, replace: bool) -> Result<()> {
+        validate_profile_name(to)?;
+        if from == to {
+            return Err(Error::Usage(format!(
+                "Cannot copy profile '{}' onto itself",
+                from
+            )));
+        }
+        if self.profiles.contains_key(to) && !replace {
+            return Err(Error::ProfileExists(to.to_string()));
+        }
+        let profile = self
+            .profiles
+            .get(from)
+            .cloned()
+            .ok_or_else(|| Error::ProfileNotFound(from.to_string()))?;
+        self.profiles.insert(to.to_string(), profile);
+        Ok(())
+    }
+
     /// List all profile names
     pub fn list_profiles(&self) -> Vec<String> {
         let mut names: Vec<String> = self.profiles.keys().cloned().collect();
@@ -281,6 +444,32 @@ impl NixyConfig {
     pub fn profile_exists(&self, name: &str) -> bool {
         self.profiles.contains_key(name)
     }
+
+    /// Whether a hash of `package`'s local definition is recorded
+    pub fn tracks_local_definition(&self, package: &str) -> bool {
+        self.local_hashes
+            .keys()
+            .any(|path| local_hashes::package_name(path) == package)
+    }
+
+    /// Drop the recorded hash of `package`'s local definition, once it is
+    /// deleted on purpose
+    pub fn forget_local_definition(&mut self, package: &str) {
+        self.local_hashes
+            .retain(|path, _| local_hashes::package_name(path) != package);
+    }
+
+    /// Names of profiles (other than `exclude`) that list `package` in any bucket.
+    ///
+    /// Local package definitions in the global packages/ directory are shared by
+    /// all profiles, so this is the reference check used before deleting one.
+    pub fn profiles_referencing(&self, package: &str, exclude: &str) -> Vec<String> {
+        self.profiles
+            .iter()
+            .filter(|(name, profile)| name.as_str() != exclude && profile.has_package(package))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
 }
 
 /// Check if nixy.json exists
@@ -307,6 +496,10 @@ impl From<&crate::state::PackageState> for ProfileConfig {
             packages: state.packages.clone(),
             resolved_packages: state.resolved_packages.clone(),
             custom_packages: state.custom_packages.clone(),
+            extra_outputs: None,
+            link_fonts: false,
+            description: None,
+            nixpkgs_url: None,
         }
     }
 }
@@ -363,6 +556,12 @@ mod tests {
                 attribute_path: "hello".to_string(),
                 commit_hash: "abc123".to_string(),
                 platforms: None,
+                pinned: false,
+                source_name: None,
+                output: None,
+                package_output: None,
+                outputs: None,
+                record: Default::default(),
             });
         }
 
@@ -375,6 +574,58 @@ mod tests {
         assert!(work_profile.has_package("hello"));
     }
 
+    #[test]
+    fn test_format_setting_roundtrip() {
+        let temp = TempDir::new().unwrap();
+        let config = test_config(&temp);
+
+        // The default is omitted so existing nixy.json files stay unchanged
+        NixyConfig::default().save(&config).unwrap();
+        let content = fs::read_to_string(&config.nixy_json).unwrap();
+        assert!(!content.contains("\"format\""));
+
+        let nixy_config = NixyConfig {
+            format: FlakeFormat::Off,
+            ..Default::default()
+        };
+        nixy_config.save(&config).unwrap();
+        let content = fs::read_to_string(&config.nixy_json).unwrap();
+        assert!(content.contains("\"format\": false"));
+        assert_eq!(NixyConfig::load(&config).unwrap().format, FlakeFormat::Off);
+    }
+
+    #[test]
+    fn test_nixpkgs_url_roundtrip() {
+        let temp = TempDir::new().unwrap();
+        let config = test_config(&temp);
+
+        // nixy.json files from before the setting load with the default
+        fs::create_dir_all(&config.config_dir).unwrap();
+        fs::write(
+            &config.nixy_json,
+            r#"{"version": 3, "active_profile": "default", "profiles": {"default": {"packages": ["hello"]}}}"#,
+        )
+        .unwrap();
+        let mut nixy_config = NixyConfig::load(&config).unwrap();
+        let profile = nixy_config.get_active_profile_mut().unwrap();
+        assert_eq!(profile.nixpkgs_url, None);
+        assert_eq!(profile.base_nixpkgs_url(), DEFAULT_NIXPKGS_URL);
+        nixy_config.save(&config).unwrap();
+        assert!(!fs::read_to_string(&config.nixy_json)
+            .unwrap()
+            .contains("nixpkgs_url"));
+
+        let mut nixy_config = NixyConfig::load(&config).unwrap();
+        nixy_config.get_active_profile_mut().unwrap().nixpkgs_url =
+            Some("github:NixOS/nixpkgs/nixos-24.05".to_string());
+        nixy_config.save(&config).unwrap();
+        let loaded = NixyConfig::load(&config).unwrap();
+        assert_eq!(
+            loaded.get_active_profile().unwrap().base_nixpkgs_url(),
+            "github:NixOS/nixpkgs/nixos-24.05"
+        );
+    }
+
     #[test]
     fn test_create_profile() {
         let mut config = NixyConfig::default();
@@ -391,6 +642,169 @@ mod tests {
         assert!(!config.profile_exists("work"));
     }
 
+    #[test]
+    fn test_delete_default_profile_fails() {
+        let mut config = NixyConfig::default();
+        config.create_profile("work").unwrap();
+        config.set_active_profile("work").unwrap();
+        assert!(matches!(
+            config.delete_profile("default"),
+            Err(Error::CannotDeleteDefaultProfile)
+        ));
+    }
+
+    #[test]
+    fn test_rename_profile_follows_active() {
+        let mut config = NixyConfig::default();
+        config.create_profile("work").unwrap();
+        config.profiles.get_mut("work").unwrap().add_package("jq");
+        config.set_active_profile("work").unwrap();
+
+        config.rename_profile("work", "office").unwrap();
+        assert!(!config.profile_exists("work"));
+        assert!(config.profiles["office"].has_package("jq"));
+        assert_eq!(config.active_profile, "office");
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn test_copy_profile_keeps_source_and_active() {
+        let mut config = NixyConfig::default();
+        config.get_active_profile_mut().unwrap().add_package("jq");
+
+        config.copy_profile("default", "work", false).unwrap();
+        config.profiles.get_mut("work").unwrap().add_package("fd");
+        config
+            .get_active_profile_mut()
+            .unwrap()
+            .remove_package("jq");
+        config.get_active_profile_mut().unwrap().add_package("bat");
+        // Neither copy sees the other's changes
+        assert!(config.profiles["work"].has_package("jq"));
+        assert!(!config.profiles["work"].has_package("bat"));
+        assert!(!config.profiles["default"].has_package("fd"));
+        assert_eq!(config.active_profile, "default");
+
+        assert!(matches!(
+            config.copy_profile("default", "work", false),
+            Err(Error::ProfileExists(_))
+        ));
+        assert!(matches!(
+            config.copy_profile("missing", "other", false),
+            Err(Error::ProfileNotFound(_))
+        ));
+        assert!(matches!(
+            config.copy_profile("default", "a/b", false),
+            Err(Error::InvalidProfileName(_))
+        ));
+        assert!(matches!(
+            config.copy_profile("default", "default", true),
+            Err(Error::Usage(_))
+        ));
+
+        // Replacing an existing profile
+        config.copy_profile("default", "work", true).unwrap();
+        assert!(config.profiles["work"].has_package("bat"));
+        assert!(!config.profiles["work"].has_package("fd"));
+    }
+
+    #[test]
+    fn test_rename_profile_rejects_bad_targets() {
+        let mut config = NixyConfig::default();
+        config.create_profile("work").unwrap();
+        config.create_profile("home").unwrap();
+
+        assert!(matches!(
+            config.rename_profile("work", "home"),
+            Err(Error::ProfileExists(_))
+        ));
+        assert!(matches!(
+            config.rename_profile("work", "a b"),
+            Err(Error::InvalidProfileName(_))
+        ));
+        assert!(matches!(
+            config.rename_profile("missing", "other"),
+            Err(Error::ProfileNotFound(_))
+        ));
+        assert!(matches!(
+            config.rename_profile("default", "main"),
+            Err(Error::CannotRenameDefaultProfile)
+        ));
+        assert!(config.profile_exists("work"));
+    }
+
+    /// Saving `nixy_config` fails with a message containing `expected`, and
+    /// leaves nixy.json alone
+    fn assert_save_rejected(nixy_config: &NixyConfig, expected: &str) {
+        let temp = TempDir::new().unwrap();
+        let config = test_config(&temp);
+        let err = nixy_config.save(&config).unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig(_)), "{:?}", err);
+        assert!(err.to_string().contains(expected), "{}", err);
+        assert!(!config.nixy_json.exists());
+    }
+
+    #[test]
+    fn test_save_rejects_missing_default_profile() {
+        let mut nixy_config = NixyConfig::default();
+        nixy_config.create_profile("work").unwrap();
+        nixy_config.set_active_profile("work").unwrap();
+        nixy_config.profiles.remove("default");
+        assert_save_rejected(&nixy_config, "the 'default' profile is missing");
+    }
+
+    #[test]
+    fn test_save_rejects_missing_active_profile() {
+        let nixy_config = NixyConfig {
+            active_profile: "gone".to_string(),
+            ..Default::default()
+        };
+        assert_save_rejected(&nixy_config, "active profile 'gone' does not exist");
+    }
+
+    #[test]
+    fn test_save_rejects_cross_bucket_duplicates() {
+        let mut nixy_config = NixyConfig::default();
+        let profile = nixy_config.profiles.get_mut("default").unwrap();
+        profile.packages.push("hello".to_string());
+        profile.resolved_packages.push(ResolvedNixpkgPackage {
+            name: "hello".to_string(),
+            version_spec: None,
+            resolved_version: "2.12.1".to_string(),
+            attribute_path: "hello".to_string(),
+            commit_hash: "abc123".to_string(),
+            platforms: None,
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
+        });
+        assert_save_rejected(
+            &nixy_config,
+            "profile 'default' lists 'hello' in both packages and resolved_packages",
+        );
+
+        let mut nixy_config = NixyConfig::default();
+        let profile = nixy_config.profiles.get_mut("default").unwrap();
+        profile.packages.push("tool".to_string());
+        profile.custom_packages.push(CustomPackage {
+            name: "tool".to_string(),
+            input_name: "tool".to_string(),
+            input_url: "github:owner/tool".to_string(),
+            package_output: "packages".to_string(),
+            source_name: None,
+            platforms: None,
+            unvalidated: false,
+            record: Default::default(),
+        });
+        assert_save_rejected(
+            &nixy_config,
+            "profile 'default' lists 'tool' in both packages and custom_packages",
+        );
+    }
+
     #[test]
     fn test_delete_active_profile_fails() {
         let mut config = NixyConfig::default();
@@ -422,6 +836,33 @@ mod tests {
         assert_eq!(profiles, vec!["default", "personal", "work"]);
     }
 
+    #[test]
+    fn test_profiles_referencing() {
+        let mut config = NixyConfig::default();
+        config.create_profile("work").unwrap();
+        config.create_profile("personal").unwrap();
+        config
+            .profiles
+            .get_mut("default")
+            .unwrap()
+            .add_package("my-tool");
+        config
+            .profiles
+            .get_mut("work")
+            .unwrap()
+            .add_package("my-tool");
+
+        assert_eq!(
+            config.profiles_referencing("my-tool", "default"),
+            vec!["work"]
+        );
+        assert_eq!(
+            config.profiles_referencing("my-tool", "personal"),
+            vec!["default", "work"]
+        );
+        assert!(config.profiles_referencing("other", "default").is_empty());
+    }
+
     #[test]
     fn test_profile_config_add_package() {
         let mut profile = ProfileConfig::default();
@@ -441,6 +882,12 @@ mod tests {
             attribute_path: "nodejs_20".to_string(),
             commit_hash: "abc123".to_string(),
             platforms: None,
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
         });
         assert!(profile.has_package("nodejs"));
         assert_eq!(
@@ -462,8 +909,19 @@ mod tests {
             package_output: "packages".to_string(),
             source_name: None,
             platforms: None,
+            unvalidated: false,
+            record: Default::default(),
         });
         assert!(profile.has_package("neovim"));
+
+        *profile.platforms_mut("neovim").unwrap() = Some(vec!["aarch64-darwin".to_string()]);
+        assert_eq!(
+            profile.custom_packages[0].platforms,
+            Some(vec!["aarch64-darwin".to_string()])
+        );
+        profile.add_package("hello");
+        assert!(profile.platforms_mut("hello").is_none());
+        assert!(profile.platforms_mut("missing").is_none());
     }
 
     #[test]
@@ -485,6 +943,12 @@ mod tests {
             attribute_path: "nodejs_20".to_string(),
             commit_hash: "abc123".to_string(),
             platforms: None,
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
         });
 
         let state: crate::state::PackageState = (&profile).into();
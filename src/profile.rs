@@ -11,6 +11,8 @@
 //!
 //! ~/.local/state/nixy/
 //! ├── env -> ...          # Symlink to current profile's build
+//! ├── builds/
+//! │   └── <name> -> ...   # Each profile's last successful build (a GC root)
 //! └── profiles/
 //!     ├── default/
 //!     │   ├── flake.nix   # Generated from nixy.json
@@ -36,17 +38,39 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::LazyLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use regex::Regex;
 
 use crate::config::{Config, DEFAULT_PROFILE};
 use crate::error::{Error, Result};
+use crate::nix::Nix;
 use crate::nixy_config::{nixy_json_exists, NixyConfig};
 
 /// Regex for validating profile names (alphanumeric, dashes, underscores only)
 static PROFILE_NAME_REGEX: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"^[a-zA-Z0-9_-]+$").expect("Invalid regex pattern"));
 
+/// Marker file in a profile's state directory, present when its flake.nix was
+/// regenerated without rebuilding the environment. Holds the reason on the
+/// first line and the Unix time it was set on the second.
+const NEEDS_SYNC_MARKER: &str = "needs-sync";
+
+/// Entries nixy itself keeps in a profile's state directory. The directory is
+/// the flake source, so anything else gets copied into the store on every
+/// evaluation.
+const STATE_DIR_ENTRIES: &[&str] = &["flake.nix", "flake.lock", NEEDS_SYNC_MARKER];
+
+/// Why a profile's environment is out of date, read from its needs-sync marker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NeedsSync {
+    /// The operation that changed the flake, e.g. "nixy update"
+    pub reason: String,
+    /// When the marker was set (`None` for markers written before nixy
+    /// recorded the time)
+    pub since: Option<SystemTime>,
+}
+
 /// Profile management
 pub struct Profile {
     /// State directory for this profile (~/.local/state/nixy/profiles/<name>)
@@ -55,6 +79,9 @@ pub struct Profile {
     pub flake_path: PathBuf,
     /// Legacy directory path (~/.config/nixy/profiles/<name>) - for migration
     pub legacy_dir: PathBuf,
+    /// Link to the last build of this profile (~/.local/state/nixy/builds/<name>),
+    /// kept outside the state directory so it is not part of the flake source
+    pub build_link: PathBuf,
 }
 
 impl Profile {
@@ -66,6 +93,7 @@ impl Profile {
             flake_path: state_dir.join("flake.nix"),
             state_dir,
             legacy_dir,
+            build_link: config.state_dir.join("builds").join(name),
         }
     }
 
@@ -75,29 +103,106 @@ impl Profile {
     }
 
     /// Check if profile exists in nixy.json
+    ///
+    /// An unreadable nixy.json counts as the profile not existing; load
+    /// [`NixyConfig`] directly to report the error instead.
     pub fn exists_in_config(name: &str, config: &Config) -> bool {
-        if !nixy_json_exists(config) {
-            return false;
-        }
-
-        match NixyConfig::load(config) {
-            Ok(nixy_config) => nixy_config.profile_exists(name),
-            Err(err) => {
-                eprintln!(
-                    "Warning: failed to load nixy.json while checking for profile '{}': {}",
-                    name, err
-                );
-                false
-            }
-        }
+        nixy_json_exists(config) && NixyConfig::load(config).is_ok_and(|c| c.profile_exists(name))
     }
 
     /// Create the profile state directory
     pub fn create(&self) -> Result<()> {
-        fs::create_dir_all(&self.state_dir)?;
+        create_state_dir(&self.state_dir)
+    }
+
+    /// Record that the profile's flake changed without a rebuild.
+    ///
+    /// Every operation that regenerates a flake.nix and does not build it
+    /// must call this; a successful build clears it with
+    /// [`Profile::clear_needs_sync`].
+    pub fn mark_needs_sync(&self, reason: &str) -> Result<()> {
+        create_state_dir(&self.state_dir)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        fs::write(
+            self.state_dir.join(NEEDS_SYNC_MARKER),
+            format!("{}\n{}\n", reason, now),
+        )?;
         Ok(())
     }
 
+    /// Clear the needs-sync marker after the environment was rebuilt
+    pub fn clear_needs_sync(&self) {
+        let _ = fs::remove_file(self.state_dir.join(NEEDS_SYNC_MARKER));
+    }
+
+    /// Build the profile's flake and point `env_link` at the result
+    ///
+    /// The build lands in the profile's build link, which `nix build
+    /// --out-link` registers as a GC root, so `nix-collect-garbage` keeps the
+    /// last build of every profile until the profile is deleted. Switching
+    /// back can then reuse it without building.
+    pub fn build_env(&self, flake_dir: &Path, env_link: &Path) -> Result<()> {
+        if env_link.starts_with(flake_dir) {
+            return Err(Error::Usage(format!(
+                "Environment link {} must be outside the flake directory {}. Set NIXY_ENV to another path.",
+                env_link.display(),
+                flake_dir.display()
+            )));
+        }
+        self.build(flake_dir)?;
+        repoint_link(env_link, &fs::read_link(&self.build_link)?)
+    }
+
+    /// Build the profile's flake into its build link only, leaving the
+    /// environment link alone
+    pub fn build(&self, flake_dir: &Path) -> Result<()> {
+        if let Some(parent) = self.build_link.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Nix::build(flake_dir, "default", &self.build_link)
+    }
+
+    /// The store path of the profile's last build, if it still exists
+    pub fn last_build(&self) -> Option<PathBuf> {
+        fs::read_link(&self.build_link)
+            .ok()
+            .filter(|target| target.exists())
+    }
+
+    /// Why the profile's environment is out of date, if it is
+    pub fn needs_sync(&self) -> Option<NeedsSync> {
+        let content = fs::read_to_string(self.state_dir.join(NEEDS_SYNC_MARKER)).ok()?;
+        let mut lines = content.lines();
+        let reason = lines
+            .next()
+            .map(str::trim)
+            .filter(|r| !r.is_empty())
+            .unwrap_or("unknown")
+            .to_string();
+        let since = lines
+            .next()
+            .and_then(|l| l.trim().parse::<u64>().ok())
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+        Some(NeedsSync { reason, since })
+    }
+
+    /// Names of entries in the state directory that nixy did not create
+    pub fn unexpected_entries(&self) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(&self.state_dir) else {
+            return Vec::new();
+        };
+        let mut names: Vec<String> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .filter(|name| !STATE_DIR_ENTRIES.contains(&name.as_str()))
+            .collect();
+        names.sort();
+        names
+    }
+
     /// Delete the profile state directory (and legacy directory if exists)
     pub fn delete(&self) -> Result<()> {
         if self.state_dir.exists() {
@@ -106,25 +211,137 @@ impl Profile {
         if self.legacy_dir.exists() {
             fs::remove_dir_all(&self.legacy_dir)?;
         }
+        if self.build_link.is_symlink() {
+            fs::remove_file(&self.build_link)?;
+        }
         Ok(())
     }
 }
 
+/// What is wrong with an existing env symlink
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvLinkProblem {
+    /// The build it points to is gone, e.g. garbage-collected
+    Dangling(PathBuf),
+    /// It points somewhere outside the Nix store
+    OutsideStore(PathBuf),
+    /// The build it points to cannot be read
+    Unreadable(PathBuf, String),
+    /// Something other than a symlink is in its place
+    NotSymlink,
+}
+
+impl std::fmt::Display for EnvLinkProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Dangling(target) => write!(
+                f,
+                "its build {} is no longer in the Nix store (garbage-collected?)",
+                target.display()
+            ),
+            Self::OutsideStore(target) => write!(
+                f,
+                "it points to {}, which is not in the Nix store",
+                target.display()
+            ),
+            Self::Unreadable(target, reason) => {
+                write!(
+                    f,
+                    "its build {} cannot be read: {}",
+                    target.display(),
+                    reason
+                )
+            }
+            Self::NotSymlink => write!(f, "it is not a symlink"),
+        }
+    }
+}
+
+/// Check that the env symlink, if there is one, points at a readable build
+/// in `store_dir`
+pub fn check_env_link(env_link: &Path, store_dir: &Path) -> Option<EnvLinkProblem> {
+    if !env_link.is_symlink() {
+        return env_link.exists().then_some(EnvLinkProblem::NotSymlink);
+    }
+    let target = fs::read_link(env_link).ok()?;
+    if !env_link.exists() {
+        return Some(EnvLinkProblem::Dangling(target));
+    }
+    if !target.starts_with(store_dir) {
+        return Some(EnvLinkProblem::OutsideStore(target));
+    }
+    match fs::read_dir(env_link) {
+        Ok(_) => None,
+        Err(e) => Some(EnvLinkProblem::Unreadable(target, e.to_string())),
+    }
+}
+
+/// Where the env symlink points when that build is gone, e.g. after
+/// `nix-collect-garbage` removed it from the store
+pub fn stale_env_target(env_link: &Path) -> Option<PathBuf> {
+    // exists() follows the link, so only a dangling link gets past this
+    if !env_link.is_symlink() || env_link.exists() {
+        return None;
+    }
+    fs::read_link(env_link).ok()
+}
+
+/// Point the symlink at `link` to `target`, replacing it atomically so the
+/// link is never missing in between
+pub fn repoint_link(link: &Path, target: &Path) -> Result<()> {
+    if let Some(parent) = link.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file_name = link
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let tmp = link.with_file_name(format!(".{}.tmp", file_name));
+    let _ = fs::remove_file(&tmp);
+    std::os::unix::fs::symlink(target, &tmp)?;
+    fs::rename(&tmp, link)?;
+    Ok(())
+}
+
+/// Remove `result` / `result-<output>` out-links left behind by running
+/// `nix build` inside a flake directory, returning the removed names
+pub fn remove_result_links(flake_dir: &Path) -> Result<Vec<String>> {
+    let mut removed = Vec::new();
+    for link in result_links(flake_dir) {
+        fs::remove_file(&link)?;
+        removed.extend(link.file_name().map(|n| n.to_string_lossy().into_owned()));
+    }
+    Ok(removed)
+}
+
+/// The `result` / `result-<output>` out-links in a flake directory, sorted
+pub fn result_links(flake_dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(flake_dir) else {
+        return Vec::new();
+    };
+
+    let mut links: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            (name == "result" || name.starts_with("result-")) && entry.path().is_symlink()
+        })
+        .map(|entry| entry.path())
+        .collect();
+    links.sort();
+    links
+}
+
 /// Get the active profile name
+///
+/// Falls back to the legacy active file (or the default profile) when
+/// nixy.json is missing or unreadable; load [`NixyConfig`] directly to report
+/// a broken config.
 pub fn get_active_profile(config: &Config) -> String {
     // Try to read from nixy.json first (new format)
     if nixy_json_exists(config) {
-        match NixyConfig::load(config) {
-            Ok(nixy_config) => {
-                return nixy_config.active_profile.clone();
-            }
-            Err(e) => {
-                eprintln!(
-                    "Warning: failed to load nixy.json for active profile: {}",
-                    e
-                );
-                // Fall through to legacy active file / default profile.
-            }
+        if let Ok(nixy_config) = NixyConfig::load(config) {
+            return nixy_config.active_profile;
         }
     }
 
@@ -212,19 +429,51 @@ pub fn get_flake_path(config: &Config) -> PathBuf {
     profile.flake_path
 }
 
+/// Get the flake directory for a named profile (nixy.json format)
+///
+/// This is the profile's state directory, which is created if missing.
+pub fn get_profile_flake_dir(config: &Config, name: &str) -> Result<PathBuf> {
+    let profile = Profile::new(name, config);
+    create_state_dir(&profile.state_dir)?;
+    Ok(profile.state_dir)
+}
+
+/// Create a state directory, explaining the likely cause on failure.
+///
+/// Nearly every command starts here, so a bare "Permission denied" would be
+/// the first and least helpful thing a misconfigured setup shows.
+fn create_state_dir(path: &Path) -> Result<()> {
+    let Err(e) = fs::create_dir_all(path) else {
+        return Ok(());
+    };
+
+    // A file somewhere along the path blocks the directory regardless of the
+    // error kind the OS reports
+    let blocker = path.ancestors().find(|p| p.exists());
+    let reason = match blocker {
+        Some(p) if !p.is_dir() => format!("{} is a file, not a directory", p.display()),
+        Some(p) if e.kind() == std::io::ErrorKind::PermissionDenied => format!(
+            "permission denied. Check that {} is writable by you, or set NIXY_STATE_DIR to a writable location",
+            p.display()
+        ),
+        _ => e.to_string(),
+    };
+    Err(Error::StateDir {
+        path: path.to_path_buf(),
+        reason,
+    })
+}
+
 /// Get the flake directory for the active profile
 ///
 /// In the new format, this returns the state directory for the profile.
 /// This function also ensures the directory exists.
 pub fn get_flake_dir(config: &Config) -> Result<PathBuf> {
     let active = get_active_profile(config);
-    let profile = Profile::new(&active, config);
 
     // If using new format (nixy.json exists), return state directory
     if nixy_json_exists(config) {
-        // Ensure the state directory exists
-        fs::create_dir_all(&profile.state_dir)?;
-        return Ok(profile.state_dir);
+        return get_profile_flake_dir(config, &active);
     }
 
     // Legacy: get flake path and determine directory
@@ -331,8 +580,8 @@ fn merge_packages_dir(src: &Path, dst: &Path) -> Result<()> {
     Ok(())
 }
 
-/// Recursively copy a directory
-fn copy_dir_recursive(src: &Path, dst: &Path) -> Result<()> {
+/// Recursively copy a directory, skipping symlinks
+pub fn copy_dir_recursive(src: &Path, dst: &Path) -> Result<()> {
     fs::create_dir_all(dst)?;
 
     for entry in fs::read_dir(src)? {
@@ -398,6 +647,50 @@ mod tests {
         assert!(validate_profile_name("").is_err());
     }
 
+    #[test]
+    fn test_get_profile_flake_dir_blocked_by_file() {
+        let temp = TempDir::new().unwrap();
+        let config = test_config(&temp);
+        // A stray file where the state directory should be
+        fs::write(temp.path().join("state"), "").unwrap();
+
+        let err = get_profile_flake_dir(&config, DEFAULT_PROFILE).unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("Cannot create state directory"), "{}", msg);
+        assert!(msg.contains("state/profiles/default"), "{}", msg);
+        assert!(
+            msg.contains(&format!(
+                "{} is a file, not a directory",
+                temp.path().join("state").display()
+            )),
+            "{}",
+            msg
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_get_profile_flake_dir_unwritable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = TempDir::new().unwrap();
+        let config = test_config(&temp);
+        let state_dir = temp.path().join("state");
+        fs::create_dir_all(&state_dir).unwrap();
+        fs::set_permissions(&state_dir, fs::Permissions::from_mode(0o555)).unwrap();
+
+        // root ignores directory permissions; nothing to simulate then
+        if fs::create_dir(state_dir.join("probe")).is_ok() {
+            return;
+        }
+
+        let err = get_profile_flake_dir(&config, DEFAULT_PROFILE).unwrap_err();
+        fs::set_permissions(&state_dir, fs::Permissions::from_mode(0o755)).unwrap();
+        let msg = err.to_string();
+        assert!(msg.contains("permission denied"), "{}", msg);
+        assert!(msg.contains("NIXY_STATE_DIR"), "{}", msg);
+    }
+
     #[test]
     fn test_get_active_profile_default() {
         let temp = TempDir::new().unwrap();
@@ -458,6 +751,165 @@ mod tests {
         assert!(!profile.exists());
     }
 
+    #[test]
+    fn test_profile_unexpected_entries() {
+        let temp = TempDir::new().unwrap();
+        let config = test_config(&temp);
+
+        let profile = Profile::new("test", &config);
+        assert!(profile.unexpected_entries().is_empty());
+
+        profile.create().unwrap();
+        fs::write(&profile.flake_path, "{}").unwrap();
+        fs::write(profile.state_dir.join("flake.lock"), "{}").unwrap();
+        profile.mark_needs_sync("test").unwrap();
+        assert!(profile.unexpected_entries().is_empty());
+
+        fs::write(profile.state_dir.join("flake.nix~"), "{}").unwrap();
+        fs::create_dir(profile.state_dir.join("packages")).unwrap();
+        assert_eq!(profile.unexpected_entries(), vec!["flake.nix~", "packages"]);
+    }
+
+    #[test]
+    fn test_needs_sync_round_trip() {
+        let temp = TempDir::new().unwrap();
+        let config = test_config(&temp);
+
+        let profile = Profile::new("test", &config);
+        assert_eq!(profile.needs_sync(), None);
+
+        let before = SystemTime::now() - Duration::from_secs(1);
+        profile.mark_needs_sync("nixy update").unwrap();
+        let marker = profile.needs_sync().unwrap();
+        assert_eq!(marker.reason, "nixy update");
+        assert!(marker.since.unwrap() >= before);
+
+        profile.clear_needs_sync();
+        assert_eq!(profile.needs_sync(), None);
+    }
+
+    #[test]
+    fn test_needs_sync_reads_marker_without_time() {
+        let temp = TempDir::new().unwrap();
+        let config = test_config(&temp);
+
+        // Written by older versions: just the reason
+        let profile = Profile::new("test", &config);
+        profile.create().unwrap();
+        fs::write(profile.state_dir.join(NEEDS_SYNC_MARKER), "nixy update\n").unwrap();
+        assert_eq!(
+            profile.needs_sync(),
+            Some(NeedsSync {
+                reason: "nixy update".to_string(),
+                since: None
+            })
+        );
+    }
+
+    #[test]
+    fn test_stale_env_target() {
+        let temp = TempDir::new().unwrap();
+        let link = temp.path().join("env");
+        assert_eq!(stale_env_target(&link), None);
+
+        let build = temp.path().join("store/abc-nixy-env");
+        fs::create_dir_all(&build).unwrap();
+        repoint_link(&link, &build).unwrap();
+        assert_eq!(stale_env_target(&link), None);
+
+        fs::remove_dir(&build).unwrap();
+        assert_eq!(stale_env_target(&link), Some(build));
+    }
+
+    #[test]
+    fn test_check_env_link() {
+        let temp = TempDir::new().unwrap();
+        let store = temp.path().join("store");
+        let link = temp.path().join("env");
+        assert_eq!(check_env_link(&link, &store), None);
+
+        let build = store.join("abc-nixy-env");
+        fs::create_dir_all(&build).unwrap();
+        repoint_link(&link, &build).unwrap();
+        assert_eq!(check_env_link(&link, &store), None);
+
+        // A build removed behind nixy's back, like nix-collect-garbage does
+        let gone = temp.path().join("gone");
+        fs::create_dir_all(&gone).unwrap();
+        let dangling = temp.path().join("dangling-env");
+        repoint_link(&dangling, &gone).unwrap();
+        fs::remove_dir(&gone).unwrap();
+        assert_eq!(
+            check_env_link(&dangling, &store),
+            Some(EnvLinkProblem::Dangling(gone))
+        );
+
+        let elsewhere = temp.path().join("elsewhere");
+        fs::create_dir_all(&elsewhere).unwrap();
+        repoint_link(&link, &elsewhere).unwrap();
+        assert_eq!(
+            check_env_link(&link, &store),
+            Some(EnvLinkProblem::OutsideStore(elsewhere))
+        );
+
+        // A build that is a file, not a directory, cannot be listed
+        let file = store.join("def-file");
+        fs::write(&file, "").unwrap();
+        repoint_link(&link, &file).unwrap();
+        assert!(matches!(
+            check_env_link(&link, &store),
+            Some(EnvLinkProblem::Unreadable(target, _)) if target == file
+        ));
+
+        let real_dir = temp.path().join("real-env");
+        fs::create_dir_all(&real_dir).unwrap();
+        assert_eq!(
+            check_env_link(&real_dir, &store),
+            Some(EnvLinkProblem::NotSymlink)
+        );
+    }
+
+    #[test]
+    fn test_last_build_round_trip() {
+        let temp = TempDir::new().unwrap();
+        let config = test_config(&temp);
+        let profile = Profile::new("test", &config);
+        assert_eq!(profile.last_build(), None);
+
+        let build = temp.path().join("store/abc-nixy-env");
+        fs::create_dir_all(&build).unwrap();
+        repoint_link(&profile.build_link, &build).unwrap();
+        assert_eq!(profile.last_build(), Some(build.clone()));
+
+        // A collected build is no build
+        fs::remove_dir(&build).unwrap();
+        assert_eq!(profile.last_build(), None);
+
+        profile.delete().unwrap();
+        assert!(!profile.build_link.is_symlink());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_remove_result_links() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path();
+        std::os::unix::fs::symlink("/nix/store/missing", dir.join("result")).unwrap();
+        std::os::unix::fs::symlink("/nix/store/missing", dir.join("result-man")).unwrap();
+        fs::write(dir.join("result-notes"), "not a link").unwrap();
+        fs::write(dir.join("flake.nix"), "{}").unwrap();
+
+        let removed = remove_result_links(dir).unwrap();
+        assert_eq!(removed, vec!["result", "result-man"]);
+        assert!(!dir.join("result").is_symlink());
+        assert!(dir.join("result-notes").exists());
+        assert!(dir.join("flake.nix").exists());
+
+        assert!(remove_result_links(&dir.join("missing"))
+            .unwrap()
+            .is_empty());
+    }
+
     #[test]
     fn test_list_profiles_empty() {
         let temp = TempDir::new().unwrap();
@@ -27,6 +27,13 @@ use crate::state::PackageState;
 /// Flag indicating operation completed successfully (prevents late rollback)
 static COMPLETED: AtomicBool = AtomicBool::new(false);
 
+/// Set while a program started by `nixy run` is in the foreground: it gets
+/// the same Ctrl+C and decides how nixy exits
+static CHILD_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Scratch directory to delete on Ctrl+C (the temporary flake of `nixy shell`)
+static SCRATCH_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
 /// Global rollback context
 static ROLLBACK_CONTEXT: Mutex<Option<RollbackContext>> = Mutex::new(None);
 
@@ -51,6 +58,8 @@ pub enum OriginalState {
 pub struct RollbackContext {
     pub flake_dir: PathBuf,
     pub original_state: OriginalState,
+    /// Files moved out of the way, as (original path, current path), to move back
+    pub set_aside: Vec<(PathBuf, PathBuf)>,
 }
 
 impl RollbackContext {
@@ -62,6 +71,7 @@ impl RollbackContext {
                 state_path,
                 state: original_state,
             },
+            set_aside: Vec::new(),
         }
     }
 
@@ -79,13 +89,28 @@ impl RollbackContext {
                 config,
                 global_packages_dir: global_packages_dir.map(|p| p.to_path_buf()),
             },
+            set_aside: Vec::new(),
         }
     }
+
+    /// Also move `set_aside` files back, before the flake is regenerated
+    pub fn with_set_aside(mut self, set_aside: Vec<(PathBuf, PathBuf)>) -> Self {
+        self.set_aside = set_aside;
+        self
+    }
 }
 
 /// Initialize the Ctrl+C handler. Should be called once at startup.
 pub fn init_signal_handler() {
     if let Err(e) = ctrlc::set_handler(move || {
+        if CHILD_RUNNING.load(Ordering::SeqCst) {
+            return;
+        }
+
+        if let Some(dir) = SCRATCH_DIR.lock().ok().and_then(|mut guard| guard.take()) {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+
         // Check if operation already completed successfully
         if COMPLETED.load(Ordering::SeqCst) {
             std::process::exit(130);
@@ -106,6 +131,18 @@ pub fn init_signal_handler() {
     }
 }
 
+/// Leave Ctrl+C to a child process while `running` (see `nixy run`)
+pub fn set_child_running(running: bool) {
+    CHILD_RUNNING.store(running, Ordering::SeqCst);
+}
+
+/// Have Ctrl+C delete `dir` (until it is set back to `None`)
+pub fn set_scratch_dir(dir: Option<PathBuf>) {
+    if let Ok(mut guard) = SCRATCH_DIR.lock() {
+        *guard = dir;
+    }
+}
+
 /// Set the rollback context before a potentially long operation.
 ///
 /// Note: Silently fails if the mutex is poisoned (which indicates a prior panic).
@@ -139,6 +176,23 @@ fn take_context() -> Option<RollbackContext> {
 
 /// Perform the actual rollback
 fn perform_rollback(ctx: &RollbackContext) {
+    // The original flake may refer to these, so they go back first
+    for (original, current) in &ctx.set_aside {
+        if let Err(e) = std::fs::rename(current, original) {
+            eprintln!(
+                "Warning: Failed to restore {} (it is in {}): {}",
+                original.display(),
+                current.display(),
+                e
+            );
+        }
+    }
+    if let Some((_, current)) = ctx.set_aside.first() {
+        if let Some(dir) = current.parent() {
+            let _ = std::fs::remove_dir(dir);
+        }
+    }
+
     // Restore original state based on format
     match &ctx.original_state {
         OriginalState::Legacy { state_path, state } => {
@@ -179,7 +233,9 @@ fn perform_rollback(ctx: &RollbackContext) {
             // Regenerate flake.nix from original config
             if let Some(profile) = config.get_active_profile() {
                 let gpd = global_packages_dir.as_deref();
-                if let Err(e) = regenerate_flake_from_profile(&ctx.flake_dir, profile, gpd) {
+                if let Err(e) =
+                    regenerate_flake_from_profile(&ctx.flake_dir, profile, gpd, config.format)
+                {
                     eprintln!("Warning: Failed to restore flake.nix: {}", e);
                 }
             }
@@ -0,0 +1,479 @@
+//! Snapshots of profiles taken before operations that are hard to reverse.
+//!
+//! A snapshot keeps, for each profile it covers, the profile's section of
+//! nixy.json and its flake.nix and flake.lock exactly as they were. Once the
+//! operation is over, [`Snapshot::seal`] records a fingerprint of what it
+//! left behind, so a later restore can tell whether anything else changed
+//! the profile in the meantime.
+//!
+//! Every successful build also records a generation: a snapshot tagged
+//! [`generation_tag`] of the profile as built, together with the store path
+//! of the environment. `nixy rollback` goes back to the one before.
+//!
+//! ```text
+//! ~/.local/state/nixy/snapshots/
+//! └── <time>-<tag>/
+//!     ├── snapshot.json     # tag, command, time and fingerprints
+//!     └── <profile>/
+//!         ├── profile.json  # the profile's section of nixy.json
+//!         ├── flake.nix
+//!         └── flake.lock
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::nixy_config::{NixyConfig, ProfileConfig};
+use crate::profile::Profile;
+
+/// Tag of the snapshots `nixy update` takes
+pub const PRE_UPDATE: &str = "pre-update";
+
+/// Snapshots kept per tag; older ones are removed when a new one is taken
+pub const KEEP_PER_TAG: usize = 5;
+
+const META_FILE: &str = "snapshot.json";
+const PROFILE_FILE: &str = "profile.json";
+const FLAKE_FILES: &[&str] = &["flake.nix", "flake.lock"];
+
+/// What a snapshot is of, stored next to the files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotMeta {
+    /// Kind of operation it was taken before, e.g. [`PRE_UPDATE`]
+    pub tag: String,
+    /// The command line that took it
+    pub command: String,
+    /// Unix time it was taken
+    pub created: u64,
+    /// Fingerprint of each covered profile after the operation (`None` until
+    /// sealed, e.g. when the operation was interrupted)
+    pub profiles: BTreeMap<String, Option<String>>,
+    /// Store path of the environment built from this state (generations only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<PathBuf>,
+}
+
+/// A snapshot on disk
+#[derive(Debug)]
+pub struct Snapshot {
+    pub dir: PathBuf,
+    pub meta: SnapshotMeta,
+}
+
+impl Snapshot {
+    /// Save the current state of `profiles` under a new snapshot tagged
+    /// `tag`, then drop the oldest snapshots beyond [`KEEP_PER_TAG`]
+    pub fn take(
+        config: &Config,
+        nixy_config: &NixyConfig,
+        profiles: &[String],
+        tag: &str,
+        command: &str,
+    ) -> Result<Self> {
+        let root = snapshots_dir(config);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        // Nanoseconds keep names unique and sorted in the order taken
+        let mut dir = root.join(format!("{:020}-{}", now.as_nanos(), tag));
+        let mut n = 1;
+        while dir.exists() {
+            n += 1;
+            dir = root.join(format!("{:020}-{}-{}", now.as_nanos(), tag, n));
+        }
+
+        fs::create_dir_all(&dir)?;
+        let mut covered = BTreeMap::new();
+        for name in profiles {
+            let profile = nixy_config
+                .profiles
+                .get(name)
+                .ok_or_else(|| Error::ProfileNotFound(name.clone()))?;
+            let target = dir.join(name);
+            fs::create_dir_all(&target)?;
+            fs::write(target.join(PROFILE_FILE), profile_json(profile)?)?;
+            let state_dir = Profile::new(name, config).state_dir;
+            for file in FLAKE_FILES {
+                if state_dir.join(file).exists() {
+                    fs::copy(state_dir.join(file), target.join(file))?;
+                }
+            }
+            covered.insert(name.clone(), None);
+        }
+
+        let snapshot = Self {
+            dir,
+            meta: SnapshotMeta {
+                tag: tag.to_string(),
+                command: command.to_string(),
+                created: now.as_secs(),
+                profiles: covered,
+                env: None,
+            },
+        };
+        snapshot.write_meta()?;
+        prune(config, tag, KEEP_PER_TAG)?;
+        Ok(snapshot)
+    }
+
+    /// Record that `profile` was just built into `env` as its newest
+    /// generation
+    ///
+    /// Does nothing when the newest generation already is `env`, e.g. after a
+    /// sync without changes.
+    pub fn record_generation(
+        config: &Config,
+        profile: &str,
+        env: &Path,
+        command: &str,
+    ) -> Result<()> {
+        let tag = generation_tag(profile);
+        let latest = Self::latest(config, &tag)?;
+        if latest.is_some_and(|g| g.meta.env.as_deref() == Some(env)) {
+            return Ok(());
+        }
+
+        let nixy_config = NixyConfig::load(config)?;
+        let mut generation =
+            Self::take(config, &nixy_config, &[profile.to_string()], &tag, command)?;
+        generation.meta.env = Some(env.to_path_buf());
+        generation.seal(config)
+    }
+
+    /// Record what the operation left behind, to compare against on restore
+    pub fn seal(&mut self, config: &Config) -> Result<()> {
+        let nixy_config = NixyConfig::load(config)?;
+        for (name, after) in self.meta.profiles.iter_mut() {
+            *after = Some(fingerprint(config, &nixy_config, name)?);
+        }
+        self.write_meta()
+    }
+
+    /// Profiles that changed after the operation (or whose operation never
+    /// finished), which restoring would silently undo as well
+    pub fn changed_profiles(&self, config: &Config) -> Result<Vec<String>> {
+        let nixy_config = NixyConfig::load(config)?;
+        let mut changed = Vec::new();
+        for (name, after) in &self.meta.profiles {
+            if after.as_deref() != Some(fingerprint(config, &nixy_config, name)?.as_str()) {
+                changed.push(name.clone());
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Put the covered profiles back as they were: their nixy.json sections,
+    /// and flake.nix / flake.lock byte for byte
+    pub fn restore(&self, config: &Config) -> Result<()> {
+        let mut nixy_config = NixyConfig::load(config)?;
+        for name in self.meta.profiles.keys() {
+            let source = self.dir.join(name);
+            let profile = self.profile(name)?;
+
+            let target = Profile::new(name, config);
+            target.create()?;
+            let state_dir = target.state_dir;
+            for file in FLAKE_FILES {
+                if source.join(file).exists() {
+                    fs::copy(source.join(file), state_dir.join(file))?;
+                } else if state_dir.join(file).exists() {
+                    fs::remove_file(state_dir.join(file))?;
+                }
+            }
+            nixy_config.profiles.insert(name.clone(), profile);
+        }
+        nixy_config.save(config)
+    }
+
+    /// The nixy.json section of covered profile `name` as it was
+    pub fn profile(&self, name: &str) -> Result<ProfileConfig> {
+        let source = self.dir.join(name);
+        let content = fs::read_to_string(source.join(PROFILE_FILE))?;
+        serde_json::from_str(&content)
+            .map_err(|e| Error::StateFile(format!("Invalid snapshot {}: {}", source.display(), e)))
+    }
+
+    /// Carry the snapshot over to profile `to` after `from` was renamed: a
+    /// generation of `from` becomes one of `to`
+    pub fn rename_profile(mut self, from: &str, to: &str) -> Result<Self> {
+        if let Some(after) = self.meta.profiles.remove(from) {
+            if self.dir.join(from).exists() {
+                fs::rename(self.dir.join(from), self.dir.join(to))?;
+            }
+            self.meta.profiles.insert(to.to_string(), after);
+        }
+        if self.meta.tag == generation_tag(from) {
+            let tag = generation_tag(to);
+            // Keep the time prefix, which orders the snapshots
+            let name = self.dir.file_name().unwrap_or_default().to_string_lossy();
+            if let Some((time, _)) = name.split_once('-') {
+                let dir = self.dir.with_file_name(format!("{}-{}", time, tag));
+                if !dir.exists() {
+                    fs::rename(&self.dir, &dir)?;
+                    self.dir = dir;
+                }
+            }
+            self.meta.tag = tag;
+        }
+        self.write_meta()?;
+        Ok(self)
+    }
+
+    /// Delete the snapshot
+    pub fn remove(self) -> Result<()> {
+        fs::remove_dir_all(&self.dir)?;
+        Ok(())
+    }
+
+    /// Snapshots tagged `tag`, oldest first
+    pub fn list(config: &Config, tag: &str) -> Result<Vec<Self>> {
+        let Ok(entries) = fs::read_dir(snapshots_dir(config)) else {
+            return Ok(Vec::new());
+        };
+        let mut dirs: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_dir())
+            .collect();
+        dirs.sort();
+
+        Ok(dirs
+            .into_iter()
+            .filter_map(|dir| {
+                let meta: SnapshotMeta =
+                    serde_json::from_str(&fs::read_to_string(dir.join(META_FILE)).ok()?).ok()?;
+                (meta.tag == tag).then_some(Self { dir, meta })
+            })
+            .collect())
+    }
+
+    /// The most recent snapshot tagged `tag`
+    pub fn latest(config: &Config, tag: &str) -> Result<Option<Self>> {
+        Ok(Self::list(config, tag)?.pop())
+    }
+
+    fn write_meta(&self) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.meta)
+            .map_err(|e| Error::StateFile(e.to_string()))?;
+        fs::write(self.dir.join(META_FILE), json)?;
+        Ok(())
+    }
+}
+
+/// Tag of the generations recorded for `profile`
+pub fn generation_tag(profile: &str) -> String {
+    format!("generation-{}", profile)
+}
+
+fn snapshots_dir(config: &Config) -> PathBuf {
+    config.state_dir.join("snapshots")
+}
+
+/// Remove all but the newest `keep` snapshots tagged `tag`
+fn prune(config: &Config, tag: &str, keep: usize) -> Result<()> {
+    let snapshots = Snapshot::list(config, tag)?;
+    let excess = snapshots.len().saturating_sub(keep);
+    for snapshot in snapshots.into_iter().take(excess) {
+        snapshot.remove()?;
+    }
+    Ok(())
+}
+
+fn profile_json(profile: &ProfileConfig) -> Result<String> {
+    serde_json::to_string_pretty(profile).map_err(|e| Error::StateFile(e.to_string()))
+}
+
+/// FNV-1a over the profile's nixy.json section and flake files
+fn fingerprint(config: &Config, nixy_config: &NixyConfig, name: &str) -> Result<String> {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut feed = |bytes: &[u8]| {
+        for b in bytes {
+            hash ^= u64::from(*b);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        // Separator, so moving bytes between parts changes the result
+        hash ^= 0xff;
+        hash = hash.wrapping_mul(0x100000001b3);
+    };
+
+    match nixy_config.profiles.get(name) {
+        Some(profile) => feed(profile_json(profile)?.as_bytes()),
+        None => feed(b"<missing>"),
+    }
+    let state_dir = Profile::new(name, config).state_dir;
+    for file in FLAKE_FILES {
+        feed(&read_or_empty(&state_dir.join(file)));
+    }
+    Ok(format!("{:016x}", hash))
+}
+
+fn read_or_empty(path: &Path) -> Vec<u8> {
+    fs::read(path).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn setup(temp: &TempDir) -> (Config, NixyConfig) {
+        let config = Config::from_dirs(temp.path().join("config"), temp.path().join("state"));
+        let mut nixy_config = NixyConfig::load(&config).unwrap();
+        nixy_config
+            .get_active_profile_mut()
+            .unwrap()
+            .add_package("hello");
+        nixy_config.save(&config).unwrap();
+
+        let state_dir = Profile::new("default", &config).state_dir;
+        fs::create_dir_all(&state_dir).unwrap();
+        fs::write(state_dir.join("flake.nix"), "{ original }\n").unwrap();
+        fs::write(state_dir.join("flake.lock"), "{\"version\": 7}\n").unwrap();
+        (config, nixy_config)
+    }
+
+    #[test]
+    fn test_restore_puts_files_back_byte_for_byte() {
+        let temp = TempDir::new().unwrap();
+        let (config, nixy_config) = setup(&temp);
+        let state_dir = Profile::new("default", &config).state_dir;
+
+        let mut snapshot = Snapshot::take(
+            &config,
+            &nixy_config,
+            &["default".to_string()],
+            PRE_UPDATE,
+            "nixy update --all",
+        )
+        .unwrap();
+
+        let mut changed = NixyConfig::load(&config).unwrap();
+        changed.get_active_profile_mut().unwrap().add_package("jq");
+        changed.save(&config).unwrap();
+        fs::write(state_dir.join("flake.nix"), "{ updated }\n").unwrap();
+        fs::remove_file(state_dir.join("flake.lock")).unwrap();
+        snapshot.seal(&config).unwrap();
+        assert!(snapshot.changed_profiles(&config).unwrap().is_empty());
+
+        snapshot.restore(&config).unwrap();
+        assert_eq!(
+            fs::read_to_string(state_dir.join("flake.nix")).unwrap(),
+            "{ original }\n"
+        );
+        assert_eq!(
+            fs::read_to_string(state_dir.join("flake.lock")).unwrap(),
+            "{\"version\": 7}\n"
+        );
+        let restored = NixyConfig::load(&config).unwrap();
+        assert!(!restored.get_active_profile().unwrap().has_package("jq"));
+    }
+
+    #[test]
+    fn test_record_generation_skips_rebuilds_of_the_same_env() {
+        let temp = TempDir::new().unwrap();
+        let (config, _) = setup(&temp);
+        let first = Path::new("/nix/store/aaaa-nixy-env");
+        let second = Path::new("/nix/store/bbbb-nixy-env");
+
+        Snapshot::record_generation(&config, "default", first, "nixy sync").unwrap();
+        Snapshot::record_generation(&config, "default", first, "nixy sync").unwrap();
+        Snapshot::record_generation(&config, "default", second, "nixy install jq").unwrap();
+
+        let generations = Snapshot::list(&config, &generation_tag("default")).unwrap();
+        let envs: Vec<_> = generations
+            .iter()
+            .map(|g| g.meta.env.as_deref().unwrap())
+            .collect();
+        assert_eq!(envs, [first, second]);
+        assert!(Snapshot::list(&config, PRE_UPDATE).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_changed_profiles_sees_later_edits_and_unsealed_snapshots() {
+        let temp = TempDir::new().unwrap();
+        let (config, nixy_config) = setup(&temp);
+
+        let mut snapshot = Snapshot::take(
+            &config,
+            &nixy_config,
+            &["default".to_string()],
+            PRE_UPDATE,
+            "nixy update --all",
+        )
+        .unwrap();
+        // Interrupted before sealing
+        assert_eq!(snapshot.changed_profiles(&config).unwrap(), vec!["default"]);
+
+        snapshot.seal(&config).unwrap();
+        let state_dir = Profile::new("default", &config).state_dir;
+        fs::write(state_dir.join("flake.lock"), "{\"version\": 7, \"x\": 1}\n").unwrap();
+        assert_eq!(snapshot.changed_profiles(&config).unwrap(), vec!["default"]);
+    }
+
+    #[test]
+    fn test_take_keeps_only_the_newest_snapshots() {
+        let temp = TempDir::new().unwrap();
+        let (config, nixy_config) = setup(&temp);
+        let profiles = ["default".to_string()];
+
+        for i in 0..KEEP_PER_TAG + 2 {
+            Snapshot::take(
+                &config,
+                &nixy_config,
+                &profiles,
+                PRE_UPDATE,
+                &format!("nixy update {}", i),
+            )
+            .unwrap();
+        }
+        Snapshot::take(&config, &nixy_config, &profiles, "other", "nixy other").unwrap();
+
+        let kept = Snapshot::list(&config, PRE_UPDATE).unwrap();
+        assert_eq!(kept.len(), KEEP_PER_TAG);
+        assert_eq!(
+            kept.last().unwrap().meta.command,
+            format!("nixy update {}", KEEP_PER_TAG + 1)
+        );
+        assert_eq!(Snapshot::list(&config, "other").unwrap().len(), 1);
+        assert_eq!(
+            Snapshot::latest(&config, PRE_UPDATE)
+                .unwrap()
+                .unwrap()
+                .meta
+                .command,
+            format!("nixy update {}", KEEP_PER_TAG + 1)
+        );
+    }
+
+    #[test]
+    fn test_rename_profile_moves_generations() {
+        let temp = TempDir::new().unwrap();
+        let (config, nixy_config) = setup(&temp);
+        let profiles = ["default".to_string()];
+        let tag = generation_tag("default");
+        Snapshot::take(&config, &nixy_config, &profiles, &tag, "nixy sync").unwrap();
+
+        let generation = Snapshot::latest(&config, &tag).unwrap().unwrap();
+        let renamed = generation.rename_profile("default", "main").unwrap();
+        assert!(renamed.dir.join("main/flake.nix").exists());
+        assert!(!renamed.dir.join("default").exists());
+        assert!(renamed
+            .dir
+            .to_string_lossy()
+            .ends_with(&generation_tag("main")));
+
+        assert!(Snapshot::list(&config, &tag).unwrap().is_empty());
+        let moved = Snapshot::latest(&config, &generation_tag("main"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(moved.meta.command, "nixy sync");
+        assert!(moved.meta.profiles.contains_key("main"));
+        assert_eq!(moved.profile("main").unwrap().packages, vec!["hello"]);
+    }
+}
@@ -8,6 +8,7 @@
 
 use std::fs;
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use serde::{Deserialize, Serialize};
 
@@ -32,6 +33,117 @@ pub struct ResolvedNixpkgPackage {
     /// None means all platforms
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub platforms: Option<Vec<String>>,
+    /// Installed with `--pin`: `nixy update` leaves `commit_hash` alone
+    /// unless run with `--include-pinned`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    /// The Nixhub package `name` stands for, when it was installed under
+    /// another name (`--as`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_name: Option<String>,
+    /// Output linked instead of the default one (e.g. "doc")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// Flake output the attribute is under, when it is not
+    /// `legacyPackages` (e.g. "packages" for a flake-based nixpkgs fork)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub package_output: Option<String>,
+    /// Outputs buildEnv links for the package, replacing its
+    /// `meta.outputsToInstall` (e.g. ["out", "man", "lib"])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outputs: Option<Vec<String>>,
+    /// When it was installed and updated
+    #[serde(flatten)]
+    pub record: InstallRecord,
+}
+
+impl ResolvedNixpkgPackage {
+    /// Name to resolve the package by on Nixhub
+    pub fn source_package_name(&self) -> &str {
+        self.source_name.as_deref().unwrap_or(&self.name)
+    }
+
+    /// The flake output holding the package: `package_output`, or
+    /// `legacyPackages` as for nixpkgs itself
+    pub fn flake_output(&self) -> &str {
+        self.package_output.as_deref().unwrap_or("legacyPackages")
+    }
+
+    /// The attribute bound in the generated flake: the attribute path, plus
+    /// the selected output if any
+    pub fn attribute(&self) -> String {
+        match &self.output {
+            Some(output) => format!("{}.{}", self.attribute_path, output),
+            None => self.attribute_path.clone(),
+        }
+    }
+}
+
+/// When a package entry was installed, by which nixy, and when `nixy
+/// update` last changed it
+///
+/// Entries written before nixy recorded this, or migrated from
+/// packages.json, have none of it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct InstallRecord {
+    /// RFC 3339 time of the install, in UTC
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub installed_at: Option<String>,
+    /// Version of nixy that installed it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub installed_with: Option<String>,
+    /// RFC 3339 time `nixy update` last moved it to another version or commit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
+}
+
+impl InstallRecord {
+    /// The record of an install happening now
+    pub fn now() -> Self {
+        Self {
+            installed_at: Some(rfc3339_now()),
+            installed_with: Some(env!("CARGO_PKG_VERSION").to_string()),
+            updated_at: None,
+        }
+    }
+
+    /// Note that the entry was just updated
+    pub fn mark_updated(&mut self) {
+        self.updated_at = Some(rfc3339_now());
+    }
+}
+
+fn rfc3339_now() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    rfc3339(secs)
+}
+
+/// Format Unix time `secs` as an RFC 3339 UTC timestamp
+fn rfc3339(secs: u64) -> String {
+    let days = secs / 86_400;
+    let rem = secs % 86_400;
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
 }
 
 /// Custom package installed from a flake registry
@@ -47,6 +159,13 @@ pub struct CustomPackage {
     /// None means all platforms
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub platforms: Option<Vec<String>>,
+    /// Installed without checking the flake has it (`--no-validate`, or a
+    /// check that timed out), so `package_output` is a guess
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unvalidated: bool,
+    /// When it was installed and updated
+    #[serde(flatten)]
+    pub record: InstallRecord,
 }
 
 impl CustomPackage {
@@ -54,6 +173,23 @@ impl CustomPackage {
     pub fn source_package_name(&self) -> &str {
         self.source_name.as_deref().unwrap_or(&self.name)
     }
+
+    /// The git commit the flake reference fixes, as `?rev=<hash>` (what
+    /// `install --rev` stores) or `github:owner/repo/<hash>`
+    pub fn pinned_rev(&self) -> Option<&str> {
+        let url = self.input_url.split('#').next().unwrap_or_default();
+        let (base, query) = url.split_once('?').unwrap_or((url, ""));
+        if let Some(rev) = query.split('&').find_map(|p| p.strip_prefix("rev=")) {
+            return Some(rev);
+        }
+        let (scheme, path) = base.split_once(':')?;
+        if !matches!(scheme, "github" | "gitlab" | "sourcehut") {
+            return None;
+        }
+        path.split('/')
+            .nth(2)
+            .filter(|r| r.len() == 40 && r.chars().all(|c| c.is_ascii_hexdigit()))
+    }
 }
 
 /// State file for tracking installed packages.
@@ -236,6 +372,9 @@ const PLATFORM_ALIASES: &[(&str, &[&str])] = &[
     ("darwin", &["x86_64-darwin", "aarch64-darwin"]),
     ("macos", &["x86_64-darwin", "aarch64-darwin"]),
     ("linux", &["x86_64-linux", "aarch64-linux"]),
+    ("aarch64", &["aarch64-darwin", "aarch64-linux"]),
+    ("x86_64", &["x86_64-darwin", "x86_64-linux"]),
+    ("amd64", &["x86_64-darwin", "x86_64-linux"]),
 ];
 
 /// Normalize platform names, expanding aliases like "darwin" to full platform names.
@@ -256,9 +395,11 @@ pub fn normalize_platforms(platforms: &[String]) -> std::result::Result<Vec<Stri
                 result.push(p_lower);
             }
         } else {
+            let aliases: Vec<&str> = PLATFORM_ALIASES.iter().map(|(alias, _)| *alias).collect();
             return Err(format!(
-                "Invalid platform '{}'. Valid platforms: darwin, macos, linux, {}",
+                "Invalid platform '{}'. Valid platforms: {}, {}",
                 p,
+                aliases.join(", "),
                 VALID_PLATFORMS.join(", ")
             ));
         }
@@ -272,6 +413,73 @@ mod tests {
     use super::*;
     use tempfile::TempDir;
 
+    #[test]
+    fn test_custom_package_pinned_rev() {
+        let rev = "0123456789abcdef0123456789abcdef01234567";
+        let package = |url: &str| CustomPackage {
+            name: "tool".to_string(),
+            input_name: "github-owner-tool".to_string(),
+            input_url: url.to_string(),
+            package_output: "packages".to_string(),
+            source_name: None,
+            platforms: None,
+            unvalidated: false,
+            record: Default::default(),
+        };
+        assert_eq!(
+            package(&format!("github:owner/tool?dir=cli&rev={}", rev)).pinned_rev(),
+            Some(rev)
+        );
+        assert_eq!(
+            package(&format!("github:owner/tool/{}", rev)).pinned_rev(),
+            Some(rev)
+        );
+        assert_eq!(package("github:owner/tool/main").pinned_rev(), None);
+        assert_eq!(package("github:owner/tool").pinned_rev(), None);
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc3339(1_767_225_599), "2025-12-31T23:59:59Z");
+        assert_eq!(rfc3339(1_792_327_322), "2026-10-18T12:42:02Z");
+    }
+
+    #[test]
+    fn test_package_output_defaults_to_legacy_packages() {
+        let json = r#"{"name":"hello","resolved_version":"2.12.1","attribute_path":"hello","commit_hash":"abc"}"#;
+        let mut pkg: ResolvedNixpkgPackage = serde_json::from_str(json).unwrap();
+        assert_eq!(pkg.package_output, None);
+        assert_eq!(pkg.flake_output(), "legacyPackages");
+        assert!(!serde_json::to_string(&pkg)
+            .unwrap()
+            .contains("package_output"));
+
+        pkg.package_output = Some("packages".to_string());
+        let written = serde_json::to_string(&pkg).unwrap();
+        let pkg: ResolvedNixpkgPackage = serde_json::from_str(&written).unwrap();
+        assert_eq!(pkg.flake_output(), "packages");
+    }
+
+    #[test]
+    fn test_install_record_is_optional() {
+        let json = r#"{"name":"hello","resolved_version":"2.12.1","attribute_path":"hello","commit_hash":"abc"}"#;
+        let pkg: ResolvedNixpkgPackage = serde_json::from_str(json).unwrap();
+        assert_eq!(pkg.record, InstallRecord::default());
+        let written = serde_json::to_string(&pkg).unwrap();
+        assert!(!written.contains("installed"), "{}", written);
+        assert!(!written.contains("updated_at"), "{}", written);
+
+        let mut record = InstallRecord::now();
+        assert_eq!(
+            record.installed_with.as_deref(),
+            Some(env!("CARGO_PKG_VERSION"))
+        );
+        record.mark_updated();
+        assert!(record.updated_at.is_some());
+    }
+
     #[test]
     fn test_default_state() {
         let state = PackageState::default();
@@ -321,6 +529,8 @@ mod tests {
             package_output: "packages".to_string(),
             source_name: None,
             platforms: None,
+            unvalidated: false,
+            record: Default::default(),
         };
         state.add_custom_package(pkg.clone());
 
@@ -338,6 +548,8 @@ mod tests {
             package_output: "packages".to_string(),
             source_name: None,
             platforms: None,
+            unvalidated: false,
+            record: Default::default(),
         };
         state.add_custom_package(pkg1);
 
@@ -348,6 +560,8 @@ mod tests {
             package_output: "packages".to_string(),
             source_name: None,
             platforms: None,
+            unvalidated: false,
+            record: Default::default(),
         };
         state.add_custom_package(pkg2);
 
@@ -376,6 +590,8 @@ mod tests {
             package_output: "packages".to_string(),
             source_name: None,
             platforms: None,
+            unvalidated: false,
+            record: Default::default(),
         };
         state.add_custom_package(pkg);
 
@@ -400,6 +616,8 @@ mod tests {
             package_output: "packages".to_string(),
             source_name: None,
             platforms: None,
+            unvalidated: false,
+            record: Default::default(),
         });
 
         let names = state.all_package_names();
@@ -420,6 +638,8 @@ mod tests {
             package_output: "packages".to_string(),
             source_name: None,
             platforms: None,
+            unvalidated: false,
+            record: Default::default(),
         });
 
         state.save(&path).unwrap();
@@ -454,6 +674,12 @@ mod tests {
             attribute_path: "nodejs_20".to_string(),
             commit_hash: "abc123".to_string(),
             platforms: None,
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
         };
         state.add_resolved_package(pkg.clone());
 
@@ -468,6 +694,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_resolved_package_pinned_round_trips() {
+        // Entries written before --pin existed have no `pinned` field
+        let json = r#"{"name":"nodejs","version_spec":"20.11.0","resolved_version":"20.11.0","attribute_path":"nodejs_20","commit_hash":"abc123"}"#;
+        let mut pkg: ResolvedNixpkgPackage = serde_json::from_str(json).unwrap();
+        assert!(!pkg.pinned);
+        let written = serde_json::to_string(&pkg).unwrap();
+        assert!(!written.contains("installed"), "{}", written);
+        assert!(!written.contains("updated_at"), "{}", written);
+
+        pkg.pinned = true;
+        let json = serde_json::to_string(&pkg).unwrap();
+        assert!(json.contains(r#""pinned":true"#), "{}", json);
+        let pkg: ResolvedNixpkgPackage = serde_json::from_str(&json).unwrap();
+        assert!(pkg.pinned);
+    }
+
     #[test]
     fn test_add_resolved_package_removes_legacy() {
         let mut state = PackageState::default();
@@ -481,6 +724,12 @@ mod tests {
             attribute_path: "nodejs_20".to_string(),
             commit_hash: "abc123".to_string(),
             platforms: None,
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
         };
         state.add_resolved_package(pkg);
 
@@ -501,6 +750,12 @@ mod tests {
             attribute_path: "nodejs_20".to_string(),
             commit_hash: "abc123".to_string(),
             platforms: None,
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
         };
         state.add_resolved_package(pkg);
 
@@ -520,6 +775,12 @@ mod tests {
             attribute_path: "resolved-pkg".to_string(),
             commit_hash: "abc123".to_string(),
             platforms: None,
+            pinned: false,
+            source_name: None,
+            output: None,
+            package_output: None,
+            outputs: None,
+            record: Default::default(),
         });
 
         assert!(state.is_legacy_package("legacy-pkg"));
@@ -561,6 +822,21 @@ mod tests {
         assert_eq!(result, vec!["aarch64-linux", "x86_64-linux"]);
     }
 
+    #[test]
+    fn test_normalize_platforms_aarch64_alias() {
+        let result = normalize_platforms(&["aarch64".to_string()]).unwrap();
+        assert_eq!(result, vec!["aarch64-darwin", "aarch64-linux"]);
+    }
+
+    #[test]
+    fn test_normalize_platforms_x86_64_alias() {
+        let result = normalize_platforms(&["x86_64".to_string()]).unwrap();
+        assert_eq!(result, vec!["x86_64-darwin", "x86_64-linux"]);
+
+        let result = normalize_platforms(&["AMD64".to_string()]).unwrap();
+        assert_eq!(result, vec!["x86_64-darwin", "x86_64-linux"]);
+    }
+
     #[test]
     fn test_normalize_platforms_full_name() {
         let result = normalize_platforms(&["x86_64-darwin".to_string()]).unwrap();
@@ -589,6 +865,10 @@ mod tests {
         assert!(result.is_err());
         let err_msg = result.unwrap_err();
         assert!(err_msg.contains("Invalid platform"));
+
+        // Only whole aliases count, not prefixes of them
+        assert!(normalize_platforms(&["aarch".to_string()]).is_err());
+        assert!(normalize_platforms(&["arm64".to_string()]).is_err());
     }
 
     #[test]
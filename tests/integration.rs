@@ -1,5 +1,8 @@
+mod support;
+
 use std::process::Command;
 
+use support::{FakeNix, LegacyTree, NixhubServer, NixyJson};
 use tempfile::TempDir;
 
 fn nixy_cmd() -> Command {
@@ -15,6 +18,11 @@ struct TestEnv {
     config_dir: std::path::PathBuf,
     state_dir: std::path::PathBuf,
     env_path: std::path::PathBuf,
+    /// Where `link_fonts` places fonts, instead of the real user font dir
+    font_dir: std::path::PathBuf,
+    /// Set for hermetic environments; shadows any real nix on PATH
+    fake_nix: Option<FakeNix>,
+    nixhub: Option<NixhubServer>,
 }
 
 impl TestEnv {
@@ -24,18 +32,65 @@ impl TestEnv {
             config_dir: temp.path().join("config"),
             state_dir: temp.path().join("state"),
             env_path: temp.path().join("state/env"),
+            font_dir: temp.path().join("fonts/nixy"),
             _temp: temp,
+            fake_nix: None,
+            nixhub: None,
         }
     }
 
+    /// Environment that never touches a real nix or the network: commands
+    /// run against the fake nix and recorded Nixhub responses
+    fn hermetic() -> Self {
+        let mut env = Self::new();
+        env.fake_nix = Some(FakeNix::install(env._temp.path()));
+        env.nixhub = Some(NixhubServer::start());
+        env
+    }
+
+    fn nix(&self) -> &FakeNix {
+        self.fake_nix
+            .as_ref()
+            .expect("only hermetic environments have a fake nix")
+    }
+
+    fn nixhub(&self) -> &NixhubServer {
+        self.nixhub
+            .as_ref()
+            .expect("only hermetic environments have a Nixhub server")
+    }
+
     /// Create a nixy command with test environment variables set
     fn cmd(&self) -> Command {
         let mut cmd = nixy_cmd();
         cmd.env("NIXY_CONFIG_DIR", &self.config_dir);
         cmd.env("NIXY_STATE_DIR", &self.state_dir);
         cmd.env("NIXY_ENV", &self.env_path);
+        cmd.env("NIXY_FONT_DIR", &self.font_dir);
+        if let Some(nix) = &self.fake_nix {
+            cmd.env("PATH", nix.path_env());
+            cmd.env("NIX_STORE_DIR", nix.store_dir());
+        }
+        if let Some(nixhub) = &self.nixhub {
+            cmd.env("NIXY_NIXHUB_URL", nixhub.url());
+            cmd.env("NIXY_GITHUB_URL", nixhub.url());
+        } else {
+            // Nothing listens here: commit checks report "could not check"
+            // instead of reaching out to GitHub
+            cmd.env("NIXY_GITHUB_URL", "http://127.0.0.1:9");
+        }
         cmd
     }
+
+    fn profile_flake(&self, profile: &str) -> String {
+        std::fs::read_to_string(
+            self.state_dir
+                .join("profiles")
+                .join(profile)
+                .join("flake.nix"),
+        )
+        .unwrap()
+    }
 }
 
 // =============================================================================
@@ -87,6 +142,41 @@ fn test_config_bash() {
         "bash config should include completion: {}",
         stdout
     );
+    assert!(stdout.contains("env/share/bash-completion/completions"));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_config_bash_loads_completions_of_installed_packages() {
+    let home = TempDir::new().unwrap();
+    let completions = home
+        .path()
+        .join(".local/state/nixy/env/share/bash-completion/completions");
+    std::fs::create_dir_all(&completions).unwrap();
+    std::fs::write(
+        completions.join("mytool"),
+        "complete -W 'alpha beta' mytool\n",
+    )
+    .unwrap();
+
+    let config = nixy_cmd().args(["config", "bash"]).output().unwrap();
+    assert!(config.status.success());
+    let script = format!(
+        "{}\ncomplete -p mytool",
+        String::from_utf8_lossy(&config.stdout)
+    );
+    let output = Command::new("bash")
+        .args(["--norc", "-c", &script])
+        .env("HOME", home.path())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(
+        stdout.contains("complete -W 'alpha beta' mytool"),
+        "stdout={}",
+        stdout
+    );
 }
 
 #[test]
@@ -101,6 +191,7 @@ fn test_config_zsh() {
         "zsh config should include completion: {}",
         stdout
     );
+    assert!(stdout.contains("env/share/zsh/site-functions"));
 }
 
 #[test]
@@ -110,6 +201,9 @@ fn test_config_fish() {
     let stdout = String::from_utf8_lossy(&output.stdout);
     assert!(stdout.contains("set -gx PATH"));
     assert!(stdout.contains(".local/state/nixy/env/bin"));
+    assert!(stdout.contains(
+        "set -g fish_complete_path $HOME/.local/state/nixy/env/share/fish/vendor_completions.d"
+    ));
 }
 
 #[test]
@@ -126,6 +220,414 @@ fn test_config_no_shell() {
     assert!(!output.status.success());
 }
 
+#[test]
+fn test_config_print_config_path_and_state_dir() {
+    let env = TestEnv::new();
+
+    let output = env
+        .cmd()
+        .args(["config", "--print-config-path"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let expected = format!("{}\n", env.config_dir.join("nixy.json").display());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+
+    let output = env
+        .cmd()
+        .args(["config", "--print-state-dir"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let expected = format!("{}\n", env.state_dir.display());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+
+    // Lookups must not create config state as a side effect
+    assert!(!env.config_dir.exists());
+}
+
+#[test]
+fn test_config_print_env_dir_fails_when_never_built() {
+    let env = TestEnv::new();
+    for flag in ["--print-env-dir", "--print-bin-dir"] {
+        let output = env.cmd().args(["config", flag]).output().unwrap();
+        assert!(!output.status.success(), "{} should fail", flag);
+        assert!(output.stdout.is_empty(), "{} must not print a path", flag);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("nixy sync"), "stderr={}", stderr);
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn test_config_print_env_and_bin_dir_after_build() {
+    let env = TestEnv::new();
+    let build = env.state_dir.join("fake-build");
+    std::fs::create_dir_all(build.join("bin")).unwrap();
+    std::os::unix::fs::symlink(&build, &env.env_path).unwrap();
+
+    let output = env
+        .cmd()
+        .args(["config", "--print-env-dir"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let expected = format!("{}\n", env.env_path.display());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+
+    let output = env
+        .cmd()
+        .args(["config", "--print-bin-dir"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let expected = format!("{}\n", env.env_path.join("bin").display());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_config_print_env_dir_defaults_under_state_dir() {
+    // Without NIXY_ENV the env link lives under NIXY_STATE_DIR
+    let env = TestEnv::new();
+    let build = env.state_dir.join("fake-build");
+    std::fs::create_dir_all(&build).unwrap();
+    let default_link = env.state_dir.join("env");
+    std::os::unix::fs::symlink(&build, &default_link).unwrap();
+
+    let output = nixy_cmd()
+        .env("NIXY_CONFIG_DIR", &env.config_dir)
+        .env("NIXY_STATE_DIR", &env.state_dir)
+        .env_remove("NIXY_ENV")
+        .args(["config", "--print-env-dir"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let expected = format!("{}\n", default_link.display());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+}
+
+#[test]
+fn test_config_print_flags_conflict_with_shell() {
+    let output = nixy_cmd()
+        .args(["config", "zsh", "--print-state-dir"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+// =============================================================================
+// Doctor command tests
+// =============================================================================
+
+fn doctor_json(env: &TestEnv) -> (bool, Vec<serde_json::Value>) {
+    let output = env.cmd().args(["doctor", "--json"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let results: Vec<serde_json::Value> = serde_json::from_str(&stdout)
+        .unwrap_or_else(|e| panic!("doctor --json is not a JSON array ({}): {}", e, stdout));
+    (output.status.success(), results)
+}
+
+fn doctor_status<'a>(results: &'a [serde_json::Value], check: &str) -> &'a str {
+    results
+        .iter()
+        .find(|r| r["check"] == check)
+        .and_then(|r| r["status"].as_str())
+        .unwrap_or_else(|| panic!("missing doctor check '{}'", check))
+}
+
+#[test]
+fn test_doctor_json_emits_check_objects() {
+    let env = TestEnv::new();
+    let (_, results) = doctor_json(&env);
+
+    assert!(!results.is_empty());
+    for result in &results {
+        assert!(result["check"].is_string(), "bad entry: {}", result);
+        assert!(result["detail"].is_string(), "bad entry: {}", result);
+        let status = result["status"].as_str().unwrap();
+        assert!(
+            ["ok", "warn", "fail"].contains(&status),
+            "bad status: {}",
+            status
+        );
+    }
+    // Nothing has been set up yet: warnings, not failures
+    assert_eq!(doctor_status(&results, "config"), "warn");
+    assert_eq!(doctor_status(&results, "env"), "warn");
+}
+
+#[test]
+fn test_doctor_does_not_create_config() {
+    let env = TestEnv::new();
+    let _ = env.cmd().arg("doctor").output().unwrap();
+    assert!(!env.config_dir.join("nixy.json").exists());
+}
+
+#[test]
+fn test_doctor_json_fails_on_invalid_config() {
+    let env = TestEnv::new();
+    write_nixy_json(&env, "{ not json");
+
+    let (success, results) = doctor_json(&env);
+    assert!(!success, "failures must produce a non-zero exit code");
+    assert_eq!(doctor_status(&results, "config"), "fail");
+}
+
+#[test]
+#[cfg(unix)]
+fn test_doctor_json_fails_on_dangling_env_link() {
+    let env = TestEnv::new();
+    std::fs::create_dir_all(&env.state_dir).unwrap();
+    std::os::unix::fs::symlink(env.state_dir.join("gone"), &env.env_path).unwrap();
+
+    let (success, results) = doctor_json(&env);
+    assert!(!success);
+    assert_eq!(doctor_status(&results, "env"), "fail");
+    let detail = results
+        .iter()
+        .find(|r| r["check"] == "env")
+        .and_then(|r| r["detail"].as_str())
+        .unwrap();
+    assert!(detail.contains("stale"), "{}", detail);
+    assert!(detail.contains("nixy sync"), "{}", detail);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_config_print_env_dir_reports_garbage_collected_build() {
+    let env = TestEnv::new();
+    std::fs::create_dir_all(&env.state_dir).unwrap();
+    let gone = env.state_dir.join("gone");
+    std::os::unix::fs::symlink(&gone, &env.env_path).unwrap();
+
+    let output = env
+        .cmd()
+        .args(["config", "--print-env-dir"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("stale"), "stderr={}", stderr);
+    assert!(
+        stderr.contains(&gone.display().to_string()),
+        "stderr={}",
+        stderr
+    );
+    assert!(stderr.contains("nixy sync"), "stderr={}", stderr);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_list_warns_on_garbage_collected_build() {
+    let env = TestEnv::new();
+    NixyJson::new()
+        .package("default", "hello")
+        .write(&env.config_dir);
+    std::fs::create_dir_all(&env.state_dir).unwrap();
+    std::os::unix::fs::symlink(env.state_dir.join("gone"), &env.env_path).unwrap();
+
+    let output = env.cmd().args(["list", "--no-pager"]).output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr={}", stderr);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("hello"));
+    assert!(stderr.contains("Environment is stale"), "stderr={}", stderr);
+}
+
+#[test]
+fn test_hermetic_sync_rebuilds_a_garbage_collected_env() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved(
+            "default",
+            "hello",
+            "2.12.1",
+            "a3a3dda3bacf61e8a39258a0ed9c924eeca8e293",
+        )
+        .write(&env.config_dir);
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    // nix-collect-garbage run outside nixy
+    let build = std::fs::read_link(&env.env_path).unwrap();
+    std::fs::remove_dir_all(&build).unwrap();
+
+    let output = env.cmd().arg("sync").output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr={}", stderr);
+    assert!(
+        stderr.contains("is no longer in the Nix store") && stderr.contains("Rebuilding it"),
+        "stderr={}",
+        stderr
+    );
+    assert_eq!(env.nix().calls_to("build").len(), 2);
+    assert!(env.env_path.exists(), "env must point at the new build");
+
+    let (_, results) = doctor_json(&env);
+    assert_eq!(doctor_status(&results, "env"), "ok");
+}
+
+#[test]
+fn test_hermetic_sync_all_builds_every_profile_and_reports_failures() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", OLD_HELLO_COMMIT)
+        .profile("work")
+        .package("work", "ripgrep")
+        .profile("empty")
+        .write(&env.config_dir);
+
+    let output = env.cmd().args(["sync", "--all"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(env.nix().calls_to("build").len(), 3);
+    for name in ["default", "work", "empty"] {
+        assert!(
+            env.state_dir.join("builds").join(name).exists(),
+            "{} was not built",
+            name
+        );
+    }
+    // Only the active profile becomes the environment
+    assert_eq!(
+        std::fs::read_link(&env.env_path).unwrap(),
+        std::fs::read_link(env.state_dir.join("builds/default")).unwrap()
+    );
+    assert!(
+        stdout.contains("default  built (active)"),
+        "stdout={}",
+        stdout
+    );
+    assert!(stdout.contains("work     built"), "stdout={}", stdout);
+
+    // Frozen, a profile without a lock can't build; the others still do
+    std::fs::remove_file(env.state_dir.join("profiles/work/flake.lock")).unwrap();
+    let output = env
+        .cmd()
+        .args(["sync", "--all", "--frozen"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert_eq!(env.nix().calls_to("build").len(), 6);
+    assert!(stdout.contains("empty    built"), "stdout={}", stdout);
+    assert!(stdout.contains("work     failed: "), "stdout={}", stdout);
+    assert!(
+        stderr.contains("Sync failed for profile(s): work"),
+        "stderr={}",
+        stderr
+    );
+}
+
+#[test]
+fn test_doctor_fix_migrates_leftovers_and_regenerates_flakes() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "hello")
+        .write(&env.config_dir);
+    // A profile an old nixy created after the migration
+    LegacyTree::new()
+        .profile("old", &["ripgrep"])
+        .write(&env.config_dir);
+
+    let (_, results) = doctor_json(&env);
+    assert_eq!(doctor_status(&results, "legacy"), "warn");
+    assert_eq!(doctor_status(&results, "features"), "ok");
+
+    let output = env.cmd().args(["doctor", "--fix"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Migrated legacy profile(s) into nixy.json: old"),
+        "stdout={}",
+        stdout
+    );
+    assert!(
+        stdout.contains("Regenerated flake.nix from nixy.json for profile(s): default ("),
+        "stdout={}",
+        stdout
+    );
+    assert!(env.profile_flake("old").contains("ripgrep"));
+    assert!(env.profile_flake("default").contains("hello"));
+    let config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    assert!(config.contains("\"old\""), "{}", config);
+    // Nothing was built: the profiles are marked for the next sync
+    assert!(env.nix().calls_to("build").is_empty());
+    assert!(env.state_dir.join("profiles/default/needs-sync").exists());
+
+    let (_, results) = doctor_json(&env);
+    assert_eq!(doctor_status(&results, "legacy"), "ok");
+
+    // A second run has nothing left to do
+    let output = env.cmd().args(["doctor", "--fix"]).output().unwrap();
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Nothing to repair"));
+}
+
+#[test]
+fn test_hermetic_doctor_warns_about_profiles_without_flake() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "hello")
+        .profile("work")
+        .write(&env.config_dir);
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+
+    let (_, results) = doctor_json(&env);
+    assert_eq!(doctor_status(&results, "flake"), "warn");
+    let detail = results
+        .iter()
+        .find(|r| r["check"] == "flake")
+        .and_then(|r| r["detail"].as_str())
+        .unwrap();
+    assert!(
+        detail.contains("no flake.nix for profile(s): work; run 'nixy doctor --fix'"),
+        "detail={}",
+        detail
+    );
+
+    assert!(env
+        .cmd()
+        .args(["doctor", "--fix"])
+        .output()
+        .unwrap()
+        .status
+        .success());
+    let (_, results) = doctor_json(&env);
+    assert_eq!(doctor_status(&results, "flake"), "ok");
+}
+
+#[test]
+fn test_hermetic_doctor_warns_when_flakes_are_disabled() {
+    let env = TestEnv::hermetic();
+    env.nix().features("nix-command");
+
+    let (_, results) = doctor_json(&env);
+    assert_eq!(doctor_status(&results, "features"), "warn");
+    let detail = results
+        .iter()
+        .find(|r| r["check"] == "features")
+        .and_then(|r| r["detail"].as_str())
+        .unwrap();
+    assert!(detail.starts_with("flakes not enabled"), "{}", detail);
+}
+
+#[test]
+fn test_doctor_json_warns_on_unexpected_state_files() {
+    let env = TestEnv::new();
+    let profile_dir = env.state_dir.join("profiles/default");
+    std::fs::create_dir_all(&profile_dir).unwrap();
+    std::fs::write(profile_dir.join("flake.nix"), "{}").unwrap();
+    std::fs::write(profile_dir.join("flake.nix.orig"), "{}").unwrap();
+
+    let (_, results) = doctor_json(&env);
+    assert_eq!(doctor_status(&results, "state"), "warn");
+    let detail = results
+        .iter()
+        .find(|r| r["check"] == "state")
+        .and_then(|r| r["detail"].as_str())
+        .unwrap();
+    assert!(detail.contains("default: flake.nix.orig"), "{}", detail);
+}
+
 // =============================================================================
 // Completions helper tests (used by shell completion scripts)
 // =============================================================================
@@ -234,6 +736,74 @@ fn test_completions_installed_empty_config_succeeds() {
     assert!(output.status.success());
 }
 
+#[test]
+fn test_completions_prints_a_script_for_each_shell() {
+    let env = TestEnv::new();
+    for (shell, hook) in [
+        ("bash", "complete -F _nixy_dynamic"),
+        ("zsh", "compdef _nixy_dynamic nixy"),
+        (
+            "fish",
+            "__fish_seen_subcommand_from profile diff\" -f -a \"(command nixy completions profiles",
+        ),
+    ] {
+        let output = env.cmd().args(["completions", shell]).output().unwrap();
+        assert!(output.status.success(), "{}", shell);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        // Generated from the CLI definition, so every subcommand is there
+        assert!(stdout.contains("doctor"), "{}: {}", shell, stdout);
+        assert!(stdout.contains("undo-update"), "{}: {}", shell, stdout);
+        assert!(stdout.contains(hook), "{}: {}", shell, stdout);
+    }
+    // Installed packages for uninstall, profile names for profile
+    for shell in ["bash", "zsh"] {
+        let output = env.cmd().args(["completions", shell]).output().unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            stdout.contains("command nixy completions $kind"),
+            "{}: {}",
+            shell,
+            stdout
+        );
+        assert!(
+            stdout.contains("        profile|diff) kind=profiles ;;"),
+            "{}: {}",
+            shell,
+            stdout
+        );
+        assert!(
+            stdout.contains("        uninstall|remove|file|info|why|platform) kind=installed ;;"),
+            "{}: {}",
+            shell,
+            stdout
+        );
+    }
+    let output = env.cmd().args(["completions", "fish"]).output().unwrap();
+    assert!(String::from_utf8_lossy(&output.stdout).contains(
+        "__fish_seen_subcommand_from uninstall remove file info why platform\" -f -a \"(command nixy completions installed"
+    ));
+
+    let output = env.cmd().args(["completions", "--help"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("bash, zsh or fish"), "{}", stdout);
+    assert!(
+        stdout.contains("fish: nixy completions fish | source"),
+        "{}",
+        stdout
+    );
+
+    let bash = env.cmd().args(["completions", "bash"]).output().unwrap();
+    let script = env.config_dir.join("nixy.bash");
+    std::fs::create_dir_all(&env.config_dir).unwrap();
+    std::fs::write(&script, &bash.stdout).unwrap();
+    let check = Command::new("bash")
+        .arg("-n")
+        .arg(&script)
+        .status()
+        .unwrap();
+    assert!(check.success(), "the bash script should parse");
+}
+
 // =============================================================================
 // List command tests
 // =============================================================================
@@ -249,6 +819,67 @@ fn test_list_no_flake() {
     assert!(stdout.contains("(none)") || stdout.contains("Installed packages"));
 }
 
+#[test]
+fn test_list_verify_passes_for_declared_packages() {
+    let env = TestEnv::new();
+    write_nixy_json(
+        &env,
+        r#"{
+  "version": 3,
+  "active_profile": "default",
+  "profiles": {
+    "default": { "packages": ["ripgrep"], "resolved_packages": [], "custom_packages": [] }
+  }
+}"#,
+    );
+
+    let output = env.cmd().args(["list", "--verify"]).output().unwrap();
+    assert!(
+        output.status.success(),
+        "list --verify should succeed: stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("All 1 declared package(s)"), "{}", stdout);
+}
+
+#[test]
+fn test_list_verify_flags_package_shadowed_by_local_package() {
+    let env = TestEnv::new();
+    write_nixy_json(
+        &env,
+        r#"{
+  "version": 3,
+  "active_profile": "default",
+  "profiles": {
+    "default": { "packages": ["ripgrep", "foo"], "resolved_packages": [], "custom_packages": [] }
+  }
+}"#,
+    );
+    let packages_dir = env.config_dir.join("packages");
+    std::fs::create_dir_all(&packages_dir).unwrap();
+    std::fs::write(
+        packages_dir.join("foo.nix"),
+        "{ stdenv }:\nstdenv.mkDerivation {\n  pname = \"foo\";\n  version = \"1.0\";\n  src = ./.;\n}\n",
+    )
+    .unwrap();
+
+    let output = env.cmd().args(["list", "--verify"]).output().unwrap();
+    assert!(!output.status.success(), "list --verify should fail");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("foo: shadowed by a local package"),
+        "Should flag foo: {}",
+        stderr
+    );
+    assert!(!stderr.contains("ripgrep:"), "ripgrep is fine: {}", stderr);
+    assert!(
+        stderr.contains("1 declared package(s) missing"),
+        "Should report the count: {}",
+        stderr
+    );
+}
+
 // =============================================================================
 // Sync command tests
 // =============================================================================
@@ -284,6 +915,201 @@ fn test_sync_no_flake() {
     );
 }
 
+#[test]
+#[cfg(unix)]
+fn test_sync_leaves_only_generated_files_in_state_dir() {
+    let env = TestEnv::new();
+    write_nixy_json(
+        &env,
+        r#"{
+  "version": 3,
+  "active_profile": "default",
+  "profiles": {
+    "default": { "packages": [], "resolved_packages": [], "custom_packages": [] }
+  }
+}"#,
+    );
+    // Leftover from running `nix build` by hand in the profile directory
+    let profile_dir = env.state_dir.join("profiles/default");
+    std::fs::create_dir_all(&profile_dir).unwrap();
+    std::os::unix::fs::symlink("/nix/store/gone", profile_dir.join("result")).unwrap();
+
+    let output = env.cmd().arg("sync").output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "Sync should succeed: stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        stdout.contains("Removed stray build link(s)"),
+        "Should announce the cleanup: {}",
+        stdout
+    );
+
+    let mut entries: Vec<String> = std::fs::read_dir(&profile_dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    entries.sort();
+    assert!(
+        entries
+            .iter()
+            .all(|e| e == "flake.nix" || e == "flake.lock"),
+        "Unexpected files in state dir: {:?}",
+        entries
+    );
+    assert!(entries.contains(&"flake.nix".to_string()));
+    assert!(
+        env.env_path.is_symlink(),
+        "The env link is created outside the flake directory"
+    );
+}
+
+/// nixy.json with one custom package from a local path that exists and one
+/// from a path that doesn't
+fn write_profile_with_local_paths(env: &TestEnv, strict: bool) -> std::path::PathBuf {
+    let present = env.config_dir.join("present-flake");
+    std::fs::create_dir_all(&present).unwrap();
+    std::fs::write(present.join("flake.nix"), "{ outputs = _: { }; }").unwrap();
+    let gone = env.config_dir.join("gone-flake");
+    write_nixy_json(
+        env,
+        &format!(
+            r#"{{
+  "version": 3,
+  "active_profile": "default",
+  "strict_local": {},
+  "profiles": {{
+    "default": {{
+      "packages": [],
+      "resolved_packages": [],
+      "custom_packages": [
+        {{ "name": "present", "input_name": "present", "input_url": "path:{}", "package_output": "packages", "source_name": null }},
+        {{ "name": "gone", "input_name": "gone", "input_url": "path:{}", "package_output": "packages", "source_name": null }}
+      ]
+    }}
+  }}
+}}"#,
+            strict,
+            present.display(),
+            gone.display()
+        ),
+    );
+    gone
+}
+
+#[test]
+fn test_sync_skips_missing_local_packages() {
+    let env = TestEnv::new();
+    let gone = write_profile_with_local_paths(&env, false);
+
+    let output = env.cmd().arg("sync").output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr={}", stderr);
+    assert!(
+        stderr.contains(&format!(
+            "Local package 'gone' not found: {} does not exist",
+            gone.display()
+        )),
+        "Should name the missing package and path: {}",
+        stderr
+    );
+
+    let flake = std::fs::read_to_string(env.state_dir.join("profiles/default/flake.nix")).unwrap();
+    assert!(flake.contains("present = inputs.present"), "{}", flake);
+    assert!(!flake.contains("gone"), "{}", flake);
+}
+
+#[test]
+fn test_sync_strict_local_fails_on_missing_local_packages() {
+    let env = TestEnv::new();
+    write_profile_with_local_paths(&env, true);
+
+    let output = env.cmd().arg("sync").output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("1 local package(s) not found"),
+        "stderr={}",
+        stderr
+    );
+    assert!(!env.env_path.exists(), "Nothing should be built");
+}
+
+#[test]
+fn test_doctor_json_lists_missing_local_packages() {
+    let env = TestEnv::new();
+    write_profile_with_local_paths(&env, false);
+
+    let (_, results) = doctor_json(&env);
+    assert_eq!(doctor_status(&results, "local"), "warn");
+    let detail = results
+        .iter()
+        .find(|r| r["check"] == "local")
+        .and_then(|r| r["detail"].as_str())
+        .unwrap();
+    assert!(detail.contains("default: gone"), "{}", detail);
+    assert!(!detail.contains("present"), "{}", detail);
+}
+
+#[test]
+fn test_sync_explains_uncreatable_state_dir() {
+    let env = TestEnv::new();
+    write_nixy_json(
+        &env,
+        r#"{
+  "version": 3,
+  "active_profile": "default",
+  "profiles": {
+    "default": { "packages": [], "resolved_packages": [], "custom_packages": [] }
+  }
+}"#,
+    );
+    // The state directory's parent is a regular file
+    let blocker = env.config_dir.join("not-a-dir");
+    std::fs::write(&blocker, "").unwrap();
+
+    let output = env
+        .cmd()
+        .env("NIXY_STATE_DIR", blocker.join("state"))
+        .arg("sync")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Cannot create state directory"),
+        "stderr={}",
+        stderr
+    );
+    assert!(
+        stderr.contains(&format!("{} is a file, not a directory", blocker.display())),
+        "stderr={}",
+        stderr
+    );
+}
+
+#[test]
+fn test_sync_rejects_env_link_inside_flake_dir() {
+    let env = TestEnv::new();
+    let output = env
+        .cmd()
+        .env("NIXY_ENV", env.state_dir.join("profiles/default/env"))
+        .arg("sync")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("must be outside the flake directory"),
+        "stderr={}",
+        stderr
+    );
+}
+
 // =============================================================================
 // Profile command tests
 // =============================================================================
@@ -481,33 +1307,31 @@ fn test_install_already_installed() {
 
 #[test]
 fn test_update_no_flake() {
-    // update --all now auto-regenerates flake.nix from packages.json
-    let env = TestEnv::new();
+    // update --all regenerates a missing flake.nix before updating it
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "hello")
+        .write(&env.config_dir);
+
     let output = env.cmd().args(["update", "--all"]).output().unwrap();
     let stdout = String::from_utf8_lossy(&output.stdout);
     let stderr = String::from_utf8_lossy(&output.stderr);
 
-    // Should mention regenerating flake.nix
     assert!(
-        stdout.contains("Regenerating flake.nix") || stdout.contains("Updating"),
-        "Expected regeneration or updating message: stdout={}, stderr={}",
+        output.status.success(),
+        "Update should succeed: stdout={}, stderr={}",
         stdout,
         stderr
     );
-
-    // Should NOT fail with "No flake.nix found"
-    assert!(
-        !stderr.contains("No flake.nix found"),
-        "Should not fail with NoFlakeFound error"
-    );
-
-    // Command should succeed (nix flake update works on empty flake)
     assert!(
-        output.status.success(),
-        "Update should succeed with empty flake: stdout={}, stderr={}",
-        stdout,
-        stderr
+        stdout.contains("Regenerating flake.nix"),
+        "stdout={}",
+        stdout
     );
+    assert!(env.profile_flake("default").contains("hello = pkgs.hello;"));
+    assert_eq!(env.nix().calls_to("update").len(), 1);
+    assert!(env.state_dir.join("profiles/default/flake.lock").exists());
+    assert!(env.env_path.is_symlink(), "environment should be rebuilt");
 }
 
 #[test]
@@ -527,6 +1351,125 @@ fn test_update_requires_target_or_all() {
     );
 }
 
+#[test]
+fn test_update_profile_nonexistent_fails() {
+    let env = TestEnv::new();
+    write_nixy_json(
+        &env,
+        r#"{"version": 3, "active_profile": "default", "profiles": {"default": {}}}"#,
+    );
+
+    let output = env
+        .cmd()
+        .args(["update", "--all", "--profile", "nope"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("'nope' does not exist"),
+        "stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_update_inactive_profile_is_not_built() {
+    let env = TestEnv::new();
+    write_nixy_json(
+        &env,
+        r#"{"version": 3, "active_profile": "default", "profiles": {"default": {}, "work": {}}}"#,
+    );
+
+    let output = env
+        .cmd()
+        .args(["update", "--all", "--profile", "work"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {}", stderr);
+
+    let work_dir = env.state_dir.join("profiles/work");
+    assert!(work_dir.join("flake.nix").exists());
+    assert!(work_dir.join("needs-sync").exists());
+    // The active environment is left alone
+    assert!(!env.env_path.exists() && !env.env_path.is_symlink());
+    assert!(!env.state_dir.join("profiles/default/flake.nix").exists());
+}
+
+#[test]
+fn test_update_all_profiles_continues_after_failure() {
+    let env = TestEnv::new();
+    write_nixy_json(
+        &env,
+        r#"{
+  "version": 3,
+  "active_profile": "default",
+  "profiles": {
+    "default": {},
+    "a": {
+      "custom_packages": [
+        {
+          "name": "tool",
+          "input_name": "github-owner-tool",
+          "input_url": "github:owner/tool",
+          "package_output": "packages",
+          "source_name": null
+        }
+      ]
+    },
+    "b": {"packages": ["tool"]}
+  }
+}"#,
+    );
+    // Profile "a" has a corrupted lock, so updating it fails
+    let a_dir = env.state_dir.join("profiles/a");
+    std::fs::create_dir_all(&a_dir).unwrap();
+    std::fs::write(a_dir.join("flake.lock"), "not valid json").unwrap();
+    let b_dir = env.state_dir.join("profiles/b");
+    std::fs::create_dir_all(&b_dir).unwrap();
+    std::fs::write(
+        b_dir.join("flake.lock"),
+        r#"{"nodes": {"root": {"inputs": {"nixpkgs": "nixpkgs"}}}, "root": "root", "version": 7}"#,
+    )
+    .unwrap();
+
+    let output = env
+        .cmd()
+        .args(["update", "tool", "--all-profiles"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(
+        !output.status.success(),
+        "a failed profile must fail the run"
+    );
+    assert!(stderr.contains("profile(s): a"), "stderr: {}", stderr);
+    // "b" is still processed after "a" fails; "default" has no "tool"
+    assert!(
+        stdout.contains("Updating profile 'b'"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        !stdout.contains("Updating profile 'default'"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(stdout.contains("Summary:"), "stdout: {}", stdout);
+}
+
+#[test]
+fn test_update_profile_flags_conflict() {
+    let output = nixy_cmd()
+        .args(["update", "--all", "--profile", "work", "--all-profiles"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
 // =============================================================================
 // Uninstall command tests
 // =============================================================================
@@ -538,6 +1481,34 @@ fn test_uninstall_requires_package() {
     assert!(!output.status.success());
 }
 
+#[test]
+fn test_uninstall_without_package_needs_terminal() {
+    let env = TestEnv::new();
+    write_nixy_json(
+        &env,
+        r#"{
+  "version": 3,
+  "active_profile": "default",
+  "profiles": {
+    "default": { "packages": ["ripgrep"], "resolved_packages": [], "custom_packages": [] }
+  }
+}"#,
+    );
+
+    let output = env.cmd().arg("uninstall").output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("requires a package name"),
+        "Should explain that a package is required: {}",
+        stderr
+    );
+
+    // Nothing was removed
+    let config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    assert!(config.contains("ripgrep"));
+}
+
 #[test]
 fn test_uninstall_no_flake() {
     // uninstall now auto-regenerates flake.nix from packages.json
@@ -574,40 +1545,273 @@ fn test_search_requires_query() {
     assert!(!output.status.success());
 }
 
-// =============================================================================
-// Help content tests
-// =============================================================================
+const PINNED_REV: &str = "9f4128e00b0ae8ec65918efeba59db998750ead6";
 
-#[test]
-fn test_help_shows_install_command() {
-    let output = nixy_cmd().arg("--help").output().unwrap();
-    assert!(output.status.success());
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    assert!(stdout.contains("install"));
-}
+/// What `nix search --json` prints for "hello"
+const SEARCH_JSON: &str = r#"{"legacyPackages.x86_64-linux.hello":{"pname":"hello","version":"2.10","description":"A program that produces a familiar, friendly greeting"},"legacyPackages.x86_64-linux.hello-wayland":{"pname":"hello-wayland","version":"0-unstable-2024-03-04","description":"Hello world Wayland client"}}"#;
 
 #[test]
-fn test_help_shows_uninstall_command() {
-    let output = nixy_cmd().arg("--help").output().unwrap();
-    assert!(output.status.success());
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    assert!(stdout.contains("uninstall"));
-}
+fn test_hermetic_search_rev_searches_that_commit() {
+    let env = TestEnv::hermetic();
+    env.nix().on_search(SEARCH_JSON);
 
-#[test]
-fn test_help_shows_list_command() {
-    let output = nixy_cmd().arg("--help").output().unwrap();
-    assert!(output.status.success());
+    let output = env
+        .cmd()
+        .args(["search", "hello", "--rev", "a3a3dda3bacf"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
     let stdout = String::from_utf8_lossy(&output.stdout);
-    assert!(stdout.contains("list"));
+    // Names and versions line up in columns
+    assert!(
+        stdout.contains("  hello          2.10                   A program that produces"),
+        "stdout={}",
+        stdout
+    );
+    assert!(
+        stdout.contains("  hello-wayland  0-unstable-2024-03-04  Hello world"),
+        "stdout={}",
+        stdout
+    );
+    let searches = env.nix().calls_to("search");
+    assert_eq!(searches.len(), 1);
+    assert!(
+        searches[0].contains("github:NixOS/nixpkgs/a3a3dda3bacf hello"),
+        "{:?}",
+        searches
+    );
 }
 
 #[test]
-fn test_help_shows_search_command() {
-    let output = nixy_cmd().arg("--help").output().unwrap();
-    assert!(output.status.success());
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    assert!(stdout.contains("search"));
+fn test_hermetic_search_limit_and_json() {
+    let env = TestEnv::hermetic();
+    env.nix().on_search(SEARCH_JSON);
+
+    let output = env
+        .cmd()
+        .args(["search", "hello", "--rev", "a3a3dda3bacf", "--limit", "1"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Found 2 packages, showing the first 1"),
+        "stdout={}",
+        stdout
+    );
+    assert!(stdout.contains("hello"), "stdout={}", stdout);
+    assert!(!stdout.contains("hello-wayland"), "stdout={}", stdout);
+
+    let output = env
+        .cmd()
+        .args(["search", "hello", "--rev", "a3a3dda3bacf", "--json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        results,
+        serde_json::json!([
+            {
+                "name": "hello",
+                "version": "2.10",
+                "description": "A program that produces a familiar, friendly greeting"
+            },
+            {
+                "name": "hello-wayland",
+                "version": "0-unstable-2024-03-04",
+                "description": "Hello world Wayland client"
+            }
+        ])
+    );
+}
+
+#[test]
+fn test_hermetic_search_marks_installed_packages() {
+    let env = TestEnv::hermetic();
+    env.nix().on_search(SEARCH_JSON);
+    NixyJson::new()
+        .package("default", "ripgrep")
+        .resolved(
+            "default",
+            "hello",
+            "2.12.1",
+            "a3a3dda3bacf61e8a39258a0ed9c924eeca8e293",
+        )
+        .write(&env.config_dir);
+
+    let output = env.cmd().args(["search", "ripgrep"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = |name: &str| {
+        stdout
+            .lines()
+            .find(|line| line.starts_with(&format!("  {} ", name)))
+            .unwrap_or_else(|| panic!("no {} in stdout={}", name, stdout))
+            .to_string()
+    };
+    assert!(line("ripgrep").ends_with(" [installed]"), "{}", stdout);
+    assert!(!line("ripgrep-all").contains("[installed"), "{}", stdout);
+
+    let output = env
+        .cmd()
+        .args(["search", "hello", "--rev", "a3a3dda3bacf"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("  hello          2.10                   A program that produces a familiar, friendly greeting [installed 2.12.1]"),
+        "stdout={}",
+        stdout
+    );
+    assert!(
+        stdout.contains("  hello-wayland  0-unstable-2024-03-04  Hello world Wayland client\n"),
+        "stdout={}",
+        stdout
+    );
+
+    let output = env
+        .cmd()
+        .args([
+            "search",
+            "hello",
+            "--rev",
+            "a3a3dda3bacf",
+            "--installed-only",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Found 1 packages:"), "stdout={}", stdout);
+    assert!(stdout.contains("[installed 2.12.1]"), "stdout={}", stdout);
+    assert!(!stdout.contains("hello-wayland"), "stdout={}", stdout);
+
+    let output = env
+        .cmd()
+        .args([
+            "search",
+            "hello",
+            "--rev",
+            "a3a3dda3bacf",
+            "--installed-only",
+        ])
+        .env("NIXY_CONFIG_DIR", env.config_dir.join("missing"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("No installed packages found for 'hello'"),
+        "stdout={}",
+        stdout
+    );
+}
+
+#[test]
+fn test_hermetic_search_rev_rejects_non_commit() {
+    let env = TestEnv::hermetic();
+    let output = env
+        .cmd()
+        .args(["search", "hello", "--rev", "nixos-24.05"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("commit hash"), "stderr={}", stderr);
+    assert!(env.nix().calls_to("search").is_empty());
+}
+
+#[test]
+fn test_hermetic_search_rev_reports_unknown_commit() {
+    let env = TestEnv::hermetic();
+    env.nix().fail_searches(
+        "error: unable to download 'https://github.com/NixOS/nixpkgs/archive/deadbeef00.tar.gz': HTTP error 404\n",
+    );
+
+    let output = env
+        .cmd()
+        .args(["search", "hello", "--rev", "deadbeef00"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("nixpkgs revision 'deadbeef00' does not exist"),
+        "stderr={}",
+        stderr
+    );
+}
+
+#[test]
+fn test_hermetic_search_pinned_uses_profile_lock() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+    write_state_lock(&env, "default", "unstable.json");
+
+    let output = env
+        .cmd()
+        .args(["search", "hello", "--pinned"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let searches = env.nix().calls_to("search");
+    assert!(
+        searches[0].contains(&format!("github:NixOS/nixpkgs/{} hello", PINNED_REV)),
+        "{:?}",
+        searches
+    );
+}
+
+#[test]
+fn test_hermetic_search_pinned_needs_a_lock() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+
+    let output = env
+        .cmd()
+        .args(["search", "hello", "--pinned"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No flake.lock"));
+}
+
+// =============================================================================
+// Help content tests
+// =============================================================================
+
+#[test]
+fn test_help_shows_install_command() {
+    let output = nixy_cmd().arg("--help").output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("install"));
+}
+
+#[test]
+fn test_help_shows_uninstall_command() {
+    let output = nixy_cmd().arg("--help").output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("uninstall"));
+}
+
+#[test]
+fn test_help_shows_list_command() {
+    let output = nixy_cmd().arg("--help").output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("list"));
+}
+
+#[test]
+fn test_help_shows_search_command() {
+    let output = nixy_cmd().arg("--help").output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("search"));
 }
 
 #[test]
@@ -878,9 +2082,9 @@ fn test_sync_with_profile() {
             stdout
         );
     } else {
-        // If failed, should be a build-related failure
+        // If failed, the error should carry nix's own message
         assert!(
-            stderr.contains("build") || stderr.contains("flake"),
+            stderr.contains("Failed to build environment: "),
             "Sync failure should be build-related: stderr={}",
             stderr
         );
@@ -911,597 +2115,5304 @@ fn test_profile_list_shows_active() {
 }
 
 #[test]
-fn test_profile_delete_requires_tty() {
+fn test_profiles_lists_profiles() {
     let env = TestEnv::new();
+    NixyJson::new().profile("work").write(&env.config_dir);
 
-    // Create two profiles with new syntax
-    let _ = env.cmd().args(["profile", "work", "-c"]).output();
-    let _ = env.cmd().args(["profile", "default", "-c"]).output();
-
-    // Try to delete (will fail because tests run without a TTY)
-    let output = env.cmd().args(["profile", "work", "-d"]).output().unwrap();
+    let output = env.cmd().arg("profiles").output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout.contains("default"), "stdout={}", stdout);
+    assert!(stdout.contains("work"), "stdout={}", stdout);
+    assert_eq!(
+        stdout,
+        String::from_utf8_lossy(&env.cmd().arg("profile").output().unwrap().stdout)
+    );
 
+    // Switching, creating and deleting stay on the singular
+    let output = env.cmd().args(["profiles", "work"]).output().unwrap();
     assert!(!output.status.success());
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    // Should mention needing a terminal for confirmation
-    assert!(
-        stderr.contains("terminal") || stderr.contains("TTY") || stderr.contains("interactively"),
-        "Should mention terminal requirement: {}",
-        stderr
-    );
 }
 
 #[test]
-fn test_profile_delete_active_fails() {
-    let env = TestEnv::new();
+fn test_profile_set_description_shows_in_listing() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().profile("work2").write(&env.config_dir);
 
-    // Create a profile and stay on it (it becomes active)
-    let _ = env.cmd().args(["profile", "work", "-c"]).output();
+    let output = env
+        .cmd()
+        .args([
+            "profile",
+            "work2",
+            "--set-description",
+            "Client X toolchain",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    // Only the description changed: no switch, no build
+    assert!(env.nix().calls_to("build").is_empty());
+    let nixy_json = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    let config: serde_json::Value = serde_json::from_str(&nixy_json).unwrap();
+    assert_eq!(config["active_profile"], "default");
+    assert_eq!(
+        config["profiles"]["work2"]["description"],
+        "Client X toolchain"
+    );
+    assert!(config["profiles"]["default"].get("description").is_none());
 
-    // Try to delete the active profile (will fail for multiple reasons in test:
-    // 1. No TTY for confirmation
-    // 2. Cannot delete active profile)
-    let output = env.cmd().args(["profile", "work", "-d"]).output().unwrap();
+    let output = env.cmd().arg("profile").output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Available profiles"), "{}", stdout);
+    assert!(stdout.contains("* default (active)"), "{}", stdout);
+    let work2 = stdout.lines().find(|l| l.contains("work2")).unwrap();
+    assert!(work2.contains("Client X toolchain"), "{}", stdout);
 
-    assert!(!output.status.success());
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    let stderr_lower = stderr.to_lowercase();
-    // Should fail - either because it's the active profile or because no TTY
-    assert!(
-        stderr_lower.contains("active")
-            || stderr_lower.contains("cannot delete")
-            || stderr_lower.contains("can't delete")
-            || stderr_lower.contains("unable to delete")
-            || stderr_lower.contains("terminal")
-            || stderr_lower.contains("interactively"),
-        "Should prevent deleting active profile or require terminal: {}",
-        stderr
-    );
+    // An empty description clears it
+    let output = env
+        .cmd()
+        .args(["profile", "work2", "--set-description", ""])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let config: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap())
+            .unwrap();
+    assert!(config["profiles"]["work2"].get("description").is_none());
 }
 
 #[test]
-fn test_profile_delete_non_tty() {
-    let env = TestEnv::new();
-
-    // Create two profiles and end up on default so work is not active
-    let _ = env.cmd().args(["profile", "work", "-c"]).output();
-    let _ = env.cmd().args(["profile", "default", "-c"]).output();
+fn test_profile_rename_moves_state_and_active_profile() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .profile("work")
+        .package("work", "ripgrep")
+        .active("work")
+        .write(&env.config_dir);
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    let first = std::fs::read_link(env.state_dir.join("builds/work")).unwrap();
+    let output = env.cmd().args(["install", "hello"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let build = std::fs::read_link(env.state_dir.join("builds/work")).unwrap();
 
-    // Try to delete work - will fail because delete requires TTY for confirmation
-    let output = env.cmd().args(["profile", "work", "-d"]).output().unwrap();
+    let output = env
+        .cmd()
+        .args(["profile", "work", "--rename", "office"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(env.nix().calls_to("build").len(), 2);
+    // The renamed out-link is registered as a GC root again
+    assert_eq!(
+        env.nix().calls_to("nix-store"),
+        vec![format!(
+            "nix-store --add-root {} --realise {}",
+            env.state_dir.join("builds/office").display(),
+            build.display()
+        )]
+    );
 
-    // Should fail because tests run without a TTY
-    assert!(
-        !output.status.success(),
-        "Profile delete should fail without TTY"
+    let config: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap())
+            .unwrap();
+    assert_eq!(config["active_profile"], "office");
+    assert!(config["profiles"].get("work").is_none());
+    assert_eq!(
+        config["profiles"]["office"]["packages"],
+        serde_json::json!(["ripgrep"])
     );
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    assert!(
-        stderr.contains("terminal") || stderr.contains("interactively"),
-        "Should mention terminal requirement: {}",
-        stderr
+    assert!(!env.state_dir.join("profiles/work").exists());
+    assert!(env.profile_flake("office").contains("ripgrep"));
+    assert!(env.state_dir.join("profiles/office/flake.lock").exists());
+    assert!(!env.state_dir.join("builds/work").is_symlink());
+    assert_eq!(
+        std::fs::read_link(env.state_dir.join("builds/office")).unwrap(),
+        build
     );
+
+    let output = env.cmd().arg("list").output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("ripgrep"));
+
+    // The rollback history moves along with the profile
+    let output = env.cmd().arg("rollback").output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(std::fs::read_link(&env.env_path).unwrap(), first);
+    assert!(!env.profile_flake("office").contains("hello"));
 }
 
 #[test]
-fn test_profile_switch_with_existing() {
-    let env = TestEnv::new();
-
-    // Create a profile with new syntax
-    let _ = env.cmd().args(["profile", "work", "-c"]).output();
-
-    // Create another profile
-    let _ = env.cmd().args(["profile", "default", "-c"]).output();
-
-    // Switch back with -c (should just switch, not error since profile exists)
-    let output = env.cmd().args(["profile", "work", "-c"]).output().unwrap();
+fn test_hermetic_info_shows_package_details() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", OLD_HELLO_COMMIT)
+        .profile("work")
+        .resolved("work", "hello", "2.12.0", OLD_HELLO_COMMIT)
+        .custom("work", "tool", "owner-tool", "github:owner/tool")
+        .write(&env.config_dir);
+    let packages_dir = env.config_dir.join("packages");
+    std::fs::create_dir_all(&packages_dir).unwrap();
+    std::fs::write(
+        packages_dir.join("mine.nix"),
+        r#"{ stdenv }: stdenv.mkDerivation { pname = "mine"; version = "1.0"; src = ./.; }"#,
+    )
+    .unwrap();
 
+    let output = env.cmd().args(["info", "hello"]).output().unwrap();
     let stdout = String::from_utf8_lossy(&output.stdout);
-    // Should switch successfully - verify both success and mention of work profile
-    assert!(
-        output.status.success(),
-        "Should switch to existing profile: stderr={}",
-        String::from_utf8_lossy(&output.stderr)
-    );
-    // Verify that the profile switch was acknowledged with specific confirmation
-    let stdout_lower = stdout.to_lowercase();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout.contains("Version:   2.12.0"), "stdout={}", stdout);
     assert!(
-        (stdout_lower.contains("switched") || stdout_lower.contains("active"))
-            && stdout_lower.contains("work"),
-        "Should confirm switch to work profile: {}",
+        stdout.contains("Latest:    2.12.1 (newer than installed)"),
+        "stdout={}",
         stdout
     );
-}
-
-// =============================================================================
-// Install --platform tests
-// =============================================================================
-
-#[test]
-fn test_install_platform_flag_help() {
-    let output = nixy_cmd().args(["install", "--help"]).output().unwrap();
-    assert!(output.status.success());
-    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(OLD_HELLO_COMMIT), "stdout={}", stdout);
     assert!(
-        stdout.contains("--platform") || stdout.contains("-p"),
-        "Help should show --platform flag: {}",
+        stdout.contains("Profiles:  default, work"),
+        "stdout={}",
         stdout
     );
-}
-
-#[test]
-fn test_install_invalid_platform() {
-    let env = TestEnv::new();
 
-    // Create a profile first
-    let _ = env.cmd().args(["profile", "default", "-c"]).output();
+    // Found in another profile than the active one
+    let output = env.cmd().args(["info", "tool", "--json"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let tool: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(tool["kind"], "custom");
+    assert_eq!(tool["input_url"], "github:owner/tool");
+    assert_eq!(tool["profiles"], serde_json::json!(["work"]));
+    assert_eq!(tool["latest_version"], serde_json::Value::Null);
 
     let output = env
         .cmd()
-        .args(["install", "hello", "--platform", "windows"])
+        .args(["info", "mine", "--json", "--offline"])
         .output()
         .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let mine: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(mine["kind"], "local-file");
+    assert_eq!(
+        mine["path"],
+        packages_dir.join("mine.nix").to_string_lossy().as_ref()
+    );
+    assert_eq!(mine["profiles"], serde_json::json!(["default", "work"]));
 
+    let output = env.cmd().args(["info", "nope"]).output().unwrap();
     assert!(!output.status.success());
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    assert!(
-        stderr.contains("Invalid platform"),
-        "Should report invalid platform: {}",
-        stderr
-    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("'nope' is not installed"));
+}
+
+#[test]
+fn test_hermetic_why_explains_where_a_package_comes_from() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", OLD_HELLO_COMMIT)
+        .package("default", "ripgrep")
+        .custom("default", "tool", "owner-tool", "github:owner/tool")
+        .profile("work")
+        .package("work", "fd")
+        .write(&env.config_dir);
+    let packages_dir = env.config_dir.join("packages");
+    std::fs::create_dir_all(&packages_dir).unwrap();
+    std::fs::write(
+        packages_dir.join("mine.nix"),
+        r#"{ stdenv }: stdenv.mkDerivation { pname = "mine"; version = "1.0"; src = ./.; }"#,
+    )
+    .unwrap();
+
+    for (package, expected) in [
+        (
+            "hello",
+            format!(
+                "hello is installed directly in profile 'default' at version 2.12.0, from nixpkgs commit {}",
+                OLD_HELLO_COMMIT
+            ),
+        ),
+        (
+            "ripgrep",
+            "ripgrep is installed directly in profile 'default', from the profile's nixpkgs channel"
+                .to_string(),
+        ),
+        (
+            "tool",
+            "tool is a flake package in profile 'default', from github:owner/tool".to_string(),
+        ),
+        (
+            "mine",
+            format!(
+                "mine is a local package in the global packages directory ({}), part of every profile",
+                packages_dir.join("mine.nix").display()
+            ),
+        ),
+    ] {
+        let output = env.cmd().args(["why", package]).output().unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), expected);
+    }
+    // Purely a state query
+    assert!(env.nix().calls_to("build").is_empty());
+
+    // Only the active profile counts
+    let output = env.cmd().args(["why", "fd"]).output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("'fd' is not installed in profile 'default'"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("nixy list"), "{}", stderr);
+}
+
+#[test]
+fn test_hermetic_which_finds_the_package_behind_a_command() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", OLD_HELLO_COMMIT)
+        .write(&env.config_dir);
+    let output = env.cmd().arg("sync").output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    // buildEnv links each command to the package it comes from
+    let package = env.nix().store_dir().join("aaaa-hello-2.12.0");
+    std::fs::create_dir_all(package.join("bin")).unwrap();
+    std::fs::write(package.join("bin/hello"), "#!/bin/sh\n").unwrap();
+    std::os::unix::fs::symlink(package.join("bin/hello"), env.env_path.join("bin/hello")).unwrap();
+    env.nix().on_eval(
+        "*outPath*",
+        &serde_json::json!({ "hello": [package.to_string_lossy()] }).to_string(),
+    );
+
+    let output = env.cmd().args(["which", "hello"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(&format!("hello: {}", package.join("bin/hello").display())),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains(&format!(
+            "hello is installed directly in profile 'default' at version 2.12.0, from nixpkgs commit {}",
+            OLD_HELLO_COMMIT
+        )),
+        "{}",
+        stdout
+    );
+
+    // Not in the environment, but elsewhere on PATH
+    let elsewhere = env._temp.path().join("elsewhere");
+    std::fs::create_dir_all(&elsewhere).unwrap();
+    let jq = elsewhere.join("jq");
+    std::fs::write(&jq, "#!/bin/sh\n").unwrap();
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(&jq, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let mut path = vec![env.env_path.join("bin"), elsewhere];
+    path.extend(std::env::split_paths(&env.nix().path_env()));
+    let output = env
+        .cmd()
+        .args(["which", "jq"])
+        .env("PATH", std::env::join_paths(path).unwrap())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "'jq' is not in the nixy environment; the one on PATH is {}",
+            jq.display()
+        )),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_hermetic_platform_edits_an_installed_package() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", OLD_HELLO_COMMIT)
+        .package("default", "ripgrep")
+        .write(&env.config_dir);
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+
+    let output = env
+        .cmd()
+        .args(["platform", "hello", "--set", "darwin"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        profiles_json(&env)["default"]["resolved_packages"][0]["platforms"],
+        serde_json::json!(["aarch64-darwin", "x86_64-darwin"])
+    );
+    let flake = env.profile_flake("default");
+    assert!(
+        flake.contains(
+            "pkgs.lib.optionals (builtins.elem system [ \"aarch64-darwin\" \"x86_64-darwin\" ]) [\n              hello\n"
+        ),
+        "{}",
+        flake
+    );
+    assert_eq!(env.nix().calls_to("build").len(), 2);
+
+    let output = env
+        .cmd()
+        .args(["platform", "hello", "--clear"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(profiles_json(&env)["default"]["resolved_packages"][0]
+        .get("platforms")
+        .map_or(true, |p| p.is_null()));
+    let flake = env.profile_flake("default");
+    assert!(!flake.contains("optionals"), "{}", flake);
+    assert!(flake.contains("              hello\n"), "{}", flake);
+
+    for (args, expected) in [
+        (
+            vec!["platform", "ripgrep", "--set", "linux"],
+            "legacy entry",
+        ),
+        (
+            vec!["platform", "nope", "--clear"],
+            "'nope' is not installed",
+        ),
+        (
+            vec!["platform", "hello", "--set", "windows"],
+            "Invalid platform",
+        ),
+        (vec!["platform", "hello"], "--set"),
+    ] {
+        let output = env.cmd().args(&args).output().unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(stderr.contains(expected), "{:?}: {}", args, stderr);
+    }
+    assert_eq!(env.nix().calls_to("build").len(), 3);
+}
+
+#[test]
+fn test_hermetic_gc_removes_only_stale_roots() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "ripgrep")
+        .profile("work")
+        .write(&env.config_dir);
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    let builds = env.state_dir.join("builds");
+    let current = std::fs::read_link(builds.join("default")).unwrap();
+    // A profile deleted by hand, and a result link from a manual nix build
+    std::os::unix::fs::symlink(&current, builds.join("old")).unwrap();
+    let result_link = env.state_dir.join("profiles/default/result");
+    std::os::unix::fs::symlink(&current, &result_link).unwrap();
+    env.nix()
+        .dead_paths(&["/nix/store/aaaa-hello-2.12", "/nix/store/bbbb-jq-1.7"]);
+
+    let output = env.cmd().args(["gc", "--dry-run"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Would remove stale root"), "{}", stdout);
+    assert!(
+        stdout.contains("/nix/store/aaaa-hello-2.12\n/nix/store/bbbb-jq-1.7\n"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("2 store path(s) would be deleted"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("Keeping the last build of: default"),
+        "{}",
+        stdout
+    );
+    // Never built, so it has no root
+    assert!(
+        stdout.contains("Profile 'work' has no build to keep"),
+        "{}",
+        stdout
+    );
+    assert!(builds.join("old").is_symlink());
+    assert!(result_link.is_symlink());
+    assert!(env.nix().calls_to("store").is_empty());
+    assert_eq!(env.nix().calls_to("nix-store").len(), 1);
+
+    env.nix()
+        .gc_log("deleting '/nix/store/aaaa-hello-2.12'\n2 store paths deleted, 1.50 MiB freed\n");
+    let output = env.cmd().arg("gc").output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Freed 1.50 MiB (2 store path(s) deleted)"),
+        "{}",
+        stdout
+    );
+    assert!(!builds.join("old").exists() && !builds.join("old").is_symlink());
+    assert!(!result_link.is_symlink());
+    assert_eq!(std::fs::read_link(builds.join("default")).unwrap(), current);
+    assert!(env.env_path.exists());
+    let calls = env.nix().calls_to("store");
+    assert_eq!(calls.len(), 1);
+    assert!(calls[0].ends_with("store gc"), "{:?}", calls);
+
+    // Output nix no longer formats the usual way is left as it is
+    env.nix().gc_log("garbage collected\n");
+    let output = env.cmd().arg("gc").output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Garbage collection finished"), "{}", stdout);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("garbage collected"));
+}
+
+#[test]
+fn test_hermetic_export_then_import_on_another_machine() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", OLD_HELLO_COMMIT)
+        .custom("default", "tool", "owner-tool", "github:owner/tool")
+        .write(&env.config_dir);
+    let local =
+        r#"{ stdenv }: stdenv.mkDerivation { pname = "mine"; version = "1.0"; src = ./.; }"#;
+    std::fs::create_dir_all(env.config_dir.join("packages")).unwrap();
+    std::fs::write(env.config_dir.join("packages/mine.nix"), local).unwrap();
+
+    let output = env.cmd().arg("export").output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let manifest: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(manifest["profile"], "default");
+    assert_eq!(
+        manifest["resolved_packages"][0]["commit_hash"],
+        OLD_HELLO_COMMIT
+    );
+    assert_eq!(
+        manifest["custom_packages"][0]["input_url"],
+        "github:owner/tool"
+    );
+    assert_eq!(manifest["local_packages"]["mine.nix"], local);
+    let file = env.config_dir.join("../default.json");
+    let output = env
+        .cmd()
+        .args(["export", "--output"])
+        .arg(&file)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        std::fs::read(&file).unwrap(),
+        env.cmd().arg("export").output().unwrap().stdout
+    );
+
+    let other = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved(
+            "default",
+            "hello",
+            "2.12.1",
+            "a3a3dda3bacf61e8a39258a0ed9c924eeca8e293",
+        )
+        .package("default", "fd")
+        .write(&other.config_dir);
+    let before = std::fs::read_to_string(other.config_dir.join("nixy.json")).unwrap();
+
+    // hello is already installed from another commit
+    let output = other.cmd().arg("import").arg(&file).output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("hello: nixpkgs hello@2.12.1 (commit a3a3dda3) is installed"),
+        "stderr={}",
+        stderr
+    );
+    assert!(stderr.contains("--force"), "stderr={}", stderr);
+    assert_eq!(
+        std::fs::read_to_string(other.config_dir.join("nixy.json")).unwrap(),
+        before
+    );
+
+    // Into a new profile: nothing conflicts, built on switch
+    let output = other
+        .cmd()
+        .args(["import", "--profile", "work"])
+        .arg(&file)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(
+        stdout.contains("Imported 2 package(s) and 1 local file(s) into profile 'work'"),
+        "stdout={}",
+        stdout
+    );
+    assert!(other.profile_flake("work").contains("nixpkgs-11111111"));
+    assert_eq!(
+        std::fs::read_to_string(other.config_dir.join("packages/mine.nix")).unwrap(),
+        local
+    );
+
+    let output = other
+        .cmd()
+        .args(["import", "--merge", "--force"])
+        .arg(&file)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let config: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(other.config_dir.join("nixy.json")).unwrap())
+            .unwrap();
+    let default = &config["profiles"]["default"];
+    assert_eq!(default["packages"], serde_json::json!(["fd"]));
+    assert_eq!(
+        default["resolved_packages"][0]["commit_hash"],
+        OLD_HELLO_COMMIT
+    );
+    assert_eq!(default["custom_packages"][0]["name"], "tool");
+    assert!(other.env_path.is_symlink());
+}
+
+#[test]
+fn test_hermetic_export_flake_is_standalone() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", OLD_HELLO_COMMIT)
+        .custom("default", "tool", "owner-tool", "github:owner/tool")
+        .profile("work")
+        .write(&env.config_dir);
+    let packages = env.config_dir.join("packages");
+    std::fs::create_dir_all(&packages).unwrap();
+    std::fs::write(
+        packages.join("mine.nix"),
+        r#"{ stdenv }: stdenv.mkDerivation { pname = "mine"; version = "1.0"; src = ./.; }"#,
+    )
+    .unwrap();
+    let output = env.cmd().arg("sync").output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let state = env.state_dir.join("profiles/default");
+    let lock = std::fs::read_to_string(state.join("flake.lock")).unwrap();
+    let nixy_json = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+
+    let out = env._temp.path().join("shared");
+    let output = env
+        .cmd()
+        .args(["export", "--flake", "--output"])
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let flake = std::fs::read_to_string(out.join("flake.nix")).unwrap();
+    assert!(flake.contains("nixpkgs-11111111"), "{}", flake);
+    assert!(flake.contains("github:owner/tool"), "{}", flake);
+    assert!(
+        flake.contains("pkgs.callPackage ./packages/mine.nix {}"),
+        "{}",
+        flake
+    );
+    assert!(!flake.contains(&env.config_dir.display().to_string()));
+    assert_eq!(
+        std::fs::read_to_string(out.join("flake.lock")).unwrap(),
+        lock
+    );
+    assert!(out.join("packages/mine.nix").is_file());
+    // The live profile is left alone
+    assert_eq!(
+        std::fs::read_to_string(state.join("flake.lock")).unwrap(),
+        lock
+    );
+    assert_eq!(
+        std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap(),
+        nixy_json
+    );
+
+    // Not over an existing export, and another profile by name
+    let output = env
+        .cmd()
+        .args(["export", "--flake", "--output"])
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is not empty"));
+    let output = env
+        .cmd()
+        .args(["export", "--flake", "--profile", "work", "--output"])
+        .arg(env._temp.path().join("work"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let flake = std::fs::read_to_string(env._temp.path().join("work/flake.nix")).unwrap();
+    assert!(!flake.contains("github:owner/tool"), "{}", flake);
+    assert!(!env.state_dir.join("profiles/work").exists());
+}
+
+#[test]
+fn test_hermetic_import_an_exported_flake_or_nixy_json() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", OLD_HELLO_COMMIT)
+        .custom("default", "tool", "owner-tool", "github:owner/tool")
+        .profile("work")
+        .package("work", "jq")
+        .write(&env.config_dir);
+    let shared = env._temp.path().join("shared");
+    let output = env
+        .cmd()
+        .args(["export", "--flake", "--output"])
+        .arg(&shared)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    // Merged into the active profile, keeping what it has
+    let other = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "fd")
+        .write(&other.config_dir);
+    other
+        .nix()
+        .on_eval("*legacyPackages.*.hello.version", "2.12.0");
+    let output = other.cmd().arg("import").arg(&shared).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let nixy_json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(other.config_dir.join("nixy.json")).unwrap())
+            .unwrap();
+    let profile = &nixy_json["profiles"]["default"];
+    assert_eq!(profile["packages"], serde_json::json!(["fd"]));
+    assert_eq!(
+        profile["resolved_packages"][0]["commit_hash"],
+        OLD_HELLO_COMMIT
+    );
+    assert_eq!(
+        profile["resolved_packages"][0]["resolved_version"],
+        "2.12.0"
+    );
+    assert_eq!(
+        profile["custom_packages"][0]["input_url"],
+        "github:owner/tool"
+    );
+    assert!(other.profile_flake("default").contains("nixpkgs-11111111"));
+
+    // A conflicting hello from another nixy.json needs --overwrite
+    let elsewhere = env._temp.path().join("elsewhere");
+    std::fs::create_dir_all(&elsewhere).unwrap();
+    NixyJson::new()
+        .profile("work")
+        .resolved(
+            "work",
+            "hello",
+            "2.12.1",
+            "a3a3dda3bacf61e8a39258a0ed9c924eeca8e293",
+        )
+        .write(&elsewhere);
+    let file = elsewhere.join("nixy.json");
+    let output = other
+        .cmd()
+        .args(["import", "--from-profile", "work"])
+        .arg(&file)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("1 conflict(s)"), "stderr={}", stderr);
+    let output = other
+        .cmd()
+        .args(["import", "--from-profile", "work", "--overwrite"])
+        .arg(&file)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let nixy_json = std::fs::read_to_string(other.config_dir.join("nixy.json")).unwrap();
+    assert!(nixy_json.contains("a3a3dda3bacf61e8a39258a0ed9c924eeca8e293"));
+    assert!(nixy_json.contains("\"fd\""));
+    assert!(!nixy_json.contains("\"work\""));
+}
+
+#[test]
+fn test_hermetic_sync_notices_edited_and_removed_local_definitions() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+    let packages = env.config_dir.join("packages");
+    std::fs::create_dir_all(&packages).unwrap();
+    let definition = packages.join("mine.nix");
+    std::fs::write(
+        &definition,
+        r#"{ stdenv }: stdenv.mkDerivation { pname = "mine"; version = "1.0"; src = ./.; }"#,
+    )
+    .unwrap();
+    let output = env.cmd().arg("sync").output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let nixy_json = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    assert!(nixy_json.contains("\"mine.nix\""), "{}", nixy_json);
+
+    // An unchanged definition leaves nixy.json alone
+    let output = env.cmd().arg("sync").output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("changed"));
+    assert_eq!(
+        std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap(),
+        nixy_json
+    );
+
+    std::fs::write(
+        &definition,
+        r#"{ stdenv }: stdenv.mkDerivation { pname = "mine"; version = "1.1"; src = ./.; }"#,
+    )
+    .unwrap();
+    let output = env.cmd().args(["list", "--json"]).output().unwrap();
+    let listed: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(listed["packages"][0]["changed_since_sync"], true);
+    let output = env.cmd().arg("sync").output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Local package mine changed"), "{}", stdout);
+    let output = env.cmd().args(["list", "--json"]).output().unwrap();
+    let listed: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(listed["packages"][0]["changed_since_sync"], false);
+
+    // A definition removed behind nixy's back stops the sync...
+    std::fs::remove_file(&definition).unwrap();
+    let output = env.cmd().arg("sync").output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("removed since the last sync: mine"),
+        "{}",
+        stderr
+    );
+
+    // ...until it is uninstalled
+    let output = env.cmd().args(["uninstall", "mine"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let output = env.cmd().arg("sync").output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let nixy_json = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    assert!(!nixy_json.contains("mine.nix"), "{}", nixy_json);
+}
+
+#[test]
+fn test_diff_between_profiles() {
+    let env = TestEnv::new();
+    NixyJson::new()
+        .package("default", "fd")
+        .resolved(
+            "default",
+            "hello",
+            "2.12.1",
+            "a3a3dda3bacf61e8a39258a0ed9c924eeca8e293",
+        )
+        .profile("work")
+        .package("work", "fd")
+        .package("work", "jq")
+        .resolved("work", "hello", "2.12.0", OLD_HELLO_COMMIT)
+        .custom("work", "tool", "owner-tool", "github:owner/tool")
+        .write(&env.config_dir);
+
+    // The second profile defaults to the active one
+    let output = env.cmd().args(["diff", "work"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(
+        stdout.contains("Changes from 'work' to 'default'"),
+        "stdout={}",
+        stdout
+    );
+    assert!(stdout.contains("-jq"), "stdout={}", stdout);
+    assert!(
+        stdout.contains("~hello 2.12.0 -> 2.12.1"),
+        "stdout={}",
+        stdout
+    );
+    assert!(
+        stdout.contains("-tool (github:owner/tool)"),
+        "stdout={}",
+        stdout
+    );
+    assert!(!stdout.contains("fd"), "stdout={}", stdout);
+
+    let output = env
+        .cmd()
+        .args(["diff", "default", "work", "--json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let document: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        document,
+        serde_json::json!({
+            "from": "default",
+            "to": "work",
+            "packages": {
+                "added": [{ "name": "jq" }],
+                "removed": [],
+                "changed": [],
+            },
+            "resolved_packages": {
+                "added": [],
+                "removed": [],
+                "changed": [{ "name": "hello", "from": "2.12.1", "to": "2.12.0" }],
+            },
+            "custom_packages": {
+                "added": [{ "name": "tool", "url": "github:owner/tool" }],
+                "removed": [],
+                "changed": [],
+            },
+        })
+    );
+
+    let output = env
+        .cmd()
+        .args(["diff", "default", "default"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("have the same packages"),
+        "stdout={}",
+        stdout
+    );
+
+    let output = env.cmd().args(["diff", "missing"]).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("missing"));
+}
+
+#[test]
+fn test_profile_copy_clones_packages_and_lock() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "ripgrep")
+        .write(&env.config_dir);
+    let local_flake_dir = env.config_dir.join("packages/my-local-flake");
+    std::fs::create_dir_all(&local_flake_dir).unwrap();
+    std::fs::write(
+        local_flake_dir.join("flake.nix"),
+        "{ outputs = { self }: { packages = { }; }; }",
+    )
+    .unwrap();
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+
+    let output = env
+        .cmd()
+        .args(["profile", "work", "--copy", "default"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let config: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap())
+            .unwrap();
+    assert_eq!(config["active_profile"], "default");
+    assert_eq!(
+        config["profiles"]["work"]["packages"],
+        serde_json::json!(["ripgrep"])
+    );
+    let flake = env.profile_flake("work");
+    assert_eq!(flake, env.profile_flake("default"));
+    // Local packages stay referenced in the shared packages/ directory
+    assert!(
+        flake.contains(&local_flake_dir.to_string_lossy().into_owned()),
+        "{}",
+        flake
+    );
+    assert_eq!(
+        std::fs::read(env.state_dir.join("profiles/work/flake.lock")).unwrap(),
+        std::fs::read(env.state_dir.join("profiles/default/flake.lock")).unwrap()
+    );
+    assert_eq!(env.nix().calls_to("build").len(), 1);
+
+    // With -c it also switches to the copy and builds it
+    let output = env
+        .cmd()
+        .args(["profile", "work2", "--copy", "work", "-c"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        profiles_json(&env)["work2"]["packages"],
+        serde_json::json!(["ripgrep"])
+    );
+    let config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    assert!(
+        config.contains("\"active_profile\": \"work2\""),
+        "{}",
+        config
+    );
+    assert_eq!(env.nix().calls_to("build").len(), 2);
+
+    for (args, expected) in [
+        (["profile", "other", "--copy", "missing"], "does not exist"),
+        (["profile", "work", "--copy", "default"], "already exists"),
+    ] {
+        let output = env.cmd().args(args).output().unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(stderr.contains(expected), "{:?}: {}", args, stderr);
+    }
+}
+
+#[test]
+fn test_profile_copy_from_force_replaces_existing() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .profile("work")
+        .package("default", "ripgrep")
+        .package("work", "fd")
+        .write(&env.config_dir);
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+
+    let output = env
+        .cmd()
+        .args(["profile", "work", "--copy-from", "default", "-c"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("--force"), "{}", stderr);
+    assert_eq!(
+        profiles_json(&env)["work"]["packages"],
+        serde_json::json!(["fd"])
+    );
+
+    let output = env
+        .cmd()
+        .args(["profile", "work", "--copy-from", "default", "-c", "--force"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Replaced profile 'work'"), "{}", stdout);
+    let profiles = profiles_json(&env);
+    assert_eq!(profiles["work"]["packages"], serde_json::json!(["ripgrep"]));
+    assert_eq!(
+        profiles["default"]["packages"],
+        serde_json::json!(["ripgrep"])
+    );
+    assert_eq!(env.profile_flake("work"), env.profile_flake("default"));
+    let config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    assert!(
+        config.contains("\"active_profile\": \"work\""),
+        "{}",
+        config
+    );
+
+    // Changing the copy leaves the source alone
+    assert!(env
+        .cmd()
+        .args(["uninstall", "ripgrep"])
+        .output()
+        .unwrap()
+        .status
+        .success());
+    assert_eq!(
+        profiles_json(&env)["default"]["packages"],
+        serde_json::json!(["ripgrep"])
+    );
+
+    let output = env
+        .cmd()
+        .args(["profile", "default", "--copy-from", "default", "--force"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("onto itself"));
+}
+
+#[test]
+fn test_profile_rename_rejects_bad_names() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().profile("work").write(&env.config_dir);
+
+    for (args, expected) in [
+        (["profile", "work", "--rename", "default"], "already exists"),
+        (
+            ["profile", "work", "--rename", "bad name"],
+            "Invalid profile name",
+        ),
+        (
+            ["profile", "missing", "--rename", "other"],
+            "does not exist",
+        ),
+        (
+            ["profile", "default", "--rename", "main"],
+            "Cannot rename the default",
+        ),
+    ] {
+        let output = env.cmd().args(args).output().unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(stderr.contains(expected), "{:?}: {}", args, stderr);
+    }
+    let config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    assert!(config.contains("\"work\""), "{}", config);
+}
+
+#[test]
+fn test_profile_set_description_rejects_bad_input() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().profile("work").write(&env.config_dir);
+
+    let output = env
+        .cmd()
+        .args(["profile", "work", "--set-description", "line one\nline two"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("single line"), "stderr={}", stderr);
+
+    let output = env
+        .cmd()
+        .args(["profile", "missing", "--set-description", "x"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("missing"), "stderr={}", stderr);
+
+    let output = env
+        .cmd()
+        .args(["profile", "work", "-c", "--set-description", "x"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_profile_delete_requires_tty() {
+    let env = TestEnv::new();
+
+    // Create two profiles with new syntax
+    let _ = env.cmd().args(["profile", "work", "-c"]).output();
+    let _ = env.cmd().args(["profile", "default", "-c"]).output();
+
+    // Try to delete (will fail because tests run without a TTY)
+    let output = env.cmd().args(["profile", "work", "-d"]).output().unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // Should mention needing a terminal for confirmation
+    assert!(
+        stderr.contains("terminal") || stderr.contains("TTY") || stderr.contains("interactively"),
+        "Should mention terminal requirement: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_profile_delete_active_fails() {
+    let env = TestEnv::new();
+
+    // Create a profile and stay on it (it becomes active)
+    let _ = env.cmd().args(["profile", "work", "-c"]).output();
+
+    // Try to delete the active profile (will fail for multiple reasons in test:
+    // 1. No TTY for confirmation
+    // 2. Cannot delete active profile)
+    let output = env.cmd().args(["profile", "work", "-d"]).output().unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stderr_lower = stderr.to_lowercase();
+    // Should fail - either because it's the active profile or because no TTY
+    assert!(
+        stderr_lower.contains("active")
+            || stderr_lower.contains("cannot delete")
+            || stderr_lower.contains("can't delete")
+            || stderr_lower.contains("unable to delete")
+            || stderr_lower.contains("terminal")
+            || stderr_lower.contains("interactively"),
+        "Should prevent deleting active profile or require terminal: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_profile_delete_non_tty() {
+    let env = TestEnv::new();
+
+    // Create two profiles and end up on default so work is not active
+    let _ = env.cmd().args(["profile", "work", "-c"]).output();
+    let _ = env.cmd().args(["profile", "default", "-c"]).output();
+
+    // Try to delete work - will fail because delete requires TTY for confirmation
+    let output = env.cmd().args(["profile", "work", "-d"]).output().unwrap();
+
+    // Should fail because tests run without a TTY
+    assert!(
+        !output.status.success(),
+        "Profile delete should fail without TTY"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("terminal") || stderr.contains("interactively"),
+        "Should mention terminal requirement: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_profile_delete_non_tty_mentions_yes() {
+    let env = TestEnv::new();
+    let _ = env.cmd().args(["profile", "work", "-c"]).output();
+    let _ = env.cmd().args(["profile", "default", "-c"]).output();
+
+    let output = env.cmd().args(["profile", "work", "-d"]).output().unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("requires a terminal or --yes"),
+        "Should point at --yes: {}",
+        stderr
+    );
+    assert!(env.config_dir.join("nixy.json").exists());
+    let config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    assert!(
+        config.contains("\"work\""),
+        "work should be kept: {}",
+        config
+    );
+}
+
+#[test]
+fn test_profile_delete_with_yes() {
+    let env = TestEnv::new();
+    let _ = env.cmd().args(["profile", "work", "-c"]).output();
+    let _ = env.cmd().args(["profile", "default", "-c"]).output();
+
+    // --yes is global, so it works before or after the subcommand
+    let output = env
+        .cmd()
+        .args(["profile", "work", "-d", "--yes"])
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    assert!(
+        !config.contains("\"work\""),
+        "work should be gone: {}",
+        config
+    );
+    assert!(!env.state_dir.join("profiles/work").exists());
+}
+
+#[test]
+fn test_profile_delete_active_with_yes_suggests_switching() {
+    let env = TestEnv::new();
+    let _ = env.cmd().args(["profile", "work", "-c"]).output();
+
+    let output = env
+        .cmd()
+        .args(["-y", "profile", "work", "-d"])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Cannot delete the active profile 'work'")
+            && stderr.contains("nixy profile <name>"),
+        "stderr={}",
+        stderr
+    );
+    let config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    assert!(
+        config.contains("\"work\""),
+        "work should be kept: {}",
+        config
+    );
+
+    // Once another profile is active, the short flag deletes it too
+    let _ = env.cmd().args(["profile", "default"]).output();
+    let output = env
+        .cmd()
+        .args(["profile", "work", "-d", "-y"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!env.state_dir.join("profiles/work").exists());
+}
+
+#[test]
+fn test_profile_switch_with_existing() {
+    let env = TestEnv::new();
+
+    // Create a profile with new syntax
+    let _ = env.cmd().args(["profile", "work", "-c"]).output();
+
+    // Create another profile
+    let _ = env.cmd().args(["profile", "default", "-c"]).output();
+
+    // Switch back with -c (should just switch, not error since profile exists)
+    let output = env.cmd().args(["profile", "work", "-c"]).output().unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // Should switch successfully - verify both success and mention of work profile
+    assert!(
+        output.status.success(),
+        "Should switch to existing profile: stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // Verify that the profile switch was acknowledged with specific confirmation
+    let stdout_lower = stdout.to_lowercase();
+    assert!(
+        (stdout_lower.contains("switched") || stdout_lower.contains("active"))
+            && stdout_lower.contains("work"),
+        "Should confirm switch to work profile: {}",
+        stdout
+    );
+}
+
+// =============================================================================
+// Install --platform tests
+// =============================================================================
+
+#[test]
+fn test_install_platform_flag_help() {
+    let output = nixy_cmd().args(["install", "--help"]).output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("--platform") || stdout.contains("-p"),
+        "Help should show --platform flag: {}",
+        stdout
+    );
+}
+
+#[test]
+fn test_install_invalid_platform() {
+    let env = TestEnv::new();
+
+    // Create a profile first
+    let _ = env.cmd().args(["profile", "default", "-c"]).output();
+
+    let output = env
+        .cmd()
+        .args(["install", "hello", "--platform", "windows"])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Invalid platform"),
+        "Should report invalid platform: {}",
+        stderr
+    );
+}
+
+// =============================================================================
+// Upgrade (self) command tests
+// =============================================================================
+
+#[test]
+fn test_upgrade_help_and_flags() {
+    // Single comprehensive test for upgrade help output
+    let output = nixy_cmd().args(["upgrade", "--help"]).output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // Verify specific upgrade content
+    assert!(
+        stdout.contains("nixy") || stdout.contains("latest"),
+        "Help should mention upgrading nixy: {}",
+        stdout
+    );
+
+    // Verify --force flag is documented
+    assert!(
+        stdout.contains("--force"),
+        "Help should show --force flag: {}",
+        stdout
+    );
+
+    // Verify -f short flag is documented
+    assert!(
+        stdout.contains("-f"),
+        "Help should show -f short flag: {}",
+        stdout
+    );
+}
+
+// =============================================================================
+// List command tests (additional)
+// =============================================================================
+
+#[test]
+fn test_list_count_empty_profile() {
+    let env = TestEnv::new();
+    NixyJson::new().write(&env.config_dir);
+
+    let output = env.cmd().args(["list", "--count"]).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "0\n");
+}
+
+#[test]
+fn test_list_count_without_nix() {
+    let env = TestEnv::new();
+    NixyJson::new()
+        .package("default", "hello")
+        .resolved("default", "jq", "1.7.1", "abc123def456")
+        .custom("default", "tool", "owner-tool", "github:owner/tool")
+        .write(&env.config_dir);
+    let packages_dir = env.config_dir.join("packages");
+    std::fs::create_dir_all(&packages_dir).unwrap();
+    // A local definition shadows the profile entry of the same name
+    std::fs::write(
+        packages_dir.join("hello.nix"),
+        r#"{ stdenv }: stdenv.mkDerivation { pname = "hello"; version = "1.0"; src = ./.; }"#,
+    )
+    .unwrap();
+    std::fs::write(
+        packages_dir.join("mine.nix"),
+        r#"{ stdenv }: stdenv.mkDerivation { pname = "mine"; version = "1.0"; src = ./.; }"#,
+    )
+    .unwrap();
+
+    // Only cargo's own directory on PATH: no nix to call
+    let toolchain_bin = std::path::Path::new(env!("CARGO")).parent().unwrap();
+    let output = env
+        .cmd()
+        .env("PATH", toolchain_bin)
+        .args(["list", "--count"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "4\n");
+
+    let output = env
+        .cmd()
+        .env("PATH", toolchain_bin)
+        .args(["list", "--count", "--json"])
+        .output()
+        .unwrap();
+    let counts: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        counts,
+        serde_json::json!({
+            "total": 4,
+            "packages": 0,
+            "resolved_packages": 1,
+            "custom_packages": 1,
+            "local": 2,
+        })
+    );
+}
+
+#[test]
+fn test_list_json_includes_local_paths() {
+    let env = TestEnv::new();
+    NixyJson::new()
+        .package("default", "fd")
+        .resolved("default", "jq", "1.7.1", "abc123def456")
+        .custom("default", "tool", "owner-tool", "github:owner/tool")
+        .write(&env.config_dir);
+    let packages_dir = env.config_dir.join("packages");
+    std::fs::create_dir_all(packages_dir.join("myflake")).unwrap();
+    std::fs::write(
+        packages_dir.join("myflake/flake.nix"),
+        "{ outputs = _: { }; }",
+    )
+    .unwrap();
+    // Named by its pname, not its file name
+    std::fs::write(
+        packages_dir.join("defs.nix"),
+        r#"{ stdenv }: stdenv.mkDerivation { pname = "mine"; version = "1.0"; src = ./.; }"#,
+    )
+    .unwrap();
+
+    let output = env.cmd().args(["list", "--json"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains('\x1b'), "stdout={}", stdout);
+    let document: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(document["active_profile"], "default");
+    assert_eq!(document["profiles"], serde_json::json!(["default"]));
+    assert_eq!(
+        document["packages"],
+        serde_json::json!([
+            {
+                "name": "fd",
+                "source": "nixpkgs",
+                "kind": "legacy",
+                "version": null,
+                "commit": null,
+                "pinned": false,
+                "platforms": null,
+                "input_url": null,
+            },
+            {
+                "name": "jq",
+                "source": "resolved",
+                "kind": "resolved",
+                "version": "1.7.1",
+                "commit": "abc123def456",
+                "pinned": false,
+                "platforms": null,
+                "input_url": null,
+            },
+            {
+                "name": "mine",
+                "source": "local",
+                "kind": "local-file",
+                "version": null,
+                "commit": null,
+                "pinned": false,
+                "platforms": null,
+                "input_url": null,
+                "changed_since_sync": false,
+                "path": packages_dir.join("defs.nix").to_string_lossy(),
+            },
+            {
+                "name": "myflake",
+                "source": "local",
+                "kind": "local-flake",
+                "version": null,
+                "commit": null,
+                "pinned": false,
+                "platforms": null,
+                "input_url": null,
+                "changed_since_sync": false,
+                "path": packages_dir.join("myflake").to_string_lossy(),
+            },
+            {
+                "name": "tool",
+                "source": "custom",
+                "kind": "custom",
+                "version": null,
+                "commit": null,
+                "pinned": false,
+                "platforms": null,
+                "input_url": "github:owner/tool",
+                "url": "github:owner/tool",
+            },
+        ])
+    );
+
+    let output = nixy_cmd()
+        .args(["list", "--json", "--verify"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_hermetic_list_outdated() {
+    let env = TestEnv::hermetic();
+    let mut config: serde_json::Value = serde_json::from_str(
+        &NixyJson::new()
+            .resolved("default", "hello", "2.12.0", OLD_HELLO_COMMIT)
+            .resolved("default", "ripgrep", "14.1.1", OLD_HELLO_COMMIT)
+            .resolved("default", "gone", "1.0", OLD_HELLO_COMMIT)
+            .custom("default", "tool", "owner-tool", "github:owner/tool")
+            .to_json(),
+    )
+    .unwrap();
+    config["profiles"]["default"]["resolved_packages"]
+        .as_array_mut()
+        .unwrap()
+        .push(serde_json::json!({
+            "name": "nodejs",
+            "version_spec": "20",
+            "resolved_version": "20.11.0",
+            "attribute_path": "nodejs_20",
+            "commit_hash": OLD_HELLO_COMMIT,
+            "pinned": true,
+        }));
+    std::fs::create_dir_all(&env.config_dir).unwrap();
+    let nixy_json = config.to_string();
+    std::fs::write(env.config_dir.join("nixy.json"), &nixy_json).unwrap();
+
+    let output = env.cmd().args(["list", "--outdated"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stdout.contains("  hello   2.12.0 -> 2.12.1\n"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("  nodejs  20.11.0 -> 20.18.1 (requested 20) (pinned)\n"),
+        "{}",
+        stdout
+    );
+    assert!(!stdout.contains("ripgrep"), "{}", stdout);
+    assert!(
+        stdout.contains("  tool    check manually (github:owner/tool)"),
+        "{}",
+        stdout
+    );
+    // One lookup failing leaves the others standing
+    assert!(stderr.contains("Could not check gone"), "{}", stderr);
+
+    // Nothing is written or built
+    assert_eq!(
+        std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap(),
+        nixy_json
+    );
+    assert!(!env.state_dir.join("nixhub-cache.json").exists());
+    assert!(env.nix().calls_to("build").is_empty());
+}
+
+#[test]
+fn test_list_json_of_empty_profile_has_no_packages() {
+    let env = TestEnv::new();
+    NixyJson::new()
+        .package("default", "ripgrep")
+        .profile("work")
+        .active("work")
+        .write(&env.config_dir);
+
+    let output = env.cmd().args(["list", "--json"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let document: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        document,
+        serde_json::json!({
+            "active_profile": "work",
+            "profiles": ["default", "work"],
+            "packages": [],
+        })
+    );
+}
+
+#[test]
+fn test_list_shows_none_for_empty_flake() {
+    let env = TestEnv::new();
+
+    // Create a profile (empty flake)
+    let _ = env.cmd().args(["profile", "default", "-c"]).output();
+
+    let output = env.cmd().arg("list").output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stdout_lower = stdout.to_lowercase();
+
+    // Command should succeed for an empty flake
+    assert!(
+        output.status.success(),
+        "List should succeed for empty flake: stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Should show indication of empty/no packages
+    assert!(
+        stdout.contains("(none)")
+            || stdout_lower.contains("no packages")
+            || stdout_lower.contains("empty")
+            || stdout.contains("Packages in"),
+        "Should indicate empty package list: {}",
+        stdout
+    );
+}
+
+#[test]
+fn test_list_shows_installed_packages() {
+    let env = TestEnv::new();
+
+    // Create a profile directory with packages.json and flake.nix
+    let profile_dir = env.config_dir.join("profiles/default");
+    std::fs::create_dir_all(&profile_dir).unwrap();
+
+    // Create packages.json with installed packages (new state-based format)
+    let state_content = r#"{
+  "version": 1,
+  "packages": ["bat", "fzf", "ripgrep"],
+  "custom_packages": []
+}"#;
+    std::fs::write(profile_dir.join("packages.json"), state_content).unwrap();
+
+    // Write a flake.nix with packages (no markers - new format)
+    let flake_content = r#"{
+  description = "nixy managed packages";
+
+  inputs = {
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  };
+
+  outputs = { self, nixpkgs }@inputs:
+    let
+      systems = [ "x86_64-linux" "aarch64-linux" "x86_64-darwin" "aarch64-darwin" ];
+      forAllSystems = f: nixpkgs.lib.genAttrs systems (system: f system);
+    in {
+      packages = forAllSystems (system:
+        let pkgs = nixpkgs.legacyPackages.${system};
+        in rec {
+          bat = pkgs.bat;
+          fzf = pkgs.fzf;
+          ripgrep = pkgs.ripgrep;
+
+          default = pkgs.buildEnv {
+            name = "nixy-env";
+            paths = [
+              bat
+              fzf
+              ripgrep
+            ];
+            extraOutputsToInstall = [ "man" "doc" "info" ];
+          };
+        });
+    };
+}
+"#;
+    std::fs::write(profile_dir.join("flake.nix"), flake_content).unwrap();
+
+    // Set active profile
+    std::fs::write(env.config_dir.join("active"), "default").unwrap();
+
+    let output = env.cmd().arg("list").output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // Command should succeed
+    assert!(
+        output.status.success(),
+        "List should succeed: stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Should show the installed packages
+    assert!(
+        stdout.contains("ripgrep"),
+        "Should show ripgrep: {}",
+        stdout
+    );
+    assert!(stdout.contains("fzf"), "Should show fzf: {}", stdout);
+    assert!(stdout.contains("bat"), "Should show bat: {}", stdout);
+
+    // Should NOT show "(none)"
+    assert!(
+        !stdout.contains("(none)"),
+        "Should not show (none) when packages exist: {}",
+        stdout
+    );
+
+    // Should show source info
+    assert!(
+        stdout.contains("(nixpkgs)"),
+        "Should show (nixpkgs) for standard packages: {}",
+        stdout
+    );
+}
+
+#[test]
+fn test_list_shows_source_info() {
+    let env = TestEnv::new();
+
+    // Create a profile directory with packages.json
+    let profile_dir = env.config_dir.join("profiles/default");
+    std::fs::create_dir_all(&profile_dir).unwrap();
+
+    // Create packages.json with both standard and custom packages
+    let state_content = r#"{
+  "version": 1,
+  "packages": ["ripgrep"],
+  "custom_packages": [
+    {
+      "name": "neovim",
+      "input_name": "neovim-nightly",
+      "input_url": "github:nix-community/neovim-nightly-overlay",
+      "package_output": "packages",
+      "source_name": null
+    }
+  ]
+}"#;
+    std::fs::write(profile_dir.join("packages.json"), state_content).unwrap();
+
+    // Set active profile
+    std::fs::write(env.config_dir.join("active"), "default").unwrap();
+
+    let output = env.cmd().arg("list").output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(
+        output.status.success(),
+        "List should succeed: stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Standard package should show (nixpkgs)
+    assert!(
+        stdout.contains("ripgrep") && stdout.contains("(nixpkgs)"),
+        "Should show ripgrep with (nixpkgs) source: {}",
+        stdout
+    );
+
+    // Custom package should show its URL
+    assert!(
+        stdout.contains("neovim") && stdout.contains("github:nix-community/neovim-nightly-overlay"),
+        "Should show neovim with its flake URL: {}",
+        stdout
+    );
 }
 
 // =============================================================================
-// Upgrade (self) command tests
+// Uninstall command tests (additional)
+// =============================================================================
+
+#[test]
+fn test_uninstall_package_not_installed() {
+    let env = TestEnv::new();
+
+    // Create a profile first
+    let _ = env.cmd().args(["profile", "default", "-c"]).output();
+
+    // Uninstalling a non-existent package
+    let output = env
+        .cmd()
+        .args(["uninstall", "nonexistent-package"])
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stderr_lower = stderr.to_lowercase();
+
+    // The implementation may either:
+    // 1. Succeed silently (no-op) - package wasn't there, nothing to uninstall
+    // 2. Fail with an error message
+    if output.status.success() {
+        // If it succeeds, it should indicate the package wasn't found or is a no-op
+        if stdout.is_empty() {
+            // Silent success is acceptable for a no-op uninstall of a non-existent package,
+            // but it must also be truly silent (no stderr output) and exit successfully.
+            assert!(
+                stderr.is_empty(),
+                "Silent no-op uninstall should not produce stderr: {}",
+                stderr
+            );
+        } else {
+            // If it succeeds with output, it should indicate the package wasn't found or that
+            // there were no changes to apply.
+            assert!(
+                stdout.contains("not installed")
+                    || stdout.contains("nonexistent-package")
+                    || stdout.contains("No changes"),
+                "No-op uninstall should indicate status: stdout={}",
+                stdout
+            );
+        }
+    } else {
+        // If it fails, it should provide a helpful error message
+        assert!(
+            stderr_lower.contains("not installed")
+                || stderr_lower.contains("not found")
+                || stderr_lower.contains("does not exist")
+                || stderr.contains("nonexistent-package"),
+            "Failed uninstall should indicate package not found: {}",
+            stderr
+        );
+    }
+}
+
+// =============================================================================
+// Flake reference routing tests
+// =============================================================================
+
+#[test]
+fn test_install_flake_reference_routes_to_flake_url() {
+    let env = TestEnv::new();
+
+    // Create a profile first
+    let _ = env.cmd().args(["profile", "default", "-c"]).output();
+
+    // `nixy install github:user/repo` should NOT go through Nixhub
+    // It should be treated as a flake reference and installed via flake URL
+    let output = env
+        .cmd()
+        .args(["install", "github:yusukeshib/wb"])
+        .output()
+        .unwrap();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // The key assertion: it should NOT try to resolve via Nixhub
+    assert!(
+        !stderr.contains("Nixhub") && !stdout.contains("Nixhub"),
+        "Flake reference should not be routed to Nixhub: stdout={}, stderr={}",
+        stdout,
+        stderr
+    );
+
+    // It should recognize this as a flake URL (either succeeding or failing with a
+    // nix/flake-related error, not a Nixhub error)
+    if !output.status.success() {
+        let combined = format!("{}{}", stdout, stderr).to_lowercase();
+        assert!(
+            combined.contains("flake")
+                || combined.contains("validating")
+                || combined.contains("nix")
+                || combined.contains("using flake url"),
+            "Error should be flake-related, not Nixhub-related: stdout={}, stderr={}",
+            stdout,
+            stderr
+        );
+    }
+}
+
+#[test]
+fn test_install_flake_reference_with_fragment() {
+    let env = TestEnv::new();
+
+    // Create a profile first
+    let _ = env.cmd().args(["profile", "default", "-c"]).output();
+
+    // `nixy install github:user/repo#pkg` should extract the fragment as the package name
+    let output = env
+        .cmd()
+        .args(["install", "github:NixOS/nixpkgs#hello"])
+        .output()
+        .unwrap();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // Should NOT go through Nixhub
+    assert!(
+        !stderr.contains("Nixhub") && !stdout.contains("Nixhub"),
+        "Flake reference with fragment should not be routed to Nixhub: stdout={}, stderr={}",
+        stdout,
+        stderr
+    );
+}
+
+#[test]
+fn test_install_plain_package_still_uses_nixhub() {
+    let env = TestEnv::new();
+
+    // Create a profile first
+    let _ = env.cmd().args(["profile", "default", "-c"]).output();
+
+    // `nixy install hello` should still go through Nixhub (the standard path)
+    let output = env.cmd().args(["install", "hello"]).output().unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // Should mention Nixhub since it's a plain package name
+    assert!(
+        stdout.contains("Nixhub") || stdout.contains("already installed"),
+        "Plain package name should be resolved via Nixhub: stdout={}",
+        stdout
+    );
+}
+
+// =============================================================================
+// Install revert on sync failure tests
+// =============================================================================
+
+#[test]
+fn test_install_reverts_flake_on_sync_failure() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+    env.nix()
+        .fail_builds("error: builder for 'hello.drv' failed");
+    let original_config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+
+    let output = env.cmd().args(["install", "hello"]).output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(!output.status.success());
+    assert!(stderr.contains("Reverted changes"), "stderr={}", stderr);
+    assert_eq!(env.nix().calls_to("build").len(), 1);
+
+    // Parse as JSON and compare to handle potential key ordering differences
+    let current_config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    let current_json: serde_json::Value = serde_json::from_str(&current_config).unwrap();
+    let original_json: serde_json::Value = serde_json::from_str(&original_config).unwrap();
+    assert_eq!(
+        current_json, original_json,
+        "Config should be reverted to original on sync failure"
+    );
+    assert!(!env.profile_flake("default").contains("hello"));
+    assert!(!env.env_path.exists());
+}
+
+// =============================================================================
+// Hermetic end-to-end tests (fake nix + recorded Nixhub responses)
 // =============================================================================
 
 #[test]
-fn test_upgrade_help_and_flags() {
-    // Single comprehensive test for upgrade help output
-    let output = nixy_cmd().args(["upgrade", "--help"]).output().unwrap();
-    assert!(output.status.success());
-    let stdout = String::from_utf8_lossy(&output.stdout);
+fn test_hermetic_install_sync_list() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+
+    let output = env.cmd().args(["install", "hello"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        stdout.contains("Found hello version 2.12.1 (commit a3a3dda3)"),
+        "stdout={}",
+        stdout
+    );
+    assert!(stdout.contains("Sync complete"), "stdout={}", stdout);
+
+    let flake = env.profile_flake("default");
+    assert!(
+        flake.contains("nixpkgs-a3a3dda3.url = \"github:NixOS/nixpkgs/a3a3dda3bacf61e8a39258a0ed9c924eeca8e293\";"),
+        "{}",
+        flake
+    );
+    assert!(
+        flake.contains("hello = inputs.nixpkgs-a3a3dda3"),
+        "{}",
+        flake
+    );
+
+    // The build went through nix and produced the environment link
+    let builds = env.nix().calls_to("build");
+    assert_eq!(builds.len(), 1, "{:?}", builds);
+    assert!(
+        builds[0].contains("profiles/default#default"),
+        "{}",
+        builds[0]
+    );
+    let built = std::fs::read_link(&env.env_path).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(built.join("flake.nix")).unwrap(),
+        flake
+    );
+
+    let output = env.cmd().args(["list", "--verify"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    assert!(stdout.contains("hello@2.12.1"), "stdout={}", stdout);
+    assert!(
+        stdout.contains("All 1 declared package(s)"),
+        "stdout={}",
+        stdout
+    );
+}
+
+#[test]
+fn test_hermetic_install_records_when_and_list_verbose_shows_it() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved("default", "ripgrep", "14.1.0", OLD_HELLO_COMMIT)
+        .write(&env.config_dir);
+
+    let output = env.cmd().args(["install", "hello"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let profile = &profiles_json(&env)["default"];
+    let hello = &profile["resolved_packages"][0];
+    assert_eq!(hello["name"], "hello");
+    assert_eq!(hello["installed_with"], env!("CARGO_PKG_VERSION"));
+    let installed_at = hello["installed_at"].as_str().unwrap().to_string();
+    assert!(installed_at.ends_with('Z'), "{}", installed_at);
+    assert!(hello.get("updated_at").is_none(), "{}", hello);
+    // Entries from before nixy recorded installs stay without a record
+    assert!(profile["resolved_packages"][1]
+        .get("installed_at")
+        .is_none());
+
+    let output = env.cmd().arg("list").output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("installed"), "stdout={}", stdout);
+
+    let output = env.cmd().args(["list", "--verbose"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}", output);
+    let hello_line = stdout.lines().find(|l| l.contains("hello@")).unwrap();
+    assert!(
+        hello_line.ends_with(&format!(
+            "installed {}, with nixy {}",
+            installed_at,
+            env!("CARGO_PKG_VERSION")
+        )),
+        "stdout={}",
+        stdout
+    );
+    let ripgrep_line = stdout.lines().find(|l| l.contains("ripgrep@")).unwrap();
+    assert!(ripgrep_line.ends_with("(nixpkgs)"), "stdout={}", stdout);
+}
+
+#[test]
+fn test_hermetic_list_verify_reconciles_with_the_flake_on_disk() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "hello")
+        .write(&env.config_dir);
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+
+    let output = env.cmd().args(["list", "--verify"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout.contains("matches nixy.json"), "stdout={}", stdout);
+    assert!(env
+        .nix()
+        .calls_to("eval")
+        .iter()
+        .any(|c| c.contains("profiles/default#packages.x86_64-linux")
+            && c.contains("builtins.attrNames")));
+
+    // A hand edit to the flake, and a package added to nixy.json without a sync
+    let flake_path = env.state_dir.join("profiles/default/flake.nix");
+    let flake = std::fs::read_to_string(&flake_path).unwrap();
+    let indent = flake
+        .lines()
+        .find(|l| l.trim_start().starts_with("hello = "))
+        .map(|l| &l[..l.len() - l.trim_start().len()])
+        .unwrap()
+        .to_string();
+    let flake = flake.replacen(
+        &format!("{}hello = ", indent),
+        &format!("{}handmade = pkgs.cowsay;\n{}hello = ", indent, indent),
+        1,
+    );
+    std::fs::write(&flake_path, flake).unwrap();
+    NixyJson::new()
+        .package("default", "hello")
+        .package("default", "jq")
+        .write(&env.config_dir);
+
+    let output = env.cmd().args(["list", "--verify"]).output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("jq: in nixy.json but not in the profile's flake.nix. Run 'nixy sync'"),
+        "stderr={}",
+        stderr
+    );
+    assert!(
+        stderr.contains("handmade: in the profile's flake.nix but not in nixy.json"),
+        "stderr={}",
+        stderr
+    );
+    assert!(
+        stderr.contains("packages/handmade.nix"),
+        "stderr={}",
+        stderr
+    );
+    assert!(!stderr.contains("hello:"), "stderr={}", stderr);
+    assert!(stderr.contains("2 difference(s)"), "stderr={}", stderr);
+}
+
+#[test]
+fn test_hermetic_install_keeps_user_blocks_in_flake() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "hello")
+        .write(&env.config_dir);
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+
+    let flake_path = env.state_dir.join("profiles/default/flake.nix");
+    let flake = env.profile_flake("default");
+    let edited = flake.replacen(
+        "default = pkgs.buildEnv",
+        "# nixy:user-begin\nmyhello = pkgs.hello.overrideAttrs (_: { doCheck = false; });\n# nixy:user-end\ndefault = pkgs.buildEnv",
+        1,
+    );
+    assert_ne!(edited, flake);
+    std::fs::write(&flake_path, &edited).unwrap();
+
+    let output = env.cmd().args(["install", "ripgrep"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let flake = env.profile_flake("default");
+    assert!(flake.contains("ripgrep"), "{}", flake);
+    assert!(
+        flake.contains("myhello = pkgs.hello.overrideAttrs (_: { doCheck = false; });"),
+        "{}",
+        flake
+    );
+
+    // Unbalanced markers stop the change instead of dropping the block
+    std::fs::write(&flake_path, flake.replace("# nixy:user-end", "")).unwrap();
+    let output = env.cmd().args(["uninstall", "ripgrep"]).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("is never closed"), "stderr={}", stderr);
+    assert!(env.profile_flake("default").contains("myhello"));
+}
+
+#[test]
+fn test_hermetic_run_resolves_and_passes_the_exit_code_on() {
+    let env = TestEnv::hermetic();
+
+    let output = env
+        .cmd()
+        .args(["run", "hello", "--", "--greeting", "hi there"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(
+            "fake run github:NixOS/nixpkgs/a3a3dda3bacf61e8a39258a0ed9c924eeca8e293#hello: --greeting hi there"
+        ),
+        "stdout={}",
+        stdout
+    );
+    // Nothing installed, nothing built
+    assert!(!env.config_dir.join("nixy.json").exists());
+    assert!(!env.state_dir.join("profiles").exists());
+    assert!(env.nix().calls_to("build").is_empty());
+
+    env.nix().run_exit(3);
+    let output = env
+        .cmd()
+        .args(["run", "tool", "--from", "github:user/repo"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("fake run github:user/repo#tool: "));
+    // The program's own failure, not nixy's
+    assert!(
+        !String::from_utf8_lossy(&output.stderr).contains("Error"),
+        "{:?}",
+        output
+    );
+
+    let output = env.cmd().args(["run", "nosuchpackage"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Package 'nosuchpackage' not found"),
+        "stderr={}",
+        stderr
+    );
+    assert_eq!(env.nix().calls_to("run").len(), 2);
+}
+
+#[test]
+fn test_hermetic_run_leaves_stdout_to_the_program() {
+    let env = TestEnv::hermetic();
+
+    let output = env
+        .cmd()
+        .args(["run", "hello", "--", "--greeting", "hi"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    // `nixy run jq -- . f.json > out.json` must get jq's output only
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "fake run github:NixOS/nixpkgs/a3a3dda3bacf61e8a39258a0ed9c924eeca8e293#hello: --greeting hi\n"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Resolving hello@latest via Nixhub..."),
+        "stderr={}",
+        stderr
+    );
+    assert!(
+        stderr.contains("Running github:NixOS/nixpkgs/"),
+        "stderr={}",
+        stderr
+    );
+}
+
+#[test]
+fn test_hermetic_shell_layers_packages_on_the_profile_and_cleans_up() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "git")
+        .write(&env.config_dir);
+    let nixy_json = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+
+    // A "shell" that saves the flake of the environment first on its PATH
+    let tmp = env._temp.path().join("tmp");
+    std::fs::create_dir_all(&tmp).unwrap();
+    let shell = env._temp.path().join("shell.sh");
+    let seen = env._temp.path().join("seen-flake.nix");
+    std::fs::write(
+        &shell,
+        format!(
+            "#!/bin/sh\ncp \"${{PATH%%:*}}/../flake.nix\" '{}'\nls '{}' > '{}.tmp'\nexit 4\n",
+            seen.display(),
+            tmp.display(),
+            seen.display()
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(&shell, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+
+    let output = env
+        .cmd()
+        .args(["shell", "hello"])
+        .env("SHELL", &shell)
+        .env("TMPDIR", &tmp)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(4), "{:?}", output);
+    // stdout is left to the shell
+    assert!(output.stdout.is_empty(), "{:?}", output);
+    let flake = std::fs::read_to_string(&seen).unwrap();
+    assert!(flake.contains("git"), "{}", flake);
+    assert!(
+        flake.contains("a3a3dda3bacf61e8a39258a0ed9c924eeca8e293"),
+        "{}",
+        flake
+    );
+    // The scratch flake existed while the shell ran, and is gone after
+    let listed = std::fs::read_to_string(env._temp.path().join("seen-flake.nix.tmp")).unwrap();
+    assert!(listed.starts_with("nixy-shell-"), "{}", listed);
+    assert_eq!(std::fs::read_dir(&tmp).unwrap().count(), 0);
+    // The profile is left as it was
+    assert_eq!(
+        std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap(),
+        nixy_json
+    );
+    assert!(!env.env_path.exists());
+
+    // Nothing is built when a package cannot be resolved
+    let output = env
+        .cmd()
+        .args(["shell", "hello", "nosuchpackage"])
+        .env("SHELL", &shell)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("nosuchpackage"));
+    assert_eq!(env.nix().calls_to("build").len(), 1);
+}
+
+#[test]
+fn test_hermetic_install_unknown_package_changes_nothing() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+
+    let output = env
+        .cmd()
+        .args(["install", "no-such-package"])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("not found"), "stderr={}", stderr);
+    assert!(env.nix().calls_to("build").is_empty());
+    let config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    assert!(!config.contains("no-such-package"));
+}
+
+const UNSTABLE_COMMIT: &str = "2222222222222222222222222222222222222222";
+
+#[test]
+fn test_hermetic_install_pin_uses_the_current_nixos_unstable() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+    env.nix()
+        .nixpkgs_branch("nixos-unstable", UNSTABLE_COMMIT)
+        .on_eval(
+            &format!(
+                "github:NixOS/nixpkgs/{}#legacyPackages.*.hello.version",
+                UNSTABLE_COMMIT
+            ),
+            "2.12.2",
+        );
+
+    let output = env
+        .cmd()
+        .args(["install", "hello", "--pin"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let hello = &profiles_json(&env)["default"]["resolved_packages"][0];
+    assert_eq!(hello["name"], "hello");
+    assert_eq!(hello["commit_hash"], UNSTABLE_COMMIT);
+    assert_eq!(hello["resolved_version"], "2.12.2");
+    assert_eq!(hello["version_spec"], serde_json::Value::Null);
+    assert_eq!(hello["pinned"], true);
+    assert!(env
+        .profile_flake("default")
+        .contains(&format!("github:NixOS/nixpkgs/{}", UNSTABLE_COMMIT)));
+
+    // update leaves it alone until it is unpinned
+    let output = env.cmd().args(["update", "hello"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(
+        stdout.contains("hello is pinned to nixpkgs 22222222"),
+        "stdout={}",
+        stdout
+    );
+    assert_eq!(
+        profiles_json(&env)["default"]["resolved_packages"][0]["commit_hash"],
+        UNSTABLE_COMMIT
+    );
+
+    let output = env
+        .cmd()
+        .args(["update", "hello", "--unpin"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let hello = &profiles_json(&env)["default"]["resolved_packages"][0];
+    assert_eq!(hello["resolved_version"], "2.12.1");
+    assert_ne!(hello["commit_hash"], UNSTABLE_COMMIT);
+    assert!(hello.get("pinned").is_none(), "{}", hello);
+}
+
+#[test]
+fn test_hermetic_update_package_already_at_latest_skips_rebuild() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved(
+            "default",
+            "hello",
+            "2.12.1",
+            "a3a3dda3bacf61e8a39258a0ed9c924eeca8e293",
+        )
+        .write(&env.config_dir);
+    let nixy_json = std::fs::read(env.config_dir.join("nixy.json")).unwrap();
+
+    let output = env.cmd().args(["update", "hello"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(
+        stdout.contains("Already up to date: hello"),
+        "stdout={}",
+        stdout
+    );
+    assert!(env.nix().calls_to("build").is_empty());
+    assert_eq!(
+        std::fs::read(env.config_dir.join("nixy.json")).unwrap(),
+        nixy_json
+    );
+}
+
+#[test]
+fn test_hermetic_install_pin_at_version_survives_update() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+
+    let output = env
+        .cmd()
+        .args(["install", "nodejs@20", "--pin"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let before = profiles_json(&env)["default"]["resolved_packages"][0].clone();
+    assert_eq!(before["version_spec"], "20");
+    assert_eq!(before["pinned"], true);
+
+    let output = env.cmd().args(["list", "--no-pager"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("(nixpkgs, pinned)"), "stdout={}", stdout);
+    let output = env.cmd().args(["list", "--json"]).output().unwrap();
+    let document: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(document["packages"][0]["pinned"], true);
+
+    // Pinned a while ago, before Nixhub moved on to a newer nodejs 20
+    let latest = before["commit_hash"].as_str().unwrap().to_string();
+    let nixy_json = env.config_dir.join("nixy.json");
+    let old = std::fs::read_to_string(&nixy_json)
+        .unwrap()
+        .replace(&latest, OLD_HELLO_COMMIT);
+    std::fs::write(&nixy_json, old).unwrap();
+    let before = profiles_json(&env)["default"]["resolved_packages"][0].clone();
+
+    let output = env.cmd().args(["update", "--all"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout.contains("--include-pinned"), "stdout={}", stdout);
+    assert_eq!(
+        profiles_json(&env)["default"]["resolved_packages"][0],
+        before
+    );
+
+    let output = env
+        .cmd()
+        .args(["update", "--all", "--include-pinned"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let after = &profiles_json(&env)["default"]["resolved_packages"][0];
+    assert_eq!(after["commit_hash"], latest.as_str());
+    assert_eq!(after["pinned"], true);
+}
+
+#[test]
+fn test_hermetic_install_several_packages_builds_once() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+
+    let output = env
+        .cmd()
+        .args(["install", "hello", "ripgrep", "nodejs@20"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let names: Vec<_> = profiles_json(&env)["default"]["resolved_packages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["name"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(names, ["hello", "nodejs", "ripgrep"]);
+    assert_eq!(
+        profiles_json(&env)["default"]["resolved_packages"][1]["version_spec"],
+        "20"
+    );
+    assert_eq!(env.nix().calls_to("build").len(), 1);
+}
+
+#[test]
+fn test_hermetic_install_several_packages_skips_unknown_ones() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+
+    let output = env
+        .cmd()
+        .args(["install", "hello", "no-such-tool", "ripgrep", "nodejs@99"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr
+            .contains("Package(s) not found: no-such-tool, nodejs@99. The others were installed."),
+        "stderr={}",
+        stderr
+    );
+    let names: Vec<_> = profiles_json(&env)["default"]["resolved_packages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["name"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(names, ["hello", "ripgrep"]);
+    assert_eq!(env.nix().calls_to("build").len(), 1);
+}
+
+#[test]
+fn test_hermetic_install_several_packages_atomic_is_all_or_nothing() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+    let before = std::fs::read(env.config_dir.join("nixy.json")).unwrap();
+
+    // Unknown names abort before anything is written or built
+    let output = env
+        .cmd()
+        .args([
+            "install",
+            "hello",
+            "no-such-tool",
+            "ripgrep",
+            "nodejs@99",
+            "--atomic",
+        ])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("Package(s) not found: no-such-tool, nodejs@99."),
+        "stderr={}",
+        stderr
+    );
+    assert_eq!(
+        std::fs::read(env.config_dir.join("nixy.json")).unwrap(),
+        before
+    );
+    assert!(env.nix().calls_to("build").is_empty());
+
+    // A failed build reverts the whole batch
+    env.nix().fail_builds("error: builder failed");
+    let output = env
+        .cmd()
+        .args(["install", "hello", "ripgrep"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_eq!(
+        profiles_json(&env)["default"]["resolved_packages"],
+        serde_json::json!([])
+    );
+    assert!(!env.profile_flake("default").contains("ripgrep"));
+}
+
+#[test]
+fn test_install_several_packages_argument_checks() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+
+    for args in [
+        vec!["install", "hello", "github:owner/tool"],
+        vec!["install", "hello", "ripgrep", "--as", "tool"],
+        vec!["install", "hello", "nodejs@20", "--pin"],
+    ] {
+        let output = env.cmd().args(&args).output().unwrap();
+        assert!(!output.status.success(), "{:?}", args);
+    }
+    assert!(env.nix().calls_to("build").is_empty());
+}
+
+#[test]
+fn test_hermetic_install_output_as_a_separate_entry() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "hello")
+        .write(&env.config_dir);
+
+    let output = env
+        .cmd()
+        .args(["install", "hello", "--output", "man"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let entry = &profiles_json(&env)["default"]["resolved_packages"][0];
+    assert_eq!(entry["name"], "hello-man");
+    assert_eq!(entry["source_name"], "hello");
+    assert_eq!(entry["output"], "man");
+    let flake = env.profile_flake("default");
+    assert!(flake.contains("hello = pkgs.hello;"), "{}", flake);
+    assert!(
+        flake.contains("hello-man = inputs.nixpkgs-a3a3dda3.legacyPackages.${system}.hello.man;"),
+        "{}",
+        flake
+    );
+
+    let output = env
+        .cmd()
+        .args([
+            "install",
+            "hello",
+            "--output",
+            "info",
+            "--as",
+            "hello-manual",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(env
+        .profile_flake("default")
+        .contains("hello-manual = inputs.nixpkgs-a3a3dda3.legacyPackages.${system}.hello.info;"));
+
+    // Updates resolve the package the entry stands for, and keep its name
+    let output = env.cmd().args(["update", "hello-man"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(
+        stdout.contains("hello-man is already at the latest version"),
+        "stdout={}",
+        stdout
+    );
+}
+
+#[test]
+fn test_hermetic_install_outputs_per_package() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "ripgrep")
+        .write(&env.config_dir);
+
+    let output = env
+        .cmd()
+        .args([
+            "install",
+            "hello",
+            "--outputs",
+            "out,man",
+            "--outputs",
+            "info",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let entry = &profiles_json(&env)["default"]["resolved_packages"][0];
+    assert_eq!(entry["name"], "hello");
+    assert_eq!(entry["outputs"], serde_json::json!(["out", "man", "info"]));
+    assert!(entry.get("output").is_none(), "{}", entry);
+    let flake = env.profile_flake("default");
+    assert!(
+        flake.contains(
+            "hello = let p = inputs.nixpkgs-a3a3dda3.legacyPackages.${system}.hello; \
+             in p // { meta = (p.meta or { }) // { outputsToInstall = [ \"out\" \"man\" \"info\" ]; }; };"
+        ),
+        "{}",
+        flake
+    );
+    assert!(flake.contains("ripgrep = pkgs.ripgrep;"), "{}", flake);
+    assert_eq!(env.nix().calls_to("build").len(), 1);
+
+    // Updates keep the outputs
+    let output = env.cmd().args(["update", "hello"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let entry = &profiles_json(&env)["default"]["resolved_packages"][0];
+    assert_eq!(entry["outputs"], serde_json::json!(["out", "man", "info"]));
+}
+
+#[test]
+fn test_hermetic_install_two_versions_side_by_side() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+
+    for (spec, alias) in [("nodejs@18", "node18"), ("nodejs@20", "node20")] {
+        let output = env
+            .cmd()
+            .args(["install", spec, "--as", alias])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}: {:?}", spec, output);
+    }
+    let entries = &profiles_json(&env)["default"]["resolved_packages"];
+    assert_eq!(entries[0]["name"], "node18");
+    assert_eq!(entries[0]["source_name"], "nodejs");
+    assert_eq!(entries[0]["attribute_path"], "nodejs_18");
+    assert_eq!(entries[1]["name"], "node20");
+    assert_eq!(entries[1]["attribute_path"], "nodejs_20");
+    let flake = env.profile_flake("default");
+    assert!(
+        flake.contains("node18 = inputs.nixpkgs-4633a7c7.legacyPackages.${system}.nodejs_18;"),
+        "{}",
+        flake
+    );
+    assert!(
+        flake.contains("node20 = inputs.nixpkgs-de186421.legacyPackages.${system}.nodejs_20;"),
+        "{}",
+        flake
+    );
+
+    let output = env.cmd().args(["list", "--json"]).output().unwrap();
+    let list: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let packages = list["packages"].as_array().unwrap();
+    assert_eq!(packages[0]["name"], "node18");
+    assert_eq!(packages[0]["source_name"], "nodejs");
+    assert_eq!(packages[1]["name"], "node20");
+    let output = env.cmd().arg("list").output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("node18@18.20.5  (nixpkgs nodejs)"),
+        "stdout={}",
+        stdout
+    );
+
+    let output = env.cmd().args(["uninstall", "node18"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let entries = &profiles_json(&env)["default"]["resolved_packages"];
+    assert_eq!(entries.as_array().unwrap().len(), 1);
+    assert_eq!(entries[0]["name"], "node20");
+    let flake = env.profile_flake("default");
+    assert!(!flake.contains("node18"), "{}", flake);
+    assert!(flake.contains("node20 = "), "{}", flake);
+}
+
+#[test]
+fn test_install_pin_and_output_argument_checks() {
+    let env = TestEnv::new();
+    NixyJson::new().write(&env.config_dir);
+
+    for args in [
+        vec!["update", "--all", "--include-pinned", "--unpin"],
+        vec!["install", "github:owner/mytool", "--pin"],
+        vec!["update", "--all", "--unpin"],
+        vec!["update", "--unpin"],
+        vec!["install", "github:owner/mytool", "--output", "doc"],
+        vec!["install", "hello", "--as", "1st"],
+        vec!["install", "hello", "--output", "doc.x"],
+        vec!["install", "github:owner/mytool", "--outputs", "out,dev"],
+        vec!["install", "hello", "--outputs", "out,\"dev\""],
+        vec!["install", "hello", "--output", "man", "--outputs", "out"],
+        vec!["install", "hello", "--rev", OLD_HELLO_COMMIT],
+        vec!["install", "github:owner/mytool", "--rev", "abc123"],
+        vec![
+            "install",
+            "github:owner/mytool/main",
+            "--rev",
+            OLD_HELLO_COMMIT,
+        ],
+    ] {
+        let output = env.cmd().args(&args).output().unwrap();
+        assert!(!output.status.success(), "{:?} should be rejected", args);
+    }
+}
+
+#[test]
+fn test_hermetic_install_and_uninstall_dry_run() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "ripgrep")
+        .write(&env.config_dir);
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    let nixy_json = std::fs::read(env.config_dir.join("nixy.json")).unwrap();
+    let flake = env.profile_flake("default");
+    let builds = env.nix().calls_to("build").len();
+    // Would the build run, it would fail
+    env.nix().fail_builds("error: should not build");
+
+    let output = env
+        .cmd()
+        .args(["install", "hello", "--dry-run"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(
+        stdout.contains("Dry run: no changes written"),
+        "stdout={}",
+        stdout
+    );
+    assert!(
+        stdout
+            .lines()
+            .any(|l| l.starts_with('+') && l.contains("\"resolved_version\": \"2.12.1\"")),
+        "stdout={}",
+        stdout
+    );
+    assert!(
+        stdout
+            .lines()
+            .any(|l| l.starts_with('+') && l.contains("a3a3dda3")),
+        "stdout={}",
+        stdout
+    );
+
+    let output = env
+        .cmd()
+        .args(["uninstall", "ripgrep", "--dry-run"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(
+        stdout.contains("Dry run: no changes written"),
+        "stdout={}",
+        stdout
+    );
+    assert!(
+        stdout
+            .lines()
+            .any(|l| l.starts_with('-') && l.contains("ripgrep")),
+        "stdout={}",
+        stdout
+    );
+
+    assert_eq!(
+        std::fs::read(env.config_dir.join("nixy.json")).unwrap(),
+        nixy_json
+    );
+    assert_eq!(env.profile_flake("default"), flake);
+    assert_eq!(env.nix().calls_to("build").len(), builds);
+}
+
+#[test]
+fn test_hermetic_uninstall() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved(
+            "default",
+            "hello",
+            "2.12.1",
+            "a3a3dda3bacf61e8a39258a0ed9c924eeca8e293",
+        )
+        .resolved(
+            "default",
+            "ripgrep",
+            "14.1.1",
+            "5633bcff0c6162b9e4b5f1264264611e950c8ec7",
+        )
+        .write(&env.config_dir);
+
+    let output = env.cmd().args(["uninstall", "ripgrep"]).output().unwrap();
+    assert!(
+        output.status.success(),
+        "stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let flake = env.profile_flake("default");
+    assert!(flake.contains("hello"), "{}", flake);
+    assert!(!flake.contains("ripgrep"), "{}", flake);
+    assert!(!flake.contains("nixpkgs-5633bcff"), "{}", flake);
+    let config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    assert!(!config.contains("ripgrep"));
+    assert_eq!(env.nix().calls_to("build").len(), 1);
+    assert!(env.env_path.is_symlink());
+}
+
+#[test]
+fn test_hermetic_install_offline_reuses_an_earlier_resolution() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved(
+            "default",
+            "hello",
+            "2.12.1",
+            "a3a3dda3bacf61e8a39258a0ed9c924eeca8e293",
+        )
+        .write(&env.config_dir);
+    // The build records a generation, which still has hello once removed
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    assert!(env
+        .cmd()
+        .args(["uninstall", "hello"])
+        .output()
+        .unwrap()
+        .status
+        .success());
+    assert!(!env.profile_flake("default").contains("hello"));
+
+    // Nothing answers on Nixhub
+    let offline = |args: &[&str]| {
+        env.cmd()
+            .args(args)
+            .env("NIXY_NIXHUB_URL", "http://127.0.0.1:9")
+            .output()
+            .unwrap()
+    };
+    let output = offline(&["install", "hello", "--offline"]);
+    assert!(
+        output.status.success(),
+        "stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Reusing hello version 2.12.1 (commit a3a3dda3)"),
+        "stdout={}",
+        stdout
+    );
+    let flake = env.profile_flake("default");
+    assert!(flake.contains("nixpkgs-a3a3dda3"), "{}", flake);
+    assert!(flake.contains("hello"), "{}", flake);
+
+    let output = offline(&["install", "ripgrep", "--offline"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("'ripgrep' has not been resolved before") && stderr.contains("network"),
+        "stderr={}",
+        stderr
+    );
+}
+
+#[test]
+fn test_hermetic_profile_channel_sets_base_nixpkgs() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "hello")
+        .profile("work")
+        .package("work", "hello")
+        .write(&env.config_dir);
+
+    // Another profile: only its flake changes, built on the next switch
+    let output = env
+        .cmd()
+        .args(["profile", "work", "--channel", "nixos-24.05"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let flake = env.profile_flake("work");
+    assert!(
+        flake.contains("nixpkgs.url = \"github:NixOS/nixpkgs/nixos-24.05\";"),
+        "{}",
+        flake
+    );
+    let config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    assert!(
+        config.contains("\"nixpkgs_url\": \"github:NixOS/nixpkgs/nixos-24.05\""),
+        "{}",
+        config
+    );
+    assert!(env.nix().calls_to("build").is_empty());
+
+    // The active profile is rebuilt on the new channel
+    let output = env
+        .cmd()
+        .args([
+            "profile",
+            "default",
+            "--channel",
+            "github:me/nixpkgs/stable",
+        ])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(env
+        .profile_flake("default")
+        .contains("nixpkgs.url = \"github:me/nixpkgs/stable\";"));
+    assert_eq!(env.nix().calls_to("build").len(), 1);
+
+    // An empty channel goes back to nixos-unstable
+    let output = env
+        .cmd()
+        .args(["profile", "work", "--channel", ""])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(env
+        .profile_flake("work")
+        .contains("nixpkgs.url = \"github:NixOS/nixpkgs/nixos-unstable\";"));
+    let config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    assert_eq!(config.matches("nixpkgs_url").count(), 1, "{}", config);
+}
+
+#[test]
+fn test_hermetic_profile_outputs_sets_extra_outputs() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "hello")
+        .profile("work")
+        .package("work", "hello")
+        .write(&env.config_dir);
+
+    // Another profile: only its flake changes, built on the next switch
+    let output = env
+        .cmd()
+        .args(["profile", "work", "--outputs", "man, info"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let flake = env.profile_flake("work");
+    assert!(
+        flake.contains("extraOutputsToInstall = [ \"man\" \"info\" ];"),
+        "{}",
+        flake
+    );
+    assert!(env.nix().calls_to("build").is_empty());
+
+    // The active profile is rebuilt; an empty list links no extra outputs
+    let output = env
+        .cmd()
+        .args(["profile", "default", "--outputs", ""])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let flake = env.profile_flake("default");
+    assert!(flake.contains("extraOutputsToInstall = [ ];"), "{}", flake);
+    assert_eq!(env.nix().calls_to("build").len(), 1);
+    let config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    assert!(config.contains("\"extra_outputs\": []"), "{}", config);
+
+    // "default" drops the field again
+    let output = env
+        .cmd()
+        .args(["profile", "work", "--outputs", "default"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(env
+        .profile_flake("work")
+        .contains("extraOutputsToInstall = [ \"man\" \"doc\" \"info\" \"dev\" ];"));
+    let config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    assert_eq!(config.matches("extra_outputs").count(), 1, "{}", config);
+
+    let output = env
+        .cmd()
+        .args(["profile", "work", "--outputs", "man;rm"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_hermetic_uninstall_several_skips_missing_names() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "fd")
+        .package("default", "jq")
+        .resolved(
+            "default",
+            "hello",
+            "2.12.1",
+            "a3a3dda3bacf61e8a39258a0ed9c924eeca8e293",
+        )
+        .write(&env.config_dir);
+
+    let output = env
+        .cmd()
+        .args(["uninstall", "hello", "no-such-tool", "fd"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr={}", stderr);
+    assert!(
+        stderr.contains("Package 'no-such-tool' is not installed; skipping"),
+        "stderr={}",
+        stderr
+    );
+    let profile = &profiles_json(&env)["default"];
+    assert_eq!(profile["packages"], serde_json::json!(["jq"]));
+    assert_eq!(profile["resolved_packages"], serde_json::json!([]));
+    assert_eq!(env.nix().calls_to("build").len(), 1);
+
+    // Only when nothing matched does it fail
+    let output = env
+        .cmd()
+        .args(["uninstall", "fd", "no-such-tool"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("None of the packages is installed: fd, no-such-tool"),
+        "stderr={}",
+        stderr
+    );
+    assert_eq!(env.nix().calls_to("build").len(), 1);
+}
+
+/// Make the state directory a git repository with an initial commit
+fn git_init(env: &TestEnv) {
+    git_init_dir(&env.state_dir);
+}
+
+/// Make `dir` a git repository with an initial commit
+fn git_init_dir(dir: &std::path::Path) {
+    std::fs::create_dir_all(dir).unwrap();
+    for args in [
+        vec!["init", "-q"],
+        vec!["config", "user.name", "nixy test"],
+        vec!["config", "user.email", "test@example.com"],
+        vec!["commit", "-q", "--allow-empty", "-m", "init"],
+    ] {
+        let status = std::process::Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(&args)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?}", args);
+    }
+}
+
+fn git_log(env: &TestEnv) -> Vec<String> {
+    git_log_dir(&env.state_dir)
+}
+
+fn git_log_dir(dir: &std::path::Path) -> Vec<String> {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["log", "--format=%s"])
+        .output()
+        .unwrap();
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn test_hermetic_auto_commit_install_and_uninstall() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .global("auto_commit", serde_json::json!(true))
+        .write(&env.config_dir);
+    git_init(&env);
+
+    let output = env.cmd().args(["install", "hello"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(git_log(&env), vec!["nixy: install hello", "init"]);
+
+    // Only the flake files are committed
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(&env.state_dir)
+        .args(["show", "--name-only", "--format=", "HEAD"])
+        .output()
+        .unwrap();
+    let files = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        files.lines().collect::<Vec<_>>(),
+        vec!["profiles/default/flake.lock", "profiles/default/flake.nix"]
+    );
+
+    // Reinstalling changes nothing, so nothing is committed
+    assert!(env
+        .cmd()
+        .args(["install", "hello"])
+        .output()
+        .unwrap()
+        .status
+        .success());
+    assert_eq!(git_log(&env).len(), 2);
+
+    let output = env
+        .cmd()
+        .args(["uninstall", "hello", "--commit-message", "drop hello"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(git_log(&env)[0], "drop hello");
+}
+
+#[test]
+fn test_hermetic_quiet_and_verbose() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+
+    let output = env
+        .cmd()
+        .args(["--quiet", "install", "hello"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(output.stdout.is_empty(), "{:?}", output);
+
+    // Warnings still get through
+    std::fs::remove_file(&env.env_path).unwrap();
+    std::os::unix::fs::symlink(env.state_dir.join("gone"), &env.env_path).unwrap();
+    let output = env.cmd().args(["sync", "-q"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(output.stdout.is_empty(), "{:?}", output);
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Warning:"),
+        "{:?}",
+        output
+    );
+
+    let output = env.cmd().args(["sync", "--verbose"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.lines().any(|line| line.starts_with("+ nix ")
+            && line.contains(" build ")
+            && line.contains("profiles/default#default")),
+        "stderr={}",
+        stderr
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Sync complete"));
+
+    let output = env.cmd().args(["sync", "-q", "-v"]).output().unwrap();
+    assert!(!output.status.success(), "{:?}", output);
+}
+
+#[test]
+fn test_hermetic_quiet_dry_run_still_reports() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "ripgrep")
+        .write(&env.config_dir);
+
+    // --quiet hides progress, not the result of a dry run
+    for args in [
+        &["-q", "install", "hello", "--dry-run"][..],
+        &["-q", "uninstall", "ripgrep", "--dry-run"],
+    ] {
+        let output = env.cmd().args(args).output().unwrap();
+        assert!(output.status.success(), "{:?}: {:?}", args, output);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(!stdout.contains("Resolving"), "{:?}: {}", args, stdout);
+        for line in [
+            "==> nixy.json:",
+            "==> flake.nix:",
+            "==> Dry run: no changes written",
+        ] {
+            assert!(stdout.contains(line), "{:?}: {}", args, stdout);
+        }
+    }
+
+    let output = env
+        .cmd()
+        .args(["-q", "sync", "--dry-run"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for line in [
+        "==> flake.nix:",
+        "==> Packages:",
+        "==> Dry run: no changes written",
+    ] {
+        assert!(stdout.contains(line), "{}", stdout);
+    }
+}
+
+#[test]
+fn test_hermetic_install_git_commit() {
+    let env = TestEnv::hermetic();
+    // Configuration kept in the same repository as the state
+    let config_dir = env.state_dir.join("config");
+    NixyJson::new().write(&config_dir);
+    std::fs::create_dir_all(config_dir.join("packages")).unwrap();
+    std::fs::write(
+        config_dir.join("packages/mytool.nix"),
+        "{ pkgs }: pkgs.hello\n",
+    )
+    .unwrap();
+    git_init(&env);
+
+    let output = env
+        .cmd()
+        .args(["install", "hello", "--git-commit"])
+        .env("NIXY_CONFIG_DIR", &config_dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(git_log(&env), vec!["nixy: install hello", "init"]);
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(&env.state_dir)
+        .args(["show", "--name-only", "--format=", "HEAD"])
+        .output()
+        .unwrap();
+    let files = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        files.lines().collect::<Vec<_>>(),
+        vec![
+            "config/nixy.json",
+            "config/packages/mytool.nix",
+            "profiles/default/flake.lock",
+            "profiles/default/flake.nix"
+        ]
+    );
+
+    // Nothing changed, nothing to commit
+    let output = env
+        .cmd()
+        .args(["install", "hello", "--git-commit"])
+        .env("NIXY_CONFIG_DIR", &config_dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("commit"));
+    assert_eq!(git_log(&env).len(), 2);
+}
+
+#[test]
+fn test_hermetic_auto_commit_config_in_its_own_repository() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .global("auto_commit", serde_json::json!(true))
+        .write(&env.config_dir);
+    git_init_dir(&env.config_dir);
+    // Staged by hand, and not nixy's to commit
+    std::fs::write(env.config_dir.join("notes.txt"), "todo\n").unwrap();
+    let status = std::process::Command::new("git")
+        .arg("-C")
+        .arg(&env.config_dir)
+        .args(["add", "notes.txt"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let files_in_head = |dir: &std::path::Path| {
+        let output = std::process::Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["show", "--name-only", "--format=", "HEAD"])
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+
+    let output = env.cmd().args(["install", "hello"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        git_log_dir(&env.config_dir),
+        vec!["nixy: install hello", "init"]
+    );
+    assert_eq!(files_in_head(&env.config_dir), vec!["nixy.json"]);
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(&env.config_dir)
+        .args(["diff", "--cached", "--name-only"])
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "notes.txt");
+
+    // A new local package definition is recorded by the sync that builds it
+    std::fs::create_dir_all(env.config_dir.join("packages")).unwrap();
+    std::fs::write(
+        env.config_dir.join("packages/mine.nix"),
+        r#"{ stdenv }: stdenv.mkDerivation { pname = "mine"; version = "1.0"; src = ./.; }"#,
+    )
+    .unwrap();
+    let output = env.cmd().arg("sync").output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(git_log_dir(&env.config_dir)[0], "nixy: sync");
+    assert_eq!(
+        files_in_head(&env.config_dir),
+        vec!["nixy.json", "packages/mine.nix"]
+    );
+
+    let output = env.cmd().args(["uninstall", "hello"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(git_log_dir(&env.config_dir)[0], "nixy: uninstall hello");
+    assert_eq!(git_log_dir(&env.config_dir).len(), 4);
+}
+
+#[test]
+fn test_hermetic_auto_commit_off_or_without_git() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+    git_init(&env);
+
+    // Disabled by default
+    assert!(env
+        .cmd()
+        .args(["install", "hello"])
+        .output()
+        .unwrap()
+        .status
+        .success());
+    assert_eq!(git_log(&env), vec!["init"]);
+
+    // Not a git repository: the flag is ignored without complaint
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+    let output = env
+        .cmd()
+        .args(["install", "hello", "--commit-message", "add hello"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("commit"));
+}
+
+#[test]
+fn test_hermetic_update_upgrades_resolved_package() {
+    let env = TestEnv::hermetic();
+    // nodejs@20 was resolved to an older release; Nixhub now has 20.18.1
+    let config = NixyJson::new().to_json().replace(
+        r#""resolved_packages": []"#,
+        r#""resolved_packages": [{
+          "name": "nodejs",
+          "version_spec": "20",
+          "resolved_version": "20.11.0",
+          "attribute_path": "nodejs_20",
+          "commit_hash": "0123456789abcdef0123456789abcdef01234567"
+        }]"#,
+    );
+    std::fs::create_dir_all(&env.config_dir).unwrap();
+    std::fs::write(env.config_dir.join("nixy.json"), config).unwrap();
+
+    let output = env.cmd().args(["update", "nodejs"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stdout={}, stderr={}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        stdout.contains("20.11.0 -> 20.18.1 (commit de186421)"),
+        "stdout={}",
+        stdout
+    );
+
+    let config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    assert!(
+        config.contains("\"resolved_version\": \"20.18.1\""),
+        "{}",
+        config
+    );
+    // The version constraint survives the upgrade
+    assert!(config.contains("\"version_spec\": \"20\""), "{}", config);
+    let flake = env.profile_flake("default");
+    assert!(flake.contains("nixpkgs-de186421"), "{}", flake);
+    assert!(!flake.contains("nixpkgs-01234567"), "{}", flake);
+    assert!(env.env_path.is_symlink());
+}
+
+#[test]
+fn test_hermetic_update_one_package_leaves_the_others_alone() {
+    let env = TestEnv::hermetic();
+    let mut config: serde_json::Value = serde_json::from_str(
+        &NixyJson::new()
+            .resolved("default", "hello", "2.12.0", OLD_HELLO_COMMIT)
+            .resolved(
+                "default",
+                "nodejs",
+                "20.11.0",
+                "0123456789abcdef0123456789abcdef01234567",
+            )
+            .to_json(),
+    )
+    .unwrap();
+    config["profiles"]["default"]["resolved_packages"][1]["version_spec"] = "20".into();
+    std::fs::create_dir_all(&env.config_dir).unwrap();
+    std::fs::write(
+        env.config_dir.join("nixy.json"),
+        serde_json::to_string_pretty(&config).unwrap(),
+    )
+    .unwrap();
+    let output = env.cmd().arg("sync").output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let output = env.cmd().args(["update", "nodejs"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let config: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap())
+            .unwrap();
+    let resolved = &config["profiles"]["default"]["resolved_packages"];
+    assert_eq!(resolved[0]["resolved_version"], "2.12.0");
+    assert_eq!(resolved[0]["commit_hash"], OLD_HELLO_COMMIT);
+    assert_eq!(resolved[1]["resolved_version"], "20.18.1");
+    let flake = env.profile_flake("default");
+    assert!(flake.contains("nixpkgs-11111111"), "{}", flake);
+    assert!(flake.contains("nixpkgs-de186421"), "{}", flake);
+    assert!(!flake.contains("nixpkgs-01234567"), "{}", flake);
+    // Only the new input gets locked; nothing else is re-locked
+    assert!(env.nix().calls_to("update").is_empty());
+}
+
+#[test]
+fn test_hermetic_migration_then_sync() {
+    let env = TestEnv::hermetic();
+    LegacyTree::new()
+        .profile("default", &["hello"])
+        .profile("work", &["ripgrep", "jq"])
+        .active("work")
+        .write(&env.config_dir);
+
+    let output = env.cmd().arg("sync").output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stdout={}, stderr={}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("Migration complete"), "stdout={}", stdout);
+
+    let config: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap())
+            .unwrap();
+    assert_eq!(config["active_profile"], "work");
+    assert_eq!(
+        config["profiles"]["work"]["packages"],
+        serde_json::json!(["ripgrep", "jq"])
+    );
+    assert_eq!(
+        config["profiles"]["default"]["packages"],
+        serde_json::json!(["hello"])
+    );
+
+    // Only the active profile is built
+    let flake = env.profile_flake("work");
+    assert!(
+        flake.contains("ripgrep") && flake.contains("jq"),
+        "{}",
+        flake
+    );
+    assert!(!flake.contains("hello"), "{}", flake);
+    let builds = env.nix().calls_to("build");
+    assert_eq!(builds.len(), 1, "{:?}", builds);
+    assert!(builds[0].contains("profiles/work#default"), "{}", builds[0]);
+}
+
+/// nixy.json with floating packages in two profiles and no state directory,
+/// as after restoring only the config from a backup
+fn restored_config(env: &TestEnv, active: &str) {
+    NixyJson::new()
+        .package("default", "hello")
+        .profile("work")
+        .package("work", "jq")
+        .active(active)
+        .write(&env.config_dir);
+}
+
+fn write_state_lock(env: &TestEnv, profile: &str, fixture: &str) {
+    let dir = env.state_dir.join("profiles").join(profile);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::copy(
+        support::fixtures_dir().join("locks").join(fixture),
+        dir.join("flake.lock"),
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_hermetic_switch_without_lock_warns_and_rebuilds_flake() {
+    let env = TestEnv::hermetic();
+    restored_config(&env, "default");
+
+    let output = env.cmd().args(["profile", "work"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr={}", stderr);
+    assert!(
+        stderr.contains("Profile 'work' has no flake.lock: 1 floating package(s)"),
+        "stderr={}",
+        stderr
+    );
+    assert!(
+        stdout.contains("nixy profile work --copy-lock-from <profile>"),
+        "stdout={}",
+        stdout
+    );
+
+    // The flake comes back from nixy.json instead of "no packages installed"
+    assert!(env.profile_flake("work").contains("jq"));
+    assert_eq!(env.nix().calls_to("build").len(), 1);
+}
+
+#[test]
+fn test_hermetic_switch_copies_lock_from_profile() {
+    let env = TestEnv::hermetic();
+    restored_config(&env, "default");
+    write_state_lock(&env, "default", "unstable.json");
+
+    let output = env
+        .cmd()
+        .args(["profile", "work", "--copy-lock-from", "default"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr={}", stderr);
+    assert!(
+        stdout.contains("Seeded flake.lock from profile 'default'"),
+        "stdout={}",
+        stdout
+    );
+    assert!(!stderr.contains("no flake.lock"), "stderr={}", stderr);
+
+    let lock_path = |p: &str| env.state_dir.join("profiles").join(p).join("flake.lock");
+    assert_eq!(
+        std::fs::read_to_string(lock_path("work")).unwrap(),
+        std::fs::read_to_string(lock_path("default")).unwrap()
+    );
+}
+
+#[test]
+fn test_hermetic_sync_refuses_lock_from_other_channel() {
+    let env = TestEnv::hermetic();
+    restored_config(&env, "work");
+    write_state_lock(&env, "default", "stable.json");
+
+    let output = env
+        .cmd()
+        .args(["sync", "--copy-lock-from", "default"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("channels differ"), "stderr={}", stderr);
+    assert!(!env.state_dir.join("profiles/work/flake.lock").exists());
+    assert!(env.nix().calls_to("build").is_empty());
+}
+
+#[test]
+fn test_hermetic_sync_copy_lock_from_refuses_existing_lock() {
+    let env = TestEnv::hermetic();
+    restored_config(&env, "work");
+    write_state_lock(&env, "default", "unstable.json");
+    write_state_lock(&env, "work", "unstable.json");
+
+    let output = env
+        .cmd()
+        .args(["sync", "--copy-lock-from", "default"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already has a flake.lock"));
+}
+
+#[test]
+fn test_doctor_reports_profiles_without_lock() {
+    let env = TestEnv::new();
+    restored_config(&env, "default");
+    write_state_lock(&env, "default", "unstable.json");
+
+    let (_, results) = doctor_json(&env);
+    assert_eq!(doctor_status(&results, "locks"), "warn");
+    let detail = results
+        .iter()
+        .find(|r| r["check"] == "locks")
+        .and_then(|r| r["detail"].as_str())
+        .unwrap();
+    assert!(
+        detail.contains("never built / no lock: work"),
+        "detail={}",
+        detail
+    );
+}
+
+#[test]
+fn test_hermetic_install_flake_without_fragment_uses_default_package() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+    env.nix().on_eval(
+        "github:owner/mytool#packages.*.default.name",
+        "\"mytool-1.0\"",
+    );
+
+    let output = env
+        .cmd()
+        .args(["install", "github:owner/mytool"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Named after the repository, installed from its `default` output
+    let config: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap())
+            .unwrap();
+    let custom = &config["profiles"]["default"]["custom_packages"][0];
+    assert_eq!(custom["name"], "mytool");
+    assert_eq!(custom["source_name"], "default");
+    assert!(
+        env.profile_flake("default")
+            .contains(".packages.${system}.default;"),
+        "{}",
+        env.profile_flake("default")
+    );
+}
+
+#[test]
+fn test_hermetic_install_flake_keeps_ref_and_dir_query() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+    let url = "github:owner/repo?ref=main&dir=subprojects/tool";
+    env.nix()
+        .on_eval(&format!("{}#packages.*.tool.name", url), "\"tool-1.0\"");
+
+    let output = env
+        .cmd()
+        .args(["install", &format!("{}#tool", url)])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let custom = custom_package(&env);
+    assert_eq!(custom["name"], "tool");
+    assert_eq!(custom["input_url"], url);
+    let flake = env.profile_flake("default");
+    assert!(flake.contains(&format!("url = \"{}\";", url)), "{}", flake);
+}
+
+#[test]
+fn test_hermetic_install_flake_without_default_lists_packages() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+    env.nix()
+        .on_eval("github:owner/multi#packages.x86_64-linux", "cli\nserver");
+
+    let output = env
+        .cmd()
+        .args(["install", "github:owner/multi"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("Available packages: cli, server"),
+        "stderr={}",
+        stderr
+    );
+    assert!(
+        stderr.contains("use github:owner/multi#<package>"),
+        "stderr={}",
+        stderr
+    );
+    assert!(env.nix().calls_to("build").is_empty());
+}
+
+fn custom_package(env: &TestEnv) -> serde_json::Value {
+    let config: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap())
+            .unwrap();
+    config["profiles"]["default"]["custom_packages"][0].clone()
+}
+
+#[test]
+fn test_hermetic_install_flake_validates_with_name_eval() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+    env.nix()
+        .on_eval("github:me/big#legacyPackages.*.tool.name", "\"tool-1.0\"");
+
+    let output = env
+        .cmd()
+        .args(["install", "github:me/big#tool"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let custom = custom_package(&env);
+    assert_eq!(custom["package_output"], "legacyPackages");
+    assert!(custom.get("unvalidated").is_none(), "{}", custom);
+    assert!(env
+        .nix()
+        .calls_to("eval")
+        .iter()
+        .any(|c| c.contains("--json github:me/big#legacyPackages.x86_64-linux.tool.name")));
+}
+
+#[test]
+fn test_hermetic_install_flake_no_validate_skips_eval() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+
+    let output = env
+        .cmd()
+        .args(["install", "github:me/big#tool", "--no-validate"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    // Only the system is looked up; the flake is never evaluated
+    assert!(env
+        .nix()
+        .calls_to("eval")
+        .iter()
+        .all(|c| !c.contains("github:me/big")));
+    let custom = custom_package(&env);
+    assert_eq!(custom["unvalidated"], true);
+    assert!(
+        env.profile_flake("default").contains(
+            "tool = inputs.github-me-big.packages.${system}.tool or inputs.github-me-big.legacyPackages.${system}.tool;"
+        ),
+        "{}",
+        env.profile_flake("default")
+    );
+}
+
+#[test]
+fn test_hermetic_install_flake_at_a_rev_stays_there() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+
+    let output = env
+        .cmd()
+        .args(["install", "github:me/big#tool", "--no-validate"])
+        .args(["--rev", OLD_HELLO_COMMIT])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let custom = custom_package(&env);
+    let url = format!("github:me/big?rev={}", OLD_HELLO_COMMIT);
+    assert_eq!(custom["input_url"], url.as_str());
+    // The rev is not part of the input's name
+    assert_eq!(custom["input_name"], "github-me-big");
+    let flake = env.profile_flake("default");
+    assert!(
+        flake.contains(&format!("github-me-big.url = \"{}\";", url)),
+        "{}",
+        flake
+    );
+
+    let output = env.cmd().args(["update", "tool"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(
+        stdout.contains(&format!(
+            "tool was installed at commit {} (--rev); skipping",
+            &OLD_HELLO_COMMIT[..8]
+        )),
+        "stdout={}",
+        stdout
+    );
+    assert!(env.nix().calls_to("flake").is_empty());
+    assert_eq!(custom_package(&env)["input_url"], url.as_str());
+}
+
+#[test]
+fn test_hermetic_install_no_validate_needs_a_flake() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+
+    let output = env
+        .cmd()
+        .args(["install", "hello", "--no-validate"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("only applies to packages from a flake")
+    );
+}
+
+#[test]
+fn test_hermetic_install_flake_validation_timeout_falls_back() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+    env.nix()
+        .on_eval("github:me/big#packages.*.tool.name", "\"tool-1.0\"")
+        .slow_evals(30);
+
+    let start = std::time::Instant::now();
+    let output = env
+        .cmd()
+        .args(["--timeout", "1", "install", "github:me/big#tool"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr={}", stderr);
+    assert!(start.elapsed() < std::time::Duration::from_secs(20));
+    assert!(stderr.contains("took longer than 1s"), "stderr={}", stderr);
+    assert_eq!(custom_package(&env)["unvalidated"], true);
+    assert_eq!(env.nix().calls_to("build").len(), 1);
+}
+
+#[test]
+fn test_hermetic_unvalidated_build_failure_names_the_package() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+    env.nix().fail_builds(
+        "error:\n       … while evaluating the attribute 'tool'\n       error: attribute 'tool' missing\n",
+    );
+
+    let output = env
+        .cmd()
+        .args(["install", "github:me/big#tool", "--no-validate"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("attribute 'tool' missing, likely from 'tool' (github:me/big), installed without validation"),
+        "stderr={}",
+        stderr
+    );
+    // The failed install was rolled back
+    assert!(custom_package(&env).is_null());
+}
+
+#[test]
+fn test_hermetic_doctor_rechecks_unvalidated_packages() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+    let output = env
+        .cmd()
+        .args(["install", "github:me/big#tool", "--no-validate"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    // The fake has no answer for it yet: not found
+    let (_, results) = doctor_json(&env);
+    assert_eq!(doctor_status(&results, "unvalidated"), "fail");
+
+    env.nix()
+        .on_eval("github:me/big#packages.*.tool.name", "\"tool-1.0\"");
+    let (_, results) = doctor_json(&env);
+    assert_eq!(doctor_status(&results, "unvalidated"), "ok");
+}
+
+fn write_needs_sync(env: &TestEnv, profile: &str, reason: &str) {
+    let dir = env.state_dir.join("profiles").join(profile);
+    std::fs::create_dir_all(&dir).unwrap();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    std::fs::write(
+        dir.join("needs-sync"),
+        format!("{}\n{}\n", reason, now - 7200),
+    )
+    .unwrap();
+}
+
+/// Every command that rewrites another profile's flake without building it
+/// leaves a needs-sync marker, which the next switch builds and clears
+#[test]
+fn test_hermetic_inactive_profile_writers_mark_needs_sync() {
+    let exported = tempfile::NamedTempFile::new().unwrap();
+    let export = exported.path().to_str().unwrap();
+    let cases: [(&[&str], &str, &str); 5] = [
+        (
+            &["profile", "work", "--channel", "nixos-24.05"],
+            "work",
+            "nixy profile --channel",
+        ),
+        (
+            &["profile", "work", "--outputs", "man"],
+            "work",
+            "nixy profile --outputs",
+        ),
+        (
+            &["update", "--all", "--profile", "work"],
+            "work",
+            "nixy update",
+        ),
+        (
+            &["profile", "copy", "--copy", "work"],
+            "copy",
+            "nixy profile copy --copy work",
+        ),
+        (
+            &["import", "--profile", "imported", export],
+            "imported",
+            "nixy import",
+        ),
+    ];
+
+    for (args, target, reason) in cases {
+        let env = TestEnv::hermetic();
+        NixyJson::new()
+            .package("default", "hello")
+            .profile("work")
+            .resolved("work", "hello", "2.12.0", OLD_HELLO_COMMIT)
+            .write(&env.config_dir);
+        assert!(env.cmd().arg("sync").output().unwrap().status.success());
+        let output = env
+            .cmd()
+            .args(["export", "--output", export])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        let builds = env.nix().calls_to("build").len();
+
+        let output = env.cmd().args(args).output().unwrap();
+        assert!(
+            output.status.success(),
+            "{:?}: stderr={}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(!env.profile_flake(target).is_empty(), "{:?}", args);
+        assert_eq!(env.nix().calls_to("build").len(), builds, "{:?}", args);
+        let marker = env
+            .state_dir
+            .join("profiles")
+            .join(target)
+            .join("needs-sync");
+        let content = std::fs::read_to_string(&marker)
+            .unwrap_or_else(|e| panic!("{:?} left no marker: {}", args, e));
+        assert_eq!(content.lines().next(), Some(reason), "{:?}", args);
+
+        let output = env.cmd().args(["profile", target]).output().unwrap();
+        assert!(output.status.success(), "{:?}: {:?}", args, output);
+        assert_eq!(env.nix().calls_to("build").len(), builds + 1, "{:?}", args);
+        assert!(!marker.exists(), "{:?}", args);
+    }
+}
+
+#[test]
+fn test_hermetic_update_inactive_profile_shows_out_of_date() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .profile("work")
+        .package("work", "jq")
+        .write(&env.config_dir);
+
+    let output = env
+        .cmd()
+        .args(["update", "--all", "--profile", "work"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = env.cmd().arg("profile").output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("    work - environment out of date (nixy update, just now)"),
+        "stdout={}",
+        stdout
+    );
+    assert!(
+        stdout.contains("  * default (active)\n"),
+        "stdout={}",
+        stdout
+    );
+}
+
+#[test]
+fn test_hermetic_update_inactive_profile_leaves_active_one_alone() {
+    for args in [
+        vec!["update", "hello", "--profile", "work"],
+        vec!["update", "--all", "--profile", "work"],
+    ] {
+        let env = TestEnv::hermetic();
+        NixyJson::new()
+            .resolved("default", "hello", "2.12.0", OLD_HELLO_COMMIT)
+            .profile("work")
+            .resolved("work", "hello", "2.12.0", OLD_HELLO_COMMIT)
+            .write(&env.config_dir);
+
+        let output = env.cmd().args(&args).output().unwrap();
+        assert!(
+            output.status.success(),
+            "{:?}: stderr={}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let profiles = profiles_json(&env);
+        let work = &profiles["work"]["resolved_packages"][0];
+        assert_eq!(work["resolved_version"], "2.12.1", "{:?}", args);
+        assert_ne!(work["commit_hash"], OLD_HELLO_COMMIT, "{:?}", args);
+        let default = &profiles["default"]["resolved_packages"][0];
+        assert_eq!(default["resolved_version"], "2.12.0", "{:?}", args);
+        assert_eq!(default["commit_hash"], OLD_HELLO_COMMIT, "{:?}", args);
+        assert!(env.nix().calls_to("build").is_empty(), "{:?}", args);
+    }
+}
+
+#[test]
+fn test_list_warns_when_active_profile_is_out_of_date() {
+    let env = TestEnv::new();
+    NixyJson::new()
+        .package("default", "hello")
+        .write(&env.config_dir);
+    write_needs_sync(&env, "default", "nixy update");
+
+    let output = env.cmd().arg("list").output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr={}", stderr);
+    assert!(
+        stderr.contains(
+            "Profile 'default': environment out of date (nixy update, 2 hours ago). Run 'nixy sync' to rebuild."
+        ),
+        "stderr={}",
+        stderr
+    );
+}
+
+#[test]
+fn test_hermetic_switch_to_out_of_date_profile_mentions_and_clears_it() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .profile("work")
+        .package("work", "jq")
+        .write(&env.config_dir);
+    // Build once so the switch has a flake and lock to rebuild from
+    assert!(env
+        .cmd()
+        .args(["profile", "work"])
+        .output()
+        .unwrap()
+        .status
+        .success());
+    assert!(env
+        .cmd()
+        .args(["profile", "default"])
+        .output()
+        .unwrap()
+        .status
+        .success());
+    write_needs_sync(&env, "work", "nixy update");
+
+    let output = env.cmd().args(["profile", "work"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    assert!(
+        stdout.contains("Profile 'work' changed since it was last built: environment out of date (nixy update, 2 hours ago)"),
+        "stdout={}",
+        stdout
+    );
+    assert!(!env.state_dir.join("profiles/work/needs-sync").exists());
+
+    let (_, results) = doctor_json(&env);
+    assert_eq!(doctor_status(&results, "sync"), "ok");
+}
+
+#[test]
+fn test_hermetic_failed_build_on_switch_keeps_the_active_profile() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "hello")
+        .profile("work")
+        .package("work", "jq")
+        .write(&env.config_dir);
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    let before = std::fs::read_link(&env.env_path).unwrap();
+    env.nix().fail_builds("error: builder failed");
+
+    let output = env.cmd().args(["profile", "work"]).output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("Build failed; still on profile 'default'"),
+        "stderr={}",
+        stderr
+    );
+    let config: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap())
+            .unwrap();
+    assert_eq!(config["active_profile"], "default");
+    assert_eq!(std::fs::read_link(&env.env_path).unwrap(), before);
+    assert!(env.state_dir.join("profiles/work/needs-sync").exists());
+
+    env.nix().pass_builds();
+    assert!(env
+        .cmd()
+        .args(["profile", "work"])
+        .output()
+        .unwrap()
+        .status
+        .success());
+    assert!(!env.state_dir.join("profiles/work/needs-sync").exists());
+    assert_ne!(std::fs::read_link(&env.env_path).unwrap(), before);
+}
+
+#[test]
+fn test_hermetic_profile_activate_only_reuses_last_build() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "hello")
+        .profile("work")
+        .package("work", "jq")
+        .write(&env.config_dir);
+
+    assert!(env
+        .cmd()
+        .args(["profile", "work"])
+        .output()
+        .unwrap()
+        .status
+        .success());
+    let work_build = std::fs::read_link(&env.env_path).unwrap();
+    assert!(env
+        .cmd()
+        .args(["profile", "default"])
+        .output()
+        .unwrap()
+        .status
+        .success());
+    assert_ne!(std::fs::read_link(&env.env_path).unwrap(), work_build);
+
+    let output = env
+        .cmd()
+        .args(["profile", "work", "--activate-only"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    assert_eq!(std::fs::read_link(&env.env_path).unwrap(), work_build);
+    assert_eq!(env.nix().calls_to("build").len(), 2);
+    let config: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap())
+            .unwrap();
+    assert_eq!(config["active_profile"], "work");
+}
+
+#[test]
+fn test_hermetic_sync_builds_into_a_gc_root() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "hello")
+        .write(&env.config_dir);
 
-    // Verify specific upgrade content
+    let output = env.cmd().arg("sync").output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    // nix registers the --out-link as a GC root; the env link only follows it
+    let build_link = env.state_dir.join("builds/default");
+    let builds = env.nix().calls_to("build");
+    assert_eq!(builds.len(), 1);
     assert!(
-        stdout.contains("nixy") || stdout.contains("latest"),
-        "Help should mention upgrading nixy: {}",
+        builds[0].contains(&format!("--out-link {}", build_link.display())),
+        "{:?}",
+        builds
+    );
+    assert_eq!(
+        std::fs::read_link(&env.env_path).unwrap(),
+        std::fs::read_link(&build_link).unwrap()
+    );
+}
+
+#[test]
+fn test_hermetic_failed_build_keeps_the_previous_env() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "hello")
+        .write(&env.config_dir);
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    let before = std::fs::read_link(&env.env_path).unwrap();
+
+    env.nix().fail_builds("error: build failed\n");
+    assert!(!env.cmd().arg("sync").output().unwrap().status.success());
+    assert_eq!(std::fs::read_link(&env.env_path).unwrap(), before);
+    assert_eq!(
+        std::fs::read_link(env.state_dir.join("builds/default")).unwrap(),
+        before
+    );
+}
+
+#[test]
+fn test_hermetic_sync_prune_drops_unused_nixpkgs_inputs() {
+    let env = TestEnv::hermetic();
+    let other_commit = "0123456789abcdef0123456789abcdef01234567";
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", OLD_HELLO_COMMIT)
+        .resolved("default", "git", "2.44.0", other_commit)
+        .resolved("default", "nodejs", "20.11.0", other_commit)
+        .write(&env.config_dir);
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    let lock = env.state_dir.join("profiles/default/flake.lock");
+    assert!(std::fs::read_to_string(&lock)
+        .unwrap()
+        .contains("nixpkgs-01234567"));
+
+    // Removing one of two packages on a commit keeps the shared input
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", OLD_HELLO_COMMIT)
+        .resolved("default", "nodejs", "20.11.0", other_commit)
+        .write(&env.config_dir);
+    let output = env.cmd().arg("sync").output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Dropped"));
+    assert!(env.profile_flake("default").contains("nixpkgs-01234567"));
+
+    // nixy.json drifted from flake.nix: the last package on the commit is gone
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", OLD_HELLO_COMMIT)
+        .write(&env.config_dir);
+    let output = env.cmd().args(["--frozen", "sync"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(
+        stdout.contains("Dropped nixpkgs input(s) no package uses anymore: nixpkgs-01234567"),
+        "stdout={}",
         stdout
     );
+    assert!(
+        stderr.contains(
+            "flake.lock still locks input(s) flake.nix no longer declares: nixpkgs-01234567"
+        ),
+        "stderr={}",
+        stderr
+    );
+    assert!(!env.profile_flake("default").contains("nixpkgs-01234567"));
 
-    // Verify --force flag is documented
+    let output = env.cmd().args(["sync", "--prune"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}", output);
     assert!(
-        stdout.contains("--force"),
-        "Help should show --force flag: {}",
+        stdout.contains("Pruned stale input(s) from flake.lock: nixpkgs-01234567"),
+        "stdout={}",
         stdout
     );
+    let locked = std::fs::read_to_string(&lock).unwrap();
+    assert!(!locked.contains("nixpkgs-01234567"), "{}", locked);
+    assert!(locked.contains("nixpkgs-11111111"), "{}", locked);
+}
 
-    // Verify -f short flag is documented
+#[test]
+fn test_hermetic_sync_dry_run_reports_drift() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", OLD_HELLO_COMMIT)
+        .write(&env.config_dir);
+    let flake_path = env.state_dir.join("profiles/default/flake.nix");
+
+    // Never synced: the whole flake would be new
+    let output = env.cmd().args(["sync", "--dry-run"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    assert!(stdout.contains("+++ b/flake.nix"), "stdout={}", stdout);
+    assert!(stdout.contains("  +hello"), "stdout={}", stdout);
+    assert!(!flake_path.exists());
+
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    let output = env.cmd().args(["sync", "--dry-run"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("is up to date"));
+
+    let synced = std::fs::read_to_string(&flake_path).unwrap();
+    NixyJson::new()
+        .resolved(
+            "default",
+            "hello",
+            "2.12.1",
+            "a3a3dda3bacf61e8a39258a0ed9c924eeca8e293",
+        )
+        .package("default", "ripgrep")
+        .write(&env.config_dir);
+    let output = env.cmd().args(["sync", "--dry-run"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    assert!(stdout.contains("@@ -"), "stdout={}", stdout);
     assert!(
-        stdout.contains("-f"),
-        "Help should show -f short flag: {}",
+        stdout
+            .lines()
+            .any(|l| l.starts_with('-') && l.contains("nixpkgs-11111111")),
+        "stdout={}",
         stdout
     );
+    assert!(stdout.contains("  ~hello"), "stdout={}", stdout);
+    assert!(stdout.contains("  +ripgrep"), "stdout={}", stdout);
+    assert!(stderr.contains("2 difference(s)"), "stderr={}", stderr);
+    assert_eq!(std::fs::read_to_string(&flake_path).unwrap(), synced);
+    assert_eq!(env.nix().calls_to("build").len(), 1);
 }
 
-// =============================================================================
-// List command tests (additional)
-// =============================================================================
+#[test]
+fn test_hermetic_frozen_sync_needs_an_existing_lock() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "hello")
+        .write(&env.config_dir);
+    let lock = env.state_dir.join("profiles/default/flake.lock");
+
+    let output = env.cmd().args(["--frozen", "sync"]).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("needs changes"), "stderr={}", stderr);
+    assert!(!lock.exists(), "--frozen must not write a flake.lock");
+    assert!(!env.env_path.exists());
+
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    let locked = std::fs::read(&lock).unwrap();
+    let output = env.cmd().args(["sync", "--frozen"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let builds = env.nix().calls_to("build");
+    assert!(builds[2].contains("--no-update-lock-file"), "{:?}", builds);
+    assert_eq!(std::fs::read(&lock).unwrap(), locked);
+}
 
 #[test]
-fn test_list_shows_none_for_empty_flake() {
+fn test_hermetic_frozen_refuses_lock_updates() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "hello")
+        .write(&env.config_dir);
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+
+    let output = env
+        .cmd()
+        .env("NIXY_FROZEN", "1")
+        .args(["update", "--all"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("nixy update is not allowed with --frozen"),
+        "stderr={}",
+        stderr
+    );
+    assert!(env.nix().calls_to("update").is_empty());
+
+    let output = env.cmd().args(["--frozen", "upgrade"]).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not allowed with --frozen"));
+
+    // NIXY_FROZEN=0 leaves it off
+    let output = env
+        .cmd()
+        .env("NIXY_FROZEN", "0")
+        .args(["update", "--all"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+}
+
+#[test]
+fn test_hermetic_offline_passes_offline_to_nix() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "hello")
+        .package("default", "ripgrep")
+        .write(&env.config_dir);
+
+    let output = env.cmd().args(["--offline", "sync"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let output = env
+        .cmd()
+        .args(["uninstall", "ripgrep", "--offline"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let output = env.cmd().args(["list", "--offline"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let builds = env.nix().calls_to("build");
+    assert_eq!(builds.len(), 2, "{:?}", builds);
+    assert!(
+        builds.iter().all(|b| b.contains("--offline")),
+        "{:?}",
+        builds
+    );
+
+    // Without the flag, nix may download again
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    assert!(!env.nix().calls_to("build")[2].contains("--offline"));
+}
+
+#[test]
+fn test_hermetic_offline_install_reuses_a_cached_nixhub_answer() {
+    let env = TestEnv::hermetic();
+    NixyJson::new().write(&env.config_dir);
+
+    // A dry run resolves (and caches) without installing anything
+    let output = env
+        .cmd()
+        .args(["install", "hello", "--dry-run"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    // Nothing answers on Nixhub
+    let offline = |spec: &str| {
+        env.cmd()
+            .args(["--offline", "install", spec])
+            .env("NIXY_NIXHUB_URL", "http://127.0.0.1:9")
+            .output()
+            .unwrap()
+    };
+    let output = offline("hello");
+    assert!(output.status.success(), "{:?}", output);
+    assert!(env.profile_flake("default").contains("hello"));
+
+    let output = offline("ripgrep");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("has not been resolved before") && stderr.contains("--offline"),
+        "stderr={}",
+        stderr
+    );
+}
+
+#[test]
+fn test_hermetic_offline_refuses_update_and_upgrade() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "hello")
+        .write(&env.config_dir);
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+
+    let output = env
+        .cmd()
+        .args(["--offline", "update", "--all"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("nixy update needs network access, which --offline forbids"),
+        "stderr={}",
+        stderr
+    );
+    assert!(env.nix().calls_to("update").is_empty());
+
+    let output = env.cmd().args(["upgrade", "--offline"]).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("which --offline forbids"));
+}
+
+#[test]
+fn test_hermetic_frozen_install_of_a_new_input_rolls_back() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "hello")
+        .write(&env.config_dir);
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    env.nix().on_eval(
+        "github:owner/mytool#packages.*.default.name",
+        "\"mytool-1.0\"",
+    );
+
+    let output = env
+        .cmd()
+        .args(["--frozen", "install", "github:owner/mytool"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("needs changes"), "stderr={}", stderr);
+    assert_eq!(
+        profiles_json(&env)["default"]["custom_packages"],
+        serde_json::json!([])
+    );
+    assert!(!env.profile_flake("default").contains("mytool"));
+}
+
+#[test]
+fn test_hermetic_profile_activate_only_never_built() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "hello")
+        .profile("work")
+        .package("work", "jq")
+        .write(&env.config_dir);
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    let default_build = std::fs::read_link(&env.env_path).unwrap();
+
+    let output = env
+        .cmd()
+        .args(["profile", "work", "--activate-only"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr={}", stderr);
+    assert!(stderr.contains("has no build yet"), "stderr={}", stderr);
+    assert!(stderr.contains("Run 'nixy sync'"), "stderr={}", stderr);
+
+    // Nothing was built or regenerated; the old environment stays in place
+    assert_eq!(env.nix().calls_to("build").len(), 1);
+    assert_eq!(std::fs::read_link(&env.env_path).unwrap(), default_build);
+    assert!(!env.state_dir.join("profiles/work/flake.nix").exists());
+    let (_, results) = doctor_json(&env);
+    assert_eq!(doctor_status(&results, "sync"), "warn");
+
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    assert!(env.profile_flake("work").contains("jq"));
+    assert!(!env.state_dir.join("profiles/work/needs-sync").exists());
+}
+
+#[test]
+fn test_profile_activate_only_argument_checks() {
     let env = TestEnv::new();
+    NixyJson::new().profile("work").write(&env.config_dir);
+
+    for args in [
+        vec!["profile", "--activate-only"],
+        vec!["profile", "work", "-c", "--activate-only"],
+        vec!["profile", "work", "-d", "--activate-only"],
+    ] {
+        let output = env.cmd().args(&args).output().unwrap();
+        assert!(!output.status.success(), "{:?} should be rejected", args);
+    }
 
-    // Create a profile (empty flake)
-    let _ = env.cmd().args(["profile", "default", "-c"]).output();
+    let output = env
+        .cmd()
+        .args(["profile", "missing", "--activate-only"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("does not exist"));
+}
 
-    let output = env.cmd().arg("list").output().unwrap();
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let stdout_lower = stdout.to_lowercase();
+// =============================================================================
+// Pre-update snapshot tests
+// =============================================================================
 
-    // Command should succeed for an empty flake
+const OLD_HELLO_COMMIT: &str = "1111111111111111111111111111111111111111";
+
+/// Directories under state/snapshots, oldest first
+fn snapshot_dirs(env: &TestEnv) -> Vec<std::path::PathBuf> {
+    let Ok(entries) = std::fs::read_dir(env.state_dir.join("snapshots")) else {
+        return Vec::new();
+    };
+    let mut dirs: Vec<_> = entries.map(|e| e.unwrap().path()).collect();
+    dirs.sort();
+    dirs
+}
+
+fn profiles_json(env: &TestEnv) -> serde_json::Value {
+    let config: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap())
+            .unwrap();
+    config["profiles"].clone()
+}
+
+#[test]
+fn test_hermetic_update_failure_keeps_pre_update_snapshot() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", OLD_HELLO_COMMIT)
+        .write(&env.config_dir);
+    write_state_lock(&env, "default", "unstable.json");
+    env.nix().fail_builds("error: builder failed");
+
+    let output = env.cmd().args(["update", "--all"]).output().unwrap();
+    assert!(!output.status.success());
+
+    let dirs = snapshot_dirs(&env);
+    assert_eq!(dirs.len(), 1);
+    let profile = std::fs::read_to_string(dirs[0].join("default/profile.json")).unwrap();
     assert!(
-        output.status.success(),
-        "List should succeed for empty flake: stderr={}",
-        String::from_utf8_lossy(&output.stderr)
+        profile.contains(OLD_HELLO_COMMIT),
+        "profile.json={}",
+        profile
+    );
+    assert_eq!(
+        std::fs::read(dirs[0].join("default/flake.lock")).unwrap(),
+        std::fs::read(support::fixtures_dir().join("locks/unstable.json")).unwrap()
     );
+    let meta = std::fs::read_to_string(dirs[0].join("snapshot.json")).unwrap();
+    assert!(meta.contains("nixy update --all"), "snapshot.json={}", meta);
+}
 
-    // Should show indication of empty/no packages
+#[test]
+fn test_hermetic_update_dry_run_changes_nothing() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", OLD_HELLO_COMMIT)
+        .write(&env.config_dir);
+    write_state_lock(&env, "default", "unstable.json");
+    env.nix()
+        .lock_rev("nixpkgs", "3333333333333333333333333333333333333333");
+    let config_before = std::fs::read(env.config_dir.join("nixy.json")).unwrap();
+    let lock_path = env.state_dir.join("profiles/default/flake.lock");
+    let lock_before = std::fs::read(&lock_path).unwrap();
+
+    let output = env
+        .cmd()
+        .args(["update", "--all", "--dry-run"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout.contains("2.12.0 -> 2.12.1"), "stdout={}", stdout);
     assert!(
-        stdout.contains("(none)")
-            || stdout_lower.contains("no packages")
-            || stdout_lower.contains("empty")
-            || stdout.contains("Packages in"),
-        "Should indicate empty package list: {}",
+        stdout.contains("nixpkgs: 9f4128e0 -> 33333333"),
+        "stdout={}",
         stdout
     );
+    assert!(stdout.contains("mytool: removed"), "stdout={}", stdout);
+    assert!(stdout.contains("nothing was changed"), "stdout={}", stdout);
+
+    assert_eq!(
+        std::fs::read(env.config_dir.join("nixy.json")).unwrap(),
+        config_before
+    );
+    assert_eq!(std::fs::read(&lock_path).unwrap(), lock_before);
+    assert!(!env.state_dir.join("profiles/default/flake.nix").exists());
+    assert!(snapshot_dirs(&env).is_empty());
+    assert!(env.nix().calls_to("build").is_empty());
 }
 
 #[test]
-fn test_list_shows_installed_packages() {
+fn test_update_dry_run_rejects_all_profiles() {
     let env = TestEnv::new();
+    let output = env
+        .cmd()
+        .args(["update", "--all-profiles", "--all", "--dry-run"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
 
-    // Create a profile directory with packages.json and flake.nix
-    let profile_dir = env.config_dir.join("profiles/default");
-    std::fs::create_dir_all(&profile_dir).unwrap();
+#[test]
+fn test_hermetic_undo_update_restores_files_byte_for_byte() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", OLD_HELLO_COMMIT)
+        .write(&env.config_dir);
+    write_state_lock(&env, "default", "unstable.json");
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+
+    let state = env.state_dir.join("profiles/default");
+    let flake_before = std::fs::read(state.join("flake.nix")).unwrap();
+    let lock_before = std::fs::read(state.join("flake.lock")).unwrap();
+    let profiles_before = profiles_json(&env);
+
+    assert!(env
+        .cmd()
+        .args(["update", "--all"])
+        .output()
+        .unwrap()
+        .status
+        .success());
+    assert_ne!(
+        std::fs::read(state.join("flake.nix")).unwrap(),
+        flake_before
+    );
+    assert_ne!(
+        std::fs::read(state.join("flake.lock")).unwrap(),
+        lock_before
+    );
 
-    // Create packages.json with installed packages (new state-based format)
-    let state_content = r#"{
-  "version": 1,
-  "packages": ["bat", "fzf", "ripgrep"],
-  "custom_packages": []
-}"#;
-    std::fs::write(profile_dir.join("packages.json"), state_content).unwrap();
+    let output = env.cmd().arg("undo-update").output().unwrap();
+    assert!(
+        output.status.success(),
+        "stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        std::fs::read(state.join("flake.nix")).unwrap(),
+        flake_before
+    );
+    assert_eq!(
+        std::fs::read(state.join("flake.lock")).unwrap(),
+        lock_before
+    );
+    assert_eq!(profiles_json(&env), profiles_before);
+    // sync, update and the undo each built once
+    assert_eq!(env.nix().calls_to("build").len(), 3);
 
-    // Write a flake.nix with packages (no markers - new format)
-    let flake_content = r#"{
-  description = "nixy managed packages";
+    // The snapshot is used up
+    let output = env.cmd().arg("undo-update").output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No update to undo"));
+}
 
-  inputs = {
-    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
-  };
+#[test]
+fn test_hermetic_undo_update_refuses_after_later_changes() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", OLD_HELLO_COMMIT)
+        .write(&env.config_dir);
 
-  outputs = { self, nixpkgs }@inputs:
-    let
-      systems = [ "x86_64-linux" "aarch64-linux" "x86_64-darwin" "aarch64-darwin" ];
-      forAllSystems = f: nixpkgs.lib.genAttrs systems (system: f system);
-    in {
-      packages = forAllSystems (system:
-        let pkgs = nixpkgs.legacyPackages.${system};
-        in rec {
-          bat = pkgs.bat;
-          fzf = pkgs.fzf;
-          ripgrep = pkgs.ripgrep;
+    assert!(env
+        .cmd()
+        .args(["update", "hello"])
+        .output()
+        .unwrap()
+        .status
+        .success());
+    assert!(env
+        .cmd()
+        .args(["install", "ripgrep"])
+        .output()
+        .unwrap()
+        .status
+        .success());
 
-          default = pkgs.buildEnv {
-            name = "nixy-env";
-            paths = [
-              bat
-              fzf
-              ripgrep
-            ];
-            extraOutputsToInstall = [ "man" "doc" "info" ];
-          };
-        });
-    };
+    let output = env.cmd().arg("undo-update").output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("changed after 'nixy update hello'"),
+        "stderr={}",
+        stderr
+    );
+    assert!(stderr.contains("--force"), "stderr={}", stderr);
+
+    let output = env
+        .cmd()
+        .args(["undo-upgrade", "--force"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let profiles = profiles_json(&env).to_string();
+    assert!(profiles.contains(OLD_HELLO_COMMIT));
+    assert!(!profiles.contains("ripgrep"));
 }
-"#;
-    std::fs::write(profile_dir.join("flake.nix"), flake_content).unwrap();
 
-    // Set active profile
-    std::fs::write(env.config_dir.join("active"), "default").unwrap();
+// =============================================================================
+// Unreachable nixpkgs commit tests
+// =============================================================================
 
-    let output = env.cmd().arg("list").output().unwrap();
-    let stdout = String::from_utf8_lossy(&output.stdout);
+const GONE_COMMIT: &str = "0badc0de0123456789abcdef0123456789abcdef";
 
-    // Command should succeed
+fn gone_commit_build_log() -> String {
+    format!(
+        "error:\n       … while fetching the input 'github:NixOS/nixpkgs/{c}'\n\n       error: unable to download 'https://github.com/NixOS/nixpkgs/archive/{c}.tar.gz': HTTP error 404\n",
+        c = GONE_COMMIT
+    )
+}
+
+#[test]
+fn test_hermetic_sync_names_packages_on_gone_commit() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", GONE_COMMIT)
+        .resolved(
+            "default",
+            "jq",
+            "1.7",
+            "a3a3dda3bacf61e8a39258a0ed9c924eeca8e293",
+        )
+        .write(&env.config_dir);
+    env.nix().fail_builds(&gone_commit_build_log());
+
+    let output = env.cmd().arg("sync").output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(!output.status.success());
     assert!(
-        output.status.success(),
-        "List should succeed: stderr={}",
-        String::from_utf8_lossy(&output.stderr)
+        stderr.contains(&format!(
+            "nixpkgs commit(s) {} can no longer be downloaded from GitHub (pinned by hello)",
+            GONE_COMMIT
+        )),
+        "stderr={}",
+        stderr
+    );
+    assert!(
+        stderr.contains(&format!("nixy update --commit {}", GONE_COMMIT)),
+        "stderr={}",
+        stderr
     );
+}
 
-    // Should show the installed packages
+#[test]
+fn test_hermetic_other_build_failures_are_not_blamed_on_commits() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", GONE_COMMIT)
+        .write(&env.config_dir);
+    env.nix()
+        .fail_builds("error: builder for '/nix/store/x-hello.drv' failed with exit code 1");
+
+    let output = env.cmd().arg("sync").output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(!output.status.success());
+    // The error itself carries nix's message, not only the relayed log
     assert!(
-        stdout.contains("ripgrep"),
-        "Should show ripgrep: {}",
-        stdout
+        stderr.contains(
+            "Failed to build environment: error: builder for '/nix/store/x-hello.drv' failed"
+        ),
+        "stderr={}",
+        stderr
     );
-    assert!(stdout.contains("fzf"), "Should show fzf: {}", stdout);
-    assert!(stdout.contains("bat"), "Should show bat: {}", stdout);
+    assert!(
+        !stderr.contains("nixy update --commit"),
+        "stderr={}",
+        stderr
+    );
+}
 
-    // Should NOT show "(none)"
+#[test]
+fn test_hermetic_rollback_restores_previous_environment() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "ripgrep")
+        .write(&env.config_dir);
+    let output = env.cmd().arg("sync").output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let first = std::fs::read_link(&env.env_path).unwrap();
+
+    let output = env.cmd().args(["install", "hello"]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_ne!(std::fs::read_link(&env.env_path).unwrap(), first);
+
+    let output = env.cmd().args(["rollback", "--list"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout.contains("nixy sync"), "stdout={}", stdout);
     assert!(
-        !stdout.contains("(none)"),
-        "Should not show (none) when packages exist: {}",
+        stdout.contains("nixy install hello  (current)"),
+        "stdout={}",
         stdout
     );
 
-    // Should show source info
+    let output = env.cmd().arg("rollback").output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(std::fs::read_link(&env.env_path).unwrap(), first);
+    assert_eq!(
+        std::fs::read_link(env.state_dir.join("builds/default")).unwrap(),
+        first
+    );
+    assert_eq!(
+        profiles_json(&env)["default"]["resolved_packages"],
+        serde_json::json!([])
+    );
+    assert!(!env.profile_flake("default").contains("hello"));
+
+    // The install is gone from the history; there is nothing before the sync
+    let output = env.cmd().arg("rollback").output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
     assert!(
-        stdout.contains("(nixpkgs)"),
-        "Should show (nixpkgs) for standard packages: {}",
-        stdout
+        stderr.contains("No earlier environment"),
+        "stderr={}",
+        stderr
     );
 }
 
 #[test]
-fn test_list_shows_source_info() {
-    let env = TestEnv::new();
-
-    // Create a profile directory with packages.json
-    let profile_dir = env.config_dir.join("profiles/default");
-    std::fs::create_dir_all(&profile_dir).unwrap();
+fn test_hermetic_rollback_fails_when_previous_build_was_collected() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "ripgrep")
+        .write(&env.config_dir);
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    let first = std::fs::read_link(&env.env_path).unwrap();
+    assert!(env
+        .cmd()
+        .args(["install", "hello"])
+        .output()
+        .unwrap()
+        .status
+        .success());
+    let second = std::fs::read_link(&env.env_path).unwrap();
+    let config_before = std::fs::read(env.config_dir.join("nixy.json")).unwrap();
 
-    // Create packages.json with both standard and custom packages
-    let state_content = r#"{
-  "version": 1,
-  "packages": ["ripgrep"],
-  "custom_packages": [
-    {
-      "name": "neovim",
-      "input_name": "neovim-nightly",
-      "input_url": "github:nix-community/neovim-nightly-overlay",
-      "package_output": "packages",
-      "source_name": null
-    }
-  ]
-}"#;
-    std::fs::write(profile_dir.join("packages.json"), state_content).unwrap();
+    // nix-collect-garbage removed the older build
+    std::fs::remove_dir_all(&first).unwrap();
 
-    // Set active profile
-    std::fs::write(env.config_dir.join("active"), "default").unwrap();
+    let output = env.cmd().arg("rollback").output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("garbage-collected"), "stderr={}", stderr);
+    assert!(stderr.contains("nixy sync"), "stderr={}", stderr);
+    assert_eq!(std::fs::read_link(&env.env_path).unwrap(), second);
+    assert_eq!(
+        std::fs::read(env.config_dir.join("nixy.json")).unwrap(),
+        config_before
+    );
+}
 
-    let output = env.cmd().arg("list").output().unwrap();
+#[test]
+fn test_hermetic_rollback_skips_collected_generations() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "ripgrep")
+        .write(&env.config_dir);
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    let first = std::fs::read_link(&env.env_path).unwrap();
+    for package in ["hello", "nodejs@20"] {
+        let output = env.cmd().args(["install", package]).output().unwrap();
+        assert!(output.status.success(), "{:?}", output);
+    }
+    let output = env.cmd().args(["rollback", "--list"]).output().unwrap();
     let stdout = String::from_utf8_lossy(&output.stdout);
+    let store_paths: Vec<_> = stdout
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with('/'))
+        .collect();
+    assert_eq!(store_paths.len(), 3, "stdout={}", stdout);
+    assert_eq!(std::path::Path::new(store_paths[0]), first);
+    let second = std::path::PathBuf::from(store_paths[1]);
+
+    // nix-collect-garbage removed the build of `nixy install hello`
+    std::fs::remove_dir_all(&second).unwrap();
+
+    let output = env.cmd().arg("rollback").output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stderr.contains("Skipping"), "stderr={}", stderr);
+    assert!(stderr.contains("nixy install hello"), "stderr={}", stderr);
+    assert_eq!(std::fs::read_link(&env.env_path).unwrap(), first);
+    assert!(!env.profile_flake("default").contains("hello"));
+}
+
+#[test]
+fn test_hermetic_update_commit_re_resolves_pinned_packages() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", GONE_COMMIT)
+        .write(&env.config_dir);
 
+    let output = env
+        .cmd()
+        .args(["update", "--commit", &GONE_COMMIT[..8]])
+        .output()
+        .unwrap();
     assert!(
         output.status.success(),
-        "List should succeed: stderr={}",
+        "stderr={}",
         String::from_utf8_lossy(&output.stderr)
     );
 
-    // Standard package should show (nixpkgs)
-    assert!(
-        stdout.contains("ripgrep") && stdout.contains("(nixpkgs)"),
-        "Should show ripgrep with (nixpkgs) source: {}",
-        stdout
-    );
+    let config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    assert!(!config.contains(GONE_COMMIT));
+    assert!(config.contains("a3a3dda3bacf61e8a39258a0ed9c924eeca8e293"));
+    assert!(env
+        .profile_flake("default")
+        .contains("github:NixOS/nixpkgs/a3a3dda3bacf61e8a39258a0ed9c924eeca8e293"));
+    assert_eq!(env.nix().calls_to("build").len(), 1);
+}
 
-    // Custom package should show its URL
+#[test]
+fn test_hermetic_update_commit_pin_missing_to() {
+    let env = TestEnv::hermetic();
+    let live = "1234567890abcdef1234567890abcdef12345678";
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", GONE_COMMIT)
+        .write(&env.config_dir);
+
+    let output = env
+        .cmd()
+        .args(["update", "--commit", GONE_COMMIT, "--pin-missing-to", live])
+        .output()
+        .unwrap();
     assert!(
-        stdout.contains("neovim") && stdout.contains("github:nix-community/neovim-nightly-overlay"),
-        "Should show neovim with its flake URL: {}",
-        stdout
+        output.status.success(),
+        "stderr={}",
+        String::from_utf8_lossy(&output.stderr)
     );
-}
 
-// =============================================================================
-// Uninstall command tests (additional)
-// =============================================================================
+    let config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+    assert!(config.contains(live));
+    assert!(!config.contains(GONE_COMMIT));
+    assert!(env
+        .profile_flake("default")
+        .contains(&format!("github:NixOS/nixpkgs/{}", live)));
+}
 
 #[test]
-fn test_uninstall_package_not_installed() {
-    let env = TestEnv::new();
+fn test_update_commit_rejects_unknown_commit_and_bad_rev() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", GONE_COMMIT)
+        .write(&env.config_dir);
 
-    // Create a profile first
-    let _ = env.cmd().args(["profile", "default", "-c"]).output();
+    let output = env
+        .cmd()
+        .args(["update", "--commit", "feedface"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No package in profile 'default'"));
 
-    // Uninstalling a non-existent package
     let output = env
         .cmd()
-        .args(["uninstall", "nonexistent-package"])
+        .args([
+            "update",
+            "--commit",
+            GONE_COMMIT,
+            "--pin-missing-to",
+            "nixos-unstable",
+        ])
         .output()
         .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("expects a nixpkgs commit hash"));
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    let stderr_lower = stderr.to_lowercase();
+    // --pin-missing-to only applies to the packages of --commit
+    let output = env
+        .cmd()
+        .args(["update", "--all", "--pin-missing-to", GONE_COMMIT])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(env.nix().calls_to("build").is_empty());
+}
 
-    // The implementation may either:
-    // 1. Succeed silently (no-op) - package wasn't there, nothing to uninstall
-    // 2. Fail with an error message
-    if output.status.success() {
-        // If it succeeds, it should indicate the package wasn't found or is a no-op
-        if stdout.is_empty() {
-            // Silent success is acceptable for a no-op uninstall of a non-existent package,
-            // but it must also be truly silent (no stderr output) and exit successfully.
-            assert!(
-                stderr.is_empty(),
-                "Silent no-op uninstall should not produce stderr: {}",
-                stderr
-            );
-        } else {
-            // If it succeeds with output, it should indicate the package wasn't found or that
-            // there were no changes to apply.
-            assert!(
-                stdout.contains("not installed")
-                    || stdout.contains("nonexistent-package")
-                    || stdout.contains("No changes"),
-                "No-op uninstall should indicate status: stdout={}",
-                stdout
-            );
-        }
-    } else {
-        // If it fails, it should provide a helpful error message
-        assert!(
-            stderr_lower.contains("not installed")
-                || stderr_lower.contains("not found")
-                || stderr_lower.contains("does not exist")
-                || stderr.contains("nonexistent-package"),
-            "Failed uninstall should indicate package not found: {}",
-            stderr
-        );
-    }
+#[test]
+fn test_hermetic_doctor_fails_on_gone_commit() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .profile("work")
+        .resolved("work", "hello", "2.12.0", GONE_COMMIT)
+        .resolved(
+            "default",
+            "jq",
+            "1.7",
+            "a3a3dda3bacf61e8a39258a0ed9c924eeca8e293",
+        )
+        .write(&env.config_dir);
+    env.nixhub().remove_commit(GONE_COMMIT);
+
+    let (ok, results) = doctor_json(&env);
+    assert!(!ok);
+    assert_eq!(doctor_status(&results, "commits"), "fail");
+    let detail = results
+        .iter()
+        .find(|r| r["check"] == "commits")
+        .and_then(|r| r["detail"].as_str())
+        .unwrap();
+    assert!(
+        detail.contains("0badc0de (work: hello)"),
+        "detail={}",
+        detail
+    );
+    assert!(!detail.contains("jq"), "detail={}", detail);
 }
 
-// =============================================================================
-// Flake reference routing tests
-// =============================================================================
+#[test]
+fn test_hermetic_doctor_caches_reachable_commits() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .resolved(
+            "default",
+            "hello",
+            "2.12.1",
+            "a3a3dda3bacf61e8a39258a0ed9c924eeca8e293",
+        )
+        .resolved(
+            "default",
+            "jq",
+            "1.7",
+            "a3a3dda3bacf61e8a39258a0ed9c924eeca8e293",
+        )
+        .write(&env.config_dir);
+
+    let (_, results) = doctor_json(&env);
+    assert_eq!(doctor_status(&results, "commits"), "ok");
+    let (_, results) = doctor_json(&env);
+    assert_eq!(doctor_status(&results, "commits"), "ok");
+
+    // One HEAD request for the shared commit, none on the second run
+    assert_eq!(env.nixhub().archive_requests().len(), 1);
+}
 
 #[test]
-fn test_install_flake_reference_routes_to_flake_url() {
+fn test_doctor_warns_when_commits_cannot_be_checked() {
     let env = TestEnv::new();
+    NixyJson::new()
+        .resolved("default", "hello", "2.12.0", GONE_COMMIT)
+        .write(&env.config_dir);
 
-    // Create a profile first
-    let _ = env.cmd().args(["profile", "default", "-c"]).output();
+    let (_, results) = doctor_json(&env);
+    assert_eq!(doctor_status(&results, "commits"), "warn");
+}
 
-    // `nixy install github:user/repo` should NOT go through Nixhub
-    // It should be treated as a flake reference and installed via flake URL
-    let output = env
-        .cmd()
-        .args(["install", "github:yusukeshib/wb"])
-        .output()
-        .unwrap();
+// =============================================================================
+// Font linking tests
+// =============================================================================
 
-    let stderr = String::from_utf8_lossy(&output.stderr);
+const FONT: &str = "share/fonts/truetype/Hack-Regular.ttf";
+
+#[test]
+fn test_hermetic_sync_links_fonts_when_enabled() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "nerd-fonts")
+        .setting("default", "link_fonts", serde_json::json!(true))
+        .write(&env.config_dir);
+    env.nix().build_file(FONT, Some("hack v1"));
+
+    let output = env.cmd().arg("sync").output().unwrap();
     let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout.contains("1 added"), "stdout={}", stdout);
+    let placed = env.font_dir.join("truetype/Hack-Regular.ttf");
+    assert_eq!(std::fs::read_to_string(&placed).unwrap(), "hack v1");
 
-    // The key assertion: it should NOT try to resolve via Nixhub
-    assert!(
-        !stderr.contains("Nixhub") && !stdout.contains("Nixhub"),
-        "Flake reference should not be routed to Nixhub: stdout={}, stderr={}",
-        stdout,
-        stderr
-    );
+    // A rebuild with a changed font replaces it, one without it removes it
+    env.nix().build_file(FONT, Some("hack v2"));
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    assert_eq!(std::fs::read_to_string(&placed).unwrap(), "hack v2");
 
-    // It should recognize this as a flake URL (either succeeding or failing with a
-    // nix/flake-related error, not a Nixhub error)
-    if !output.status.success() {
-        let combined = format!("{}{}", stdout, stderr).to_lowercase();
-        assert!(
-            combined.contains("flake")
-                || combined.contains("validating")
-                || combined.contains("nix")
-                || combined.contains("using flake url"),
-            "Error should be flake-related, not Nixhub-related: stdout={}, stderr={}",
-            stdout,
-            stderr
-        );
-    }
+    env.nix().build_file(FONT, None);
+    let output = env.cmd().arg("sync").output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("1 removed"));
+    assert!(!placed.exists());
+    assert!(!env.state_dir.join("fonts.json").exists());
 }
 
 #[test]
-fn test_install_flake_reference_with_fragment() {
-    let env = TestEnv::new();
+fn test_hermetic_sync_leaves_fonts_alone_by_default() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "nerd-fonts")
+        .write(&env.config_dir);
+    env.nix().build_file(FONT, Some("hack"));
+
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    assert!(!env.font_dir.exists());
+}
 
-    // Create a profile first
-    let _ = env.cmd().args(["profile", "default", "-c"]).output();
+#[test]
+fn test_hermetic_fonts_removed_when_setting_turned_off() {
+    let env = TestEnv::hermetic();
+    let config = NixyJson::new().package("default", "nerd-fonts");
+    config
+        .clone()
+        .setting("default", "link_fonts", serde_json::json!(true))
+        .write(&env.config_dir);
+    env.nix().build_file(FONT, Some("hack"));
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    assert!(env.font_dir.join("truetype/Hack-Regular.ttf").exists());
+    // Fonts the user put there themselves are not nixy's to remove
+    std::fs::write(env.font_dir.join("mine.ttf"), "mine").unwrap();
+
+    config.write(&env.config_dir);
+    let output = env.cmd().arg("sync").output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Removed 1 linked font(s)"));
+    assert!(!env.font_dir.join("truetype").exists());
+    assert!(env.font_dir.join("mine.ttf").exists());
+}
 
-    // `nixy install github:user/repo#pkg` should extract the fragment as the package name
+#[test]
+fn test_hermetic_fonts_removed_when_profile_deleted() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .profile("fonts")
+        .active("fonts")
+        .package("fonts", "nerd-fonts")
+        .setting("fonts", "link_fonts", serde_json::json!(true))
+        .write(&env.config_dir);
+    env.nix().build_file(FONT, Some("hack"));
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    assert!(env.font_dir.join("truetype/Hack-Regular.ttf").exists());
+
+    // Switching without a build keeps the old environment and its fonts
     let output = env
         .cmd()
-        .args(["install", "github:NixOS/nixpkgs#hello"])
+        .args(["profile", "default", "--activate-only"])
         .output()
         .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(env.font_dir.join("truetype/Hack-Regular.ttf").exists());
 
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    let stdout = String::from_utf8_lossy(&output.stdout);
-
-    // Should NOT go through Nixhub
-    assert!(
-        !stderr.contains("Nixhub") && !stdout.contains("Nixhub"),
-        "Flake reference with fragment should not be routed to Nixhub: stdout={}, stderr={}",
-        stdout,
-        stderr
-    );
+    let output = env
+        .cmd()
+        .args(["profile", "fonts", "-d", "--yes"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(!env.font_dir.exists());
 }
 
 #[test]
-fn test_install_plain_package_still_uses_nixhub() {
-    let env = TestEnv::new();
-
-    // Create a profile first
-    let _ = env.cmd().args(["profile", "default", "-c"]).output();
-
-    // `nixy install hello` should still go through Nixhub (the standard path)
-    let output = env.cmd().args(["install", "hello"]).output().unwrap();
+fn test_hermetic_font_linking_never_fails_the_sync() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "nerd-fonts")
+        .setting("default", "link_fonts", serde_json::json!(true))
+        .write(&env.config_dir);
+    env.nix().build_file(FONT, Some("hack"));
+    // A file where the font directory should be
+    std::fs::create_dir_all(env.font_dir.parent().unwrap()).unwrap();
+    std::fs::write(&env.font_dir, "in the way").unwrap();
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-
-    // Should mention Nixhub since it's a plain package name
-    assert!(
-        stdout.contains("Nixhub") || stdout.contains("already installed"),
-        "Plain package name should be resolved via Nixhub: stdout={}",
-        stdout
-    );
+    let output = env.cmd().arg("sync").output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr={}", stderr);
+    assert!(stderr.contains("Could not link fonts"), "stderr={}", stderr);
 }
 
 // =============================================================================
-// Install revert on sync failure tests
+// Real nix tests (opt-in: cargo test -- --ignored)
 // =============================================================================
 
 #[test]
-fn test_install_reverts_flake_on_sync_failure() {
+#[ignore = "needs a real nix installation and network access"]
+fn test_real_nix_sync_empty_profile() {
     let env = TestEnv::new();
+    NixyJson::new().write(&env.config_dir);
 
-    // Create the new nixy.json format
-    let nixy_json_content = r#"{
-  "version": 3,
-  "active_profile": "default",
-  "profiles": {
-    "default": {
-      "packages": [],
-      "resolved_packages": [],
-      "custom_packages": []
-    }
-  }
-}"#;
-    std::fs::create_dir_all(&env.config_dir).unwrap();
-    std::fs::write(env.config_dir.join("nixy.json"), nixy_json_content).unwrap();
-
-    // Create state directory for flake.nix
-    let state_profile_dir = env.state_dir.join("profiles/default");
-    std::fs::create_dir_all(&state_profile_dir).unwrap();
-
-    // Create a flake.nix (no markers - new format)
-    let flake_content = r#"{
-  description = "nixy managed packages";
-
-  inputs = {
-    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
-  };
-
-  outputs = { self, nixpkgs }@inputs:
-    let
-      systems = [ "x86_64-linux" "aarch64-linux" "x86_64-darwin" "aarch64-darwin" ];
-      forAllSystems = f: nixpkgs.lib.genAttrs systems (system: f system);
-    in {
-      packages = forAllSystems (system:
-        let pkgs = nixpkgs.legacyPackages.${system};
-        in rec {
-
-          default = pkgs.buildEnv {
-            name = "nixy-env";
-            paths = [
-            ];
-            extraOutputsToInstall = [ "man" "doc" "info" ];
-          };
-        });
-    };
+    let output = env.cmd().arg("sync").output().unwrap();
+    assert!(
+        output.status.success(),
+        "stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(env.env_path.is_symlink());
+    assert!(env.state_dir.join("profiles/default/flake.lock").exists());
 }
-"#;
-    std::fs::write(state_profile_dir.join("flake.nix"), flake_content).unwrap();
 
-    // Save original config for comparison
-    let original_config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
+#[test]
+#[ignore = "needs a real nix installation and network access"]
+fn test_real_nix_install_and_uninstall() {
+    let env = TestEnv::new();
+    NixyJson::new().write(&env.config_dir);
 
-    // Try to install a package - this will modify nixy.json and flake.nix, then run sync
-    // Sync may fail in test environment (no nix, missing lock, etc.)
     let output = env.cmd().args(["install", "hello"]).output().unwrap();
+    assert!(
+        output.status.success(),
+        "stderr={}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(env.env_path.join("bin/hello").exists());
 
-    let stderr = String::from_utf8_lossy(&output.stderr);
-
-    // If sync failed and revert happened, verify the config was restored
-    if !output.status.success() && stderr.to_lowercase().contains("reverted") {
-        let current_config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
-        // Parse as JSON and compare to handle potential key ordering differences
-        let current_json: serde_json::Value = serde_json::from_str(&current_config).unwrap();
-        let original_json: serde_json::Value = serde_json::from_str(&original_config).unwrap();
-        assert_eq!(
-            current_json, original_json,
-            "Config should be reverted to original on sync failure"
-        );
-    }
-
-    // Also verify the command behavior is consistent:
-    // - If it succeeded, hello should be in the config
-    // - If it failed with revert, the config should be unchanged
-    // - If it failed without revert message, something else went wrong (acceptable in test)
-    if output.status.success() {
-        let current_config = std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap();
-        assert!(
-            current_config.contains("hello"),
-            "On success, config should contain the installed package"
-        );
-        let current_flake = std::fs::read_to_string(state_profile_dir.join("flake.nix")).unwrap();
-        // Check for either legacy format (pkgs.hello) or new Nixhub format (inputs.nixpkgs-*)
-        assert!(
-            current_flake.contains("hello = pkgs.hello")
-                || current_flake.contains("hello = inputs.nixpkgs-"),
-            "On success, flake should contain the installed package (legacy or Nixhub format)"
-        );
-    }
+    let output = env.cmd().args(["uninstall", "hello"]).output().unwrap();
+    assert!(output.status.success());
+    assert!(!env.env_path.join("bin/hello").exists());
 }
 
 // =============================================================================
@@ -1951,3 +7862,249 @@ stdenv.mkDerivation {
         "Local package definition should be removed"
     );
 }
+
+#[test]
+fn test_uninstall_listed_local_package_kept_without_purge() {
+    let env = TestEnv::new();
+    write_nixy_json(
+        &env,
+        r#"{
+  "version": 3,
+  "active_profile": "default",
+  "profiles": {
+    "default": { "packages": ["my-local-pkg"], "resolved_packages": [], "custom_packages": [] }
+  }
+}"#,
+    );
+    let packages_dir = env.config_dir.join("packages");
+    std::fs::create_dir_all(&packages_dir).unwrap();
+    let local_pkg_file = packages_dir.join("my-local-pkg.nix");
+    std::fs::write(
+        &local_pkg_file,
+        r#"{ stdenv }: stdenv.mkDerivation { pname = "my-local-pkg"; version = "1.0"; src = ./.; }"#,
+    )
+    .unwrap();
+
+    let output = env
+        .cmd()
+        .args(["uninstall", "my-local-pkg"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    // Non-interactive without --purge: the definition is kept
+    assert!(local_pkg_file.exists(), "stderr={}", stderr);
+    assert!(
+        stderr.contains("--purge"),
+        "should hint at --purge: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_uninstall_purge_removes_unused_local_package() {
+    let env = TestEnv::new();
+    write_nixy_json(
+        &env,
+        r#"{
+  "version": 3,
+  "active_profile": "default",
+  "profiles": {
+    "default": { "packages": ["my-local-pkg"], "resolved_packages": [], "custom_packages": [] }
+  }
+}"#,
+    );
+    let packages_dir = env.config_dir.join("packages");
+    std::fs::create_dir_all(&packages_dir).unwrap();
+    let local_pkg_file = packages_dir.join("my-local-pkg.nix");
+    std::fs::write(
+        &local_pkg_file,
+        r#"{ stdenv }: stdenv.mkDerivation { pname = "my-local-pkg"; version = "1.0"; src = ./.; }"#,
+    )
+    .unwrap();
+
+    env.cmd()
+        .args(["uninstall", "my-local-pkg", "--purge"])
+        .output()
+        .unwrap();
+
+    assert!(!local_pkg_file.exists());
+}
+
+#[test]
+fn test_uninstall_yes_removes_unused_local_package() {
+    let env = TestEnv::new();
+    write_nixy_json(
+        &env,
+        r#"{
+  "version": 3,
+  "active_profile": "default",
+  "profiles": {
+    "default": { "packages": ["my-local-pkg"], "resolved_packages": [], "custom_packages": [] }
+  }
+}"#,
+    );
+    let packages_dir = env.config_dir.join("packages");
+    std::fs::create_dir_all(&packages_dir).unwrap();
+    let local_pkg_file = packages_dir.join("my-local-pkg.nix");
+    std::fs::write(
+        &local_pkg_file,
+        r#"{ stdenv }: stdenv.mkDerivation { pname = "my-local-pkg"; version = "1.0"; src = ./.; }"#,
+    )
+    .unwrap();
+
+    env.cmd()
+        .args(["-y", "uninstall", "my-local-pkg"])
+        .output()
+        .unwrap();
+
+    assert!(!local_pkg_file.exists());
+}
+
+#[test]
+fn test_uninstall_yes_keeps_discovered_local_package_used_by_other_profile() {
+    let env = TestEnv::new();
+    write_nixy_json(
+        &env,
+        r#"{
+  "version": 3,
+  "active_profile": "default",
+  "profiles": {
+    "default": { "packages": [], "resolved_packages": [], "custom_packages": [] },
+    "work": { "packages": ["my-local-pkg"], "resolved_packages": [], "custom_packages": [] }
+  }
+}"#,
+    );
+    let packages_dir = env.config_dir.join("packages");
+    std::fs::create_dir_all(&packages_dir).unwrap();
+    let local_pkg_file = packages_dir.join("my-local-pkg.nix");
+    std::fs::write(
+        &local_pkg_file,
+        r#"{ stdenv }: stdenv.mkDerivation { pname = "my-local-pkg"; version = "1.0"; src = ./.; }"#,
+    )
+    .unwrap();
+
+    let output = env
+        .cmd()
+        .args(["-y", "uninstall", "my-local-pkg"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(local_pkg_file.exists(), "file is still used by 'work'");
+    assert!(
+        stderr.contains("still used by profile(s): work. Use --purge"),
+        "stderr={}",
+        stderr
+    );
+
+    let output = env
+        .cmd()
+        .args(["uninstall", "my-local-pkg", "--purge"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(!local_pkg_file.exists());
+}
+
+#[test]
+fn test_hermetic_failed_purge_keeps_local_definition_and_its_hash() {
+    let env = TestEnv::hermetic();
+    NixyJson::new()
+        .package("default", "my-local-pkg")
+        .write(&env.config_dir);
+    let packages_dir = env.config_dir.join("packages");
+    std::fs::create_dir_all(&packages_dir).unwrap();
+    let local_pkg_file = packages_dir.join("my-local-pkg.nix");
+    std::fs::write(
+        &local_pkg_file,
+        r#"{ stdenv }: stdenv.mkDerivation { pname = "my-local-pkg"; version = "1.0"; src = ./.; }"#,
+    )
+    .unwrap();
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+    let hashes = || -> serde_json::Value {
+        let config: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(env.config_dir.join("nixy.json")).unwrap(),
+        )
+        .unwrap();
+        config["local_hashes"].clone()
+    };
+    let recorded = hashes();
+    assert!(recorded.get("my-local-pkg.nix").is_some(), "{}", recorded);
+
+    env.nix().fail_builds("error: builder failed");
+    let output = env
+        .cmd()
+        .args(["uninstall", "my-local-pkg", "--purge"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("Reverted nixy.json, flake.nix and the local package definitions"),
+        "stderr={}",
+        stderr
+    );
+    // The definition is back, and still matches the hash nixy.json records
+    assert!(local_pkg_file.exists());
+    assert_eq!(hashes(), recorded);
+    assert!(!env.config_dir.join(".uninstall-backup").exists());
+    assert!(profiles_json(&env)["default"]["packages"]
+        .to_string()
+        .contains("my-local-pkg"));
+
+    // So later syncs don't report it as deleted
+    env.nix().pass_builds();
+    let output = env.cmd().arg("sync").output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    // And a successful purge removes both
+    let output = env
+        .cmd()
+        .args(["uninstall", "my-local-pkg", "--purge"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(!local_pkg_file.exists());
+    assert!(hashes().get("my-local-pkg.nix").is_none());
+    assert!(!env.config_dir.join(".uninstall-backup").exists());
+    assert!(env.cmd().arg("sync").output().unwrap().status.success());
+}
+
+#[test]
+fn test_uninstall_purge_keeps_local_package_used_by_other_profile() {
+    let env = TestEnv::new();
+    write_nixy_json(
+        &env,
+        r#"{
+  "version": 3,
+  "active_profile": "default",
+  "profiles": {
+    "default": { "packages": ["my-local-pkg"], "resolved_packages": [], "custom_packages": [] },
+    "work": { "packages": ["my-local-pkg"], "resolved_packages": [], "custom_packages": [] }
+  }
+}"#,
+    );
+    let packages_dir = env.config_dir.join("packages");
+    std::fs::create_dir_all(&packages_dir).unwrap();
+    let local_pkg_file = packages_dir.join("my-local-pkg.nix");
+    std::fs::write(
+        &local_pkg_file,
+        r#"{ stdenv }: stdenv.mkDerivation { pname = "my-local-pkg"; version = "1.0"; src = ./.; }"#,
+    )
+    .unwrap();
+
+    let output = env
+        .cmd()
+        .args(["uninstall", "my-local-pkg", "--purge"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(local_pkg_file.exists(), "file is still used by 'work'");
+    assert!(
+        stdout.contains("work"),
+        "should name the referencing profile: {}",
+        stdout
+    );
+}
@@ -0,0 +1,269 @@
+#!/bin/sh
+# Fake `nix` for hermetic integration tests.
+#
+# Implements the subcommands nixy runs, driven by files in $FAKE_NIX_DIR
+# (baked in by tests/support/mod.rs when the script is installed):
+#
+#   calls.log        every invocation, one line of arguments each
+#   system           output of `eval builtins.currentSystem`; `eval --apply
+#                    builtins.attrNames` lists the names bound next to
+#                    `default = pkgs.buildEnv` in the flake
+#   eval/<n>         canned `nix eval` answers: line 1 is a shell glob matched
+#                    against each argument, the rest is printed on a match
+#   build-fails      if present, `build` fails and prints it to stderr;
+#                    with --no-update-lock-file, `build` also fails when the
+#                    flake.lock is missing an input the flake declares
+#   update-fails     if present, `flake update` fails and prints it to stderr
+#   eval-delay       seconds every flake `nix eval` sleeps before answering
+#   search           output of `nix search`
+#   branches/<b>     commit `flake metadata github:NixOS/nixpkgs/<b>` locks
+#   search-fails     if present, `search` fails and prints it to stderr
+#   build-files/     copied into every build output
+#   store/           where builds and prefetches are "realised"
+#   features         output of `config show experimental-features` (default:
+#                    "flakes nix-command")
+#   dead             output of `nix-store --gc --print-dead` (this script is
+#                    also installed as nix-store, which also implements
+#                    `--add-root <link> --realise <path>`)
+#   gc-log           what `store gc` prints to stderr (default: nothing freed)
+#   run-exit         exit status of `run`, which prints what it would run
+
+dir="@FAKE_NIX_DIR@"
+case "$0" in */nix-store) set -- nix-store "$@" ;; esac
+printf '%s\n' "$*" >>"$dir/calls.log"
+
+# Drop the global flags nixy always passes
+while [ $# -gt 0 ]; do
+  case "$1" in
+    --extra-experimental-features) shift 2 ;;
+    --accept-flake-config) shift ;;
+    *) break ;;
+  esac
+done
+
+# Write a minimal flake.lock for the inputs declared in <dir>/flake.nix
+write_lock() {
+  inputs=$(sed -n 's/^ *\([A-Za-z0-9_-]*\)\.url *=.*/\1/p' "$1/flake.nix" | sort -u)
+  {
+    printf '{"nodes": {"root": {"inputs": {'
+    sep=""
+    for name in $inputs; do
+      printf '%s"%s": "%s"' "$sep" "$name" "$name"
+      sep=", "
+    done
+    printf '}}'
+    for name in $inputs; do
+      rev=""
+      [ -f "$dir/revs/$name" ] && rev=$(printf ', "rev": "%s"' "$(cat "$dir/revs/$name")")
+      printf ', "%s": {"locked": {"type": "github", "narHash": "sha256-fake"%s}}' "$name" "$rev"
+    done
+    printf '}, "root": "root", "version": 7}\n'
+  } >"$1/flake.lock"
+}
+
+# Strip a `path:` prefix and `#output` suffix from a flake reference
+flake_path() {
+  ref=${1%%#*}
+  ref=${ref#path:}
+  printf '%s' "$ref" | sed 's/%20/ /g'
+}
+
+cmd=$1
+[ $# -gt 0 ] && shift
+case "$cmd" in
+  --version)
+    echo "nix (Nix) 2.24.0"
+    ;;
+
+  eval)
+    for arg in "$@"; do
+      case "$arg" in
+        builtins.currentSystem)
+          if [ -f "$dir/system" ]; then cat "$dir/system"; else printf 'x86_64-linux'; fi
+          exit 0
+          ;;
+        builtins.attrNames)
+          for ref in "$@"; do
+            case "$ref" in *'#packages.'*) flake=$(flake_path "$ref") ;; esac
+          done
+          [ -f "$flake/flake.nix" ] || { echo "error: no flake.nix in $flake" >&2; exit 1; }
+          indent=$(sed -n 's/^\( *\)default = pkgs\.buildEnv.*/\1/p' "$flake/flake.nix" | head -n 1)
+          names=$(sed -n "s/^$indent\([A-Za-z0-9_-]*\) = .*/\1/p" "$flake/flake.nix" | sort | sed 's/.*/"&"/' | paste -sd, -)
+          printf '[%s]\n' "$names"
+          exit 0
+          ;;
+        *builtins.readFile*)
+          lock=$(printf '%s' "$arg" | sed -n 's/.*readFile "\([^"]*\)".*/\1/p')
+          names=$(tr -d '\n' <"$lock" 2>/dev/null |
+            sed -n 's/.*"root": *{[^{}]*"inputs": *{\([^}]*\)}.*/\1/p' |
+            tr ',' '\n' | sed -n 's/^ *"\([^"]*\)".*/\1/p')
+          if [ -z "$names" ]; then
+            echo "error: syntax error, unexpected invalid token" >&2
+            exit 1
+          fi
+          printf '%s' "$names"
+          exit 0
+          ;;
+      esac
+    done
+    if [ -f "$dir/eval-delay" ]; then sleep "$(cat "$dir/eval-delay")"; fi
+    for answer in "$dir"/eval/*; do
+      [ -f "$answer" ] || continue
+      pattern=$(head -n 1 "$answer")
+      for arg in "$@"; do
+        # shellcheck disable=SC2254
+        case "$arg" in
+          $pattern)
+            tail -n +2 "$answer"
+            exit 0
+            ;;
+        esac
+      done
+    done
+    echo "error: attribute missing (fake nix has no answer for: $*)" >&2
+    exit 1
+    ;;
+
+  build)
+    if [ -f "$dir/build-fails" ]; then
+      cat "$dir/build-fails" >&2
+      exit 1
+    fi
+    installable="" out_link="" frozen=""
+    while [ $# -gt 0 ]; do
+      case "$1" in
+        --out-link) out_link=$2; shift 2 ;;
+        --no-update-lock-file) frozen=1; shift ;;
+        -*) shift ;;
+        *) installable=$1; shift ;;
+      esac
+    done
+    flake=$(flake_path "$installable")
+    if [ -n "$frozen" ]; then
+      # Every input needs an entry in an existing lock
+      for name in $(sed -n 's/^ *\([A-Za-z0-9_-]*\)\.url *=.*/\1/p' "$flake/flake.nix"); do
+        if ! grep -q "\"$name\": {" "$flake/flake.lock" 2>/dev/null; then
+          echo "error: flake 'path:$flake' requires lock file changes but they're prohibited by --no-update-lock-file" >&2
+          exit 1
+        fi
+      done
+    fi
+    [ -f "$flake/flake.lock" ] || write_lock "$flake"
+    mkdir -p "$dir/store"
+    out=$(mktemp -d "$dir/store/env.XXXXXX")
+    mkdir -p "$out/bin"
+    [ -d "$dir/build-files" ] && cp -R "$dir/build-files/." "$out/"
+    cp "$flake/flake.nix" "$out/flake.nix"
+    if [ -n "$out_link" ]; then ln -sfn "$out" "$out_link"; fi
+    ;;
+
+  flake)
+    sub=$1
+    shift
+    case "$sub" in
+      update)
+        if [ -f "$dir/update-fails" ]; then
+          cat "$dir/update-fails" >&2
+          exit 1
+        fi
+        flake=""
+        while [ $# -gt 0 ]; do
+          case "$1" in
+            --flake) flake=$2; shift 2 ;;
+            *) shift ;;
+          esac
+        done
+        write_lock "$(flake_path "${flake:-.}")"
+        ;;
+      prefetch)
+        url=""
+        for arg in "$@"; do
+          case "$arg" in -*) ;; *) url=$arg ;; esac
+        done
+        out="$dir/store/source-$(printf '%s' "$url" | tr -c 'A-Za-z0-9' '-')"
+        mkdir -p "$out"
+        printf '{"hash": "sha256-fake", "storePath": "%s"}\n' "$out"
+        ;;
+      metadata)
+        url=""
+        for arg in "$@"; do
+          case "$arg" in -*) ;; *) url=$arg ;; esac
+        done
+        branch=${url#github:NixOS/nixpkgs/}
+        if [ ! -f "$dir/branches/$branch" ]; then
+          echo "error: unable to download '$url'" >&2
+          exit 1
+        fi
+        printf '{"locked": {"type": "github", "rev": "%s"}}\n' "$(cat "$dir/branches/$branch")"
+        ;;
+      *)
+        echo "error: fake nix does not implement 'flake $sub'" >&2
+        exit 1
+        ;;
+    esac
+    ;;
+
+  search)
+    if [ -f "$dir/search-fails" ]; then
+      cat "$dir/search-fails" >&2
+      exit 1
+    fi
+    if [ -f "$dir/search" ]; then cat "$dir/search"; fi
+    ;;
+
+  registry)
+    echo "global flake:nixpkgs github:NixOS/nixpkgs/nixpkgs-unstable"
+    ;;
+
+  config)
+    if [ -f "$dir/features" ]; then cat "$dir/features"; else echo "flakes nix-command"; fi
+    ;;
+
+  store)
+    if [ "$1" != gc ]; then
+      echo "error: fake nix does not implement 'store $1'" >&2
+      exit 1
+    fi
+    if [ -f "$dir/gc-log" ]; then
+      cat "$dir/gc-log" >&2
+    else
+      echo "0 store paths deleted, 0.00 MiB freed" >&2
+    fi
+    ;;
+
+  run)
+    installable=""
+    while [ $# -gt 0 ] && [ "$1" != "--" ]; do
+      case "$1" in -*) ;; *) installable=$1 ;; esac
+      shift
+    done
+    [ $# -gt 0 ] && shift
+    echo "fake run $installable: $*"
+    if [ -f "$dir/run-exit" ]; then exit "$(cat "$dir/run-exit")"; fi
+    ;;
+
+  nix-store)
+    if [ "$1" = "--add-root" ] && [ "$3" = "--realise" ] && [ $# -eq 4 ]; then
+      ln -sfn "$4" "$2"
+      echo "$2"
+      exit 0
+    fi
+    if [ "$*" != "--gc --print-dead" ]; then
+      echo "error: fake nix-store does not implement '$*'" >&2
+      exit 1
+    fi
+    echo "finding garbage collector roots..." >&2
+    [ -f "$dir/dead" ] && cat "$dir/dead"
+    ;;
+
+  path-info)
+    for arg in "$@"; do
+      case "$arg" in -*) ;; *) echo "$arg" ;; esac
+    done
+    ;;
+
+  *)
+    echo "error: fake nix does not implement '$cmd'" >&2
+    exit 1
+    ;;
+esac
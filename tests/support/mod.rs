@@ -0,0 +1,510 @@
+//! Hermetic test harness: a fake `nix` on PATH, a local server replaying
+//! recorded Nixhub responses, and builders for config trees.
+//!
+//! Tests built on this run the same on machines with and without nix.
+
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const FAKE_NIX_SCRIPT: &str = include_str!("fake_nix.sh");
+
+/// A fake `nix` executable installed into its own bin directory.
+///
+/// Answers are configured per test through files next to the script; see
+/// `fake_nix.sh` for the layout.
+pub struct FakeNix {
+    dir: PathBuf,
+}
+
+impl FakeNix {
+    /// Install the fake under `root`
+    pub fn install(root: &Path) -> Self {
+        let dir = root.join("fake-nix");
+        std::fs::create_dir_all(dir.join("bin")).unwrap();
+        std::fs::create_dir_all(dir.join("eval")).unwrap();
+
+        let script = FAKE_NIX_SCRIPT.replace("@FAKE_NIX_DIR@", &dir.to_string_lossy());
+        let nix = dir.join("bin/nix");
+        std::fs::write(&nix, script).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&nix, std::fs::Permissions::from_mode(0o755)).unwrap();
+            std::os::unix::fs::symlink("nix", dir.join("bin/nix-store")).unwrap();
+        }
+
+        Self { dir }
+    }
+
+    /// Directory to put first on PATH
+    /// Where builds are "realised", standing in for /nix/store
+    pub fn store_dir(&self) -> PathBuf {
+        self.dir.join("store")
+    }
+
+    pub fn bin_dir(&self) -> PathBuf {
+        self.dir.join("bin")
+    }
+
+    /// PATH with the fake nix shadowing any real one
+    pub fn path_env(&self) -> std::ffi::OsString {
+        let mut paths = vec![self.bin_dir()];
+        if let Some(path) = std::env::var_os("PATH") {
+            paths.extend(std::env::split_paths(&path));
+        }
+        std::env::join_paths(paths).unwrap()
+    }
+
+    /// Answer `nix eval` with `output` when any argument matches the shell
+    /// glob `pattern`
+    pub fn on_eval(&self, pattern: &str, output: &str) -> &Self {
+        let n = std::fs::read_dir(self.dir.join("eval")).unwrap().count();
+        std::fs::write(
+            self.dir.join("eval").join(format!("{:03}", n)),
+            format!("{}\n{}", pattern, output),
+        )
+        .unwrap();
+        self
+    }
+
+    /// Make every flake `nix eval` take `secs` seconds
+    pub fn slow_evals(&self, secs: u64) -> &Self {
+        std::fs::write(self.dir.join("eval-delay"), secs.to_string()).unwrap();
+        self
+    }
+
+    /// Make `nix flake metadata` lock the nixpkgs `branch` to `commit`
+    pub fn nixpkgs_branch(&self, branch: &str, commit: &str) -> &Self {
+        let file = self.dir.join("branches").join(branch);
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(file, commit).unwrap();
+        self
+    }
+
+    /// Make newly written locks pin `input` to `rev`
+    pub fn lock_rev(&self, input: &str, rev: &str) -> &Self {
+        let file = self.dir.join("revs").join(input);
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(file, rev).unwrap();
+        self
+    }
+
+    /// Make `nix eval builtins.currentSystem` report `system`
+    pub fn system(&self, system: &str) -> &Self {
+        std::fs::write(self.dir.join("system"), system).unwrap();
+        self
+    }
+
+    /// Make `nix run` exit with `code`
+    pub fn run_exit(&self, code: i32) -> &Self {
+        std::fs::write(self.dir.join("run-exit"), code.to_string()).unwrap();
+        self
+    }
+
+    /// Make every `nix build` fail with `message`
+    pub fn fail_builds(&self, message: &str) -> &Self {
+        std::fs::write(self.dir.join("build-fails"), message).unwrap();
+        self
+    }
+
+    /// Undo [`FakeNix::fail_builds`]
+    pub fn pass_builds(&self) -> &Self {
+        let _ = std::fs::remove_file(self.dir.join("build-fails"));
+        self
+    }
+
+    /// Put a file at `path` (relative to the output) into every later build,
+    /// or remove it again with `None`
+    pub fn build_file(&self, path: &str, contents: Option<&str>) -> &Self {
+        let file = self.dir.join("build-files").join(path);
+        match contents {
+            Some(contents) => {
+                std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+                std::fs::write(file, contents).unwrap();
+            }
+            None => std::fs::remove_file(file).unwrap(),
+        }
+        self
+    }
+
+    /// Answer `nix search` with `output`
+    pub fn on_search(&self, output: &str) -> &Self {
+        std::fs::write(self.dir.join("search"), output).unwrap();
+        self
+    }
+
+    /// Make every `nix search` fail with `message`
+    pub fn fail_searches(&self, message: &str) -> &Self {
+        std::fs::write(self.dir.join("search-fails"), message).unwrap();
+        self
+    }
+
+    /// Report only `features` as enabled in the nix configuration
+    pub fn features(&self, features: &str) -> &Self {
+        std::fs::write(self.dir.join("features"), features).unwrap();
+        self
+    }
+
+    /// Make `nix-store --gc --print-dead` list `paths`
+    pub fn dead_paths(&self, paths: &[&str]) -> &Self {
+        std::fs::write(self.dir.join("dead"), paths.join("\n")).unwrap();
+        self
+    }
+
+    /// Make `nix store gc` print `log` to stderr instead of its usual summary
+    pub fn gc_log(&self, log: &str) -> &Self {
+        std::fs::write(self.dir.join("gc-log"), log).unwrap();
+        self
+    }
+
+    /// Make every `nix flake update` fail with `message`
+    pub fn fail_updates(&self, message: &str) -> &Self {
+        std::fs::write(self.dir.join("update-fails"), message).unwrap();
+        self
+    }
+
+    /// Every invocation so far, as its space-joined arguments
+    pub fn calls(&self) -> Vec<String> {
+        std::fs::read_to_string(self.dir.join("calls.log"))
+            .unwrap_or_default()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    /// Invocations of a subcommand such as `build` or `flake update`
+    pub fn calls_to(&self, subcommand: &str) -> Vec<String> {
+        let needle = format!(" {} ", subcommand);
+        self.calls()
+            .into_iter()
+            .filter(|c| format!(" {} ", c).contains(&needle))
+            .collect()
+    }
+}
+
+/// Serves recorded Nixhub API responses from `tests/fixtures/nixhub`.
+///
+/// Routes:
+/// - `/v2/resolve?name=N&version=V` → `resolve/N@V.json`
+/// - `/v2/pkg?name=N` → `pkg/N.json`
+/// - `/v2/search?q=Q` → `search/Q.json`
+///
+/// Anything else, or a missing fixture, is a 404 like the real API returns
+/// for unknown packages.
+///
+/// It also stands in for GitHub's nixpkgs archives:
+/// `/NixOS/nixpkgs/archive/<commit>.tar.gz` answers 200 unless the commit
+/// was marked gone with [`NixhubServer::remove_commit`].
+pub struct NixhubServer {
+    url: String,
+    removed_commits: Arc<Mutex<Vec<String>>>,
+    archive_requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl NixhubServer {
+    pub fn start() -> Self {
+        Self::start_with(fixtures_dir().join("nixhub"))
+    }
+
+    pub fn start_with(fixtures: PathBuf) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let removed_commits = Arc::new(Mutex::new(Vec::new()));
+        let archive_requests = Arc::new(Mutex::new(Vec::new()));
+        let (removed, requests) = (removed_commits.clone(), archive_requests.clone());
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let mut request_line = String::new();
+                let mut reader = BufReader::new(&stream);
+                if reader.read_line(&mut request_line).is_err() {
+                    continue;
+                }
+                // Drain the headers so the client sees a clean response
+                let mut line = String::new();
+                while reader.read_line(&mut line).is_ok_and(|n| n > 2) {
+                    line.clear();
+                }
+
+                let target = request_line.split_whitespace().nth(1).unwrap_or("/");
+                if let Some(commit) = archive_commit(target) {
+                    requests.lock().unwrap().push(commit.to_string());
+                    let status = if removed.lock().unwrap().iter().any(|c| c == commit) {
+                        "404 Not Found"
+                    } else {
+                        "200 OK"
+                    };
+                    let _ = stream.write_all(
+                        format!(
+                            "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                            status
+                        )
+                        .as_bytes(),
+                    );
+                    continue;
+                }
+                let response = match fixture_for(&fixtures, target)
+                    .and_then(|p| std::fs::read_to_string(p).ok())
+                {
+                    Some(body) => format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    ),
+                    None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                        .to_string(),
+                };
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+
+        Self {
+            url,
+            removed_commits,
+            archive_requests,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Make the archive of a nixpkgs commit 404, as for a force-pushed or
+    /// garbage-collected commit
+    pub fn remove_commit(&self, commit: &str) -> &Self {
+        self.removed_commits
+            .lock()
+            .unwrap()
+            .push(commit.to_string());
+        self
+    }
+
+    /// Commits whose archive was requested so far
+    pub fn archive_requests(&self) -> Vec<String> {
+        self.archive_requests.lock().unwrap().clone()
+    }
+}
+
+fn archive_commit(target: &str) -> Option<&str> {
+    target
+        .strip_prefix("/NixOS/nixpkgs/archive/")?
+        .strip_suffix(".tar.gz")
+}
+
+fn fixture_for(fixtures: &Path, target: &str) -> Option<PathBuf> {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let param = |key: &str| {
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| percent_decode(v))
+    };
+    let file = match path {
+        "/v2/resolve" => format!("resolve/{}@{}.json", param("name")?, param("version")?),
+        "/v2/pkg" => format!("pkg/{}.json", param("name")?),
+        "/v2/search" => format!("search/{}.json", param("q")?),
+        _ => return None,
+    };
+    Some(fixtures.join(file))
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+                match u8::from_str_radix(hex, 16) {
+                    Ok(b) => {
+                        out.push(b);
+                        i += 3;
+                        continue;
+                    }
+                    Err(_) => out.push(b'%'),
+                }
+            }
+            b'+' => out.push(b' '),
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+pub fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// Builder for a `nixy.json` tree
+#[derive(Clone)]
+pub struct NixyJson {
+    active: String,
+    profiles: Vec<(String, serde_json::Value)>,
+    settings: Vec<(String, serde_json::Value)>,
+}
+
+impl NixyJson {
+    /// A config whose only profile is an empty, active "default"
+    pub fn new() -> Self {
+        Self {
+            active: "default".to_string(),
+            profiles: vec![("default".to_string(), empty_profile())],
+            settings: Vec::new(),
+        }
+    }
+
+    /// Add an empty profile (or reset an existing one)
+    pub fn profile(mut self, name: &str) -> Self {
+        self.profiles.retain(|(n, _)| n != name);
+        self.profiles.push((name.to_string(), empty_profile()));
+        self
+    }
+
+    pub fn active(mut self, name: &str) -> Self {
+        self.active = name.to_string();
+        self
+    }
+
+    /// Add a plain nixpkgs package to `profile`
+    pub fn package(mut self, profile: &str, name: &str) -> Self {
+        self.entry(profile, "packages", serde_json::json!(name));
+        self
+    }
+
+    /// Add a Nixhub-resolved package to `profile`
+    pub fn resolved(mut self, profile: &str, name: &str, version: &str, commit: &str) -> Self {
+        self.entry(
+            profile,
+            "resolved_packages",
+            serde_json::json!({
+                "name": name,
+                "version_spec": null,
+                "resolved_version": version,
+                "attribute_path": name,
+                "commit_hash": commit,
+            }),
+        );
+        self
+    }
+
+    /// Add a package from a flake URL to `profile`
+    pub fn custom(mut self, profile: &str, name: &str, input_name: &str, url: &str) -> Self {
+        self.entry(
+            profile,
+            "custom_packages",
+            serde_json::json!({
+                "name": name,
+                "input_name": input_name,
+                "input_url": url,
+                "package_output": "packages",
+                "source_name": null,
+            }),
+        );
+        self
+    }
+
+    /// Set a profile-level setting such as `link_fonts`
+    pub fn setting(mut self, profile: &str, key: &str, value: serde_json::Value) -> Self {
+        let (_, p) = self
+            .profiles
+            .iter_mut()
+            .find(|(n, _)| n == profile)
+            .unwrap_or_else(|| panic!("no profile '{}' in builder", profile));
+        p[key] = value;
+        self
+    }
+
+    /// Set a top-level setting such as `strict_local`
+    pub fn global(mut self, key: &str, value: serde_json::Value) -> Self {
+        self.settings.push((key.to_string(), value));
+        self
+    }
+
+    fn entry(&mut self, profile: &str, list: &str, value: serde_json::Value) {
+        let (_, p) = self
+            .profiles
+            .iter_mut()
+            .find(|(n, _)| n == profile)
+            .unwrap_or_else(|| panic!("no profile '{}' in builder", profile));
+        p[list].as_array_mut().unwrap().push(value);
+    }
+
+    pub fn to_json(&self) -> String {
+        let profiles: serde_json::Map<String, serde_json::Value> =
+            self.profiles.iter().cloned().collect();
+        let mut json = serde_json::json!({
+            "version": 3,
+            "active_profile": self.active,
+            "profiles": profiles,
+        });
+        for (key, value) in &self.settings {
+            json[key] = value.clone();
+        }
+        serde_json::to_string_pretty(&json).unwrap()
+    }
+
+    /// Write to `<config_dir>/nixy.json`
+    pub fn write(&self, config_dir: &Path) {
+        std::fs::create_dir_all(config_dir).unwrap();
+        std::fs::write(config_dir.join("nixy.json"), self.to_json()).unwrap();
+    }
+}
+
+fn empty_profile() -> serde_json::Value {
+    serde_json::json!({ "packages": [], "resolved_packages": [], "custom_packages": [] })
+}
+
+/// Builder for a pre-0.3 config tree (`profiles/<name>/packages.json` plus
+/// an `active` file), as found by migration
+pub struct LegacyTree {
+    active: String,
+    profiles: Vec<(String, Vec<String>)>,
+}
+
+impl LegacyTree {
+    pub fn new() -> Self {
+        Self {
+            active: "default".to_string(),
+            profiles: Vec::new(),
+        }
+    }
+
+    pub fn profile(mut self, name: &str, packages: &[&str]) -> Self {
+        self.profiles.push((
+            name.to_string(),
+            packages.iter().map(|p| p.to_string()).collect(),
+        ));
+        self
+    }
+
+    pub fn active(mut self, name: &str) -> Self {
+        self.active = name.to_string();
+        self
+    }
+
+    pub fn write(&self, config_dir: &Path) {
+        for (name, packages) in &self.profiles {
+            let dir = config_dir.join("profiles").join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            let state = serde_json::json!({
+                "version": 1,
+                "packages": packages,
+                "custom_packages": [],
+            });
+            std::fs::write(
+                dir.join("packages.json"),
+                serde_json::to_string_pretty(&state).unwrap(),
+            )
+            .unwrap();
+        }
+        std::fs::write(config_dir.join("active"), &self.active).unwrap();
+    }
+}